@@ -0,0 +1,99 @@
+//! Filesystem operations on e5fs images in temp dir of host.
+//! Run with `cargo bench --bench fs`, compare runs to see regressions
+//! from changes to caching and allocation
+
+#[path = "../tests/common/mod.rs"]
+mod common;
+
+use criterion::{criterion_group, criterion_main, BatchSize, Criterion, Throughput};
+use eunix::{
+  e5fs::E5FSFilesystem,
+  fs::{Filesystem, EVERYTHING},
+};
+
+const IMAGE_SIZE: u64 = 4 * 1024 * 1024;
+const BLOCK_DATA_SIZE: u32 = 4096;
+const INODE_TABLE_PERCENTAGE: f32 = 0.05;
+/// Largest file e5fs holds for sure: inodes have 11 usable direct blocks
+const FILE_SIZE: usize = 10 * BLOCK_DATA_SIZE as usize;
+/// Directories made in one image, few enough to not run out of inodes
+const DIRECTORIES: usize = 8;
+
+/// Empty host file of `IMAGE_SIZE` bytes, unique to this bench run
+fn image(name: &str) -> String {
+  common::image(&format!("bench-{name}"), IMAGE_SIZE)
+}
+
+fn mkfs(realpath: &str) -> E5FSFilesystem {
+  E5FSFilesystem::mkfs(realpath, INODE_TABLE_PERCENTAGE, BLOCK_DATA_SIZE).unwrap()
+}
+
+fn bench_mkfs(c: &mut Criterion) {
+  let realpath = image("mkfs");
+
+  c.bench_function("mkfs", |b| b.iter(|| mkfs(&realpath)));
+
+  std::fs::remove_file(&realpath).unwrap();
+}
+
+fn bench_sequential(c: &mut Criterion) {
+  let realpath = image("sequential");
+  let data = vec![b'x'; FILE_SIZE];
+  let mut e5fs = mkfs(&realpath);
+  e5fs.create_file("/file").unwrap();
+
+  let mut group = c.benchmark_group("sequential");
+  group.throughput(Throughput::Bytes(FILE_SIZE as u64));
+  group.bench_function("write", |b| b.iter(|| e5fs.write_file("/file", &data).unwrap()));
+  group.bench_function("read", |b| b.iter(|| e5fs.read_file("/file", EVERYTHING).unwrap()));
+  group.finish();
+
+  drop(e5fs);
+  std::fs::remove_file(&realpath).unwrap();
+}
+
+fn bench_create_dir(c: &mut Criterion) {
+  let realpath = image("create_dir");
+
+  let mut group = c.benchmark_group("create_dir");
+  group.throughput(Throughput::Elements(DIRECTORIES as u64));
+  // Every batch gets fresh filesystem, as inodes are never freed
+  group.bench_function("fresh image", |b| b.iter_batched(
+    || mkfs(&realpath),
+    |mut e5fs| {
+      for number in 0..DIRECTORIES {
+        e5fs.create_dir(&format!("/dir{number}")).unwrap();
+      }
+      e5fs
+    },
+    BatchSize::PerIteration,
+  ));
+  group.finish();
+
+  std::fs::remove_file(&realpath).unwrap();
+}
+
+fn bench_lookup_path(c: &mut Criterion) {
+  let realpath = image("lookup_path");
+  let mut e5fs = mkfs(&realpath);
+  let mut pathname = String::new();
+  for name in ["usr", "share", "doc", "eunix"] {
+    pathname = format!("{pathname}/{name}");
+    e5fs.create_dir(&pathname).unwrap();
+  }
+  pathname = format!("{pathname}/README");
+  e5fs.create_file(&pathname).unwrap();
+
+  let mut group = c.benchmark_group("lookup_path");
+  group.bench_function("root", |b| b.iter(|| e5fs.lookup_path("/").unwrap()));
+  group.bench_function("5 components", |b| b.iter(|| e5fs.lookup_path(&pathname).unwrap()));
+  group.finish();
+
+  drop(e5fs);
+  std::fs::remove_file(&realpath).unwrap();
+}
+
+criterion_group!(benches, bench_mkfs, bench_sequential, bench_create_dir, bench_lookup_path);
+criterion_main!(benches);
+
+// vim:ts=2 sw=2
@@ -0,0 +1,423 @@
+use std::fs::{File, FileTimes, Permissions};
+use std::os::unix::fs::{MetadataExt, PermissionsExt};
+use std::path::Path;
+use std::time::{Duration, UNIX_EPOCH};
+
+use clap::{Parser, Subcommand};
+use eunix::e5fs::E5FSFilesystem;
+use eunix::fs::{AddressSize, Filesystem, FileMode, FileModeType, Id, VFS, EVERYTHING};
+use eunix::kernel::{Errno, ErrnoContext};
+use eunix::ustar::{self, Entry, EntryKind};
+
+/// Look into and change e5fs image on host without booting a machine.
+/// Image must not be in use by a running machine
+#[derive(Debug, Parser)]
+#[clap(name = "e5fs-tool", version)]
+struct ToolArgs {
+  /// e5fs image on host, like `machines/1/devices/system.enxvd`
+  image: String,
+
+  #[clap(subcommand)]
+  command: Command,
+}
+
+#[derive(Debug, Subcommand)]
+enum Command {
+  /// Make new e5fs in image, replacing everything there
+  Mkfs {
+    #[clap(short, long, default_value_t = 4096)]
+    block_data_size: AddressSize,
+    #[clap(short, long, default_value_t = 0.1)]
+    inode_table_percentage: f32,
+    /// Unpack this ustar archive on host into the new filesystem
+    #[clap(long)]
+    rootfs: Option<String>,
+  },
+  /// List directory in image
+  Ls {
+    #[clap(default_value = "/")]
+    pathname: String,
+  },
+  /// Print file from image
+  Cat {
+    pathname: String,
+  },
+  /// Make directory in image
+  Mkdir {
+    /// Make parents as needed, no error if directory exists
+    #[clap(short, long)]
+    parents: bool,
+    /// Octal mode like `755`
+    #[clap(short, long, default_value = "755")]
+    mode: String,
+    pathname: String,
+  },
+  /// Print attributes of file in image
+  Stat {
+    pathname: String,
+  },
+  /// Copy host file into image, replacing what is there
+  #[clap(alias = "cp")]
+  CpIn {
+    /// Octal mode like `644`
+    #[clap(short, long, default_value = "644")]
+    mode: String,
+    #[clap(short, long, default_value_t = 0)]
+    uid: Id,
+    #[clap(short, long, default_value_t = 0)]
+    gid: Id,
+    host_path: String,
+    pathname: String,
+  },
+  /// Copy file of image to host, replacing what is there
+  CpOut {
+    pathname: String,
+    host_path: String,
+  },
+  /// Copy host directory and everything in it into image, keeping
+  /// modes and modification times. Owners are those given
+  Import {
+    #[clap(short, long, default_value_t = 0)]
+    uid: Id,
+    #[clap(short, long, default_value_t = 0)]
+    gid: Id,
+    host_dir: String,
+    #[clap(default_value = "/")]
+    pathname: String,
+  },
+  /// Copy directory of image and everything in it to host, keeping
+  /// modes and modification times
+  Export {
+    pathname: String,
+    host_dir: String,
+  },
+}
+
+/// Parse octal mode like `755` into `mode` of file
+fn with_octal_mode(mode: FileMode, octal: &str) -> Result<FileMode, Errno> {
+  match u16::from_str_radix(octal, 8) {
+    Ok(bits) if octal.len() == 3 => Ok(mode
+      .with_user((bits >> 6 & 0o7) as u8)
+      .with_group((bits >> 3 & 0o7) as u8)
+      .with_others((bits & 0o7) as u8)),
+    _ => Err(Errno::EINVAL(format!("invalid mode '{octal}' (expected octal like 755)").into())),
+  }
+}
+
+/// `ls -l`-like line for `mode`: type and permissions
+fn mode_string(mode: FileMode) -> String {
+  let file_type = match FileModeType::try_from(mode.file_type()) {
+    Ok(FileModeType::Dir) => 'd',
+    Ok(FileModeType::Block) => 'b',
+    Ok(FileModeType::Char) => 'c',
+    Ok(FileModeType::Sys) => 's',
+    Ok(FileModeType::Symlink) => 'l',
+    Ok(FileModeType::File) | Err(_) => '-',
+  };
+  let permissions = [mode.user(), mode.group(), mode.others()]
+    .iter()
+    .flat_map(|&bits| [(0b100, 'r'), (0b010, 'w'), (0b001, 'x')]
+      .map(|(bit, char)| if bits & bit != 0 { char } else { '-' }))
+    .collect::<String>();
+
+  format!("{file_type}{permissions}")
+}
+
+fn ls(e5fs: &mut E5FSFilesystem, pathname: &str) -> Result<(), Errno> {
+  for name in e5fs.read_dir(pathname)?.entries.into_keys() {
+    if name == "." || name == ".." {
+      continue;
+    }
+    let stat = e5fs.stat(&format!("{}/{name}", pathname.trim_end_matches('/')))?;
+    println!("{} {} {} {:>8} {name}", mode_string(stat.mode), stat.uid, stat.gid, stat.size);
+  }
+
+  Ok(())
+}
+
+fn mkdir(e5fs: &mut E5FSFilesystem, pathname: &str, parents: bool, mode: &str) -> Result<(), Errno> {
+  let pathnames = match parents {
+    // Every ancestor, then `pathname` itself
+    true => {
+      let (ancestors, final_component) = VFS::split_path(pathname)?;
+      ancestors
+        .iter()
+        .chain(std::iter::once(&final_component))
+        .scan(String::new(), |parent, name| {
+          *parent = format!("{parent}/{name}");
+          Some(parent.clone())
+        })
+        .collect()
+    },
+    false => vec![pathname.to_owned()],
+  };
+
+  for pathname in pathnames {
+    match e5fs.lookup_path(&pathname) {
+      Ok(vinode) if parents && vinode.mode.file_type() == FileModeType::Dir as u8 => continue,
+      Ok(_) => return Err(Errno::EEXIST(format!("{pathname}: File exists").into())),
+      Err(Errno::ENOENT(_)) => (),
+      Err(errno) => return Err(errno),
+    }
+    e5fs.create_dir(&pathname)?;
+    let vinode = e5fs.lookup_path(&pathname)?;
+    e5fs.change_mode(&pathname, with_octal_mode(vinode.mode, mode)?)?;
+    e5fs.change_owners(&pathname, 0, 0)?;
+  }
+
+  Ok(())
+}
+
+fn stat(e5fs: &mut E5FSFilesystem, pathname: &str) -> Result<(), Errno> {
+  let stat = e5fs.stat(pathname)?;
+  let time = |unixtime: u64| chrono::DateTime::from_timestamp(unixtime as i64, 0)
+    .map_or(unixtime.to_string(), |time| time.format("%Y-%m-%d %H:%M:%S").to_string());
+
+  println!("  File: {pathname}");
+  println!("  Size: {}\tInode: {}\tLinks: {}", stat.size, stat.inode_number, stat.links_count);
+  println!("  Mode: {} ({:o})\tUid: {}\tGid: {}", mode_string(stat.mode), stat.mode.0, stat.uid, stat.gid);
+  println!("Access: {}", time(stat.atime));
+  println!("Modify: {}", time(stat.mtime));
+  println!("Change: {}", time(stat.ctime));
+  println!(" Birth: {}", time(stat.btime));
+
+  Ok(())
+}
+
+fn cp_in(e5fs: &mut E5FSFilesystem, host_path: &str, pathname: &str, mode: &str, uid: Id, gid: Id) -> Result<(), Errno> {
+  let data = std::fs::read(host_path)
+    .with_context(|| format!("cannot read {host_path}"))?;
+
+  let vinode = match e5fs.lookup_path(pathname) {
+    Ok(vinode) if vinode.mode.file_type() == FileModeType::Dir as u8 => {
+      return Err(Errno::EISDIR(format!("{pathname}: Is a directory").into()))
+    },
+    Ok(vinode) => vinode,
+    Err(Errno::ENOENT(_)) => e5fs.create_file(pathname)?,
+    Err(errno) => return Err(errno),
+  };
+  e5fs.write_file(pathname, &data)?;
+  e5fs.change_mode(pathname, with_octal_mode(vinode.mode, mode)?)?;
+  e5fs.change_owners(pathname, uid, gid)
+}
+
+fn cp_out(e5fs: &mut E5FSFilesystem, pathname: &str, host_path: &str) -> Result<(), Errno> {
+  if e5fs.stat(pathname)?.mode.file_type() == FileModeType::Dir as u8 {
+    return Err(Errno::EISDIR(format!("{pathname}: Is a directory").into()));
+  }
+
+  let data = e5fs.read_file(pathname, EVERYTHING)?;
+  std::fs::write(host_path, data)
+    .with_context(|| format!("cannot write {host_path}"))
+}
+
+/// Push entries for `host_path` and everything in it onto `entries`,
+/// with pathnames under `pathname`. Host files that are neither
+/// regular files nor directories are skipped
+fn host_entries(host_path: &Path, pathname: &str, uid: Id, gid: Id, entries: &mut Vec<Entry>) -> Result<(), Errno> {
+  let metadata = std::fs::symlink_metadata(host_path)
+    .with_context(|| format!("cannot stat {}", host_path.display()))?;
+  let kind = match metadata.file_type() {
+    file_type if file_type.is_dir() => EntryKind::Dir,
+    file_type if file_type.is_file() => EntryKind::File(
+      std::fs::read(host_path).with_context(|| format!("cannot read {}", host_path.display()))?
+    ),
+    _ => {
+      println!("e5fs-tool: skipping {}: not a file or directory", host_path.display());
+      return Ok(());
+    },
+  };
+  entries.push(Entry {
+    pathname: pathname.to_owned(),
+    permissions: (metadata.mode() & 0o777) as u16,
+    uid,
+    gid,
+    mtime: metadata.mtime().max(0) as u64,
+    kind,
+  });
+
+  if metadata.is_dir() {
+    let mut children = std::fs::read_dir(host_path)
+      .and_then(|children| children.collect::<Result<Vec<_>, _>>())
+      .with_context(|| format!("cannot read {}", host_path.display()))?;
+    children.sort_by_key(|child| child.file_name());
+    for child in children {
+      let child_pathname = format!("{}/{}", pathname.trim_end_matches('/'), child.file_name().to_string_lossy());
+      host_entries(&child.path(), &child_pathname, uid, gid, entries)?;
+    }
+  }
+
+  Ok(())
+}
+
+/// Copy host directory `host_dir` into image at `pathname`, making
+/// it and its parents if they are not there
+fn import(e5fs: &mut E5FSFilesystem, host_dir: &str, pathname: &str, uid: Id, gid: Id) -> Result<(), Errno> {
+  // Read whole tree before touching image, so unreadable one leaves it as is
+  let mut entries = Vec::new();
+  host_entries(Path::new(host_dir), pathname, uid, gid, &mut entries)?;
+  if !matches!(entries.first(), Some(Entry { kind: EntryKind::Dir, .. })) {
+    return Err(Errno::ENOTDIR(format!("{host_dir}: Not a directory").into()));
+  }
+
+  ustar::unpack(e5fs, &entries)
+}
+
+/// Copy `pathname` of image and everything in it to `host_path`.
+/// Devices and other special files are skipped
+fn export(e5fs: &mut E5FSFilesystem, pathname: &str, host_path: &Path) -> Result<(), Errno> {
+  let stat = e5fs.stat(pathname)?;
+  match FileModeType::try_from(stat.mode.file_type()) {
+    Ok(FileModeType::Dir) => {
+      std::fs::create_dir_all(host_path)
+        .with_context(|| format!("cannot create {}", host_path.display()))?;
+      for name in e5fs.read_dir(pathname)?.entries.into_keys() {
+        if name == "." || name == ".." {
+          continue;
+        }
+        export(e5fs, &format!("{}/{name}", pathname.trim_end_matches('/')), &host_path.join(&name))?;
+      }
+    },
+    Ok(FileModeType::File) => {
+      std::fs::write(host_path, e5fs.read_file(pathname, EVERYTHING)?)
+        .with_context(|| format!("cannot write {}", host_path.display()))?;
+    },
+    // Link itself has no mode or times to keep
+    Ok(FileModeType::Symlink) => {
+      return std::os::unix::fs::symlink(e5fs.readlink(pathname)?, host_path)
+        .with_context(|| format!("cannot create {}", host_path.display()));
+    },
+    _ => {
+      println!("e5fs-tool: skipping {pathname}: not a file, directory or symlink");
+      return Ok(());
+    },
+  }
+
+  // Times go after children, as creating them changes times of
+  // directories, and before mode, which may not let file be opened
+  let times = FileTimes::new()
+    .set_accessed(UNIX_EPOCH + Duration::from_secs(stat.atime))
+    .set_modified(UNIX_EPOCH + Duration::from_secs(stat.mtime));
+  let permissions = (stat.mode.user() as u32) << 6 | (stat.mode.group() as u32) << 3 | stat.mode.others() as u32;
+  File::open(host_path)
+    .and_then(|file| file.set_times(times))
+    .and_then(|_| std::fs::set_permissions(host_path, Permissions::from_mode(permissions)))
+    .with_context(|| format!("cannot set mode and times of {}", host_path.display()))
+}
+
+/// Make e5fs in image at `realpath`, with contents of `rootfs` archive if there is one
+fn mkfs(realpath: &str, block_data_size: AddressSize, inode_table_percentage: f32, rootfs: Option<&str>) -> Result<(), Errno> {
+  // Read archive before touching image, so bad one leaves it as is
+  let entries = match rootfs {
+    Some(rootfs) => std::fs::read(rootfs)
+      .with_context(|| format!("cannot read {rootfs}"))
+      .and_then(|archive| ustar::parse(&archive))?,
+    None => Vec::new(),
+  };
+
+  let mut e5fs = E5FSFilesystem::mkfs(realpath, inode_table_percentage, block_data_size)?;
+  ustar::unpack(&mut e5fs, &entries)
+}
+
+fn run(args: ToolArgs) -> Result<(), Errno> {
+  if let Command::Mkfs { block_data_size, inode_table_percentage, rootfs } = args.command {
+    return mkfs(&args.image, block_data_size, inode_table_percentage, rootfs.as_deref());
+  }
+
+  let mut e5fs = E5FSFilesystem::from(&args.image)?;
+
+  let result = match args.command {
+    Command::Ls { pathname } => ls(&mut e5fs, &pathname),
+    Command::Cat { pathname } => e5fs
+      .read_file(&pathname, EVERYTHING)
+      .map(|data| print!("{}", String::from_utf8_lossy(&data))),
+    Command::Mkdir { parents, mode, pathname } => mkdir(&mut e5fs, &pathname, parents, &mode),
+    Command::Stat { pathname } => stat(&mut e5fs, &pathname),
+    Command::CpIn { mode, uid, gid, host_path, pathname } => cp_in(&mut e5fs, &host_path, &pathname, &mode, uid, gid),
+    Command::CpOut { pathname, host_path } => cp_out(&mut e5fs, &pathname, &host_path),
+    Command::Import { uid, gid, host_dir, pathname } => import(&mut e5fs, &host_dir, &pathname, uid, gid),
+    Command::Export { pathname, host_dir } => export(&mut e5fs, &pathname, Path::new(&host_dir)),
+    Command::Mkfs { .. } => unreachable!("e5fs-tool: mkfs is done before reading image"),
+  };
+
+  // Image is marked clean whether command succeeded or not
+  e5fs.unmount()?;
+  result
+}
+
+pub fn main() {
+  if let Err(errno) = run(ToolArgs::parse()) {
+    println!("e5fs-tool: {errno}");
+    std::process::exit(1);
+  }
+}
+
+#[cfg(test)]
+#[path = "../../tests/common/mod.rs"]
+mod common;
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::common::image;
+
+  #[test]
+  fn files_are_copied_into_image() {
+    let realpath = image("e5fs-tool", 1024 * 1024);
+    let host_path = format!("{realpath}.passwd");
+    std::fs::write(&host_path, "root:x:0:0::/root:\n").unwrap();
+    let mut e5fs = E5FSFilesystem::mkfs(&realpath, 0.05, 4096).unwrap();
+
+    mkdir(&mut e5fs, "/etc/skel", true, "755").unwrap();
+    cp_in(&mut e5fs, &host_path, "/etc/passwd", "600", 0, 0).unwrap();
+
+    assert_eq!(e5fs.read_file("/etc/passwd", EVERYTHING).unwrap(), b"root:x:0:0::/root:\n");
+    assert_eq!(mode_string(e5fs.stat("/etc/passwd").unwrap().mode), "-rw-------");
+    assert_eq!(mode_string(e5fs.stat("/etc/skel").unwrap().mode), "drwxr-xr-x");
+    assert!(matches!(mkdir(&mut e5fs, "/etc", false, "755"), Err(Errno::EEXIST(_))));
+    assert!(matches!(cp_in(&mut e5fs, &host_path, "/etc", "644", 0, 0), Err(Errno::EISDIR(_))));
+
+    let copied_path = format!("{realpath}.copied");
+    cp_out(&mut e5fs, "/etc/passwd", &copied_path).unwrap();
+    assert_eq!(std::fs::read(&copied_path).unwrap(), b"root:x:0:0::/root:\n");
+    assert!(matches!(cp_out(&mut e5fs, "/etc", &copied_path), Err(Errno::EISDIR(_))));
+    std::fs::remove_file(&copied_path).unwrap();
+
+    std::fs::remove_file(&realpath).unwrap();
+    std::fs::remove_file(&host_path).unwrap();
+  }
+
+  #[test]
+  fn host_directory_is_imported_and_exported() {
+    let realpath = image("e5fs-tool-import", 1024 * 1024);
+    let host_dir = Path::new(&format!("{realpath}.d")).to_owned();
+    std::fs::create_dir_all(host_dir.join("etc/skel")).unwrap();
+    std::fs::write(host_dir.join("etc/motd"), "hello\n").unwrap();
+    std::fs::set_permissions(host_dir.join("etc/motd"), Permissions::from_mode(0o640)).unwrap();
+    File::open(host_dir.join("etc/motd"))
+      .unwrap()
+      .set_modified(UNIX_EPOCH + Duration::from_secs(1_000_000))
+      .unwrap();
+    let mut e5fs = E5FSFilesystem::mkfs(&realpath, 0.05, 4096).unwrap();
+
+    import(&mut e5fs, &host_dir.to_string_lossy(), "/usr", 0, 0).unwrap();
+    assert_eq!(e5fs.read_file("/usr/etc/motd", EVERYTHING).unwrap(), b"hello\n");
+    assert_eq!(mode_string(e5fs.stat("/usr/etc/motd").unwrap().mode), "-rw-r-----");
+    assert_eq!(e5fs.stat("/usr/etc/motd").unwrap().mtime, 1_000_000);
+    assert!(e5fs.lookup_path("/usr/etc/skel").is_ok());
+    assert!(matches!(import(&mut e5fs, &host_dir.join("etc/motd").to_string_lossy(), "/", 0, 0), Err(Errno::ENOTDIR(_))));
+
+    let exported = host_dir.join("exported");
+    export(&mut e5fs, "/usr/etc", &exported).unwrap();
+    let metadata = std::fs::metadata(exported.join("motd")).unwrap();
+    assert_eq!(std::fs::read(exported.join("motd")).unwrap(), b"hello\n");
+    assert_eq!(metadata.mode() & 0o777, 0o640);
+    assert_eq!(metadata.mtime(), 1_000_000);
+    assert!(exported.join("skel").is_dir());
+
+    std::fs::remove_file(&realpath).unwrap();
+    std::fs::remove_dir_all(&host_dir).unwrap();
+  }
+}
+
+// vim:ts=2 sw=2
@@ -0,0 +1,63 @@
+use std::net::UdpSocket;
+
+use clap::Parser;
+use eunix::devices::MAX_FRAME_SIZE;
+use eunix::e5fs::E5FSFilesystem;
+use eunix::fs::Filesystem;
+use eunix::kernel::{Errno, ErrnoContext};
+use eunix::netfs;
+
+/// Export tree of e5fs image on host to eunix machines, which mount
+/// it with `mount -t netfs /dev/<nic> <target>`. Network interface
+/// of machine must have this server as its udp peer.
+/// Image must not be in use by a running machine
+#[derive(Debug, Parser)]
+#[clap(name = "netfs-server", version)]
+struct ServerArgs {
+  /// e5fs image on host, like `machines/1/devices/system.enxvd`
+  image: String,
+  /// Address to receive requests on, like `127.0.0.1:5640`
+  #[clap(short, long)]
+  bind: String,
+  /// Directory of image to export
+  #[clap(short, long, default_value = "/")]
+  root: String,
+  /// Exit after serving this many requests, serve forever if 0
+  #[clap(short, long, default_value_t = 0)]
+  count: usize,
+}
+
+fn run(args: ServerArgs) -> Result<(), Errno> {
+  let socket = UdpSocket::bind(&args.bind)
+    .with_context(|| format!("cannot bind {}", args.bind))?;
+  let mut e5fs = E5FSFilesystem::from(&args.image)?;
+
+  let mut served = 0;
+  let mut buffer = vec![0u8; MAX_FRAME_SIZE];
+  while args.count == 0 || served < args.count {
+    let (size, peer) = socket.recv_from(&mut buffer)
+      .with_context(|| "cannot receive")?;
+    // Datagrams that are not requests are not ours
+    let Some(call) = netfs::receive(&buffer[..size]) else {
+      continue;
+    };
+    // e5fs checks no permissions, only keeps blocks for root
+    e5fs.set_current_user(call.body.uid, call.body.gid);
+    if let Some(response) = netfs::respond(&mut e5fs, &args.root, call) {
+      socket.send_to(&response, peer)
+        .with_context(|| format!("cannot send to {peer}"))?;
+      served += 1;
+    }
+  }
+
+  e5fs.unmount()
+}
+
+pub fn main() {
+  if let Err(errno) = run(ServerArgs::parse()) {
+    println!("netfs-server: {errno}");
+    std::process::exit(1);
+  }
+}
+
+// vim:ts=2 sw=2
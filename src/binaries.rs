@@ -1,23 +1,36 @@
+use std::collections::BTreeMap;
 use std::fs::File;
 use std::process::Command;
-use crate::eunix::users::{Passwd, ParseError};
+use std::sync::{MappedMutexGuard, MutexGuard};
+use crate::eunix::users::{Passwd, ParseError, Shadow, AccountStatus, LOCKED_PREFIX};
+use crate::eunix::passwords::{self, PasswordScheme, DEFAULT_SCHEME};
+use crate::eunix::records::{self, Record, RecordType, WTMP_PATH, UTMP_PATH};
 
-use chrono::{DateTime, NaiveDateTime, Utc};
+use chrono::{DateTime, NaiveDateTime, TimeZone, Utc};
 use clap::Parser;
 use fancy_regex::Regex;
 use itertools::Itertools;
-use sha2::{Sha256, Digest};
-use std::io::{Read, Write, stdout, stdin};
-
+use std::io::{Read, Write};
+
+use crate::eunix::ustar;
+use crate::eunix::dmesg;
+use crate::eunix::watch;
+use crate::eunix::signal::{self, Signal};
+use crate::eunix::netfs;
+use crate::eunix::net::{Port, SocketAddress, SocketDescriptor, SocketType};
+use crate::eunix::devices::{BlockStorage, NET_MAJOR, MAX_FRAME_SIZE, TTYMode};
+use crate::eunix::line_editor::{Edit, LineEditor};
 use crate::eunix::devfs::DeviceFilesystem;
-use crate::eunix::fs::{FilesystemType, VINode, Id, NOBODY_UID, NOBODY_GID};
-use crate::eunix::kernel::{Times, ROOT_GID, ROOT_UID};
-use crate::util::{self, unixtime};
+use crate::eunix::sysfs::BLOCK_CLASS_PATH;
+use crate::eunix::fs::{FilesystemType, VINode, Id, NOBODY_UID, NOBODY_GID, NO_ADDRESS, EVERYTHING, DeviceNumber, OpenFlags, OpenMode, VDirectoryEntry, PERM_R, PERM_W, PERM_X};
+use crate::eunix::kernel::{MountOptions, Times, PowerAction, ROOT_GID, ROOT_UID, STDOUT_FILENO};
+use crate::util;
 use crate::{
   eunix::{
-    e5fs::E5FSFilesystem,
-    fs::{AddressSize, FileModeType, FileStat, Filesystem, VFS},
-    kernel::{Args, Errno, Kernel},
+    e5fs::{E5FSFilesystem, dump, fsck, trash, MAX_RESERVED_BLOCKS_PERCENTAGE, STATE_CLEAN},
+    partitions::{PartitionTable, SECTOR_SIZE},
+    fs::{AddressSize, FileMode, FileModeType, FileStat, Filesystem, VFS},
+    kernel::{Args, Errno, Kernel, UnixtimeSize},
   },
   machine::VirtualDeviceType,
 };
@@ -27,118 +40,447 @@ pub const EXIT_SUCCESS: AddressSize = 0;
 pub const EXIT_FAILURE: AddressSize = 1;
 
 pub const PASSWD_PATH: &'static str = "/etc/passwd";
+pub const GROUP_PATH: &'static str = "/etc/group";
+pub const SHADOW_PATH: &'static str = "/etc/shadow";
+pub const LOGIN_DEFS_PATH: &'static str = "/etc/login.defs";
+/// Initial contents of home directories made by `useradd`
+pub const SKEL_PATH: &'static str = "/etc/skel";
+/// Password field of `Passwd` which hash is in /etc/shadow
+pub const SHADOWED_PASSWORD: &'static str = "x";
+pub const CONSOLE_PATH: &'static str = "/dev/tty1";
+/// Controlling terminal of the caller
+pub const TTY_PATH: &'static str = "/dev/tty";
+pub const DEV_PATH: &'static str = "/dev";
+/// Where sysfs is mounted
+pub const SYS_PATH: &'static str = "/sys";
+
+/// Like `print!`, but to standard output of current process of
+/// `kernel`. Errors are ignored, like when stdout is closed
+macro_rules! kprint {
+  ($kernel:expr, $($arg:tt)*) => {{
+    let text = format!($($arg)*);
+    let _ = $kernel.write($crate::eunix::kernel::STDOUT_FILENO, text.into_bytes());
+  }};
+}
+pub(crate) use kprint;
+
+/// Like `println!`, see `kprint`
+macro_rules! kprintln {
+  ($kernel:expr) => {
+    $crate::binaries::kprint!($kernel, "\n")
+  };
+  ($kernel:expr, $($arg:tt)*) => {
+    $crate::binaries::kprint!($kernel, "{}\n", format_args!($($arg)*))
+  };
+}
+pub(crate) use kprintln;
+
+/// Like `eprintln!`, but to standard error of current process
+/// of `kernel`, see `kprint`
+macro_rules! keprintln {
+  ($kernel:expr, $($arg:tt)*) => {{
+    let text = format!("{}\n", format_args!($($arg)*));
+    let _ = $kernel.write($crate::eunix::kernel::STDERR_FILENO, text.into_bytes());
+  }};
+}
+pub(crate) use keprintln;
+
+/// Print `prompt` to the controlling terminal and read one line back,
+/// both through the kernel
+pub fn prompt_line(kernel: &mut Kernel, prompt: &str) -> Result<String, Errno> {
+  kernel.write_file(TTY_PATH, prompt.as_bytes())?;
+  let bytes = kernel.read_file(TTY_PATH, EVERYTHING)?;
+
+  String::from_utf8(bytes).or(Err(Errno::EILSEQ(format!("prompt_line: invalid utf8 read from {TTY_PATH}").into())))
+}
+
+/// Like `prompt_line`, but the line is typed with `editor`: terminal
+/// is in raw mode meanwhile. On Tab, `complete` is given the line up
+/// to cursor and the word before it, and returns what word can become.
+/// Returns: line with newline, what was typed at end of input,
+/// or empty line if nothing was
+pub fn edit_line(
+  kernel: &mut Kernel,
+  prompt: &str,
+  editor: &mut LineEditor,
+  complete: impl FnMut(&mut Kernel, &str, &str) -> Vec<String>,
+) -> Result<String, Errno> {
+  kernel.set_tty_mode(TTY_PATH, TTYMode::Raw)?;
+  let line = read_edited_line(kernel, prompt, editor, complete);
+  kernel.set_tty_mode(TTY_PATH, TTYMode::Canonical)?;
+
+  line
+}
+
+fn read_edited_line(
+  kernel: &mut Kernel,
+  prompt: &str,
+  editor: &mut LineEditor,
+  mut complete: impl FnMut(&mut Kernel, &str, &str) -> Vec<String>,
+) -> Result<String, Errno> {
+  kernel.write_file(TTY_PATH, prompt.as_bytes())?;
+  editor.start(prompt);
+
+  loop {
+    let Some(&byte) = kernel.read_file(TTY_PATH, 1)?.first() else {
+      return Ok(editor.line());
+    };
+    let (edit, output) = editor.feed(byte);
+    kernel.write_file(TTY_PATH, &output)?;
+
+    match edit {
+      Edit::Pending => (),
+      Edit::Line(line) => return Ok(format!("{line}\n")),
+      Edit::Complete(word) => {
+        let candidates = complete(kernel, &editor.line_before_cursor(), &word);
+        let output = editor.complete(&candidates);
+        kernel.write_file(TTY_PATH, &output)?;
+      },
+      Edit::Interrupted => {
+        kernel.write_file(TTY_PATH, prompt.as_bytes())?;
+        editor.start(prompt);
+      },
+      Edit::EndOfInput => return Ok(String::new()),
+    }
+  }
+}
+
+/// Days since unix epoch by clock of `kernel`, as used in /etc/shadow
+fn days_since_epoch(kernel: &Kernel) -> u64 {
+  kernel.clock.now() / (60 * 60 * 24)
+}
+
+/// Run `f` with root permissions on vfs, like setuid root binary would
+pub fn as_root<R>(kernel: &mut Kernel, f: impl FnOnce(&mut Kernel) -> R) -> R {
+  let (uid, gid) = (kernel.vfs_context.uid, kernel.vfs_context.gid);
+  kernel.set_vfs_ids(ROOT_UID, ROOT_GID);
+
+  let result = f(kernel);
+
+  kernel.set_vfs_ids(uid, gid);
+  result
+}
+
+/// Read entries of /etc/shadow, which is missing if there are none
+pub fn read_shadows(kernel: &mut Kernel) -> Result<Vec<Shadow>, Errno> {
+  as_root(kernel, |kernel| match kernel.read_file(SHADOW_PATH, EVERYTHING) {
+    Ok(bytes) => {
+      let contents = String::from_utf8(bytes)
+        .or(Err(Errno::EILSEQ(format!("read_shadows: invalid utf8 in {SHADOW_PATH}").into())))?;
+      Ok(Shadow::parse_shadows(&contents))
+    },
+    Err(Errno::ENOENT(_)) => Ok(Vec::new()),
+    Err(errno) => Err(errno),
+  })
+}
+
+/// Write entries of /etc/shadow, creating it readable only by root
+pub fn write_shadows(kernel: &mut Kernel, shadows: &[Shadow]) -> Result<(), Errno> {
+  as_root(kernel, |kernel| {
+    let result = kernel.vfs().lookup_path(SHADOW_PATH);
+    match result {
+      Ok(_) => (),
+      Err(Errno::ENOENT(_)) => {
+        // rw-------
+        let vinode = kernel.vfs().create_file(SHADOW_PATH)?;
+        kernel.vfs().change_mode(SHADOW_PATH, vinode.mode.with_user(0b110).with_group(0).with_others(0))?;
+      },
+      Err(errno) => return Err(errno),
+    }
+
+    kernel.vfs().write_file(SHADOW_PATH, Shadow::serialize_shadows(shadows).as_bytes())?;
+    Ok(())
+  })
+}
+
+/// Get password hash of `passwd`, consulting /etc/shadow if it's there
+pub fn password_hash(kernel: &mut Kernel, passwd: &Passwd) -> Result<String, Errno> {
+  // Hash is right in /etc/passwd
+  if passwd.password.starts_with(LOCKED_PREFIX) {
+    return Err(Errno::EACCES(format!("password_hash: account '{}' is locked", passwd.name).into()));
+  }
+  if passwd.password != SHADOWED_PASSWORD {
+    return Ok(passwd.password.clone());
+  }
+
+  let shadow = read_shadows(kernel)?
+    .into_iter()
+    .find(|shadow| shadow.name == passwd.name)
+    .ok_or(Errno::ENOENT(format!("password_hash: no '{}' in {SHADOW_PATH}", passwd.name).into()))?;
+
+  // Guard for locked and expired accounts
+  match shadow.status(days_since_epoch(kernel)) {
+    AccountStatus::Active => Ok(shadow.password),
+    AccountStatus::Locked => Err(Errno::EACCES(format!("password_hash: account '{}' is locked", passwd.name).into())),
+    AccountStatus::Expired => Err(Errno::EACCES(format!("password_hash: account '{}' has expired", passwd.name).into())),
+  }
+}
+
+/// Whether account of `passwd` can be logged into. Hashes still
+/// in /etc/passwd never expire, but can be locked all the same
+pub fn account_status(kernel: &mut Kernel, passwd: &Passwd) -> Result<AccountStatus, Errno> {
+  if passwd.password != SHADOWED_PASSWORD {
+    return Ok(match passwd.password.starts_with(LOCKED_PREFIX) {
+      true => AccountStatus::Locked,
+      false => AccountStatus::Active,
+    });
+  }
+
+  read_shadows(kernel)?
+    .into_iter()
+    .find(|shadow| shadow.name == passwd.name)
+    .map(|shadow| shadow.status(days_since_epoch(kernel)))
+    .ok_or(Errno::ENOENT(format!("account_status: no '{}' in {SHADOW_PATH}", passwd.name).into()))
+}
+
+/// Modify /etc/shadow entry of `name` with `f`, moving
+/// the hash there from /etc/passwd first if it's still there
+fn modify_shadow(kernel: &mut Kernel, name: &str, f: impl FnOnce(&mut Shadow)) -> Result<(), Errno> {
+  let passwd = as_root(kernel, |kernel| -> Result<_, Errno> {
+    let bytes = kernel.vfs().read_file(PASSWD_PATH, EVERYTHING)?;
+    let contents = String::from_utf8(bytes)
+      .or(Err(Errno::EILSEQ(format!("modify_shadow: invalid utf8 in {PASSWD_PATH}").into())))?;
+
+    Passwd::parse_passwds(&contents)
+      .into_iter()
+      .find(|passwd| passwd.name == name)
+      .ok_or(Errno::ENOENT(format!("modify_shadow: no '{name}' in {PASSWD_PATH}").into()))
+  })?;
+  if passwd.password != SHADOWED_PASSWORD {
+    set_shadow_password(kernel, name, passwd.password)?;
+    move_password_to_shadow(kernel, name)?;
+  }
+
+  let mut shadows = read_shadows(kernel)?;
+  let shadow = shadows
+    .iter_mut()
+    .find(|shadow| shadow.name == name)
+    .ok_or(Errno::ENOENT(format!("modify_shadow: no '{name}' in {SHADOW_PATH}").into()))?;
+  f(shadow);
+
+  write_shadows(kernel, &shadows)
+}
+
+/// Password scheme from `ENCRYPT_METHOD` of /etc/login.defs,
+/// or the default one if it's not set
+fn configured_scheme(kernel: &mut Kernel) -> Box<dyn PasswordScheme> {
+  let contents = kernel.vfs()
+    .read_file(LOGIN_DEFS_PATH, EVERYTHING)
+    .ok()
+    .and_then(|bytes| String::from_utf8(bytes).ok())
+    .unwrap_or_default();
+  let name = contents
+    .lines()
+    .filter_map(|line| line.trim().strip_prefix("ENCRYPT_METHOD"))
+    .map(str::trim)
+    .last()
+    .unwrap_or(DEFAULT_SCHEME);
+
+  passwords::scheme_by_name(name)
+    .or_else(|_| passwords::scheme_by_name(DEFAULT_SCHEME))
+    .expect("we know that default scheme exists")
+}
+
+/// Hash `password` with configured scheme and store it in /etc/shadow,
+/// moving hash of `name` out of /etc/passwd if it's still there
+fn store_password(kernel: &mut Kernel, name: &str, password: &str) -> Result<(), Errno> {
+  let hash = configured_scheme(kernel).hash(password, kernel.rng.as_ref())?;
+  set_shadow_password(kernel, name, hash)?;
+
+  move_password_to_shadow(kernel, name)
+}
+
+/// Replace password of `name` in /etc/passwd with `x`,
+/// its hash must be in /etc/shadow already
+fn move_password_to_shadow(kernel: &mut Kernel, name: &str) -> Result<(), Errno> {
+  as_root(kernel, |kernel| {
+    let bytes = kernel.vfs().read_file(PASSWD_PATH, EVERYTHING)?;
+    let contents = String::from_utf8(bytes)
+      .or(Err(Errno::EILSEQ(format!("move_password_to_shadow: invalid utf8 in {PASSWD_PATH}").into())))?;
+    let mut passwds = Passwd::parse_passwds(&contents);
+
+    match passwds.iter_mut().find(|passwd| passwd.name == name) {
+      Some(passwd) if passwd.password != SHADOWED_PASSWORD => {
+        passwd.password = String::from(SHADOWED_PASSWORD);
+        kernel.vfs().write_file(PASSWD_PATH, Passwd::serialize_passwds(&passwds).as_bytes())?;
+      },
+      _ => (),
+    }
+
+    Ok(())
+  })
+}
+
+/// Check `password` of `passwd`. On success, hashes made by schemes
+/// other than configured one are transparently rehashed
+pub fn authenticate(kernel: &mut Kernel, passwd: &Passwd, password: &str) -> Result<bool, Errno> {
+  let hash = password_hash(kernel, passwd)?;
+  if !passwords::verify(password, &hash)? {
+    return Ok(false);
+  }
+
+  if passwords::needs_upgrade(&hash, configured_scheme(kernel).as_ref()) {
+    store_password(kernel, &passwd.name, password)?;
+  }
+
+  Ok(true)
+}
+
+/// Set password hash of `name` in /etc/shadow, resetting its last change
+fn set_shadow_password(kernel: &mut Kernel, name: &str, password_hash: String) -> Result<(), Errno> {
+  let mut shadows = read_shadows(kernel)?;
+
+  match shadows.iter_mut().find(|shadow| shadow.name == name) {
+    Some(shadow) => {
+      shadow.password = password_hash;
+      shadow.last_change = days_since_epoch(kernel);
+    },
+    None => shadows.push(Shadow {
+      name: name.to_owned(),
+      password: password_hash,
+      last_change: days_since_epoch(kernel),
+      expire: None,
+    }),
+  }
+
+  write_shadows(kernel, &shadows)
+}
 
 // FS reading stuff
 
+/// How many entries `ls` asks for at a time
+const LS_BATCH_SIZE: AddressSize = 64;
+
 pub fn ls(args: Args, kernel: &mut Kernel) -> AddressSize {
   let arg0 = args.get(0).unwrap().clone();
-  if let Some(pathname) = args.get(1) {
-    let _parent_dir = match VFS::parent_dir(pathname) {
-        Ok(parent_dir) => parent_dir,
-        Err(Errno::EINVAL(message)) => {
-          println!("{arg0}: invalid path: {message}");
-          return 1;
-        },
-        Err(errno) => {
-          println!("{arg0}: invalid path: {errno:?}");
-          return 1;
-        },
-    };
+  // Working directory by default
+  let pathname = args.get(1).map(String::as_str).unwrap_or(".");
+  let absolute_pathname = kernel.vfs().absolute_path(pathname);
+  let _parent_dir = match VFS::parent_dir(&absolute_pathname) {
+      Ok(parent_dir) => parent_dir,
+      Err(Errno::EINVAL(message)) => {
+        kprintln!(kernel, "{arg0}: invalid path: {message}");
+        return 1;
+      },
+      Err(errno) => {
+        kprintln!(kernel, "{arg0}: invalid path: {errno}");
+        return 1;
+      },
+  };
+
+  let file_descriptor = match kernel.open(pathname, OpenFlags::new(OpenMode::Read, false, false)) {
+    Ok(file_descriptor) => file_descriptor,
+    Err(Errno::ENOENT(_)) => {
+      kprintln!(kernel, "{arg0}: cannot access '{pathname}': No such file or directory");
+      return EXIT_ENOENT;
+    },
+    Err(Errno::EACCES(_)) => {
+      kprintln!(kernel, "{arg0}: '{pathname}': Permission denied");
+      return EXIT_FAILURE
+    },
+    Err(errno) => {
+      kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+      return 1;
+    }
+  };
 
-    let dir = match kernel.vfs.read_dir(&pathname) {
-      Ok(dir) => dir,
+  // Directory is listed a part at a time, so big one is
+  // not read whole
+  loop {
+    let entries = match kernel.getdents(file_descriptor, LS_BATCH_SIZE) {
+      Ok(entries) if entries.is_empty() => break,
+      Ok(entries) => entries,
       Err(Errno::ENOTDIR(_)) => {
-        println!("{arg0}: not a directory: {pathname}");
+        kprintln!(kernel, "{arg0}: not a directory: {pathname}");
+        let _ = kernel.close(file_descriptor);
         return 1;
       },
-      Err(Errno::EACCES(_)) => {
-        println!("{arg0}: '{pathname}': Permission denied");
-        return EXIT_FAILURE
-      },
       Err(errno) => {
-        println!("{arg0}: unexpected error: {errno:?}");
+        kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+        let _ = kernel.close(file_descriptor);
         return 1;
       }
     };
 
-    for (child_name, _) in dir.entries {
+    for VDirectoryEntry { name: child_name, .. } in entries {
       let child_pathname = format!("{pathname}/{child_name}");
       let vinode = kernel
-        .vfs
-        .lookup_path(&child_pathname)
+        .vfs()
+        .lookup_link(&child_pathname)
         .expect(&format!("{arg0}: we know that {child_pathname} exists"));
 
       // Print file type
       match vinode.mode.file_type().try_into().unwrap() {
-        FileModeType::Dir => print!("d"),
-        FileModeType::File => print!("-"),
-        FileModeType::Sys => print!("s"),
-        FileModeType::Block => print!("b"),
-        FileModeType::Char => print!("c"),
+        FileModeType::Dir => kprint!(kernel, "d"),
+        FileModeType::File => kprint!(kernel, "-"),
+        FileModeType::Sys => kprint!(kernel, "s"),
+        FileModeType::Block => kprint!(kernel, "b"),
+        FileModeType::Char => kprint!(kernel, "c"),
+        FileModeType::Symlink => kprint!(kernel, "l"),
       }
 
       // Print file permissions
       // User - read
       if util::get_bit_at(vinode.mode.user(), 2) {
-        print!("r");
+        kprint!(kernel, "r");
       } else {
-        print!("-");
+        kprint!(kernel, "-");
       }
       // User - write
       if util::get_bit_at(vinode.mode.user(), 1) {
-        print!("w");
+        kprint!(kernel, "w");
       } else {
-        print!("-");
+        kprint!(kernel, "-");
       }
       // User - execute
       if util::get_bit_at(vinode.mode.user(), 0) {
-        print!("x");
+        kprint!(kernel, "x");
       } else {
-        print!("-");
+        kprint!(kernel, "-");
       }
       // group - read
       if util::get_bit_at(vinode.mode.group(), 2) {
-        print!("r");
+        kprint!(kernel, "r");
       } else {
-        print!("-");
+        kprint!(kernel, "-");
       }
       // group - write
       if util::get_bit_at(vinode.mode.group(), 1) {
-        print!("w");
+        kprint!(kernel, "w");
       } else {
-        print!("-");
+        kprint!(kernel, "-");
       }
       // group - execute
       if util::get_bit_at(vinode.mode.group(), 0) {
-        print!("x");
+        kprint!(kernel, "x");
       } else {
-        print!("-");
+        kprint!(kernel, "-");
       }
       // others - read
       if util::get_bit_at(vinode.mode.others(), 2) {
-        print!("r");
+        kprint!(kernel, "r");
       } else {
-        print!("-");
+        kprint!(kernel, "-");
       }
       // others - write
       if util::get_bit_at(vinode.mode.others(), 1) {
-        print!("w");
+        kprint!(kernel, "w");
       } else {
-        print!("-");
+        kprint!(kernel, "-");
       }
       // others - execute
       if util::get_bit_at(vinode.mode.others(), 0) {
-        print!("x");
+        kprint!(kernel, "x");
       } else {
-        print!("-");
+        kprint!(kernel, "-");
       }
 
-      print!("\t");
+      kprint!(kernel, "\t");
 
       // Links count
-      print!("{}", vinode.links_count);
+      kprint!(kernel, "{}", vinode.links_count);
 
-      print!("\t");
+      kprint!(kernel, "\t");
 
       // User and group owners
       let user = kernel
@@ -151,13 +493,18 @@ pub fn ls(args: Args, kernel: &mut Kernel) -> AddressSize {
         .get(&vinode.gid)
         .unwrap_or(&format!("<gid{}>", vinode.gid))
         .clone();
-      print!("{user} {group}");
+      kprint!(kernel, "{user} {group}");
 
-      print!("\t");
+      kprint!(kernel, "\t");
 
-      print!("{}", vinode.file_size);
+      // Device number for special files, size otherwise
+      if vinode.is_device() {
+        kprint!(kernel, "{}", vinode.rdev);
+      } else {
+        kprint!(kernel, "{}", vinode.file_size);
+      }
 
-      print!("\t");
+      kprint!(kernel, "\t");
 
       // Date and time
       // Create a NaiveDateTime from the timestamp
@@ -168,22 +515,26 @@ pub fn ls(args: Args, kernel: &mut Kernel) -> AddressSize {
 
       // Format the datetime how you want
       let human_readable_date = datetime.format("%Y-%m-%d %H:%M:%S");
-      print!("{}", human_readable_date);
+      kprint!(kernel, "{}", human_readable_date);
 
-      print!("\t");
+      kprint!(kernel, "\t");
 
       // Finally, file name, and newline for the next
-      println!("{}", child_name);
+      let result = kernel.vfs().readlink(&child_pathname);
+      match result {
+        Ok(target) if vinode.mode.file_type() == FileModeType::Symlink as u8 => kprintln!(kernel, "{child_name} -> {target}"),
+        _ => kprintln!(kernel, "{}", child_name),
+      }
     }
-    0
-  } else {
-    1
   }
+  let _ = kernel.close(file_descriptor);
+  0
 }
 
 pub fn stat(args: Args, kernel: &mut Kernel) -> AddressSize {
   let arg0 = args.get(0).unwrap().clone();
   if let Some(pathname) = args.get(1) {
+    let result = kernel.vfs().stat(&pathname);
     let FileStat {
       mode,
       size,
@@ -191,23 +542,24 @@ pub fn stat(args: Args, kernel: &mut Kernel) -> AddressSize {
       links_count,
       uid,
       gid,
+      rdev,
       block_size,
       atime,
       mtime,
       ctime,
       btime,
-    } = match kernel.vfs.stat(&pathname) {
+    } = match result {
       Ok(stat) => stat,
       Err(Errno::ENOENT(_)) => {
-        println!("{arg0}: {pathname}: No such file or directory");
+        kprintln!(kernel, "{arg0}: {pathname}: No such file or directory");
         return EXIT_ENOENT;
       },
       Err(Errno::EACCES(_)) => {
-        println!("{arg0}: '{pathname}': Permission denied");
+        kprintln!(kernel, "{arg0}: '{pathname}': Permission denied");
         return EXIT_FAILURE
       },
       Err(errno) => {
-        println!("{arg0}: unexpected error: {errno:?}");
+        kprintln!(kernel, "{arg0}: unexpected error: {errno}");
         return EXIT_FAILURE;
       }
     };
@@ -236,34 +588,200 @@ pub fn stat(args: Args, kernel: &mut Kernel) -> AddressSize {
       .get(&gid)
       .unwrap_or(&String::from("<no name>"))
       .clone();
-    println!("  File: {pathname}");
-    println!("  Size: {size}\tBlocks: {blocks_count}\t{file_type}");
-    println!("Device: <unknown>\tInode: {inode_number}\tLinks: {links_count}");
-    println!("Access: {file_mode_raw:o}\tUid: ({uid}/{user})\tGid: ({gid}/{group})");
-    println!("Access: {atime_human}");
-    println!("Modify: {mtime_human}");
-    println!("Change: {ctime_human}");
-    println!(" Birth: {btime_human}");
+    kprintln!(kernel, "  File: {pathname}");
+    kprintln!(kernel, "  Size: {size}\tBlocks: {blocks_count}\t{file_type}");
+    match file_type {
+      FileModeType::Block | FileModeType::Char => {
+        kprintln!(kernel, "Device: <unknown>\tInode: {inode_number}\tLinks: {links_count}\tDevice type: {rdev}");
+      },
+      _ => {
+        kprintln!(kernel, "Device: <unknown>\tInode: {inode_number}\tLinks: {links_count}");
+      },
+    }
+    kprintln!(kernel, "Access: {file_mode_raw:o}\tUid: ({uid}/{user})\tGid: ({gid}/{group})");
+    kprintln!(kernel, "Access: {atime_human}");
+    kprintln!(kernel, "Modify: {mtime_human}");
+    kprintln!(kernel, "Change: {ctime_human}");
+    kprintln!(kernel, " Birth: {btime_human}");
     EXIT_SUCCESS
   } else {
     EXIT_FAILURE
   }
 }
 
-pub fn df(args: Args, kernel: &mut Kernel) -> AddressSize {
+/// Size in powers of 1024 like `df -h` prints it, rounded up: 512, 1.5K, 12M
+fn format_human_size(size: AddressSize) -> String {
+  let mut scaled = size as f64;
+  let mut unit = 0;
+  while scaled >= 1024.0 && unit < 4 {
+    scaled /= 1024.0;
+    unit += 1;
+  }
+
+  let suffix = ["", "K", "M", "G", "T"][unit];
+  match unit {
+    0 => format!("{size}"),
+    _ if scaled < 10.0 => format!("{:.1}{suffix}", (scaled * 10.0).ceil() / 10.0),
+    _ => format!("{}{suffix}", scaled.ceil()),
+  }
+}
+
+/// Check file at pathname: `test [-e] [-f] [-d] [-r] [-w] [-x]
+/// <pathname>`. Succeeds if all that is asked holds, nothing is
+/// printed. Permissions are checked like `access` does
+pub fn test(args: Args, kernel: &mut Kernel) -> AddressSize {
   let arg0 = args.get(0).unwrap().clone();
   #[derive(Debug, Parser)]
   struct BinArgs {
+    /// File exists
+    #[clap(short)]
+    e: bool,
+    /// It is a regular file
+    #[clap(short)]
+    f: bool,
+    /// It is a directory
+    #[clap(short)]
+    d: bool,
+    /// It can be read
+    #[clap(short)]
+    r: bool,
+    /// It can be written
+    #[clap(short)]
+    w: bool,
+    /// It can be executed, or searched if it is a directory
+    #[clap(short)]
+    x: bool,
+
     pathname: String,
   }
 
   match BinArgs::try_parse_from(args.iter()) {
     Err(message) => {
-      println!("{arg0}: invalid arguments: {message}");
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
+      2
+    }
+    Ok(BinArgs { f, d, r, w, x, pathname, .. }) => {
+      let result = kernel.vfs().stat(&pathname);
+      let file_type = match result {
+        Ok(stat) => stat.mode.file_type(),
+        Err(_) => return EXIT_FAILURE,
+      };
+      if (f && file_type != FileModeType::File as u8) || (d && file_type != FileModeType::Dir as u8) {
+        return EXIT_FAILURE;
+      }
+
+      let mask = [(r, PERM_R), (w, PERM_W), (x, PERM_X)]
+        .into_iter()
+        .filter(|(is_asked, _)| *is_asked)
+        .fold(0, |mask, (_, permission)| mask | permission);
+      match kernel.access(&pathname, mask) {
+        Ok(()) => EXIT_SUCCESS,
+        Err(_) => EXIT_FAILURE,
+      }
+    },
+  }
+}
+
+pub fn df(args: Args, kernel: &mut Kernel) -> AddressSize {
+  let arg0 = args.get(0).unwrap().clone();
+  #[derive(Debug, Parser)]
+  struct BinArgs {
+    /// List inodes instead of blocks
+    #[clap(short, long)]
+    inodes: bool,
+
+    /// Files on filesystems to show, every mounted one if none
+    pathnames: Vec<String>,
+  }
+
+  match BinArgs::try_parse_from(args.iter()) {
+    Err(message) => {
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
       1
     }
-    Ok(BinArgs { pathname }) => {
-      EXIT_SUCCESS
+    Ok(BinArgs { inodes, pathnames }) => {
+      let pathnames = match pathnames.is_empty() {
+        true => kernel.vfs().mount_points.keys().cloned().collect(),
+        false => pathnames,
+      };
+
+      match inodes {
+        true => kprintln!(kernel, "{:<10} {:>7} {:>7} {:>7} {:>5} Mounted on", "Filesystem", "Inodes", "IUsed", "IFree", "IUse%"),
+        false => kprintln!(kernel, "{:<10} {:>6} {:>6} {:>6} {:>4} Mounted on", "Filesystem", "Size", "Used", "Avail", "Use%"),
+      }
+
+      let mut status = EXIT_SUCCESS;
+      for pathname in pathnames {
+        let stat = kernel.vfs().statfs(&pathname);
+        let mount_point = kernel.vfs().match_mount_point(&pathname);
+        let (stat, mount_point) = match (stat, mount_point) {
+          (Ok(stat), Ok((mount_point, _))) => (stat, mount_point),
+          (Err(errno), _) | (_, Err(errno)) => {
+            kprintln!(kernel, "{arg0}: {pathname}: {errno}");
+            status = EXIT_FAILURE;
+            continue;
+          },
+        };
+        let r#type = kernel.vfs().mount_points[&mount_point].r#type.to_string();
+
+        // Filesystems without blocks or inodes have no use
+        let percentage = |used: AddressSize, total: AddressSize| match total {
+          0 => String::from("-"),
+          total => format!("{}%", (used * 100).div_ceil(total)),
+        };
+        match inodes {
+          true => {
+            let used = stat.inodes_count - stat.free_inodes_count;
+            kprintln!(kernel,
+              "{:<10} {:>7} {:>7} {:>7} {:>5} {mount_point}",
+              r#type,
+              stat.inodes_count,
+              used,
+              stat.free_inodes_count,
+              percentage(used, stat.inodes_count),
+            );
+          },
+          false => {
+            let used = stat.blocks_count - stat.free_blocks_count;
+            // Blocks reserved for root are not available to others,
+            // like df of Linux, use is out of what users can have
+            let available = stat.free_blocks_count.saturating_sub(stat.reserved_blocks_count);
+            kprintln!(kernel,
+              "{:<10} {:>6} {:>6} {:>6} {:>4} {mount_point}",
+              r#type,
+              format_human_size(stat.blocks_count * stat.block_size),
+              format_human_size(used * stat.block_size),
+              format_human_size(available * stat.block_size),
+              percentage(used * stat.block_size, (used + available) * stat.block_size),
+            );
+          },
+        }
+      }
+
+      status
+    },
+  }
+}
+
+pub fn sync(args: Args, kernel: &mut Kernel) -> AddressSize {
+  let arg0 = args.get(0).unwrap().clone();
+  #[derive(Debug, Parser)]
+  struct BinArgs {}
+
+  match BinArgs::try_parse_from(args.iter()) {
+    Err(message) => {
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
+      1
+    }
+    Ok(BinArgs {}) => {
+      let result = kernel.vfs().sync();
+      match result {
+        Ok(()) => EXIT_SUCCESS,
+        Err(errno) => {
+          kprintln!(kernel, "{arg0}: {errno}");
+          EXIT_FAILURE
+        },
+      }
     },
   }
 }
@@ -277,7 +795,7 @@ pub fn du(args: Args, kernel: &mut Kernel) -> AddressSize {
 
   match BinArgs::try_parse_from(args.iter()) {
     Err(message) => {
-      println!("{arg0}: invalid arguments: {message}");
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
       1
     }
     Ok(BinArgs { pathname }) => {
@@ -286,6 +804,9 @@ pub fn du(args: Args, kernel: &mut Kernel) -> AddressSize {
   }
 }
 
+/// How many bytes `cat` reads at a time
+const CAT_BUFFER_SIZE: AddressSize = 4096;
+
 pub fn cat(args: Args, kernel: &mut Kernel) -> AddressSize {
   let arg0 = args.get(0).unwrap().clone();
   // #[derive(Debug, Parser)]
@@ -294,51 +815,66 @@ pub fn cat(args: Args, kernel: &mut Kernel) -> AddressSize {
   // }
   
   if args[1..].is_empty() {
-    println!("{arg0}: no files to concatenate");
+    kprintln!(kernel, "{arg0}: no files to concatenate");
     return 1;
   }
 
-  let mut concatenated_bytes = Vec::new();
+  // Last byte printed, for the newline at the end
+  let mut last_byte = None;
 
   for pathname in args[1..].to_vec() {
-    // For every pathname check for errors and return or append bytes to result
-    let mut bytes = match kernel.vfs.read_file(&pathname, AddressSize::MAX) {
+    let file_descriptor = match kernel.open(&pathname, OpenFlags::new(OpenMode::Read, false, false)) {
+      Ok(file_descriptor) => file_descriptor,
+      Err(Errno::ENOENT(_)) => {
+        kprintln!(kernel, "{arg0}: {pathname}: No such file or directory");
+        return EXIT_ENOENT;
+      },
+      Err(Errno::EACCES(_)) => {
+        kprintln!(kernel, "{arg0}: '{pathname}': Permission denied");
+        return EXIT_FAILURE
+      },
+      Err(errno) => {
+        kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+        return EXIT_FAILURE;
+      },
+    };
+    // Devices give what they have at once, they have no end to read up to
+    let is_device = matches!(
+      kernel.stat(file_descriptor),
+      Ok(stat) if [FileModeType::Block as u8, FileModeType::Char as u8].contains(&stat.mode.file_type())
+    );
+
+    // File is printed a part at a time, so big one is not read whole
+    loop {
+      let bytes = match kernel.read(file_descriptor, CAT_BUFFER_SIZE) {
         Ok(bytes) => bytes,
-        Err(Errno::ENOENT(_)) => {
-          println!("{arg0}: {pathname}: No such file or directory");
-          return EXIT_ENOENT;
-        },
         Err(Errno::EISDIR(_)) => {
-          println!("{arg0}: {pathname}: Is a directory");
+          kprintln!(kernel, "{arg0}: {pathname}: Is a directory");
+          let _ = kernel.close(file_descriptor);
           return EXIT_FAILURE;
         },
-        Err(Errno::EACCES(_)) => {
-          println!("{arg0}: '{pathname}': Permission denied");
-          return EXIT_FAILURE
-        },
         Err(errno) => {
-          println!("{arg0}: unexpected error: {errno:?}");
+          kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+          let _ = kernel.close(file_descriptor);
           return EXIT_FAILURE;
         },
-    };
-    concatenated_bytes.append(&mut bytes);
+      };
+      let is_last = bytes.is_empty() || is_device;
+      if let Some(&byte) = bytes.last() {
+        last_byte = Some(byte);
+        let _ = kernel.write(STDOUT_FILENO, bytes);
+      }
+      if is_last {
+        break;
+      }
+    }
+    let _ = kernel.close(file_descriptor);
   }
 
-  // Try to parse utf8 from file (most probably succeeds)
-  let utf8_string = match std::str::from_utf8(&concatenated_bytes) {
-      Ok(utf8_string) => utf8_string,
-      Err(utf8error) => {
-        println!("{arg0}: can't parse utf8: {utf8error}");
-        return EXIT_FAILURE;
-      },
-  };
-
   // Guard for having '\n' at the end (for some reason gets inserted by nvim or whatnot)
-  if let Some(char) = utf8_string.chars().last() && char == '\n' {
-    print!("{utf8_string}")
-  } else {
-    println!("{utf8_string}")
-  };
+  if last_byte.is_some_and(|byte| byte != b'\n') {
+    kprintln!(kernel);
+  }
 
   0
 }
@@ -355,408 +891,571 @@ pub fn mkfs_e5fs(args: Args, kernel: &mut Kernel) -> AddressSize {
     #[clap(short, long, default_value_t = 0.1)]
     inode_table_percentage: f32,
 
+    /// Volume label, to mount it by with `LABEL=`
+    #[clap(short = 'L', long)]
+    label: Option<String>,
+
+    /// Unpack this ustar archive into the new filesystem
+    #[clap(long)]
+    rootfs: Option<String>,
+
+    /// Encrypt data with a passphrase, which is asked for
+    #[clap(long)]
+    encrypt: bool,
+
+    /// Percentage of blocks that only root can use
+    #[clap(short = 'm', long, default_value_t = 5.0)]
+    reserved_blocks_percentage: f32,
+
     device_pathname: String,
   }
 
   match BinArgs::try_parse_from(args.iter()) {
     Err(message) => {
-      println!("{arg0}: invalid arguments: {message}");
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
       1
     }
     Ok(parsed_args) => {
       let dev_pathname = parsed_args.device_pathname;
-      let (mount_point, internal_pathname) = kernel.vfs.match_mount_point(&dev_pathname).unwrap();
-      let mounted_fs = kernel.vfs.mount_points.get_mut(&mount_point).expect("{arg0}::lookup_path: we know that mount_point exist"); 
 
-      let device_realpath = if mounted_fs.r#type == FilesystemType::devfs {
-        match mounted_fs
-          .driver
-          .as_any()
-          .downcast_ref::<DeviceFilesystem>()
-          .expect("we know that mounted_fs.driver === instanceof DeviceFilesystem")
-          .device_by_pathname(&internal_pathname) 
-        {
-            Ok(realpath) => realpath,
-            Err(Errno::ENOENT(_)) => {
-              println!("{arg0}: {dev_pathname}: No such file or directory");
-              return EXIT_ENOENT;
-            },
-            Err(errno) => {
-              println!("{arg0}: unexpected error: {errno:?}");
-              return EXIT_FAILURE;
-            },
-        }
-      } else {
-        println!("{arg0}: {dev_pathname}: Not a device");
+      if let Some(Err(Errno::EINVAL(message))) = parsed_args.label.as_deref().map(E5FSFilesystem::parse_label) {
+        kprintln!(kernel, "{arg0}: {message}");
+        return EXIT_FAILURE;
+      }
+      if !(0.0..=MAX_RESERVED_BLOCKS_PERCENTAGE).contains(&parsed_args.reserved_blocks_percentage) {
+        kprintln!(kernel, "{arg0}: reserved blocks percentage must be between 0 and {MAX_RESERVED_BLOCKS_PERCENTAGE}");
         return EXIT_FAILURE;
+      }
+
+      // Read archive before touching device, so bad one leaves it as is
+      let rootfs_entries = match &parsed_args.rootfs {
+        Some(rootfs) => match kernel.read_file(rootfs, EVERYTHING).and_then(|archive| ustar::parse(&archive)) {
+          Ok(entries) => Some(entries),
+          Err(Errno::ENOENT(_)) => {
+            kprintln!(kernel, "{arg0}: {rootfs}: No such file or directory");
+            return EXIT_ENOENT;
+          },
+          Err(Errno::EINVAL(message)) => {
+            kprintln!(kernel, "{arg0}: {rootfs}: {message}");
+            return EXIT_FAILURE;
+          },
+          Err(errno) => {
+            kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+            return EXIT_FAILURE;
+          },
+        },
+        None => None,
       };
 
-      match E5FSFilesystem::mkfs(
-        &device_realpath, 
-        parsed_args.inode_table_percentage, 
-        parsed_args.block_data_size
-      ) {
-        Ok(_) => EXIT_SUCCESS,
+      let passphrase = match parsed_args.encrypt {
+        true => match prompt_line(kernel, "Passphrase: ")
+          .and_then(|passphrase_one| Ok((passphrase_one, prompt_line(kernel, "Retype passphrase: ")?)))
+        {
+          Ok((passphrase_one, passphrase_two)) if passphrase_one != passphrase_two => {
+            kprintln!(kernel, "{arg0}: passphrases do not match");
+            return EXIT_FAILURE;
+          },
+          Ok((passphrase, _)) if passphrase.trim_end_matches('\n').is_empty() => {
+            kprintln!(kernel, "{arg0}: passphrase can't be empty");
+            return EXIT_FAILURE;
+          },
+          Ok((passphrase, _)) => Some(passphrase.trim_end_matches('\n').to_owned()),
+          Err(errno) => {
+            kprintln!(kernel, "{arg0}: cannot read passphrase: {errno}");
+            return EXIT_FAILURE;
+          },
+        },
+        false => None,
+      };
+
+      // Validate device before touching it
+      let result = kernel.vfs().stat(&dev_pathname);
+      match result {
+        Ok(FileStat { mode, .. }) if mode.file_type() != FileModeType::Block as u8 => {
+          kprintln!(kernel, "{arg0}: {dev_pathname}: Not a block device");
+          return EXIT_FAILURE;
+        },
+        Ok(FileStat { size, .. }) if size < parsed_args.block_data_size * 2 => {
+          kprintln!(kernel, "{arg0}: {dev_pathname}: Device of {size} bytes is too small for block size {}", parsed_args.block_data_size);
+          return EXIT_FAILURE;
+        },
+        Ok(_) => (),
+        Err(Errno::ENOENT(_)) => {
+          kprintln!(kernel, "{arg0}: {dev_pathname}: No such file or directory");
+          return EXIT_ENOENT;
+        },
         Err(errno) => {
-          println!("{arg0}: unexpected error: {errno:?}");
+          kprintln!(kernel, "{arg0}: unexpected error: {errno}");
           return EXIT_FAILURE;
         },
       }
-    }
-  }
-}
-
-pub fn mkdir(args: Args, kernel: &mut Kernel) -> AddressSize {
-  let arg0 = args.get(0).unwrap().clone();
-  #[derive(Debug, Parser)]
-  struct BinArgs {
-    pathname: String,
-  }
 
-  match BinArgs::try_parse_from(args.iter()) {
-    Err(message) => {
-      println!("{arg0}: invalid arguments: {message}");
-      1
-    },
-    Ok(BinArgs { pathname }) => {
-      match kernel.vfs.create_dir(&pathname) {
-        Ok(_) => EXIT_SUCCESS,
-        Err(Errno::ENOENT(_)) => {
-          println!("{arg0}: cannot create directory: '{pathname}': No such file or directory");
-          EXIT_ENOENT
+      let (mount_point, internal_pathname) = kernel.vfs().match_mount_point(&dev_pathname).unwrap();
+      let mut vfs = kernel.vfs();
+      let mounted_fs = vfs.mount_points.get_mut(&mount_point).expect("{arg0}::lookup_path: we know that mount_point exist");
+      let storage = (mounted_fs.r#type == FilesystemType::devfs).then(|| mounted_fs
+        .driver
+        .as_any()
+        .downcast_ref::<DeviceFilesystem>()
+        .expect("we know that mounted_fs.driver === instanceof DeviceFilesystem")
+        .open_block_storage(&internal_pathname)
+      );
+      drop(vfs);
+
+      let storage = match storage {
+        Some(Ok(storage)) => storage,
+        Some(Err(Errno::ENOENT(_))) => {
+          kprintln!(kernel, "{arg0}: {dev_pathname}: No such file or directory");
+          return EXIT_ENOENT;
         },
-        Err(Errno::EACCES(_)) => {
-          println!("{arg0}: '{pathname}': Permission denied");
-          return EXIT_FAILURE
+        Some(Err(errno)) => {
+          kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+          return EXIT_FAILURE;
         },
-        Err(errno) => {
-          println!("{arg0}: unexpected error: {errno:?}");
-          EXIT_FAILURE
+        None => {
+          kprintln!(kernel, "{arg0}: {dev_pathname}: Not a device");
+          return EXIT_FAILURE;
         },
-      }
-    },
-  }
-}
-
-pub fn rmdir(args: Args, kernel: &mut Kernel) -> AddressSize {
-  let arg0 = args.get(0).unwrap().clone();
-  #[derive(Debug, Parser)]
-  struct BinArgs {
-    pathname: String,
-  }
-
-  match BinArgs::try_parse_from(args.iter()) {
-    Err(message) => {
-      println!("{arg0}: invalid arguments: {message}");
-      1
-    }
-    Ok(BinArgs { pathname }) => {
-      EXIT_SUCCESS
-    },
-  }
-}
+      };
 
-pub fn touch(args: Args, kernel: &mut Kernel) -> AddressSize {
-  let arg0 = args.get(0).unwrap().clone();
-  #[derive(Debug, Parser)]
-  struct BinArgs {
-    pathname: String,
-  }
+      match E5FSFilesystem::mkfs_storage_with_passphrase(
+        storage, 
+        parsed_args.inode_table_percentage, 
+        parsed_args.block_data_size,
+        passphrase.as_deref(),
+      ) {
+        Ok(mut e5fs) => {
+          if let Some(label) = &parsed_args.label {
+            if let Err(errno) = e5fs.set_label(label) {
+              kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+              return EXIT_FAILURE;
+            }
+          }
+          if let Err(errno) = e5fs.set_reserved_blocks_percentage(parsed_args.reserved_blocks_percentage) {
+            kprintln!(kernel, "{arg0}: {errno}");
+            return EXIT_FAILURE;
+          }
 
-  match BinArgs::try_parse_from(args.iter()) {
-    Err(message) => {
-      println!("{arg0}: invalid arguments: {message}");
-      1
-    }
-    Ok(BinArgs { pathname }) => {
-      match kernel.vfs.lookup_path(&pathname) {
-        Ok(vinode) => {
-          match kernel.vfs.change_times(&pathname, Times {
-            atime: unixtime(),
-            mtime: vinode.mtime,
-            ctime: unixtime(),
-            btime: vinode.btime,
-          }) {
-            Ok(_) => EXIT_SUCCESS,
-            Err(Errno::EACCES(_)) => {
-              println!("{arg0}: '{pathname}': Permission denied");
-              return EXIT_FAILURE
-            },
-            Err(Errno::EPERM(_)) => {
-              println!("{arg0}: '{pathname}': Operation not permitted");
-              return EXIT_FAILURE
-            },
-            Err(errno) => {
-              println!("{arg0}: unexpected error 1: {errno:?}");
-              EXIT_FAILURE
-            },
+          // Let devfs pick up the new UUID and label for `/dev/disk/by-*`
+          kernel.vfs().mount_points
+            .get_mut(&mount_point)
+            .expect("{arg0}::lookup_path: we know that mount_point exist")
+            .driver
+            .as_any()
+            .downcast_mut::<DeviceFilesystem>()
+            .expect("we know that mounted_fs.driver === instanceof DeviceFilesystem")
+            .rescan();
+
+          if let Some(entries) = rootfs_entries {
+            if let Err(errno) = ustar::unpack(&mut e5fs, &entries) {
+              kprintln!(kernel, "{arg0}: cannot unpack {}: {errno}", parsed_args.rootfs.unwrap_or_default());
+              return EXIT_FAILURE;
+            }
           }
+
+          EXIT_SUCCESS
         },
-        Err(Errno::ENOENT(_)) => {
-          println!("file {pathname} don't exist. creating it..");
-          match VFS::parent_dir(&pathname)
-            .and_then(|parent_pathname| kernel.vfs.lookup_path(&parent_pathname))
-          {
-            Ok(_) => {
-              match kernel.vfs.create_file(&pathname) {
-                Ok(_) => EXIT_SUCCESS,
-                Err(Errno::EACCES(_)) => {
-                  println!("{arg0}: '{pathname}': Permission denied");
-                  return EXIT_FAILURE
-                },
-                Err(Errno::EPERM(_)) => {
-                  println!("{arg0}: '{pathname}': Operation not permitted");
-                  return EXIT_FAILURE
-                },
-                Err(errno) => {
-                  println!("{arg0}: unexpected error 2: {errno:?}");
-                  EXIT_FAILURE
-                },
-              }
-            },
-            Err(Errno::ENOENT(_)) => {
-              println!("{arg0}: cannot touch '{pathname}': No such file or directory");
-              EXIT_ENOENT
-            },
-            Err(errno) => {
-              println!("{arg0}: unexpected error 3: {errno:?}");
-              EXIT_FAILURE
-            },
-          }
+        Err(Errno::EINVAL(message)) => {
+          kprintln!(kernel, "{arg0}: {dev_pathname}: {message}");
+          return EXIT_FAILURE;
         },
         Err(errno) => {
-          println!("{arg0}: unexpected error 4: {errno:?}");
-          EXIT_FAILURE
+          kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+          return EXIT_FAILURE;
         },
       }
-    },
+    }
   }
 }
 
-pub fn rm(args: Args, kernel: &mut Kernel) -> AddressSize {
+pub fn fsck_e5fs(args: Args, kernel: &mut Kernel) -> AddressSize {
   let arg0 = args.get(0).unwrap().clone();
   #[derive(Debug, Parser)]
   struct BinArgs {
-    #[clap(short, long, takes_value = false)]
-    recurse: bool,
-
-    // #[clap(short, long)]
-    // force: bool,
+    /// Fix what is found, only report it otherwise
+    #[clap(short = 'y', long)]
+    yes: bool,
 
-    pathname: String,
+    device_pathname: String,
   }
 
   match BinArgs::try_parse_from(args.iter()) {
     Err(message) => {
-      println!("{arg0}: invalid arguments: {message}");
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
       1
     }
-    Ok(BinArgs { pathname, recurse }) => {
-      let vinode = match kernel.vfs.lookup_path(&pathname) {
-        Ok(vinode) => vinode,
-        Err(Errno::ENOENT(_)) => {
-          println!("{arg0}: cannot remove '{pathname}': No such file or directory");
+    Ok(BinArgs { yes, device_pathname: dev_pathname }) => {
+      if kernel.current_uid != ROOT_UID {
+        kprintln!(kernel, "{arg0}: {dev_pathname}: Permission denied");
+        return EXIT_FAILURE;
+      }
+
+      let result = kernel.vfs().match_mount_point(&dev_pathname);
+      let (mount_point, internal_pathname) = match result {
+        Ok(matched) => matched,
+        Err(errno) => {
+          kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+          return EXIT_FAILURE;
+        },
+      };
+      let mut vfs = kernel.vfs();
+      let mounted_fs = vfs.mount_points.get_mut(&mount_point).expect("{arg0}::lookup_path: we know that mount_point exist");
+      let storage = (mounted_fs.r#type == FilesystemType::devfs).then(|| mounted_fs
+        .driver
+        .as_any()
+        .downcast_ref::<DeviceFilesystem>()
+        .expect("we know that mounted_fs.driver === instanceof DeviceFilesystem")
+        .open_block_storage(&internal_pathname)
+      );
+      drop(vfs);
+
+      let storage = match storage {
+        Some(Ok(storage)) => storage,
+        Some(Err(Errno::ENOENT(_))) => {
+          kprintln!(kernel, "{arg0}: {dev_pathname}: No such file or directory");
           return EXIT_ENOENT;
         },
-        Err(Errno::EACCES(_)) => {
-          println!("{arg0}: '{pathname}': Permission denied");
-          return EXIT_FAILURE
+        Some(Err(Errno::EINVAL(_))) => {
+          kprintln!(kernel, "{arg0}: {dev_pathname}: Not a block device");
+          return EXIT_FAILURE;
         },
-        Err(errno) => {
-          println!("{arg0}: unexpected error: {errno:?}");
+        Some(Err(errno)) => {
+          kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+          return EXIT_FAILURE;
+        },
+        None => {
+          kprintln!(kernel, "{arg0}: {dev_pathname}: Not a device");
           return EXIT_FAILURE;
         },
       };
 
-      // File case
-      if vinode.mode.file_type() != FileModeType::Dir as u8 {
-        return match kernel.vfs.remove_file(&pathname) {
-          Ok(()) => EXIT_SUCCESS,
-          Err(Errno::EACCES(_)) => {
-            println!("{arg0}: '{pathname}': Permission denied");
-            return EXIT_FAILURE
-          },
-          Err(errno) => {
-            println!("{arg0}: unexpected error: {errno:?}");
-            EXIT_FAILURE
-          },
-        }
-      }
-
-      // Directory case
-      if !recurse {
-        println!("{arg0}: cannot remove '{pathname}': Is a directory");
+      // Mounted filesystem has changes of its own in memory
+      if let Some(mount_point) = kernel.device_mount_point(&dev_pathname) {
+        kprintln!(kernel, "{arg0}: {dev_pathname} is mounted on {mount_point}, unmount it first");
         return EXIT_FAILURE;
       }
 
-      // Recurse
-      match kernel.vfs.read_dir(&pathname) {
-        Ok(dir) => {
-          for (name, _) in dir
-            .entries
-            .into_iter()
-            .filter(|(name, _)| name != "." && name != "..")
-          {
-            let cloned_arg0 = args.get(0).unwrap().clone();
-            let new_pathname = format!("{pathname}/{name}");
-            println!("{arg0}: descending into '({new_pathname})'");
-            let exit_status = rm(vec![cloned_arg0, String::from("-r"), new_pathname], kernel);
-            if exit_status != EXIT_SUCCESS {
-              return exit_status;
-            }
-          }
-          return match kernel.vfs.remove_file(&pathname) {
-            Ok(()) => EXIT_SUCCESS,
-            Err(Errno::EACCES(_)) => {
-              println!("{arg0}: '{pathname}': Permission denied");
-              return EXIT_FAILURE
-            },
-            Err(errno) => {
-              println!("{arg0}: unexpected error: {errno:?}");
-              EXIT_FAILURE
-            },
-          } 
+      let mut e5fs = match read_e5fs(kernel, &dev_pathname, storage) {
+        Ok(e5fs) => e5fs,
+        Err(Errno::EILSEQ(message) | Errno::EACCES(message)) => {
+          kprintln!(kernel, "{arg0}: {dev_pathname}: {message}");
+          return fsck::FSCK_UNCORRECTED;
         },
         Err(errno) => {
-          println!("{arg0}: unexpected error: {errno:?}");
-          EXIT_FAILURE
+          kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+          return EXIT_FAILURE;
+        },
+      };
+
+      let report = match fsck::check(&mut e5fs, yes) {
+        Ok(report) => report,
+        Err(errno) => {
+          kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+          return EXIT_FAILURE;
         },
+      };
+
+      for problem in &report.problems {
+        match problem.repaired {
+          true => kprintln!(kernel, "{dev_pathname}: {}: fixed", problem.message),
+          false => kprintln!(kernel, "{dev_pathname}: {}", problem.message),
+        }
       }
-    },
+      kprintln!(kernel,
+        "{dev_pathname}: {}/{} files, {}/{} blocks",
+        report.used_inodes_count, report.inodes_count,
+        report.used_blocks_count, report.blocks_count,
+      );
+
+      // Filesystem with problems left stays dirty
+      if report.exit_code() != fsck::FSCK_UNCORRECTED {
+        if let Err(errno) = e5fs.unmount() {
+          kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+          return EXIT_FAILURE;
+        }
+      }
+
+      report.exit_code()
+    }
   }
 }
 
-pub fn mv(args: Args, kernel: &mut Kernel) -> AddressSize {
+/// Open block device at `pathname` in devfs as storage
+///
+/// Errors:
+/// ENOENT -> there is no such device
+/// EINVAL -> `pathname` is not a block device
+fn open_block_device(kernel: &mut Kernel, pathname: &str) -> Result<Box<dyn BlockStorage>, Errno> {
+  let mut vfs = kernel.vfs();
+  let (mount_point, internal_pathname) = vfs.match_mount_point(pathname)?;
+  let mounted_fs = vfs.mount_points.get_mut(&mount_point).expect("open_block_device: we know that mount_point exist");
+  if mounted_fs.r#type != FilesystemType::devfs {
+    return Err(Errno::EINVAL(format!("{pathname} is not a device").into()));
+  }
+
+  mounted_fs
+    .driver
+    .as_any()
+    .downcast_ref::<DeviceFilesystem>()
+    .expect("we know that mounted_fs.driver === instanceof DeviceFilesystem")
+    .open_block_storage(&internal_pathname)
+}
+
+/// Run `f` on e5fs mounted at `mount_point`. VFS is locked
+/// meanwhile, so `f` must not print or go through `kernel`.
+/// Returns: what `f` does, `None` if it is not e5fs
+fn with_e5fs<R>(kernel: &mut Kernel, mount_point: &str, f: impl FnOnce(&mut E5FSFilesystem) -> R) -> Option<R> {
+  kernel.vfs().mount_points
+    .get_mut(mount_point)
+    .filter(|mounted_fs| mounted_fs.r#type == FilesystemType::e5fs)
+    .and_then(|mounted_fs| mounted_fs.driver.as_any().downcast_mut::<E5FSFilesystem>())
+    .map(f)
+}
+
+/// Read e5fs on `storage` of device `pathname`, asking
+/// for passphrase if it is encrypted
+///
+/// Errors:
+/// EACCES -> passphrase is wrong
+fn read_e5fs(kernel: &mut Kernel, pathname: &str, mut storage: Box<dyn BlockStorage>) -> Result<E5FSFilesystem, Errno> {
+  let passphrase = match E5FSFilesystem::is_encrypted(storage.as_mut()) {
+    true => Some(prompt_line(kernel, &format!("Passphrase for {pathname}: "))?),
+    false => None,
+  };
+
+  E5FSFilesystem::from_storage_with_passphrase(storage, passphrase.as_deref().map(|passphrase| passphrase.trim_end_matches('\n')))
+}
+
+/// List UUIDs and labels of filesystems on block devices
+pub fn blkid(args: Args, kernel: &mut Kernel) -> AddressSize {
   let arg0 = args.get(0).unwrap().clone();
   #[derive(Debug, Parser)]
   struct BinArgs {
-    source_pathname: String,
-    target_pathname: String,
+    /// Print only device with this label
+    #[clap(short = 'L', long, conflicts_with = "uuid")]
+    label: Option<String>,
+
+    /// Print only device with this UUID
+    #[clap(short = 'U', long)]
+    uuid: Option<String>,
+
+    /// Devices to look at, all block devices if none
+    device_pathnames: Vec<String>,
   }
 
   match BinArgs::try_parse_from(args.iter()) {
     Err(message) => {
-      println!("{arg0}: invalid arguments: {message}");
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
       1
     }
-    Ok(BinArgs { source_pathname, target_pathname }) => {
-      let arg0 = args.get(0).unwrap().clone();
-      cp(vec![arg0.clone(), source_pathname.clone(), target_pathname], kernel);
-      rm(vec![arg0.clone(), String::from("-r"), source_pathname.clone()], kernel);
-      EXIT_SUCCESS
-    },
+    Ok(BinArgs { label, uuid, mut device_pathnames }) => {
+      if device_pathnames.is_empty() {
+        let result = kernel.vfs().read_dir(DEV_PATH);
+        let dir = match result {
+          Ok(dir) => dir,
+          Err(errno) => {
+            kprintln!(kernel, "{arg0}: cannot read '{DEV_PATH}': {errno}");
+            return EXIT_FAILURE;
+          },
+        };
+
+        device_pathnames = dir.entries
+          .into_keys()
+          .map(|name| format!("{DEV_PATH}/{name}"))
+          .filter(|pathname| kernel.vfs().stat(pathname).map_or(false, |stat| stat.mode.file_type() == FileModeType::Block as u8))
+          .collect();
+      }
+
+      // Like blkid of util-linux, 2 means nothing was found
+      let mut status = 2;
+      for pathname in device_pathnames {
+        let Some(identity) = open_block_device(kernel, &pathname)
+          .ok()
+          .and_then(|mut storage| E5FSFilesystem::probe(storage.as_mut()))
+        else {
+          continue;
+        };
+
+        match (&label, &uuid) {
+          (Some(label), _) if *label == identity.label => kprintln!(kernel, "{pathname}"),
+          (_, Some(uuid)) if *uuid == identity.uuid => kprintln!(kernel, "{pathname}"),
+          (None, None) => match identity.label.is_empty() {
+            true => kprintln!(kernel, "{pathname}: UUID=\"{}\" TYPE=\"e5fs\"", identity.uuid),
+            false => kprintln!(kernel, "{pathname}: UUID=\"{}\" LABEL=\"{}\" TYPE=\"e5fs\"", identity.uuid, identity.label),
+          },
+          _ => continue,
+        }
+        status = EXIT_SUCCESS;
+      }
+
+      status
+    }
   }
 }
 
-pub fn cp(args: Args, kernel: &mut Kernel) -> AddressSize {
+pub fn resize_e5fs(args: Args, kernel: &mut Kernel) -> AddressSize {
   let arg0 = args.get(0).unwrap().clone();
   #[derive(Debug, Parser)]
   struct BinArgs {
-    source_pathname: String,
-    target_pathname: String,
+    device_pathname: String,
   }
 
-  let arg0 = args.get(0).unwrap().clone();
   match BinArgs::try_parse_from(args.iter()) {
     Err(message) => {
-      println!("{arg0}: invalid arguments: {message}");
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
       1
     }
-    Ok(BinArgs { source_pathname, target_pathname }) => {
-      let source_vinode = match kernel.vfs.lookup_path(&source_pathname) {
-        Ok(vinode) => vinode,
+    Ok(BinArgs { device_pathname: dev_pathname }) => {
+      if kernel.current_uid != ROOT_UID {
+        kprintln!(kernel, "{arg0}: {dev_pathname}: Permission denied");
+        return EXIT_FAILURE;
+      }
+
+      let mut storage = match open_block_device(kernel, &dev_pathname) {
+        Ok(storage) => storage,
         Err(Errno::ENOENT(_)) => {
-          println!("{arg0}: {source_pathname}: No such file or directory");
+          kprintln!(kernel, "{arg0}: {dev_pathname}: No such file or directory");
           return EXIT_ENOENT;
         },
-        Err(Errno::EACCES(_)) => {
-          println!("{arg0}: {source_pathname}: Permission denied");
-          return EXIT_FAILURE
+        Err(Errno::EINVAL(_)) => {
+          kprintln!(kernel, "{arg0}: {dev_pathname}: Not a block device");
+          return EXIT_FAILURE;
         },
         Err(errno) => {
-          println!("{arg0}: unexpected error: {errno:?}");
+          kprintln!(kernel, "{arg0}: unexpected error: {errno}");
           return EXIT_FAILURE;
         },
       };
-
-      // Guard for target already existing
-      if let Ok(_) = kernel.vfs.lookup_path(&target_pathname) {
-        println!("{arg0}: {target_pathname}: Already exists");
+      let Some(identity) = E5FSFilesystem::probe(storage.as_mut()) else {
+        kprintln!(kernel, "{arg0}: {dev_pathname}: no e5fs on device");
         return EXIT_FAILURE;
-      }
+      };
 
-      println!("{arg0}: source_pathname is {source_pathname} source_vinode.mode is {:03b}", source_vinode.mode.file_type());
+      // Filesystem that is mounted is grown right there,
+      // it would be overwritten on unmount otherwise
+      let mounted = match kernel.vfs().mount_points
+        .values_mut()
+        .filter(|mounted_fs| mounted_fs.r#type == FilesystemType::e5fs)
+        .filter_map(|mounted_fs| mounted_fs.driver.as_any().downcast_mut::<E5FSFilesystem>())
+        .find(|e5fs| e5fs.identity() == identity)
+      {
+        Some(e5fs) => Ok(e5fs.resize(storage)),
+        None => Err(storage),
+      };
+      let result = match mounted {
+        Ok(result) => result,
+        Err(storage) => open_block_device(kernel, &dev_pathname)
+          .and_then(|device| read_e5fs(kernel, &dev_pathname, device))
+          .and_then(|mut e5fs| {
+            let grown = e5fs.resize(storage)?;
+            e5fs.unmount()?;
+            Ok(grown)
+          }),
+      };
 
-      // Main part - base file case or recurse
-      if source_vinode.mode.file_type() == FileModeType::File as u8 {
-        println!("{arg0}: file case");
-        let source_bytes = kernel.vfs.read_file(&source_pathname, AddressSize::MAX).unwrap();
-        kernel.vfs.create_file(&target_pathname).unwrap();
-        kernel.vfs.write_file(&target_pathname, &source_bytes).unwrap();
-        EXIT_SUCCESS
-      } else {
-        println!("{arg0}: dir case (creating dir: {target_pathname})");
-        kernel.vfs.create_dir(&target_pathname).unwrap();
-        let dir = kernel.vfs.read_dir(&source_pathname).unwrap();
-        for (name, _) in dir
-          .entries
-          .iter()
-          .filter(|(name, _)| **name != "." && **name != "..") 
-        {
-          let cloned_arg0 = args.get(0).unwrap().clone();
-          let new_source_pathname = format!("{source_pathname}/{name}");
-          let new_target_pathname = format!("{target_pathname}/{name}");
-          println!("{arg0}: descending into '({source_pathname})'");
-          let exit_status = cp(vec![cloned_arg0, new_source_pathname, new_target_pathname], kernel);
-          if exit_status != EXIT_SUCCESS {
-            return exit_status;
-          }
-        }
-        EXIT_SUCCESS 
+      match result {
+        Ok(true) => EXIT_SUCCESS,
+        Ok(false) => {
+          kprintln!(kernel, "{arg0}: {dev_pathname}: filesystem already fills the device, nothing to do");
+          EXIT_SUCCESS
+        },
+        Err(Errno::EINVAL(message) | Errno::EILSEQ(message) | Errno::EACCES(message)) => {
+          kprintln!(kernel, "{arg0}: {dev_pathname}: {message}");
+          EXIT_FAILURE
+        },
+        Err(errno) => {
+          kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+          EXIT_FAILURE
+        },
       }
-    },
+    }
   }
 }
 
-pub fn write(args: Args, kernel: &mut Kernel) -> AddressSize {
+/// Manage snapshots of e5fs: `create <name>`, `delete <name>`,
+/// `rollback <name>` and `list`. Snapshots are in /.snapshots of
+/// filesystem, mount one with `mount -o snapshot=<name>`
+pub fn snapshot(args: Args, kernel: &mut Kernel) -> AddressSize {
   let arg0 = args.get(0).unwrap().clone();
   #[derive(Debug, Parser)]
   struct BinArgs {
-    pathname: String,
-    text: String,
+    /// Any path on filesystem to manage snapshots of
+    #[clap(short, long, default_value = "/")]
+    filesystem: String,
+
+    /// `create`, `delete`, `rollback` or `list`
+    command: String,
+
+    name: Option<String>,
   }
 
   match BinArgs::try_parse_from(args.iter()) {
     Err(message) => {
-      println!("{arg0}: invalid arguments: {message}");
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
       1
-    },
-    Ok(BinArgs { pathname, text }) => {
-      let bytes = text.as_bytes();
-      match kernel.vfs.write_file(&pathname, bytes) {
-        Ok(_) => EXIT_SUCCESS,
-        Err(Errno::ENOENT(_)) => {
-          println!("{arg0}: {pathname}: No such file or directory");
-          return EXIT_ENOENT;
+    }
+    Ok(BinArgs { filesystem, command, name }) => {
+      if command != "list" && kernel.current_uid != ROOT_UID {
+        kprintln!(kernel, "{arg0}: {command}: Permission denied");
+        return EXIT_FAILURE;
+      }
+
+      let result = kernel.vfs().match_mount_point(&filesystem);
+      let mount_point = match result {
+        Ok((mount_point, _)) => mount_point,
+        Err(errno) => {
+          kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+          return EXIT_FAILURE;
         },
-        Err(Errno::EISDIR(_)) => {
-          println!("{arg0}: {pathname}: Is a directory");
+      };
+      match (command.as_str(), &name) {
+        ("create" | "delete" | "rollback", Some(_)) | ("list", None) => (),
+        ("list", Some(_)) => {
+          kprintln!(kernel, "{arg0}: list: takes no name");
           return EXIT_FAILURE;
         },
-        Err(Errno::EACCES(_)) => {
-          println!("{arg0}: '{pathname}': Permission denied");
-          return EXIT_FAILURE
+        ("create" | "delete" | "rollback", None) => {
+          kprintln!(kernel, "{arg0}: {command}: snapshot name is needed");
+          return EXIT_FAILURE;
         },
-        Err(errno) => {
-          println!("{arg0}: unexpected error: {errno:?}");
+        _ => {
+          kprintln!(kernel, "{arg0}: unknown command '{command}'");
           return EXIT_FAILURE;
         },
       }
-    },
+
+      let result = with_e5fs(kernel, &mount_point, |e5fs| match (command.as_str(), name) {
+        ("create", Some(name)) => e5fs.create_snapshot(&name).map(|()| Vec::new()),
+        ("delete", Some(name)) => e5fs.delete_snapshot(&name).map(|()| Vec::new()),
+        ("rollback", Some(name)) => e5fs.rollback_snapshot(&name).map(|()| Vec::new()),
+        _ => e5fs.list_snapshots(),
+      });
+      let Some(result) = result else {
+        kprintln!(kernel, "{arg0}: {filesystem}: not on e5fs");
+        return EXIT_FAILURE;
+      };
+
+      match result {
+        Ok(names) => {
+          for name in names {
+            kprintln!(kernel, "{name}");
+          }
+          EXIT_SUCCESS
+        },
+        Err(Errno::EEXIST(message) | Errno::ENOENT(message) | Errno::EROFS(message) | Errno::EINVAL(message)) => {
+          kprintln!(kernel, "{arg0}: {message}");
+          EXIT_FAILURE
+        },
+        Err(errno) => {
+          kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+          EXIT_FAILURE
+        },
+      }
+    }
   }
 }
 
-pub fn ed(args: Args, kernel: &mut Kernel) -> AddressSize {
+pub fn mkdir(args: Args, kernel: &mut Kernel) -> AddressSize {
   let arg0 = args.get(0).unwrap().clone();
   #[derive(Debug, Parser)]
   struct BinArgs {
@@ -765,78 +1464,83 @@ pub fn ed(args: Args, kernel: &mut Kernel) -> AddressSize {
 
   match BinArgs::try_parse_from(args.iter()) {
     Err(message) => {
-      println!("{arg0}: parse error: {message}");
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
       1
     },
     Ok(BinArgs { pathname }) => {
-      // Read file
-      let bytes = match kernel.vfs.read_file(&pathname, AddressSize::MAX) {
-        Ok(bytes) => bytes,
+      let result = kernel.vfs().create_dir(&pathname);
+      match result {
+        Ok(_) => EXIT_SUCCESS,
         Err(Errno::ENOENT(_)) => {
-          println!("{arg0}: {pathname}: No such file or directory");
-          return EXIT_ENOENT;
-        },
-        Err(Errno::EISDIR(_)) => {
-          println!("{arg0}: {pathname}: Is a directory");
-          return EXIT_FAILURE;
+          kprintln!(kernel, "{arg0}: cannot create directory: '{pathname}': No such file or directory");
+          EXIT_ENOENT
         },
         Err(Errno::EACCES(_)) => {
-          println!("{arg0}: '{pathname}': Permission denied");
+          kprintln!(kernel, "{arg0}: '{pathname}': Permission denied");
           return EXIT_FAILURE
         },
         Err(errno) => {
-          println!("{arg0}: unexpected error: {errno:?}");
-          return EXIT_FAILURE;
-        },
-      };
-      
-      // Edit file
-      let editor = std::env::var("EDITOR").expect("EDITOR env var must be set");
-      let mut file_path = std::env::temp_dir();
-      file_path.push("eunix_editor_file");
-
-      match File::create(&file_path)
-        .and_then(|mut file| file.write_all(&bytes)) 
-      {
-        Ok(_) => {
-        },
-        Err(message) => {
-          println!("{arg0}: error while creating platform-provided temp file: {message:#?}");
-          return EXIT_FAILURE;
+          kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+          EXIT_FAILURE
         },
       }
-      
+    },
+  }
+}
 
-      if let Err(message) = Command::new(editor)
-          .arg(&file_path)
-          .status() 
-      {
-        println!("{arg0}: error while opening platform-provided editor: {message:#?}");
-        return EXIT_FAILURE;
-      }
+pub fn mknod(args: Args, kernel: &mut Kernel) -> AddressSize {
+  let arg0 = args.get(0).unwrap().clone();
+  #[derive(Debug, Parser)]
+  struct BinArgs {
+    pathname: String,
 
-      let mut edited_bytes = Vec::new();
+    /// 'b' for block device, 'c' for character device
+    file_type: String,
 
-      match File::open(&file_path)
-        .and_then(|mut file| file.read_to_end(&mut edited_bytes)) 
-      {
-        Ok(_) => {
-        },
-        Err(message) => {
-          println!("{arg0}: error while reading back edited platform-provided temp file: {message:#?}");
+    major: u16,
+    minor: u16,
+  }
+
+  match BinArgs::try_parse_from(args.iter()) {
+    Err(message) => {
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
+      1
+    },
+    Ok(BinArgs { pathname, file_type, major, minor }) => {
+      let file_type = match file_type.as_str() {
+        "b" => FileModeType::Block,
+        "c" | "u" => FileModeType::Char,
+        _ => {
+          kprintln!(kernel, "{arg0}: invalid device type '{file_type}'");
           return EXIT_FAILURE;
         },
-      }
+      };
 
-      // Write file back
-      return match kernel.vfs.write_file(&pathname, &edited_bytes) {
+      let result = kernel.vfs().mknod(&pathname, file_type, DeviceNumber::new(major, minor));
+      match result {
         Ok(_) => EXIT_SUCCESS,
+        Err(Errno::ENOENT(_)) => {
+          kprintln!(kernel, "{arg0}: {pathname}: No such file or directory");
+          EXIT_ENOENT
+        },
+        Err(Errno::EEXIST(_)) => {
+          kprintln!(kernel, "{arg0}: {pathname}: File exists");
+          EXIT_FAILURE
+        },
+        Err(Errno::EINVAL(_)) => {
+          kprintln!(kernel, "{arg0}: {pathname}: Invalid argument");
+          EXIT_FAILURE
+        },
+        Err(Errno::EPERM(_)) => {
+          kprintln!(kernel, "{arg0}: {pathname}: Operation not permitted");
+          EXIT_FAILURE
+        },
         Err(Errno::EACCES(_)) => {
-          println!("{arg0}: '{pathname}': Permission denied");
-          return EXIT_FAILURE
+          kprintln!(kernel, "{arg0}: '{pathname}': Permission denied");
+          EXIT_FAILURE
         },
         Err(errno) => {
-          println!("{arg0}: unexpected error: {errno:?}");
+          kprintln!(kernel, "{arg0}: unexpected error: {errno}");
           EXIT_FAILURE
         },
       }
@@ -844,281 +1548,2511 @@ pub fn ed(args: Args, kernel: &mut Kernel) -> AddressSize {
   }
 }
 
-pub fn chmod(args: Args, kernel: &mut Kernel) -> AddressSize {
+pub fn fdisk(args: Args, kernel: &mut Kernel) -> AddressSize {
   let arg0 = args.get(0).unwrap().clone();
   #[derive(Debug, Parser)]
   struct BinArgs {
-    mode: String,
-    pathname: String,
+    /// Print partition table and exit
+    #[clap(short, long, takes_value = false)]
+    list: bool,
+
+    device_pathname: String,
+
+    /// Sizes of partitions to create (like `512K` or `1M`),
+    /// `-` for the rest of the disk
+    sizes: Vec<String>,
   }
 
   match BinArgs::try_parse_from(args.iter()) {
     Err(message) => {
-      println!("{arg0}: invalid arguments: {message}");
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
       1
-    }
-    Ok(BinArgs { pathname, mode: new_mode_string }) => {
-      let old_mode = match kernel.vfs.lookup_path(&pathname) {
-        Ok(vinode) => vinode.mode,
+    },
+    Ok(BinArgs { list, device_pathname, sizes }) => {
+      let result = kernel.vfs().stat(&device_pathname);
+      let disk_size = match result {
+        Ok(FileStat { mode, size, .. }) if mode.file_type() == FileModeType::Block as u8 => size as u64,
+        Ok(_) => {
+          kprintln!(kernel, "{arg0}: {device_pathname}: Not a block device");
+          return EXIT_FAILURE;
+        },
         Err(Errno::ENOENT(_)) => {
-          println!("{arg0}: {pathname}: No such file or directory");
+          kprintln!(kernel, "{arg0}: {device_pathname}: No such file or directory");
           return EXIT_ENOENT;
         },
-        Err(Errno::EACCES(_)) => {
-          println!("{arg0}: '{pathname}': Permission denied");
-          return EXIT_FAILURE
-        },
         Err(errno) => {
-          println!("{arg0}: unexpected error: {errno:?}");
+          kprintln!(kernel, "{arg0}: unexpected error: {errno}");
           return EXIT_FAILURE;
         },
       };
 
-      if !Regex::new("^[0-7]{3}$")
-        .unwrap()
-        .is_match(&new_mode_string)
-        .unwrap()
-      {
-        println!("{arg0}: invalid mode: '{new_mode_string}'");
+      let result = kernel.vfs().match_mount_point(&device_pathname);
+      let (mount_point, internal_pathname) = match result {
+        Ok(matched) => matched,
+        Err(_) => {
+          kprintln!(kernel, "{arg0}: {device_pathname}: No such file or directory");
+          return EXIT_ENOENT;
+        },
+      };
+      let is_devfs = kernel.vfs().mount_points.get(&mount_point).expect("{arg0}::lookup_path: we know that mount_point exist").r#type == FilesystemType::devfs;
+
+      // Guard for not a device
+      if !is_devfs {
+        kprintln!(kernel, "{arg0}: {device_pathname}: Not a device");
         return EXIT_FAILURE;
       }
 
-      let user: AddressSize = new_mode_string.chars().map(|c| c.to_digit(8)).nth(0).unwrap().unwrap();
-      let group: AddressSize = new_mode_string.chars().map(|c| c.to_digit(8)).nth(1).unwrap().unwrap();
-      let others: AddressSize = new_mode_string.chars().map(|c| c.to_digit(8)).nth(2).unwrap().unwrap();
+      fn devfs<'a>(kernel: &'a mut Kernel, mount_point: &str) -> MappedMutexGuard<'a, DeviceFilesystem> {
+        MutexGuard::map(kernel.vfs(), |vfs| vfs.mount_points
+          .get_mut(mount_point)
+          .expect("we know that mount_point exist")
+          .driver
+          .as_any()
+          .downcast_mut::<DeviceFilesystem>()
+          .expect("we know that mounted_fs.driver === instanceof DeviceFilesystem"))
+      }
+
+      if list || sizes.is_empty() {
+        kprintln!(kernel, "Disk {device_pathname}: {disk_size} bytes, {} sectors", disk_size / SECTOR_SIZE);
+
+        let result = devfs(kernel, &mount_point).read_partition_table(&internal_pathname);
+        match result {
+          Ok(None) => kprintln!(kernel, "{arg0}: {device_pathname}: No partition table"),
+          Ok(Some(table)) => {
+            kprintln!(kernel, "Device\tStart\tSectors\tSize\tType");
+            table.partitions
+              .iter()
+              .enumerate()
+              .filter_map(|(slot, partition)| partition.map(|partition| (slot + 1, partition)))
+              .for_each(|(index, partition)| kprintln!(kernel,
+                "{device_pathname}{index}\t{}\t{}\t{}\t{:02x}",
+                partition.first_sector,
+                partition.sectors_count,
+                partition.size(),
+                partition.partition_type,
+              ));
+          },
+          Err(Errno::EINVAL(_)) => {
+            kprintln!(kernel, "{arg0}: {device_pathname}: Not a whole disk");
+            return EXIT_FAILURE;
+          },
+          Err(errno) => {
+            kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+            return EXIT_FAILURE;
+          },
+        }
+
+        return EXIT_SUCCESS;
+      }
+
+      let mut partition_sizes = Vec::new();
+      for size in sizes {
+        match size.as_str() {
+          "-" => partition_sizes.push(0),
+          _ => match util::parse_size(&size) {
+            Some(bytes) if bytes > 0 => partition_sizes.push(bytes),
+            _ => {
+              kprintln!(kernel, "{arg0}: invalid partition size '{size}'");
+              return EXIT_FAILURE;
+            },
+          },
+        }
+      }
 
-      let new_mode = old_mode
-        .with_user(user as u8)
-        .with_group(group as u8)
-        .with_others(others as u8);
+      let table = match PartitionTable::with_sizes(disk_size, &partition_sizes) {
+        Ok(table) => table,
+        Err(Errno::EINVAL(message) | Errno::ENOSPC(message)) => {
+          kprintln!(kernel, "{arg0}: {message}");
+          return EXIT_FAILURE;
+        },
+        Err(errno) => {
+          kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+          return EXIT_FAILURE;
+        },
+      };
 
-      match kernel.vfs.change_mode(&pathname, new_mode) {
+      let result = devfs(kernel, &mount_point).write_partition_table(&internal_pathname, &table);
+      match result {
         Ok(_) => EXIT_SUCCESS,
-        Err(Errno::EPERM(_)) => {
-          println!("{arg0}: {pathname}: Operation not permitted");
-          return EXIT_FAILURE
+        Err(Errno::EINVAL(_)) => {
+          kprintln!(kernel, "{arg0}: {device_pathname}: Not a whole disk");
+          EXIT_FAILURE
+        },
+        Err(errno) => {
+          kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+          EXIT_FAILURE
+        },
+      }
+    },
+  }
+}
+
+pub fn losetup(args: Args, kernel: &mut Kernel) -> AddressSize {
+  let arg0 = args.get(0).unwrap().clone();
+  #[derive(Debug, Parser)]
+  struct BinArgs {
+    /// Detach loop device, writing its contents back to the file
+    #[clap(short, long)]
+    detach: Option<String>,
+
+    /// File to attach to the first free loop device
+    pathname: Option<String>,
+  }
+
+  match BinArgs::try_parse_from(args.iter()) {
+    Err(message) => {
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
+      1
+    },
+    Ok(BinArgs { detach: Some(device_pathname), .. }) => {
+      match kernel.detach_loop(&device_pathname) {
+        Ok(_) => EXIT_SUCCESS,
+        Err(Errno::ENOENT(_)) => {
+          kprintln!(kernel, "{arg0}: {device_pathname}: No such file or directory");
+          EXIT_ENOENT
+        },
+        Err(Errno::EINVAL(_)) => {
+          kprintln!(kernel, "{arg0}: {device_pathname}: Not a loop device");
+          EXIT_FAILURE
+        },
+        Err(Errno::EBUSY(_)) => {
+          kprintln!(kernel, "{arg0}: {device_pathname}: Device or resource busy");
+          EXIT_FAILURE
+        },
+        Err(errno) => {
+          kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+          EXIT_FAILURE
+        },
+      }
+    },
+    Ok(BinArgs { pathname: Some(pathname), .. }) => {
+      match kernel.attach_loop(&pathname) {
+        Ok(device_pathname) => {
+          kprintln!(kernel, "{device_pathname}");
+          EXIT_SUCCESS
+        },
+        Err(Errno::ENOENT(_)) => {
+          kprintln!(kernel, "{arg0}: {pathname}: No such file or directory");
+          EXIT_ENOENT
+        },
+        Err(Errno::EINVAL(_)) => {
+          kprintln!(kernel, "{arg0}: {pathname}: Not a regular file");
+          EXIT_FAILURE
         },
         Err(Errno::EACCES(_)) => {
-          println!("{arg0}: '{pathname}': Permission denied");
-          return EXIT_FAILURE
+          kprintln!(kernel, "{arg0}: '{pathname}': Permission denied");
+          EXIT_FAILURE
+        },
+        Err(Errno::ENXIO(_)) => {
+          kprintln!(kernel, "{arg0}: cannot find an unused loop device");
+          EXIT_FAILURE
         },
         Err(errno) => {
-          println!("{arg0}: unexpected error: {errno:?}");
-          return EXIT_FAILURE;
+          kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+          EXIT_FAILURE
+        },
+      }
+    },
+    Ok(BinArgs { .. }) => {
+      match kernel.loop_devices() {
+        Ok(loop_devices) => {
+          loop_devices
+            .iter()
+            .for_each(|(device_pathname, backing_pathname)| kprintln!(kernel, "{device_pathname}: {backing_pathname}"));
+          EXIT_SUCCESS
+        },
+        Err(errno) => {
+          kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+          EXIT_FAILURE
         },
       }
     },
   }
 }
 
-pub fn chown(args: Args, kernel: &mut Kernel) -> AddressSize {
+pub fn rmdir(args: Args, kernel: &mut Kernel) -> AddressSize {
   let arg0 = args.get(0).unwrap().clone();
   #[derive(Debug, Parser)]
   struct BinArgs {
-    new_owners_string: String,
     pathname: String,
   }
 
   match BinArgs::try_parse_from(args.iter()) {
     Err(message) => {
-      println!("{arg0}: invalid arguments: {message}");
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
       1
     }
-    Ok(BinArgs { pathname, new_owners_string }) => {
-      let VINode {
-        uid,
-        gid,
-        ..
-      } = match kernel.vfs.lookup_path(&pathname) {
-        Ok(vinode) => vinode,
-        Err(Errno::EPERM(_)) => {
-          println!("{arg0}: changing owner of '{pathname}': Operation not permitted");
-          return EXIT_FAILURE
+    Ok(BinArgs { pathname }) => {
+      let result = kernel.vfs().rmdir(&pathname);
+      match result {
+        Ok(()) => EXIT_SUCCESS,
+        Err(Errno::ENOENT(_)) => {
+          kprintln!(kernel, "{arg0}: failed to remove '{pathname}': No such file or directory");
+          EXIT_ENOENT
+        },
+        Err(Errno::ENOTDIR(_)) => {
+          kprintln!(kernel, "{arg0}: failed to remove '{pathname}': Not a directory");
+          EXIT_FAILURE
+        },
+        Err(Errno::ENOTEMPTY(_)) => {
+          kprintln!(kernel, "{arg0}: failed to remove '{pathname}': Directory not empty");
+          EXIT_FAILURE
         },
         Err(Errno::EACCES(_)) => {
-          println!("{arg0}: '{pathname}': Permission denied");
-          return EXIT_FAILURE
+          kprintln!(kernel, "{arg0}: failed to remove '{pathname}': Permission denied");
+          EXIT_FAILURE
         },
-        Err(Errno::ENOENT(_)) => {
-          println!("{arg0}: {pathname}: No such file or directory");
-          return EXIT_ENOENT;
+        Err(Errno::EBUSY(_)) => {
+          kprintln!(kernel, "{arg0}: failed to remove '{pathname}': Device or resource busy");
+          EXIT_FAILURE
+        },
+        Err(Errno::EINVAL(_)) => {
+          kprintln!(kernel, "{arg0}: failed to remove '{pathname}': Invalid argument");
+          EXIT_FAILURE
         },
         Err(errno) => {
-          println!("{arg0}: unexpected error: {errno:?}");
-          return EXIT_FAILURE;
+          kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+          EXIT_FAILURE
         },
-      };
+      }
+    },
+  }
+}
 
-      // Parse names - default to current
-      let user_name = new_owners_string
-        .split(":")
-        .nth(0)
-        .unwrap_or(kernel.uid_map.get(&kernel.current_uid).unwrap());
-      let group_name = new_owners_string
-        .split(":")
-        .nth(1)
-        .unwrap_or(kernel.uid_map.get(&kernel.current_gid).unwrap());
+pub fn touch(args: Args, kernel: &mut Kernel) -> AddressSize {
+  let arg0 = args.get(0).unwrap().clone();
+  #[derive(Debug, Parser)]
+  struct BinArgs {
+    pathname: String,
+  }
 
-      // Guard user
-      let uid = if let Some(uid) = kernel
-        .uid_map
-        .iter()
-        .find(|(_, name)| user_name == *name)
-        .map(|(id, _)| *id)
-      {
-        uid
-      } else {
-        println!("{arg0}: invalid user: '{new_owners_string}'");
-        return EXIT_FAILURE;
+  match BinArgs::try_parse_from(args.iter()) {
+    Err(message) => {
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
+      1
+    }
+    Ok(BinArgs { pathname }) => {
+      let result = kernel.vfs().lookup_path(&pathname);
+      match result {
+        Ok(vinode) => {
+          let result = kernel.vfs().change_times(&pathname, Times {
+            atime: kernel.clock.now(),
+            mtime: vinode.mtime,
+            ctime: kernel.clock.now(),
+            btime: vinode.btime,
+          });
+          match result {
+            Ok(_) => EXIT_SUCCESS,
+            Err(Errno::EACCES(_)) => {
+              kprintln!(kernel, "{arg0}: '{pathname}': Permission denied");
+              return EXIT_FAILURE
+            },
+            Err(Errno::EPERM(_)) => {
+              kprintln!(kernel, "{arg0}: '{pathname}': Operation not permitted");
+              return EXIT_FAILURE
+            },
+            Err(Errno::EROFS(_)) => {
+              kprintln!(kernel, "{arg0}: cannot touch '{pathname}': Read-only file system");
+              return EXIT_FAILURE
+            },
+            Err(errno) => {
+              kprintln!(kernel, "{arg0}: unexpected error 1: {errno}");
+              EXIT_FAILURE
+            },
+          }
+        },
+        Err(Errno::ENOENT(_)) => {
+          tracing::debug!(pathname, "touch: file does not exist, creating it");
+          let absolute_pathname = kernel.vfs().absolute_path(&pathname);
+          let result = VFS::parent_dir(&absolute_pathname)
+            .and_then(|parent_pathname| kernel.vfs().lookup_path(&parent_pathname));
+          match result {
+            Ok(_) => {
+              let result = kernel.vfs().create_file(&pathname);
+              match result {
+                Ok(_) => EXIT_SUCCESS,
+                Err(Errno::EACCES(_)) => {
+                  kprintln!(kernel, "{arg0}: '{pathname}': Permission denied");
+                  return EXIT_FAILURE
+                },
+                Err(Errno::EPERM(_)) => {
+                  kprintln!(kernel, "{arg0}: '{pathname}': Operation not permitted");
+                  return EXIT_FAILURE
+                },
+                Err(Errno::EROFS(_)) => {
+                  kprintln!(kernel, "{arg0}: cannot touch '{pathname}': Read-only file system");
+                  return EXIT_FAILURE
+                },
+                Err(errno) => {
+                  kprintln!(kernel, "{arg0}: unexpected error 2: {errno}");
+                  EXIT_FAILURE
+                },
+              }
+            },
+            Err(Errno::ENOENT(_)) => {
+              kprintln!(kernel, "{arg0}: cannot touch '{pathname}': No such file or directory");
+              EXIT_ENOENT
+            },
+            Err(errno) => {
+              kprintln!(kernel, "{arg0}: unexpected error 3: {errno}");
+              EXIT_FAILURE
+            },
+          }
+        },
+        Err(errno) => {
+          kprintln!(kernel, "{arg0}: unexpected error 4: {errno}");
+          EXIT_FAILURE
+        },
+      }
+    },
+  }
+}
+
+pub fn truncate(args: Args, kernel: &mut Kernel) -> AddressSize {
+  let arg0 = args.get(0).unwrap().clone();
+  #[derive(Debug, Parser)]
+  struct BinArgs {
+    /// Do not create files that don't exist
+    #[clap(short = 'c', long)]
+    no_create: bool,
+
+    /// New size like `4096`, `1K` or `1M`, or a change of
+    /// the current one with `+` or `-` in front
+    #[clap(short, long, allow_hyphen_values = true)]
+    size: String,
+
+    #[clap(required = true)]
+    pathnames: Vec<String>,
+  }
+
+  match BinArgs::try_parse_from(args.iter()) {
+    Err(message) => {
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
+      1
+    }
+    Ok(BinArgs { no_create, size, pathnames }) => {
+      let (sign, digits) = match size.chars().next() {
+        Some(sign @ ('+' | '-')) => (Some(sign), &size[1..]),
+        _ => (None, size.as_str()),
+      };
+      let bytes = match util::parse_size(digits).and_then(|bytes| AddressSize::try_from(bytes).ok()) {
+        Some(bytes) => bytes,
+        None => {
+          kprintln!(kernel, "{arg0}: invalid number: '{size}'");
+          return EXIT_FAILURE;
+        },
+      };
+
+      let mut exit_code = EXIT_SUCCESS;
+      for pathname in pathnames {
+        let result = kernel.vfs().lookup_path(&pathname);
+        let vinode = match result {
+          Ok(vinode) => vinode,
+          Err(Errno::ENOENT(_)) if no_create => continue,
+          Err(Errno::ENOENT(_)) => {
+            let result = kernel.vfs().create_file(&pathname);
+            match result {
+              Ok(vinode) => vinode,
+              Err(Errno::ENOENT(_)) => {
+                kprintln!(kernel, "{arg0}: cannot open '{pathname}' for writing: No such file or directory");
+                exit_code = EXIT_FAILURE;
+                continue;
+              },
+              Err(Errno::EACCES(_)) => {
+                kprintln!(kernel, "{arg0}: cannot open '{pathname}' for writing: Permission denied");
+                exit_code = EXIT_FAILURE;
+                continue;
+              },
+              Err(errno) => {
+                kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+                exit_code = EXIT_FAILURE;
+                continue;
+              },
+            }
+          },
+          Err(errno) => {
+            kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+            exit_code = EXIT_FAILURE;
+            continue;
+          },
+        };
+
+        let new_size = match sign {
+          Some('+') => vinode.file_size.saturating_add(bytes),
+          Some(_) => vinode.file_size.saturating_sub(bytes),
+          None => bytes,
+        };
+
+        let result = kernel.vfs().truncate(&pathname, new_size);
+        match result {
+          Ok(_) => (),
+          Err(Errno::EISDIR(_)) => {
+            kprintln!(kernel, "{arg0}: cannot open '{pathname}' for writing: Is a directory");
+            exit_code = EXIT_FAILURE;
+          },
+          Err(Errno::EACCES(_)) => {
+            kprintln!(kernel, "{arg0}: cannot open '{pathname}' for writing: Permission denied");
+            exit_code = EXIT_FAILURE;
+          },
+          Err(Errno::EPERM(_)) => {
+            kprintln!(kernel, "{arg0}: cannot truncate '{pathname}': Operation not permitted");
+            exit_code = EXIT_FAILURE;
+          },
+          Err(errno) => {
+            kprintln!(kernel, "{arg0}: cannot truncate '{pathname}': {errno}");
+            exit_code = EXIT_FAILURE;
+          },
+        }
+      }
+
+      exit_code
+    },
+  }
+}
+
+pub fn rm(args: Args, kernel: &mut Kernel) -> AddressSize {
+  let arg0 = args.get(0).unwrap().clone();
+  #[derive(Debug, Parser)]
+  struct BinArgs {
+    #[clap(short, long, takes_value = false)]
+    recurse: bool,
+
+    // #[clap(short, long)]
+    // force: bool,
+
+    pathname: String,
+  }
+
+  match BinArgs::try_parse_from(args.iter()) {
+    Err(message) => {
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
+      1
+    }
+    Ok(BinArgs { pathname, recurse }) => {
+      let result = kernel.vfs().lookup_path(&pathname);
+      let vinode = match result {
+        Ok(vinode) => vinode,
+        Err(Errno::ENOENT(_)) => {
+          kprintln!(kernel, "{arg0}: cannot remove '{pathname}': No such file or directory");
+          return EXIT_ENOENT;
+        },
+        Err(Errno::EACCES(_)) => {
+          kprintln!(kernel, "{arg0}: '{pathname}': Permission denied");
+          return EXIT_FAILURE
+        },
+        Err(errno) => {
+          kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+          return EXIT_FAILURE;
+        },
+      };
+
+      // File case
+      if vinode.mode.file_type() != FileModeType::Dir as u8 {
+        let result = kernel.vfs().unlink(&pathname);
+        return match result {
+          Ok(()) => EXIT_SUCCESS,
+          Err(Errno::EACCES(_)) => {
+            kprintln!(kernel, "{arg0}: '{pathname}': Permission denied");
+            return EXIT_FAILURE
+          },
+          Err(errno) => {
+            kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+            EXIT_FAILURE
+          },
+        }
+      }
+
+      // Directory case
+      if !recurse {
+        kprintln!(kernel, "{arg0}: cannot remove '{pathname}': Is a directory");
+        return EXIT_FAILURE;
+      }
+
+      // Recurse
+      let result = kernel.vfs().read_dir(&pathname);
+      match result {
+        Ok(dir) => {
+          for (name, _) in dir
+            .entries
+            .into_iter()
+            .filter(|(name, _)| name != "." && name != "..")
+          {
+            let cloned_arg0 = args.get(0).unwrap().clone();
+            let new_pathname = format!("{pathname}/{name}");
+            kprintln!(kernel, "{arg0}: descending into '({new_pathname})'");
+            let exit_status = rm(vec![cloned_arg0, String::from("-r"), new_pathname], kernel);
+            if exit_status != EXIT_SUCCESS {
+              return exit_status;
+            }
+          }
+          let result = kernel.vfs().rmdir(&pathname);
+          return match result {
+            Ok(()) => EXIT_SUCCESS,
+            Err(Errno::EACCES(_)) => {
+              kprintln!(kernel, "{arg0}: '{pathname}': Permission denied");
+              return EXIT_FAILURE
+            },
+            Err(errno) => {
+              kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+              EXIT_FAILURE
+            },
+          } 
+        },
+        Err(errno) => {
+          kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+          EXIT_FAILURE
+        },
+      }
+    },
+  }
+}
+
+/// Copy file at `source` to `target` with everything under it,
+/// keeping mode, owners and times, like `cp -a`. Owners are kept
+/// only if current user may give files away
+fn copy_tree(kernel: &mut Kernel, source: &str, target: &str) -> Result<(), Errno> {
+  let vinode = kernel.vfs().lookup_link(source)?;
+
+  match vinode.mode.file_type() {
+    file_type if file_type == FileModeType::Dir as u8 => {
+      kernel.vfs().create_dir(target)?;
+      let dir = kernel.vfs().read_dir(source)?;
+      for name in dir.entries.keys().filter(|&name| name != "." && name != "..") {
+        copy_tree(kernel, &format!("{source}/{name}"), &format!("{target}/{name}"))?;
+      }
+    },
+    // Symbolic link points to the same target, its mode and times don't matter
+    file_type if file_type == FileModeType::Symlink as u8 => {
+      let link_target = kernel.vfs().readlink(source)?;
+      kernel.vfs().symlink(&link_target, target)?;
+      return Ok(());
+    },
+    file_type if file_type == FileModeType::Block as u8 => {
+      kernel.vfs().mknod(target, FileModeType::Block, vinode.rdev)?;
+    },
+    file_type if file_type == FileModeType::Char as u8 => {
+      kernel.vfs().mknod(target, FileModeType::Char, vinode.rdev)?;
+    },
+    _ => {
+      let bytes = kernel.vfs().read_file(source, EVERYTHING)?;
+      kernel.vfs().create_file(target)?;
+      kernel.vfs().write_file(target, &bytes)?;
+    },
+  }
+
+  let result = kernel.vfs().change_owners(target, vinode.uid, vinode.gid);
+  match result {
+    Ok(()) | Err(Errno::EPERM(_)) => (),
+    Err(errno) => return Err(errno),
+  }
+  kernel.vfs().change_mode(target, vinode.mode)?;
+  kernel.vfs().change_times(target, Times {
+    atime: vinode.atime,
+    mtime: vinode.mtime,
+    ctime: kernel.clock.now(),
+    btime: vinode.btime,
+  })
+}
+
+/// Remove file at `pathname` with everything under it
+fn remove_tree(kernel: &mut Kernel, pathname: &str) -> Result<(), Errno> {
+  if kernel.vfs().lookup_link(pathname)?.mode.file_type() != FileModeType::Dir as u8 {
+    return kernel.vfs().unlink(pathname);
+  }
+
+  let dir = kernel.vfs().read_dir(pathname)?;
+  for name in dir.entries.keys().filter(|&name| name != "." && name != "..") {
+    remove_tree(kernel, &format!("{pathname}/{name}"))?;
+  }
+  kernel.vfs().rmdir(pathname)
+}
+
+pub fn mv(args: Args, kernel: &mut Kernel) -> AddressSize {
+  let arg0 = args.get(0).unwrap().clone();
+  #[derive(Debug, Parser)]
+  struct BinArgs {
+    source_pathname: String,
+    /// New name, or directory to move into
+    target_pathname: String,
+  }
+
+  match BinArgs::try_parse_from(args.iter()) {
+    Err(message) => {
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
+      1
+    }
+    Ok(BinArgs { source_pathname, target_pathname }) => {
+      // Into existing directory under the same name, like `mv file dir`
+      let result = kernel.vfs().lookup_path(&target_pathname);
+      let target_pathname = match result {
+        Ok(vinode) if vinode.mode.file_type() == FileModeType::Dir as u8 => match VFS::split_path(&source_pathname) {
+          Ok((_, name)) => format!("{}/{name}", target_pathname.trim_end_matches('/')),
+          Err(_) => target_pathname,
+        },
+        _ => target_pathname,
+      };
+
+      // Within filesystem it is only a new name. Across
+      // filesystems it is copied and removed from where it was
+      let result = kernel.vfs().rename(&source_pathname, &target_pathname);
+      let result = match result {
+        Err(Errno::EXDEV(_)) => {
+          tracing::debug!(source_pathname, target_pathname, "mv: crossing filesystems, copying");
+          let result = kernel.vfs().lookup_link(&target_pathname);
+          match result {
+            Ok(vinode) if vinode.mode.file_type() == FileModeType::Dir as u8 => {
+              Err(Errno::EISDIR(format!("mv: {target_pathname} is a directory").into()))
+            },
+            Ok(_) => kernel.vfs().unlink(&target_pathname),
+            Err(Errno::ENOENT(_)) => Ok(()),
+            Err(errno) => Err(errno),
+          }
+          .and_then(|()| copy_tree(kernel, &source_pathname, &target_pathname))
+          .and_then(|()| remove_tree(kernel, &source_pathname))
+        },
+        result => result,
+      };
+
+      match result {
+        Ok(()) => EXIT_SUCCESS,
+        Err(Errno::ENOENT(_)) => {
+          kprintln!(kernel, "{arg0}: cannot move '{source_pathname}': No such file or directory");
+          EXIT_ENOENT
+        },
+        Err(Errno::EACCES(_)) => {
+          kprintln!(kernel, "{arg0}: cannot move '{source_pathname}' to '{target_pathname}': Permission denied");
+          EXIT_FAILURE
+        },
+        Err(Errno::EISDIR(_)) => {
+          kprintln!(kernel, "{arg0}: cannot overwrite directory '{target_pathname}' with non-directory");
+          EXIT_FAILURE
+        },
+        Err(Errno::ENOTDIR(_)) => {
+          kprintln!(kernel, "{arg0}: cannot overwrite non-directory '{target_pathname}' with directory '{source_pathname}'");
+          EXIT_FAILURE
+        },
+        Err(Errno::ENOTEMPTY(_)) => {
+          kprintln!(kernel, "{arg0}: cannot move '{source_pathname}' to '{target_pathname}': Directory not empty");
+          EXIT_FAILURE
+        },
+        Err(Errno::EINVAL(_)) => {
+          kprintln!(kernel, "{arg0}: cannot move '{source_pathname}' to a subdirectory of itself, '{target_pathname}'");
+          EXIT_FAILURE
+        },
+        Err(Errno::EBUSY(_)) => {
+          kprintln!(kernel, "{arg0}: cannot move '{source_pathname}' to '{target_pathname}': Device or resource busy");
+          EXIT_FAILURE
+        },
+        Err(errno) => {
+          kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+          EXIT_FAILURE
+        },
+      }
+    },
+  }
+}
+
+pub fn ln(args: Args, kernel: &mut Kernel) -> AddressSize {
+  let arg0 = args.get(0).unwrap().clone();
+  #[derive(Debug, Parser)]
+  struct BinArgs {
+    /// Make symbolic link to `target_pathname`, which doesn't have to exist
+    #[clap(short, long)]
+    symbolic: bool,
+    /// File to give one more name
+    target_pathname: String,
+    /// The new name, in the same filesystem unless symbolic
+    link_pathname: String,
+  }
+
+  match BinArgs::try_parse_from(args.iter()) {
+    Err(message) => {
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
+      1
+    }
+    Ok(BinArgs { symbolic, target_pathname, link_pathname }) => {
+      let result = match symbolic {
+        true => kernel.vfs().symlink(&target_pathname, &link_pathname),
+        false => kernel.vfs().link(&target_pathname, &link_pathname),
+      };
+      match result {
+        Ok(_) => EXIT_SUCCESS,
+        Err(Errno::ENOENT(_)) => {
+          kprintln!(kernel, "{arg0}: {target_pathname}: No such file or directory");
+          EXIT_ENOENT
+        },
+        Err(Errno::EACCES(_)) => {
+          kprintln!(kernel, "{arg0}: '{link_pathname}': Permission denied");
+          EXIT_FAILURE
+        },
+        Err(Errno::EEXIST(_)) => {
+          kprintln!(kernel, "{arg0}: '{link_pathname}': File exists");
+          EXIT_FAILURE
+        },
+        Err(Errno::EXDEV(_)) => {
+          kprintln!(kernel, "{arg0}: '{link_pathname}' -> '{target_pathname}': Invalid cross-device link");
+          EXIT_FAILURE
+        },
+        Err(Errno::EPERM(_)) => {
+          kprintln!(kernel, "{arg0}: '{target_pathname}': hard link not allowed");
+          EXIT_FAILURE
+        },
+        Err(errno) => {
+          kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+          EXIT_FAILURE
+        },
+      }
+    },
+  }
+}
+
+pub fn readlink(args: Args, kernel: &mut Kernel) -> AddressSize {
+  let arg0 = args.get(0).unwrap().clone();
+  #[derive(Debug, Parser)]
+  struct BinArgs {
+    /// Symbolic link to print target of
+    pathname: String,
+  }
+
+  match BinArgs::try_parse_from(args.iter()) {
+    Err(message) => {
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
+      1
+    }
+    Ok(BinArgs { pathname }) => {
+      let result = kernel.vfs().readlink(&pathname);
+      match result {
+        Ok(target) => {
+          kprintln!(kernel, "{target}");
+          EXIT_SUCCESS
+        },
+        Err(Errno::ENOENT(_)) => {
+          kprintln!(kernel, "{arg0}: {pathname}: No such file or directory");
+          EXIT_ENOENT
+        },
+        // Like readlink(1) without -v, not a link is just a failure
+        Err(Errno::EINVAL(_)) => EXIT_FAILURE,
+        Err(errno) => {
+          kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+          EXIT_FAILURE
+        },
+      }
+    },
+  }
+}
+
+pub fn cp(args: Args, kernel: &mut Kernel) -> AddressSize {
+  let arg0 = args.get(0).unwrap().clone();
+  #[derive(Debug, Parser)]
+  struct BinArgs {
+    source_pathname: String,
+    target_pathname: String,
+  }
+
+  let arg0 = args.get(0).unwrap().clone();
+  match BinArgs::try_parse_from(args.iter()) {
+    Err(message) => {
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
+      1
+    }
+    Ok(BinArgs { source_pathname, target_pathname }) => {
+      let result = kernel.vfs().lookup_path(&source_pathname);
+      let source_vinode = match result {
+        Ok(vinode) => vinode,
+        Err(Errno::ENOENT(_)) => {
+          kprintln!(kernel, "{arg0}: {source_pathname}: No such file or directory");
+          return EXIT_ENOENT;
+        },
+        Err(Errno::EACCES(_)) => {
+          kprintln!(kernel, "{arg0}: {source_pathname}: Permission denied");
+          return EXIT_FAILURE
+        },
+        Err(errno) => {
+          kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+          return EXIT_FAILURE;
+        },
+      };
+
+      // Guard for target already existing
+      if kernel.vfs().lookup_path(&target_pathname).is_ok() {
+        kprintln!(kernel, "{arg0}: {target_pathname}: Already exists");
+        return EXIT_FAILURE;
+      }
+
+      tracing::debug!(source_pathname, file_type = source_vinode.mode.file_type(), "cp: copying");
+
+      // Main part - base file case or recurse
+      if source_vinode.mode.file_type() == FileModeType::File as u8 {
+        let source_bytes = kernel.vfs().read_file(&source_pathname, AddressSize::MAX).unwrap();
+        kernel.vfs().create_file(&target_pathname).unwrap();
+        kernel.vfs().write_file(&target_pathname, &source_bytes).unwrap();
+        EXIT_SUCCESS
+      } else {
+        tracing::debug!(target_pathname, "cp: creating directory");
+        kernel.vfs().create_dir(&target_pathname).unwrap();
+        let dir = kernel.vfs().read_dir(&source_pathname).unwrap();
+        for (name, _) in dir
+          .entries
+          .iter()
+          .filter(|(name, _)| **name != "." && **name != "..") 
+        {
+          let cloned_arg0 = args.get(0).unwrap().clone();
+          let new_source_pathname = format!("{source_pathname}/{name}");
+          let new_target_pathname = format!("{target_pathname}/{name}");
+          tracing::debug!(source_pathname, "cp: descending");
+          let exit_status = cp(vec![cloned_arg0, new_source_pathname, new_target_pathname], kernel);
+          if exit_status != EXIT_SUCCESS {
+            return exit_status;
+          }
+        }
+        EXIT_SUCCESS 
+      }
+    },
+  }
+}
+
+pub fn write(args: Args, kernel: &mut Kernel) -> AddressSize {
+  let arg0 = args.get(0).unwrap().clone();
+  #[derive(Debug, Parser)]
+  struct BinArgs {
+    pathname: String,
+    text: String,
+  }
+
+  match BinArgs::try_parse_from(args.iter()) {
+    Err(message) => {
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
+      1
+    },
+    Ok(BinArgs { pathname, text }) => {
+      let bytes = text.as_bytes();
+      let result = kernel.vfs().write_file(&pathname, bytes);
+      match result {
+        Ok(_) => EXIT_SUCCESS,
+        Err(Errno::ENOENT(_)) => {
+          kprintln!(kernel, "{arg0}: {pathname}: No such file or directory");
+          return EXIT_ENOENT;
+        },
+        Err(Errno::EISDIR(_)) => {
+          kprintln!(kernel, "{arg0}: {pathname}: Is a directory");
+          return EXIT_FAILURE;
+        },
+        Err(Errno::EACCES(_)) => {
+          kprintln!(kernel, "{arg0}: '{pathname}': Permission denied");
+          return EXIT_FAILURE
+        },
+        Err(errno) => {
+          kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+          return EXIT_FAILURE;
+        },
+      }
+    },
+  }
+}
+
+pub fn ed(args: Args, kernel: &mut Kernel) -> AddressSize {
+  let arg0 = args.get(0).unwrap().clone();
+  #[derive(Debug, Parser)]
+  struct BinArgs {
+    pathname: String,
+  }
+
+  match BinArgs::try_parse_from(args.iter()) {
+    Err(message) => {
+      kprintln!(kernel, "{arg0}: parse error: {message}");
+      1
+    },
+    Ok(BinArgs { pathname }) => {
+      // Read file
+      let result = kernel.vfs().read_file(&pathname, AddressSize::MAX);
+      let bytes = match result {
+        Ok(bytes) => bytes,
+        Err(Errno::ENOENT(_)) => {
+          kprintln!(kernel, "{arg0}: {pathname}: No such file or directory");
+          return EXIT_ENOENT;
+        },
+        Err(Errno::EISDIR(_)) => {
+          kprintln!(kernel, "{arg0}: {pathname}: Is a directory");
+          return EXIT_FAILURE;
+        },
+        Err(Errno::EACCES(_)) => {
+          kprintln!(kernel, "{arg0}: '{pathname}': Permission denied");
+          return EXIT_FAILURE
+        },
+        Err(errno) => {
+          kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+          return EXIT_FAILURE;
+        },
+      };
+      
+      // Edit file
+      let editor = match std::env::var("EDITOR").ok().or(kernel.host_editor.clone()) {
+        Some(editor) => editor,
+        None => {
+          kprintln!(kernel, "{arg0}: EDITOR of host is not set and host config has no editor");
+          return EXIT_FAILURE;
+        },
+      };
+      let mut file_path = std::env::temp_dir();
+      file_path.push("eunix_editor_file");
+
+      match File::create(&file_path)
+        .and_then(|mut file| file.write_all(&bytes)) 
+      {
+        Ok(_) => {
+        },
+        Err(message) => {
+          kprintln!(kernel, "{arg0}: error while creating platform-provided temp file: {message:#?}");
+          return EXIT_FAILURE;
+        },
+      }
+      
+
+      if let Err(message) = Command::new(editor)
+          .arg(&file_path)
+          .status() 
+      {
+        kprintln!(kernel, "{arg0}: error while opening platform-provided editor: {message:#?}");
+        return EXIT_FAILURE;
+      }
+
+      let mut edited_bytes = Vec::new();
+
+      match File::open(&file_path)
+        .and_then(|mut file| file.read_to_end(&mut edited_bytes)) 
+      {
+        Ok(_) => {
+        },
+        Err(message) => {
+          kprintln!(kernel, "{arg0}: error while reading back edited platform-provided temp file: {message:#?}");
+          return EXIT_FAILURE;
+        },
+      }
+
+      // Write file back
+      let result = kernel.vfs().write_file(&pathname, &edited_bytes);
+      return match result {
+        Ok(_) => EXIT_SUCCESS,
+        Err(Errno::EACCES(_)) => {
+          kprintln!(kernel, "{arg0}: '{pathname}': Permission denied");
+          return EXIT_FAILURE
+        },
+        Err(errno) => {
+          kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+          EXIT_FAILURE
+        },
+      }
+    },
+  }
+}
+
+/// Apply symbolic mode like `u+x,go-w`, `=r` or `-x` to `mode`.
+/// Omitted `ugoa` means all classes.
+/// Returns: `None` if `symbolic_mode` is invalid
+fn apply_symbolic_mode(mode: FileMode, symbolic_mode: &str) -> Option<FileMode> {
+  let clause_regex = Regex::new("^([ugoa]*)([-+=])([rwx]*)$").unwrap();
+
+  symbolic_mode
+    .split(',')
+    .try_fold(mode, |mode, clause| {
+      let captures = clause_regex.captures(clause).ok()??;
+      let who = match &captures[1] {
+        "" => "a",
+        who => who,
+      };
+      let permissions = captures[3]
+        .chars()
+        .fold(0u8, |permissions, permission| permissions | match permission {
+          'r' => 0b100,
+          'w' => 0b010,
+          _ => 0b001,
+        });
+      let apply = |bits: u8| match &captures[2] {
+        "+" => bits | permissions,
+        "-" => bits & !permissions,
+        _ => permissions,
+      };
+      let affects = |class: char| who.contains(class) || who.contains('a');
+
+      let mode = if affects('u') { mode.with_user(apply(mode.user())) } else { mode };
+      let mode = if affects('g') { mode.with_group(apply(mode.group())) } else { mode };
+      let mode = if affects('o') { mode.with_others(apply(mode.others())) } else { mode };
+
+      Some(mode)
+    })
+}
+
+pub fn chmod(args: Args, kernel: &mut Kernel) -> AddressSize {
+  let arg0 = args.get(0).unwrap().clone();
+  #[derive(Debug, Parser)]
+  struct BinArgs {
+    /// Octal like `755` or symbolic like `u+x,go-w`
+    #[clap(allow_hyphen_values = true)]
+    mode: String,
+    pathname: String,
+  }
+
+  match BinArgs::try_parse_from(args.iter()) {
+    Err(message) => {
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
+      1
+    }
+    Ok(BinArgs { pathname, mode: new_mode_string }) => {
+      let result = kernel.vfs().lookup_path(&pathname);
+      let old_mode = match result {
+        Ok(vinode) => vinode.mode,
+        Err(Errno::ENOENT(_)) => {
+          kprintln!(kernel, "{arg0}: {pathname}: No such file or directory");
+          return EXIT_ENOENT;
+        },
+        Err(Errno::EACCES(_)) => {
+          kprintln!(kernel, "{arg0}: '{pathname}': Permission denied");
+          return EXIT_FAILURE
+        },
+        Err(errno) => {
+          kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+          return EXIT_FAILURE;
+        },
+      };
+
+      let new_mode = if Regex::new("^[0-7]{3}$")
+        .unwrap()
+        .is_match(&new_mode_string)
+        .unwrap()
+      {
+        let user: AddressSize = new_mode_string.chars().map(|c| c.to_digit(8)).nth(0).unwrap().unwrap();
+        let group: AddressSize = new_mode_string.chars().map(|c| c.to_digit(8)).nth(1).unwrap().unwrap();
+        let others: AddressSize = new_mode_string.chars().map(|c| c.to_digit(8)).nth(2).unwrap().unwrap();
+
+        old_mode
+          .with_user(user as u8)
+          .with_group(group as u8)
+          .with_others(others as u8)
+      } else if let Some(new_mode) = apply_symbolic_mode(old_mode, &new_mode_string) {
+        new_mode
+      } else {
+        kprintln!(kernel, "{arg0}: invalid mode: '{new_mode_string}'");
+        return EXIT_FAILURE;
+      };
+
+      let result = kernel.vfs().change_mode(&pathname, new_mode);
+      match result {
+        Ok(_) => EXIT_SUCCESS,
+        Err(Errno::EPERM(_)) => {
+          kprintln!(kernel, "{arg0}: {pathname}: Operation not permitted");
+          return EXIT_FAILURE
+        },
+        Err(Errno::EACCES(_)) => {
+          kprintln!(kernel, "{arg0}: '{pathname}': Permission denied");
+          return EXIT_FAILURE
+        },
+        Err(errno) => {
+          kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+          return EXIT_FAILURE;
+        },
+      }
+    },
+  }
+}
+
+pub fn chown(args: Args, kernel: &mut Kernel) -> AddressSize {
+  let arg0 = args.get(0).unwrap().clone();
+  #[derive(Debug, Parser)]
+  struct BinArgs {
+    new_owners_string: String,
+    pathname: String,
+  }
+
+  match BinArgs::try_parse_from(args.iter()) {
+    Err(message) => {
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
+      1
+    }
+    Ok(BinArgs { pathname, new_owners_string }) => {
+      let result = kernel.vfs().lookup_path(&pathname);
+      let VINode {
+        uid,
+        gid,
+        ..
+      } = match result {
+        Ok(vinode) => vinode,
+        Err(Errno::EPERM(_)) => {
+          kprintln!(kernel, "{arg0}: changing owner of '{pathname}': Operation not permitted");
+          return EXIT_FAILURE
+        },
+        Err(Errno::EACCES(_)) => {
+          kprintln!(kernel, "{arg0}: '{pathname}': Permission denied");
+          return EXIT_FAILURE
+        },
+        Err(Errno::ENOENT(_)) => {
+          kprintln!(kernel, "{arg0}: {pathname}: No such file or directory");
+          return EXIT_ENOENT;
+        },
+        Err(errno) => {
+          kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+          return EXIT_FAILURE;
+        },
+      };
+
+      // Parse names - default to current
+      let user_name = new_owners_string
+        .split(":")
+        .nth(0)
+        .unwrap_or(kernel.uid_map.get(&kernel.current_uid).unwrap());
+      let group_name = new_owners_string
+        .split(":")
+        .nth(1)
+        .unwrap_or(kernel.uid_map.get(&kernel.current_gid).unwrap());
+
+      // Guard user
+      let uid = if let Some(uid) = kernel
+        .uid_map
+        .iter()
+        .find(|(_, name)| user_name == *name)
+        .map(|(id, _)| *id)
+      {
+        uid
+      } else {
+        kprintln!(kernel, "{arg0}: invalid user: '{new_owners_string}'");
+        return EXIT_FAILURE;
+      };
+
+      // Guard group
+      let gid = if let Some(gid) = kernel
+        .gid_map
+        .iter()
+        .find(|(_, name)| group_name == *name)
+        .map(|(id, _)| *id)
+      {
+        gid
+      } else {
+        kprintln!(kernel, "{arg0}: invalid group: '{new_owners_string}'");
+        return EXIT_FAILURE;
+      };
+
+      let result = kernel.vfs().change_owners(&pathname, uid, gid);
+      match result {
+        Ok(_) => EXIT_SUCCESS,
+        Err(Errno::EPERM(_)) => {
+          kprintln!(kernel, "{arg0}: changing owner of '{pathname}': Operation not permitted");
+          return EXIT_FAILURE
+        },
+        Err(Errno::EACCES(_)) => {
+          kprintln!(kernel, "{arg0}: '{pathname}': Permission denied");
+          return EXIT_FAILURE
+        },
+        Err(errno) => {
+          kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+          EXIT_FAILURE
+        },
+      }
+    },
+  }
+}
+
+pub fn getfattr(args: Args, kernel: &mut Kernel) -> AddressSize {
+  let arg0 = args.get(0).unwrap().clone();
+  #[derive(Debug, Parser)]
+  struct BinArgs {
+    /// Print values of all attributes, not only names
+    #[clap(short, long)]
+    dump: bool,
+
+    /// Print value of attribute `name` only
+    #[clap(short, long)]
+    name: Option<String>,
+
+    #[clap(required = true)]
+    pathnames: Vec<String>,
+  }
+
+  match BinArgs::try_parse_from(args.iter()) {
+    Err(message) => {
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
+      1
+    }
+    Ok(BinArgs { dump, name, pathnames }) => {
+      // Values are printed for -n too
+      let with_values = dump || name.is_some();
+
+      let mut exit_code = EXIT_SUCCESS;
+      for pathname in pathnames {
+        let names = match &name {
+          Some(name) => Ok(vec![name.clone()]),
+          None => kernel.vfs().list_xattr(&pathname),
+        };
+        let attributes = names.and_then(|names| {
+          names
+            .into_iter()
+            .map(|name| match with_values {
+              true => kernel.vfs().get_xattr(&pathname, &name).map(|value| (name, Some(value))),
+              false => Ok((name, None)),
+            })
+            .collect::<Result<Vec<_>, Errno>>()
+        });
+        let attributes = match attributes {
+          Ok(attributes) => attributes,
+          Err(errno) => {
+            exit_code = print_xattr_error(kernel, &arg0, &pathname, errno);
+            continue;
+          },
+        };
+        if attributes.is_empty() {
+          continue;
+        }
+
+        kprintln!(kernel, "# file: {pathname}");
+        for (name, value) in attributes {
+          match value {
+            Some(value) => kprintln!(kernel, "{name}={}", format_xattr_value(&value)),
+            None => kprintln!(kernel, "{name}"),
+          }
+        }
+        kprintln!(kernel);
+      }
+
+      exit_code
+    },
+  }
+}
+
+pub fn setfattr(args: Args, kernel: &mut Kernel) -> AddressSize {
+  let arg0 = args.get(0).unwrap().clone();
+  #[derive(Debug, Parser)]
+  struct BinArgs {
+    /// Name of attribute to set, like `user.comment`
+    #[clap(short, long, required_unless_present = "remove", conflicts_with = "remove")]
+    name: Option<String>,
+
+    /// Value of attribute: text, or `0x` and hex digits.
+    /// Empty if not given
+    #[clap(short, long, requires = "name")]
+    value: Option<String>,
+
+    /// Name of attribute to remove
+    #[clap(short = 'x', long)]
+    remove: Option<String>,
+
+    #[clap(required = true)]
+    pathnames: Vec<String>,
+  }
+
+  match BinArgs::try_parse_from(args.iter()) {
+    Err(message) => {
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
+      1
+    }
+    Ok(BinArgs { name, value, remove, pathnames }) => {
+      let value = match value.as_deref().map(parse_xattr_value).transpose() {
+        Ok(value) => value.unwrap_or_default(),
+        Err(_) => {
+          kprintln!(kernel, "{arg0}: invalid value: '{}'", value.unwrap_or_default());
+          return EXIT_FAILURE;
+        },
+      };
+
+      let mut exit_code = EXIT_SUCCESS;
+      for pathname in pathnames {
+        let result = match (&name, &remove) {
+          (_, Some(remove)) => kernel.vfs().set_xattr(&pathname, remove, None),
+          (Some(name), None) => kernel.vfs().set_xattr(&pathname, name, Some(&value)),
+          (None, None) => unreachable!("clap requires one of them"),
+        };
+        if let Err(errno) = result {
+          exit_code = print_xattr_error(kernel, &arg0, &pathname, errno);
+        }
+      }
+
+      exit_code
+    },
+  }
+}
+
+/// Value of attribute as getfattr prints it: quoted text if it is
+/// printable, `0x` and hex digits otherwise
+fn format_xattr_value(value: &[u8]) -> String {
+  match std::str::from_utf8(value) {
+    Ok(text) if !text.chars().any(|char| char.is_control() || char == '"') => format!("\"{text}\""),
+    _ => format!("0x{}", hex::encode(value)),
+  }
+}
+
+/// Value of attribute as setfattr takes it, see `format_xattr_value`
+fn parse_xattr_value(value: &str) -> Result<Vec<u8>, hex::FromHexError> {
+  match value.strip_prefix("0x") {
+    Some(digits) => hex::decode(digits),
+    None => Ok(value.trim_matches('"').as_bytes().to_owned()),
+  }
+}
+
+/// Print error of getting or setting attribute like attr(1) does.
+/// Returns: exit code
+fn print_xattr_error(kernel: &mut Kernel, arg0: &str, pathname: &str, errno: Errno) -> AddressSize {
+  let message = match errno {
+    Errno::ENOENT(_) => {
+      kprintln!(kernel, "{arg0}: {pathname}: No such file or directory");
+      return EXIT_ENOENT;
+    },
+    Errno::ENODATA(_) => "No such attribute",
+    Errno::EACCES(_) => "Permission denied",
+    Errno::EPERM(_) => "Operation not permitted",
+    Errno::ENOTSUP(_) => "Operation not supported",
+    Errno::ENOSPC(_) => "No space left on device",
+    Errno::EROFS(_) => "Read-only file system",
+    errno => {
+      kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+      return EXIT_FAILURE;
+    },
+  };
+  kprintln!(kernel, "{arg0}: {pathname}: {message}");
+
+  EXIT_FAILURE
+}
+
+// System related stuff
+pub fn uname(args: Args, kernel: &mut Kernel) -> AddressSize {
+  let arg0 = args.get(0).unwrap().clone();
+  #[derive(Debug, Parser)]
+  struct BinArgs {
+  }
+
+  match BinArgs::try_parse_from(args.iter()) {
+    Err(message) => {
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
+      1
+    }
+    Ok(BinArgs { }) => {
+      kprintln!(kernel, "Eunix");
+      EXIT_SUCCESS
+    },
+  }
+}
+
+pub fn lsblk(args: Args, kernel: &mut Kernel) -> AddressSize {
+  let arg0 = args.get(0).unwrap().clone();
+  let result = kernel.vfs().read_dir(DEV_PATH);
+  let dir = match result {
+    Ok(dir) => dir,
+    Err(errno) => {
+      kprintln!(kernel, "{arg0}: cannot read '{DEV_PATH}': {errno}");
+      return EXIT_FAILURE;
+    },
+  };
+
+  kprintln!(kernel, "NAME\tMAJ:MIN\tSIZE\tBLOCKS\tMOUNTPOINT");
+  for (name, _) in dir.entries {
+    let result = kernel.vfs().stat(&format!("{DEV_PATH}/{name}"));
+    let FileStat { mode, size, rdev, block_size, .. } = match result {
+      Ok(stat) => stat,
+      Err(errno) => {
+        kprintln!(kernel, "{arg0}: cannot stat '{DEV_PATH}/{name}': {errno}");
+        return EXIT_FAILURE;
+      },
+    };
+
+    if mode.file_type() != FileModeType::Block as u8 {
+      continue;
+    }
+
+    // Only disks of machine have it, sysfs may be not mounted too
+    let mount_point = kernel.read_file(&format!("{SYS_PATH}{BLOCK_CLASS_PATH}/{name}/mounted"), EVERYTHING)
+      .map(|bytes| String::from_utf8_lossy(&bytes).trim_end().to_owned())
+      .unwrap_or_default();
+
+    let blocks_count = size.checked_div(block_size).unwrap_or(0);
+    kprintln!(kernel, "{name}\t{}:{}\t{size}\t{blocks_count}\t{mount_point}", rdev.major, rdev.minor);
+  }
+
+  EXIT_SUCCESS
+}
+
+pub fn free(args: Args, kernel: &mut Kernel) -> AddressSize {
+  let arg0 = args.get(0).unwrap().clone();
+  #[derive(Debug, Parser)]
+  struct BinArgs {
+    /// Show sizes in MiB instead of KiB
+    #[clap(short, long, takes_value = false)]
+    mebi: bool,
+  }
+
+  match BinArgs::try_parse_from(args.iter()) {
+    Err(message) => {
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
+      1
+    }
+    Ok(BinArgs { mebi }) => {
+      let unit = if mebi { 1024 * 1024 } else { 1024 };
+      let total = kernel.resources.memory;
+      let used = kernel.memory_used();
+      let free = total.saturating_sub(used);
+
+      kprintln!(kernel, "\ttotal\tused\tfree");
+      kprintln!(kernel, "Mem:\t{}\t{}\t{}", total / unit, used / unit, free / unit);
+
+      EXIT_SUCCESS
+    },
+  }
+}
+
+pub fn dmesg(args: Args, kernel: &mut Kernel) -> AddressSize {
+  let arg0 = args.get(0).unwrap().clone();
+  #[derive(Debug, Parser)]
+  struct BinArgs {
+    /// Clear the buffer after printing it
+    #[clap(short = 'c', long, takes_value = false)]
+    read_clear: bool,
+
+    /// Print only messages at this level or more severe:
+    /// error, warn, info, debug, trace
+    #[clap(short, long)]
+    level: Option<String>,
+
+    /// Set level of messages written to the serial port
+    #[clap(short = 'n', long)]
+    console_level: Option<String>,
+  }
+
+  match BinArgs::try_parse_from(args.iter()) {
+    Err(message) => {
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
+      1
+    }
+    Ok(BinArgs { read_clear, level, console_level }) => {
+      if (read_clear || console_level.is_some()) && kernel.current_uid != ROOT_UID {
+        kprintln!(kernel, "{arg0}: must be superuser");
+        return EXIT_FAILURE;
+      }
+
+      if let Some(console_level) = console_level {
+        match dmesg::parse_level(&console_level) {
+          Ok(console_level) => kernel.dmesg.lock().unwrap().console_level = console_level,
+          Err(errno) => {
+            kprintln!(kernel, "{arg0}: {errno}");
+            return EXIT_FAILURE;
+          },
+        }
+        return EXIT_SUCCESS;
+      }
+
+      let level = match level.as_deref().map(dmesg::parse_level).transpose() {
+        Ok(level) => level.unwrap_or(tracing::Level::TRACE),
+        Err(errno) => {
+          kprintln!(kernel, "{arg0}: {errno}");
+          return EXIT_FAILURE;
+        },
+      };
+      let lines = kernel.dmesg.lock().unwrap()
+        .records()
+        .filter(|record| record.level <= level)
+        .map(|record| format!(
+          "[{}] {:<5} {}\n",
+          Utc.timestamp_opt(record.time as i64, 0).unwrap().format("%Y-%m-%d %H:%M:%S"),
+          record.level,
+          record.message,
+        ))
+        .collect::<String>();
+      kprint!(kernel, "{lines}");
+      if read_clear {
+        kernel.dmesg.lock().unwrap().clear();
+      }
+
+      EXIT_SUCCESS
+    },
+  }
+}
+
+/// Ask machine to do `action` once init exits
+fn request_power_action(args: Args, kernel: &mut Kernel, action: PowerAction) -> AddressSize {
+  let arg0 = args.get(0).unwrap().clone();
+  #[derive(Debug, Parser)]
+  struct BinArgs {
+  }
+
+  match BinArgs::try_parse_from(args.iter()) {
+    Err(message) => {
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
+      1
+    }
+    Ok(BinArgs { }) => {
+      if kernel.current_uid != ROOT_UID {
+        kprintln!(kernel, "{arg0}: must be superuser");
+        return EXIT_FAILURE;
+      }
+
+      *kernel.power_action.lock().unwrap() = Some(action);
+      EXIT_SUCCESS
+    },
+  }
+}
+
+pub fn reboot(args: Args, kernel: &mut Kernel) -> AddressSize {
+  request_power_action(args, kernel, PowerAction::Reboot)
+}
+
+pub fn poweroff(args: Args, kernel: &mut Kernel) -> AddressSize {
+  request_power_action(args, kernel, PowerAction::Poweroff)
+}
+
+pub fn passwd(args: Args, kernel: &mut Kernel) -> AddressSize {
+  let arg0 = args.get(0).unwrap().clone();
+  #[derive(Debug, Parser)]
+  struct BinArgs {
+    /// Only reread /etc/passwd
+    #[clap(short, long, takes_value = false)]
+    update: bool,
+
+    /// Lock password of user instead of changing it
+    #[clap(short, long, takes_value = false)]
+    lock: bool,
+
+    /// User to change password of, current one by default
+    user: Option<String>,
+  }
+
+  match BinArgs::try_parse_from(args.iter()) {
+    Err(message) => {
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
+      1
+    }
+    Ok(BinArgs { update, lock, user }) => {
+      if update && let Err(errno) = kernel.update_uid_gid_maps() {
+        kprintln!(kernel, "{arg0}: cannot update '{PASSWD_PATH}': {errno}");
+        return EXIT_FAILURE;
+      }
+      if update {
+        return EXIT_SUCCESS;
+      }
+
+      let user = match user.or_else(|| kernel.uid_map.get(&kernel.current_uid).cloned()) {
+        Some(user) => user,
+        None => {
+          kprintln!(kernel, "{arg0}: current user has no name");
+          return EXIT_FAILURE;
+        },
       };
 
-      // Guard group
-      let gid = if let Some(gid) = kernel
-        .gid_map
+      let bytes = match as_root(kernel, |kernel| kernel.vfs().read_file(PASSWD_PATH, EVERYTHING)) {
+        Ok(bytes) => bytes,
+        Err(errno) => {
+          kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+          return EXIT_FAILURE
+        },
+      };
+      let passwd = match Passwd::parse_passwds(&String::from_utf8(bytes).unwrap())
+        .into_iter()
+        .find(|passwd| passwd.name == user)
+      {
+        Some(passwd) => passwd,
+        None => {
+          kprintln!(kernel, "{arg0}: user '{user}' does not exist in {PASSWD_PATH}");
+          return EXIT_FAILURE;
+        },
+      };
+
+      if lock {
+        if kernel.current_uid != ROOT_UID {
+          kprintln!(kernel, "{arg0}: locking password: Operation not permitted");
+          return EXIT_FAILURE;
+        }
+        if let Err(errno) = modify_shadow(kernel, &user, Shadow::lock) {
+          kprintln!(kernel, "{arg0}: cannot update '{SHADOW_PATH}': {errno}");
+          return EXIT_FAILURE;
+        }
+
+        kprintln!(kernel, "{arg0}: password of '{user}' locked");
+        return EXIT_SUCCESS;
+      }
+
+      // Only root can change passwords of others, and without knowing them
+      if kernel.current_uid != ROOT_UID {
+        if passwd.uid != kernel.current_uid {
+          kprintln!(kernel, "{arg0}: You may not modify password information for {user}");
+          return EXIT_FAILURE;
+        }
+
+        let input_password = match prompt_line(kernel, "Current password: ") {
+          Ok(input_password) => input_password,
+          Err(errno) => {
+            kprintln!(kernel, "{arg0}: cannot read password: {errno}");
+            return EXIT_FAILURE;
+          },
+        };
+        match authenticate(kernel, &passwd, &input_password) {
+          Ok(true) => (),
+          Ok(false) => {
+            kprintln!(kernel, "{arg0}: Authentication token manipulation error");
+            return EXIT_FAILURE;
+          },
+          Err(errno) => {
+            kprintln!(kernel, "{arg0}: cannot check password of '{user}': {errno}");
+            return EXIT_FAILURE;
+          },
+        }
+      }
+
+      // Read new password from user
+      let (password_one, password_two) = match prompt_line(kernel, "New password: ")
+        .and_then(|password_one| Ok((password_one, prompt_line(kernel, "Retype password: ")?)))
+      {
+        Ok(passwords) => passwords,
+        Err(errno) => {
+          kprintln!(kernel, "{arg0}: cannot read password: {errno}");
+          return EXIT_FAILURE;
+        },
+      };
+      if password_one != password_two {
+        kprintln!(kernel, "{arg0}: Passwords do not match");
+        return EXIT_FAILURE;
+      }
+
+      if let Err(errno) = store_password(kernel, &user, &password_one) {
+        kprintln!(kernel, "{arg0}: cannot update password of '{user}': {errno}");
+        return EXIT_FAILURE;
+      }
+
+      kprintln!(kernel, "{arg0}: password updated successfully");
+      EXIT_SUCCESS
+    }
+  }
+}
+
+pub fn dumpe5fs(args: Args, kernel: &mut Kernel) -> AddressSize {
+  let arg0 = args.get(0).unwrap().clone();
+  #[derive(Debug, Parser)]
+  struct BinArgs {
+    /// Print used inodes and their blocks too
+    #[clap(short, long)]
+    inodes: bool,
+
+    /// Any path on filesystem to dump
+    pathname: String,
+  }
+
+  match BinArgs::try_parse_from(args.iter()) {
+    Err(message) => {
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
+      1
+    }
+    Ok(BinArgs { inodes, pathname }) => {
+      let result = kernel.vfs().match_mount_point(&pathname);
+      let mount_point = match result {
+        Ok((mount_point, _)) => mount_point,
+        Err(errno) => {
+          kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+          return EXIT_FAILURE;
+        },
+      };
+      let Some(result) = with_e5fs(kernel, &mount_point, |e5fs| dump::dump(e5fs, inodes)) else {
+        kprintln!(kernel, "{arg0}: {pathname}: not on e5fs");
+        return EXIT_FAILURE;
+      };
+
+      let dump = match result {
+        Ok(dump) => dump,
+        Err(errno) => {
+          kprintln!(kernel, "{arg0}: {pathname}: {errno}");
+          return EXIT_FAILURE;
+        },
+      };
+      let superblock = &dump.superblock;
+      let label = match superblock.label() {
+        label if label.is_empty() => String::from("<none>"),
+        label => label,
+      };
+      let state = match superblock.state {
+        STATE_CLEAN => "clean",
+        _ => "not clean",
+      };
+      let superblock_backups = match dump.superblock_backups.is_empty() {
+        true => String::from("<none>"),
+        false => dump.superblock_backups.iter().map(|address| address.to_string()).join(", "),
+      };
+
+      kprintln!(kernel, "Filesystem volume name:   {label}");
+      kprintln!(kernel, "Filesystem UUID:          {}", uuid::Uuid::from_bytes(superblock.uuid));
+      kprintln!(kernel, "Filesystem state:         {state}");
+      kprintln!(kernel, "Format version:           {}", superblock.format_version);
+      kprintln!(kernel, "Filesystem size:          {}", superblock.filesystem_size);
+      kprintln!(kernel, "Encrypted:                {}", if dump.encrypted { "yes" } else { "no" });
+      kprintln!(kernel, "Journal size:             {}", dump.journal_size);
+      kprintln!(kernel, "Superblock backups:       {superblock_backups}");
+      kprintln!(kernel, "Inode count:              {}", superblock.inodes_count);
+      kprintln!(kernel, "Used inodes:              {}", dump.used_inodes_count);
+      kprintln!(kernel, "Inode table size:         {}", superblock.inode_table_size);
+      kprintln!(kernel, "Inode table percentage:   {}%", superblock.inode_table_percentage * 100.0);
+      kprintln!(kernel, "Block count:              {}", superblock.blocks_count);
+      kprintln!(kernel, "Block size:               {}", superblock.block_size);
+      kprintln!(kernel, "Free blocks:              {}", dump.free_blocks_count);
+      kprintln!(kernel, "Reserved blocks:          {}", superblock.reserved_blocks_count);
+      kprintln!(kernel, "Shared blocks:            {}", dump.shared_blocks_count);
+      kprintln!(kernel, "First fbl block:          {}", superblock.first_fbl_block_number);
+      kprintln!(kernel, "Fbl blocks:               {}", dump.fbl_blocks_count);
+      if dump.bad_blocks_count > 0 {
+        kprintln!(kernel, "Bad fbl entries:          {} (run fsck.e5fs)", dump.bad_blocks_count);
+      }
+
+      for inode in &dump.inodes {
+        let file_type = FileModeType::try_from(inode.mode.file_type())
+          .map_or(String::from("unknown"), |file_type| file_type.to_string());
+        let permissions = inode.mode.get_raw() & 0o777;
+        let block_numbers = inode.block_numbers
+          .iter()
+          .map(|&block_number| match block_number {
+            NO_ADDRESS => String::from("-"),
+            block_number => block_number.to_string(),
+          })
+          .join(", ");
+
+        kprintln!(kernel);
+        kprintln!(kernel, "Inode {}: {file_type} {permissions:03o}, {} links, {} bytes", inode.number, inode.links_count, inode.file_size);
+        if !block_numbers.is_empty() {
+          kprintln!(kernel, "  Blocks: {block_numbers}");
+        }
+        if let Some(block_number) = inode.indirect_block_number {
+          kprintln!(kernel, "  Indirect block: {block_number}");
+        }
+        if let Some(block_number) = inode.xattr_block_number {
+          kprintln!(kernel, "  Attributes block: {block_number}");
+        }
+      }
+
+      EXIT_SUCCESS
+    }
+  }
+}
+
+/// Put removed file back from trash of e5fs: `undelete <name>
+/// [pathname]`, where name is one `--list` prints. Trash is there
+/// after `--enable`, files in it older than `--purge` seconds are
+/// released for good
+pub fn undelete(args: Args, kernel: &mut Kernel) -> AddressSize {
+  let arg0 = args.get(0).unwrap().clone();
+  #[derive(Debug, Parser)]
+  struct BinArgs {
+    /// Any path on filesystem whose trash to use
+    #[clap(short, long, default_value = "/")]
+    filesystem: String,
+
+    /// List files in trash
+    #[clap(short, long)]
+    list: bool,
+
+    /// Release files that have been in trash for this many seconds,
+    /// 0 to empty it
+    #[clap(short, long)]
+    purge: Option<UnixtimeSize>,
+
+    /// Make trash, so removed files go there
+    #[clap(long)]
+    enable: bool,
+
+    /// Empty trash and remove it, so removed files are released
+    #[clap(long)]
+    disable: bool,
+
+    /// Name of file in trash
+    name: Option<String>,
+
+    /// Where to put it instead of where it was removed from
+    pathname: Option<String>,
+  }
+
+  let BinArgs { filesystem, list, purge, enable, disable, name, pathname } = match BinArgs::try_parse_from(args.iter()) {
+    Ok(args) => args,
+    Err(message) => {
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
+      return EXIT_FAILURE;
+    },
+  };
+  // Trash has files of every user
+  if kernel.current_uid != ROOT_UID {
+    kprintln!(kernel, "{arg0}: Permission denied");
+    return EXIT_FAILURE;
+  }
+
+  let result = kernel.vfs().match_mount_point(&filesystem);
+  let mount_point = match result {
+    Ok((mount_point, _)) => mount_point,
+    Err(errno) => {
+      kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+      return EXIT_FAILURE;
+    },
+  };
+  let now = kernel.clock.now();
+  match (list, purge, enable, disable, &name) {
+    (true, None, false, false, None)
+      | (false, Some(_), false, false, None)
+      | (false, None, true, false, None)
+      | (false, None, false, true, None)
+      | (false, None, false, false, Some(_)) => (),
+    (false, None, false, false, None) => {
+      kprintln!(kernel, "{arg0}: name of file in /{} is needed, see --list", trash::TRASH_DIR_NAME);
+      return EXIT_FAILURE;
+    },
+    _ => {
+      kprintln!(kernel, "{arg0}: --list, --purge, --enable, --disable and name don't go together");
+      return EXIT_FAILURE;
+    },
+  }
+
+  // What is printed is printed once filesystem is let go
+  let result = with_e5fs(kernel, &mount_point, |e5fs| match (purge, enable, disable, name) {
+    (Some(age), _, _, _) => e5fs.purge_trash(now.saturating_sub(age).saturating_add(1))
+      .map(|count| format!("{arg0}: purged {count} files\n")),
+    (_, true, _, _) => e5fs.enable_trash().map(|()| String::new()),
+    (_, _, true, _) => e5fs.disable_trash().map(|()| String::new()),
+    (_, _, _, Some(name)) => e5fs.undelete(&name, pathname.as_deref())
+      .map(|pathname| format!("{arg0}: {name} -> {}/{}\n", mount_point.trim_end_matches('/'), pathname.trim_start_matches('/'))),
+    _ => e5fs.list_trash().map(|entries| entries
+      .into_iter()
+      .map(|entry| {
+        let deleted = Utc.timestamp_opt(entry.deleted as i64, 0)
+          .unwrap()
+          .format("%Y-%m-%d %H:%M:%S");
+        format!("{}\t{deleted}\t{}\t{}\n", entry.name, entry.size, entry.pathname)
+      })
+      .collect::<String>()
+    ),
+  });
+  let Some(result) = result else {
+    kprintln!(kernel, "{arg0}: {filesystem}: not on e5fs");
+    return EXIT_FAILURE;
+  };
+
+  match result {
+    Ok(output) => {
+      kprint!(kernel, "{output}");
+      EXIT_SUCCESS
+    },
+    Err(Errno::EEXIST(message) | Errno::ENOENT(message) | Errno::EROFS(message)) => {
+      kprintln!(kernel, "{arg0}: {message}");
+      EXIT_FAILURE
+    },
+    Err(errno) => {
+      kprintln!(kernel, "{arg0}: unexpected error: {errno}");
+      EXIT_FAILURE
+    },
+  }
+}
+
+/// Exit code of `inotifywait` when nothing watched happened
+pub const EXIT_NO_EVENTS: AddressSize = 2;
+
+/// Watch files while command runs and print what happened to them:
+/// `inotifywait [-e event]... <pathname>... -- <command> [arg]...`.
+/// Commands run one after another, so waiting is for the command,
+/// not for some other process. Events are printed like inotify tools
+/// do: watched pathname, event and name of file in it, if any
+pub fn inotifywait(args: Args, kernel: &mut Kernel) -> AddressSize {
+  let arg0 = args.get(0).unwrap().clone();
+  #[derive(Debug, Parser)]
+  struct BinArgs {
+    /// Event to watch for: create, modify or delete. All by default
+    #[clap(short, long = "event")]
+    events: Vec<String>,
+
+    #[clap(required = true)]
+    pathnames: Vec<String>,
+
+    #[clap(last = true, required = true)]
+    command: Vec<String>,
+  }
+
+  match BinArgs::try_parse_from(args.iter()) {
+    Err(message) => {
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
+      EXIT_FAILURE
+    }
+    Ok(BinArgs { events, pathnames, command }) => {
+      let mask = match events
         .iter()
-        .find(|(_, name)| group_name == *name)
-        .map(|(id, _)| *id)
+        .map(|event| watch::parse_event(event))
+        .fold_ok(0, |mask, event| mask | event)
       {
-        gid
-      } else {
-        println!("{arg0}: invalid group: '{new_owners_string}'");
+        Ok(0) => watch::IN_ALL_EVENTS,
+        Ok(mask) => mask,
+        Err(_) => {
+          kprintln!(kernel, "{arg0}: unknown event, expected create, modify or delete");
+          return EXIT_FAILURE;
+        },
+      };
+
+      let mut watches = BTreeMap::new();
+      for pathname in pathnames {
+        match kernel.add_watch(&pathname, mask) {
+          Ok(watch_descriptor) => {
+            watches.insert(watch_descriptor, pathname);
+          },
+          Err(errno) => {
+            match errno {
+              Errno::ENOENT(_) => kprintln!(kernel, "{arg0}: cannot watch '{pathname}': No such file or directory"),
+              Errno::EACCES(_) => kprintln!(kernel, "{arg0}: cannot watch '{pathname}': Permission denied"),
+              errno => kprintln!(kernel, "{arg0}: unexpected error: {errno}"),
+            }
+            for watch_descriptor in watches.keys() {
+              let _ = kernel.remove_watch(*watch_descriptor);
+            }
+            return EXIT_FAILURE;
+          },
+        }
+      }
+
+      // Look command up like the shell does
+      let pathname = match command[0].contains('/') {
+        true => command[0].clone(),
+        false => ["/usr/bin", "/bin"]
+          .iter()
+          .map(|directory| format!("{directory}/{}", command[0]))
+          .find(|pathname| kernel.access(pathname, PERM_X).is_ok())
+          .unwrap_or_else(|| command[0].clone()),
+      };
+      let argv = command.iter().map(String::as_str).collect::<Vec<_>>();
+      if let Err(errno) = kernel.run(&pathname, &argv) {
+        kprintln!(kernel, "{arg0}: cannot run '{pathname}': {errno}");
+      }
+
+      let events = kernel.read_watch_events();
+      for watch_descriptor in watches.keys() {
+        let _ = kernel.remove_watch(*watch_descriptor);
+      }
+      for event in &events {
+        kprintln!(kernel, "{} {} {}",
+          watches[&event.watch_descriptor],
+          watch::event_name(event.mask),
+          event.name.as_deref().unwrap_or(""),
+        );
+      }
+
+      match events.is_empty() {
+        true => EXIT_NO_EVENTS,
+        false => EXIT_SUCCESS,
+      }
+    },
+  }
+}
+
+pub fn mount(args: Args, kernel: &mut Kernel) -> AddressSize {
+  let arg0 = args.get(0).unwrap().clone();
+  #[derive(Debug, Parser)]
+  struct BinArgs {
+    #[clap(short = 't', long, default_value_t = FilesystemType::e5fs)]
+    filesystem_type: FilesystemType,
+
+    /// Comma-separated: `ro`, `noexec`, `nosuid`, `noatime`,
+    /// `snapshot=<name>`, `lowerdir=<dir>` and `upperdir=<dir>`
+    /// of overlay
+    #[clap(short = 'o', long)]
+    options: Option<String>,
+
+    /// Show directory `source` at `target` too
+    #[clap(long)]
+    bind: bool,
+
+    /// Without source and target, mount table is printed
+    #[clap(requires = "target")]
+    source: Option<String>,
+    target: Option<String>,
+  }
+
+  match BinArgs::try_parse_from(args.iter()) {
+    Err(message) => {
+      kprintln!(kernel, "{arg0}: error: {message}");
+      1
+    }
+    Ok(BinArgs { source: None, .. }) => {
+      for entry in kernel.mount_table() {
+        kprintln!(kernel, "{} on {} type {} ({})", entry.source, entry.target, entry.r#type, entry.flags);
+      }
+      EXIT_SUCCESS
+    },
+    Ok(BinArgs { source: Some(source), target: None, .. }) => {
+      kprintln!(kernel, "{arg0}: error: no target for {source}");
+      EXIT_FAILURE
+    },
+    Ok(BinArgs { bind: true, source: Some(source), target: Some(target), .. }) => match kernel.bind_mount(&source, &target) {
+      Ok(_) => EXIT_SUCCESS,
+      Err(Errno::ENOENT(_)) => {
+        kprintln!(kernel, "{arg0}: can't find {source}");
+        EXIT_FAILURE
+      },
+      Err(errno) => {
+        kprintln!(kernel, "{arg0}: {source}: {errno}");
+        EXIT_FAILURE
+      },
+    },
+    Ok(BinArgs {
+      filesystem_type,
+      options,
+      source: Some(source),
+      target: Some(target),
+      ..
+    }) => {
+      let mut mount_options = MountOptions::default();
+      for option in options.iter().flat_map(|options| options.split(',')) {
+        match option.split_once('=') {
+          None if option == "ro" => mount_options.flags.read_only = true,
+          None if option == "rw" => mount_options.flags.read_only = false,
+          None if option == "noexec" => mount_options.flags.noexec = true,
+          None if option == "exec" => mount_options.flags.noexec = false,
+          None if option == "nosuid" => mount_options.flags.nosuid = true,
+          None if option == "suid" => mount_options.flags.nosuid = false,
+          None if option == "noatime" => mount_options.flags.noatime = true,
+          None if option == "atime" => mount_options.flags.noatime = false,
+          Some(("snapshot", snapshot)) => mount_options.snapshot = Some(snapshot.to_owned()),
+          Some(("lowerdir", lowerdir)) => mount_options.lowerdir = Some(lowerdir.to_owned()),
+          Some(("upperdir", upperdir)) => mount_options.upperdir = Some(upperdir.to_owned()),
+          _ => {
+            kprintln!(kernel, "{arg0}: unknown option '{option}'");
+            return EXIT_FAILURE;
+          },
+        }
+      }
+
+      // Encrypted e5fs can't be mounted without its passphrase
+      let encrypted = filesystem_type == FilesystemType::e5fs
+        && kernel.open_mount_source(&source).map_or(false, |mut storage| E5FSFilesystem::is_encrypted(storage.as_mut()));
+      if encrypted {
+        match prompt_line(kernel, &format!("Passphrase for {source}: ")) {
+          Ok(passphrase) => mount_options.passphrase = Some(passphrase.trim_end_matches('\n').to_owned()),
+          Err(errno) => {
+            kprintln!(kernel, "{arg0}: cannot read passphrase: {errno}");
+            return EXIT_FAILURE;
+          },
+        }
+      }
+
+      match kernel.mount_with_options(&source, &target, filesystem_type, &mount_options) {
+        Ok(_) => 0,
+        Err(Errno::ENOENT(message)) if mount_options.snapshot.is_some() => {
+          kprintln!(kernel, "{arg0}: {source}: {message}");
+          EXIT_FAILURE
+        },
+        Err(Errno::ENOENT(message)) if mount_options.lowerdir.is_some() => {
+          kprintln!(kernel, "{arg0}: {message}");
+          EXIT_FAILURE
+        },
+        Err(Errno::ENOENT(_)) => {
+          kprintln!(kernel, "{arg0}: can't find {source}");
+          EXIT_FAILURE
+        },
+        Err(Errno::EPERM(_)) => {
+          kprintln!(kernel, "{arg0}: unable to mount: Operation not permitted");
+          return EXIT_FAILURE
+        },
+        Err(Errno::EACCES(message)) => {
+          kprintln!(kernel, "{arg0}: {source}: {message}");
+          EXIT_FAILURE
+        },
+        Err(Errno::EINVAL(message)) => {
+          kprintln!(kernel, "{arg0}: error: {message}");
+          1
+        }
+        Err(errno) => {
+          kprintln!(kernel, "{arg0}: {target}: {errno}");
+          EXIT_FAILURE
+        },
+      }
+    },
+  }
+}
+
+/// Export `directory` to netfs clients on the other end of network
+/// interface `device`, serving each request with permissions of
+/// the user of client that sent it. Only root can take them on
+pub fn netfsd(args: Args, kernel: &mut Kernel) -> AddressSize {
+  let arg0 = args.get(0).unwrap().clone();
+  #[derive(Debug, Parser)]
+  struct BinArgs {
+    /// Exit after serving this many requests, serve forever if 0
+    #[clap(short, long, default_value_t = 0)]
+    count: usize,
+
+    device: String,
+    directory: String,
+  }
+
+  let BinArgs { count, device, directory } = match BinArgs::try_parse_from(args.iter()) {
+    Ok(args) => args,
+    Err(message) => {
+      kprintln!(kernel, "{arg0}: error: {message}");
+      return EXIT_FAILURE;
+    },
+  };
+
+  if kernel.current_uid != ROOT_UID {
+    kprintln!(kernel, "{arg0}: {device}: Permission denied");
+    return EXIT_FAILURE;
+  }
+
+  let result = kernel.vfs().stat(&device);
+  match result {
+    Ok(stat) if stat.rdev.major == NET_MAJOR && stat.mode.file_type() == FileModeType::Char as u8 => (),
+    Ok(_) => {
+      kprintln!(kernel, "{arg0}: {device}: not a network interface");
+      return EXIT_FAILURE;
+    },
+    Err(errno) => {
+      kprintln!(kernel, "{arg0}: cannot stat '{device}': {errno}");
+      return EXIT_FAILURE;
+    },
+  }
+
+  let mut served = 0;
+  while (count == 0 || served < count) && !kernel.termination_pending() {
+    let frame = match kernel.read_file(&device, MAX_FRAME_SIZE as AddressSize) {
+      Ok(frame) if frame.is_empty() => {
+        std::thread::sleep(std::time::Duration::from_millis(1));
+        continue;
+      },
+      Ok(frame) => frame,
+      Err(errno) => {
+        kprintln!(kernel, "{arg0}: cannot read '{device}': {errno}");
         return EXIT_FAILURE;
-      };
+      },
+    };
 
-      match kernel.vfs.change_owners(&pathname, uid, gid) {
-        Ok(_) => EXIT_SUCCESS,
-        Err(Errno::EPERM(_)) => {
-          println!("{arg0}: changing owner of '{pathname}': Operation not permitted");
-          return EXIT_FAILURE
-        },
-        Err(Errno::EACCES(_)) => {
-          println!("{arg0}: '{pathname}': Permission denied");
-          return EXIT_FAILURE
-        },
-        Err(errno) => {
-          println!("{arg0}: unexpected error: {errno:?}");
-          EXIT_FAILURE
-        },
+    // Frames that are not requests are not ours
+    let Some(call) = netfs::receive(&frame) else {
+      continue;
+    };
+    // Supplementary groups of client are not known here
+    kernel.set_vfs_ids(call.body.uid, call.body.gid);
+    kernel.vfs_context.sgids = Vec::new();
+    let response = netfs::respond(&mut *kernel.vfs(), &directory, call);
+    kernel.update_vfs_current_uid_gid();
+
+    if let Some(response) = response {
+      if let Err(errno) = kernel.write_file(&device, &response) {
+        kprintln!(kernel, "{arg0}: cannot write '{device}': {errno}");
+        return EXIT_FAILURE;
       }
-    },
+      served += 1;
+    }
   }
+
+  EXIT_SUCCESS
 }
 
-// System related stuff
-pub fn uname(args: Args, kernel: &mut Kernel) -> AddressSize {
+/// Print everything that came to socket, until end of stream or
+/// nothing comes for `wait` seconds (0 waits forever).
+/// Returns: whether stream has ended
+fn print_received(kernel: &mut Kernel, socket: SocketDescriptor, wait: u64) -> Result<bool, Errno> {
+  let mut idle_since = std::time::Instant::now();
+  while (wait == 0 || idle_since.elapsed().as_secs() < wait) && !kernel.termination_pending() {
+    match kernel.recv(socket, EVERYTHING)? {
+      Some((_, data)) if data.is_empty() => return Ok(true),
+      Some((_, data)) => {
+        kprint!(kernel, "{}", String::from_utf8_lossy(&data));
+        idle_since = std::time::Instant::now();
+      },
+      None => std::thread::sleep(std::time::Duration::from_millis(1)),
+    }
+  }
+
+  Ok(false)
+}
+
+/// Connect to port on network interface and send lines from terminal there,
+/// or listen on it, printing what comes
+pub fn nc(args: Args, kernel: &mut Kernel) -> AddressSize {
   let arg0 = args.get(0).unwrap().clone();
   #[derive(Debug, Parser)]
   struct BinArgs {
+    /// Use datagrams instead of stream
+    #[clap(short, long)]
+    udp: bool,
+    /// Listen for connection instead of making one
+    #[clap(short, long)]
+    listen: bool,
+    /// Give up after this many seconds of silence, 0 waits forever
+    #[clap(short, long, default_value_t = 1)]
+    wait: u64,
+
+    interface: String,
+    port: Port,
   }
 
-  match BinArgs::try_parse_from(args.iter()) {
+  let BinArgs { udp, listen, wait, interface, port } = match BinArgs::try_parse_from(args.iter()) {
+    Ok(args) => args,
     Err(message) => {
-      println!("{arg0}: invalid arguments: {message}");
-      1
-    }
-    Ok(BinArgs { }) => {
-      println!("Eunix");
-      EXIT_SUCCESS
+      kprintln!(kernel, "{arg0}: error: {message}");
+      return EXIT_FAILURE;
+    },
+  };
+  let address = SocketAddress::new(&interface, port);
+  let r#type = if udp { SocketType::Datagram } else { SocketType::Stream };
+
+  let result = kernel.socket(r#type).and_then(|socket| {
+    let result = match listen {
+      true => nc_listen(kernel, socket, address, wait),
+      false => nc_connect(kernel, socket, address, wait),
+    };
+    kernel.close_socket(socket).and(result)
+  });
+
+  match result {
+    Ok(()) => EXIT_SUCCESS,
+    Err(Errno::ECONNREFUSED(_)) => {
+      kprintln!(kernel, "{arg0}: {interface}:{port}: Connection refused");
+      EXIT_FAILURE
+    },
+    Err(Errno::ETIMEDOUT(_)) => {
+      kprintln!(kernel, "{arg0}: {interface}:{port}: Connection timed out");
+      EXIT_FAILURE
+    },
+    Err(errno) => {
+      kprintln!(kernel, "{arg0}: {errno}");
+      EXIT_FAILURE
     },
   }
 }
 
-pub fn lsblk(args: Args, kernel: &mut Kernel) -> AddressSize {
+fn nc_listen(kernel: &mut Kernel, socket: SocketDescriptor, address: SocketAddress, wait: u64) -> Result<(), Errno> {
+  kernel.bind(socket, address)?;
+  if kernel.socket_type(socket)? == SocketType::Datagram {
+    return print_received(kernel, socket, wait).map(|_| ());
+  }
+
+  kernel.listen(socket)?;
+  let started = std::time::Instant::now();
+  let connection = loop {
+    if let Some((connection, _)) = kernel.accept(socket)? {
+      break connection;
+    }
+    if wait != 0 && started.elapsed().as_secs() >= wait {
+      return Err(Errno::ETIMEDOUT(String::from("nc: nobody connected").into()));
+    }
+    std::thread::sleep(std::time::Duration::from_millis(1));
+  };
+
+  let result = print_received(kernel, connection, wait);
+  kernel.close_socket(connection).and(result.map(|_| ()))
+}
+
+fn nc_connect(kernel: &mut Kernel, socket: SocketDescriptor, address: SocketAddress, wait: u64) -> Result<(), Errno> {
+  kernel.connect(socket, address)?;
+
+  // Lines until end of input
+  loop {
+    let line = kernel.read_file(TTY_PATH, EVERYTHING)?;
+    if line.is_empty() {
+      break;
+    }
+    kernel.send(socket, &line)?;
+    if let Some((_, data)) = kernel.recv(socket, EVERYTHING)? {
+      kprint!(kernel, "{}", String::from_utf8_lossy(&data));
+    }
+  }
+
+  print_received(kernel, socket, wait).map(|_| ())
+}
+
+/// Send echo requests to the other end of network interface
+/// and wait for replies from its kernel
+pub fn ping(args: Args, kernel: &mut Kernel) -> AddressSize {
   let arg0 = args.get(0).unwrap().clone();
-  let device_table = kernel.devices();
-  let mount_points = &kernel.vfs.mount_points;
-  println!("{device_table:#?}");
-  println!("mount_points: {mount_points:#?}");
-  EXIT_SUCCESS
+  #[derive(Debug, Parser)]
+  struct BinArgs {
+    /// Stop after sending this many requests
+    #[clap(short, long, default_value_t = 4)]
+    count: u16,
+
+    interface: String,
+  }
+
+  let BinArgs { count, interface } = match BinArgs::try_parse_from(args.iter()) {
+    Ok(args) => args,
+    Err(message) => {
+      kprintln!(kernel, "{arg0}: error: {message}");
+      return EXIT_FAILURE;
+    },
+  };
+
+  let socket = match kernel
+    .socket(SocketType::Echo)
+    .and_then(|socket| kernel.connect(socket, SocketAddress::new(&interface, 0)).map(|_| socket)) {
+    Ok(socket) => socket,
+    Err(errno) => {
+      kprintln!(kernel, "{arg0}: {interface}: {errno}");
+      return EXIT_FAILURE;
+    },
+  };
+
+  kprintln!(kernel, "PING {interface}");
+  let mut received = 0;
+  for sequence in 1..=count {
+    if kernel.termination_pending() {
+      break;
+    }
+    let sent = std::time::Instant::now();
+    if let Err(errno) = kernel.send(socket, &sequence.to_be_bytes()) {
+      kprintln!(kernel, "{arg0}: {interface}: {errno}");
+      break;
+    }
+
+    // Replies to earlier requests came late, they are not counted
+    while sent.elapsed() < std::time::Duration::from_secs(1) {
+      match kernel.recv(socket, EVERYTHING) {
+        Ok(Some((_, reply))) if reply == sequence.to_be_bytes() => {
+          kprintln!(kernel, "{} bytes from {interface}: seq={sequence} time={:.3} ms", reply.len(), sent.elapsed().as_secs_f64() * 1000.0);
+          received += 1;
+          break;
+        },
+        Ok(_) => std::thread::sleep(std::time::Duration::from_millis(1)),
+        Err(errno) => {
+          kprintln!(kernel, "{arg0}: {interface}: {errno}");
+          break;
+        },
+      }
+    }
+  }
+  let _ = kernel.close_socket(socket);
+
+  kprintln!(kernel, "--- {interface} ping statistics ---");
+  kprintln!(kernel, "{count} packets transmitted, {received} received, {}% packet loss", (count - received) * 100 / count.max(1));
+
+  match received {
+    0 => EXIT_FAILURE,
+    _ => EXIT_SUCCESS,
+  }
 }
 
-pub fn passwd(args: Args, kernel: &mut Kernel) -> AddressSize {
+// User related stuff
+
+pub fn id(args: Args, kernel: &mut Kernel) -> AddressSize {
   let arg0 = args.get(0).unwrap().clone();
   #[derive(Debug, Parser)]
   struct BinArgs {
-    #[clap(short, long, takes_value = false)]
-    update: bool,
   }
 
   match BinArgs::try_parse_from(args.iter()) {
     Err(message) => {
-      println!("{arg0}: invalid arguments: {message}");
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
       1
     }
-    Ok(BinArgs { update }) => {
-      if update && let Err(errno) = kernel.update_uid_gid_maps() {
-        println!("{arg0}: cannot update '{PASSWD_PATH}': {errno:?}");
-        return EXIT_FAILURE;
-      }
+    Ok(BinArgs { }) => {
+      let (current_uid, current_gid) = (kernel.current_uid, kernel.current_gid);
+      let current_username = kernel
+        .user_name(current_uid)
+        .unwrap_or(String::from("<no name>"));
+      let current_groupname = kernel
+        .group_name(current_gid)
+        .unwrap_or(String::from("<no name>"));
+      let current_sgids_string = kernel
+        .current_sgids
+        .clone()
+        .into_iter()
+        .map(|sgid| {
+          let groupname = kernel
+            .group_name(sgid)
+            .unwrap_or(String::from("<no name>"));
+          format!("{sgid}({groupname})")
+        })
+        .join(",");
 
+      kprintln!(kernel, "uid={current_uid}({current_username}) gid={current_gid}({current_groupname}) groups={current_sgids_string}");
       EXIT_SUCCESS
-    }
+    },
   }
 }
 
-pub fn dumpe5fs(args: Args, kernel: &mut Kernel) -> AddressSize {
+pub fn whoami(args: Args, kernel: &mut Kernel) -> AddressSize {
   let arg0 = args.get(0).unwrap().clone();
   #[derive(Debug, Parser)]
   struct BinArgs {
-    pathname: String,
   }
 
   match BinArgs::try_parse_from(args.iter()) {
     Err(message) => {
-      println!("{arg0}: invalid arguments: {message}");
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
       1
     }
-    Ok(BinArgs { pathname }) => {
-      // let (mount_point, internal_path) = kernel.vfs.match_mount_point(&pathname).unwrap();
-      // let mounted_fs = kernel.vfs.mount_points.get_mut(&mount_point).expect("{arg0}::lookup_path: we know that mount_point exist");  
-      //
-      // mounted_fs.driver.as_any().downcast_mut()
-      //
-      // println!("{device_table:#?}");
-      // println!("mount_points: {mount_points:#?}");
+    Ok(BinArgs {}) => {
+      let current_uid = kernel.current_uid;
+      let user_name = kernel
+        .user_name(current_uid)
+        .unwrap_or(format!("<no name>({current_uid})"));
+
+      kprintln!(kernel, "{user_name}");
+
       EXIT_SUCCESS
-    }
+    },
   }
 }
 
-pub fn mount(args: Args, kernel: &mut Kernel) -> AddressSize {
+/// Send signal to processes, TERM by default
+pub fn kill(args: Args, kernel: &mut Kernel) -> AddressSize {
   let arg0 = args.get(0).unwrap().clone();
   #[derive(Debug, Parser)]
   struct BinArgs {
-    #[clap(short = 't', long, default_value_t = FilesystemType::e5fs)]
-    filesystem_type: FilesystemType,
+    /// Signal to send, by name or number. 0 only checks
+    /// whether it could be sent
+    #[clap(short, long, default_value = "TERM")]
+    signal: String,
 
-    source: String,
-    target: String,
+    /// List names of signals
+    #[clap(short, long)]
+    list: bool,
+
+    pids: Vec<AddressSize>,
   }
 
-  match BinArgs::try_parse_from(args.iter()) {
-    Err(message) => {
-      println!("{arg0}: error: {message}");
-      1
+  // `-9` and `-KILL` are `-s 9` and `-s KILL`
+  let mut args = args;
+  if let Some(name) = args.get(1).and_then(|arg| arg.strip_prefix('-')) {
+    if name == "0" || signal::parse_signal(name).is_ok() {
+      let name = name.to_owned();
+      args.splice(1..2, [String::from("-s"), name]);
     }
-    Ok(BinArgs {
-      filesystem_type,
-      source,
-      target,
-    }) => match kernel.mount(&source, &target, filesystem_type) {
-      Ok(_) => 0,
-      Err(Errno::EPERM(_)) => {
-        println!("{arg0}: unable to mount: Operation not permitted");
-        return EXIT_FAILURE
+  }
+
+  let BinArgs { signal, list, pids } = match BinArgs::try_parse_from(args.iter()) {
+    Ok(args) => args,
+    Err(message) => {
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
+      return EXIT_FAILURE;
+    },
+  };
+
+  if list {
+    kprintln!(kernel, "{}", signal::SIGNALS.map(signal::signal_name).join(" "));
+    return EXIT_SUCCESS;
+  }
+  let signal: Signal = match signal.as_str() {
+    "0" => 0,
+    name => match signal::parse_signal(name) {
+      Ok(signal) => signal,
+      Err(errno) => {
+        kprintln!(kernel, "{arg0}: {errno}");
+        return EXIT_FAILURE;
       },
-      Err(Errno::EINVAL(message)) => {
-        println!("{arg0}: error: {message}");
-        1
-      }
-      Err(_) => unreachable!(),
     },
+  };
+  if pids.is_empty() {
+    kprintln!(kernel, "{arg0}: usage: {arg0} [-s signal | -signal] pid...");
+    return EXIT_FAILURE;
+  }
+
+  let mut exit_code = EXIT_SUCCESS;
+  for pid in pids {
+    if let Err(errno) = kernel.kill(pid, signal) {
+      kprintln!(kernel, "{arg0}: {errno}");
+      exit_code = EXIT_FAILURE;
+    }
   }
+
+  exit_code
 }
 
-// User related stuff
+/// Format unix `time` for `who` and `last`
+fn format_record_time(time: u64) -> String {
+  Utc.timestamp_opt(time as i64, 0)
+    .unwrap()
+    .format("%Y-%m-%d %H:%M")
+    .to_string()
+}
 
-pub fn id(args: Args, kernel: &mut Kernel) -> AddressSize {
+pub fn who(args: Args, kernel: &mut Kernel) -> AddressSize {
   let arg0 = args.get(0).unwrap().clone();
   #[derive(Debug, Parser)]
   struct BinArgs {
@@ -1126,59 +4060,76 @@ pub fn id(args: Args, kernel: &mut Kernel) -> AddressSize {
 
   match BinArgs::try_parse_from(args.iter()) {
     Err(message) => {
-      println!("{arg0}: invalid arguments: {message}");
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
       1
     }
-    Ok(BinArgs { }) => {
-      let Kernel { current_uid, current_gid, .. } = kernel;
-      let current_username = kernel
-        .uid_map
-        .get(current_uid)
-        .unwrap_or(&String::from("<no name>"))
-        .clone();
-      let current_groupname = kernel
-        .gid_map
-        .get(current_gid)
-        .unwrap_or(&String::from("<no name>"))
-        .clone();
-      let current_sgids_string = kernel
-        .current_sgids
-        .iter()
-        .map(|sgid| {
-          let groupname = kernel
-            .gid_map
-            .get(sgid)
-            .unwrap_or(&String::from("<no name>"))
-            .clone();
-          format!("{sgid}({groupname})")
-        })
-        .join(",");
+    Ok(BinArgs {}) => {
+      let records = match records::read_records(kernel, UTMP_PATH) {
+        Ok(records) => records,
+        Err(errno) => {
+          kprintln!(kernel, "{arg0}: cannot read '{UTMP_PATH}': {errno}");
+          return EXIT_FAILURE;
+        },
+      };
+
+      for Record { name, tty, time, .. } in records {
+        kprintln!(kernel, "{name: <8} {tty: <8} {}", format_record_time(time));
+      }
 
-      println!("uid={current_uid}({current_username}) gid={current_gid}({current_groupname}) groups={current_sgids_string}");
       EXIT_SUCCESS
     },
   }
 }
 
-pub fn whoami(args: Args, kernel: &mut Kernel) -> AddressSize {
+pub fn last(args: Args, kernel: &mut Kernel) -> AddressSize {
   let arg0 = args.get(0).unwrap().clone();
   #[derive(Debug, Parser)]
   struct BinArgs {
+    /// Show only logins of this user
+    user: Option<String>,
   }
 
   match BinArgs::try_parse_from(args.iter()) {
     Err(message) => {
-      println!("{arg0}: invalid arguments: {message}");
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
       1
     }
-    Ok(BinArgs {}) => {
-      let user_name = kernel
-        .uid_map
-        .get(&kernel.current_uid)
-        .unwrap_or(&format!("<no name>({})", kernel.current_uid))
-        .clone();
+    Ok(BinArgs { user }) => {
+      let records = match records::read_records(kernel, WTMP_PATH) {
+        Ok(records) => records,
+        Err(errno) => {
+          kprintln!(kernel, "{arg0}: cannot read '{WTMP_PATH}': {errno}");
+          return EXIT_FAILURE;
+        },
+      };
 
-      println!("{user_name}");
+      // Most recent first
+      for (index, record) in records.iter().enumerate().rev() {
+        if user.as_ref().map_or(false, |user| *user != record.name) {
+          continue;
+        }
+
+        let Record { name, tty, time, .. } = record;
+        let start = format_record_time(*time);
+
+        match record.r#type {
+          RecordType::Boot => kprintln!(kernel, "{name: <8} {: <8} {start}", "system boot"),
+          RecordType::Login => {
+            // Session ends with logout from the same tty or with the next boot
+            let end = match records[index + 1..]
+              .iter()
+              .find(|end| end.r#type == RecordType::Boot || (end.r#type == RecordType::Logout && end.tty == *tty))
+            {
+              Some(Record { r#type: RecordType::Boot, .. }) => String::from("- crash"),
+              Some(Record { time: end, .. }) => format!("- {} ({}m)", format_record_time(*end), end.saturating_sub(*time) / 60),
+              None => String::from("  still logged in"),
+            };
+
+            kprintln!(kernel, "{name: <8} {tty: <8} {start} {end}");
+          },
+          RecordType::Logout => (),
+        }
+      }
 
       EXIT_SUCCESS
     },
@@ -1194,7 +4145,7 @@ pub fn su(args: Args, kernel: &mut Kernel) -> AddressSize {
 
   match BinArgs::try_parse_from(args.iter()) {
     Err(message) => {
-      println!("{arg0}: invalid arguments: {message}");
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
       1
     }
     Ok(BinArgs { user }) => {
@@ -1204,48 +4155,83 @@ pub fn su(args: Args, kernel: &mut Kernel) -> AddressSize {
         .find(|(_, name)| user == **name)
         .map(|(id, _)| *id)
       {
-        let bytes = match kernel.vfs.read_file("/etc/passwd", AddressSize::MAX) {
+        let result = kernel.vfs().read_file("/etc/passwd", AddressSize::MAX);
+        let bytes = match result {
           Ok(bytes) => bytes,
           Err(Errno::EACCES(_)) => {
-            println!("{arg0}: Permission denied");
+            kprintln!(kernel, "{arg0}: Permission denied");
             return EXIT_FAILURE
           },
           Err(errno) => {
-            println!("{arg0}: unexpected error: {errno:?}");
+            kprintln!(kernel, "{arg0}: unexpected error: {errno}");
             return EXIT_FAILURE
           },
         };
-        let Passwd { name, password, uid, gid, comment, home, shell } = match Passwd::parse_passwds(&String::from_utf8(bytes).unwrap())
+        let passwd = match Passwd::parse_passwds(&String::from_utf8(bytes).unwrap())
           .into_iter()
           .find(|p| p.name == user)
         {
-          Some(gid) => gid,
+          Some(passwd) => passwd,
           None => {
-            println!("{arg0}: user '{user}' does not exist in /etc/passwd");
+            kprintln!(kernel, "{arg0}: user '{user}' does not exist in /etc/passwd");
             return EXIT_FAILURE;
           },
         };
 
+        // Root doesn't need password, so only expiration stops it
+        match account_status(kernel, &passwd) {
+          Ok(AccountStatus::Active) => (),
+          Ok(AccountStatus::Locked) if kernel.current_uid == ROOT_UID => (),
+          Ok(AccountStatus::Locked) => {
+            kprintln!(kernel, "{arg0}: account '{user}' is locked");
+            return EXIT_FAILURE;
+          },
+          Ok(AccountStatus::Expired) => {
+            kprintln!(kernel, "{arg0}: account '{user}' has expired");
+            return EXIT_FAILURE;
+          },
+          Err(errno) => {
+            kprintln!(kernel, "{arg0}: cannot check account of '{user}': {errno}");
+            return EXIT_FAILURE;
+          },
+        }
+
         // Always switch to user if run as root
         if kernel.current_uid != ROOT_UID {
-          let mut input_password = String::new();
-
           // Read password from user
-          print!("Password: ");
-          stdout().flush().unwrap();
-          stdin().read_line(&mut input_password).unwrap();
+          let input_password = match prompt_line(kernel, "Password: ") {
+            Ok(input_password) => input_password,
+            Err(errno) => {
+              kprintln!(kernel, "{arg0}: cannot read password: {errno}");
+              return EXIT_FAILURE;
+            },
+          };
 
-          if hex::encode(Sha256::digest(input_password)) != password {
-            println!("{arg0}: Authentication failure");
-            return EXIT_FAILURE;
+          match authenticate(kernel, &passwd, &input_password) {
+            Ok(true) => (),
+            Ok(false) => {
+              kprintln!(kernel, "{arg0}: Authentication failure");
+              return EXIT_FAILURE;
+            },
+            Err(Errno::EACCES(_)) => {
+              kprintln!(kernel, "{arg0}: Authentication token expired");
+              return EXIT_FAILURE;
+            },
+            Err(errno) => {
+              kprintln!(kernel, "{arg0}: cannot check password of '{user}': {errno}");
+              return EXIT_FAILURE;
+            },
           }
         }
-        kernel.current_gid = gid;
-        kernel.current_uid = uid;
+        kernel.current_gid = passwd.gid;
+        kernel.current_uid = passwd.uid;
         kernel.update_vfs_current_uid_gid();
+        if let Err(errno) = kernel.update_current_sgids() {
+          kprintln!(kernel, "{arg0}: cannot read '{GROUP_PATH}': {errno}");
+        }
         EXIT_SUCCESS
       } else {
-        println!("{arg0}: user '{user}' does not exist; you might want to reread /etc/passwd by typing 'passwd -u'");
+        kprintln!(kernel, "{arg0}: user '{user}' does not exist; you might want to reread /etc/passwd by typing 'passwd -u'");
         EXIT_FAILURE
       }
     },
@@ -1276,23 +4262,24 @@ pub fn useradd(args: Args, kernel: &mut Kernel) -> AddressSize {
 
   match BinArgs::try_parse_from(args.iter()) {
     Err(message) => {
-      println!("{arg0}: invalid arguments: {message}");
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
       EXIT_FAILURE
     }
     Ok(BinArgs { name, primary_group, comment, home, supplementary_groups, shell }) => {
       if kernel.current_uid != ROOT_UID {
-        println!("{arg0}: creating user: Operation not permitted");
+        kprintln!(kernel, "{arg0}: creating user: Operation not permitted");
         return EXIT_FAILURE;
       }
 
-      let bytes = match kernel.vfs.read_file("/etc/passwd", AddressSize::MAX) {
+      let result = kernel.vfs().read_file("/etc/passwd", AddressSize::MAX);
+      let bytes = match result {
         Ok(bytes) => bytes,
         Err(Errno::EACCES(_)) => {
-          println!("{arg0}: Permission denied");
+          kprintln!(kernel, "{arg0}: Permission denied");
           return EXIT_FAILURE
         },
         Err(errno) => {
-          println!("{arg0}: unexpected error: {errno:?}");
+          kprintln!(kernel, "{arg0}: unexpected error: {errno}");
           return EXIT_FAILURE
         },
       };
@@ -1304,28 +4291,26 @@ pub fn useradd(args: Args, kernel: &mut Kernel) -> AddressSize {
 
       // Guard for user already existing
       if passwds.iter().map(|p| &p.name).contains(&name) {
-        println!("{arg0}: '{name}': User already exists");
+        kprintln!(kernel, "{arg0}: '{name}': User already exists");
         return EXIT_FAILURE;
       }
 
-      let mut password_one = String::new();
-      let mut password_two = String::new();
-
       // Read password from user
-      print!("New password: ");
-      stdout().flush().unwrap();
-      stdin().read_line(&mut password_one).unwrap();
-      print!("Retype password: ");
-      stdout().flush().unwrap();
-      stdin().read_line(&mut password_two).unwrap();
+      let (password_one, password_two) = match prompt_line(kernel, "New password: ")
+        .and_then(|password_one| Ok((password_one, prompt_line(kernel, "Retype password: ")?)))
+      {
+        Ok(passwords) => passwords,
+        Err(errno) => {
+          kprintln!(kernel, "{arg0}: cannot read password: {errno}");
+          return EXIT_FAILURE;
+        },
+      };
 
       if password_one != password_two {
-        println!("{arg0}: Passwords do not match");
+        kprintln!(kernel, "{arg0}: Passwords do not match");
         return EXIT_FAILURE;
       }
 
-      let password_hash = hex::encode(Sha256::digest(&password_one.as_bytes()));
-
       let new_uid = unclaimed_uids.next().unwrap();
       let new_gid = match kernel
         .gid_map
@@ -1335,13 +4320,13 @@ pub fn useradd(args: Args, kernel: &mut Kernel) -> AddressSize {
       {
         Some(gid) => gid,
         None => {
-          println!("{arg0}: group '{primary_group}' does not exist");
+          kprintln!(kernel, "{arg0}: group '{primary_group}' does not exist");
           return EXIT_FAILURE
         },
       };
       passwds.push(Passwd {
         name: name.clone(),
-        password: password_hash,
+        password: String::from(SHADOWED_PASSWORD),
         uid: new_uid,
         gid: new_gid,
         comment,
@@ -1353,39 +4338,57 @@ pub fn useradd(args: Args, kernel: &mut Kernel) -> AddressSize {
 
       // Guard for home dir creation
       if home != "" {
-        match kernel.vfs.create_dir(&home) {
-          Ok(_) => (),
+        // Populate it from /etc/skel, if there is one
+        let mut vfs = kernel.vfs();
+        let result = match vfs.lookup_path(SKEL_PATH) {
+          Ok(_) => vfs
+            .copy_tree(SKEL_PATH, &home, new_uid, new_gid)
+            .and_then(|_| vfs.lookup_path(&home)),
+          Err(_) => vfs.create_dir(&home),
+        };
+        drop(vfs);
+        let vinode = match result {
+          Ok(vinode) => vinode,
           Err(Errno::EEXIST(_)) => {
-            println!("{arg0}: creating home dir '{home}': Already exists");
+            kprintln!(kernel, "{arg0}: creating home dir '{home}': Already exists");
             return EXIT_FAILURE
           },
           Err(Errno::EACCES(_)) => {
-            println!("{arg0}: creating home dir '{home}': Permission denied");
+            kprintln!(kernel, "{arg0}: creating home dir '{home}': Permission denied");
             return EXIT_FAILURE
           },
           Err(errno) => {
-            println!("{arg0}: unexpected error: {errno:?}");
+            kprintln!(kernel, "{arg0}: unexpected error: {errno}");
             return EXIT_FAILURE
           },
         };
-        kernel.vfs.change_owners(&home, new_uid, new_gid).unwrap();
+        kernel.vfs().change_owners(&home, new_uid, new_gid).unwrap();
+        // rwx------
+        kernel.vfs().change_mode(&home, vinode.mode.with_user(0b111).with_group(0).with_others(0)).unwrap();
       }
       
       // Write /etc/passwd
-      match kernel.vfs.write_file(PASSWD_PATH, serialized.as_bytes()) {
+      let result = kernel.vfs().write_file(PASSWD_PATH, serialized.as_bytes());
+      match result {
         Ok(_) => (),
         Err(Errno::EACCES(_)) => {
-          println!("{arg0}: Permission denied");
+          kprintln!(kernel, "{arg0}: Permission denied");
           return EXIT_FAILURE
         },
         Err(errno) => {
-          println!("{arg0}: unexpected error: {errno:?}");
+          kprintln!(kernel, "{arg0}: unexpected error: {errno}");
           return EXIT_FAILURE
         },
       };
 
+      // Write /etc/shadow
+      if let Err(errno) = store_password(kernel, &name, &password_one) {
+        kprintln!(kernel, "{arg0}: cannot update '{SHADOW_PATH}': {errno}");
+        return EXIT_FAILURE
+      }
+
       if let Err(errno) = kernel.update_uid_gid_maps() {
-        println!("{arg0}: cannot update '{PASSWD_PATH}': {errno:?}");
+        kprintln!(kernel, "{arg0}: cannot update '{PASSWD_PATH}': {errno}");
       }
 
       EXIT_SUCCESS
@@ -1397,16 +4400,72 @@ pub fn usermod(args: Args, kernel: &mut Kernel) -> AddressSize {
   let arg0 = args.get(0).unwrap().clone();
   #[derive(Debug, Parser)]
   struct BinArgs {
+    /// Lock password of user
+    #[clap(short = 'L', long, takes_value = false, conflicts_with = "unlock")]
+    lock: bool,
+
+    /// Unlock password of user
+    #[clap(short = 'U', long, takes_value = false)]
+    unlock: bool,
+
+    /// Date when account expires, YYYY-MM-DD, empty to never expire
+    #[clap(short = 'e', long)]
+    expiredate: Option<String>,
+
     name: String,
   }
 
   match BinArgs::try_parse_from(args.iter()) {
     Err(message) => {
-      println!("{arg0}: invalid arguments: {message}");
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
       1
     }
-    Ok(BinArgs { name }) => {
-      EXIT_SUCCESS
+    Ok(BinArgs { lock, unlock, expiredate, name }) => {
+      if kernel.current_uid != ROOT_UID {
+        kprintln!(kernel, "{arg0}: modifying user: Operation not permitted");
+        return EXIT_FAILURE;
+      }
+
+      // Days since unix epoch
+      let expire = match expiredate.as_deref() {
+        None => None,
+        Some("") => Some(None),
+        Some(date) => match chrono::NaiveDate::parse_from_str(date, "%Y-%m-%d") {
+          Ok(date) => Some(Some((date - chrono::NaiveDate::from_ymd_opt(1970, 1, 1).unwrap()).num_days().max(0) as u64)),
+          Err(_) => {
+            kprintln!(kernel, "{arg0}: invalid date '{date}'");
+            return EXIT_FAILURE;
+          },
+        },
+      };
+      if !lock && !unlock && expire.is_none() {
+        kprintln!(kernel, "{arg0}: no changes");
+        return EXIT_SUCCESS;
+      }
+
+      let result = modify_shadow(kernel, &name, |shadow| {
+        if lock {
+          shadow.lock();
+        }
+        if unlock {
+          shadow.unlock();
+        }
+        if let Some(expire) = expire {
+          shadow.expire = expire;
+        }
+      });
+
+      match result {
+        Ok(()) => EXIT_SUCCESS,
+        Err(Errno::ENOENT(_)) => {
+          kprintln!(kernel, "{arg0}: user '{name}' does not exist");
+          EXIT_FAILURE
+        },
+        Err(errno) => {
+          kprintln!(kernel, "{arg0}: cannot update '{SHADOW_PATH}': {errno}");
+          EXIT_FAILURE
+        },
+      }
     },
   }
 }
@@ -1420,22 +4479,23 @@ pub fn userdel(args: Args, kernel: &mut Kernel) -> AddressSize {
 
   match BinArgs::try_parse_from(args.iter()) {
     Err(message) => {
-      println!("{arg0}: invalid arguments: {message}");
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
       1
     }
     Ok(BinArgs { name }) => {
       if kernel.current_uid != ROOT_UID {
-        println!("{arg0}: deleting user: Operation not permitted");
+        kprintln!(kernel, "{arg0}: deleting user: Operation not permitted");
         return EXIT_FAILURE;
       }
-      let bytes = match kernel.vfs.read_file("/etc/passwd", AddressSize::MAX) {
+      let result = kernel.vfs().read_file("/etc/passwd", AddressSize::MAX);
+      let bytes = match result {
         Ok(bytes) => bytes,
         Err(Errno::EACCES(_)) => {
-          println!("{arg0}: Permission denied");
+          kprintln!(kernel, "{arg0}: Permission denied");
           return EXIT_FAILURE
         },
         Err(errno) => {
-          println!("{arg0}: unexpected error: {errno:?}");
+          kprintln!(kernel, "{arg0}: unexpected error: {errno}");
           return EXIT_FAILURE
         },
       };
@@ -1444,7 +4504,7 @@ pub fn userdel(args: Args, kernel: &mut Kernel) -> AddressSize {
 
       // Guard for user not existing
       if !passwds.iter().map(|p| &p.name).contains(&name) {
-        println!("{arg0}: user '{name}' does not exist");
+        kprintln!(kernel, "{arg0}: user '{name}' does not exist");
         return EXIT_FAILURE;
       }
 
@@ -1455,20 +4515,34 @@ pub fn userdel(args: Args, kernel: &mut Kernel) -> AddressSize {
 
       let serialized = Passwd::serialize_passwds(&passwds);
 
-      match kernel.vfs.write_file("/etc/passwd", serialized.as_bytes()) {
+      let result = kernel.vfs().write_file("/etc/passwd", serialized.as_bytes());
+      match result {
         Ok(_) => (),
         Err(Errno::EACCES(_)) => {
-          println!("{arg0}: Permission denied");
+          kprintln!(kernel, "{arg0}: Permission denied");
           return EXIT_FAILURE
         },
         Err(errno) => {
-          println!("{arg0}: unexpected error: {errno:?}");
+          kprintln!(kernel, "{arg0}: unexpected error: {errno}");
           return EXIT_FAILURE
         },
       }
 
+      // Remove from /etc/shadow too
+      let result = read_shadows(kernel).and_then(|shadows| {
+        let shadows: Vec<Shadow> = shadows
+          .into_iter()
+          .filter(|shadow| shadow.name != name)
+          .collect();
+        write_shadows(kernel, &shadows)
+      });
+      if let Err(errno) = result {
+        kprintln!(kernel, "{arg0}: cannot update '{SHADOW_PATH}': {errno}");
+        return EXIT_FAILURE;
+      }
+
       if let Err(errno) = kernel.update_uid_gid_maps() {
-        println!("{arg0}: cannot update '{PASSWD_PATH}': {errno:?}");
+        kprintln!(kernel, "{arg0}: cannot update '{PASSWD_PATH}': {errno}");
       }
 
       EXIT_SUCCESS
@@ -1485,7 +4559,7 @@ pub fn groupmod(args: Args, kernel: &mut Kernel) -> AddressSize {
 
   match BinArgs::try_parse_from(args.iter()) {
     Err(message) => {
-      println!("{arg0}: invalid arguments: {message}");
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
       1
     }
     Ok(BinArgs { pathname }) => {
@@ -1503,7 +4577,7 @@ pub fn groupdel(args: Args, kernel: &mut Kernel) -> AddressSize {
 
   match BinArgs::try_parse_from(args.iter()) {
     Err(message) => {
-      println!("{arg0}: invalid arguments: {message}");
+      kprintln!(kernel, "{arg0}: invalid arguments: {message}");
       1
     },
     Ok(BinArgs { pathname }) => {
@@ -1512,4 +4586,29 @@ pub fn groupdel(args: Args, kernel: &mut Kernel) -> AddressSize {
   }
 }
 
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::kernel;
+
+  fn args(args: &[&str]) -> Args {
+    args.iter().map(|&arg| arg.to_owned()).collect()
+  }
+
+  #[test]
+  fn mount_without_args_prints_mount_table() {
+    let mut kernel = kernel();
+    kernel.mount("", "/", FilesystemType::binfs).unwrap();
+    kernel.vfs().create_dir("/proc").unwrap();
+    let file_descriptor = kernel.open("/out", OpenFlags::new(OpenMode::Write, true, false)).unwrap();
+    kernel.dup2(file_descriptor, STDOUT_FILENO).unwrap();
+
+    assert_eq!(mount(args(&["mount", "-t", "procfs", "-o", "ro,noexec", "tools", "/proc"]), &mut kernel), EXIT_SUCCESS);
+    assert_eq!(mount(args(&["mount"]), &mut kernel), EXIT_SUCCESS);
+
+    let output = String::from_utf8(kernel.vfs().read_file("/out", EVERYTHING).unwrap()).unwrap();
+    assert_eq!(output, "binfs on / type binfs (rw)\ntools on /proc type procfs (ro,noexec)\n");
+  }
+}
+
 // vim:ts=2 sw=2
@@ -0,0 +1,151 @@
+use std::fmt;
+use std::path::{Path, PathBuf};
+use serde::Deserialize;
+
+/// Defaults of `eunix` on host, so that they don't have to be passed
+/// as flags every run. Flags win over the file, the file wins over
+/// built-in defaults. Like:
+/// ```toml
+/// machine = "/home/user/eunix/machine.yaml"
+/// editor = "vim"
+/// color = false
+/// log_level = "info"
+/// snapshot_dir = "/home/user/.local/share/eunix"
+/// ```
+#[derive(Debug, Clone, PartialEq, Deserialize)]
+#[serde(default, deny_unknown_fields)]
+pub struct HostConfig {
+  /// Path to machine.yaml to run when `--machine` is not given
+  pub machine: Option<String>,
+  /// Editor of host that `ed` opens files in when EDITOR is not set
+  pub editor: Option<String>,
+  /// Whether kernel messages on console are colored
+  pub color: bool,
+  /// Level of events printed to stderr when `--log-level` is not given
+  pub log_level: Option<String>,
+  /// Host directory that keeps /bin snapshot of every machine,
+  /// used when `--snapshot` is not given
+  pub snapshot_dir: Option<String>,
+}
+
+impl Default for HostConfig {
+  fn default() -> Self {
+    Self {
+      machine: None,
+      editor: None,
+      color: true,
+      log_level: None,
+      snapshot_dir: None,
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub enum ConfigError {
+  /// Config can't be read from host
+  Read { path: String, message: String },
+  /// Config is not TOML of expected shape
+  Syntax { path: String, message: String },
+}
+
+impl fmt::Display for ConfigError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Read { path, message } => write!(f, "config: cannot read {path}: {message}"),
+      Self::Syntax { path, message } => write!(f, "config: {path}: {message}"),
+    }
+  }
+}
+
+impl HostConfig {
+  pub fn parse(path: &str, source: &str) -> Result<Self, ConfigError> {
+    toml::from_str(source)
+      .map_err(|error| ConfigError::Syntax { path: path.to_owned(), message: error.message().to_owned() })
+  }
+
+  /// Read config at `path`, or at `default_path` if there is none.
+  /// Missing default config is not an error, missing given one is
+  pub fn load(path: Option<&str>) -> Result<Self, ConfigError> {
+    let (path, required) = match path {
+      Some(path) => (path.to_owned(), true),
+      None => match default_path() {
+        Some(path) => (path.to_string_lossy().into_owned(), false),
+        None => return Ok(Self::default()),
+      },
+    };
+
+    match std::fs::read_to_string(&path) {
+      Ok(source) => Self::parse(&path, &source),
+      Err(error) if !required && error.kind() == std::io::ErrorKind::NotFound => Ok(Self::default()),
+      Err(error) => Err(ConfigError::Read { path, message: error.to_string() }),
+    }
+  }
+
+  /// Snapshot of machine at `machine_path` in `snapshot_dir`,
+  /// named after directory of the machine: `machines/1/machine.yaml`
+  /// is kept in `<snapshot_dir>/1.snapshot`
+  pub fn snapshot_for(&self, machine_path: &str) -> Option<String> {
+    let snapshot_dir = self.snapshot_dir.as_ref()?;
+    let name = Path::new(machine_path)
+      .parent()
+      .and_then(Path::file_name)
+      .map(|name| name.to_string_lossy().into_owned())
+      .unwrap_or_else(|| String::from("machine"));
+
+    Some(Path::new(snapshot_dir).join(format!("{name}.snapshot")).to_string_lossy().into_owned())
+  }
+}
+
+/// `$XDG_CONFIG_HOME/eunix/config.toml`, or `~/.config/eunix/config.toml`
+pub fn default_path() -> Option<PathBuf> {
+  std::env::var_os("XDG_CONFIG_HOME")
+    .map(PathBuf::from)
+    .or_else(|| std::env::var_os("HOME").map(|home| Path::new(&home).join(".config")))
+    .map(|config_home| config_home.join("eunix/config.toml"))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn config_is_parsed() {
+    let config = HostConfig::parse("config.toml", concat!(
+      "machine = \"/vm/machine.yaml\"\n",
+      "editor = \"vim\"\n",
+      "color = false\n",
+      "log_level = \"info\"\n",
+      "snapshot_dir = \"/snapshots\"\n",
+    )).unwrap();
+
+    assert_eq!(config, HostConfig {
+      machine: Some(String::from("/vm/machine.yaml")),
+      editor: Some(String::from("vim")),
+      color: false,
+      log_level: Some(String::from("info")),
+      snapshot_dir: Some(String::from("/snapshots")),
+    });
+    assert_eq!(config.snapshot_for("/vm/machine.yaml").as_deref(), Some("/snapshots/vm.snapshot"));
+  }
+
+  #[test]
+  fn empty_config_has_defaults() {
+    assert_eq!(HostConfig::parse("config.toml", "").unwrap(), HostConfig::default());
+    assert_eq!(HostConfig::default().snapshot_for("/vm/machine.yaml"), None);
+  }
+
+  #[test]
+  fn unknown_keys_and_missing_files_are_errors() {
+    assert!(matches!(
+      HostConfig::parse("config.toml", "colour = true\n"),
+      Err(ConfigError::Syntax { .. }),
+    ));
+
+    assert!(matches!(
+      HostConfig::load(Some("/nonexistent/eunix/config.toml")),
+      Err(ConfigError::Read { .. }),
+    ));
+  }
+}
+
+// vim:ts=2 sw=2
@@ -1,7 +1,27 @@
 pub mod kernel;
+pub mod clock;
+pub mod rng;
+pub mod dmesg;
 pub mod fs;
 pub mod e5fs;
+pub mod journal;
 pub mod devfs;
+pub mod devices;
+pub mod console;
+pub mod line_editor;
+pub mod partitions;
+pub mod ustar;
+pub mod netfs;
+pub mod overlayfs;
+pub mod net;
 pub mod binfs;
+pub mod procfs;
+pub mod sysfs;
+pub mod script;
 pub mod virtfs;
+pub mod watch;
+pub mod signal;
 pub mod users;
+pub mod userdb;
+pub mod passwords;
+pub mod records;
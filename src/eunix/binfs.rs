@@ -1,23 +1,36 @@
-use std::{fmt, rc::Rc, borrow::Borrow};
+use std::{fmt, sync::Arc};
 
-use super::{fs::{Filesystem, AddressSize}, virtfs::{VirtFsFilesystem, Payload}, kernel::{Args, Kernel, Errno, Times}};
+use super::{clock::Clock, fs::{Filesystem, FilesystemStat, AddressSize, FileModeType}, virtfs::VirtFsFilesystem, kernel::{Args, Kernel, Errno, Times}};
 
-type BinaryFn = fn(Args, &mut Kernel) -> AddressSize;
+pub type BinaryFn = fn(Args, &mut Kernel) -> AddressSize;
 
+/// Contents of binfs file: either function compiled into the kernel,
+/// or rhai script which is interpreted on exec (see `script::run`)
 #[derive(Clone)]
-pub struct Binary(pub BinaryFn);
+pub enum Binary {
+  Native(BinaryFn),
+  Script(String),
+}
 
 impl fmt::Debug for Binary {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-      // let fun = self.0 as ;
-      let fun: fn(_, &'static mut _) -> _ = self.0;
-      write!(f, "{:?}", fun)
+    match self {
+      Self::Native(binary_fn) => {
+        let fun: fn(_, &'static mut _) -> _ = *binary_fn;
+        write!(f, "{:?}", fun)
+      },
+      Self::Script(source) => write!(f, "Script({} bytes)", source.len()),
+    }
   }
 }
 
 impl fmt::Display for Binary {
   fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
-      write!(f, "{:?}", self)
+    match self {
+      Self::Native(_) => write!(f, "{:?}", self),
+      // Scripts are readable as is
+      Self::Script(source) => write!(f, "{source}"),
+    }
   }
 }
 
@@ -28,10 +41,17 @@ fn default_binary(_: Args, _: &mut Kernel) -> AddressSize {
 
 impl Default for Binary {
   fn default() -> Self {
-    Self(default_binary)
+    Self::Native(default_binary)
   }
 }
 
+/// Anything written to binfs file becomes a script
+fn parse_script(data: &[u8]) -> Result<Binary, Errno> {
+  String::from_utf8(data.to_owned())
+    .map(Binary::Script)
+    .or(Err(Errno::EILSEQ(String::from("binfs: script is not utf-8").into())))
+}
+
 pub struct BinFilesytem {
   pub virtfs: VirtFsFilesystem<Binary>,
 }
@@ -39,49 +59,92 @@ pub struct BinFilesytem {
 impl BinFilesytem {
   pub fn new() -> Self {
     Self {
-      virtfs: VirtFsFilesystem::new("binfs", 1024),
+      virtfs: VirtFsFilesystem::new("binfs", 1024).with_writer(parse_script),
     }
   }
-  pub fn write_binary(&mut self, pathname: &str, binary_fn: BinaryFn)
+  pub fn write_binary(&mut self, pathname: &str, binary: Binary)
     -> Result<super::fs::VINode, super::kernel::Errno> {
       let vinode = self.lookup_path(pathname)?;
-      self.virtfs.write_payload(&Payload::File(Binary(binary_fn)), vinode.number)?;
-
-      Ok(vinode)
-  }
-
-  pub(crate) fn add_bins(&mut self, binary_fns: Vec<(String, BinaryFn)>) -> Result<(), Errno> {
-    // Guard - check that all specified bins dont exist
-    // for (pathname, result) in binary_fns
-    //   .iter()
-    //   .map(|(pathname, _)| { println!("{pathname}"); (pathname, self.lookup_path(pathname)) })
-    // {
-    //   match result {
-    //     Err(Errno::ENOENT(_)) => {
-    //       return Err(Errno::EEXIST(format!("binfs: add_bins: {pathname} already exists")))
-    //     },
-    //     Err(errno) => {
-    //       return Err(Errno::EBADFS(format!("binfs: add_bins: unexpected error: ERRNO: {errno:?}")))
-    //     },
-    //     Ok(_) => (),
-    //   }
-    // }
-
-    // Actually add binaries
-    for (pathname, binary_fn) in binary_fns.iter() {
-      self.create_file(pathname).expect("binfs: file creation should succeed");
-      self.write_binary(pathname, *binary_fn).expect("binfs: file creation should succeed");
+      self.virtfs.write_file_payload(vinode.number, binary)
+  }
+
+  /// Dump tree of binaries as YAML with sources of scripts. Native
+  /// binaries are functions of the running kernel, so they are left out
+  pub fn serialize(&self) -> Result<String, Errno> {
+    self.virtfs.serialize_with(|binary| match binary {
+      Binary::Native(_) => None,
+      Binary::Script(source) => Some(source.clone()),
+    })
+  }
+
+  /// Restore binfs dumped by `serialize`. Native binaries come back
+  /// as empty scripts, until they are replaced with `replace_bin`
+  pub fn deserialize(serialized: &str) -> Result<Self, Errno> {
+    let virtfs = VirtFsFilesystem::deserialize_with(serialized, |source: Option<String>| {
+      Binary::Script(source.unwrap_or_default())
+    })?;
+
+    Ok(Self {
+      virtfs: virtfs.with_writer(parse_script),
+    })
+  }
+
+  /// Add new binary at `pathname`
+  pub fn add_bin(&mut self, pathname: &str, binary_fn: BinaryFn)
+    -> Result<super::fs::VINode, Errno> {
+    self.add_binary(pathname, Binary::Native(binary_fn))
+  }
+
+  /// Add new rhai script at `pathname`
+  pub fn add_script(&mut self, pathname: &str, source: &str)
+    -> Result<super::fs::VINode, Errno> {
+    self.add_binary(pathname, Binary::Script(source.to_owned()))
+  }
+
+  fn add_binary(&mut self, pathname: &str, binary: Binary)
+    -> Result<super::fs::VINode, Errno> {
+    // Guard for file already existing
+    match self.lookup_path(pathname) {
+      Ok(_) => return Err(Errno::EEXIST(format!("binfs: add_binary: {pathname} already exists").into())),
+      Err(Errno::ENOENT(_)) => (),
+      Err(errno) => return Err(errno),
+    }
+
+    // Binaries are executable by everyone: rwxr-xr-x
+    let vinode = self.create_file(pathname)?;
+    self.change_mode(pathname, vinode.mode.with_user(0b111).with_group(0b101).with_others(0b101))?;
+
+    self.write_binary(pathname, binary)
+  }
+
+  /// Remove binary at `pathname`
+  pub fn remove_bin(&mut self, pathname: &str) -> Result<(), Errno> {
+    // Guard for directories
+    if self.lookup_path(pathname)?.mode.file_type() == FileModeType::Dir as u8 {
+      return Err(Errno::EISDIR(format!("binfs: remove_bin: {pathname} is a directory").into()));
+    }
+
+    self.remove_file(pathname)
+  }
+
+  /// Replace binary at `pathname`, which must exist
+  pub fn replace_bin(&mut self, pathname: &str, binary_fn: BinaryFn)
+    -> Result<super::fs::VINode, Errno> {
+    // Guard for directories
+    if self.lookup_path(pathname)?.mode.file_type() == FileModeType::Dir as u8 {
+      return Err(Errno::EISDIR(format!("binfs: replace_bin: {pathname} is a directory").into()));
     }
 
-    Ok(())
+    self.write_binary(pathname, Binary::Native(binary_fn))
   }
+
   // pub fn exec_binary(&mut self, pathname: &str, kernel: &mut Kernel)
   //   -> Result<AddressSize, super::kernel::Errno> {
   //     let vinode = self.lookup_path(pathname)?;
   //
   //     let binary = match self.virtfs.read_payload(vinode.number) {
   //         Ok(Payload::File(binary)) => binary.0(),
-  //         Ok(Payload::Directory(_)) => return Err(Errno::EISDIR(format!("binfs: is a directory: {pathname}"))),
+  //         Ok(Payload::Directory(_)) => return Err(Errno::EISDIR(format!("binfs: is a directory: {pathname}").into())),
   //         Err(errno) => return Err(errno),
   //     }
   //
@@ -97,7 +160,7 @@ impl Filesystem for BinFilesytem {
 
   fn remove_file(&mut self, pathname: &str)
     -> Result<(), Errno> {
-    todo!()
+    self.virtfs.remove_file(pathname)
   }
 
   fn create_dir(&mut self, pathname: &str)
@@ -105,6 +168,26 @@ impl Filesystem for BinFilesytem {
     self.virtfs.create_dir(pathname)
   }
 
+  fn mknod(&mut self, pathname: &str, file_type: super::fs::FileModeType, device_number: super::fs::DeviceNumber)
+    -> Result<super::fs::VINode, super::kernel::Errno> {
+    self.virtfs.mknod(pathname, file_type, device_number)
+  }
+
+  fn link(&mut self, existing: &str, new: &str)
+    -> Result<super::fs::VINode, super::kernel::Errno> {
+    self.virtfs.link(existing, new)
+  }
+
+  fn symlink(&mut self, target: &str, pathname: &str)
+    -> Result<super::fs::VINode, super::kernel::Errno> {
+    self.virtfs.symlink(target, pathname)
+  }
+
+  fn readlink(&mut self, pathname: &str)
+    -> Result<String, super::kernel::Errno> {
+    self.virtfs.readlink(pathname)
+  }
+
   fn read_file(&mut self, pathname: &str, count: super::fs::AddressSize)
     -> Result<Vec<u8>, super::kernel::Errno> {
     self.virtfs.read_file(pathname, count)
@@ -115,6 +198,16 @@ impl Filesystem for BinFilesytem {
     self.virtfs.write_file(pathname, data)
   }
 
+  fn read_at(&mut self, pathname: &str, offset: super::fs::AddressSize, count: super::fs::AddressSize)
+    -> Result<Vec<u8>, super::kernel::Errno> {
+    self.virtfs.read_at(pathname, offset, count)
+  }
+
+  fn write_at(&mut self, pathname: &str, offset: super::fs::AddressSize, data: &[u8])
+    -> Result<super::fs::VINode, super::kernel::Errno> {
+    self.virtfs.write_at(pathname, offset, data)
+  }
+
   fn read_dir(&mut self, pathname: &str)
     -> Result<super::fs::VDirectory, super::kernel::Errno> {
     self.virtfs.read_dir(pathname)
@@ -132,12 +225,12 @@ impl Filesystem for BinFilesytem {
 
   fn change_owners(&mut self, pathname: &str, uid: super::fs::Id, gid: super::fs::Id) 
     -> Result<(), Errno> {
-    todo!()
+    self.virtfs.change_owners(pathname, uid, gid)
   }
 
   fn change_times(&mut self, pathname: &str, times: Times)
     -> Result<(), Errno> {
-    todo!()
+    self.virtfs.change_times(pathname, times)
   }
 
   fn lookup_path(&mut self, pathname: &str)
@@ -145,6 +238,15 @@ impl Filesystem for BinFilesytem {
     self.virtfs.lookup_path(pathname)
   }
 
+  fn statfs(&mut self, pathname: &str)
+    -> Result<FilesystemStat, Errno> {
+    Filesystem::statfs(&mut self.virtfs, pathname)
+  }
+
+  fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+    self.virtfs.set_clock(clock)
+  }
+
   fn name(&self) -> String {
     String::from("binfs")
   }
@@ -152,6 +254,100 @@ impl Filesystem for BinFilesytem {
 fn as_any(&mut self) -> &mut dyn std::any::Any {
     self
   }
+
+  fn as_any_ref(&self) -> &dyn std::any::Any {
+    self
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::eunix::{virtfs::Payload, kernel::{ROOT_UID, ROOT_GID}};
+
+  fn one(_: Args, _: &mut Kernel) -> AddressSize {
+    1
+  }
+
+  fn two(_: Args, _: &mut Kernel) -> AddressSize {
+    2
+  }
+
+  fn read_binary(binfs: &mut BinFilesytem, pathname: &str) -> BinaryFn {
+    let vinode = binfs.lookup_path(pathname).unwrap();
+    match binfs.virtfs.read_payload(vinode.number).unwrap() {
+      Payload::File(Binary::Native(binary_fn)) => binary_fn,
+      Payload::File(Binary::Script(_)) => panic!("{pathname} is a script"),
+      Payload::Directory(_) => panic!("{pathname} is a directory"),
+      Payload::Generator(_) => panic!("{pathname} is generated"),
+    }
+  }
+
+  #[test]
+  fn add_replace_remove_bin_works() {
+    let mut binfs = BinFilesytem::new();
+
+    binfs.add_bin("/one", one).unwrap();
+    assert!(matches!(binfs.add_bin("/one", two), Err(Errno::EEXIST(_))));
+    assert_eq!(read_binary(&mut binfs, "/one") as usize, one as BinaryFn as usize);
+
+    binfs.replace_bin("/one", two).unwrap();
+    assert_eq!(read_binary(&mut binfs, "/one") as usize, two as BinaryFn as usize);
+
+    binfs.remove_bin("/one").unwrap();
+    assert!(matches!(binfs.lookup_path("/one"), Err(Errno::ENOENT(_))));
+    assert!(matches!(binfs.replace_bin("/one", one), Err(Errno::ENOENT(_))));
+  }
+
+  #[test]
+  fn add_bin_makes_executable() {
+    let mut binfs = BinFilesytem::new();
+
+    let vinode = binfs.add_bin("/one", one).unwrap();
+
+    assert_eq!((vinode.mode.user(), vinode.mode.group(), vinode.mode.others()), (0b111, 0b101, 0b101));
+    assert_eq!(vinode.mode.file_type(), FileModeType::File as u8);
+  }
+
+  #[test]
+  fn stat_matches_contents() {
+    let mut binfs = BinFilesytem::new();
+    binfs.add_bin("/one", one).unwrap();
+
+    let stat = binfs.stat("/one").unwrap();
+    let contents = binfs.read_file("/one", AddressSize::MAX).unwrap();
+
+    assert_eq!(stat.size as usize, contents.len());
+    assert!(stat.size > 0);
+    assert!(stat.mtime > 0);
+    assert_eq!((stat.uid, stat.gid), (ROOT_UID, ROOT_GID));
+  }
+
+  #[test]
+  fn serialize_keeps_scripts_only() {
+    let mut binfs = BinFilesytem::new();
+    binfs.add_bin("/one", one).unwrap();
+    binfs.add_script("/hello", "print(\"hello\")").unwrap();
+
+    let mut restored = BinFilesytem::deserialize(&binfs.serialize().unwrap()).unwrap();
+
+    assert_eq!(restored.read_file("/hello", AddressSize::MAX).unwrap(), b"print(\"hello\")");
+    assert_eq!(restored.read_file("/one", AddressSize::MAX).unwrap(), b"");
+    restored.replace_bin("/one", two).unwrap();
+    assert_eq!(read_binary(&mut restored, "/one") as usize, two as BinaryFn as usize);
+  }
+
+  #[test]
+  fn written_files_become_scripts() {
+    let mut binfs = BinFilesytem::new();
+    binfs.add_script("/hello", "print(\"hello\")").unwrap();
+
+    binfs.write_file("/hello", b"42").unwrap();
+
+    let vinode = binfs.lookup_path("/hello").unwrap();
+    assert!(matches!(binfs.virtfs.read_payload(vinode.number).unwrap(), Payload::File(Binary::Script(source)) if source == "42"));
+    assert_eq!(binfs.read_file("/hello", AddressSize::MAX).unwrap(), b"42");
+  }
 }
 
 // vim:ts=2 sw=2
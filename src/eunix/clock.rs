@@ -0,0 +1,101 @@
+use std::fmt::Debug;
+use std::sync::Arc;
+use std::time::Instant;
+
+use super::kernel::UnixtimeSize;
+use crate::machine::MachineClock;
+use crate::util::unixtime;
+
+/// Source of time for everything in kernel: filesystem timestamps,
+/// login records, password ages. Owned by `Kernel` and shared with
+/// mounted filesystems, so that machine can have its own time.
+/// Filesystems use host time until kernel mounts them
+pub trait Clock: Debug + Send + Sync {
+  /// Seconds since unix epoch
+  fn now(&self) -> UnixtimeSize;
+}
+
+/// Time of host
+#[derive(Debug, Default)]
+pub struct HostClock;
+
+impl Clock for HostClock {
+  fn now(&self) -> UnixtimeSize {
+    unixtime()
+  }
+}
+
+/// Time that stands still, for reproducible timestamps
+#[derive(Debug)]
+pub struct FixedClock {
+  pub epoch: UnixtimeSize,
+}
+
+impl Clock for FixedClock {
+  fn now(&self) -> UnixtimeSize {
+    self.epoch
+  }
+}
+
+/// Time that goes `speed` times as fast as host time,
+/// starting from host time when clock was made
+#[derive(Debug)]
+pub struct ScaledClock {
+  origin: UnixtimeSize,
+  started: Instant,
+  speed: f64,
+}
+
+impl ScaledClock {
+  pub fn new(speed: f64) -> Self {
+    Self {
+      origin: unixtime(),
+      started: Instant::now(),
+      speed,
+    }
+  }
+}
+
+impl Clock for ScaledClock {
+  fn now(&self) -> UnixtimeSize {
+    self.origin + (self.started.elapsed().as_secs_f64() * self.speed) as UnixtimeSize
+  }
+}
+
+/// Clock that machine with `clock` setting has
+pub fn from_machine_clock(clock: MachineClock) -> Arc<dyn Clock> {
+  match clock {
+    MachineClock::Host => Arc::new(HostClock),
+    MachineClock::Fixed { epoch } => Arc::new(FixedClock { epoch }),
+    MachineClock::Scaled { speed } => Arc::new(ScaledClock::new(speed)),
+  }
+}
+
+/// Host clock, for filesystems that are not mounted by kernel yet
+pub fn host_clock() -> Arc<dyn Clock> {
+  Arc::new(HostClock)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::eunix::fs::FilesystemType;
+  use crate::testing::kernel_with_devices;
+
+  #[test]
+  fn mounted_filesystems_use_kernel_clock() {
+    let mut kernel = kernel_with_devices(Vec::new(), MachineClock::Fixed { epoch: 1_000_000 });
+    kernel.mount("", "/", FilesystemType::binfs).unwrap();
+    let mut vfs = kernel.vfs();
+    let root = &mut vfs.mount_points.get_mut("/").unwrap().driver;
+
+    root.create_file("/file").unwrap();
+    root.write_file("/file", b"contents").unwrap();
+    let stat = root.stat("/file").unwrap();
+
+    assert_eq!((stat.atime, stat.mtime, stat.ctime, stat.btime), (1_000_000, 1_000_000, 1_000_000, 1_000_000));
+    assert_eq!(kernel.clock.now(), 1_000_000);
+  }
+}
+
+// vim:ts=2 sw=2
@@ -0,0 +1,190 @@
+use std::sync::{Arc, RwLock};
+use std::collections::VecDeque;
+use std::fmt::Debug;
+use std::io::{self, stdin, stdout, Read, Write};
+use std::process::{Command, Stdio};
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Whether kernel messages on consoles are colored, see `set_color`
+static COLOR: AtomicBool = AtomicBool::new(true);
+
+/// Turn colors of kernel messages on or off, for terminals of host
+/// that don't understand ANSI escapes
+pub fn set_color(color: bool) {
+  COLOR.store(color, Ordering::Relaxed);
+}
+
+pub fn color() -> bool {
+  COLOR.load(Ordering::Relaxed)
+}
+
+/// Whether Ctrl-C was pressed on terminal of host, see `hook_interrupt`
+static INTERRUPTED: AtomicBool = AtomicBool::new(false);
+
+/// Catch Ctrl-C on terminal of host instead of letting it end the
+/// machine, so kernel can send SIGINT to foreground process
+pub fn hook_interrupt() {
+  #[cfg(unix)]
+  {
+    extern "C" fn on_interrupt(_: libc::c_int) {
+      INTERRUPTED.store(true, Ordering::Relaxed);
+    }
+
+    // Without SA_RESTART, so that blocking read of terminal is
+    // interrupted by Ctrl-C instead of waiting for next line
+    unsafe {
+      let mut action: libc::sigaction = std::mem::zeroed();
+      action.sa_sigaction = on_interrupt as extern "C" fn(libc::c_int) as libc::sighandler_t;
+      action.sa_flags = 0;
+      libc::sigemptyset(&mut action.sa_mask);
+      libc::sigaction(libc::SIGINT, &action, std::ptr::null_mut());
+    }
+  }
+}
+
+/// Returns: whether Ctrl-C was pressed on terminal of host since
+/// the last call
+pub fn take_interrupt() -> bool {
+  INTERRUPTED.swap(false, Ordering::Relaxed)
+}
+
+/// Terminal that consoles of machine are shown on and typed into:
+/// terminal of host, or terminal emulator of another frontend,
+/// like one in a browser for WASM build
+pub trait ConsoleBackend: Debug + Send + Sync {
+  /// Read one line, newline included.
+  /// Returns: empty line at end of input
+  fn read_line(&mut self) -> io::Result<Vec<u8>>;
+
+  /// Read one byte as soon as it is typed.
+  /// Returns: nothing at end of input
+  fn read_byte(&mut self) -> io::Result<Vec<u8>>;
+
+  fn write(&mut self, data: &[u8]) -> io::Result<()>;
+
+  /// Turn line editing and echo of terminal off (`raw`) or back on
+  fn set_raw(&mut self, raw: bool) -> io::Result<()>;
+}
+
+/// Terminal of host: stdin and stdout, set up with `stty`
+#[derive(Debug, Default)]
+pub struct HostConsole;
+
+impl ConsoleBackend for HostConsole {
+  fn read_line(&mut self) -> io::Result<Vec<u8>> {
+    let mut line = String::new();
+    stdin().read_line(&mut line)?;
+
+    Ok(line.into_bytes())
+  }
+
+  fn read_byte(&mut self) -> io::Result<Vec<u8>> {
+    let mut byte = vec![0u8; 1];
+    let count = stdin().read(&mut byte)?;
+    byte.truncate(count);
+
+    Ok(byte)
+  }
+
+  fn write(&mut self, data: &[u8]) -> io::Result<()> {
+    stdout()
+      .write_all(data)
+      .and_then(|_| stdout().flush())
+  }
+
+  fn set_raw(&mut self, raw: bool) -> io::Result<()> {
+    let stty_args: &[&str] = match raw {
+      true => &["raw", "-echo"],
+      false => &["-raw", "echo"],
+    };
+    Command::new("stty")
+      .args(stty_args)
+      .stdin(Stdio::inherit())
+      .status()
+      .map(|_| ())
+  }
+}
+
+/// Terminal of frontend that drives machine itself, like terminal
+/// emulator in a browser: it types into `input` and shows what is
+/// taken from `output`. Reads never block, nothing typed yet reads
+/// as end of input
+#[derive(Debug, Default)]
+pub struct BufferConsole {
+  pub input: VecDeque<u8>,
+  pub output: Vec<u8>,
+  pub raw: bool,
+}
+
+impl BufferConsole {
+  pub fn type_input(&mut self, data: &[u8]) {
+    self.input.extend(data);
+  }
+
+  /// Returns: everything written since the last call
+  pub fn take_output(&mut self) -> Vec<u8> {
+    std::mem::take(&mut self.output)
+  }
+}
+
+impl ConsoleBackend for BufferConsole {
+  fn read_line(&mut self) -> io::Result<Vec<u8>> {
+    let line_length = self.input
+      .iter()
+      .position(|&byte| byte == b'\n')
+      .map_or(self.input.len(), |position| position + 1);
+    let line = self.input.drain(..line_length).collect::<Vec<u8>>();
+    // Terminal echoes what is typed, unless it is raw
+    if !self.raw {
+      self.output.extend(&line);
+    }
+
+    Ok(line)
+  }
+
+  fn read_byte(&mut self) -> io::Result<Vec<u8>> {
+    Ok(self.input.pop_front().into_iter().collect())
+  }
+
+  fn write(&mut self, data: &[u8]) -> io::Result<()> {
+    self.output.extend(data);
+
+    Ok(())
+  }
+
+  fn set_raw(&mut self, raw: bool) -> io::Result<()> {
+    self.raw = raw;
+
+    Ok(())
+  }
+}
+
+/// Terminal of host, for consoles that were not given another one
+pub fn host_console() -> Arc<RwLock<dyn ConsoleBackend>> {
+  Arc::new(RwLock::new(HostConsole))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::eunix::devices::{CharDevice, HostTTY, TTYMode};
+
+  #[test]
+  fn tty_is_driven_through_buffer_console() {
+    let console = Arc::new(RwLock::new(BufferConsole::default()));
+    let mut tty = HostTTY::new("/dev/tty1", console.clone());
+    console.write().unwrap().type_input(b"ls /\nq");
+
+    tty.write(b"# ").unwrap();
+    assert_eq!(tty.read(1024).unwrap(), b"ls /\n");
+    tty.set_tty_mode(TTYMode::Raw).unwrap();
+    assert_eq!(tty.read(1024).unwrap(), b"q");
+    assert_eq!(tty.read(1024).unwrap(), b"");
+
+    // Line is echoed in canonical mode only
+    assert_eq!(console.write().unwrap().take_output(), b"# ls /\n");
+    assert_eq!(console.write().unwrap().take_output(), b"");
+  }
+}
+
+// vim:ts=2 sw=2
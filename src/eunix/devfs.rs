@@ -1,14 +1,20 @@
 use std::any::Any;
+use std::sync::{Arc, RwLock};
 use std::collections::BTreeMap;
 use std::time::SystemTime;
 
+use itertools::Itertools;
+
 use crate::eunix::kernel::Kernel;
-use crate::machine::VirtualDeviceType;
 use crate::eunix::fs::Filesystem;
-use crate::util::unixtime;
 
-use super::fs::{AddressSize, VDirectoryEntry, VINode, VDirectory, VFS, FileMode, FileStat, FileModeType};
+use super::fs::{AddressSize, VDirectoryEntry, VINode, VDirectory, VFS, FileMode, FileStat, FileModeType, DeviceNumber, FilesystemStat};
+use super::clock::{Clock, host_clock};
+use super::rng::Rng;
 use super::kernel::{Errno, KernelDeviceTable, UnixtimeSize, Times};
+use super::devices::{self, BlockStorage, CharDevice, DeviceDriver, Partition, TTYMode};
+use super::e5fs::{E5FSFilesystem, FilesystemIdentity};
+use super::partitions::{PartitionTable, SECTOR_SIZE};
 
 pub struct DirectoryEntry<'a> {
   inode_address: AddressSize,
@@ -27,6 +33,7 @@ pub struct INode {
   mtime: UnixtimeSize,
   ctime: UnixtimeSize,
   btime: UnixtimeSize,
+  rdev: DeviceNumber,
   number: AddressSize,
 }
 impl From<INode> for VINode {
@@ -41,11 +48,16 @@ impl From<INode> for VINode {
       ctime: inode.ctime,
       mtime: inode.mtime,
       btime: inode.btime,
+      rdev: inode.rdev,
       number: inode.number,
     }
   }
 }
 
+
+/// Block size reported for block devices, same as partition table sector
+pub const DEVICE_BLOCK_SIZE: AddressSize = SECTOR_SIZE as AddressSize;
+
 pub struct Superblock {
   filesystem_type: [u8; 255],
   filesystem_size: AddressSize, // in blocks
@@ -59,30 +71,191 @@ pub struct Superblock {
   free_blocks: [AddressSize; 16],
 }
 
+/// Device registered in devfs
+#[derive(Debug, Clone)]
+struct RegisteredDevice {
+  name: String,
+  rdev: DeviceNumber,
+  driver: DeviceDriver,
+  /// Name of the disk for partitions found by `DeviceFilesystem::rescan`
+  disk: Option<String>,
+}
+
+/// Directory of aliases for disks with e5fs labels
+pub const DISK_BY_LABEL_PATH: &'static str = "/disk/by-label";
+/// Directory of aliases for disks with e5fs UUIDs
+pub const DISK_BY_UUID_PATH: &'static str = "/disk/by-uuid";
+
 pub struct DeviceFilesystem {
-  device_table: KernelDeviceTable,
+  /// Device `devices[i]` has inode number `i + 1`
+  devices: Vec<RegisteredDevice>,
   inodes: Vec<INode>,
+  /// Map of `directory pathname -> (name -> inode number)`,
+  /// aliases in `/disk/by-*` share inode with the device they point to
+  directories: BTreeMap<String, BTreeMap<String, AddressSize>>,
+  clock: Arc<dyn Clock>,
 }
 
 impl DeviceFilesystem {
-  pub fn new(device_table: &KernelDeviceTable) -> Self {
-    let inodes = vec![INode {
+  pub fn new(device_table: &KernelDeviceTable, rng: Arc<dyn Rng>) -> Self {
+    let mut devfs = Self {
+      devices: devices::from_device_table(device_table, rng)
+        .into_iter()
+        .map(|(name, rdev, driver)| RegisteredDevice { name, rdev, driver, disk: None })
+        .collect(),
+      inodes: Vec::new(),
+      directories: BTreeMap::new(),
+      clock: host_clock(),
+    };
+    devfs.rescan();
+    devfs
+  }
+
+  fn directory_inode(number: AddressSize, links_count: AddressSize, now: UnixtimeSize) -> INode {
+    INode {
       mode: FileMode::new(0b0_000_001_111_101_101),
-      links_count: 2,
+      links_count,
       file_size: 0,
       uid: 0,
       gid: 0,
-      atime: unixtime(),
-      mtime: unixtime(),
-      ctime: unixtime(), 
-      btime: unixtime(), 
-      number: 0,
-    }];
-    let rest_inodes = device_table
-      .devices
+      atime: now,
+      mtime: now,
+      ctime: now,
+      btime: now,
+      rdev: DeviceNumber::default(),
+      number,
+    }
+  }
+
+  /// Returns: Map of `pathname -> inode number` for every file
+  /// except aliases in `/disk/by-*`
+  fn pathnames(&self) -> BTreeMap<String, AddressSize> {
+    self.directories
+      .iter()
+      .filter(|(dir_pathname, _)| *dir_pathname != DISK_BY_LABEL_PATH && *dir_pathname != DISK_BY_UUID_PATH)
+      .flat_map(|(dir_pathname, entries)| entries
+        .iter()
+        .map(move |(name, &inode_number)| (
+          format!("{}/{name}", dir_pathname.trim_end_matches('/')),
+          inode_number,
+        )))
+      .chain([(String::from("/"), 0)])
+      .collect()
+  }
+
+  /// Make device available as `/name`. New device kinds only
+  /// need a `BlockDevice` or `CharDevice` implementation
+  pub fn register_device(&mut self, name: &str, rdev: DeviceNumber, driver: DeviceDriver) -> Result<(), Errno> {
+    // Guard for taken name or number
+    if self.devices.iter().any(|device| device.name == name || device.rdev == rdev) {
+      return Err(Errno::EEXIST(format!("devfs: device {name} ({rdev}) already exists").into()));
+    }
+
+    self.devices.push(RegisteredDevice {
+      name: name.to_owned(),
+      rdev,
+      driver,
+      disk: None,
+    });
+    self.rescan();
+
+    Ok(())
+  }
+
+  /// Remove device named `name` along with its partitions.
+  /// Returns: driver of the device
+  pub fn unregister_device(&mut self, name: &str) -> Result<DeviceDriver, Errno> {
+    // Guard for partitions, they go away with their disk
+    let device = self.device_by_name(name)?;
+    if device.disk.is_some() {
+      return Err(Errno::EINVAL(format!("devfs: {name} is a partition, not a whole disk").into()));
+    }
+    let driver = device.driver.clone();
+
+    self.devices.retain(|device| device.name != name && device.disk.as_deref() != Some(name));
+    self.rescan();
+
+    Ok(driver)
+  }
+
+  /// Swap driver of device named `name`, keeping its name and number.
+  /// Returns: previous driver
+  pub fn replace_driver(&mut self, name: &str, driver: DeviceDriver) -> Result<DeviceDriver, Errno> {
+    let device = self.devices
+      .iter_mut()
+      .find(|device| device.name == name)
+      .ok_or(Errno::ENOENT(format!("devfs: no device corresponds to name {name}").into()))?;
+    let previous_driver = std::mem::replace(&mut device.driver, driver);
+    self.rescan();
+
+    Ok(previous_driver)
+  }
+
+  /// Returns: driver of device named `name`
+  pub fn device_driver(&self, name: &str) -> Result<DeviceDriver, Errno> {
+    Ok(self.device_by_name(name)?.driver.clone())
+  }
+
+  /// Returns: `(name, driver)` of every device, in registration order
+  pub fn drivers(&self) -> Vec<(String, DeviceDriver)> {
+    self.devices
+      .iter()
+      .map(|device| (device.name.to_owned(), device.driver.clone()))
+      .collect()
+  }
+
+  /// Returns: partitions found in partition table of `device`,
+  /// if its driver reserves minor numbers for them
+  /// Like:
+  /// ("sda1", 8:1, Partition(sda, 512, 1048576))
+  fn probe_partitions(device: &RegisteredDevice) -> Vec<RegisteredDevice> {
+    let disk = match &device.driver {
+      DeviceDriver::Block(disk) if disk.partition_minors() > 0 => disk,
+      _ => return Vec::new(),
+    };
+
+    // Unreadable disks and disks without a table just have no partitions
+    let partitions = disk.open()
+      .and_then(|mut storage| PartitionTable::read_from(storage.as_mut()))
+      .ok()
+      .flatten()
+      .unwrap_or_default()
+      .partitions;
+
+    partitions
+      .into_iter()
+      .zip(1..=disk.partition_minors())
+      .filter_map(|(partition, index)| partition.map(|partition| (index, partition)))
+      .map(|(index, partition)| RegisteredDevice {
+        name: format!("{}{index}", device.name),
+        rdev: DeviceNumber::new(device.rdev.major, device.rdev.minor + index),
+        driver: DeviceDriver::Block(Arc::new(Partition::new(disk.clone(), partition.offset(), partition.size()))),
+        disk: Some(device.name.to_owned()),
+      })
+      .collect()
+  }
+
+  /// Re-read partition tables and filesystem labels of disks.
+  /// Mode, owners and times of files that are still there are kept
+  pub fn rescan(&mut self) {
+    let previous_inodes: BTreeMap<String, INode> = self
+      .pathnames()
+      .into_iter()
+      .filter_map(|(pathname, inode_number)| self.inodes.get(inode_number as usize).map(|&inode| (pathname, inode)))
+      .collect();
+
+    // Each disk is followed by its partitions
+    self.devices = self.devices
+      .iter()
+      .filter(|device| device.disk.is_none())
+      .flat_map(|device| std::iter::once(device.clone()).chain(DeviceFilesystem::probe_partitions(device)))
+      .collect();
+
+    let now = self.clock.now();
+    let rest_inodes = self.devices
       .iter()
       .enumerate()
-      .map(|(device_number, (_path, (dev_type, _1)))| INode {
+      .map(|(device_number, device)| INode {
         //    free?
         ///   | unused
         ///   | |   filetype
@@ -98,73 +271,217 @@ impl DeviceFilesystem {
         ///   001 - dir    101 - unused
         ///   010 - sys    110 - unused
         ///   011 - block  111 - unused
-        mode: FileMode::new(0b0_000_011_110_000_000).with_file_type(
-          match dev_type {
-            VirtualDeviceType::BlockDevice => FileModeType::Block,
-            VirtualDeviceType::TTYDevice => FileModeType::Char,
-          } as u8
-        ),
+        mode: match &device.driver {
+          DeviceDriver::Block(_) => FileMode::new(0b0_000_011_110_000_000)
+            .with_file_type(FileModeType::Block as u8),
+          // TTYs are readable and writable by everyone, like /dev/tty
+          DeviceDriver::Char(driver) if driver.read().unwrap().is_tty() => FileMode::new(0b0_000_011_110_110_110)
+            .with_file_type(FileModeType::Char as u8),
+          DeviceDriver::Char(_) => FileMode::new(0b0_000_011_110_000_000)
+            .with_file_type(FileModeType::Char as u8),
+        },
         links_count: 1,
-        file_size: 0,
+        file_size: match &device.driver {
+          DeviceDriver::Block(driver) => driver.size().try_into().unwrap_or(AddressSize::MAX),
+          DeviceDriver::Char(_) => 0,
+        },
         uid: 0,
         gid: 0,
-        atime: unixtime(),
-        mtime: unixtime(),
-        ctime: unixtime(), 
-        btime: unixtime(), 
+        atime: now,
+        mtime: now,
+        ctime: now,
+        btime: now,
+        rdev: device.rdev,
         number: device_number as AddressSize + 1,
-      }).collect::<Vec<INode>>();
+      })
+      .collect::<Vec<_>>();
 
-    let inodes = inodes
-      .into_iter()
-      .chain(rest_inodes.into_iter())
+    // Directories go after devices: `/disk`, `/disk/by-label`, `/disk/by-uuid`
+    let disk_dir_number = self.devices.len() as AddressSize + 1;
+    let by_label_dir_number = disk_dir_number + 1;
+    let by_uuid_dir_number = disk_dir_number + 2;
+
+    let mut root_dir: BTreeMap<String, AddressSize> = self.devices
+      .iter()
+      .zip(1..)
+      .map(|(device, inode_number)| (device.name.to_owned(), inode_number))
       .collect();
+    root_dir.insert(String::from("disk"), disk_dir_number);
+
+    let disk_dir = BTreeMap::from([
+      (String::from("by-label"), by_label_dir_number),
+      (String::from("by-uuid"), by_uuid_dir_number),
+    ]);
+
+    let mut by_label_dir = BTreeMap::new();
+    let mut by_uuid_dir = BTreeMap::new();
+    for (device, inode_number) in self.devices.iter().zip(1..) {
+      // Only block devices are probed: reading TTYs would block on the host terminal
+      let identity = match &device.driver {
+        DeviceDriver::Block(driver) => driver.open()
+          .ok()
+          .and_then(|mut storage| E5FSFilesystem::probe(storage.as_mut())),
+        DeviceDriver::Char(_) => None,
+      };
+
+      if let Some(FilesystemIdentity { uuid, label }) = identity {
+        if !label.is_empty() {
+          by_label_dir.insert(label, inode_number);
+        }
+        by_uuid_dir.insert(uuid, inode_number);
+      }
+    }
 
-    Self {
-      device_table: device_table.clone(),
-      inodes,
+    self.inodes = std::iter::once(DeviceFilesystem::directory_inode(0, 3, now))
+      .chain(rest_inodes)
+      .chain([
+        DeviceFilesystem::directory_inode(disk_dir_number, 4, now),
+        DeviceFilesystem::directory_inode(by_label_dir_number, 2, now),
+        DeviceFilesystem::directory_inode(by_uuid_dir_number, 2, now),
+      ])
+      .collect();
+    self.directories = BTreeMap::from([
+      (String::from("/"), root_dir),
+      (String::from("/disk"), disk_dir),
+      (String::from(DISK_BY_LABEL_PATH), by_label_dir),
+      (String::from(DISK_BY_UUID_PATH), by_uuid_dir),
+    ]);
+
+    for (pathname, inode_number) in self.pathnames() {
+      if let Some(previous_inode) = previous_inodes.get(&pathname) {
+        let inode = &mut self.inodes[inode_number as usize];
+        inode.mode = previous_inode.mode;
+        inode.uid = previous_inode.uid;
+        inode.gid = previous_inode.gid;
+        inode.atime = previous_inode.atime;
+        inode.mtime = previous_inode.mtime;
+        inode.ctime = previous_inode.ctime;
+        inode.btime = previous_inode.btime;
+      }
     }
   }
 
-  /// Returns: Map of `name -> realpath`
-  /// Like:
-  /// "sda" -> "/home/user/disk.enxvd"
-  pub fn device_names(&self) -> BTreeMap<String, String> {
-    let mut tty_devices_count = 0;
-    let mut block_devices_count = 0;
+  /// Returns: inode number of file at `pathname`
+  fn resolve(&self, pathname: &str) -> Result<AddressSize, Errno> {
+    let (everything_else, final_component) = VFS::split_path(pathname)?;
+
+    if everything_else.is_empty() && final_component == "/" {
+      return Ok(0);
+    }
+
+    let parent_pathname = format!("/{}", everything_else.join("/"));
+    self.directories
+      .get(&parent_pathname)
+      .and_then(|entries| entries.get(&final_component))
+      .copied()
+      .ok_or(Errno::ENOENT(String::from("no such file or directory 2").into()))
+  }
+
+  /// Returns: device at `pathname`, following `/disk/by-*` aliases
+  fn device_by_pathname(&self, pathname: &str) -> Result<&RegisteredDevice, Errno> {
+    let inode_number = self.resolve(pathname)?;
+
+    match inode_number {
+      0 => None,
+      inode_number => self.devices.get(inode_number as usize - 1),
+    }
+    .ok_or(Errno::EISDIR(format!("devfs: {pathname} is a directory").into()))
+  }
 
-    self.device_table.devices
+  fn device_by_name(&self, name: &str) -> Result<&RegisteredDevice, Errno> {
+    self.devices
       .iter()
-      .enumerate()
-      .map(|(_device_number, (realpath, (device_type, _)))| {
-        let name = match device_type {
-          VirtualDeviceType::BlockDevice => {
-            block_devices_count += 1;
-            format!("sd{}", char::from_u32(96u32 + block_devices_count).unwrap())
-          }
-          VirtualDeviceType::TTYDevice => {
-            tty_devices_count += 1;
-            format!("tty{}", tty_devices_count)
-          }
-        };
-        (name.to_owned(), realpath.to_owned())
-      })
-      .collect()
+      .find(|device| device.name == name)
+      .ok_or(Errno::ENOENT(format!("devfs: no device corresponds to name {name}").into()))
+  }
+
+  fn inode_mut(&mut self, pathname: &str) -> Result<&mut INode, Errno> {
+    let inode_number = self.resolve(pathname)?;
+
+    self.inodes
+      .get_mut(inode_number as usize)
+      .ok_or(Errno::EIO(String::from("devfs::inode_mut: can't find inode from dir").into()))
+  }
+
+  /// Returns: driver of character device at `pathname`
+  pub fn char_device(&self, pathname: &str) -> Result<Arc<RwLock<dyn CharDevice>>, Errno> {
+    match &self.device_by_pathname(pathname)?.driver {
+      DeviceDriver::Char(driver) => Ok(driver.clone()),
+      DeviceDriver::Block(_) => Err(Errno::EPERM(String::from("devfs: permission denied").into())),
+    }
+  }
+
+  /// Returns: name of device with number `rdev`
+  /// Like:
+  /// 8:16 -> "sdb"
+  pub fn device_name_by_number(&self, rdev: DeviceNumber) -> Result<String, Errno> {
+    self.devices
+      .iter()
+      .find(|device| device.rdev == rdev)
+      .map(|device| device.name.to_owned())
+      .ok_or(Errno::ENXIO(format!("devfs: no device with number {rdev}").into()))
   }
 
-  pub(crate) fn device_by_pathname(&self, pathname: &str) -> Result<String, Errno> {
-    let (_everything_else, final_component) = VFS::split_path(pathname)?;
-    let device_names = self.device_names();
-    let realpath = device_names.get(&final_component).ok_or(Errno::ENOENT(String::from("no device corresponds to that name")))?;
+  /// Open storage of block device at `pathname`,
+  /// bounded to the partition if it is one
+  pub fn open_block_storage(&self, pathname: &str) -> Result<Box<dyn BlockStorage>, Errno> {
+    let device = self.device_by_pathname(pathname)
+      .or(Err(Errno::ENOENT(String::from("no device corresponds to that name").into())))?;
 
-    Ok(realpath.to_owned())
+    match &device.driver {
+      DeviceDriver::Block(driver) => driver.open(),
+      DeviceDriver::Char(_) => Err(Errno::EINVAL(format!("devfs: {} is not a block device", device.name).into())),
+    }
+  }
+
+  /// Returns: partition table of disk at `pathname`, `None` if it has none
+  pub fn read_partition_table(&self, pathname: &str) -> Result<Option<PartitionTable>, Errno> {
+    let mut storage = self.open_whole_disk(pathname)?;
+    PartitionTable::read_from(storage.as_mut())
+  }
+
+  /// Write partition table to disk at `pathname` and re-read
+  /// partitions, so that new `sdXN` nodes appear
+  pub fn write_partition_table(&mut self, pathname: &str, table: &PartitionTable) -> Result<(), Errno> {
+    let mut storage = self.open_whole_disk(pathname)?;
+    table.write_to(storage.as_mut())?;
+    self.rescan();
+
+    Ok(())
+  }
+
+  /// Open storage of disk at `pathname`, refusing partitions
+  fn open_whole_disk(&self, pathname: &str) -> Result<Box<dyn BlockStorage>, Errno> {
+    // Guard for partitions of partitions
+    let device = self.device_by_pathname(pathname)?;
+    if device.disk.is_some() {
+      return Err(Errno::EINVAL(format!("devfs: {} is a partition, not a whole disk", device.name).into()));
+    }
+
+    self.open_block_storage(pathname)
+  }
+
+  /// Whether device named `name` is a terminal
+  pub fn is_tty(&self, name: &str) -> Result<bool, Errno> {
+    match &self.device_by_name(name)?.driver {
+      DeviceDriver::Char(driver) => Ok(driver.read().unwrap().is_tty()),
+      DeviceDriver::Block(_) => Ok(false),
+    }
+  }
+
+  /// Switch line discipline of TTY named `name`
+  pub fn set_tty_mode(&mut self, name: &str, mode: TTYMode) -> Result<(), Errno> {
+    match &self.device_by_name(name)?.driver {
+      DeviceDriver::Char(driver) if driver.read().unwrap().is_tty() => driver.write().unwrap().set_tty_mode(mode),
+      _ => Err(Errno::ENOTTY(format!("devfs: {name} is not a tty").into())),
+    }
   }
 }
 
 impl Filesystem for DeviceFilesystem {
   fn create_file(&mut self, pathname: &str)
     -> Result<VINode, Errno> {
-    Err(Errno::EPERM(String::from("operation not permitted")))
+    Err(Errno::EPERM(String::from("operation not permitted").into()))
   }
 
   fn remove_file(&mut self, pathname: &str)
@@ -177,29 +494,57 @@ impl Filesystem for DeviceFilesystem {
         todo!()
     }
 
+  fn mknod(&mut self, _pathname: &str, _file_type: FileModeType, _device_number: DeviceNumber)
+    -> Result<VINode, Errno> {
+    Err(Errno::EPERM(String::from("operation not permitted").into()))
+  }
+
+  fn link(&mut self, _existing: &str, _new: &str)
+    -> Result<VINode, Errno> {
+    Err(Errno::EPERM(String::from("operation not permitted").into()))
+  }
+
+  fn symlink(&mut self, _target: &str, _pathname: &str)
+    -> Result<VINode, Errno> {
+    Err(Errno::EPERM(String::from("operation not permitted").into()))
+  }
+
+  fn readlink(&mut self, pathname: &str)
+    -> Result<String, Errno> {
+    Err(Errno::EINVAL(format!("devfs: {pathname}: not a symbolic link").into()))
+  }
+
   fn read_file(&mut self, pathname: &str, count: AddressSize) -> Result<Vec<u8>, Errno> {
-    Err(Errno::EPERM(String::from("devfs read_bytes: permission denied")))
+    let driver = self.char_device(pathname)?;
+    let data = driver.write().unwrap().read(count)?;
+
+    Ok(data)
   }
 
   fn write_file(&mut self, pathname: &str, data: &[u8]) -> Result<VINode, Errno> {
-    Err(Errno::EPERM(String::from("devfs write_bytes: permission denied")))
+    let driver = self.char_device(pathname)?;
+    driver.write().unwrap().write(data)?;
+    self.lookup_path(pathname)
   }
 
   fn read_dir(&mut self, pathname: &str) -> Result<VDirectory, Errno> {
-    // TODO: FIXME: remove /. when .. and . is implemented 
-    if pathname != "/" && pathname != "/." && pathname != "/.." { // OLD
-    // if pathname != "/" {
-      return Err(Errno::ENOENT(String::from("no such file or directory")))
-    }
+    let (everything_else, final_component) = VFS::split_path(pathname)?;
+
+    let dir_pathname = match final_component.as_str() {
+      "/" if everything_else.is_empty() => String::from("/"),
+      _ => format!("/{}", everything_else.into_iter().chain([final_component]).join("/")),
+    };
+
+    let entries = self.directories
+      .get(&dir_pathname)
+      .ok_or(Errno::ENOENT(String::from("no such file or directory").into()))?;
 
     Ok(
       VDirectory {
-        entries: self
-          .device_names()
+        entries: entries
           .iter()
-          .zip(1..)
-          .map(|((name, _), device_number)| {
-            (name.to_owned(), VDirectoryEntry::new(device_number as AddressSize, name))
+          .map(|(name, &inode_number)| {
+            (name.to_owned(), VDirectoryEntry::new(inode_number, name))
           })
           .collect()
       }
@@ -219,6 +564,7 @@ impl Filesystem for DeviceFilesystem {
       mtime,
       ctime,
       btime,
+      rdev,
       ..
     } = self.lookup_path(pathname)?; 
 
@@ -229,7 +575,11 @@ impl Filesystem for DeviceFilesystem {
       links_count,
       uid,
       gid,
-      block_size: 0, // TODO: FIXME: magic number
+      rdev,
+      block_size: match mode.file_type() {
+        file_type if file_type == FileModeType::Block as u8 => DEVICE_BLOCK_SIZE,
+        _ => 0,
+      },
       atime,
       mtime,
       ctime, 
@@ -239,38 +589,61 @@ impl Filesystem for DeviceFilesystem {
 
   fn change_mode(&mut self, pathname: &str, mode: super::fs::FileMode)
     -> Result<(), Errno> {
-    Err(Errno::EPERM(String::from("operation not permitted")))
+    let now = self.clock.now();
+    let inode = self.inode_mut(pathname)?;
+
+    // Devices can't change their type
+    inode.mode = mode.with_file_type(inode.mode.file_type());
+    inode.ctime = now;
+
+    Ok(())
   }
 
   fn change_owners(&mut self, pathname: &str, uid: super::fs::Id, gid: super::fs::Id) 
     -> Result<(), Errno> {
-    todo!()
+    let now = self.clock.now();
+    let inode = self.inode_mut(pathname)?;
+    inode.uid = uid;
+    inode.gid = gid;
+    inode.ctime = now;
+
+    Ok(())
   }
 
   fn change_times(&mut self, pathname: &str, times: Times)
     -> Result<(), Errno> {
-    todo!()
+    let inode = self.inode_mut(pathname)?;
+    inode.atime = times.atime;
+    inode.mtime = times.mtime;
+    inode.ctime = times.ctime;
+    inode.btime = times.btime;
+
+    Ok(())
   }
 
 // Поиск файла в файловой системе. Возвращает INode файла.
   // Для VFS сначала матчинг на маунт-поинты и вызов lookup_path("/mount/point") у конкретной файловой системы;
   // Для конкретных реализаций (e5fs) поиск сразу от рута файловой системы
   fn lookup_path(&mut self, pathname: &str) -> Result<VINode, Errno> {
-    let (_, final_component) = VFS::split_path(pathname)?;
-    let dir = self.read_dir("/")?; // TODO: FIXME: magic string
-
-    let inode_number = if final_component == "/." || final_component == "/.." || final_component == "/" {
-      0
-    } else {
-      dir
-        .entries
-        .get(&final_component).ok_or(Errno::ENOENT(String::from("no such file or directory 2")))?.inode_number
-    };
+    let inode_number = self.resolve(pathname)?;
 
     self.inodes
       .get(inode_number as usize)
       .map(|&inode| inode.into())
-      .ok_or(Errno::EIO(String::from("devfs::lookup_path: can't find inode from dir")))
+      .ok_or(Errno::EIO(String::from("devfs::lookup_path: can't find inode from dir").into()))
+  }
+
+  /// Devices take no blocks, only inodes are counted
+  fn statfs(&mut self, _pathname: &str) -> Result<FilesystemStat, Errno> {
+    Ok(FilesystemStat {
+      inodes_count: self.inodes.len() as AddressSize,
+      free_inodes_count: self.inodes.iter().filter(|inode| inode.mode.free() == 1).count() as AddressSize,
+      ..FilesystemStat::default()
+    })
+  }
+
+  fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+    self.clock = clock;
   }
 
 fn name(&self) -> String {
@@ -280,6 +653,60 @@ fn name(&self) -> String {
 fn as_any(&mut self) -> &mut dyn Any {
     self
   }
+
+  fn as_any_ref(&self) -> &dyn Any {
+    self
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::eunix::clock::FixedClock;
+  use crate::eunix::rng::SeededRng;
+
+  /// Devfs with `ram0` of `size` bytes, besides `/dev/tty` and `/dev/random`
+  fn devfs_with_ram_disk(size: u64) -> DeviceFilesystem {
+    let device_table = KernelDeviceTable { devices: Vec::new(), ram_disks: vec![size] };
+    let mut devfs = DeviceFilesystem::new(&device_table, Arc::new(SeededRng::new(42)));
+    devfs.set_clock(Arc::new(FixedClock { epoch: 1000 }));
+
+    devfs
+  }
+
+  #[test]
+  fn stat_has_size_of_device() {
+    let mut devfs = devfs_with_ram_disk(64 * 1024);
+
+    let stat = devfs.stat("/ram0").unwrap();
+    assert_eq!((stat.size, stat.block_size), (64 * 1024, DEVICE_BLOCK_SIZE));
+    assert_eq!(stat.mode.file_type(), FileModeType::Block as u8);
+    // Character devices have no size
+    let stat = devfs.stat("/random").unwrap();
+    assert_eq!((stat.size, stat.block_size), (0, 0));
+  }
+
+  #[test]
+  fn mode_and_owners_of_devices_can_be_changed() {
+    let mut devfs = devfs_with_ram_disk(4096);
+
+    let mode = devfs.stat("/ram0").unwrap().mode;
+    devfs.change_mode("/ram0", FileMode::zero().with_user(0o6).with_group(0o6)).unwrap();
+    devfs.change_owners("/ram0", 1000, 100).unwrap();
+    devfs.change_times("/tty", Times { atime: 1, mtime: 2, ctime: 3, btime: 4 }).unwrap();
+
+    let stat = devfs.stat("/ram0").unwrap();
+    // Type of device stays
+    assert_eq!(stat.mode, FileMode::zero().with_user(0o6).with_group(0o6).with_file_type(mode.file_type()));
+    assert_eq!((stat.uid, stat.gid, stat.ctime), (1000, 100, 1000));
+    let stat = devfs.stat("/tty").unwrap();
+    assert_eq!((stat.atime, stat.mtime, stat.ctime, stat.btime), (1, 2, 3, 4));
+
+    // They are kept when devices are scanned again
+    devfs.rescan();
+    assert_eq!((devfs.stat("/ram0").unwrap().uid, devfs.stat("/tty").unwrap().atime), (1000, 1));
+    assert!(matches!(devfs.change_mode("/nope", mode), Err(Errno::ENOENT(_))));
+  }
 }
 
 // impl DeviceFilesystem {
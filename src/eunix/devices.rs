@@ -0,0 +1,1323 @@
+use std::any::Any;
+use std::sync::{Arc, Mutex, RwLock};
+use std::collections::{BTreeMap, VecDeque};
+use std::fmt;
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::net::UdpSocket;
+
+use crate::machine::{MachineDevice, NetBackend, VirtualDeviceType};
+
+use super::clock::Clock;
+use super::console::{self, ConsoleBackend};
+use super::devfs::DeviceFilesystem;
+use super::fs::{AddressSize, DeviceNumber};
+use super::kernel::{Errno, ErrnoContext, KernelDeviceTable};
+use super::rng::Rng;
+
+/// Major number of SCSI disk devices (`sda`, `sdb`, ...)
+pub const SD_MAJOR: u16 = 8;
+/// Minor numbers reserved for each disk (for partitions)
+pub const SD_MINORS_PER_DISK: u16 = 16;
+/// Major number of virtual console devices (`tty1`, `tty2`, ...)
+pub const TTY_MAJOR: u16 = 4;
+/// Major number of serial ports (`ttyS0`, `ttyS1`, ...), same as TTYs
+pub const SERIAL_MAJOR: u16 = 4;
+/// Minor number of `ttyS0`
+pub const SERIAL_FIRST_MINOR: u16 = 64;
+/// Name of `/dev/tty` - controlling terminal of the caller
+pub const CONTROLLING_TTY_NAME: &'static str = "tty";
+/// Device number of `/dev/tty`
+pub const CONTROLLING_TTY_RDEV: DeviceNumber = DeviceNumber::new(5, 0);
+/// Major number of loop devices (`loop0`, `loop1`, ...)
+pub const LOOP_MAJOR: u16 = 7;
+/// Max number of loop devices attached at once
+pub const MAX_LOOP_DEVICES: u16 = 8;
+/// Major number of RAM disks (`ram0`, `ram1`, ...)
+pub const RAM_MAJOR: u16 = 1;
+/// Max number of RAM disks
+pub const MAX_RAM_DISKS: u16 = 16;
+/// Major number of network interfaces (`eth0`, `eth1`, ...). Linux has
+/// no device files for them, here they are character devices
+pub const NET_MAJOR: u16 = 70;
+/// Major number of `/dev/random` and `/dev/urandom`. Linux has them
+/// at 1, which RAM disks have here, so they are misc devices
+pub const RANDOM_MAJOR: u16 = 10;
+/// Minor number of `/dev/random`, `/dev/urandom` is the next one
+pub const RANDOM_MINOR: u16 = 8;
+/// Most bytes one read of `/dev/random` returns
+pub const MAX_RANDOM_READ: AddressSize = 256;
+/// Size of pages that `BufferedStorage` caches reads of storage in
+pub const BUFFERED_PAGE_SIZE: u64 = 4096;
+/// Most pages that `BufferedStorage` keeps, 4 MiB of them
+pub const BUFFERED_PAGES: usize = 1024;
+/// Largest frame a network interface sends or receives, like Ethernet one
+pub const MAX_FRAME_SIZE: usize = 1514;
+
+/// Line discipline of a TTY device
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TTYMode {
+  /// Line-buffered: reads return one whole line (with trailing newline),
+  /// host terminal echoes input
+  Canonical,
+  /// Unbuffered: reads return bytes as soon as they are typed,
+  /// without echo
+  Raw,
+}
+
+impl Default for TTYMode {
+  fn default() -> Self {
+    TTYMode::Canonical
+  }
+}
+
+/// Byte-addressable backing storage of a block device
+pub trait BlockStorage: Read + Write + Seek + fmt::Debug + Send + Sync {
+  /// Size of storage in bytes
+  fn size(&self) -> u64;
+}
+
+/// Window `[offset, offset + size)` of another storage, seen as
+/// a whole device: reads and writes can't go past its bounds
+#[derive(Debug)]
+pub struct StorageRegion<S> {
+  storage: S,
+  offset: u64,
+  size: u64,
+  position: u64,
+  /// Writes fail
+  read_only: bool,
+}
+
+/// Window of a file on host
+pub type FileRegion = StorageRegion<std::fs::File>;
+
+impl FileRegion {
+  /// Open whole file on host
+  pub fn open(realpath: &str) -> Result<Self, Errno> {
+    Self::open_region(realpath, 0, None)
+  }
+
+  /// Open whole file on host, which is not written to
+  pub fn open_read_only(realpath: &str) -> Result<Self, Errno> {
+    Self::open_file(realpath, 0, None, true)
+  }
+
+  /// Open `size` bytes of file on host starting at `offset`,
+  /// `None` size means "up to the end of file"
+  pub fn open_region(realpath: &str, offset: u64, size: Option<u64>) -> Result<Self, Errno> {
+    Self::open_file(realpath, offset, size, false)
+  }
+
+  fn open_file(realpath: &str, offset: u64, size: Option<u64>, read_only: bool) -> Result<Self, Errno> {
+    let file = std::fs::OpenOptions::new()
+      .read(true)
+      .write(!read_only)
+      .open(realpath)
+      .with_context(|| format!("devfs: cannot open {realpath}"))?;
+    let file_size = file
+      .metadata()
+      .with_context(|| format!("devfs: cannot get size of {realpath}"))?
+      .len();
+
+    // Guard for region not fitting in file
+    let size = size.unwrap_or(file_size.saturating_sub(offset));
+    if offset + size > file_size {
+      return Err(Errno::EINVAL(format!("devfs: region {offset}+{size} is out of bounds of {realpath}").into()));
+    }
+
+    Ok(Self { storage: file, offset, size, position: 0, read_only })
+  }
+}
+
+impl StorageRegion<Box<dyn BlockStorage>> {
+  /// Window of `size` bytes of `storage` starting at `offset`
+  pub fn new(storage: Box<dyn BlockStorage>, offset: u64, size: u64) -> Result<Self, Errno> {
+    // Guard for region not fitting in storage
+    if offset + size > storage.size() {
+      return Err(Errno::EINVAL(format!("devfs: region {offset}+{size} is out of bounds of device").into()));
+    }
+
+    Ok(Self { storage, offset, size, position: 0, read_only: false })
+  }
+}
+
+impl<S> StorageRegion<S> {
+  /// Bytes left from current position to the end of region
+  fn remaining(&self) -> u64 {
+    self.size.saturating_sub(self.position)
+  }
+}
+
+impl<S: Read + Seek> Read for StorageRegion<S> {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let count = (buf.len() as u64).min(self.remaining()) as usize;
+    self.storage.seek(SeekFrom::Start(self.offset + self.position))?;
+    let count = self.storage.read(&mut buf[..count])?;
+    self.position += count as u64;
+    Ok(count)
+  }
+}
+
+impl<S: Write + Seek> Write for StorageRegion<S> {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    // Guard for read-only storage
+    if self.read_only {
+      return Err(io::Error::new(io::ErrorKind::PermissionDenied, "storage is read-only"));
+    }
+
+    let count = (buf.len() as u64).min(self.remaining()) as usize;
+    self.storage.seek(SeekFrom::Start(self.offset + self.position))?;
+    let count = self.storage.write(&buf[..count])?;
+    self.position += count as u64;
+    Ok(count)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.storage.flush()
+  }
+}
+
+impl<S> Seek for StorageRegion<S> {
+  fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+    let position = match pos {
+      SeekFrom::Start(position) => Some(position),
+      SeekFrom::End(delta) => self.size.checked_add_signed(delta),
+      SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+    }
+    .ok_or(io::Error::new(io::ErrorKind::InvalidInput, "seek before start of region"))?;
+
+    self.position = position;
+    Ok(position)
+  }
+}
+
+impl<S: Read + Write + Seek + fmt::Debug + Send + Sync> BlockStorage for StorageRegion<S> {
+  fn size(&self) -> u64 {
+    self.size
+  }
+}
+
+/// Buffer in memory, shared by everyone who opened the device.
+/// Can't be resized through reads and writes
+#[derive(Debug)]
+pub struct MemoryStorage {
+  buffer: Arc<RwLock<Vec<u8>>>,
+  position: u64,
+}
+
+impl MemoryStorage {
+  pub fn new(buffer: Arc<RwLock<Vec<u8>>>) -> Self {
+    Self { buffer, position: 0 }
+  }
+}
+
+impl Read for MemoryStorage {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let buffer = self.buffer.read().unwrap();
+    let start = (self.position as usize).min(buffer.len());
+    let count = buf.len().min(buffer.len() - start);
+    buf[..count].copy_from_slice(&buffer[start..start + count]);
+    self.position += count as u64;
+    Ok(count)
+  }
+}
+
+impl Write for MemoryStorage {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    let mut buffer = self.buffer.write().unwrap();
+    let start = (self.position as usize).min(buffer.len());
+    let count = buf.len().min(buffer.len() - start);
+    buffer[start..start + count].copy_from_slice(&buf[..count]);
+    self.position += count as u64;
+    Ok(count)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    Ok(())
+  }
+}
+
+impl Seek for MemoryStorage {
+  fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+    let position = match pos {
+      SeekFrom::Start(position) => Some(position),
+      SeekFrom::End(delta) => (self.buffer.read().unwrap().len() as u64).checked_add_signed(delta),
+      SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+    }
+    .ok_or(io::Error::new(io::ErrorKind::InvalidInput, "seek before start of buffer"))?;
+
+    self.position = position;
+    Ok(position)
+  }
+}
+
+impl BlockStorage for MemoryStorage {
+  fn size(&self) -> u64 {
+    self.buffer.read().unwrap().len() as u64
+  }
+}
+
+/// Cache of pages of another storage. Writes stay in cached pages
+/// until `flush` or until their page is evicted, so a block written
+/// many times goes to storage once. Saves filesystems a host seek
+/// and read for most accesses to a block or inode. When cache is full,
+/// the least recently used page is evicted. Machine killed without
+/// unmount or `sync` loses pages that are not flushed, which journal
+/// of e5fs flushes before it relies on them
+#[derive(Debug)]
+pub struct BufferedStorage {
+  storage: Box<dyn BlockStorage>,
+  /// Page index -> page
+  pages: BTreeMap<u64, BufferedPage>,
+  /// When page was last used -> its index, least recent first
+  recency: BTreeMap<u64, u64>,
+  /// Incremented on every use of a page
+  uses: u64,
+  position: u64,
+}
+
+#[derive(Debug)]
+struct BufferedPage {
+  contents: Vec<u8>,
+  /// Key of page in `recency`
+  last_used: u64,
+  /// Written since it was read or flushed
+  dirty: bool,
+}
+
+impl BufferedStorage {
+  pub fn new(storage: Box<dyn BlockStorage>) -> Self {
+    Self {
+      storage,
+      pages: BTreeMap::new(),
+      recency: BTreeMap::new(),
+      uses: 0,
+      position: 0,
+    }
+  }
+
+  /// Count of pages that are written and not flushed yet
+  pub fn dirty_pages_count(&self) -> usize {
+    self.pages.values().filter(|page| page.dirty).count()
+  }
+
+  /// Returns: page at `index`, read from storage if it is not cached.
+  /// With `overwritten`, page that is not cached is not read, because
+  /// all of it is about to be written
+  fn page(&mut self, index: u64, overwritten: bool) -> io::Result<&mut BufferedPage> {
+    if !self.pages.contains_key(&index) {
+      if self.pages.len() >= BUFFERED_PAGES {
+        self.evict()?;
+      }
+
+      let start = index * BUFFERED_PAGE_SIZE;
+      let mut contents = vec![0u8; BUFFERED_PAGE_SIZE.min(self.storage.size() - start) as usize];
+      if !overwritten {
+        self.storage.seek(SeekFrom::Start(start))?;
+        self.storage.read_exact(&mut contents)?;
+      }
+      self.pages.insert(index, BufferedPage { contents, last_used: 0, dirty: false });
+    }
+
+    self.uses += 1;
+    let page = self.pages.get_mut(&index).expect("we know that page was just cached");
+    self.recency.remove(&page.last_used);
+    self.recency.insert(self.uses, index);
+    page.last_used = self.uses;
+
+    Ok(page)
+  }
+
+  /// Drop the least recently used page, writing it to storage if it is dirty
+  fn evict(&mut self) -> io::Result<()> {
+    let Some((&last_used, &index)) = self.recency.first_key_value() else {
+      return Ok(());
+    };
+
+    self.write_back(index)?;
+    self.recency.remove(&last_used);
+    self.pages.remove(&index);
+
+    Ok(())
+  }
+
+  /// Write page at `index` to storage if it is dirty
+  fn write_back(&mut self, index: u64) -> io::Result<()> {
+    let Some(page) = self.pages.get_mut(&index).filter(|page| page.dirty) else {
+      return Ok(());
+    };
+
+    self.storage.seek(SeekFrom::Start(index * BUFFERED_PAGE_SIZE))?;
+    self.storage.write_all(&page.contents)?;
+    page.dirty = false;
+
+    Ok(())
+  }
+
+  /// Returns: page index and offset in it of current position,
+  /// and count of bytes of `wanted` that are in that page
+  fn span(&self, wanted: usize) -> (u64, usize, usize) {
+    let index = self.position / BUFFERED_PAGE_SIZE;
+    let offset = (self.position % BUFFERED_PAGE_SIZE) as usize;
+    let left = self.storage.size().saturating_sub(self.position);
+    let count = (wanted as u64).min(BUFFERED_PAGE_SIZE - offset as u64).min(left) as usize;
+
+    (index, offset, count)
+  }
+}
+
+impl Read for BufferedStorage {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let (index, offset, count) = self.span(buf.len());
+    if count == 0 {
+      return Ok(0);
+    }
+
+    let page = self.page(index, false)?;
+    buf[..count].copy_from_slice(&page.contents[offset..offset + count]);
+    self.position += count as u64;
+    Ok(count)
+  }
+}
+
+impl Write for BufferedStorage {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    let (index, offset, count) = self.span(buf.len());
+    if count == 0 {
+      return Ok(0);
+    }
+
+    let page_size = BUFFERED_PAGE_SIZE.min(self.storage.size() - index * BUFFERED_PAGE_SIZE) as usize;
+    let page = self.page(index, offset == 0 && count == page_size)?;
+    page.contents[offset..offset + count].copy_from_slice(&buf[..count]);
+    page.dirty = true;
+    self.position += count as u64;
+    Ok(count)
+  }
+
+  /// Write dirty pages to storage, in order of their addresses
+  fn flush(&mut self) -> io::Result<()> {
+    let indices = self.pages.keys().copied().collect::<Vec<_>>();
+    for index in indices {
+      self.write_back(index)?;
+    }
+
+    self.storage.flush()
+  }
+}
+
+impl Drop for BufferedStorage {
+  fn drop(&mut self) {
+    if let Err(error) = self.flush() {
+      tracing::error!("cannot flush buffered storage: {error}");
+    }
+  }
+}
+
+impl Seek for BufferedStorage {
+  fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+    let position = match pos {
+      SeekFrom::Start(position) => Some(position),
+      SeekFrom::End(delta) => self.storage.size().checked_add_signed(delta),
+      SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+    }
+    .ok_or(io::Error::new(io::ErrorKind::InvalidInput, "seek before start of storage"))?;
+
+    self.position = position;
+    Ok(position)
+  }
+}
+
+impl BlockStorage for BufferedStorage {
+  fn size(&self) -> u64 {
+    self.storage.size()
+  }
+}
+
+/// Handle of `BufferedStorage` that everything which opens the same
+/// device shares, so that nothing reads pages that another handle has
+/// written and not flushed as they were before. Every handle has a
+/// position of its own, and flushes the cache when it is dropped
+#[derive(Debug)]
+pub struct SharedStorage {
+  cache: Arc<Mutex<BufferedStorage>>,
+  position: u64,
+}
+
+impl SharedStorage {
+  pub fn new(storage: Box<dyn BlockStorage>) -> Self {
+    Self { cache: Arc::new(Mutex::new(BufferedStorage::new(storage))), position: 0 }
+  }
+
+  /// Another handle of the same cache, at the start of storage
+  pub fn handle(&self) -> Self {
+    Self { cache: self.cache.clone(), position: 0 }
+  }
+}
+
+impl Read for SharedStorage {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    let mut cache = self.cache.lock().unwrap();
+    cache.seek(SeekFrom::Start(self.position))?;
+    let count = cache.read(buf)?;
+    self.position += count as u64;
+    Ok(count)
+  }
+}
+
+impl Write for SharedStorage {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    let mut cache = self.cache.lock().unwrap();
+    cache.seek(SeekFrom::Start(self.position))?;
+    let count = cache.write(buf)?;
+    self.position += count as u64;
+    Ok(count)
+  }
+
+  fn flush(&mut self) -> io::Result<()> {
+    self.cache.lock().unwrap().flush()
+  }
+}
+
+impl Drop for SharedStorage {
+  fn drop(&mut self) {
+    if let Err(error) = self.flush() {
+      tracing::error!("cannot flush shared storage: {error}");
+    }
+  }
+}
+
+impl Seek for SharedStorage {
+  fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+    let position = match pos {
+      SeekFrom::Start(position) => Some(position),
+      SeekFrom::End(delta) => self.size().checked_add_signed(delta),
+      SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+    }
+    .ok_or(io::Error::new(io::ErrorKind::InvalidInput, "seek before start of storage"))?;
+
+    self.position = position;
+    Ok(position)
+  }
+}
+
+impl BlockStorage for SharedStorage {
+  fn size(&self) -> u64 {
+    self.cache.lock().unwrap().size()
+  }
+}
+
+/// Driver of a random-access device that filesystems live on
+pub trait BlockDevice: fmt::Debug + Send + Sync {
+  /// Open storage with contents of the device
+  fn open(&self) -> Result<Box<dyn BlockStorage>, Errno>;
+
+  /// Size of device in bytes, `0` if it can't be opened
+  fn size(&self) -> u64 {
+    self.open()
+      .map(|storage| storage.size())
+      .unwrap_or(0)
+  }
+
+  /// Minor numbers after the device's own that are reserved for its
+  /// partitions, `0` if partition table of the device is not read
+  fn partition_minors(&self) -> u16 {
+    0
+  }
+
+  fn as_any(&self) -> &dyn Any;
+}
+
+/// Driver of a device that is read and written as a stream of bytes
+pub trait CharDevice: fmt::Debug + Send + Sync {
+  /// Read at most `count` bytes
+  fn read(&mut self, count: AddressSize) -> Result<Vec<u8>, Errno>;
+
+  fn write(&mut self, data: &[u8]) -> Result<(), Errno>;
+
+  /// Whether device is a terminal: it can be made controlling
+  /// and has a line discipline
+  fn is_tty(&self) -> bool {
+    false
+  }
+
+  /// Switch line discipline of the terminal
+  fn set_tty_mode(&mut self, _mode: TTYMode) -> Result<(), Errno> {
+    Err(Errno::ENOTTY(String::from("devfs: device is not a tty").into()))
+  }
+}
+
+/// Driver registered in devfs under some name,
+/// see `DeviceFilesystem::register_device`
+#[derive(Debug, Clone)]
+pub enum DeviceDriver {
+  Block(Arc<dyn BlockDevice>),
+  Char(Arc<RwLock<dyn CharDevice>>),
+}
+
+/// Disk image on host, partition table of which is read by devfs.
+/// Everything that opens it, its partitions included, goes through
+/// one cache of it
+#[derive(Debug)]
+pub struct HostDisk {
+  realpath: String,
+  read_only: bool,
+  /// Made on the first `open`
+  cache: Mutex<Option<SharedStorage>>,
+}
+
+impl HostDisk {
+  pub fn new(realpath: &str, read_only: bool) -> Self {
+    Self { realpath: realpath.to_owned(), read_only, cache: Mutex::new(None) }
+  }
+}
+
+impl BlockDevice for HostDisk {
+  fn open(&self) -> Result<Box<dyn BlockStorage>, Errno> {
+    let mut cache = self.cache.lock().unwrap();
+    if let Some(cache) = &*cache {
+      return Ok(Box::new(cache.handle()));
+    }
+
+    let storage: Box<dyn BlockStorage> = match self.read_only {
+      true => Box::new(FileRegion::open_read_only(&self.realpath)?),
+      false => Box::new(FileRegion::open(&self.realpath)?),
+    };
+    let shared = SharedStorage::new(storage);
+    let handle = shared.handle();
+    *cache = Some(shared);
+
+    Ok(Box::new(handle))
+  }
+
+  fn partition_minors(&self) -> u16 {
+    SD_MINORS_PER_DISK - 1
+  }
+
+  fn as_any(&self) -> &dyn Any {
+    self
+  }
+}
+
+/// Partition of another block device
+#[derive(Debug)]
+pub struct Partition {
+  disk: Arc<dyn BlockDevice>,
+  offset: u64,
+  size: u64,
+}
+
+impl Partition {
+  /// `size` bytes of `disk` starting at `offset`
+  pub fn new(disk: Arc<dyn BlockDevice>, offset: u64, size: u64) -> Self {
+    Self { disk, offset, size }
+  }
+}
+
+impl BlockDevice for Partition {
+  fn open(&self) -> Result<Box<dyn BlockStorage>, Errno> {
+    Ok(Box::new(StorageRegion::new(self.disk.open()?, self.offset, self.size)?))
+  }
+
+  fn as_any(&self) -> &dyn Any {
+    self
+  }
+}
+
+/// Zero-filled buffer in memory, contents are lost on shutdown
+#[derive(Debug)]
+pub struct RamDisk {
+  buffer: Arc<RwLock<Vec<u8>>>,
+}
+
+impl RamDisk {
+  pub fn new(size: u64) -> Self {
+    Self { buffer: Arc::new(RwLock::new(vec![0u8; size as usize])) }
+  }
+
+  /// Create new RAM disk of `size` bytes as the first free `ramN` in `devfs`.
+  /// Returns: name of the device
+  pub fn create(devfs: &mut DeviceFilesystem, size: u64) -> Result<String, Errno> {
+    let index = (0..MAX_RAM_DISKS)
+      .find(|index| devfs.device_driver(&format!("ram{index}")).is_err())
+      .ok_or(Errno::ENXIO(String::from("devfs: no free ram device").into()))?;
+    let name = format!("ram{index}");
+
+    devfs.register_device(&name, DeviceNumber::new(RAM_MAJOR, index), DeviceDriver::Block(Arc::new(RamDisk::new(size))))?;
+
+    Ok(name)
+  }
+}
+
+impl BlockDevice for RamDisk {
+  fn open(&self) -> Result<Box<dyn BlockStorage>, Errno> {
+    Ok(Box::new(MemoryStorage::new(self.buffer.clone())))
+  }
+
+  fn as_any(&self) -> &dyn Any {
+    self
+  }
+}
+
+/// Regular eunix file attached as a block device
+#[derive(Debug)]
+pub struct LoopDevice {
+  /// Pathname of attached file, contents are written back there on detach
+  backing_pathname: String,
+  buffer: Arc<RwLock<Vec<u8>>>,
+}
+
+impl LoopDevice {
+  /// Attach contents of eunix file `backing_pathname` as the first
+  /// free `loopN` device in `devfs`.
+  /// Returns: name of the device
+  pub fn attach(devfs: &mut DeviceFilesystem, backing_pathname: &str, data: Vec<u8>) -> Result<String, Errno> {
+    let index = (0..MAX_LOOP_DEVICES)
+      .find(|index| devfs.device_driver(&format!("loop{index}")).is_err())
+      .ok_or(Errno::ENXIO(String::from("devfs: no free loop device").into()))?;
+    let name = format!("loop{index}");
+
+    let loop_device = LoopDevice {
+      backing_pathname: backing_pathname.to_owned(),
+      buffer: Arc::new(RwLock::new(data)),
+    };
+    devfs.register_device(&name, DeviceNumber::new(LOOP_MAJOR, index), DeviceDriver::Block(Arc::new(loop_device)))?;
+
+    Ok(name)
+  }
+
+  /// Detach loop device named `name` from `devfs`.
+  /// Returns: `(backing_pathname, data)` to write back
+  pub fn detach(devfs: &mut DeviceFilesystem, name: &str) -> Result<(String, Vec<u8>), Errno> {
+    let (backing_pathname, buffer) = match devfs.device_driver(name)? {
+      DeviceDriver::Block(driver) => driver
+        .as_any()
+        .downcast_ref::<LoopDevice>()
+        .map(|loop_device| (loop_device.backing_pathname.to_owned(), loop_device.buffer.clone())),
+      DeviceDriver::Char(_) => None,
+    }
+    .ok_or(Errno::EINVAL(format!("devfs: {name} is not a loop device").into()))?;
+
+    // Guard for device still being open, e.g. mounted.
+    // One reference is ours, one is the driver's
+    if Arc::strong_count(&buffer) > 2 {
+      return Err(Errno::EBUSY(format!("devfs: {name} is in use").into()));
+    }
+
+    devfs.unregister_device(name)?;
+    let data = std::mem::take(&mut *buffer.write().unwrap());
+
+    Ok((backing_pathname, data))
+  }
+
+  /// Returns: `(name, backing_pathname)` of every loop device in `devfs`
+  pub fn list(devfs: &DeviceFilesystem) -> Vec<(String, String)> {
+    devfs
+      .drivers()
+      .into_iter()
+      .filter_map(|(name, driver)| match driver {
+        DeviceDriver::Block(driver) => driver
+          .as_any()
+          .downcast_ref::<LoopDevice>()
+          .map(|loop_device| (name, loop_device.backing_pathname.to_owned())),
+        DeviceDriver::Char(_) => None,
+      })
+      .collect()
+  }
+}
+
+impl BlockDevice for LoopDevice {
+  fn open(&self) -> Result<Box<dyn BlockStorage>, Errno> {
+    Ok(Box::new(MemoryStorage::new(self.buffer.clone())))
+  }
+
+  fn as_any(&self) -> &dyn Any {
+    self
+  }
+}
+
+/// Terminal shown on console backend, terminal of host by default
+#[derive(Debug)]
+pub struct HostTTY {
+  realpath: String,
+  mode: TTYMode,
+  console: Arc<RwLock<dyn ConsoleBackend>>,
+}
+
+impl HostTTY {
+  pub fn new(realpath: &str, console: Arc<RwLock<dyn ConsoleBackend>>) -> Self {
+    Self {
+      realpath: realpath.to_owned(),
+      mode: TTYMode::default(),
+      console,
+    }
+  }
+}
+
+impl CharDevice for HostTTY {
+  fn read(&mut self, count: AddressSize) -> Result<Vec<u8>, Errno> {
+    match self.mode {
+      TTYMode::Canonical => self.console
+        .write().unwrap()
+        .read_line()
+        .with_context(|| format!("devfs: cannot read line from {}", self.realpath)),
+      // Raw reads return at most one byte - whatever was typed first
+      TTYMode::Raw if count == 0 => Ok(Vec::new()),
+      TTYMode::Raw => self.console
+        .write().unwrap()
+        .read_byte()
+        .with_context(|| format!("devfs: cannot read byte from {}", self.realpath)),
+    }
+  }
+
+  fn write(&mut self, data: &[u8]) -> Result<(), Errno> {
+    self.console
+      .write().unwrap()
+      .write(data)
+      .with_context(|| format!("devfs: cannot write to {}", self.realpath))
+  }
+
+  fn is_tty(&self) -> bool {
+    true
+  }
+
+  /// Puts the console to the corresponding mode, with `stty` for host one
+  fn set_tty_mode(&mut self, mode: TTYMode) -> Result<(), Errno> {
+    self.console
+      .write().unwrap()
+      .set_raw(mode == TTYMode::Raw)
+      .with_context(|| format!("devfs: cannot set mode of {}", self.realpath))?;
+
+    self.mode = mode;
+
+    Ok(())
+  }
+}
+
+/// Terminal typed into by a script on host: reads return its lines
+/// one by one, then end of file. Writes go to console backend, as do
+/// lines read in canonical mode, echoed like typed ones
+#[derive(Debug)]
+pub struct ScriptTTY {
+  realpath: String,
+  /// Bytes of the script that were not read yet
+  input: VecDeque<u8>,
+  mode: TTYMode,
+  console: Arc<RwLock<dyn ConsoleBackend>>,
+}
+
+impl ScriptTTY {
+  pub fn open(realpath: &str) -> Result<Self, Errno> {
+    let mut input = std::fs::read(realpath)
+      .with_context(|| format!("devfs: cannot read script {realpath}"))?;
+    // Last line counts even without newline
+    if input.last().map_or(false, |&byte| byte != b'\n') {
+      input.push(b'\n');
+    }
+
+    Ok(Self {
+      realpath: realpath.to_owned(),
+      input: input.into(),
+      mode: TTYMode::default(),
+      console: console::host_console(),
+    })
+  }
+
+  /// Show terminal on `console` instead of terminal of host
+  pub fn with_console(mut self, console: Arc<RwLock<dyn ConsoleBackend>>) -> Self {
+    self.console = console;
+    self
+  }
+}
+
+impl CharDevice for ScriptTTY {
+  fn read(&mut self, count: AddressSize) -> Result<Vec<u8>, Errno> {
+    match self.mode {
+      TTYMode::Canonical => {
+        let line_length = self.input
+          .iter()
+          .position(|&byte| byte == b'\n')
+          .map_or(self.input.len(), |position| position + 1);
+        let line = self.input.drain(..line_length).collect::<Vec<u8>>();
+        self.write(&line)?;
+
+        Ok(line)
+      },
+      TTYMode::Raw => {
+        let count = (count as usize).min(1).min(self.input.len());
+
+        Ok(self.input.drain(..count).collect())
+      },
+    }
+  }
+
+  fn write(&mut self, data: &[u8]) -> Result<(), Errno> {
+    self.console
+      .write().unwrap()
+      .write(data)
+      .with_context(|| format!("devfs: cannot write to {}", self.realpath))
+  }
+
+  fn is_tty(&self) -> bool {
+    true
+  }
+
+  /// There is no host terminal to set up, only reads change
+  fn set_tty_mode(&mut self, mode: TTYMode) -> Result<(), Errno> {
+    self.mode = mode;
+
+    Ok(())
+  }
+}
+
+/// Terminal that logs everything going through another one to a host
+/// file. Every read and write is a line of `<unixtime> <direction> <data>`,
+/// where direction is `<` for input and `>` for output, and data is
+/// escaped, so that transcript is exactly what was sent. Time is
+/// of machine, so fixed clock gives the same transcript every run
+#[derive(Debug)]
+pub struct TranscriptTTY {
+  realpath: String,
+  tty: Arc<RwLock<dyn CharDevice>>,
+  clock: Arc<dyn Clock>,
+}
+
+impl TranscriptTTY {
+  pub fn new(realpath: &str, tty: Arc<RwLock<dyn CharDevice>>, clock: Arc<dyn Clock>) -> Self {
+    Self {
+      realpath: realpath.to_owned(),
+      tty,
+      clock,
+    }
+  }
+
+  fn log(&self, direction: char, data: &[u8]) -> Result<(), Errno> {
+    std::fs::OpenOptions::new()
+      .create(true)
+      .append(true)
+      .open(&self.realpath)
+      .and_then(|mut file| writeln!(file, "{} {direction} {}", self.clock.now(), data.escape_ascii()))
+      .with_context(|| format!("devfs: cannot write transcript to {}", self.realpath))
+  }
+}
+
+impl CharDevice for TranscriptTTY {
+  fn read(&mut self, count: AddressSize) -> Result<Vec<u8>, Errno> {
+    let data = self.tty.write().unwrap().read(count)?;
+    // End of file is not input
+    if !data.is_empty() {
+      self.log('<', &data)?;
+    }
+
+    Ok(data)
+  }
+
+  fn write(&mut self, data: &[u8]) -> Result<(), Errno> {
+    self.log('>', data)?;
+    self.tty.write().unwrap().write(data)
+  }
+
+  fn is_tty(&self) -> bool {
+    self.tty.read().unwrap().is_tty()
+  }
+
+  fn set_tty_mode(&mut self, mode: TTYMode) -> Result<(), Errno> {
+    self.tty.write().unwrap().set_tty_mode(mode)
+  }
+}
+
+/// Serial port wired to a log file on host: writes are appended
+/// to it, reads return what was logged, from the start of the file
+#[derive(Debug)]
+pub struct HostSerial {
+  realpath: String,
+  /// Offset in log file of the next byte to read
+  read_position: u64,
+}
+
+impl CharDevice for HostSerial {
+  fn read(&mut self, count: AddressSize) -> Result<Vec<u8>, Errno> {
+    // Nothing logged yet reads as end of file
+    let mut file = match std::fs::File::open(&self.realpath) {
+      Ok(file) => file,
+      Err(error) if error.kind() == io::ErrorKind::NotFound => return Ok(Vec::new()),
+      Err(_) => return Err(Errno::EIO(format!("devfs: cannot open {}", self.realpath).into())),
+    };
+
+    let mut bytes = Vec::new();
+    file
+      .seek(SeekFrom::Start(self.read_position))
+      .and_then(|_| file.take(count as u64).read_to_end(&mut bytes))
+      .with_context(|| format!("devfs: cannot read from {}", self.realpath))?;
+    self.read_position += bytes.len() as u64;
+
+    Ok(bytes)
+  }
+
+  fn write(&mut self, data: &[u8]) -> Result<(), Errno> {
+    std::fs::OpenOptions::new()
+      .create(true)
+      .append(true)
+      .open(&self.realpath)
+      .and_then(|mut file| file.write_all(data))
+      .with_context(|| format!("devfs: cannot write to {}", self.realpath))
+  }
+}
+
+/// Network interface card: every write sends one frame, every read
+/// returns one received frame, truncated to `count`, or nothing
+/// if none came yet. Reads never block
+#[derive(Debug)]
+pub struct VirtualNic {
+  backend: NetBackend,
+  /// Frames sent to loopback, that were not read back yet
+  loopback_frames: VecDeque<Vec<u8>>,
+  /// Socket of UDP backend, bound on first use
+  socket: Option<UdpSocket>,
+}
+
+impl VirtualNic {
+  pub fn new(backend: NetBackend) -> Self {
+    Self {
+      backend,
+      loopback_frames: VecDeque::new(),
+      socket: None,
+    }
+  }
+
+  fn udp_socket(&mut self) -> Result<&UdpSocket, Errno> {
+    let (bind, peer) = match self.backend {
+      NetBackend::Udp { bind, peer } => (bind, peer),
+      NetBackend::Loopback => return Err(Errno::EINVAL(String::from("devfs: loopback interface has no socket").into())),
+    };
+
+    if self.socket.is_none() {
+      let socket = UdpSocket::bind(bind)
+        .and_then(|socket| socket.connect(peer).map(|_| socket))
+        .and_then(|socket| socket.set_nonblocking(true).map(|_| socket))
+        .with_context(|| format!("devfs: cannot open udp socket {bind} -> {peer}"))?;
+      self.socket = Some(socket);
+    }
+
+    Ok(self.socket.as_ref().expect("we know that socket was just opened"))
+  }
+}
+
+impl CharDevice for VirtualNic {
+  fn read(&mut self, count: AddressSize) -> Result<Vec<u8>, Errno> {
+    let mut frame = match self.backend {
+      NetBackend::Loopback => self.loopback_frames.pop_front().unwrap_or_default(),
+      NetBackend::Udp { .. } => {
+        let mut buffer = vec![0u8; MAX_FRAME_SIZE];
+        match self.udp_socket()?.recv(&mut buffer) {
+          Ok(size) => {
+            buffer.truncate(size);
+            buffer
+          },
+          Err(error) if error.kind() == io::ErrorKind::WouldBlock => Vec::new(),
+          Err(error) => return Err(Errno::EIO(format!("devfs: cannot receive frame: {error}").into())),
+        }
+      },
+    };
+    frame.truncate(count as usize);
+
+    Ok(frame)
+  }
+
+  fn write(&mut self, data: &[u8]) -> Result<(), Errno> {
+    // Guard for frames that don't fit
+    if data.len() > MAX_FRAME_SIZE {
+      return Err(Errno::EINVAL(format!("devfs: frame of {} bytes is larger than {MAX_FRAME_SIZE}", data.len()).into()));
+    }
+
+    match self.backend {
+      NetBackend::Loopback => {
+        self.loopback_frames.push_back(data.to_owned());
+        Ok(())
+      },
+      NetBackend::Udp { .. } => self.udp_socket()?
+        .send(data)
+        .map(|_| ())
+        .with_context(|| "devfs: cannot send frame"),
+    }
+  }
+}
+
+/// `/dev/tty`, resolved by VFS to controlling terminal of the caller.
+/// Reaching the driver itself means there is none
+#[derive(Debug)]
+pub struct ControllingTTY;
+
+impl CharDevice for ControllingTTY {
+  fn read(&mut self, _count: AddressSize) -> Result<Vec<u8>, Errno> {
+    Err(Errno::ENXIO(String::from("devfs: no controlling terminal").into()))
+  }
+
+  fn write(&mut self, _data: &[u8]) -> Result<(), Errno> {
+    Err(Errno::ENXIO(String::from("devfs: no controlling terminal").into()))
+  }
+
+  fn is_tty(&self) -> bool {
+    true
+  }
+
+  fn set_tty_mode(&mut self, _mode: TTYMode) -> Result<(), Errno> {
+    Err(Errno::ENXIO(String::from("devfs: no controlling terminal").into()))
+  }
+}
+
+/// `/dev/random` and `/dev/urandom`: bytes from randomness of kernel,
+/// writes are ignored like on Linux
+#[derive(Debug)]
+pub struct RandomDevice {
+  rng: Arc<dyn Rng>,
+}
+
+impl RandomDevice {
+  pub fn new(rng: Arc<dyn Rng>) -> Self {
+    Self { rng }
+  }
+}
+
+impl CharDevice for RandomDevice {
+  fn read(&mut self, count: AddressSize) -> Result<Vec<u8>, Errno> {
+    Ok(self.rng.bytes(count.min(MAX_RANDOM_READ) as usize))
+  }
+
+  fn write(&mut self, _data: &[u8]) -> Result<(), Errno> {
+    Ok(())
+  }
+}
+
+/// Name of `index`th network interface in devfs and sysfs
+pub fn net_device_name(index: u16) -> String {
+  format!("eth{index}")
+}
+
+/// Name of `index`th disk of machine in devfs and sysfs: `sda`, `sdb`...
+pub fn disk_name(index: u16) -> String {
+  format!("sd{}", char::from(b'a' + index as u8))
+}
+
+/// Name of `index`th terminal of machine in devfs and sysfs, from `tty1`
+pub fn tty_device_name(index: u16) -> String {
+  format!("tty{}", index + 1)
+}
+
+/// Name of `index`th serial port of machine in devfs and sysfs, from `ttyS0`
+pub fn serial_device_name(index: u16) -> String {
+  format!("ttyS{index}")
+}
+
+/// Returns: `(name, rdev, driver)` for every device in the table,
+/// in table order, then RAM disks, then `/dev/tty`, `/dev/random`
+/// and `/dev/urandom` reading from `rng`
+/// Like:
+/// ("sda", 8:0, HostDisk("/home/user/disk.enxvd"))
+/// ("tty1", 4:1, HostTTY("/dev/stdin"))
+/// ("ttyS0", 4:64, HostSerial("/home/user/serial0.log"))
+/// ("eth0", 70:0, VirtualNic(Loopback))
+/// ("ram0", 1:0, RamDisk)
+/// ("random", 10:8, RandomDevice)
+pub fn from_device_table(device_table: &KernelDeviceTable, rng: Arc<dyn Rng>) -> Vec<(String, DeviceNumber, DeviceDriver)> {
+  let mut tty_devices_count = 0;
+  let mut block_devices_count = 0;
+  let mut serial_devices_count = 0;
+  let mut net_devices_count = 0;
+
+  let ram_disks = device_table.ram_disks
+    .iter()
+    .zip(0..MAX_RAM_DISKS)
+    .map(|(&size, index)| (
+      format!("ram{index}"),
+      DeviceNumber::new(RAM_MAJOR, index),
+      DeviceDriver::Block(Arc::new(RamDisk::new(size))),
+    ));
+
+  device_table.devices
+    .iter()
+    .map(|(MachineDevice { realpath, r#type, read_only, backend }, _)| match r#type {
+      VirtualDeviceType::BlockDevice => {
+        block_devices_count += 1;
+        (
+          disk_name(block_devices_count as u16 - 1),
+          DeviceNumber::new(SD_MAJOR, (block_devices_count as u16 - 1) * SD_MINORS_PER_DISK),
+          DeviceDriver::Block(Arc::new(HostDisk::new(realpath, *read_only))),
+        )
+      },
+      VirtualDeviceType::TTYDevice => {
+        tty_devices_count += 1;
+        (
+          tty_device_name(tty_devices_count - 1),
+          DeviceNumber::new(TTY_MAJOR, tty_devices_count),
+          DeviceDriver::Char(Arc::new(RwLock::new(HostTTY::new(realpath, console::host_console())))),
+        )
+      },
+      VirtualDeviceType::SerialDevice => {
+        serial_devices_count += 1;
+        (
+          serial_device_name(serial_devices_count - 1),
+          DeviceNumber::new(SERIAL_MAJOR, SERIAL_FIRST_MINOR + serial_devices_count - 1),
+          DeviceDriver::Char(Arc::new(RwLock::new(HostSerial { realpath: realpath.to_owned(), read_position: 0 }))),
+        )
+      },
+      VirtualDeviceType::NetDevice => {
+        net_devices_count += 1;
+        (
+          net_device_name(net_devices_count - 1),
+          DeviceNumber::new(NET_MAJOR, net_devices_count - 1),
+          DeviceDriver::Char(Arc::new(RwLock::new(VirtualNic::new(backend.clone().unwrap_or(NetBackend::Loopback))))),
+        )
+      },
+    })
+    .chain(ram_disks)
+    .chain([(
+      String::from(CONTROLLING_TTY_NAME),
+      CONTROLLING_TTY_RDEV,
+      DeviceDriver::Char(Arc::new(RwLock::new(ControllingTTY))),
+    )])
+    .chain(["random", "urandom"].into_iter().zip(RANDOM_MINOR..).map(|(name, minor)| (
+      String::from(name),
+      DeviceNumber::new(RANDOM_MAJOR, minor),
+      DeviceDriver::Char(Arc::new(RwLock::new(RandomDevice::new(rng.clone())))),
+    )))
+    .collect()
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::eunix::clock::FixedClock;
+  use crate::eunix::rng::SeededRng;
+  use crate::util::mktemp;
+
+  #[test]
+  fn loopback_nic_returns_sent_frames() {
+    let mut nic = VirtualNic::new(NetBackend::Loopback);
+
+    nic.write(b"first").unwrap();
+    nic.write(b"second").unwrap();
+
+    assert_eq!(nic.read(AddressSize::MAX).unwrap(), b"first");
+    // Frames are truncated to `count`, the rest of them is lost
+    assert_eq!(nic.read(3).unwrap(), b"sec");
+    assert!(nic.read(AddressSize::MAX).unwrap().is_empty());
+    assert!(matches!(nic.write(&[0u8; MAX_FRAME_SIZE + 1]), Err(Errno::EINVAL(_))));
+  }
+
+  #[test]
+  fn buffered_storage_writes_back() {
+    let buffer = Arc::new(RwLock::new((0..3 * BUFFERED_PAGE_SIZE).map(|byte| byte as u8).collect::<Vec<u8>>()));
+    let mut storage = BufferedStorage::new(Box::new(MemoryStorage::new(buffer.clone())));
+
+    // Read across pages caches both of them
+    let mut bytes = vec![0u8; 8];
+    storage.seek(SeekFrom::Start(BUFFERED_PAGE_SIZE - 4)).unwrap();
+    storage.read_exact(&mut bytes).unwrap();
+    assert_eq!(bytes, buffer.read().unwrap()[BUFFERED_PAGE_SIZE as usize - 4..][..8]);
+
+    storage.seek(SeekFrom::Start(BUFFERED_PAGE_SIZE - 2)).unwrap();
+    storage.write_all(b"eunix").unwrap();
+    // Write stays in the two pages until flush
+    assert_ne!(&buffer.read().unwrap()[BUFFERED_PAGE_SIZE as usize - 2..][..5], b"eunix");
+    assert_eq!(storage.dirty_pages_count(), 2);
+    storage.flush().unwrap();
+    assert_eq!(&buffer.read().unwrap()[BUFFERED_PAGE_SIZE as usize - 2..][..5], b"eunix");
+    assert_eq!(storage.dirty_pages_count(), 0);
+
+    storage.seek(SeekFrom::Start(BUFFERED_PAGE_SIZE - 4)).unwrap();
+    storage.read_exact(&mut bytes).unwrap();
+    assert_eq!(&bytes[2..7], b"eunix");
+    // Nothing is read past the end of storage
+    storage.seek(SeekFrom::End(-1)).unwrap();
+    assert_eq!(storage.read(&mut bytes).unwrap(), 1);
+  }
+
+  #[test]
+  fn buffered_storage_evicts_least_recently_used_page() {
+    let buffer = Arc::new(RwLock::new(vec![0u8; (BUFFERED_PAGES as u64 + 1) as usize * BUFFERED_PAGE_SIZE as usize]));
+    let mut storage = BufferedStorage::new(Box::new(MemoryStorage::new(buffer.clone())));
+    let mut byte = [0u8];
+    let mut read_page = |storage: &mut BufferedStorage, index: u64| {
+      storage.seek(SeekFrom::Start(index * BUFFERED_PAGE_SIZE)).unwrap();
+      storage.read_exact(&mut byte).unwrap();
+    };
+
+    storage.write_all(b"e5").unwrap();
+    for index in 1..BUFFERED_PAGES as u64 {
+      read_page(&mut storage, index);
+    }
+    // Page 0 is used again, so page 1 is the one to go
+    read_page(&mut storage, 0);
+    read_page(&mut storage, BUFFERED_PAGES as u64);
+    assert!(storage.pages.contains_key(&0) && !storage.pages.contains_key(&1));
+    assert_eq!(storage.pages.len(), BUFFERED_PAGES);
+    assert_eq!(&buffer.read().unwrap()[..2], [0, 0]);
+
+    // Dirty page is written to storage when it is evicted
+    for index in 1..BUFFERED_PAGES as u64 {
+      read_page(&mut storage, index);
+    }
+    assert!(!storage.pages.contains_key(&0));
+    assert_eq!(&buffer.read().unwrap()[..2], b"e5");
+  }
+
+  #[test]
+  fn handles_of_host_disk_share_cache() {
+    let image = mktemp();
+    std::fs::write(&image, vec![0u8; 2 * BUFFERED_PAGE_SIZE as usize]).unwrap();
+    let disk = Arc::new(HostDisk::new(&image, false));
+    let mut first = disk.open().unwrap();
+    let mut partition = Partition::new(disk.clone(), BUFFERED_PAGE_SIZE, BUFFERED_PAGE_SIZE).open().unwrap();
+
+    first.seek(SeekFrom::Start(BUFFERED_PAGE_SIZE)).unwrap();
+    first.write_all(b"e5").unwrap();
+    // Not on host yet, but other handles see it
+    assert_eq!(std::fs::read(&image).unwrap()[BUFFERED_PAGE_SIZE as usize], 0);
+    let mut bytes = [0u8; 2];
+    partition.read_exact(&mut bytes).unwrap();
+    assert_eq!(&bytes, b"e5");
+    let mut second = disk.open().unwrap();
+    second.seek(SeekFrom::Start(BUFFERED_PAGE_SIZE)).unwrap();
+    second.read_exact(&mut bytes).unwrap();
+    assert_eq!(&bytes, b"e5");
+
+    drop(second);
+    assert_eq!(&std::fs::read(&image).unwrap()[BUFFERED_PAGE_SIZE as usize..][..2], b"e5");
+  }
+
+  #[test]
+  fn random_device_reads_from_rng() {
+    let mut random = RandomDevice::new(Arc::new(SeededRng::new(42)));
+
+    let bytes = random.read(16).unwrap();
+    assert_eq!(bytes, SeededRng::new(42).bytes(16));
+    assert_ne!(random.read(16).unwrap(), bytes);
+    assert_eq!(random.read(AddressSize::MAX).unwrap().len(), MAX_RANDOM_READ as usize);
+  }
+
+  #[test]
+  fn transcript_has_input_and_output() {
+    let script = mktemp();
+    std::fs::write(&script, "ls /\n").unwrap();
+    let transcript = mktemp();
+    let mut tty = TranscriptTTY::new(
+      &transcript,
+      Arc::new(RwLock::new(ScriptTTY::open(&script).unwrap())),
+      Arc::new(FixedClock { epoch: 1000 }),
+    );
+
+    tty.write(b"# ").unwrap();
+    tty.read(AddressSize::MAX).unwrap();
+    // End of script
+    tty.read(AddressSize::MAX).unwrap();
+
+    let lines = std::fs::read_to_string(&transcript).unwrap()
+      .lines()
+      .map(str::to_owned)
+      .collect::<Vec<_>>();
+    assert_eq!(lines, vec!["1000 > # ", "1000 < ls /\\n"]);
+  }
+}
+
+// vim:ts=2 sw=2
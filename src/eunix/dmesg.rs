@@ -0,0 +1,102 @@
+use std::collections::VecDeque;
+
+use tracing::Level;
+
+use super::kernel::{Errno, UnixtimeSize};
+
+/// Most records kept, the oldest ones are dropped first
+pub const DMESG_CAPACITY: usize = 1024;
+
+/// Console level of a fresh kernel: everything but debug messages
+pub const DEFAULT_CONSOLE_LEVEL: Level = Level::INFO;
+
+/// One event of kernel, like `mounted /dev/sda on /`
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DmesgRecord {
+  pub time: UnixtimeSize,
+  pub level: Level,
+  pub message: String,
+}
+
+/// Ring buffer of kernel events, read with `dmesg`. Every record is
+/// kept, `console_level` only decides which are written to the serial
+/// port as well, like `console_loglevel` of Linux
+#[derive(Debug)]
+pub struct Dmesg {
+  records: VecDeque<DmesgRecord>,
+  pub console_level: Level,
+}
+
+impl Default for Dmesg {
+  fn default() -> Self {
+    Self {
+      records: VecDeque::new(),
+      console_level: DEFAULT_CONSOLE_LEVEL,
+    }
+  }
+}
+
+impl Dmesg {
+  pub fn push(&mut self, record: DmesgRecord) {
+    if self.records.len() == DMESG_CAPACITY {
+      self.records.pop_front();
+    }
+    self.records.push_back(record);
+  }
+
+  /// Records from the oldest one
+  pub fn records(&self) -> impl Iterator<Item = &DmesgRecord> {
+    self.records.iter()
+  }
+
+  pub fn clear(&mut self) {
+    self.records.clear();
+  }
+
+  /// Whether messages of `level` go to the serial port
+  pub fn is_on_console(&self, level: Level) -> bool {
+    // More verbose levels are greater
+    level <= self.console_level
+  }
+}
+
+/// Get level by name (`error`, `warn`, `info`, `debug`, `trace`)
+/// or by number from 1 for `error` to 5 for `trace`
+pub fn parse_level(name: &str) -> Result<Level, Errno> {
+  name
+    .parse()
+    .map_err(|_| Errno::EINVAL(format!("dmesg: unknown level: {name}").into()))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn record(time: UnixtimeSize) -> DmesgRecord {
+    DmesgRecord { time, level: Level::INFO, message: format!("event {time}") }
+  }
+
+  #[test]
+  fn oldest_records_are_dropped() {
+    let mut dmesg = Dmesg::default();
+    for time in 0..DMESG_CAPACITY as UnixtimeSize + 2 {
+      dmesg.push(record(time));
+    }
+
+    assert_eq!(dmesg.records().count(), DMESG_CAPACITY);
+    assert_eq!(dmesg.records().next(), Some(&record(2)));
+  }
+
+  #[test]
+  fn console_level_filters_verbose_messages() {
+    let mut dmesg = Dmesg::default();
+    dmesg.console_level = parse_level("warn").unwrap();
+
+    assert!(dmesg.is_on_console(Level::ERROR));
+    assert!(dmesg.is_on_console(Level::WARN));
+    assert!(!dmesg.is_on_console(Level::INFO));
+    assert!(parse_level("loud").is_err());
+  }
+}
+
+// vim:ts=2 sw=2
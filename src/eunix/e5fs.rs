@@ -1,35 +1,56 @@
 use core::fmt;
 use std::any::Any;
-use std::cell::RefCell;
+use std::sync::{Arc, RwLock};
 use std::collections::BTreeMap;
 use std::collections::VecDeque;
 use std::io::prelude::*;
 use std::io::SeekFrom;
 use std::io::Write;
 use std::slice::SliceIndex;
+use std::ops::Range;
 
 use fancy_regex::Regex;
 use itertools::Itertools;
+use uuid::Uuid;
 
 use crate::eunix::fs::FileModeType;
 use crate::eunix::fs::NOBODY_UID;
 use crate::eunix::kernel::UnixtimeSize;
 use crate::util::fixedpoint;
-use crate::util::unixtime;
 
+use super::clock::{Clock, host_clock};
+use super::devices::{BlockStorage, BufferedStorage};
+use super::devices::FileRegion;
+use super::journal::JournaledStorage;
 use super::fs::AddressSize;
+use super::fs::DeviceNumber;
 use super::fs::FileMode;
 use super::fs::FileStat;
+use super::fs::FilesystemStat;
 use super::fs::Filesystem;
 use super::fs::Id;
 use super::fs::NO_ADDRESS;
+use super::fs::NAME_MAX;
 use super::fs::VDirectory;
 use super::fs::VDirectoryEntry;
 use super::fs::VINode;
 use super::fs::VFS;
-use super::kernel::Errno;
+use super::kernel::{Errno, ErrnoContext, ROOT_UID};
 use super::kernel::Times;
 
+pub mod bitmap;
+pub mod crypt;
+pub mod dump;
+pub mod fsck;
+pub mod hashdir;
+pub mod snapshot;
+pub mod trash;
+pub mod upgrade;
+pub mod xattr;
+
+use bitmap::BlockBitmap;
+use crypt::{CryptHeader, Key, CRYPT_HEADER_BLOCK_NUMBER};
+
 struct FindFblBlockResult {
   fbl_block_number: AddressSize,
   index_in_fbl_block: usize,
@@ -39,8 +60,11 @@ struct FindFblBlockResult {
 /* 
  * LEGEND: 
  * fbl       - free blocks list, the reserved blocks at the
- *             end of the blocks list which contain free
- *             block numbers for quick allocation
+ *             end of the blocks list which say what blocks
+ *             are free and how many files share used ones:
+ *             entry of every block before format version 2,
+ *             bitmap of free blocks and table of references
+ *             since then
  * fbl_chunk - vector of numbers parsed from `fbl` block
  * fbl_index - index into `fbl` by step of address_size
  * */
@@ -54,13 +78,24 @@ pub struct DirectoryEntry {
 }
 
 impl DirectoryEntry {
+  /// Errors:
+  /// EINVAL       -> name is empty or has '/' or NUL in it, so it
+  ///                 could not be looked up
+  /// ENAMETOOLONG -> name is longer than `NAME_MAX` bytes
   fn new(inode_number: AddressSize, name: &str) -> Result<Self, Errno> {
     use std::mem::size_of;
 
+    if name.is_empty() || name.contains(['/', '\0']) {
+      return Err(Errno::EINVAL(format!("DirectoryEntry::new: invalid name {name:?}").into()));
+    }
+    if name.len() > NAME_MAX {
+      return Err(Errno::ENAMETOOLONG(format!("DirectoryEntry::new: name can't be longer than {NAME_MAX} bytes").into()));
+    }
+
     Ok(Self {
       inode_number,
       rec_len: (size_of::<AddressSize>() + size_of::<u16>() + size_of::<u8>() + name.len()) as u16,
-      name_len: name.len().try_into().or_else(|_| Err(Errno::ENAMETOOLONG(String::from("DirectoryEntry::new: name can't be bigger than 255"))))?,
+      name_len: name.len() as u8,
       name: name.to_owned(),
     })
   }
@@ -106,7 +141,7 @@ impl Directory {
   pub fn insert(&mut self, inode_number: AddressSize, name: &str) -> Result<(), Errno> {
     // Guard for entry already existing
     if let Some(_) = self.entries.get(name) {
-      return Err(Errno::EEXIST(format!("e5fs::Directory: entry {name} already exists")))
+      return Err(Errno::EEXIST(format!("e5fs::Directory: entry {name} already exists").into()))
     }
     self.entries.insert(
       name.to_owned(),
@@ -116,7 +151,7 @@ impl Directory {
     Ok(())
   }
   pub fn remove(&mut self, name: &str) -> Result<(), Errno> {
-    self.entries.remove(name).ok_or(Errno::ENOENT(String::from("no such file in directory")))?;
+    self.entries.remove(name).ok_or(Errno::ENOENT(String::from("no such file in directory").into()))?;
     self.entries_count -= 1;
     Ok(())
   }
@@ -141,14 +176,45 @@ pub struct INode {
   btime: UnixtimeSize,
   direct_block_numbers: [AddressSize; 12],
   indirect_block_numbers: [AddressSize; 3],
+  /// Block with extended attributes, see `Xattrs`
+  xattr_block_number: AddressSize,
+  /// Links from snapshots, counted in `links_count` too. Inode that
+  /// has them is changed only after it is unshared, see `snapshot`
+  snapshot_links: u16,
   number: AddressSize,
 }
 
+impl INode {
+  fn is_device(&self) -> bool {
+    self.mode.file_type() == FileModeType::Block as u8
+      || self.mode.file_type() == FileModeType::Char as u8
+  }
+
+  /// Device number of special file - stored in place
+  /// of the first direct block number, like in ext2
+  fn rdev(&self) -> DeviceNumber {
+    if self.is_device() {
+      DeviceNumber::from_raw(self.direct_block_numbers[0])
+    } else {
+      DeviceNumber::default()
+    }
+  }
+
+  /// Links from the tree itself, or from snapshots
+  /// if it is not in the tree anymore
+  fn visible_links_count(&self) -> AddressSize {
+    match self.links_count.saturating_sub(self.snapshot_links as AddressSize) {
+      0 => self.links_count,
+      links_count => links_count,
+    }
+  }
+}
+
 impl From<INode> for VINode {
   fn from(inode: INode) -> Self {
     Self {
       mode: inode.mode,
-      links_count: inode.links_count,
+      links_count: inode.visible_links_count(),
       file_size: inode.file_size,
       uid: inode.uid,
       gid: inode.gid,
@@ -156,6 +222,7 @@ impl From<INode> for VINode {
       ctime: inode.ctime,
       mtime: inode.mtime,
       btime: inode.btime,
+      rdev: inode.rdev(),
       number: inode.number,
     }
   }
@@ -205,15 +272,76 @@ impl Default for INode {
       btime: 0,
       direct_block_numbers: [NO_ADDRESS; 12],
       indirect_block_numbers: [NO_ADDRESS; 3],
+      xattr_block_number: NO_ADDRESS,
+      snapshot_links: 0,
       number: 0,
     }
   }
 }
 
+/// Count of block numbers right in inode, others are
+/// in its indirect block
+const DIRECT_BLOCKS_COUNT: usize = 12;
+/// Size of inode in inode table, fields take all 112 bytes of it
+pub const INODE_SIZE: AddressSize = 112;
+/// Max length of volume label in bytes
+pub const LABEL_MAX_LEN: usize = 16;
+/// Size of journal in bytes, devices smaller than 16 of them
+/// get a 1/16 of their size
+pub const JOURNAL_SIZE: AddressSize = 64 * 1024;
+/// Most of data blocks that can be reserved for root, in percents
+pub const MAX_RESERVED_BLOCKS_PERCENTAGE: f32 = 50.0;
+/// Version of on-disk format that mkfs makes. Filesystems of older
+/// ones are upgraded when they are mounted, see `upgrade`, newer
+/// ones are not mounted at all.
+/// 0 -> made before versions: directories are one list of entries
+///      and free counts in superblock are whatever mkfs put there
+/// 1 -> directories are hashed, free counts are kept up to date
+/// 2 -> `fbl` is bitmap of free blocks and table of references
+///      instead of entry of every block
+pub const FORMAT_VERSION: u32 = 2;
+/// Format version since which `fbl` is bitmap of free blocks, a bit
+/// set for a free one, with table of references right after it:
+/// count of files sharing every block besides the first, as `u16`
+pub const BLOCK_MAP_FORMAT_VERSION: u32 = 2;
+/// Bytes of count of references to a block in table of references
+const REFERENCES_SIZE: AddressSize = std::mem::size_of::<u16>() as AddressSize;
+/// Size of superblock of filesystems made before it had uuid, label
+/// and state. Inode table is right after it, there is no journal
+pub const LEGACY_SUPERBLOCK_SIZE: AddressSize = 120;
+/// `Superblock::state` of filesystem that was unmounted properly
+pub const STATE_CLEAN: u32 = 1;
+/// `Superblock::state` of filesystem that is mounted, or was
+/// not unmounted before the machine went down
+pub const STATE_DIRTY: u32 = 2;
+/// Addresses of backup copies of superblock, ones that land on data
+/// blocks get them. They don't depend on layout of filesystem, so
+/// they can be found when superblock can't be read
+pub const SUPERBLOCK_BACKUP_ADDRESSES: [AddressSize; 6] = [
+  512 * 1024,
+  2 * 1024 * 1024,
+  8 * 1024 * 1024,
+  32 * 1024 * 1024,
+  128 * 1024 * 1024,
+  512 * 1024 * 1024,
+];
+/// Most files that can share a block. `fbl` entry of a used block is
+/// `NO_ADDRESS` less the count of files sharing it besides the first,
+/// so it is never a block number. That count fits in table of
+/// references too
+pub const MAX_BLOCK_REFERENCES: AddressSize = 1 << 16;
+
 #[derive(Default, Debug, PartialEq, Clone, Copy)]
 pub struct Superblock {
   /// A name of filesystem, basically
-  pub filesystem_type: [u8; 16],
+  pub filesystem_type: [u8; 8],
+  /// Free blocks that only root can claim. Like `format_version`,
+  /// it is in what used to be zero padding of `filesystem_type`
+  pub reserved_blocks_count: AddressSize,
+  /// Version of on-disk format, see `FORMAT_VERSION`. It takes the
+  /// end of what used to be zero-padded `filesystem_type`, so it is
+  /// zero on filesystems made before it
+  pub format_version: u32,
   /// In blocks? Dc, unused anyway
   pub filesystem_size: AddressSize, 
   /// Total inode table size in bytes
@@ -241,6 +369,12 @@ pub struct Superblock {
   /// a list of blocks containing free block numbers as
   /// contents
   pub first_fbl_block_number: AddressSize,
+  /// Unique identifier, generated by mkfs
+  pub uuid: [u8; 16],
+  /// Volume name, zero-padded, empty if unset
+  pub label: [u8; LABEL_MAX_LEN],
+  /// `STATE_CLEAN` or `STATE_DIRTY`
+  pub state: u32,
 }
 
 
@@ -274,21 +408,61 @@ impl Superblock {
 
     Self {
       filesystem_type: [
-        'e' as u8, '5' as u8, 'f' as u8, 's' as u8, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0,
+        'e' as u8, '5' as u8, 'f' as u8, 's' as u8, 0, 0, 0, 0,
       ],
+      reserved_blocks_count: 0,
+      format_version: FORMAT_VERSION,
       filesystem_size, // in blocks?
       inode_table_size,
       inode_table_percentage,
       free_inodes_count: inodes_count,
-      free_blocks_count: blocks_count,
+      free_blocks_count: fs_info.free_blocks_count,
       inodes_count,
       blocks_count,
       block_size,
       block_data_size,
       free_inode_numbers: free_inodes,
       first_fbl_block_number: fs_info.free_blocks_count,
+      uuid: *Uuid::new_v4().as_bytes(),
+      label: [0; LABEL_MAX_LEN],
+      state: STATE_CLEAN,
     }
   }
+
+  /// Size superblock takes on device. Legacy one, see
+  /// `LEGACY_SUPERBLOCK_SIZE`, is told apart by what is left
+  /// of filesystem for superblock and journal.
+  /// Returns: `None` if sizes of filesystem parts don't add up
+  fn on_disk_size(&self) -> Option<AddressSize> {
+    let rest = (self.filesystem_size as u64)
+      .checked_sub(self.inode_table_size as u64 + self.blocks_count as u64 * self.block_size as u64)?;
+
+    match rest {
+      rest if rest == LEGACY_SUPERBLOCK_SIZE as u64 => Some(LEGACY_SUPERBLOCK_SIZE),
+      rest if rest >= Superblock::size() as u64 => Some(Superblock::size()),
+      _ => None,
+    }
+  }
+
+  fn is_e5fs(&self) -> bool {
+    self.filesystem_type.starts_with(b"e5fs\0")
+  }
+
+  /// Label as string, up to the first zero byte
+  pub fn label(&self) -> String {
+    let length = self.label.iter().position(|&byte| byte == 0).unwrap_or(LABEL_MAX_LEN);
+
+    String::from_utf8_lossy(&self.label[..length]).into_owned()
+  }
+}
+
+/// Identifiers of filesystem found on device
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FilesystemIdentity {
+  /// Hyphenated UUID, like `67e55044-10b1-426f-9247-bb680e5fe0c8`
+  pub uuid: String,
+  /// Empty if unset
+  pub label: String,
 }
 
 #[derive(Default, Debug, PartialEq, Eq)]
@@ -298,9 +472,10 @@ pub struct Block {
 
 #[derive(Debug)]
 pub struct E5FSFilesystemBuilder {
-  realfile: RefCell<std::fs::File>,
+  realfile: RwLock<JournaledStorage>,
   device_size: AddressSize,
   superblock_size: AddressSize,
+  journal_size: AddressSize,
   inode_size: AddressSize,
   block_size: AddressSize,
   inodes_count: AddressSize,
@@ -319,14 +494,76 @@ pub struct E5FSFilesystemBuilder {
   first_fbl_block_number: AddressSize,
   first_fbl_block_address: AddressSize,
   root_inode_number: AddressSize,
+  /// Version of on-disk format, layout of `fbl` depends on it
+  format_version: u32,
 }
 
 impl E5FSFilesystemBuilder {
   pub fn new(device_realpath: &str, inode_table_percentage: f32, block_data_size: AddressSize) -> Result<Self, &'static str> {
+    let storage = E5FSFilesystem::open_image(device_realpath).or(Err("cannot open device"))?;
+
+    Self::with_storage(storage, inode_table_percentage, block_data_size)
+  }
+
+  pub fn with_storage(storage: Box<dyn BlockStorage>, inode_table_percentage: f32, block_data_size: AddressSize) -> Result<Self, &'static str> {
+    Self::check_parameters(inode_table_percentage, block_data_size)?;
+
+    let device_size = storage.size() as AddressSize;
+
+    // Journal goes right after superblock
+    let journal_size = JOURNAL_SIZE.min(device_size / 16);
+    let (inodes_count, blocks_count) = Self::counts(device_size, journal_size, inode_table_percentage, block_data_size);
+
+    Self::with_counts(storage, Superblock::size(), journal_size, FORMAT_VERSION, inodes_count, blocks_count, inode_table_percentage, block_data_size)
+  }
+
+  /// Layout that `superblock` describes, which doesn't depend on
+  /// size of device: device may have grown since mkfs
+  fn with_superblock(storage: Box<dyn BlockStorage>, superblock: &Superblock) -> Result<Self, &'static str> {
+    let (superblock_size, journal_size) = Self::check_superblock(superblock, storage.size())?;
+
+    Self::with_counts(
+      storage,
+      superblock_size,
+      journal_size,
+      superblock.format_version,
+      superblock.inodes_count,
+      superblock.blocks_count,
+      superblock.inode_table_percentage,
+      superblock.block_data_size,
+    )
+  }
+
+  /// Check that layout `superblock` describes fits on device.
+  /// Returns: `(superblock_size, journal_size)`
+  fn check_superblock(superblock: &Superblock, device_size: u64) -> Result<(AddressSize, AddressSize), &'static str> {
+    Self::check_parameters(superblock.inode_table_percentage, superblock.block_data_size)?;
+    if superblock.block_size != superblock.block_data_size {
+      return Err("block size doesn't match block data size");
+    }
+
+    // Journal is whatever is left between superblock and inode table
+    let superblock_size = superblock.on_disk_size().ok_or("sizes of filesystem parts don't add up")?;
+    let journal_size = superblock.filesystem_size
+      - superblock_size
+      - superblock.inode_table_size
+      - superblock.blocks_count * superblock.block_size;
+    if superblock.filesystem_size as u64 > device_size {
+      return Err("filesystem is larger than device");
+    }
+    if superblock.inode_table_size as u64 != superblock.inodes_count as u64 * INODE_SIZE as u64 {
+      return Err("inode table size doesn't match inodes count");
+    }
+
+    Ok((superblock_size, journal_size))
+  }
+
+  fn check_parameters(inode_table_percentage: f32, block_data_size: AddressSize) -> Result<(), &'static str> {
     // Guard for percent_inodes
     match inode_table_percentage {
       n if n < 0f32 => return Err("percent_inodes can't be less than 0"),
       n if n > 1f32 => return Err("percent_inodes can't be more than 1"),
+      n if n.is_nan() => return Err("percent_inodes must be a number"),
       _ => (),
     };
 
@@ -337,210 +574,445 @@ impl E5FSFilesystemBuilder {
       _ => (),
     };
 
-    let mut realfile = RefCell::new(std::fs::OpenOptions::new()
-                    .read(true)
-                    .write(true)
-                    .open(device_realpath)
-                    .unwrap());
+    Ok(())
+  }
+
+  /// Counts of inodes and blocks that fit on device of `device_size`
+  /// with journal of `journal_size`.
+  /// Returns: `(inodes_count, blocks_count)`
+  fn counts(device_size: AddressSize, journal_size: AddressSize, inode_table_percentage: f32, block_size: AddressSize) -> (AddressSize, AddressSize) {
+    let inode_size = INODE_SIZE;
+    let inodes_count = ((device_size as f32 * inode_table_percentage) / inode_size as f32) as AddressSize;
+    let inode_table_size = inode_size * inodes_count;
+
+    // Superblock is not accounted in percentages, so drop blocks
+    // that would stick out past the end of device
+    let blocks_count =
+      (((device_size as f32 * (1f32 - inode_table_percentage)) / block_size as f32) as AddressSize)
+        .min(device_size.saturating_sub(Superblock::size() + journal_size + inode_table_size) / block_size);
 
-    let device_size = realfile.borrow_mut().metadata().unwrap().len() as AddressSize;
-    let superblock_size = Superblock::size();
-    let inode_size = std::mem::size_of::<INode>() as AddressSize;
+    (inodes_count, blocks_count)
+  }
 
+  fn with_counts(
+    storage: Box<dyn BlockStorage>,
+    superblock_size: AddressSize,
+    journal_size: AddressSize,
+    format_version: u32,
+    inodes_count: AddressSize,
+    blocks_count: AddressSize,
+    inode_table_percentage: f32,
+    block_data_size: AddressSize,
+  ) -> Result<Self, &'static str> {
+    let device_size = storage.size() as AddressSize;
+    let realfile = RwLock::new(JournaledStorage::new(storage, superblock_size as u64, journal_size as u64));
     let address_size = std::mem::size_of::<AddressSize>() as AddressSize;
 
-    // next_block_number + data
-    let block_size = block_data_size;
-
-    let inodes_count = ((device_size as f32 * inode_table_percentage) / inode_size as f32) as AddressSize;
-    let blocks_count =
-      ((device_size as f32 * (1f32 - inode_table_percentage)) / block_size as f32) as AddressSize;
+    let mut fs_info = Self {
+      realfile,
+      device_size,
+      superblock_size,
+      journal_size,
+      inode_size: INODE_SIZE,
+
+      // next_block_number + data
+      block_size: block_data_size,
+
+      // Set by `set_counts`
+      inodes_count: 0,
+      blocks_count: 0,
+      inode_table_size: 0,
+      filesystem_size: 0,
+      blocks_needed_for_fbl: 0,
+      first_inode_address: 0,
+      first_block_address: 0,
+      block_table_size: 0,
+      free_blocks_count: 0,
+      first_fbl_block_number: 0,
+      first_fbl_block_address: 0,
 
-    let inode_table_size = inode_size * inodes_count;
+      block_data_size,
+      address_size,
+      block_numbers_per_fbl_chunk: block_data_size / address_size,
+      inode_table_percentage,
+      root_inode_number: 0,
+      format_version,
+    };
+    fs_info.set_counts(inodes_count, blocks_count)?;
 
-    let filesystem_size = superblock_size + inode_table_size + block_size * blocks_count;
+    Ok(fs_info)
+  }
 
-    let first_inode_address = superblock_size;
-    let first_block_address = superblock_size + inode_table_size;
+  /// Lay out inode table and blocks for that many of them
+  /// Blocks that `fbl` of `blocks_count` blocks takes
+  fn blocks_needed_for_fbl(&self, blocks_count: AddressSize) -> AddressSize {
+    if self.has_block_map() {
+      return self.blocks_needed_for_block_map(blocks_count);
+    }
 
     // ceil(
     //   blocks_count / (block_data_size / block_address_size)
     // )
-    let blocks_needed_for_fbl = 
-      (blocks_count as f64 / (block_data_size as f64 / address_size as f64))
-        .ceil() as AddressSize;
+    (blocks_count as f64 / (self.block_data_size as f64 / self.address_size as f64))
+      .ceil() as AddressSize
+  }
+
+  /// Blocks that bitmap and table of references of `blocks_count`
+  /// blocks take together
+  fn blocks_needed_for_block_map(&self, blocks_count: AddressSize) -> AddressSize {
+    (blocks_count.div_ceil(8) + blocks_count * REFERENCES_SIZE).div_ceil(self.block_size)
+  }
+
+  /// Whether `fbl` is bitmap and table of references
+  fn has_block_map(&self) -> bool {
+    self.format_version >= BLOCK_MAP_FORMAT_VERSION
+  }
+
+  /// Address of table of references, right after bitmap in `fbl`
+  fn references_address(&self) -> AddressSize {
+    self.first_fbl_block_address + self.blocks_count.div_ceil(8)
+  }
+
+  fn set_counts(&mut self, inodes_count: AddressSize, blocks_count: AddressSize) -> Result<(), &'static str> {
+    let inode_table_size = self.inode_size * inodes_count;
+
+    let filesystem_size = self.superblock_size + self.journal_size + inode_table_size + self.block_size * blocks_count;
+
+    let first_inode_address = self.superblock_size + self.journal_size;
+    let first_block_address = first_inode_address + inode_table_size;
+
+    let blocks_needed_for_fbl = self.blocks_needed_for_fbl(blocks_count);
 
     // Sanity check
     if blocks_needed_for_fbl < 1 {
       return Err("blocks_needed_for_fbl can't be less than 1");
     }
 
-    let free_blocks_count = blocks_count - blocks_needed_for_fbl;
-
-    let block_numbers_per_fbl_chunk = block_data_size / address_size;
-
     // Guard for not enough blocks even for free blocks list
     if blocks_needed_for_fbl >= blocks_count {
       return Err("disk size is too small: blocks_needed_for_fbl > blocks_count");
     }
 
-    let block_table_size = block_size * blocks_count;
+    let free_blocks_count = blocks_count - blocks_needed_for_fbl;
 
     // Basically step over all free block numbers - 
     // first after that will be beginning of `fbl`
     let first_fbl_block_number = free_blocks_count;
-    let first_fbl_block_address = 
-      superblock_size 
-      + inode_table_size 
-      + (blocks_count - blocks_needed_for_fbl) * block_size;
-
-    Ok(Self {
-      realfile,
-      device_size,
-      superblock_size,
-      inode_size,
-
-      block_size,
-
-      inodes_count,
-      blocks_count,
-
-      inode_table_size,
-
-      filesystem_size,
-      blocks_needed_for_fbl,
-
-      first_inode_address,
-      first_block_address,
-
-      block_table_size,
-      block_data_size,
+    let first_fbl_block_address = first_block_address + first_fbl_block_number * self.block_size;
+
+    self.inodes_count = inodes_count;
+    self.blocks_count = blocks_count;
+    self.inode_table_size = inode_table_size;
+    self.filesystem_size = filesystem_size;
+    self.blocks_needed_for_fbl = blocks_needed_for_fbl;
+    self.first_inode_address = first_inode_address;
+    self.first_block_address = first_block_address;
+    self.block_table_size = self.block_size * blocks_count;
+    self.free_blocks_count = free_blocks_count;
+    self.first_fbl_block_number = first_fbl_block_number;
+    self.first_fbl_block_address = first_fbl_block_address;
 
-      free_blocks_count,
-      address_size,
-      block_numbers_per_fbl_chunk,
-      inode_table_percentage,
-      first_fbl_block_number,
-      first_fbl_block_address,
-      root_inode_number: 0,
-    })
+    Ok(())
   }
 }
 
 pub struct E5FSFilesystem {
   superblock: Superblock,
   fs_info: E5FSFilesystemBuilder,
+  /// Filesystem was dirty when it was read: last time it was
+  /// mounted, it was not unmounted
+  pub needs_check: bool,
+  /// Superblock was corrupt when it was read, so it was restored
+  /// from backup at this address
+  pub superblock_backup: Option<AddressSize>,
+  /// Key that data blocks are encrypted with, if they are
+  key: Option<Key>,
+  /// Free blocks as `fbl` has them, for `claim_free_block`. Memory
+  /// only, `fbl` is what is on disk
+  free_blocks: BlockBitmap,
+  /// Whoever does operations, reserved blocks are only claimed for root
+  current_uid: Id,
+  /// Opened with `from_storage_read_only`, or to mount a snapshot
+  read_only: bool,
+  clock: Arc<dyn Clock>,
 }
 
 impl Filesystem for E5FSFilesystem {
   fn create_file(&mut self, pathname: &str)
     -> Result<VINode, Errno> {
-    let (_, final_component) = VFS::split_path(pathname)?;
-    let parent_pathname = VFS::parent_dir(pathname)?;
-
-    // Get dir path with this regex
-    let parent_inode = self.lookup_path(parent_pathname.as_str())?;
+    self.check_writable(pathname)?;
+    self.transaction(|e5fs| {
+      let (_, final_component) = VFS::split_path(pathname)?;
+      let parent_pathname = VFS::parent_dir(pathname)?;
 
-    // Read dir from disk
-    let mut parent_dir = self.read_as_dir_i(parent_inode.number)?;
+      // Get dir path with this regex
+      let parent_inode = e5fs.lookup_path(parent_pathname.as_str())?;
 
-    // Guard for file already existing
-    if let Some(_) = parent_dir.entries.get(&final_component)
-    {
-      return Err(Errno::EINVAL(format!("e5fs::create_file: file {final_component} already exists in {parent_pathname}")));
-    }
-
-    // Allocate inode
-    let (_, inode) = self.allocate_file()?;
+      // Guard for file already existing
+      if e5fs.find_dir_entry_i(parent_inode.number, &final_component)?.is_some() {
+        return Err(Errno::EINVAL(format!("e5fs::create_file: file {final_component} already exists in {parent_pathname}").into()));
+      }
 
-    // Push allocated to dir
-    parent_dir.insert(inode.number, final_component.as_str())?;
+      // Allocate inode
+      let (_, inode) = e5fs.allocate_file()?;
 
-    // Write dir
-    self.write_dir_i(&parent_dir, parent_inode.number)?;
+      // Push allocated to dir and write it
+      e5fs.insert_dir_entry_i(parent_inode.number, inode.number, &final_component)?;
 
-    // Set inode's links count to 1 (link from parent dir)
-    self.write_links_count_i(inode.number, 1)?;
+      // Set inode's links count to 1 (link from parent dir)
+      e5fs.write_links_count_i(inode.number, 1)?;
 
-    // Read new inode before returning, just to be sure
-    // that we got correct sizes and all that crap
-    //
-    let inode = self.read_inode(inode.number);
-    Ok(inode.into())
+      // Read new inode before returning, just to be sure
+      // that we got correct sizes and all that crap
+      //
+      let inode = e5fs.read_inode(inode.number)?;
+      Ok(inode.into())
+    })
   }
 
   fn remove_file(&mut self, pathname: &str)
     -> Result<(), Errno> {
-    let parent_pathname = VFS::parent_dir(pathname)?;
-    let (_, final_component) = VFS::split_path(pathname)?;
-    let parent_vinode = self.lookup_path(&parent_pathname)?;
-    let mut parent_dir = self.read_dir(&parent_pathname)?;
+    self.check_writable(pathname)?;
+    self.transaction(|e5fs| {
+      let parent_pathname = VFS::parent_dir(pathname)?;
+      let (_, final_component) = VFS::split_path(pathname)?;
+      let parent_vinode = e5fs.lookup_path(&parent_pathname)?;
+
+      if final_component == "." || final_component == ".." {
+        return Err(Errno::EINVAL(format!("e5fs::remove_file: you cannot remove e5fs or parent-reference").into()))
+      }
 
-    if final_component == "." || final_component == ".." {
-      return Err(Errno::EINVAL(format!("e5fs::remove_file: you cannot remove self or parent-reference")))
-    }
+      // Directory goes with its `.` and `..` and a link of parent,
+      // only if there is nothing else in it
+      let inode_number = e5fs.find_dir_entry_i(parent_vinode.number, &final_component)?
+        .ok_or_else(|| Errno::ENOENT(format!("e5fs::remove_file: no such file or directory {pathname}").into()))?;
+      if e5fs.read_inode(inode_number)?.mode.file_type() == FileModeType::Dir as u8 {
+        if e5fs.read_as_dir_i(inode_number)?.entries.len() > 2 {
+          return Err(Errno::ENOTEMPTY(format!("e5fs::remove_file: {pathname}: directory not empty").into()));
+        }
+        e5fs.remove_dir_entry_i(parent_vinode.number, &final_component)?;
+        e5fs.release_tree(inode_number, false)?;
+        let links_count = e5fs.read_inode(parent_vinode.number)?.links_count;
+        e5fs.write_links_count_i(parent_vinode.number, links_count - 1)?;
+        return Ok(());
+      }
     
-    // Mutate dir and write (save) it
-    let VDirectoryEntry {
-        inode_number,
-        ..
-    } = parent_dir
-      .entries
-      .remove(&final_component)
-      .ok_or(Errno::ENOENT(format!("e5fs::remove_file: no such file or directory '{final_component}'")))?;
-    self.write_dir_i(&parent_dir.into(), parent_vinode.number)?;
-
-    // Read inode and update it's values
-    let mut inode = self.read_inode(inode_number);
-    inode.links_count -= 1;
-    inode.ctime = unixtime();
-
-    // Free blocks of inode if no links left
-    if inode.links_count < 1 {
-      for block_number in self
-        .iter_blocks_i(inode_number)
-        .take_while(|&block_number| block_number != NO_ADDRESS)
-      {
-        self.release_block(block_number)?;
+      // Trash changes attributes of file, snapshots keep theirs
+      if e5fs.root_dir_entry_i(trash::TRASH_DIR_NAME)?.is_some() {
+        e5fs.unshare_inode(pathname)?;
       }
-      inode.mode = inode.mode.with_free(1);
-    }
 
-    // Write (save) inode to disk
-    self.write_inode(&inode, inode.number)
-  } 
+      // Mutate dir and write (save) it
+      let inode_number = e5fs.remove_dir_entry_i(parent_vinode.number, &final_component)?;
+
+      // Read inode and update it's values
+      let mut inode = e5fs.read_inode(inode_number)?;
+      // Last link of file moves it to trash, if there is one
+      if inode.links_count == 1 && e5fs.trash_i(inode_number, pathname, parent_vinode.number)? {
+        return Ok(());
+      }
+      inode.links_count -= 1;
+      if inode.snapshot_links == 0 {
+        inode.ctime = e5fs.clock.now();
+      }
+
+      // Free blocks of inode if no links left
+      if inode.links_count < 1 {
+        e5fs.release_blocks_i(&mut inode)?;
+        if inode.xattr_block_number != NO_ADDRESS {
+          e5fs.release_block(inode.xattr_block_number)?;
+          inode.xattr_block_number = NO_ADDRESS;
+        }
+        inode.mode = inode.mode.with_free(1);
+        e5fs.superblock.free_inodes_count += 1;
+      }
+
+      // Write (save) inode to disk
+      e5fs.write_inode(&inode, inode.number)
+    })
+  }
 
   fn create_dir(&mut self, pathname: &str)
     -> Result<VINode, Errno> {
-    let vinode = self.create_file(pathname)?;
+    self.transaction(|e5fs| {
+      let vinode = e5fs.create_file(pathname)?;
+
+      let parent_pathname = format!("/{}", VFS::split_path(pathname)?.0.join("/"));
+      let parent_vinode = e5fs.lookup_path(&parent_pathname)?;
+
+      // Construct dir with parent- and e5fs- references
+      let mut dir = Directory::new();
+      dir.insert(parent_vinode.number, "..")?;
+      dir.insert(vinode.number, ".")?;
+      e5fs.write_dir_i(&dir, vinode.number)?;
+
+      // Change inode mode to be of type `Dir`
+      e5fs.write_mode_i(vinode.number, vinode.mode.with_file_type(FileModeType::Dir as u8))?;
+
+      // Set links count of new inode
+      // to 2 (e5fs-reference and reference by a parent dir)
+      e5fs.write_links_count_i(vinode.number, 2)?;
+
+      // Increment link count of parent inode
+      e5fs.write_links_count_i(parent_vinode.number, parent_vinode.links_count + 1)?;
+
+      Ok(vinode)
+    })
+  }
+
+  fn mknod(&mut self, pathname: &str, file_type: FileModeType, device_number: DeviceNumber)
+    -> Result<VINode, Errno> {
+    self.transaction(|e5fs| {
+      // Guard for file type not being a device
+      match file_type {
+        FileModeType::Block | FileModeType::Char => (),
+        _ => return Err(Errno::EINVAL(format!("e5fs::mknod: {file_type} is not a device type").into())),
+      }
+
+      let vinode = e5fs.create_file(pathname)?;
+
+      // Device files have no data - release
+      // the block claimed by `allocate_file`
+      for block_number in e5fs
+        .iter_blocks_i(vinode.number)?
+        .filter(|&block_number| block_number != NO_ADDRESS)
+        .collect::<Vec<AddressSize>>()
+      {
+        e5fs.release_block(block_number)?;
+      }
+
+      let mut inode = e5fs.read_inode(vinode.number)?;
+      inode.mode = inode.mode.with_file_type(file_type as u8);
+      inode.file_size = 0;
+      inode.direct_block_numbers = [NO_ADDRESS; 12];
+      inode.direct_block_numbers[0] = device_number.to_raw();
+      e5fs.write_inode(&inode, inode.number)?;
+
+      Ok(inode.into())
+    })
+  }
+
+  fn link(&mut self, existing: &str, new: &str)
+    -> Result<VINode, Errno> {
+    self.check_writable(existing)?;
+    self.check_writable(new)?;
+    self.transaction(|e5fs| {
+      e5fs.unshare_inode(existing)?;
+      let vinode = e5fs.lookup_path(existing)?;
+      // Guard for directories, links to them would make loops
+      if vinode.mode.file_type() == FileModeType::Dir as u8 {
+        return Err(Errno::EPERM(format!("e5fs::link: {existing}: hard links to directories are not allowed").into()));
+      }
+
+      let (_, final_component) = VFS::split_path(new)?;
+      let parent_pathname = VFS::parent_dir(new)?;
+      let parent_inode = e5fs.lookup_path(&parent_pathname)?;
+
+      // Guard for file already existing
+      if e5fs.find_dir_entry_i(parent_inode.number, &final_component)?.is_some() {
+        return Err(Errno::EEXIST(format!("e5fs::link: file {final_component} already exists in {parent_pathname}").into()));
+      }
+
+      // Second entry for the same inode
+      e5fs.insert_dir_entry_i(parent_inode.number, vinode.number, &final_component)?;
+
+      let mut inode = e5fs.read_inode(vinode.number)?;
+      inode.links_count += 1;
+      inode.ctime = e5fs.clock.now();
+      e5fs.write_inode(&inode, inode.number)?;
+
+      Ok(inode.into())
+    })
+  }
+
+  fn rename(&mut self, old: &str, new: &str)
+    -> Result<(), Errno> {
+    self.check_writable(old)?;
+    self.check_writable(new)?;
+    self.transaction(|e5fs| {
+      e5fs.unshare_inode(old)?;
+      let (_, old_name) = VFS::split_path(old)?;
+      let (_, new_name) = VFS::split_path(new)?;
+      let old_parent_number = e5fs.lookup_path(&VFS::parent_dir(old)?)?.number;
+      let new_parent_number = e5fs.lookup_path(&VFS::parent_dir(new)?)?.number;
+      let inode_number = e5fs.find_dir_entry_i(old_parent_number, &old_name)?
+        .ok_or_else(|| Errno::ENOENT(format!("e5fs::rename: no such file or directory {old}").into()))?;
+      let is_dir = e5fs.read_inode(inode_number)?.mode.file_type() == FileModeType::Dir as u8;
+
+      // Guard for directory going into itself, it would be cut off
+      if is_dir && new.starts_with(&format!("{}/", old.trim_end_matches('/'))) {
+        return Err(Errno::EINVAL(format!("e5fs::rename: cannot move {old} into itself").into()));
+      }
+
+      if let Some(existing_number) = e5fs.find_dir_entry_i(new_parent_number, &new_name)? {
+        // Both names are of the same file, like Linux do nothing
+        if existing_number == inode_number {
+          return Ok(());
+        }
 
-    let parent_pathname = format!("/{}", VFS::split_path(pathname)?.0.join("/"));
-    let parent_vinode = self.lookup_path(&parent_pathname)?;
+        let existing_is_dir = e5fs.read_inode(existing_number)?.mode.file_type() == FileModeType::Dir as u8;
+        match (is_dir, existing_is_dir) {
+          (false, true) => return Err(Errno::EISDIR(format!("e5fs::rename: {new} is a directory").into())),
+          (true, false) => return Err(Errno::ENOTDIR(format!("e5fs::rename: {new} is not a directory").into())),
+          // Directory in the way has to be empty
+          _ => e5fs.remove_file(new)?,
+        }
+      }
 
-    // Construct dir with parent- and self- references
-    let mut dir = Directory::new();
-    dir.insert(parent_vinode.number, "..")?;
-    dir.insert(vinode.number, ".")?;
-    self.write_dir_i(&dir, vinode.number)?;
+      e5fs.remove_dir_entry_i(old_parent_number, &old_name)?;
+      e5fs.insert_dir_entry_i(new_parent_number, inode_number, &new_name)?;
+
+      // `..` of directory is its new parent now
+      if is_dir && old_parent_number != new_parent_number {
+        e5fs.remove_dir_entry_i(inode_number, "..")?;
+        e5fs.insert_dir_entry_i(inode_number, new_parent_number, "..")?;
+        let links_count = e5fs.read_inode(old_parent_number)?.links_count;
+        e5fs.write_links_count_i(old_parent_number, links_count - 1)?;
+        let links_count = e5fs.read_inode(new_parent_number)?.links_count;
+        e5fs.write_links_count_i(new_parent_number, links_count + 1)?;
+      }
 
-    // Change inode mode to be of type `Dir`
-    self.write_mode_i(vinode.number, vinode.mode.with_file_type(FileModeType::Dir as u8))?;
+      let mut inode = e5fs.read_inode(inode_number)?;
+      inode.ctime = e5fs.clock.now();
+      e5fs.write_inode(&inode, inode_number)
+    })
+  }
 
-    // Set links count of new inode
-    // to 2 (self-reference and reference by a parent dir)
-    self.write_links_count_i(vinode.number, 2)?;
+  fn symlink(&mut self, target: &str, pathname: &str)
+    -> Result<VINode, Errno> {
+    self.transaction(|e5fs| {
+      let vinode = e5fs.create_file(pathname)?;
+      // Target is kept in data blocks, like contents of a file
+      e5fs.write_data_i(target.as_bytes().to_owned(), vinode.number, false)?;
+      e5fs.write_mode_i(vinode.number, vinode.mode
+        .with_file_type(FileModeType::Symlink as u8)
+        .with_user(0b111)
+        .with_group(0b111)
+        .with_others(0b111))?;
+
+      e5fs.lookup_path(pathname)
+    })
+  }
 
-    // Increment link count of parent inode
-    self.write_links_count_i(parent_vinode.number, parent_vinode.links_count + 1)?;
+  fn readlink(&mut self, pathname: &str)
+    -> Result<String, Errno> {
+    let vinode = self.lookup_path(pathname)?;
+    // Guard for not a link
+    if vinode.mode.file_type() != FileModeType::Symlink as u8 {
+      return Err(Errno::EINVAL(format!("e5fs::readlink: {pathname}: not a symbolic link").into()));
+    }
 
-    Ok(vinode)
+    String::from_utf8(self.read_data_i(vinode.number)?)
+      .map_err(|_| Errno::EILSEQ(format!("e5fs::readlink: {pathname}: target is not utf8").into()))
   }
 
   fn read_file(&mut self, pathname: &str, _count: AddressSize)
     -> Result<Vec<u8>, Errno> {
     let vinode = self.lookup_path(pathname)?;
     if vinode.mode.file_type() == FileModeType::Dir as u8 {
-      Err(Errno::EISDIR(format!("read_file: {pathname}: is a directory")))
+      Err(Errno::EISDIR(format!("read_file: {pathname}: is a directory").into()))
+    } else if vinode.is_device() {
+      Err(Errno::ENXIO(format!("e5fs::read_file: {pathname}: is a device file, read it through VFS").into()))
     } else {
       self.read_data_i(vinode.number)
     }
@@ -548,12 +1020,84 @@ impl Filesystem for E5FSFilesystem {
 
   fn write_file(&mut self, pathname: &str, data: &[u8])
     -> Result<VINode, Errno> {
+    self.check_writable(pathname)?;
+    self.transaction(|e5fs| {
+      e5fs.unshare_inode(pathname)?;
+      let vinode = e5fs.lookup_path(pathname)?;
+      if vinode.mode.file_type() == FileModeType::Dir as u8 {
+        return Err(Errno::EISDIR(format!("e5fs::write_file: is a directory").into()))
+      }
+      if vinode.is_device() {
+        return Err(Errno::ENXIO(format!("e5fs::write_file: {pathname}: is a device file, write it through VFS").into()))
+      }
+      e5fs.write_data_i(data.to_owned(), vinode.number, false)?;
+      // Blocks past new end are not needed anymore
+      let new_vinode: VINode = e5fs.truncate_i(vinode.number, data.len() as AddressSize)?.into();
+      Ok(new_vinode)
+    })
+  }
+
+  fn truncate(&mut self, pathname: &str, size: AddressSize)
+    -> Result<VINode, Errno> {
+    self.check_writable(pathname)?;
+    self.transaction(|e5fs| {
+      e5fs.unshare_inode(pathname)?;
+      let vinode = e5fs.lookup_path(pathname)?;
+      if vinode.mode.file_type() == FileModeType::Dir as u8 {
+        return Err(Errno::EISDIR(format!("e5fs::truncate: is a directory").into()))
+      }
+      if vinode.is_device() {
+        return Err(Errno::EINVAL(format!("e5fs::truncate: {pathname}: is a device file").into()))
+      }
+      let new_vinode: VINode = e5fs.truncate_i(vinode.number, size)?.into();
+      Ok(new_vinode)
+    })
+  }
+
+  fn read_at(&mut self, pathname: &str, offset: AddressSize, count: AddressSize)
+    -> Result<Vec<u8>, Errno> {
     let vinode = self.lookup_path(pathname)?;
     if vinode.mode.file_type() == FileModeType::Dir as u8 {
-      return Err(Errno::EISDIR(format!("e5fs::write_file: is a directory")))
+      Err(Errno::EISDIR(format!("e5fs::read_at: {pathname}: is a directory").into()))
+    } else if vinode.is_device() {
+      Err(Errno::ENXIO(format!("e5fs::read_at: {pathname}: is a device file, read it through VFS").into()))
+    } else {
+      self.read_data_at_i(vinode.number, offset, count)
     }
-    let new_vinode: VINode = self.write_data_i(data.to_owned(), vinode.number, false)?.into();
-    Ok(new_vinode)
+  }
+
+  fn write_at(&mut self, pathname: &str, offset: AddressSize, data: &[u8])
+    -> Result<VINode, Errno> {
+    self.check_writable(pathname)?;
+    self.transaction(|e5fs| {
+      e5fs.unshare_inode(pathname)?;
+      let vinode = e5fs.lookup_path(pathname)?;
+      if vinode.mode.file_type() == FileModeType::Dir as u8 {
+        return Err(Errno::EISDIR(format!("e5fs::write_at: is a directory").into()))
+      }
+      if vinode.is_device() {
+        return Err(Errno::ENXIO(format!("e5fs::write_at: {pathname}: is a device file, write it through VFS").into()))
+      }
+      let new_vinode: VINode = e5fs.write_data_at_i(data, vinode.number, offset, false)?.into();
+      Ok(new_vinode)
+    })
+  }
+
+  fn append_file(&mut self, pathname: &str, data: &[u8])
+    -> Result<VINode, Errno> {
+    self.check_writable(pathname)?;
+    self.transaction(|e5fs| {
+      e5fs.unshare_inode(pathname)?;
+      let vinode = e5fs.lookup_path(pathname)?;
+      if vinode.mode.file_type() == FileModeType::Dir as u8 {
+        return Err(Errno::EISDIR(format!("e5fs::append_file: is a directory").into()))
+      }
+      if vinode.is_device() {
+        return Err(Errno::ENXIO(format!("e5fs::append_file: {pathname}: is a device file, write it through VFS").into()))
+      }
+      let new_vinode: VINode = e5fs.write_data_i(data.to_owned(), vinode.number, true)?.into();
+      Ok(new_vinode)
+    })
   }
 
   fn read_dir(&mut self, pathname: &str)
@@ -569,7 +1113,7 @@ impl Filesystem for E5FSFilesystem {
     //     Ok(self.read_as_dir_i(vinode.number)?.into())
     //   },
     //   _ => {
-    //     Err(Errno::ENOTDIR(format!("e5fs::read_dir: not a directory: {pathname}")))
+    //     Err(Errno::ENOTDIR(format!("e5fs::read_dir: not a directory: {pathname}").into()))
     //   }
     // }
     //
@@ -579,13 +1123,26 @@ impl Filesystem for E5FSFilesystem {
     Ok(dir.into())
   }
 
+  fn readdir(&mut self, pathname: &str, offset: AddressSize, count: AddressSize)
+    -> Result<Vec<VDirectoryEntry>, Errno> {
+    let vinode = self.lookup_path(pathname)?;
+    if vinode.mode.file_type() != FileModeType::Dir as u8 {
+      return Err(Errno::ENOTDIR(format!("e5fs::readdir: not a directory: {pathname}").into()));
+    }
+    let entries = self.read_dir_entries_i(vinode.number, offset, count)?;
+
+    Ok(entries.into_iter().map(VDirectoryEntry::from).collect())
+  }
+
   fn stat(&mut self, pathname: &str) 
     -> Result<FileStat, Errno> {
     let inode_number = self.lookup_path(pathname)?.number;
+    let inode = self.read_inode(inode_number)?;
+    let rdev = inode.rdev();
+    let links_count = inode.visible_links_count();
     let INode {
       mode,
       file_size,
-      links_count,
       uid,
       gid,
       atime,
@@ -593,7 +1150,7 @@ impl Filesystem for E5FSFilesystem {
       ctime,
       btime,
       ..
-    } = self.read_inode(inode_number);
+    } = inode;
 
     Ok(FileStat {
       mode,
@@ -602,6 +1159,7 @@ impl Filesystem for E5FSFilesystem {
       links_count,
       uid,
       gid,
+      rdev,
       block_size: self.fs_info.block_size,
       atime,
       mtime,
@@ -612,29 +1170,76 @@ impl Filesystem for E5FSFilesystem {
 
   fn change_mode(&mut self, pathname: &str, mode: FileMode)
     -> Result<(), Errno> {
-    let inode_number = self.lookup_path(pathname)?.number;
-    self.write_mode_i(inode_number, mode)
-  } 
+    self.check_writable(pathname)?;
+    self.transaction(|e5fs| {
+      e5fs.unshare_inode(pathname)?;
+      let inode_number = e5fs.lookup_path(pathname)?.number;
+      e5fs.write_mode_i(inode_number, mode)
+    })
+  }
 
   fn change_owners(&mut self, pathname: &str, uid: Id, gid: Id) 
     -> Result<(), Errno> {
-    let inode_number = self.lookup_path(pathname)?.number;
-    let mut inode = self.read_inode(inode_number);
-    inode.uid = uid;
-    inode.gid = gid;
-
-    self.write_inode(&inode, inode.number)
+    self.check_writable(pathname)?;
+    self.transaction(|e5fs| {
+      e5fs.unshare_inode(pathname)?;
+      let inode_number = e5fs.lookup_path(pathname)?.number;
+      let mut inode = e5fs.read_inode(inode_number)?;
+      inode.uid = uid;
+      inode.gid = gid;
+
+      e5fs.write_inode(&inode, inode.number)
+    })
   }
 
   fn change_times(&mut self, pathname: &str, times: Times)
     -> Result<(), Errno> {
+    self.check_writable(pathname)?;
+    self.transaction(|e5fs| {
+      e5fs.unshare_inode(pathname)?;
+      let inode_number = e5fs.lookup_path(pathname)?.number;
+      let mut inode = e5fs.read_inode(inode_number)?;
+      inode.atime = times.atime;
+      inode.mtime = times.mtime;
+      inode.ctime = times.ctime;
+      inode.btime = times.btime;
+      e5fs.write_inode(&inode, inode.number)
+    })
+  }
+
+  fn get_xattr(&mut self, pathname: &str, name: &str)
+    -> Result<Vec<u8>, Errno> {
+    let inode_number = self.lookup_path(pathname)?.number;
+    self.read_xattrs_i(inode_number)?
+      .remove(name)
+      .ok_or_else(|| Errno::ENODATA(format!("e5fs: {pathname}: no attribute '{name}'").into()))
+  }
+
+  fn set_xattr(&mut self, pathname: &str, name: &str, value: Option<&[u8]>)
+    -> Result<(), Errno> {
+    self.check_writable(pathname)?;
+    self.transaction(|e5fs| {
+      e5fs.unshare_inode(pathname)?;
+      let inode_number = e5fs.lookup_path(pathname)?.number;
+      let mut xattrs = e5fs.read_xattrs_i(inode_number)?;
+      match value {
+        Some(value) => {
+          xattrs.insert(name.to_owned(), value.to_owned());
+        },
+        None => {
+          xattrs
+            .remove(name)
+            .ok_or_else(|| Errno::ENODATA(format!("e5fs: {pathname}: no attribute '{name}'").into()))?;
+        },
+      }
+      e5fs.write_xattrs_i(inode_number, &xattrs)
+    })
+  }
+
+  fn list_xattr(&mut self, pathname: &str)
+    -> Result<Vec<String>, Errno> {
     let inode_number = self.lookup_path(pathname)?.number;
-    let mut inode = self.read_inode(inode_number);
-    inode.atime = times.atime;
-    inode.mtime = times.mtime;
-    inode.ctime = times.ctime;
-    inode.btime = times.btime;
-    self.write_inode(&inode, inode.number)
+    Ok(self.read_xattrs_i(inode_number)?.into_keys().collect())
   }
 
   // Поиск файла в файловой системе. Возвращает INode фала.
@@ -647,7 +1252,7 @@ impl Filesystem for E5FSFilesystem {
     // Base case: 
     //   lookup_path /
     if split_pathname == (Vec::new(), String::from("/")) {
-      let inode = self.read_inode(self.fs_info.root_inode_number);
+      let inode = self.read_inode(self.fs_info.root_inode_number)?;
       return Ok(inode.into());
     };
 
@@ -665,21 +1270,47 @@ impl Filesystem for E5FSFilesystem {
     let mut inode_number = self.fs_info.root_inode_number;
 
     for component in everything_else {
-      let dir = self.read_as_dir_i(inode_number)?;
-      inode_number = dir.entries
-        .get(&component)
-        .map(|entry| entry.inode_number)
-        .ok_or(Errno::ENOENT(format!("e5fs.lookup_path: no such component: {component}")))?;
+      inode_number = self.find_dir_entry_i(inode_number, &component)?
+        .ok_or(Errno::ENOENT(format!("e5fs.lookup_path: no such component: {component}").into()))?;
     }
 
     // After we advanced our inode_number for every 
-    // `component` in `everything_else`, read that last
-    // dir and read `final_component`'s inode from it
-    let dir = self.read_as_dir_i(inode_number)?;
-    dir.entries
-      .get(&final_component)
-      .map(|entry| self.read_inode(entry.inode_number).into())
-      .ok_or(Errno::ENOENT(format!("e5fs.lookup_path: no such file or directory {final_component} (get(final_component)) (pathname: {pathname})")))
+    // `component` in `everything_else`, look in that last
+    // dir for `final_component` and read its inode
+    let inode_number = self.find_dir_entry_i(inode_number, &final_component)?
+      .ok_or(Errno::ENOENT(format!("e5fs.lookup_path: no such file or directory {final_component} (get(final_component)) (pathname: {pathname})").into()))?;
+
+    Ok(self.read_inode(inode_number)?.into())
+  }
+
+  /// Free counts come from superblock, blocks of `fbl` don't count
+  fn statfs(&mut self, _pathname: &str)
+    -> Result<FilesystemStat, Errno> {
+    Ok(FilesystemStat {
+      block_size: self.fs_info.block_size,
+      blocks_count: self.fs_info.first_fbl_block_number,
+      free_blocks_count: self.superblock.free_blocks_count,
+      reserved_blocks_count: self.superblock.reserved_blocks_count,
+      inodes_count: self.fs_info.inodes_count,
+      free_inodes_count: self.superblock.free_inodes_count,
+    })
+  }
+
+  /// Flush pages of storage that were written and not flushed yet
+  fn sync(&mut self)
+    -> Result<(), Errno> {
+    self.fs_info.realfile
+      .write().unwrap()
+      .flush()
+      .with_context(|| "e5fs: cannot flush storage")
+  }
+
+  fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+    self.clock = clock;
+  }
+
+  fn set_current_user(&mut self, uid: Id, _gid: Id) {
+    self.current_uid = uid;
   }
 
 fn name(&self) -> String { 
@@ -689,98 +1320,612 @@ fn name(&self) -> String {
 fn as_any(&mut self) -> &mut dyn Any {
       self
     } 
+
+  fn as_any_ref(&self) -> &dyn Any {
+    self
+  }
 }
 
 impl E5FSFilesystem {
   /// Read filesystem from device (file on host) path
   pub fn from(device_realpath: &str) -> Result<Self, Errno> {
-    let superblock = E5FSFilesystem::read_superblock(device_realpath);
+    E5FSFilesystem::from_storage(E5FSFilesystem::open_image(device_realpath)?)
+  }
 
-    let fs_info = 
-      E5FSFilesystemBuilder::new(
-        device_realpath, 
-        superblock.inode_table_percentage, 
-        superblock.block_data_size,
-      )
-      .unwrap();
+  /// Storage of image on host at `realpath`. Blocks and inodes are
+  /// small and accessed all the time, so they are cached instead of
+  /// going to host every time. Devices of machine are cached by
+  /// their drivers, see `SharedStorage`
+  fn open_image(realpath: &str) -> Result<Box<dyn BlockStorage>, Errno> {
+    Ok(Box::new(BufferedStorage::new(Box::new(FileRegion::open(realpath)?))))
+  }
 
-    Ok(Self {
-      superblock,
-      fs_info,
-    })
+  /// Read filesystem from block device storage (like a partition),
+  /// finishing the last operation from journal if host went down in
+  /// the middle of it, and mark it dirty until `unmount`
+  pub fn from_storage(storage: Box<dyn BlockStorage>) -> Result<Self, Errno> {
+    E5FSFilesystem::from_storage_with_passphrase(storage, None)
   }
 
-  /// Create new filesystem and write it to disk
-  pub fn mkfs(device_realpath: &str, inode_table_percentage: f32, block_data_size: AddressSize) -> Result<Self, Errno> {
-    let mut fs_info = E5FSFilesystemBuilder::new(
-        device_realpath, 
-        inode_table_percentage, 
-        block_data_size,
-      )
-      .unwrap();
+  /// Same as `from_storage`, unlocking encrypted filesystem
+  /// with `passphrase`
+  ///
+  /// Errors:
+  /// EACCES -> filesystem is encrypted and passphrase is wrong
+  ///           or not given
+  pub fn from_storage_with_passphrase(storage: Box<dyn BlockStorage>, passphrase: Option<&str>) -> Result<Self, Errno> {
+    E5FSFilesystem::open(storage, passphrase, false)
+  }
+
+  /// Same as `from_storage_with_passphrase`, but nothing is ever
+  /// written to storage, not even on `unmount`: journal is not
+  /// replayed and changes fail with EROFS
+  pub fn from_storage_read_only(storage: Box<dyn BlockStorage>, passphrase: Option<&str>) -> Result<Self, Errno> {
+    E5FSFilesystem::open(storage, passphrase, true)
+  }
+
+  fn open(mut storage: Box<dyn BlockStorage>, passphrase: Option<&str>, read_only: bool) -> Result<Self, Errno> {
+    let (superblock, superblock_backup) = E5FSFilesystem::find_superblock(storage.as_mut())?;
+    if let Some(address) = superblock_backup {
+      tracing::warn!("e5fs: superblock is corrupt, using backup at {address}");
+    }
+
+    let mut fs_info = E5FSFilesystemBuilder::with_superblock(storage, &superblock)
+      .or_else(|message| Err(Errno::EILSEQ(format!("e5fs: bad superblock: {message}").into())))?;
+
+    // Superblock may be one of the replayed writes
+    let realfile = fs_info.realfile.get_mut().unwrap();
+    let replayed = match read_only {
+      true => false,
+      false => realfile.replay().with_context(|| "e5fs: cannot replay journal")?,
+    };
+    let superblock = match replayed {
+      true => {
+        tracing::info!("e5fs: replayed journal of unfinished operation");
+        match E5FSFilesystem::read_superblock_from(realfile)? {
+          // Like one of `resize` or `upgrade`
+          replayed if replayed.is_e5fs() => {
+            fs_info.format_version = replayed.format_version;
+            fs_info
+              .set_counts(replayed.inodes_count, replayed.blocks_count)
+              .or_else(|message| Err(Errno::EILSEQ(format!("e5fs: bad superblock: {message}").into())))?;
+            replayed
+          },
+          _ => superblock,
+        }
+      },
+      false => superblock,
+    };
+    if superblock.format_version > FORMAT_VERSION {
+      return Err(Errno::EINVAL(format!(
+        "e5fs: format version {} is newer than {FORMAT_VERSION}, which is the latest supported one",
+        superblock.format_version,
+      ).into()));
+    }
 
     let mut e5fs = Self {
-      superblock: Superblock::new(&mut fs_info),
+      superblock,
       fs_info,
+      needs_check: superblock.state != STATE_CLEAN || superblock_backup.is_some(),
+      superblock_backup,
+      key: None,
+      free_blocks: BlockBitmap::default(),
+      current_uid: ROOT_UID,
+      read_only,
+      clock: host_clock(),
     };
 
-    let superblock = Superblock::new(&mut e5fs.fs_info);
+    let crypt_header = E5FSFilesystem::read_crypt_header(&mut *e5fs.fs_info.realfile.write().unwrap(), &e5fs.superblock);
+    if let Some(header) = crypt_header {
+      let passphrase = passphrase
+        .ok_or_else(|| Errno::EACCES(String::from("e5fs: filesystem is encrypted, passphrase is needed").into()))?;
+      e5fs.key = Some(header.unlock(passphrase).ok_or_else(|| Errno::EACCES(String::from("e5fs: wrong passphrase").into()))?);
+    }
 
-    // 1. Write Superblock
-    e5fs.write_superblock(&superblock).unwrap();
+    // Backup doesn't follow cache of free inodes, inodes there may
+    // have been claimed since it was written
+    if superblock_backup.is_some() {
+      for index in 0..e5fs.superblock.free_inode_numbers.len() {
+        let inode_number = e5fs.superblock.free_inode_numbers[index];
+        if inode_number != NO_ADDRESS
+          && (inode_number >= e5fs.fs_info.inodes_count || !fsck::is_free(&e5fs.read_inode(inode_number)?))
+        {
+          e5fs.superblock.free_inode_numbers[index] = NO_ADDRESS;
+        }
+      }
+    }
+
+    // Puts superblock back in place if it came from backup
+    if !read_only {
+      e5fs.set_state(STATE_DIRTY)?;
+    }
+    // Counts on disk are written with every transaction that changes
+    // them, but filesystem that was not unmounted may be older than
+    // them, and one made before format version 1 never had them
+    match e5fs.needs_check || e5fs.superblock.format_version == 0 {
+      true => e5fs.count_free()?,
+      false => e5fs.free_blocks = e5fs.read_free_blocks()?,
+    }
+    // Read-only one is read the old way, which works for every version
+    if !read_only && e5fs.upgrade()? {
+      tracing::info!("e5fs: upgraded filesystem to format version {}", e5fs.superblock.format_version);
+    }
+
+    Ok(e5fs)
+  }
+
+  /// Superblock of filesystem on `storage`, or first backup of it
+  /// that makes sense if it is corrupt.
+  /// Returns: it and address of backup it came from
+  fn find_superblock(storage: &mut dyn BlockStorage) -> Result<(Superblock, Option<AddressSize>), Errno> {
+    let device_size = storage.size();
+    let check = |superblock: &Superblock| match superblock.is_e5fs() {
+      true => E5FSFilesystemBuilder::check_superblock(superblock, device_size)
+        .map(|_| ())
+        .or_else(|message| Err(Errno::EILSEQ(format!("e5fs: bad superblock: {message}").into()))),
+      false => Err(Errno::EILSEQ(String::from("e5fs: no e5fs on device").into())),
+    };
+
+    let superblock = E5FSFilesystem::read_superblock_from(storage)?;
+    let Err(errno) = check(&superblock) else {
+      return Ok((superblock, None));
+    };
+
+    SUPERBLOCK_BACKUP_ADDRESSES
+      .into_iter()
+      .filter(|&address| (address + Superblock::size()) as u64 <= device_size)
+      .find_map(|address| {
+        let backup = E5FSFilesystem::read_superblock_at(storage, address).ok()?;
+        check(&backup).ok().map(|_| (backup, Some(address)))
+      })
+      .ok_or(errno)
+  }
+
+  /// Whether filesystem on `storage` is encrypted,
+  /// so that it needs a passphrase to be read
+  pub fn is_encrypted(storage: &mut dyn BlockStorage) -> bool {
+    E5FSFilesystem::find_superblock(storage)
+      .map_or(false, |(superblock, _)| E5FSFilesystem::read_crypt_header(storage, &superblock).is_some())
+  }
+
+  /// Header of encryption in first block of filesystem
+  /// that `superblock` describes, if there is one
+  fn read_crypt_header(storage: &mut dyn BlockStorage, superblock: &Superblock) -> Option<CryptHeader> {
+    // Blocks are at the very end of filesystem
+    let first_block_address = superblock
+      .blocks_count
+      .checked_mul(superblock.block_size)
+      .and_then(|blocks_size| superblock.filesystem_size.checked_sub(blocks_size))?;
+    let address = first_block_address + CRYPT_HEADER_BLOCK_NUMBER * superblock.block_size;
+
+    let mut bytes = [0u8; CryptHeader::SIZE];
+    storage.seek(SeekFrom::Start(address as u64)).ok()?;
+    storage.read_exact(&mut bytes).ok()?;
+
+    CryptHeader::parse(&bytes)
+  }
+
+  /// Mark filesystem clean and flush everything to storage.
+  /// It should not be used afterwards
+  pub fn unmount(&mut self) -> Result<(), Errno> {
+    if self.read_only {
+      return Ok(());
+    }
 
-    // 2. Write fbl (free_block_list)
-    e5fs.write_fbl();
-
-    // 3. Write root dir - first allocated file (inode) 
-    //    will always be 0-th inode in inode table
-    let (root_inode_number, _) = e5fs.allocate_file()?;
-    let mut root_dir = Directory::new();
-    root_dir.insert(root_inode_number, "..").expect("this should succeed");
-    root_dir.insert(root_inode_number, ".").expect("this should succeed");
-    e5fs.write_dir_i(&root_dir, root_inode_number)?;
-
-    // Set mode, time, link count, gid and uid to root inode
-    let mut root_inode = e5fs.read_inode(root_inode_number);
-    root_inode.mode = FileMode::zero()
-      .with_free(0)
-      .with_file_type(FileModeType::Dir as u8)
-      .with_user(0o7)
-      .with_group(0o5)
-      .with_others(0o5);
-    root_inode.atime = unixtime();
-    root_inode.mtime = unixtime();
-    root_inode.ctime = unixtime();
-    root_inode.btime = unixtime();
-    root_inode.links_count = 2;
-    root_inode.uid = 0;
-    root_inode.gid = 0;
-    e5fs.write_inode(&root_inode, root_inode_number)?;
+    self.set_state(STATE_CLEAN)
+  }
+
+  fn set_state(&mut self, state: u32) -> Result<(), Errno> {
+    self.transaction(|e5fs| {
+      e5fs.superblock.state = state;
+      e5fs.write_superblock(&e5fs.superblock.clone())
+    })?;
+
+    self.fs_info.realfile
+      .write().unwrap()
+      .flush()
+      .with_context(|| "e5fs: cannot flush storage")
+  }
+
+  /// Run `operation` as one transaction: everything it writes but
+  /// contents of regular files gets to its place only after all of it
+  /// is in the journal. Operations that `operation` runs are a part of it.
+  /// If it fails or doesn't fit in the journal, nothing it wrote gets
+  /// there, and free counts and blocks are as they were before it
+  fn transaction<T>(&mut self, operation: impl FnOnce(&mut Self) -> Result<T, Errno>) -> Result<T, Errno> {
+    self.fs_info.realfile.write().unwrap().begin();
+    let superblock = self.superblock;
+    let free_blocks = self.free_blocks.clone();
+    let mut result = operation(self);
+    // Free counts go to disk with the transaction that changed them
+    if result.is_ok()
+      && (self.superblock.free_inodes_count, self.superblock.free_blocks_count) != (superblock.free_inodes_count, superblock.free_blocks_count)
+    {
+      result = self.write_superblock(&self.superblock.clone()).and(result);
+    }
+
+    if result.is_err() {
+      self.fs_info.realfile.write().unwrap().abort();
+    } else {
+      let committed = self.fs_info.realfile.write().unwrap().commit();
+      result = committed.with_context(|| "e5fs: cannot commit journal").and(result);
+    }
+    if result.is_err() {
+      self.superblock = superblock;
+      self.free_blocks = free_blocks;
+    }
+
+    result
+  }
+
+  /// Create new filesystem and write it to disk
+  pub fn mkfs(device_realpath: &str, inode_table_percentage: f32, block_data_size: AddressSize) -> Result<Self, Errno> {
+    E5FSFilesystem::mkfs_storage(E5FSFilesystem::open_image(device_realpath)?, inode_table_percentage, block_data_size)
+  }
+
+  /// Create new filesystem and write it to block device storage
+  pub fn mkfs_storage(storage: Box<dyn BlockStorage>, inode_table_percentage: f32, block_data_size: AddressSize) -> Result<Self, Errno> {
+    E5FSFilesystem::mkfs_storage_with_passphrase(storage, inode_table_percentage, block_data_size, None)
+  }
+
+  /// Same as `mkfs_storage`, encrypting data blocks with a key
+  /// derived from `passphrase` if it is given
+  pub fn mkfs_storage_with_passphrase(
+    storage: Box<dyn BlockStorage>,
+    inode_table_percentage: f32,
+    block_data_size: AddressSize,
+    passphrase: Option<&str>,
+  ) -> Result<Self, Errno> {
+    let mut fs_info = E5FSFilesystemBuilder::with_storage(
+        storage, 
+        inode_table_percentage, 
+        block_data_size,
+      )
+      .or_else(|message| Err(Errno::EINVAL(format!("e5fs::mkfs: {message}").into())))?;
+
+    let mut e5fs = Self {
+      superblock: Superblock::new(&mut fs_info),
+      fs_info,
+      needs_check: false,
+      superblock_backup: None,
+      key: None,
+      free_blocks: BlockBitmap::default(),
+      current_uid: ROOT_UID,
+      read_only: false,
+      clock: host_clock(),
+    };
+
+    e5fs.fs_info.realfile
+      .write().unwrap()
+      .format()
+      .with_context(|| "e5fs::mkfs: cannot write journal")?;
+
+    // Nothing is there to keep yet, so it is written in place,
+    // fbl of big device wouldn't fit in journal anyway
+    let write_filesystem = |e5fs: &mut Self| {
+      let superblock = e5fs.superblock;
+
+      // 1. Write Superblock
+      e5fs.write_superblock(&superblock)?;
+
+      // 2. Write fbl (free_block_list), every block is free
+      e5fs.write_references(vec![0; e5fs.fs_info.first_fbl_block_number as usize])?;
+
+      // 3. Write header of encryption to the first block, before
+      //    anything else gets encrypted
+      if let Some(passphrase) = passphrase {
+        let (header, key) = CryptHeader::new(passphrase);
+        let mut data = header.to_bytes();
+        data.resize(e5fs.fs_info.block_data_size as usize, 0);
+        e5fs.claim_block(CRYPT_HEADER_BLOCK_NUMBER)?;
+        e5fs.write_block(&Block { data }, CRYPT_HEADER_BLOCK_NUMBER)?;
+        e5fs.key = Some(key);
+      }
+
+      // 4. Write root dir - first allocated file (inode) 
+      //    will always be 0-th inode in inode table
+      let (root_inode_number, _) = e5fs.allocate_file()?;
+      let mut root_dir = Directory::new();
+      root_dir.insert(root_inode_number, "..").expect("this should succeed");
+      root_dir.insert(root_inode_number, ".").expect("this should succeed");
+      e5fs.write_dir_i(&root_dir, root_inode_number)?;
+
+      // Set mode, time, link count, gid and uid to root inode
+      let mut root_inode = e5fs.read_inode(root_inode_number)?;
+      root_inode.mode = FileMode::zero()
+        .with_free(0)
+        .with_file_type(FileModeType::Dir as u8)
+        .with_user(0o7)
+        .with_group(0o5)
+        .with_others(0o5);
+      let now = e5fs.clock.now();
+      root_inode.atime = now;
+      root_inode.mtime = now;
+      root_inode.ctime = now;
+      root_inode.btime = now;
+      root_inode.links_count = 2;
+      root_inode.uid = 0;
+      root_inode.gid = 0;
+      e5fs.write_inode(&root_inode, root_inode_number)?;
+
+      // 5. Write backups of superblock, then superblock again
+      //    with free counts of everything above
+      e5fs.update_superblock_backups()?;
+      e5fs.write_superblock(&e5fs.superblock.clone())
+    };
+    write_filesystem(&mut e5fs)?;
+    e5fs.fs_info.realfile
+      .write().unwrap()
+      .flush()
+      .with_context(|| "e5fs::mkfs: cannot flush storage")?;
 
     Ok(e5fs)
   }
 
+  /// Set volume label, empty one unsets it
+  pub fn set_label(&mut self, label: &str) -> Result<(), Errno> {
+    let label = E5FSFilesystem::parse_label(label)?;
+    if self.fs_info.superblock_size == LEGACY_SUPERBLOCK_SIZE {
+      return Err(Errno::ENOTSUP(String::from("e5fs: superblock of filesystem this old has no room for label").into()));
+    }
+
+    self.transaction(|e5fs| {
+      e5fs.superblock.label = label;
+      e5fs.write_superblock(&e5fs.superblock.clone())?;
+      e5fs.update_superblock_backups()
+    })
+  }
+
+  /// Keep `percentage` of data blocks for root: other users
+  /// get ENOSPC when only that many blocks are free
+  ///
+  /// Errors:
+  /// EINVAL -> percentage is not between 0 and `MAX_RESERVED_BLOCKS_PERCENTAGE`
+  pub fn set_reserved_blocks_percentage(&mut self, percentage: f32) -> Result<(), Errno> {
+    if !(0.0..=MAX_RESERVED_BLOCKS_PERCENTAGE).contains(&percentage) {
+      return Err(Errno::EINVAL(format!(
+        "e5fs: reserved blocks percentage must be between 0 and {MAX_RESERVED_BLOCKS_PERCENTAGE}, not {percentage}",
+      ).into()));
+    }
+
+    self.transaction(|e5fs| {
+      e5fs.superblock.reserved_blocks_count = (e5fs.fs_info.first_fbl_block_number as f32 * percentage / 100.0) as AddressSize;
+      e5fs.write_superblock(&e5fs.superblock.clone())?;
+      e5fs.update_superblock_backups()
+    })
+  }
+
+  /// Label as it is kept in superblock, zero-padded
+  ///
+  /// Errors:
+  /// EINVAL -> label is longer than `LABEL_MAX_LEN` bytes or can't be
+  ///           a name in `/dev/disk/by-label`
+  pub fn parse_label(label: &str) -> Result<[u8; LABEL_MAX_LEN], Errno> {
+    if label.len() > LABEL_MAX_LEN {
+      return Err(Errno::EINVAL(format!("label '{label}' is longer than {LABEL_MAX_LEN} bytes").into()));
+    }
+    if label.contains(['/', '\0']) || label == "." || label == ".." {
+      return Err(Errno::EINVAL(format!("label '{label}' can't be a file name").into()));
+    }
+
+    let mut bytes = [0; LABEL_MAX_LEN];
+    bytes[..label.len()].copy_from_slice(label.as_bytes());
+
+    Ok(bytes)
+  }
+
+  /// What `probe` finds on device of filesystem
+  pub fn identity(&self) -> FilesystemIdentity {
+    FilesystemIdentity {
+      uuid: Uuid::from_bytes(self.superblock.uuid).to_string(),
+      label: self.superblock.label(),
+    }
+  }
+
+  /// Grow filesystem to fill `storage` - its device, opened again
+  /// after it grew. Inode table grows with device like mkfs would
+  /// make it, used blocks are moved past it and new blocks are added
+  /// to `fbl`, so it works on a mounted filesystem too. Blocks are
+  /// moved one at a time, each in a transaction of its own, and
+  /// filesystem takes new size in the last one. If machine goes down
+  /// in between, blocks moved so far are in their new places only.
+  /// Returns: whether filesystem grew
+  ///
+  /// Errors:
+  /// EINVAL -> device is smaller than filesystem
+  /// ENOSPC -> new `fbl` doesn't fit in the journal
+  pub fn resize(&mut self, storage: Box<dyn BlockStorage>) -> Result<bool, Errno> {
+    let device_size = storage.size().min(AddressSize::MAX as u64) as AddressSize;
+    if device_size < self.fs_info.filesystem_size {
+      return Err(Errno::EINVAL(String::from("e5fs::resize: device is smaller than filesystem, shrinking is not supported").into()));
+    }
+
+    let old_inodes_count = self.fs_info.inodes_count;
+    let old_first_fbl_block_number = self.fs_info.first_fbl_block_number;
+    let (inodes_count, blocks_count) = E5FSFilesystemBuilder::counts(
+      device_size,
+      self.fs_info.journal_size,
+      self.fs_info.inode_table_percentage,
+      self.fs_info.block_size,
+    );
+    let inodes_count = inodes_count.max(old_inodes_count);
+    let blocks_count = blocks_count.max(self.fs_info.blocks_count);
+    if inodes_count == old_inodes_count && blocks_count == self.fs_info.blocks_count {
+      return Ok(false);
+    }
+
+    // Last transaction has all of new `fbl`, so it must
+    // be known to fit in the journal before anything is moved
+    let superblocks_count = 1 + SUPERBLOCK_BACKUP_ADDRESSES.len();
+    let extents = std::iter::once(self.fs_info.blocks_needed_for_fbl(blocks_count) * self.fs_info.block_size)
+      .chain(std::iter::repeat(self.fs_info.superblock_size).take(superblocks_count))
+      .map(u64::from);
+    if !self.fs_info.realfile.read().unwrap().fits(extents) {
+      return Err(Errno::ENOSPC(String::from("e5fs::resize: new fbl doesn't fit in journal").into()));
+    }
+
+    // Nothing is pending outside of transactions, so storage
+    // can just be swapped for the bigger one
+    self.fs_info.realfile = RwLock::new(JournaledStorage::new(
+      storage,
+      self.fs_info.superblock_size as u64,
+      self.fs_info.journal_size as u64,
+    ));
+    self.fs_info.device_size = device_size;
+
+    // Read `fbl` before anything is written over it, what makes
+    // no sense there is left for fsck, block is not free
+    let mut references = self.read_references()?
+      .into_iter()
+      .map(|references| references.unwrap_or(1))
+      .collect::<Vec<_>>();
+    // Backups of superblock stay at their addresses, not blocks
+    for block_number in self.superblock_backup_blocks() {
+      references[block_number as usize] = 0;
+    }
+
+    let old_first_block_address = self.fs_info.first_block_address;
+    self.fs_info
+      .set_counts(inodes_count, blocks_count)
+      .or_else(|message| Err(Errno::EINVAL(format!("e5fs::resize: {message}").into())))?;
+
+    // Every block moves forward by as much as inode table grew, so
+    // new place of a block only covers old places of blocks after
+    // it: going from the last one, those are moved already. Block
+    // number stays the same, so bytes are copied as they are,
+    // encrypted or not
+    let shift = self.fs_info.first_block_address - old_first_block_address;
+    let mut bytes = vec![0u8; self.fs_info.block_size as usize];
+    for block_number in (0..old_first_fbl_block_number).rev() {
+      if references[block_number as usize] == 0 {
+        continue;
+      }
+
+      let address = old_first_block_address + block_number * self.fs_info.block_size;
+      self.transaction(|e5fs| {
+        e5fs.read_storage(address, &mut bytes)?;
+        e5fs.write_storage(address + shift, &bytes)
+      })
+      .map_err(|errno| errno.context(format!("e5fs::resize: cannot move block {block_number}")))?;
+    }
+    // Blocks that were not there are free
+    references.resize(self.fs_info.first_fbl_block_number as usize, 0);
+
+    // New inodes are where blocks were, nothing points to them
+    // before the last transaction, so they go a block at a time
+    let inodes_per_block = (self.fs_info.block_size / self.fs_info.inode_size).max(1);
+    for first_inode_number in (old_inodes_count..inodes_count).step_by(inodes_per_block as usize) {
+      self.transaction(|e5fs| {
+        for inode_number in first_inode_number..(first_inode_number + inodes_per_block).min(inodes_count) {
+          e5fs.write_inode(&INode { number: inode_number, ..Default::default() }, inode_number)?;
+        }
+        Ok(())
+      })?;
+    }
+
+    self.transaction(|e5fs| {
+      e5fs.write_references(references)?;
+
+      let mut superblock = e5fs.superblock;
+      superblock.filesystem_size = e5fs.fs_info.filesystem_size;
+      superblock.inode_table_size = e5fs.fs_info.inode_table_size;
+      superblock.free_inodes_count += inodes_count - superblock.inodes_count;
+      superblock.free_blocks_count += e5fs.fs_info.first_fbl_block_number - old_first_fbl_block_number;
+      superblock.inodes_count = inodes_count;
+      superblock.blocks_count = blocks_count;
+      superblock.first_fbl_block_number = e5fs.fs_info.first_fbl_block_number;
+      e5fs.superblock = superblock;
+      e5fs.write_superblock(&superblock)?;
+      e5fs.update_superblock_backups()
+    })?;
+
+    self.fs_info.realfile
+      .write().unwrap()
+      .flush()
+      .with_context(|| "e5fs::resize: cannot flush storage")?;
+
+    Ok(true)
+  }
+
+  /// Backups of superblock that land on data blocks of filesystem:
+  /// `(address, blocks it takes)`
+  fn superblock_backups(&self) -> Vec<(AddressSize, Range<AddressSize>)> {
+    let first_block_address = self.fs_info.first_block_address;
+    let block_size = self.fs_info.block_size;
+
+    SUPERBLOCK_BACKUP_ADDRESSES
+      .into_iter()
+      .filter(|&address| address >= first_block_address)
+      .map(|address| {
+        let first_block_number = (address - first_block_address) / block_size;
+        let blocks_end = (address + self.fs_info.superblock_size - first_block_address).div_ceil(block_size);
+        (address, first_block_number..blocks_end)
+      })
+      .filter(|(_, block_numbers)| block_numbers.end <= self.fs_info.first_fbl_block_number)
+      .collect()
+  }
+
+  /// Whether there is a backup of superblock at `address`: its blocks
+  /// are claimed and have superblock of this filesystem. Backup
+  /// that can't be read is not there
+  fn has_superblock_backup(&self, address: AddressSize, block_numbers: Range<AddressSize>) -> bool {
+    block_numbers.into_iter().all(|block_number| self.read_block_references(block_number).is_ok_and(|references| references == Some(1)))
+      && E5FSFilesystem::read_superblock_at(&mut *self.fs_info.realfile.write().unwrap(), address)
+        .map_or(false, |backup| backup.is_e5fs() && backup.uuid == self.superblock.uuid)
+  }
+
+  /// Blocks that backups of superblock take
+  fn superblock_backup_blocks(&self) -> Vec<AddressSize> {
+    self.superblock_backups()
+      .into_iter()
+      .filter(|(address, block_numbers)| self.has_superblock_backup(*address, block_numbers.clone()))
+      .flat_map(|(_, block_numbers)| block_numbers)
+      .collect()
+  }
+
+  /// Blocks that are not of files, but of filesystem itself:
+  /// those of superblock backups and header of encryption
+  fn reserved_blocks(&self) -> Vec<AddressSize> {
+    let mut block_numbers = self.superblock_backup_blocks();
+    if self.key.is_some() {
+      block_numbers.push(CRYPT_HEADER_BLOCK_NUMBER);
+    }
+
+    block_numbers
+  }
+
+  /// Write superblock to its backups, claiming blocks for new ones.
+  /// Backups that would land on blocks of files are left out
+  fn update_superblock_backups(&mut self) -> Result<(), Errno> {
+    for (address, block_numbers) in self.superblock_backups() {
+      let is_free = block_numbers
+        .clone()
+        .try_fold(true, |is_free, block_number| {
+          Ok::<_, Errno>(is_free && self.read_block_references(block_number)? == Some(0))
+        })?;
+      if is_free {
+        for block_number in block_numbers {
+          self.claim_block(block_number)?;
+        }
+      } else if !self.has_superblock_backup(address, block_numbers) {
+        continue;
+      }
+
+      self.write_superblock_at(&self.superblock.clone(), address)?;
+    }
+
+    Ok(())
+  }
+
+  /// Write whole directory, hashed, see `hashdir`
   fn write_dir_i(&mut self, dir: &Directory, inode_number: AddressSize) -> Result<INode, Errno> {
-    // We know that we're getting wrong dir data at this point already
     // Convert `Directory` to bytes
-    let entries_count_bytes = dir.entries_count.to_le_bytes().as_slice().to_owned();
-    let entries_bytes = dir.entries.iter().fold(Vec::new(), |mut bytes, (_name, entry)| {
-      bytes.write(entry.inode_number.to_le_bytes().as_slice()).unwrap();
-      bytes.write(entry.rec_len.to_le_bytes().as_slice()).unwrap();
-      bytes.write(entry.name_len.to_le_bytes().as_slice()).unwrap();
-      bytes.write(entry.name.as_bytes()).unwrap();
-      bytes
-    });
-
-    // Write them to one `Vec`
-    let mut data = Vec::new();
-    data.write(&entries_count_bytes)
-      .or(Err(Errno::EIO(format!("write_dir: can't write entries_count_bytes to data"))))?;
-    data.write(&entries_bytes)
-      .or(Err(Errno::EIO(format!("write_dir: can't write entries_bytes to data"))))?;
+    let data = hashdir::hashed_dir_to_bytes(dir, self.fs_info.block_size, self.block_slots_count())?;
+    let size = data.len() as AddressSize;
 
-    // Write `Vec` to file
-    let new_inode = self.write_data_i(data, inode_number, false)?;
+    // Write `Vec` to file, buckets past new end are not needed anymore
+    self.write_data_i(data, inode_number, false)?;
+    let new_inode = self.truncate_i(inode_number, size)?;
 
     // NOTICE: Set inode mode to be directory (???)
     //new_inode.mode = new_inode.mode.with_type(FileModeType::Dir as u8);
@@ -791,76 +1936,198 @@ impl E5FSFilesystem {
 
   fn read_as_dir_i(&self, inode_number: AddressSize) -> Result<Directory, Errno> {
     let dir_bytes = self.read_data_i(inode_number)?;
-    let directory = E5FSFilesystem::parse_directory(&self.fs_info, dir_bytes)?;
+    let Some(buckets) = hashdir::buckets(&dir_bytes, self.fs_info.block_size) else {
+      return E5FSFilesystem::parse_directory(&self.fs_info, dir_bytes);
+    };
 
-    Ok(directory)
+    let mut entries = BTreeMap::new();
+    for (bucket, block) in buckets.enumerate() {
+      let bucket_entries = block
+        .strip_prefix(&hashdir::BUCKET_MAGIC)
+        .ok_or_else(|| Errno::EILSEQ(format!("e5fs: directory {inode_number} has damaged bucket {bucket}").into()))?;
+      entries.extend(E5FSFilesystem::parse_directory(&self.fs_info, bucket_entries.to_owned())?.entries);
+    }
+
+    Ok(Directory::from(entries))
+  }
+
+  /// Write `data` to blocks of inode from the start or,
+  /// with `append`, from the end of what is there
+  fn write_data_i(&mut self, data: Vec<u8>, inode_number: AddressSize, append: bool) -> Result<INode, Errno> {
+    match append {
+      true => {
+        let file_size = self.read_inode(inode_number)?.file_size;
+        self.write_data_at_i(&data, inode_number, file_size, false)
+      },
+      false => self.write_data_at_i(&data, inode_number, 0, true),
+    }
   }
 
-  fn write_data_i(&mut self, data: Vec<u8>, inode_number: AddressSize, _append: bool) -> Result<INode, Errno> {
-    let inode = self.read_inode(inode_number);
+  /// Write `data` to blocks of inode from `offset`, growing it if
+  /// needed. With `truncate`, file ends where `data` does.
+  /// Blocks are only claimed for `data`, gap before it is a hole
+  fn write_data_at_i(&mut self, data: &[u8], inode_number: AddressSize, offset: AddressSize, truncate: bool) -> Result<INode, Errno> {
+    let inode = self.read_inode(inode_number)?;
+    let block_size = self.fs_info.block_size;
+    let end = offset + data.len() as AddressSize;
+
+    // Whole blocks are written from the one with end of file or
+    // `offset`, whichever is first, so gap before `data` gets zeros
+    // and first and last ones keep bytes that are already there
+    let first_block_index = offset.min(inode.file_size) / block_size;
+    let blocks_end = end.div_ceil(block_size).max(first_block_index);
+    let blocks_start = first_block_index * block_size;
+    if blocks_end > self.block_slots_count() {
+      return Err(Errno::EIO(String::from("not enough block slots in inode").into()));
+    }
+    let block_numbers = self.block_numbers_i(&inode)?;
+
+    let mut buffer = vec![0u8; ((blocks_end - first_block_index) * block_size) as usize];
+    let kept_end = match truncate {
+      true => offset.min(inode.file_size),
+      false => inode.file_size,
+    };
+    for block_index in [first_block_index, blocks_end.saturating_sub(1)].into_iter().dedup() {
+      let block_start = block_index * block_size;
+      let kept = block_start..(block_start + block_size).min(kept_end);
+      let block_number = block_numbers[block_index as usize];
+      // Holes have nothing to keep
+      if block_index >= blocks_end || kept.is_empty() || block_number == NO_ADDRESS {
+        continue;
+      }
 
-    // If data is greater than available in inode's blocks,
-    // grow the file
-    let difference = data.len() as isize - (self.get_inode_blocks_count(inode_number)? * self.fs_info.block_size) as isize;
-    if difference > 0 {
-      self.grow_file(inode_number, (difference as f64 / self.fs_info.block_size as f64).ceil() as AddressSize)?;
+      let block = self.read_block(block_number)?;
+      buffer[(kept.start - blocks_start) as usize..(kept.end - blocks_start) as usize]
+        .copy_from_slice(&block.data[..kept.len()]);
+    }
+    buffer[(offset - blocks_start) as usize..(end - blocks_start) as usize].copy_from_slice(data);
+
+    // Blocks shared with snapshots are copied before they are written,
+    // then blocks are claimed for holes that `data` goes to
+    self.unshare_blocks(inode_number, first_block_index..blocks_end)?;
+    if !data.is_empty() {
+      self.fill_holes(inode_number, offset / block_size..blocks_end)?;
     }
 
     // Refresh inode from disk
-    let inode = self.read_inode(inode_number);
+    let inode = self.read_inode(inode_number)?;
+    let block_numbers = self.block_numbers_i(&inode)?;
 
     // Split data to chunks...
-    let chunks = data
-      .chunks(self.fs_info.block_size as usize)
-      .zip(0..);
-    // ...and write it to inode's blocks
+    let chunks = buffer
+      .chunks(block_size as usize)
+      .zip(first_block_index as usize..);
+    // ...and write it to inode's blocks, except holes. Only metadata
+    // is journaled, contents of regular files are not, like in ext3
+    let is_regular_file = inode.mode.file_type() == FileModeType::File as u8;
     for (chunk, i) in chunks {
-      self.write_block(&Block { data: chunk.to_owned(), }, inode.direct_block_numbers[i])?;
+      let block_number = block_numbers[i];
+      if block_number == NO_ADDRESS {
+        continue;
+      }
+
+      let block = Block { data: chunk.to_owned(), };
+      match is_regular_file {
+        true => self.write_data_block(&block, block_number)?,
+        false => self.write_block(&block, block_number)?,
+      }
     };
 
     // Refresh inode from disk
-    let mut inode = self.read_inode(inode_number);
-    inode.file_size = data.len() as AddressSize;
-    inode.mtime = unixtime();
+    let mut inode = self.read_inode(inode_number)?;
+    inode.file_size = match truncate {
+      true => end,
+      false => inode.file_size.max(end),
+    };
+    inode.mtime = self.clock.now();
     self.write_inode(&inode, inode_number)?;
 
-    let inode = self.read_inode(inode_number);
+    let inode = self.read_inode(inode_number)?;
     Ok(inode)
   }
 
-  fn read_data_i(&self, inode_number: AddressSize) -> Result<Vec<u8>, Errno> {
-    let inode = self.read_inode(inode_number);
+  /// Make inode's data `size` bytes long, releasing blocks past it
+  /// or filling the gap with zeros
+  fn truncate_i(&mut self, inode_number: AddressSize, size: AddressSize) -> Result<INode, Errno> {
+    let mut inode = self.read_inode(inode_number)?;
+    if size > inode.file_size {
+      return self.write_data_at_i(&[], inode_number, size, true);
+    }
 
-    let data = self
-      .iter_blocks_i(inode_number)
-      .take_while(|&block_number| block_number != NO_ADDRESS)
-      .flat_map(|block_number| self.read_block(block_number).data)
-      .take(inode.file_size as usize)
+    self.shrink_file(inode_number, size.div_ceil(self.fs_info.block_size))?;
+    inode = self.read_inode(inode_number)?;
+
+    if inode.file_size != size {
+      inode.file_size = size;
+      inode.mtime = self.clock.now();
+      self.write_inode(&inode, inode_number)?;
+    }
+
+    Ok(inode)
+  }
+
+  /// Read up to `count` bytes of inode's data from `offset`,
+  /// only from blocks that have them. Holes read as zeros
+  fn read_data_at_i(&self, inode_number: AddressSize, offset: AddressSize, count: AddressSize) -> Result<Vec<u8>, Errno> {
+    let inode = self.read_inode(inode_number)?;
+    let block_size = self.fs_info.block_size;
+    let end = inode.file_size.min(offset.saturating_add(count));
+    if offset >= end {
+      return Ok(Vec::new());
+    }
+
+    let data = self.block_numbers_i(&inode)?
+      .into_iter()
+      .take(end.div_ceil(block_size) as usize)
+      .skip((offset / block_size) as usize)
+      .map(|block_number| self.read_data_block_or_hole(block_number))
+      .collect::<Result<Vec<Vec<u8>>, Errno>>()?
+      .into_iter()
+      .flatten()
+      .skip((offset % block_size) as usize)
+      .take((end - offset) as usize)
       .collect();
 
     Ok(data)
   }
 
-  fn get_inode_blocks_count(&mut self, inode_number: AddressSize) -> Result<AddressSize, Errno> {
-    let inode = self.read_inode(inode_number);
+  fn read_data_i(&self, inode_number: AddressSize) -> Result<Vec<u8>, Errno> {
+    let inode = self.read_inode(inode_number)?;
+
+    let block_numbers = self
+      .iter_blocks_i(inode_number)?
+      .take(inode.file_size.div_ceil(self.fs_info.block_size) as usize)
+      .collect::<Vec<_>>();
+    // Damaged inode may point anywhere
+    if let Some(block_number) = block_numbers
+      .iter()
+      .find(|&&block_number| block_number != NO_ADDRESS && block_number >= self.fs_info.blocks_count)
+    {
+      return Err(Errno::EILSEQ(format!("e5fs: inode {inode_number} has block {block_number} past end of filesystem").into()));
+    }
 
-    Ok(
-      inode
-        .direct_block_numbers
-        .iter()
-        .take_while(|&&block_number| block_number != NO_ADDRESS)
-        .map(|_| 1)
-        .sum()
-    )
+    let data = block_numbers
+      .into_iter()
+      .map(|block_number| self.read_data_block_or_hole(block_number))
+      .collect::<Result<Vec<Vec<u8>>, Errno>>()?
+      .into_iter()
+      .flatten()
+      .take(inode.file_size as usize)
+      .collect();
+
+    Ok(data)
   }
 
-  fn read_mode(&mut self, inode_number: AddressSize) -> Result<FileMode, Errno> {
-    let inode = self.read_inode(inode_number);
-    Ok(inode.mode)
+  /// Data of block, or zeros if it is a hole
+  fn read_data_block_or_hole(&self, block_number: AddressSize) -> Result<Vec<u8>, Errno> {
+    match block_number {
+      NO_ADDRESS => Ok(vec![0; self.fs_info.block_size as usize]),
+      block_number => Ok(self.read_block(block_number)?.data),
+    }
   }
 
   fn write_mode_i(&mut self, inode_number: AddressSize, mode: FileMode) -> Result<(), Errno> {
-    let mut inode = self.read_inode(inode_number);
+    let mut inode = self.read_inode(inode_number)?;
     inode.mode = mode;
     self.write_inode(&inode, inode_number)
   }
@@ -885,7 +2152,7 @@ impl E5FSFilesystem {
       .enumerate()
       .find(|(_, inode_number)| **inode_number != NO_ADDRESS)
       .map(|(index, inode_number)| (index, *inode_number))
-      .ok_or(Errno::ENOSPC(format!("no free inodes left")))?;
+      .ok_or(Errno::ENOSPC(format!("no free inodes left").into()))?;
 
     // Replace and write inode number in superblock with NO_ADDRESS
     *self
@@ -893,90 +2160,296 @@ impl E5FSFilesystem {
       .free_inode_numbers
       .get_mut(index)
       .ok_or(
-        Errno::EIO(format!("e5fs::claim_free_inode: cannot index free_inode_numbers sith {index}: this should not happen"))
+        Errno::EIO(format!("e5fs::claim_free_inode: cannot index free_inode_numbers sith {index}: this should not happen").into())
       )? = NO_ADDRESS;
+    self.superblock.free_inodes_count = self.superblock.free_inodes_count.saturating_sub(1);
     self.write_superblock(&self.superblock.clone())?;
 
     // Write mode to not free
-    let mut inode = self.read_inode(inode_number);
+    let mut inode = self.read_inode(inode_number)?;
     inode.mode = inode.mode.with_free(0);
     self.write_inode(&inode, inode_number)?;
 
     Ok(inode_number)
   }
 
-  /// Release specified inode
-  fn release_inode(&mut self, inode_number: AddressSize) -> Result<(), Errno> {
-    // Get inode from disk, change it to be not free
-    let mut inode = self.read_inode(inode_number);
-    inode.mode = inode.mode.with_free(1);
+  /// Mark the lowest free block used in `fbl`, finding it in `free_blocks`
+  ///
+  /// Errors:
+  /// ENOSPC -> there are no free blocks, or only reserved ones
+  ///           and current user is not root
+  fn claim_free_block(&mut self) -> Result<AddressSize, Errno> {
+    if self.current_uid != ROOT_UID && self.superblock.free_blocks_count <= self.superblock.reserved_blocks_count {
+      return Err(Errno::ENOSPC(format!("e5fs: only blocks reserved for root are left").into()));
+    }
 
-    // Write changed inode to disk
-    self.write_inode(&inode, inode_number)
+    let block_number = self.free_blocks
+      .first_free()
+      .ok_or(Errno::ENOSPC(format!("e5fs: no free blocks left").into()))?;
+
+    self.write_block_references(block_number, 1)?;
+    self.superblock.free_blocks_count = self.superblock.free_blocks_count.saturating_sub(1);
+
+    Ok(block_number)
   }
 
-  /// Returns block number, which is also an index into `fbl`.
-  fn find_block_in_fbl<F>(&mut self, f: F) -> Result<AddressSize, Errno> 
-    where F: Fn(AddressSize) -> bool
-  {
-    (self.fs_info.first_fbl_block_number..self.fs_info.blocks_count)
-      .flat_map(|fbl_block_number| { 
-        E5FSFilesystem::parse_block_numbers_from_block(
-          &self.read_block(fbl_block_number)
-        ) 
-      })
-      .find(|block_number| f(*block_number))
-      .ok_or(Errno::ENOSPC(format!("e5fs::find_block_in_fbl: not found")))
+  /// Drop one file's reference to block in `fbl`: mark it free
+  /// if it was the last one
+  /// FIXME: block_number may left dangling in inode's fields
+  fn release_block(&mut self, block_number: AddressSize) -> Result<(), Errno> {
+    let references = self.block_references(block_number)?;
+    if references == 1 {
+      self.superblock.free_blocks_count += 1;
+    }
+    self.write_block_references(block_number, references.saturating_sub(1))
   }
 
-  /// Replace specified inode in `free_inode_numbers` with `NO_ADDRESS`
-  fn claim_free_block(&mut self) -> Result<AddressSize, Errno> {
-    // 1. Basically try to find index of block with number != NO_ADDRESS in `fbl`
-    let block_number = self.find_block_in_fbl(|n| n != NO_ADDRESS)?;
+  /// Mark specified block as used in `fbl`, like `claim_free_block` does
+  fn claim_block(&mut self, block_number: AddressSize) -> Result<(), Errno> {
+    if self.block_references(block_number)? == 0 {
+      self.superblock.free_blocks_count = self.superblock.free_blocks_count.saturating_sub(1);
+    }
+    self.write_block_references(block_number, 1)
+  }
 
-    let address_size = self.fs_info.address_size;
-    let address = self.fs_info.first_fbl_block_address + (block_number * address_size);
+  /// Add one more file to those sharing a used block
+  ///
+  /// Errors:
+  /// ENOSPC -> block is shared by `MAX_BLOCK_REFERENCES` files already
+  fn reference_block(&mut self, block_number: AddressSize) -> Result<(), Errno> {
+    let references = self.block_references(block_number)?;
+    if references >= MAX_BLOCK_REFERENCES {
+      return Err(Errno::ENOSPC(format!("e5fs: block {block_number} is shared by too many files").into()));
+    }
 
-    // 2. Write (save to disk) NO_ADDRESS to that index
-    // to indicate that this block was claimed
-    self.fs_info.realfile.borrow_mut().seek(SeekFrom::Start(address.try_into().unwrap())).unwrap();
-    self.fs_info.realfile.borrow_mut().write_all(&NO_ADDRESS.to_le_bytes()).unwrap();
+    self.write_block_references(block_number, references + 1)
+  }
 
-    // 3. Return block_number
-    Ok(block_number)
+  /// Count free inodes and blocks into superblock, reading free
+  /// blocks from `fbl` on the way
+  fn count_free(&mut self) -> Result<(), Errno> {
+    self.free_blocks = self.read_free_blocks()?;
+    self.superblock.free_inodes_count = self.counted_free_inodes()?;
+    self.superblock.free_blocks_count = self.free_blocks.free_count();
+
+    Ok(())
   }
 
-  /// Replace specified inode in `fbl` with `block_number`
-  /// FIXME: block_number may left dangling in inode's fields
-  fn release_block(&mut self, block_number: AddressSize) -> Result<(), Errno> {
-    let address_size = self.fs_info.address_size;
-    let address = self.fs_info.first_fbl_block_address + (block_number * address_size);
+  /// Bitmap of free blocks, going by `fbl`. Only the bitmap
+  /// is read if `fbl` has one
+  fn read_free_blocks(&self) -> Result<BlockBitmap, Errno> {
+    if !self.fs_info.has_block_map() {
+      return Ok(BlockBitmap::from_references(self.read_references()?));
+    }
+
+    let blocks_count = self.fs_info.first_fbl_block_number;
+    let mut bytes = vec![0u8; blocks_count.div_ceil(8) as usize];
+    self.read_storage(self.fs_info.first_fbl_block_address, &mut bytes)
+      .map_err(|errno| errno.context("e5fs: cannot read bitmap of free blocks"))?;
+
+    Ok(BlockBitmap::from_bytes(&bytes, blocks_count))
+  }
+
+  /// Count of files that use every block before `fbl`, going by it.
+  /// Returns: `None` for those where it makes no sense
+  fn read_references(&self) -> Result<Vec<Option<AddressSize>>, Errno> {
+    if !self.fs_info.has_block_map() {
+      return Ok(
+        (0..)
+          .zip(self.read_fbl_entries()?)
+          .map(|(block_number, entry)| E5FSFilesystem::fbl_references(block_number, entry))
+          .collect()
+      );
+    }
+
+    let free_blocks = self.read_free_blocks()?;
+    let mut table = vec![0u8; (self.fs_info.first_fbl_block_number * REFERENCES_SIZE) as usize];
+    self.read_storage(self.fs_info.references_address(), &mut table)
+      .map_err(|errno| errno.context("e5fs: cannot read table of references"))?;
+
+    Ok(
+      (0..)
+        .zip(table.chunks(REFERENCES_SIZE as usize))
+        .map(|(block_number, extra)| {
+          E5FSFilesystem::block_map_references(free_blocks.is_free(block_number), u16::from_le_bytes(extra.try_into().unwrap()))
+        })
+        .collect()
+    )
+  }
+
+  /// Write `fbl` with count of files that use every block before it
+  fn write_references(&mut self, references: Vec<AddressSize>) -> Result<(), Errno> {
+    self.free_blocks = BlockBitmap::from_references(references.iter().map(|&references| Some(references)));
+
+    let mut bytes = match self.fs_info.has_block_map() {
+      true => {
+        let mut bytes = self.free_blocks.to_bytes();
+        bytes.resize(self.fs_info.blocks_count.div_ceil(8) as usize, 0);
+        bytes.extend(references.iter().flat_map(|&references| (references.saturating_sub(1) as u16).to_le_bytes()));
+        bytes
+      },
+      // Entries of blocks of `fbl` itself and past the last block
+      // are those of used ones
+      false => (0..)
+        .zip(references.iter().copied().chain(std::iter::repeat(1)))
+        .take((self.fs_info.blocks_needed_for_fbl * self.fs_info.block_numbers_per_fbl_chunk) as usize)
+        .flat_map(|(block_number, references)| E5FSFilesystem::fbl_entry(block_number, references).to_le_bytes())
+        .collect(),
+    };
+    bytes.resize((self.fs_info.blocks_needed_for_fbl * self.fs_info.block_size) as usize, 0);
+
+    for (data, block_number) in bytes
+      .chunks(self.fs_info.block_size as usize)
+      .zip(self.fs_info.first_fbl_block_number..self.fs_info.blocks_count)
+    {
+      self.write_block(&Block { data: data.to_vec() }, block_number)?;
+    }
+
+    Ok(())
+  }
+
+  /// Entries of `fbl` of format version before `BLOCK_MAP_FORMAT_VERSION`
+  /// for every block before it
+  fn read_fbl_entries(&self) -> Result<Vec<AddressSize>, Errno> {
+    let mut entries = Vec::new();
+    for fbl_block_number in self.fs_info.first_fbl_block_number..self.fs_info.blocks_count {
+      entries.extend(E5FSFilesystem::parse_block_numbers_from_block(&self.read_block(fbl_block_number)?));
+    }
+    entries.truncate(self.fs_info.first_fbl_block_number as usize);
+
+    Ok(entries)
+  }
+
+  /// Returns: counts of free inodes and free blocks, going by
+  /// inode table and `fbl`
+  fn counted_free(&self) -> Result<(AddressSize, AddressSize), Errno> {
+    Ok((self.counted_free_inodes()?, self.read_free_blocks()?.free_count()))
+  }
+
+  /// Returns: count of free inodes, going by inode table
+  fn counted_free_inodes(&self) -> Result<AddressSize, Errno> {
+    let mut free_inodes_count = 0;
+    for inode_number in 0..self.fs_info.inodes_count {
+      if fsck::is_free(&self.read_inode(inode_number)?) {
+        free_inodes_count += 1;
+      }
+    }
 
-    // 1. Write (save to disk) `block_number` to fbl index of
-    // `block_number` (fbl indices correlate 1:1 to block numbers)
-    // to indicate that this block is claimed
-    self.fs_info.realfile.borrow_mut().seek(SeekFrom::Start(address.try_into().unwrap())).unwrap();
-    self.fs_info.realfile.borrow_mut().write_all(&block_number.to_le_bytes()).unwrap();
+    Ok(free_inodes_count)
+  }
+
+  /// Count of files that use block, going by `fbl`
+  fn block_references(&self, block_number: AddressSize) -> Result<AddressSize, Errno> {
+    // What makes no sense is left for fsck, block is not free
+    Ok(self.read_block_references(block_number)?.unwrap_or(1))
+  }
+
+  /// Count of files that use block, as `fbl` has it.
+  /// Returns: `None` if it makes no sense
+  fn read_block_references(&self, block_number: AddressSize) -> Result<Option<AddressSize>, Errno> {
+    if !self.fs_info.has_block_map() {
+      return Ok(E5FSFilesystem::fbl_references(block_number, self.read_fbl_entry(block_number)?));
+    }
+
+    let mut byte = [0u8; 1];
+    self.read_storage(self.fs_info.first_fbl_block_address + block_number / 8, &mut byte)
+      .map_err(|errno| errno.context("e5fs: cannot read bitmap of free blocks"))?;
+    let mut extra = [0u8; REFERENCES_SIZE as usize];
+    self.read_storage(self.fs_info.references_address() + block_number * REFERENCES_SIZE, &mut extra)
+      .map_err(|errno| errno.context("e5fs: cannot read table of references"))?;
+
+    Ok(E5FSFilesystem::block_map_references(byte[0] & (1 << (block_number % 8)) != 0, u16::from_le_bytes(extra)))
+  }
+
+  /// Record in `fbl` that `references` files use block
+  fn write_block_references(&mut self, block_number: AddressSize, references: AddressSize) -> Result<(), Errno> {
+    match self.fs_info.has_block_map() {
+      true => {
+        let address = self.fs_info.first_fbl_block_address + block_number / 8;
+        let mut byte = [0u8; 1];
+        self.read_storage(address, &mut byte)
+          .map_err(|errno| errno.context("e5fs: cannot read bitmap of free blocks"))?;
+        match references {
+          0 => byte[0] |= 1 << (block_number % 8),
+          _ => byte[0] &= !(1 << (block_number % 8)),
+        }
+        self.write_storage(address, &byte)
+          .map_err(|errno| errno.context("e5fs: cannot write bitmap of free blocks"))?;
+
+        let extra = references.saturating_sub(1) as u16;
+        self.write_storage(self.fs_info.references_address() + block_number * REFERENCES_SIZE, &extra.to_le_bytes())
+          .map_err(|errno| errno.context("e5fs: cannot write table of references"))?;
+      },
+      false => self.write_fbl_entry(block_number, E5FSFilesystem::fbl_entry(block_number, references))?,
+    }
+    self.free_blocks.set_free(block_number, references == 0);
 
-    // 2. And return it
     Ok(())
   }
 
+  /// Count of files that use block that is `free` in bitmap and has
+  /// `extra` files besides the first in table of references.
+  /// Returns: `None` if free block has them
+  fn block_map_references(free: bool, extra: u16) -> Option<AddressSize> {
+    match (free, extra) {
+      (true, 0) => Some(0),
+      (true, _) => None,
+      (false, extra) => Some(extra as AddressSize + 1),
+    }
+  }
+
+  /// `fbl` entry of block that `references` files use
+  fn fbl_entry(block_number: AddressSize, references: AddressSize) -> AddressSize {
+    match references {
+      0 => block_number,
+      references => NO_ADDRESS - (references - 1),
+    }
+  }
+
+  /// Count of files that use block with `fbl` entry `entry`.
+  /// Returns: `None` if entry is neither free nor used one
+  fn fbl_references(block_number: AddressSize, entry: AddressSize) -> Option<AddressSize> {
+    match entry {
+      entry if entry == block_number => Some(0),
+      entry if entry > NO_ADDRESS - MAX_BLOCK_REFERENCES => Some(NO_ADDRESS - entry + 1),
+      _ => None,
+    }
+  }
+
+  fn write_fbl_entry(&mut self, block_number: AddressSize, entry: AddressSize) -> Result<(), Errno> {
+    let address = self.fs_info.first_fbl_block_address + block_number * self.fs_info.address_size;
+
+    self.write_storage(address, &entry.to_le_bytes())
+      .map_err(|errno| errno.context("e5fs: cannot write fbl"))
+  }
+
+  /// Entry of `fbl` for specified block: the block number if it is
+  /// free, `NO_ADDRESS` if one file uses it, less if more of them do
+  fn read_fbl_entry(&self, block_number: AddressSize) -> Result<AddressSize, Errno> {
+    let address = self.fs_info.first_fbl_block_address + block_number * self.fs_info.address_size;
+    let mut entry = [0u8; std::mem::size_of::<AddressSize>()];
+    self.read_storage(address, &mut entry)
+      .map_err(|errno| errno.context("e5fs: cannot read fbl"))?;
+
+    Ok(AddressSize::from_le_bytes(entry))
+  }
+
   /// Returns:
   /// ENOENT -> if no free block or inode exists
   fn allocate_file(&mut self) -> Result<(AddressSize, INode), Errno> {
     let inode_number = self.claim_free_inode()?;
 
+    let now = self.clock.now();
     let mut inode = INode {
       mode: FileMode::default().with_free(0),
       links_count: 0,
       file_size: 0,
       uid: NOBODY_UID,
       gid: NOBODY_UID,
-      atime: unixtime(),
-      mtime: unixtime(),
-      ctime: unixtime(),
-      btime: unixtime(),
+      atime: now,
+      mtime: now,
+      ctime: now,
+      btime: now,
       number: inode_number,
       ..Default::default()
     };
@@ -987,49 +2460,33 @@ impl E5FSFilesystem {
     self.write_inode(&inode, inode_number)?;
     self.write_block(&Block {
       data: vec![0; self.fs_info.block_data_size as usize],
-    }, inode_number)?;
+    }, block_number)?;
 
     Ok((inode_number, inode))
   }
   
-  fn grow_file(&mut self, inode_number: AddressSize, blocks_count: AddressSize) -> Result<INode, Errno> {
+  /// Claim blocks for holes among slots `block_indices` of inode
+  fn fill_holes(&mut self, inode_number: AddressSize, block_indices: Range<AddressSize>) -> Result<INode, Errno> {
     // Read inode
-    let mut inode = self.read_inode(inode_number);
-
-    // Find first empty slot
-    let empty_slot = self
-      .iter_blocks_i(inode_number)
-      .zip(0..)
-      .find_map(|(block_number, slot_index)| if block_number == NO_ADDRESS {
-        Some(slot_index)
-      } else {
-        None
-      }).ok_or_else(|| Errno::EIO(String::from("no more empty block slots in inode")))?;
-
-    let free_slots_count = inode.direct_block_numbers.len() as AddressSize - (empty_slot + 1);
-
-    // Guard for not enough empty slots in direct block number array
-    // TODO: implement indirect blocks
-    match free_slots_count {
-      n if n < blocks_count => return Err(Errno::EIO(String::from("not enough empty block slots in inode"))),
-      _ => (),
-    };
+    let mut inode = self.read_inode(inode_number)?;
 
-    // Allocate new blocks and store their numbers
-    let block_numbers = (0..blocks_count).fold(Vec::new(), |mut block_numbers, _| {
-      block_numbers.push(self.claim_free_block());
-      block_numbers
-    })
-      .into_iter()
-      .collect::<Result<Vec<AddressSize>, Errno>>()?;
+    // Guard for not enough slots, direct and indirect
+    if block_indices.end > self.block_slots_count() {
+      return Err(Errno::EIO(String::from("not enough block slots in inode").into()));
+    }
 
-    // Write these block numbees to direct blocks of inode
-    block_numbers
-      .iter()
-      .zip(empty_slot..inode.direct_block_numbers.len() as AddressSize)
-      .for_each(|(&block_number, index)| {
-        inode.direct_block_numbers[index as usize] = block_number;
-      });
+    let mut block_numbers = self.block_numbers_i(&inode)?;
+    let mut filled = false;
+    for index in block_indices {
+      let slot = &mut block_numbers[index as usize];
+      if *slot == NO_ADDRESS {
+        *slot = self.claim_free_block()?;
+        filled = true;
+      }
+    }
+    if filled {
+      self.set_block_numbers_i(&mut inode, &block_numbers)?;
+    }
 
     // Write modified inode to the disk
     self.write_inode(&inode, inode_number)?;
@@ -1037,60 +2494,181 @@ impl E5FSFilesystem {
     Ok(inode)
   }
 
+  /// Give inode copies of blocks among slots `block_indices` that it
+  /// shares with other files, so that writing them changes only it
+  fn unshare_blocks(&mut self, inode_number: AddressSize, block_indices: Range<AddressSize>) -> Result<(), Errno> {
+    let mut inode = self.read_inode(inode_number)?;
+    let mut block_numbers = self.block_numbers_i(&inode)?;
+    let mut unshared = false;
+
+    for index in block_indices {
+      let block_number = block_numbers[index as usize];
+      if block_number == NO_ADDRESS || self.block_references(block_number)? < 2 {
+        continue;
+      }
+
+      let copy_number = self.claim_free_block()?;
+      let block = self.read_block(block_number)?;
+      self.write_block(&block, copy_number)?;
+      self.release_block(block_number)?;
+      block_numbers[index as usize] = copy_number;
+      unshared = true;
+    }
+
+    if unshared {
+      self.set_block_numbers_i(&mut inode, &block_numbers)?;
+      self.write_inode(&inode, inode_number)?;
+    }
+
+    Ok(())
+  }
+
+  /// Release blocks of inode from slot `blocks_count` on,
+  /// so that only first `blocks_count` slots may have them
   fn shrink_file(&mut self, inode_number: AddressSize, blocks_count: AddressSize) -> Result<(), Errno> {
     // Read inode
-    let mut inode = self.read_inode(inode_number);
+    let mut inode = self.read_inode(inode_number)?;
+    let mut block_numbers = self.block_numbers_i(&inode)?;
+    if blocks_count >= block_numbers.len() as AddressSize {
+      return Ok(());
+    }
+
+    // Release them, leaving holes. Indirect block goes
+    // too if there is nothing left in it
+    for block_number in &mut block_numbers[blocks_count as usize..] {
+      if *block_number != NO_ADDRESS {
+        self.release_block(*block_number)?;
+        *block_number = NO_ADDRESS;
+      }
+    }
+    self.set_block_numbers_i(&mut inode, &block_numbers)?;
+
+    // Write modified inode to the disk
+    self.write_inode(&inode, inode_number)?;
+
+    Ok(())
+  }
+
+  fn iter_blocks_i(&self, inode_number: AddressSize) -> Result<impl Iterator<Item = AddressSize>, Errno> {
+    let inode = self.read_inode(inode_number)?;
+
+    Ok(self.block_numbers_i(&inode)?.into_iter())
+  }
+
+  /// Count of blocks inode can have: direct ones, then
+  /// as many as numbers of them fit in its indirect block
+  fn block_slots_count(&self) -> AddressSize {
+    DIRECT_BLOCKS_COUNT as AddressSize + self.fs_info.block_size / self.fs_info.address_size
+  }
+
+  /// Block numbers in every slot of inode, direct ones and
+  /// those in its indirect block, `NO_ADDRESS` in holes
+  fn block_numbers_i(&self, inode: &INode) -> Result<Vec<AddressSize>, Errno> {
+    let indirect_slots_count = (self.block_slots_count() - DIRECT_BLOCKS_COUNT as AddressSize) as usize;
+    let indirect_block_numbers = match inode.indirect_block_numbers[0] {
+      block_number if block_number < self.fs_info.first_fbl_block_number => {
+        E5FSFilesystem::parse_block_numbers_from_block(&self.read_block(block_number)?)
+      },
+      // None or out of range, fsck drops those
+      _ => vec![NO_ADDRESS; indirect_slots_count],
+    };
+
+    Ok(inode.direct_block_numbers
+      .into_iter()
+      .chain(indirect_block_numbers.into_iter().take(indirect_slots_count))
+      .collect())
+  }
+
+  /// Put `block_numbers` into slots of inode, like `block_numbers_i`
+  /// has them. Indirect block is claimed when slots past direct ones
+  /// get used and released when they are all holes again.
+  /// Inode is not written
+  fn set_block_numbers_i(&mut self, inode: &mut INode, block_numbers: &[AddressSize]) -> Result<(), Errno> {
+    let (direct_block_numbers, indirect_block_numbers) = block_numbers.split_at(DIRECT_BLOCKS_COUNT);
+    inode.direct_block_numbers.copy_from_slice(direct_block_numbers);
+
+    let indirect_block_number = inode.indirect_block_numbers[0];
+    if indirect_block_numbers.iter().all(|&block_number| block_number == NO_ADDRESS) {
+      if indirect_block_number != NO_ADDRESS {
+        self.release_block(indirect_block_number)?;
+        inode.indirect_block_numbers[0] = NO_ADDRESS;
+      }
+      return Ok(());
+    }
+
+    if indirect_block_number == NO_ADDRESS {
+      inode.indirect_block_numbers[0] = self.claim_free_block()?;
+    }
+    let data = indirect_block_numbers
+      .iter()
+      .flat_map(|block_number| block_number.to_le_bytes())
+      // Slots that aren't given are holes
+      .chain(NO_ADDRESS.to_le_bytes().into_iter().cycle())
+      .take(self.fs_info.block_data_size as usize)
+      .collect();
+    self.write_block(&Block { data }, inode.indirect_block_numbers[0])
+  }
+
+  /// Release every block of inode, indirect one too.
+  /// Device files have no blocks - only device number.
+  /// Inode is not written
+  fn release_blocks_i(&mut self, inode: &mut INode) -> Result<(), Errno> {
+    if inode.is_device() {
+      return Ok(());
+    }
 
-    // Find last used slot
-    let first_used_slot = inode.direct_block_numbers
-      .iter()
-      .zip((0..inode.direct_block_numbers.len()).rev())
-      .find_map(|(&block_number, slot_index)| if block_number == NO_ADDRESS {
-        Some(slot_index as AddressSize)
-      } else {
-        None
-      }).ok_or_else(|| Errno::EIO(String::from("no block slots used in inode - can't shrink")))?;
+    for block_number in self.block_numbers_i(inode)?.into_iter().filter(|&block_number| block_number != NO_ADDRESS) {
+      self.release_block(block_number)?;
+    }
+    self.set_block_numbers_i(inode, &vec![NO_ADDRESS; self.block_slots_count() as usize])
+  }
 
-    let used_slots_count = inode.direct_block_numbers.len() as AddressSize - (first_used_slot + 1);
+  /// Same as `write_block`, but past the journal
+  fn write_data_block(&mut self, block: &Block, block_number: AddressSize) -> Result<(), Errno> {
+    let address = self.block_address_for(block, block_number)?;
 
-    // Guard for not enough used slots in direct block number array
-    // TODO: implement indirect blocks
-    match used_slots_count {
-      n if blocks_count > n => return Err(Errno::EIO(String::from("not enough used slots in inode - can't shrink"))),
-      _ => (),
-    };
+    let data = self.encrypted(block, block_number);
 
-    // Release N blocks
-     inode.direct_block_numbers[first_used_slot as usize..]
-      .iter_mut()
-      .for_each(|block_number| {
-        self.release_block(*block_number).unwrap();
-        *block_number = NO_ADDRESS;
-      });
+    let mut realfile = self.fs_info.realfile.write().unwrap();
+    realfile.seek(SeekFrom::Start(address as u64))
+      .and_then(|_| realfile.write_direct(&data))
+      .map_err(|error| Errno::EIO(error.to_string().into()).context(format!("e5fs: cannot write block {block_number}")))
+  }
 
-    // Write modified inode to the disk
-    self.write_inode(&inode, inode_number)?;
+  // Errors:
+  // ENOENT -> block_number does not exist
+  fn write_block(&mut self, block: &Block, block_number: AddressSize) -> Result<(), Errno> {
+    let address = self.block_address_for(block, block_number)?;
+    let data = self.encrypted(block, block_number);
 
-    Ok(())
+    // Seek to it and write bytes
+    self.write_storage(address, &data)
+      .map_err(|errno| errno.context(format!("e5fs: cannot write block {block_number}")))
   }
 
-  fn iter_blocks_i(&self, inode_number: AddressSize) -> impl Iterator<Item = AddressSize> {
-    let inode = self.read_inode(inode_number);
+  /// Bytes of `block` as they are kept on device: encrypted, if
+  /// filesystem is encrypted and it is a data block
+  fn encrypted(&self, block: &Block, block_number: AddressSize) -> Vec<u8> {
+    let mut data = block.data.clone();
+    if let Some(key) = self.data_block_key(block_number) {
+      crypt::encrypt_block(key, block_number, &mut data);
+    }
 
-    inode.direct_block_numbers
-      .into_iter()
+    data
   }
 
-  fn iter_inode_numbers(&self, inode_number: AddressSize) -> impl Iterator<Item = AddressSize> {
-    1..self.fs_info.inodes_count
+  /// Key to encrypt block with, if it is to be encrypted. Blocks of
+  /// `fbl` are not, as its entries are written one by one
+  fn data_block_key(&self, block_number: AddressSize) -> Option<&Key> {
+    self.key.as_ref().filter(|_| block_number < self.fs_info.first_fbl_block_number)
   }
 
-  // Errors:
-  // ENOENT -> block_number does not exist
-  fn write_block(&mut self, block: &Block, block_number: AddressSize) -> Result<(), Errno> {
+  /// Returns: absolute address of block `block_number`,
+  /// if `block` can be written there
+  fn block_address_for(&self, block: &Block, block_number: AddressSize) -> Result<AddressSize, Errno> {
     // Guard for block_number out of bounds
     if block_number > self.fs_info.blocks_count {
-      return Err(Errno::ENOENT(String::from("write_block: block_number out of bounds")))
+      return Err(Errno::ENOENT(String::from("write_block: block_number out of bounds").into()))
     }
     // Guard for block data being of invalid size
     if block.data.len() as AddressSize > self.fs_info.block_data_size {
@@ -1100,27 +2678,18 @@ impl E5FSFilesystem {
             self.fs_info.block_data_size,
             block.data.len()
           )
+          .into()
       ))
     }
 
-    // Read bytes from file
-    let mut block_bytes = Vec::new();
-    block_bytes.write(&block.data).unwrap();
-
     // Get absolute address of block
-    let address = self.fs_info.first_block_address + block_number * self.fs_info.block_size;
-
-    // Seek to it and write bytes
-    self.fs_info.realfile.borrow_mut().seek(SeekFrom::Start(address.try_into().unwrap())).unwrap();
-    self.fs_info.realfile.borrow_mut().write_all(&block_bytes).unwrap();
-
-    Ok(())
+    Ok(self.fs_info.first_block_address + block_number * self.fs_info.block_size)
   }
   
   fn write_inode(&mut self, inode: &INode, inode_number: AddressSize) -> Result<(), Errno> {
     // Guard for inoe_number out of bounds
     if inode_number > self.fs_info.inodes_count {
-      return Err(Errno::ENOENT(String::from("write_inode: inode_number out of bounds")))
+      return Err(Errno::ENOENT(String::from("write_inode: inode_number out of bounds").into()))
     }
     
     // Read bytes from file
@@ -1136,21 +2705,29 @@ impl E5FSFilesystem {
     inode_bytes.write(&inode.btime.to_le_bytes()).unwrap();
     inode_bytes.write(&inode.direct_block_numbers.iter().flat_map(|x| x.to_le_bytes()).collect::<Vec<u8>>()).unwrap();
     inode_bytes.write(&inode.indirect_block_numbers.iter().flat_map(|x| x.to_le_bytes()).collect::<Vec<u8>>()).unwrap();
+    // Inodes from before it have zeros there, so 0 is no block
+    inode_bytes.write(&inode.xattr_block_number.wrapping_add(1).to_le_bytes()).unwrap();
+    // Inodes from before it have zeros there too, no snapshot links
+    inode_bytes.write(&inode.snapshot_links.to_le_bytes()).unwrap();
 
     // Get absolute address of inode
     let address = self.fs_info.first_inode_address + inode_number * self.fs_info.inode_size;
 
     // Seek to it and write bytes
-    self.fs_info.realfile.borrow_mut().seek(SeekFrom::Start(address.try_into().unwrap())).unwrap();
-    self.fs_info.realfile.borrow_mut().write_all(&inode_bytes).unwrap();
-
-    Ok(())
+    self.write_storage(address, &inode_bytes)
+      .map_err(|errno| errno.context(format!("e5fs: cannot write inode {inode_number}")))
   }
 
   fn write_superblock(&mut self, superblock: &Superblock) -> Result<(), Errno> {
+    self.write_superblock_at(superblock, 0)
+  }
+
+  fn write_superblock_at(&mut self, superblock: &Superblock, address: AddressSize) -> Result<(), Errno> {
     // Read bytes from file
     let mut superblock_bytes = Vec::new();
     superblock_bytes.write(&superblock.filesystem_type).unwrap();
+    superblock_bytes.write(&superblock.reserved_blocks_count.to_le_bytes()).unwrap();
+    superblock_bytes.write(&superblock.format_version.to_le_bytes()).unwrap();
     superblock_bytes.write(&superblock.filesystem_size.to_le_bytes()).unwrap();
     superblock_bytes.write(&superblock.inode_table_size.to_le_bytes()).unwrap();
     superblock_bytes.write(&superblock.inode_table_percentage.to_le_bytes()).unwrap();
@@ -1162,33 +2739,41 @@ impl E5FSFilesystem {
     superblock_bytes.write(&superblock.block_data_size.to_le_bytes()).unwrap();
     superblock_bytes.write(&superblock.free_inode_numbers.iter().flat_map(|x| x.to_le_bytes()).collect::<Vec<u8>>()).unwrap();
     superblock_bytes.write(&superblock.first_fbl_block_number.to_le_bytes()).unwrap();
+    superblock_bytes.write(&superblock.uuid).unwrap();
+    superblock_bytes.write(&superblock.label).unwrap();
+    superblock_bytes.write(&superblock.state.to_le_bytes()).unwrap();
+    // Legacy one has no room for fields that came after it
+    superblock_bytes.truncate(self.fs_info.superblock_size as usize);
 
-    // Seek to 0 and write bytes
-    self.fs_info.realfile.borrow_mut().seek(SeekFrom::Start(0)).unwrap();
-    self.fs_info.realfile.borrow_mut().write_all(&superblock_bytes).unwrap();
-
-    Ok(())
+    // Seek to it and write bytes
+    self.write_storage(address, &superblock_bytes)
+      .map_err(|errno| errno.context("e5fs: cannot write superblock"))
   }
 
-  fn read_block(&self, block_number: AddressSize) -> Block {
+  /// Errors:
+  /// EIO -> device can't be read
+  fn read_block(&self, block_number: AddressSize) -> Result<Block, Errno> {
     let mut block_bytes = vec![0u8; self.fs_info.block_size.try_into().unwrap()];
 
     // Get absolute address of block
     let address = self.fs_info.first_block_address + block_number * self.fs_info.block_size;
 
     // Seek to it and read bytes
-    self.fs_info.realfile.borrow_mut().seek(
-      SeekFrom::Start(address.try_into().unwrap()).try_into().unwrap()
-    ).unwrap();
-    self.fs_info.realfile.borrow_mut().read_exact(&mut block_bytes).unwrap();
+    self.read_storage(address, &mut block_bytes)
+      .map_err(|errno| errno.context(format!("e5fs: cannot read block {block_number}")))?;
+    if let Some(key) = self.data_block_key(block_number) {
+      crypt::decrypt_block(key, block_number, &mut block_bytes);
+    }
 
     // Return bytes as is, as it is raw data of a file
-    Block {
+    Ok(Block {
       data: block_bytes,
-    }
+    })
   }
 
-  fn read_inode(&self, inode_number: AddressSize) -> INode {
+  /// Errors:
+  /// EIO -> device can't be read
+  fn read_inode(&self, inode_number: AddressSize) -> Result<INode, Errno> {
     use std::mem::size_of;
 
     let mut inode_bytes = vec![0u8; self.fs_info.inode_size.try_into().unwrap()];
@@ -1197,8 +2782,8 @@ impl E5FSFilesystem {
     let address = self.fs_info.first_inode_address + inode_number * self.fs_info.inode_size;
 
     // Seek to it and read bytes
-    self.fs_info.realfile.borrow_mut().seek(SeekFrom::Start(address.try_into().unwrap())).unwrap();
-    self.fs_info.realfile.borrow_mut().read_exact(&mut inode_bytes).unwrap();
+    self.read_storage(address, &mut inode_bytes)
+      .map_err(|errno| errno.context(format!("e5fs: cannot read inode {inode_number}")))?;
 
     // Then parse bytes, draining from vector mutably
     let mode = FileMode(u16::from_le_bytes(inode_bytes.drain(0..size_of::<u16>()).as_slice().try_into().unwrap())); 
@@ -1218,9 +2803,11 @@ impl E5FSFilesystem {
       block_addresses.push(AddressSize::from_le_bytes(inode_bytes.drain(0..size_of::<AddressSize>()).as_slice().try_into().unwrap()));
       block_addresses
     });
+    let xattr_block_number = AddressSize::from_le_bytes(inode_bytes.drain(0..size_of::<AddressSize>()).as_slice().try_into().unwrap()).wrapping_sub(1);
+    let snapshot_links = u16::from_le_bytes(inode_bytes.drain(0..size_of::<u16>()).as_slice().try_into().unwrap());
 
     // Return parsed
-    INode {
+    Ok(INode {
       mode,
       links_count,
       uid,
@@ -1232,28 +2819,54 @@ impl E5FSFilesystem {
       btime,
       direct_block_numbers: direct_block_numbers.try_into().unwrap(),
       indirect_block_numbers: indirect_block_numbers.try_into().unwrap(),
+      xattr_block_number,
+      snapshot_links,
       number: inode_number
-    }
+    })
+  }
+
+  /// Read bytes of device at `address` into `buffer`, going
+  /// through journal, so pending writes are seen
+  ///
+  /// Errors:
+  /// EIO -> device can't be read there, it may be truncated
+  fn read_storage(&self, address: AddressSize, buffer: &mut [u8]) -> Result<(), Errno> {
+    let mut realfile = self.fs_info.realfile.write().unwrap();
+    realfile.seek(SeekFrom::Start(address as u64))
+      .and_then(|_| realfile.read_exact(buffer))
+      .map_err(|error| Errno::EIO(error.to_string().into()))
+  }
+
+  /// Write `bytes` to device at `address`, through journal
+  ///
+  /// Errors:
+  /// EIO -> device can't be written there
+  fn write_storage(&mut self, address: AddressSize, bytes: &[u8]) -> Result<(), Errno> {
+    let mut realfile = self.fs_info.realfile.write().unwrap();
+    realfile.seek(SeekFrom::Start(address as u64))
+      .and_then(|_| realfile.write_all(bytes))
+      .map_err(|error| Errno::EIO(error.to_string().into()))
+  }
+
+  /// Returns: superblock at start of `storage`, whatever is there.
+  /// Errors: EIO if `storage` is too small to hold one
+  fn read_superblock_from(storage: &mut dyn BlockStorage) -> Result<Superblock, Errno> {
+    E5FSFilesystem::read_superblock_at(storage, 0)
   }
 
-  fn read_superblock(device_realpath: &str) -> Superblock {
+  fn read_superblock_at(storage: &mut dyn BlockStorage, address: AddressSize) -> Result<Superblock, Errno> {
     use std::mem::size_of;
 
     let mut superblock_bytes = vec![0u8; Superblock::size().try_into().unwrap()];
 
-    let realfile = RefCell::new(
-      std::fs::OpenOptions::new()
-        .read(true)
-        .write(true)
-        .open(device_realpath)
-        .unwrap()
-    );
-
-    realfile.borrow_mut().seek(SeekFrom::Start(0)).unwrap();
-    realfile.borrow_mut().read_exact(&mut superblock_bytes).unwrap();
+    storage.seek(SeekFrom::Start(address as u64))
+      .and_then(|_| storage.read_exact(&mut superblock_bytes))
+      .with_context(|| "e5fs: cannot read superblock")?;
 
     // Then parse bytes, draining from vector mutably
-    let filesystem_type: [u8; 16] = superblock_bytes.drain(0..16).as_slice().try_into().unwrap(); 
+    let filesystem_type: [u8; 8] = superblock_bytes.drain(0..8).as_slice().try_into().unwrap(); 
+    let reserved_blocks_count = AddressSize::from_le_bytes(superblock_bytes.drain(0..size_of::<AddressSize>()).as_slice().try_into().unwrap());
+    let format_version = u32::from_le_bytes(superblock_bytes.drain(0..size_of::<u32>()).as_slice().try_into().unwrap());
     let filesystem_size = AddressSize::from_le_bytes(superblock_bytes.drain(0..size_of::<AddressSize>()).as_slice().try_into().unwrap());
     let inode_table_size = AddressSize::from_le_bytes(superblock_bytes.drain(0..size_of::<AddressSize>()).as_slice().try_into().unwrap());
     let inode_table_percentage = f32::from_le_bytes(superblock_bytes.drain(0..size_of::<f32>()).as_slice().try_into().unwrap());
@@ -1268,9 +2881,14 @@ impl E5FSFilesystem {
       free_inode_numbers 
     });
     let first_fbl_block_number = AddressSize::from_le_bytes(superblock_bytes.drain(0..size_of::<AddressSize>()).as_slice().try_into().unwrap());
+    let uuid: [u8; 16] = superblock_bytes.drain(0..16).as_slice().try_into().unwrap(); 
+    let label: [u8; LABEL_MAX_LEN] = superblock_bytes.drain(0..LABEL_MAX_LEN).as_slice().try_into().unwrap(); 
+    let state = u32::from_le_bytes(superblock_bytes.drain(0..size_of::<u32>()).as_slice().try_into().unwrap());
 
-    Superblock {
+    let mut superblock = Superblock {
       filesystem_type,
+      reserved_blocks_count,
+      format_version,
       filesystem_size,
       inode_table_size,
       inode_table_percentage,
@@ -1282,7 +2900,35 @@ impl E5FSFilesystem {
       block_data_size,
       free_inode_numbers: free_inode_numbers.try_into().unwrap(),
       first_fbl_block_number,
+      uuid,
+      label,
+      state,
+    };
+
+    // Past legacy superblock is inode table, not these
+    if superblock.on_disk_size() == Some(LEGACY_SUPERBLOCK_SIZE) {
+      superblock.uuid = [0; 16];
+      superblock.label = [0; LABEL_MAX_LEN];
+      superblock.state = STATE_CLEAN;
+    }
+
+    Ok(superblock)
+  }
+
+  /// Look for e5fs on `storage` without mounting it.
+  /// Returns: `None` if there is none
+  pub fn probe(storage: &mut dyn BlockStorage) -> Option<FilesystemIdentity> {
+    // Guard for device too small to even hold a superblock
+    if storage.size() < Superblock::size() as u64 {
+      return None;
     }
+
+    let (superblock, _) = E5FSFilesystem::find_superblock(storage).ok()?;
+
+    Some(FilesystemIdentity {
+      uuid: Uuid::from_bytes(superblock.uuid).to_string(),
+      label: superblock.label(),
+    })
   }
 
   /// Parse one fbl block and return it for further use
@@ -1308,19 +2954,27 @@ impl E5FSFilesystem {
     // pub name_len: u8,
     // pub name: String,
 
+    // Directory may be cut short, so draining past its end is an error
+    let drain = |data: &mut Vec<u8>, count: usize, what: &str| -> Result<Vec<u8>, Errno> {
+      match data.len() >= count {
+        true => Ok(data.drain(0..count).collect()),
+        false => Err(Errno::EILSEQ(format!("can't parse {what}").into())),
+      }
+    };
+
     let drain_one_entry = |data: &mut Vec<u8>| -> Result<DirectoryEntry, Errno> {
       let address_size = size_of::<AddressSize>();
 
-      let inode_number = AddressSize::from_le_bytes(data.drain(0..address_size as usize).as_slice().try_into().or(Err(Errno::EILSEQ(String::from("can't parse inode_number"))))?);
-      let rec_len = u16::from_le_bytes(data.drain(0..size_of::<u16>()).as_slice().try_into().or(Err(Errno::EILSEQ(String::from("can't parse rec_len"))))?);
-      let name_len = u8::from_le_bytes(data.drain(0..size_of::<u8>()).as_slice().try_into().or(Err(Errno::EILSEQ(String::from("can't parse name_len"))))?);
-      let name = String::from_utf8(data.drain(0..name_len as usize).collect()).or(Err(Errno::EILSEQ(String::from("can't parse name"))))?;
+      let inode_number = AddressSize::from_le_bytes(drain(data, address_size, "inode_number")?.try_into().unwrap());
+      let rec_len = u16::from_le_bytes(drain(data, size_of::<u16>(), "rec_len")?.try_into().unwrap());
+      let name_len = u8::from_le_bytes(drain(data, size_of::<u8>(), "name_len")?.try_into().unwrap());
+      let name = String::from_utf8(drain(data, name_len as usize, "name")?).or(Err(Errno::EILSEQ(String::from("can't parse name").into())))?;
 
       // NOTICE: May be an off by 1 error here 
-      if inode_number >= fs_info.inodes_count - 1 {
-        return Err(Errno::EILSEQ(String::from("parse_directory: drain_one_entry: inode_number out of bounds")));
+      if inode_number >= fs_info.inodes_count.saturating_sub(1) {
+        return Err(Errno::EILSEQ(String::from("parse_directory: drain_one_entry: inode_number out of bounds").into()));
       } else if (rec_len as usize) < (address_size + size_of::<u16>() + size_of::<u8>() + size_of::<u8>()) {
-        return Err(Errno::EILSEQ(String::from("parse_directory: drain_one_entry: rec_len is smaller than minimal")));
+        return Err(Errno::EILSEQ(String::from("parse_directory: drain_one_entry: rec_len is smaller than minimal").into()));
       }
 
       Ok(DirectoryEntry {
@@ -1332,10 +2986,9 @@ impl E5FSFilesystem {
     };
 
     let entries_count = AddressSize::from_le_bytes(
-      data.drain(0..size_of::<AddressSize>() as usize)
-        .as_slice()
+      drain(&mut data, size_of::<AddressSize>(), "entries_count from dir")?
         .try_into()
-        .or(Err(Errno::EILSEQ(String::from("can't parse entries_count from dir"))))?
+        .unwrap()
       );
 
 
@@ -1347,7 +3000,7 @@ impl E5FSFilesystem {
           entries.insert(entry.name.to_owned(), entry); 
         },
         Err(errno) => {
-          eprintln!("info: parse_directory: got to the end of directory: entry index: {entry_index} errno: {:?}", errno);
+          tracing::debug!(entry_index, %errno, "parse_directory: got to the end of directory");
           break;
         },
       }
@@ -1356,51 +3009,10 @@ impl E5FSFilesystem {
     Ok(Directory::from(entries))
   }
 
-  fn generate_fbl(&self) -> Vec<AddressSize> {
-    let fbl_size_in_slots = 
-      (self.fs_info.block_size / self.fs_info.address_size) * self.fs_info.blocks_needed_for_fbl;
-
-    // Zip stub iterator with number of elements equal to
-    // amount of slots in `fbl` sector
-    // with actual free block numbers tailed with NO_ADDRESS
-    (0..fbl_size_in_slots)
-      .zip((0..self.fs_info.first_fbl_block_number).chain(std::iter::repeat(NO_ADDRESS)))
-      .map(|(_, block_address)| block_address)
-      .collect()
-  }
-
-  fn write_fbl(&mut self) {
-    let fbl = self.generate_fbl();
-
-    // let fbl_bytes: Vec<u8> = fbl.iter().flat_map(|x| x.to_le_bytes()).collect();
-
-    // let address = self.fs_info.first_block_address + self.fs_info.first_fbl_block_number * self.fs_info.block_size;
-
-    // self.fs_info.realfile.borrow_mut().seek(SeekFrom::Start(address.try_into().unwrap())).unwrap();
-    // self.fs_info.realfile.borrow_mut().write_all(&fbl_bytes).unwrap();
-
-    // Write free blocks list to last N blocks
-    // [ sb ... i1..iN ... b1[b1..bX ... fbl1..fblN]bN ]
-    // Something like that ^
-    // use itertools::Itertools;
-    fbl
-      .into_iter()
-      .flat_map(AddressSize::to_le_bytes)
-      .chunks(self.fs_info.block_size as usize)
-      .into_iter()
-      .zip(self.fs_info.first_fbl_block_number..self.fs_info.blocks_count)
-      .for_each(|(block_bytes, block_number)| {
-        let block = Block {
-          data: block_bytes.collect(),
-        };
-        self.write_block(&block, block_number).unwrap();
-      });
-  }
-
   fn write_links_count_i(&mut self, inode_number: AddressSize, links_count: u32)
     -> Result<INode, Errno>
   {
-    let mut inode = self.read_inode(inode_number);
+    let mut inode = self.read_inode(inode_number)?;
     inode.links_count = links_count;
     self.write_inode(&inode, inode_number)?;
 
@@ -1416,7 +3028,7 @@ impl E5FSFilesystem {
     //   .enumerate()
     //   .filter(|(_, n)| *n == NO_ADDRESS) 
     //   .zip((1..self.fs_info.inodes_count)
-    //     .map(|n| Some(self.read_inode(n)))
+    //     .map(|n| Some(self.read_inode(n)?))
     //     .filter(|inode| inode.unwrap().mode.free() == 1)
     //     .chain(std::iter::repeat(None))
     //   )
@@ -1425,7 +3037,7 @@ impl E5FSFilesystem {
     //     // FIXME: will error even if we have some free inodes left,
     //     // but not enough to refill the whole `free_inode_numbers`
     //     free_inode_number
-    //       .ok_or(Errno::ENOSPC(format!("e5fs::refill_free_inode_numbers: no free inodes left in the filesystem")))?
+    //       .ok_or(Errno::ENOSPC(format!("e5fs::refill_free_inode_numbers: no free inodes left in the filesystem").into()))?
     //       .number
     //   ;
     //   *self
@@ -1436,13 +3048,18 @@ impl E5FSFilesystem {
     //   ;
     // }
     //
+    let free_inodes = (1..self.fs_info.inodes_count)
+      .map(|n| self.read_inode(n))
+      .filter(|inode| inode.as_ref().map_or(true, |inode| inode.mode.free() == 1))
+      .take(self.superblock.free_inode_numbers.len())
+      .collect::<Result<Vec<INode>, Errno>>()?;
     let new_free_inode_numbers: Vec<AddressSize> = self
       .superblock
       .free_inode_numbers
       .into_iter()
-      .zip((1..self.fs_info.inodes_count)
-        .map(|n| Some(self.read_inode(n)))
-        .filter(|inode| inode.unwrap().mode.free() == 1)
+      .zip(free_inodes
+        .into_iter()
+        .map(Some)
         .chain(std::iter::repeat(None))
       )
       .flat_map(|(inode_number, free_inode_number)| {
@@ -1458,7 +3075,7 @@ impl E5FSFilesystem {
     // FIXME: will error even if we have some free inodes left,
     // but not enough to refill the whole `free_inode_numbers`
     if new_free_inode_numbers.len() != self.superblock.free_inode_numbers.len() {
-      return Err(Errno::ENOSPC(format!("e5fs::refill_free_inode_numbers: no free inodes left in the filesystem")));
+      return Err(Errno::ENOSPC(format!("e5fs::refill_free_inode_numbers: no free inodes left in the filesystem").into()));
     }
 
     for (i, inode_number) in &mut self.superblock.free_inode_numbers.iter_mut().enumerate() {
@@ -1473,8 +3090,12 @@ impl E5FSFilesystem {
 mod e5fs_fs_tests {
   use std::array::IntoIter;
 
-use crate::{util::{mktemp, mkenxvd}, eunix::fs::NOBODY_UID};
+use crate::{util::{mktemp, mkenxvd}, eunix::{fs::NOBODY_UID, devices::MemoryStorage}};
+  use std::sync::Arc;
+  use crate::util::unixtime;
   use super::*;
+  use crate::testing::{memory_e5fs, E5FS_SIZE};
+  use crate::eunix::kernel::ROOT_GID;
 
   #[test]
   fn write_superblock_works() {
@@ -1488,7 +3109,8 @@ use crate::{util::{mktemp, mkenxvd}, eunix::fs::NOBODY_UID};
 
     drop(e5fs);
 
-    let superblock_from_file = E5FSFilesystem::read_superblock(tempfile.as_str());
+    let mut storage = FileRegion::open(tempfile.as_str()).unwrap();
+    let superblock_from_file = E5FSFilesystem::read_superblock_from(&mut storage).unwrap();
 
     assert_eq!(superblock_from_file, superblock);
   }
@@ -1515,6 +3137,8 @@ use crate::{util::{mktemp, mkenxvd}, eunix::fs::NOBODY_UID};
         btime: unixtime(),
         direct_block_numbers: [i % 5; 12],
         indirect_block_numbers: [i % 6; 3],
+        xattr_block_number: i % 7,
+        snapshot_links: (i % 8) as u16,
         number: i,
       });
 
@@ -1534,7 +3158,7 @@ use crate::{util::{mktemp, mkenxvd}, eunix::fs::NOBODY_UID};
       .iter()
       .zip(inode_indices.clone())
       .for_each(|(inode, _inode_number)| {
-        let inode_from_file = e5fs.read_inode(inode.number);
+        let inode_from_file = e5fs.read_inode(inode.number).unwrap();
 
         assert_eq!(*inode, inode_from_file);
       });
@@ -1567,7 +3191,7 @@ use crate::{util::{mktemp, mkenxvd}, eunix::fs::NOBODY_UID};
       .iter()
       .zip(block_indices.clone())
       .for_each(|(block, block_number)| {
-        let block_from_file = e5fs.read_block(block_number);
+        let block_from_file = e5fs.read_block(block_number).unwrap();
 
         assert_eq!(*block, block_from_file, "block {block_number} should be correctly read");
       });
@@ -1579,20 +3203,18 @@ use crate::{util::{mktemp, mkenxvd}, eunix::fs::NOBODY_UID};
     mkenxvd("1M".to_owned(), tempfile.clone());
 
     let mut e5fs = E5FSFilesystem::mkfs(tempfile.as_str(), 0.05, 4096).unwrap();
-
-    e5fs.write_fbl();
-
-    let fbl = e5fs.generate_fbl();
-
-    let fbl_from_file: Vec<AddressSize> = (e5fs.fs_info.first_fbl_block_number..e5fs.fs_info.blocks_count)
-      .flat_map(|fbl_block_number| { 
-        E5FSFilesystem::parse_block_numbers_from_block(
-          &e5fs.read_block(fbl_block_number)
-        ) 
-      })
-      .collect();
-
-    assert_eq!(fbl, fbl_from_file, "fbl from file should match expected");
+    let blocks_count = e5fs.fs_info.first_fbl_block_number;
+
+    let references = (0..blocks_count).map(|block_number| block_number % 3).collect::<Vec<_>>();
+    e5fs.write_references(references.clone()).unwrap();
+
+    let references_from_file = e5fs.read_references().unwrap();
+    assert_eq!(references_from_file, references.iter().copied().map(Some).collect::<Vec<_>>(), "fbl from file should match expected");
+    assert_eq!(e5fs.read_free_blocks().unwrap().free_count(), blocks_count.div_ceil(3));
+    assert_eq!(e5fs.read_block_references(4).unwrap(), Some(1));
+    e5fs.write_block_references(4, 0).unwrap();
+    assert_eq!(e5fs.read_block_references(4).unwrap(), Some(0));
+    assert_eq!(e5fs.read_block_references(5).unwrap(), Some(2));
   }
 
   #[test]
@@ -1635,7 +3257,7 @@ use crate::{util::{mktemp, mkenxvd}, eunix::fs::NOBODY_UID};
     }
 
     // Read these inodes back and compare
-    let inodes_read: Vec<INode> = range.clone().map(|n| e5fs.read_inode(n + 1)).collect();
+    let inodes_read: Vec<INode> = range.clone().map(|n| e5fs.read_inode(n + 1).unwrap()).collect();
     assert_eq!(inodes, inodes_read, "allocated and read inodes should be equal");
   }
 
@@ -1672,9 +3294,9 @@ use crate::{util::{mktemp, mkenxvd}, eunix::fs::NOBODY_UID};
     let tempfile = mktemp().to_owned();
     mkenxvd("1M".to_owned(), tempfile.clone());
 
-    let mut e5fs = E5FSFilesystem::mkfs(tempfile.as_str(), 0.05, 4096).unwrap();
+    let e5fs = E5FSFilesystem::mkfs(tempfile.as_str(), 0.05, 4096).unwrap();
 
-    let block_number = e5fs.find_block_in_fbl(|block_number| block_number != NO_ADDRESS).unwrap();
+    let block_number = e5fs.read_free_blocks().unwrap().first_free().unwrap();
 
     assert_eq!(1, block_number);
   }
@@ -1685,16 +3307,16 @@ use crate::{util::{mktemp, mkenxvd}, eunix::fs::NOBODY_UID};
     mkenxvd("10M".to_owned(), tempfile.clone());
 
     let mut e5fs = E5FSFilesystem::mkfs(tempfile.as_str(), 0.05, 4096).unwrap();
-    let root_inode = e5fs.read_inode(0);
+    let root_inode = e5fs.read_inode(0).unwrap();
     let mnt_inode = e5fs.allocate_file().unwrap().1;
     let bin_inode = e5fs.allocate_file().unwrap().1;
     let home_inode = e5fs.allocate_file().unwrap().1;
     e5fs.write_mode_i(home_inode.number, home_inode.mode.with_file_type(FileModeType::Dir as u8)).unwrap();
     e5fs.write_mode_i(bin_inode.number, bin_inode.mode.with_file_type(FileModeType::Dir as u8)).unwrap();
     e5fs.write_mode_i(mnt_inode.number, mnt_inode.mode.with_file_type(FileModeType::Dir as u8)).unwrap();
-    let mnt_inode = e5fs.read_inode(mnt_inode.number);
-    let bin_inode = e5fs.read_inode(bin_inode.number);
-    let home_inode = e5fs.read_inode(home_inode.number);
+    let mnt_inode = e5fs.read_inode(mnt_inode.number).unwrap();
+    let bin_inode = e5fs.read_inode(bin_inode.number).unwrap();
+    let home_inode = e5fs.read_inode(home_inode.number).unwrap();
     let expected_root_directory = {
       let mut dir = Directory::new();
       dir.insert(root_inode.number, ".").unwrap();
@@ -1722,7 +3344,7 @@ use crate::{util::{mktemp, mkenxvd}, eunix::fs::NOBODY_UID};
     let nrv_inode = {
       let nrv_inode = e5fs.allocate_file().unwrap().1;
       e5fs.write_mode_i(nrv_inode.number, nrv_inode.mode.with_file_type(FileModeType::Dir as u8)).unwrap();
-      e5fs.read_inode(nrv_inode.number)
+      e5fs.read_inode(nrv_inode.number).unwrap()
     };
     let bashrc_inode = e5fs.allocate_file().unwrap().1;
 
@@ -1750,7 +3372,7 @@ use crate::{util::{mktemp, mkenxvd}, eunix::fs::NOBODY_UID};
     let read_nrv_directory = e5fs.read_as_dir_i(nrv_inode.number).unwrap();
     assert_eq!(expected_nrv_directory, read_nrv_directory, "nrv directory should contain all created files");
 
-    let first_fbl_block = E5FSFilesystem::parse_block_numbers_from_block(&e5fs.read_block(e5fs.fs_info.first_fbl_block_number));
+    let first_fbl_block = E5FSFilesystem::parse_block_numbers_from_block(&e5fs.read_block(e5fs.fs_info.first_fbl_block_number).unwrap());
     let read_nrv_vinode = e5fs.lookup_path("/home/nrv").unwrap();
     let read_bashrc_vinode = e5fs.lookup_path("/home/nrv/.bashrc").unwrap();
 
@@ -1774,7 +3396,7 @@ use crate::{util::{mktemp, mkenxvd}, eunix::fs::NOBODY_UID};
 
     let root_vinode = e5fs.lookup_path("/").unwrap();
     assert_eq!(root_vinode.number, 0, "inode_number of root_inode should be 0");
-    let root_inode = e5fs.read_inode(root_vinode.number);
+    let root_inode = e5fs.read_inode(root_vinode.number).unwrap();
 
     assert_eq!(root_vinode, root_inode.into(), "looked up and read vinode and directly read inode should be equal");
 
@@ -1855,7 +3477,7 @@ use crate::{util::{mktemp, mkenxvd}, eunix::fs::NOBODY_UID};
     let mut e5fs = E5FSFilesystem::from(tempfile.as_str()).unwrap();
 
     let vinode = e5fs.create_file("/test1").unwrap();
-    let vinode_from_disk: VINode = e5fs.read_inode(1).into();
+    let vinode_from_disk: VINode = e5fs.read_inode(1).unwrap().into();
 
     assert_eq!(vinode_from_disk, vinode);
   }
@@ -1870,7 +3492,7 @@ use crate::{util::{mktemp, mkenxvd}, eunix::fs::NOBODY_UID};
     let vinode1 = e5fs.create_file("/test12").unwrap();
 
     // Change type to Dir
-    let mut inode1 = e5fs.read_inode(vinode1.number);
+    let mut inode1 = e5fs.read_inode(vinode1.number).unwrap();
     inode1.mode = inode1.mode.with_file_type(FileModeType::Dir as u8);
     e5fs.write_inode(&inode1, inode1.number).unwrap();
 
@@ -1878,6 +3500,808 @@ use crate::{util::{mktemp, mkenxvd}, eunix::fs::NOBODY_UID};
 
     assert_eq!(vinode2.number, 2);
   }
+
+  #[test]
+  fn mknod_works() {
+    let tempfile = mktemp().to_owned();
+    mkenxvd("1M".to_owned(), tempfile.clone());
+
+    let mut e5fs = E5FSFilesystem::mkfs(tempfile.as_str(), 0.05, 4096).unwrap();
+
+    let vinode = e5fs.mknod("/sdb", FileModeType::Block, DeviceNumber::new(8, 16)).unwrap();
+    let stat = e5fs.stat("/sdb").unwrap();
+
+    assert_eq!(vinode.mode.file_type(), FileModeType::Block as u8);
+    assert_eq!(vinode.rdev, DeviceNumber::new(8, 16));
+    assert_eq!(stat.rdev, DeviceNumber::new(8, 16));
+    assert_eq!(stat.size, 0);
+  }
+
+  #[test]
+  fn link_works() {
+    let tempfile = mktemp().to_owned();
+    mkenxvd("1M".to_owned(), tempfile.clone());
+
+    let mut e5fs = E5FSFilesystem::mkfs(tempfile.as_str(), 0.05, 4096).unwrap();
+    e5fs.create_file("/file").unwrap();
+    e5fs.write_file("/file", b"shared").unwrap();
+
+    let vinode = e5fs.link("/file", "/alias").unwrap();
+    assert_eq!(vinode.links_count, 2);
+    assert_eq!(e5fs.stat("/alias").unwrap().inode_number, e5fs.stat("/file").unwrap().inode_number);
+    assert!(matches!(e5fs.link("/file", "/alias"), Err(Errno::EEXIST(_))));
+    assert!(matches!(e5fs.link("/", "/root"), Err(Errno::EPERM(_))));
+
+    // Data stays while there is a name left
+    e5fs.remove_file("/file").unwrap();
+    assert_eq!(e5fs.read_file("/alias", AddressSize::MAX).unwrap(), b"shared");
+    assert_eq!(e5fs.stat("/alias").unwrap().links_count, 1);
+  }
+
+  #[test]
+  fn rename_works() {
+    let (mut e5fs, _) = memory_e5fs(E5FS_SIZE);
+    e5fs.create_dir("/etc").unwrap();
+    e5fs.create_dir("/home").unwrap();
+    e5fs.create_file("/etc/motd").unwrap();
+    e5fs.write_file("/etc/motd", b"hello").unwrap();
+    let motd_number = e5fs.stat("/etc/motd").unwrap().inode_number;
+
+    e5fs.rename("/etc/motd", "/motd").unwrap();
+    assert!(e5fs.lookup_path("/etc/motd").is_err());
+    assert_eq!(e5fs.stat("/motd").unwrap().inode_number, motd_number);
+    assert_eq!(e5fs.read_file("/motd", AddressSize::MAX).unwrap(), b"hello");
+
+    // File in the way is replaced
+    e5fs.create_file("/issue").unwrap();
+    e5fs.rename("/motd", "/issue").unwrap();
+    assert_eq!(e5fs.read_file("/issue", AddressSize::MAX).unwrap(), b"hello");
+
+    // Directory takes `..` and a link of parent with it
+    let root_links_count = e5fs.stat("/").unwrap().links_count;
+    e5fs.create_dir("/etc/skel").unwrap();
+    e5fs.rename("/etc", "/home/etc").unwrap();
+    assert_eq!(e5fs.stat("/").unwrap().links_count, root_links_count - 1);
+    assert_eq!(e5fs.stat("/home/etc/..").unwrap().inode_number, e5fs.stat("/home").unwrap().inode_number);
+    assert!(matches!(e5fs.rename("/home", "/home/etc/skel/home"), Err(Errno::EINVAL(_))));
+    assert!(matches!(e5fs.rename("/issue", "/home/etc/skel"), Err(Errno::EISDIR(_))));
+    assert!(matches!(e5fs.rename("/home/etc/skel", "/issue"), Err(Errno::ENOTDIR(_))));
+    assert!(matches!(e5fs.rename("/home/etc/skel", "/home/etc"), Err(Errno::ENOTEMPTY(_))));
+    e5fs.create_dir("/skel").unwrap();
+    e5fs.rename("/home/etc/skel", "/skel").unwrap();
+    assert!(fsck::check(&mut e5fs, false).unwrap().is_clean());
+  }
+
+  #[test]
+  fn rmdir_releases_empty_directory() {
+    let (mut e5fs, _) = memory_e5fs(E5FS_SIZE);
+    let free_inodes_count = e5fs.superblock.free_inodes_count;
+    let root_links_count = e5fs.stat("/").unwrap().links_count;
+    e5fs.create_dir("/var").unwrap();
+    e5fs.create_file("/var/log").unwrap();
+
+    assert!(matches!(e5fs.rmdir("/var"), Err(Errno::ENOTEMPTY(_))));
+    assert!(matches!(e5fs.rmdir("/var/log"), Err(Errno::ENOTDIR(_))));
+    assert!(matches!(e5fs.unlink("/var"), Err(Errno::EISDIR(_))));
+    e5fs.unlink("/var/log").unwrap();
+    e5fs.rmdir("/var").unwrap();
+
+    assert!(e5fs.lookup_path("/var").is_err());
+    assert_eq!(e5fs.superblock.free_inodes_count, free_inodes_count);
+    assert_eq!(e5fs.stat("/").unwrap().links_count, root_links_count);
+    assert!(fsck::check(&mut e5fs, false).unwrap().is_clean());
+  }
+
+  #[test]
+  fn symlink_works() {
+    let tempfile = mktemp().to_owned();
+    mkenxvd("1M".to_owned(), tempfile.clone());
+
+    let mut e5fs = E5FSFilesystem::mkfs(tempfile.as_str(), 0.05, 4096).unwrap();
+    let vinode = e5fs.symlink("/nowhere/file", "/link").unwrap();
+
+    assert_eq!(vinode.mode.file_type(), FileModeType::Symlink as u8);
+    assert_eq!(e5fs.readlink("/link").unwrap(), "/nowhere/file");
+    assert!(matches!(e5fs.symlink("/file", "/link"), Err(Errno::EINVAL(_))));
+    assert!(matches!(e5fs.readlink("/"), Err(Errno::EINVAL(_))));
+  }
+
+  #[test]
+  fn failed_write_leaves_filesystem_as_it_was() {
+    let (mut e5fs, buffer) = memory_e5fs(256 * 1024);
+    e5fs.create_file("/file").unwrap();
+    let free_blocks_count = e5fs.superblock.free_blocks_count;
+
+    let data = vec![b'x'; 200 * e5fs.fs_info.block_data_size as usize];
+    assert!(matches!(e5fs.write_file("/file", &data), Err(Errno::ENOSPC(_))));
+    assert!(matches!(e5fs.write_at("/file", 4096, &data), Err(Errno::ENOSPC(_))));
+    assert_eq!(e5fs.superblock.free_blocks_count, free_blocks_count);
+    assert_eq!(e5fs.stat("/file").unwrap().size, 0);
+    assert!(fsck::check(&mut e5fs, false).unwrap().is_clean());
+
+    // Blocks are there to be claimed, on disk too
+    e5fs.write_file("/file", &data[..3 * 4096]).unwrap();
+    let free_blocks_count = e5fs.superblock.free_blocks_count;
+    drop(e5fs);
+    let mut e5fs = E5FSFilesystem::from_storage(Box::new(MemoryStorage::new(buffer))).unwrap();
+    assert_eq!(e5fs.superblock.free_blocks_count, free_blocks_count);
+    assert!(fsck::check(&mut e5fs, false).unwrap().is_clean());
+  }
+
+  #[test]
+  fn append_file_works() {
+    let (mut e5fs, _) = memory_e5fs(E5FS_SIZE);
+    e5fs.create_file("/log").unwrap();
+    e5fs.write_file("/log", &[b'a'; 4000]).unwrap();
+
+    // Goes past the end of the first block
+    let vinode = e5fs.append_file("/log", &[b'b'; 200]).unwrap();
+    assert_eq!(vinode.file_size, 4200);
+    e5fs.append_file("/log", b"end").unwrap();
+
+    let data = e5fs.read_file("/log", AddressSize::MAX).unwrap();
+    assert_eq!(data.len(), 4203);
+    assert!(data[..4000].iter().all(|&byte| byte == b'a'));
+    assert!(data[4000..4200].iter().all(|&byte| byte == b'b'));
+    assert_eq!(&data[4200..], b"end");
+    assert!(matches!(e5fs.append_file("/", b"x"), Err(Errno::EISDIR(_))));
+  }
+
+  #[test]
+  fn read_and_write_at_offset_works() {
+    let (mut e5fs, _) = memory_e5fs(E5FS_SIZE);
+    e5fs.create_file("/file").unwrap();
+    e5fs.write_file("/file", &[b'a'; 6000]).unwrap();
+    e5fs.write_file("/file", b"short").unwrap();
+
+    // Old bytes past the end don't come back in the gap
+    let vinode = e5fs.write_at("/file", 8190, b"over block").unwrap();
+    assert_eq!(vinode.file_size, 8200);
+    e5fs.write_at("/file", 1, b"HO").unwrap();
+
+    assert_eq!(e5fs.read_at("/file", 0, 5).unwrap(), b"sHOrt");
+    assert_eq!(e5fs.read_at("/file", 5, 8185).unwrap(), vec![0u8; 8185]);
+    assert_eq!(e5fs.read_at("/file", 8190, 100).unwrap(), b"over block");
+    assert_eq!(e5fs.read_at("/file", 9000, 100).unwrap(), b"");
+    assert_eq!(e5fs.read_file("/file", AddressSize::MAX).unwrap().len(), 8200);
+  }
+
+  /// Count of blocks inode has claimed, holes are not counted
+  fn claimed_blocks_count(e5fs: &E5FSFilesystem, inode_number: AddressSize) -> usize {
+    let inode = e5fs.read_inode(inode_number).unwrap();
+    e5fs.block_numbers_i(&inode).unwrap()
+      .into_iter()
+      .filter(|&block_number| block_number != NO_ADDRESS)
+      .count()
+  }
+
+  #[test]
+  fn truncate_releases_and_zeroes_blocks() {
+    let (mut e5fs, _) = memory_e5fs(E5FS_SIZE);
+    e5fs.create_file("/file").unwrap();
+    e5fs.write_file("/file", &[b'a'; 9000]).unwrap();
+    let number = e5fs.lookup_path("/file").unwrap().number;
+    let last_block = e5fs.read_inode(number).unwrap().direct_block_numbers[2];
+
+    // Shorter write gives blocks back
+    e5fs.write_file("/file", b"short").unwrap();
+    assert_eq!(claimed_blocks_count(&e5fs, number), 1);
+    assert_eq!(e5fs.block_references(last_block).unwrap(), 0);
+
+    assert_eq!(e5fs.truncate("/file", 2).unwrap().file_size, 2);
+    let vinode = e5fs.truncate("/file", 5000).unwrap();
+    assert_eq!(vinode.file_size, 5000);
+    assert_eq!(e5fs.read_file("/file", AddressSize::MAX).unwrap()[..6], *b"sh\0\0\0\0");
+    assert_eq!(e5fs.truncate("/file", 0).unwrap().file_size, 0);
+    assert_eq!(claimed_blocks_count(&e5fs, number), 0);
+    assert!(matches!(e5fs.truncate("/", 0), Err(Errno::EISDIR(_))));
+  }
+
+  #[test]
+  fn sparse_files_have_holes() {
+    let (mut e5fs, _) = memory_e5fs(E5FS_SIZE);
+    e5fs.create_file("/sparse").unwrap();
+    let number = e5fs.lookup_path("/sparse").unwrap().number;
+    e5fs.write_file("/sparse", b"head").unwrap();
+
+    // Only first and last blocks are claimed
+    let vinode = e5fs.write_at("/sparse", 10 * 4096, b"tail").unwrap();
+    assert_eq!(vinode.file_size, 10 * 4096 + 4);
+    assert_eq!(claimed_blocks_count(&e5fs, number), 2);
+    assert_eq!(e5fs.read_inode(number).unwrap().direct_block_numbers[5], NO_ADDRESS);
+
+    assert_eq!(e5fs.read_at("/sparse", 4090, 12).unwrap(), vec![0u8; 12]);
+    let data = e5fs.read_file("/sparse", AddressSize::MAX).unwrap();
+    assert_eq!(data[..4], *b"head");
+    assert!(data[4..10 * 4096].iter().all(|&byte| byte == 0));
+    assert_eq!(data[10 * 4096..], *b"tail");
+
+    // Writing to a hole claims only its block
+    e5fs.write_at("/sparse", 5 * 4096 + 1, b"middle").unwrap();
+    assert_eq!(claimed_blocks_count(&e5fs, number), 3);
+    assert_eq!(e5fs.read_at("/sparse", 5 * 4096, 8).unwrap(), b"\0middle\0");
+
+    // Holes past end of file are not claimed either
+    e5fs.truncate("/sparse", 11 * 4096).unwrap();
+    assert_eq!(claimed_blocks_count(&e5fs, number), 3);
+    assert!(fsck::check(&mut e5fs, false).unwrap().is_clean());
+
+    e5fs.truncate("/sparse", 4).unwrap();
+    assert_eq!(claimed_blocks_count(&e5fs, number), 1);
+    assert!(fsck::check(&mut e5fs, false).unwrap().is_clean());
+  }
+
+  #[test]
+  fn resize_grows_filesystem() {
+    let (mut e5fs, buffer) = memory_e5fs(512 * 1024);
+    e5fs.create_dir("/dir").unwrap();
+    e5fs.create_file("/dir/file").unwrap();
+    e5fs.write_file("/dir/file", &[b'a'; 9000]).unwrap();
+    let (inodes_count, blocks_count) = (e5fs.fs_info.inodes_count, e5fs.fs_info.blocks_count);
+    e5fs.unmount().unwrap();
+
+    // Device grew, filesystem didn't yet
+    let mut grown = buffer.read().unwrap().clone();
+    grown.resize(1024 * 1024, 0);
+    let grown = Arc::new(RwLock::new(grown));
+    let mut e5fs = E5FSFilesystem::from_storage(Box::new(MemoryStorage::new(grown.clone()))).unwrap();
+    assert_eq!(e5fs.fs_info.blocks_count, blocks_count);
+    assert!(!e5fs.resize(Box::new(MemoryStorage::new(buffer.clone()))).unwrap());
+
+    assert!(e5fs.resize(Box::new(MemoryStorage::new(grown.clone()))).unwrap());
+    assert!(e5fs.fs_info.inodes_count > inodes_count);
+    assert!(e5fs.fs_info.blocks_count > blocks_count);
+    assert_eq!(e5fs.read_file("/dir/file", AddressSize::MAX).unwrap(), vec![b'a'; 9000]);
+    assert!(fsck::check(&mut e5fs, false).unwrap().is_clean());
+
+    // New blocks and inodes can be used, and are there after remount
+    e5fs.create_file("/big").unwrap();
+    e5fs.write_file("/big", &vec![b'b'; 12 * 4096]).unwrap();
+    e5fs.unmount().unwrap();
+    let mut e5fs = E5FSFilesystem::from_storage(Box::new(MemoryStorage::new(grown))).unwrap();
+    assert_eq!(e5fs.read_file("/big", AddressSize::MAX).unwrap(), vec![b'b'; 12 * 4096]);
+    assert!(fsck::check(&mut e5fs, false).unwrap().is_clean());
+
+    let smaller = Arc::new(RwLock::new(vec![0u8; 256 * 1024]));
+    assert!(matches!(e5fs.resize(Box::new(MemoryStorage::new(smaller))), Err(Errno::EINVAL(_))));
+  }
+
+  #[test]
+  fn resize_moves_encrypted_blocks() {
+    let buffer = Arc::new(RwLock::new(vec![0u8; 512 * 1024]));
+    let mut e5fs = E5FSFilesystem::mkfs_storage_with_passphrase(
+      Box::new(MemoryStorage::new(buffer.clone())), 0.05, 4096, Some("hunter2"),
+    ).unwrap();
+    let files = (0..8).map(|number| (format!("/file{number}"), vec![b'a' + number; 4096 * (number as usize + 1)]));
+    for (pathname, data) in files.clone() {
+      e5fs.create_file(&pathname).unwrap();
+      e5fs.write_file(&pathname, &data).unwrap();
+    }
+    e5fs.unmount().unwrap();
+
+    let mut grown = buffer.read().unwrap().clone();
+    grown.resize(2 * 1024 * 1024, 0);
+    let grown = Arc::new(RwLock::new(grown));
+    let mut e5fs = E5FSFilesystem::from_storage_with_passphrase(Box::new(MemoryStorage::new(grown.clone())), Some("hunter2")).unwrap();
+    assert!(e5fs.resize(Box::new(MemoryStorage::new(grown.clone()))).unwrap());
+    e5fs.unmount().unwrap();
+
+    let mut e5fs = E5FSFilesystem::from_storage_with_passphrase(Box::new(MemoryStorage::new(grown)), Some("hunter2")).unwrap();
+    for (pathname, data) in files {
+      assert_eq!(e5fs.read_file(&pathname, AddressSize::MAX).unwrap(), data);
+    }
+    assert!(fsck::check(&mut e5fs, false).unwrap().is_clean());
+  }
+
+  #[test]
+  fn corrupt_superblock_is_restored_from_backup() {
+    let (mut e5fs, buffer) = memory_e5fs(E5FS_SIZE);
+    e5fs.create_file("/file").unwrap();
+    e5fs.write_file("/file", b"contents").unwrap();
+    let uuid = e5fs.superblock.uuid;
+    assert_eq!(e5fs.superblock_backup_blocks().len(), 1);
+    assert!(fsck::check(&mut e5fs, false).unwrap().is_clean());
+    e5fs.unmount().unwrap();
+
+    buffer.write().unwrap()[..Superblock::size() as usize].fill(0xff);
+    let mut e5fs = E5FSFilesystem::from_storage(Box::new(MemoryStorage::new(buffer.clone()))).unwrap();
+    assert_eq!(e5fs.superblock_backup, Some(SUPERBLOCK_BACKUP_ADDRESSES[0]));
+    assert!(e5fs.needs_check);
+    assert_eq!(e5fs.read_file("/file", AddressSize::MAX).unwrap(), b"contents");
+    e5fs.create_file("/other").unwrap();
+
+    let report = fsck::check(&mut e5fs, true).unwrap();
+    assert_eq!(report.exit_code(), fsck::FSCK_CORRECTED);
+    assert!(fsck::check(&mut e5fs, false).unwrap().is_clean());
+    e5fs.unmount().unwrap();
+
+    // Primary one is back
+    let superblock = E5FSFilesystem::read_superblock_from(&mut MemoryStorage::new(buffer.clone())).unwrap();
+    assert_eq!(superblock.uuid, uuid);
+    let e5fs = E5FSFilesystem::from_storage(Box::new(MemoryStorage::new(buffer))).unwrap();
+    assert_eq!(e5fs.superblock_backup, None);
+  }
+
+  #[test]
+  fn label_is_set_and_checked() {
+    let (mut e5fs, buffer) = memory_e5fs(E5FS_SIZE);
+    e5fs.set_label("home").unwrap();
+    assert!(matches!(e5fs.set_label("longer-than-16-bytes"), Err(Errno::EINVAL(_))));
+    assert!(matches!(e5fs.set_label("a/b"), Err(Errno::EINVAL(_))));
+    e5fs.unmount().unwrap();
+
+    let identity = E5FSFilesystem::probe(&mut MemoryStorage::new(buffer.clone())).unwrap();
+    assert_eq!(identity.label, "home");
+    assert_eq!(identity, E5FSFilesystem::from_storage(Box::new(MemoryStorage::new(buffer))).unwrap().identity());
+  }
+
+  #[test]
+  fn encrypted_filesystem_needs_passphrase() {
+    let buffer = Arc::new(RwLock::new(vec![0u8; 1024 * 1024]));
+    let mut e5fs = E5FSFilesystem::mkfs_storage_with_passphrase(
+      Box::new(MemoryStorage::new(buffer.clone())), 0.05, 4096, Some("hunter2"),
+    ).unwrap();
+    e5fs.create_file("/secret").unwrap();
+    e5fs.write_file("/secret", b"plaintext").unwrap();
+    e5fs.unmount().unwrap();
+    drop(e5fs);
+
+    assert!(E5FSFilesystem::is_encrypted(&mut MemoryStorage::new(buffer.clone())));
+    assert!(!buffer.read().unwrap().windows(9).any(|window| window == b"plaintext"));
+    assert!(!buffer.read().unwrap().windows(6).any(|window| window == b"secret"));
+    assert!(matches!(E5FSFilesystem::from_storage(Box::new(MemoryStorage::new(buffer.clone()))), Err(Errno::EACCES(_))));
+    assert!(matches!(
+      E5FSFilesystem::from_storage_with_passphrase(Box::new(MemoryStorage::new(buffer.clone())), Some("hunter3")),
+      Err(Errno::EACCES(_)),
+    ));
+
+    let mut e5fs = E5FSFilesystem::from_storage_with_passphrase(Box::new(MemoryStorage::new(buffer)), Some("hunter2")).unwrap();
+    assert_eq!(e5fs.read_file("/secret", 0).unwrap(), b"plaintext");
+    assert!(fsck::check(&mut e5fs, false).unwrap().is_clean());
+  }
+
+  #[test]
+  fn snapshot_keeps_files_as_they_were() {
+    let (mut e5fs, _) = memory_e5fs(E5FS_SIZE);
+    e5fs.create_dir("/etc").unwrap();
+    e5fs.create_file("/etc/motd").unwrap();
+    e5fs.write_file("/etc/motd", b"before").unwrap();
+
+    e5fs.create_snapshot("s1").unwrap();
+    assert_eq!(e5fs.list_snapshots().unwrap(), vec!["s1"]);
+    let inode_number = e5fs.lookup_path("/etc/motd").unwrap().number;
+    let block_number = e5fs.read_inode(inode_number).unwrap().direct_block_numbers[0];
+    assert_eq!(e5fs.lookup_path("/.snapshots/s1/etc/motd").unwrap().number, inode_number);
+    assert_eq!(e5fs.stat("/etc/motd").unwrap().links_count, 1);
+    assert_eq!(e5fs.block_references(block_number).unwrap(), 1);
+    assert!(matches!(e5fs.create_snapshot("s1"), Err(Errno::EEXIST(_))));
+    assert!(matches!(e5fs.write_file("/.snapshots/s1/etc/motd", b"after"), Err(Errno::EROFS(_))));
+
+    e5fs.write_file("/etc/motd", b"after").unwrap();
+    e5fs.create_file("/new").unwrap();
+    assert_eq!(e5fs.read_file("/.snapshots/s1/etc/motd", 0).unwrap(), b"before");
+    assert_ne!(e5fs.lookup_path("/etc/motd").unwrap().number, inode_number);
+    assert_eq!(e5fs.block_references(block_number).unwrap(), 1);
+    assert!(fsck::check(&mut e5fs, false).unwrap().is_clean());
+
+    e5fs.rollback_snapshot("s1").unwrap();
+    assert_eq!(e5fs.read_file("/etc/motd", 0).unwrap(), b"before");
+    assert!(matches!(e5fs.read_file("/new", 0), Err(Errno::ENOENT(_))));
+    assert!(fsck::check(&mut e5fs, false).unwrap().is_clean());
+
+    e5fs.delete_snapshot("s1").unwrap();
+    assert!(e5fs.list_snapshots().unwrap().is_empty());
+    assert_eq!(e5fs.read_file("/etc/motd", 0).unwrap(), b"before");
+    assert!(fsck::check(&mut e5fs, false).unwrap().is_clean());
+  }
+
+  #[test]
+  fn snapshot_shares_inodes_until_they_change() {
+    let (mut e5fs, _) = memory_e5fs(E5FS_SIZE);
+    e5fs.enable_trash().unwrap();
+    e5fs.create_dir("/etc").unwrap();
+    for name in ["passwd", "group", "motd"] {
+      e5fs.create_file(&format!("/etc/{name}")).unwrap();
+      e5fs.write_file(&format!("/etc/{name}"), name.as_bytes()).unwrap();
+    }
+    e5fs.link("/etc/motd", "/motd").unwrap();
+    e5fs.create_file("/junk").unwrap();
+    e5fs.remove_file("/junk").unwrap();
+
+    // Only directories are copied, trash is left out
+    let free_inodes_count = e5fs.superblock.free_inodes_count;
+    e5fs.create_snapshot("s1").unwrap();
+    assert_eq!(free_inodes_count - e5fs.superblock.free_inodes_count, 3);
+    assert!(matches!(e5fs.lookup_path("/.snapshots/s1/.trash"), Err(Errno::ENOENT(_))));
+    assert_eq!(e5fs.stat("/motd").unwrap().links_count, 2);
+
+    // Hard links go to the copy together
+    let motd_number = e5fs.lookup_path("/motd").unwrap().number;
+    e5fs.write_file("/motd", b"changed").unwrap();
+    let copy_number = e5fs.lookup_path("/motd").unwrap().number;
+    assert_ne!(copy_number, motd_number);
+    assert_eq!(e5fs.lookup_path("/etc/motd").unwrap().number, copy_number);
+    assert_eq!(e5fs.read_file("/.snapshots/s1/etc/motd", 0).unwrap(), b"motd");
+    assert_eq!(e5fs.read_file("/.snapshots/s1/motd", 0).unwrap(), b"motd");
+    assert_eq!(e5fs.stat("/.snapshots/s1/motd").unwrap().links_count, 2);
+
+    // Attributes change only on the copy, trash gets one too
+    let passwd_number = e5fs.lookup_path("/etc/passwd").unwrap().number;
+    let mode = e5fs.stat("/etc/passwd").unwrap().mode;
+    e5fs.change_mode("/etc/passwd", mode.with_others(0)).unwrap();
+    assert_eq!(e5fs.stat("/.snapshots/s1/etc/passwd").unwrap().mode, mode);
+    e5fs.remove_file("/etc/group").unwrap();
+    assert_eq!(e5fs.list_trash().unwrap().len(), 2);
+    assert_eq!(e5fs.read_file("/.snapshots/s1/etc/group", 0).unwrap(), b"group");
+    assert!(fsck::check(&mut e5fs, false).unwrap().is_clean());
+
+    // Rollback keeps trash, snapshot is the last one to have them
+    e5fs.rollback_snapshot("s1").unwrap();
+    assert_eq!(e5fs.lookup_path("/etc/passwd").unwrap().number, passwd_number);
+    assert_eq!(e5fs.list_trash().unwrap().len(), 2);
+    assert!(fsck::check(&mut e5fs, false).unwrap().is_clean());
+    e5fs.delete_snapshot("s1").unwrap();
+    assert_eq!(e5fs.read_inode(passwd_number).unwrap().snapshot_links, 0);
+    assert_eq!(e5fs.read_file("/etc/group", 0).unwrap(), b"group");
+    assert!(fsck::check(&mut e5fs, false).unwrap().is_clean());
+  }
+
+  #[test]
+  fn xattrs_live_in_spill_block() {
+    let (mut e5fs, _) = memory_e5fs(E5FS_SIZE);
+    e5fs.create_file("/motd").unwrap();
+    assert!(e5fs.list_xattr("/motd").unwrap().is_empty());
+    assert!(matches!(e5fs.get_xattr("/motd", "user.comment"), Err(Errno::ENODATA(_))));
+
+    e5fs.set_xattr("/motd", "user.comment", Some(b"hello")).unwrap();
+    e5fs.set_xattr("/motd", "system.posix_acl_access", Some(&[1, 2, 3])).unwrap();
+    assert_eq!(e5fs.get_xattr("/motd", "user.comment").unwrap(), b"hello");
+    assert_eq!(e5fs.list_xattr("/motd").unwrap(), vec!["system.posix_acl_access", "user.comment"]);
+    assert!(matches!(e5fs.set_xattr("/motd", "user.big", Some(&[0; 8192])), Err(Errno::ENOSPC(_))));
+
+    // Snapshot shares the inode, and the block with copy of the
+    // inode, until one of them changes it
+    let inode_number = e5fs.lookup_path("/motd").unwrap().number;
+    let block_number = e5fs.read_inode(inode_number).unwrap().xattr_block_number;
+    e5fs.create_snapshot("s1").unwrap();
+    assert_eq!(e5fs.block_references(block_number).unwrap(), 1);
+    e5fs.set_xattr("/motd", "user.comment", None).unwrap();
+    assert_eq!(e5fs.lookup_path("/.snapshots/s1/motd").unwrap().number, inode_number);
+    assert_eq!(e5fs.get_xattr("/.snapshots/s1/motd", "user.comment").unwrap(), b"hello");
+    assert_eq!(e5fs.list_xattr("/motd").unwrap(), vec!["system.posix_acl_access"]);
+    assert!(fsck::check(&mut e5fs, false).unwrap().is_clean());
+
+    e5fs.delete_snapshot("s1").unwrap();
+    e5fs.set_xattr("/motd", "system.posix_acl_access", None).unwrap();
+    let inode_number = e5fs.lookup_path("/motd").unwrap().number;
+    assert_eq!(e5fs.read_inode(inode_number).unwrap().xattr_block_number, NO_ADDRESS);
+    assert!(fsck::check(&mut e5fs, false).unwrap().is_clean());
+  }
+
+  #[test]
+  fn statfs_follows_claims_and_releases() {
+    let (mut e5fs, buffer) = memory_e5fs(E5FS_SIZE);
+    let counted = |e5fs: &mut E5FSFilesystem| {
+      let stat = e5fs.statfs("/").unwrap();
+      e5fs.count_free().unwrap();
+      assert_eq!(e5fs.statfs("/").unwrap(), stat);
+      stat
+    };
+
+    let empty = counted(&mut e5fs);
+    assert_eq!(empty.inodes_count - empty.free_inodes_count, 1);
+    assert_eq!(empty.blocks_count, e5fs.fs_info.first_fbl_block_number);
+
+    e5fs.create_dir("/etc").unwrap();
+    e5fs.create_file("/etc/motd").unwrap();
+    e5fs.write_file("/etc/motd", &[1; 8192]).unwrap();
+    e5fs.set_xattr("/etc/motd", "user.comment", Some(b"hi")).unwrap();
+    let full = counted(&mut e5fs);
+    assert_eq!(full.free_inodes_count, empty.free_inodes_count - 2);
+    assert_eq!(full.free_blocks_count, empty.free_blocks_count - 4);
+
+    e5fs.create_snapshot("s1").unwrap();
+    e5fs.write_file("/etc/motd", &[2; 4096]).unwrap();
+    counted(&mut e5fs);
+    e5fs.delete_snapshot("s1").unwrap();
+    e5fs.remove_file("/etc/motd").unwrap();
+    let emptied = counted(&mut e5fs);
+    // Directory of snapshots stays
+    assert_eq!(emptied.free_inodes_count, full.free_inodes_count);
+    assert_eq!(emptied.free_blocks_count, full.free_blocks_count + 3 - 1);
+
+    drop(e5fs);
+    let mut e5fs = E5FSFilesystem::from_storage(Box::new(MemoryStorage::new(buffer))).unwrap();
+    assert_eq!(e5fs.statfs("/").unwrap(), emptied);
+  }
+
+  #[test]
+  fn reserved_blocks_are_left_for_root() {
+    let (mut e5fs, buffer) = memory_e5fs(E5FS_SIZE);
+    assert!(matches!(e5fs.set_reserved_blocks_percentage(60.0), Err(Errno::EINVAL(_))));
+    e5fs.set_reserved_blocks_percentage(20.0).unwrap();
+    let reserved_blocks_count = e5fs.statfs("/").unwrap().reserved_blocks_count;
+    assert_eq!(reserved_blocks_count, e5fs.fs_info.first_fbl_block_number / 5);
+
+    e5fs.set_current_user(1000, 1000);
+    e5fs.create_file("/user").unwrap();
+    let full = (1..).find(|&size| e5fs.write_file("/user", &vec![1; size * 4096]).is_err()).unwrap();
+    assert!(matches!(e5fs.write_file("/user", &vec![1; full * 4096]), Err(Errno::ENOSPC(_))));
+    assert_eq!(e5fs.statfs("/").unwrap().free_blocks_count, reserved_blocks_count);
+
+    e5fs.set_current_user(ROOT_UID, ROOT_GID);
+    e5fs.create_file("/root").unwrap();
+    e5fs.write_file("/root", &[1; 4096]).unwrap();
+
+    drop(e5fs);
+    let mut e5fs = E5FSFilesystem::from_storage(Box::new(MemoryStorage::new(buffer))).unwrap();
+    assert_eq!(e5fs.statfs("/").unwrap().reserved_blocks_count, reserved_blocks_count);
+  }
+
+  #[test]
+  fn free_counts_are_written_with_transactions() {
+    let (mut e5fs, buffer) = memory_e5fs(E5FS_SIZE);
+    let on_disk = |e5fs: &mut E5FSFilesystem| {
+      e5fs.sync().unwrap();
+      let superblock = E5FSFilesystem::read_superblock_from(&mut MemoryStorage::new(buffer.clone())).unwrap();
+      (superblock.free_inodes_count, superblock.free_blocks_count)
+    };
+
+    e5fs.create_dir("/etc").unwrap();
+    e5fs.create_file("/etc/motd").unwrap();
+    e5fs.write_file("/etc/motd", &[1; 8192]).unwrap();
+    assert_eq!(on_disk(&mut e5fs), e5fs.counted_free().unwrap());
+
+    e5fs.truncate("/etc/motd", 0).unwrap();
+    e5fs.remove_file("/etc/motd").unwrap();
+    assert_eq!(on_disk(&mut e5fs), e5fs.counted_free().unwrap());
+  }
+
+  #[test]
+  fn free_counts_are_counted_only_on_dirty_mount() {
+    let (mut e5fs, buffer) = memory_e5fs(E5FS_SIZE);
+    let free_inodes_count = e5fs.statfs("/").unwrap().free_inodes_count;
+    e5fs.superblock.free_inodes_count = 7;
+    e5fs.unmount().unwrap();
+
+    // Clean one is trusted
+    let mut e5fs = E5FSFilesystem::from_storage(Box::new(MemoryStorage::new(buffer.clone()))).unwrap();
+    assert_eq!(e5fs.statfs("/").unwrap().free_inodes_count, 7);
+    drop(e5fs);
+
+    let mut e5fs = E5FSFilesystem::from_storage(Box::new(MemoryStorage::new(buffer))).unwrap();
+    assert_eq!(e5fs.statfs("/").unwrap().free_inodes_count, free_inodes_count);
+  }
+
+  #[test]
+  fn large_directory_goes_past_direct_blocks() {
+    let mut e5fs = E5FSFilesystem::mkfs_storage(
+      Box::new(MemoryStorage::new(Arc::new(RwLock::new(vec![0u8; 2 * 1024 * 1024])))), 0.05, 512,
+    ).unwrap();
+    e5fs.create_dir("/big").unwrap();
+    let file_number = e5fs.create_file("/file").unwrap().number;
+    for index in 0..2000 {
+      e5fs.link("/file", &format!("/big/{index}")).unwrap();
+    }
+
+    let big_number = e5fs.lookup_path("/big").unwrap().number;
+    let big = e5fs.read_inode(big_number).unwrap();
+    assert!(big.file_size > 12 * 512);
+    assert_ne!(big.indirect_block_numbers[0], NO_ADDRESS);
+    assert_eq!(e5fs.lookup_path("/big/1234").unwrap().number, file_number);
+    assert_eq!(e5fs.read_dir("/big").unwrap().entries.len(), 2002);
+    assert!(fsck::check(&mut e5fs, false).unwrap().is_clean());
+
+    // Listed a part at a time, every entry comes once
+    let mut names = Vec::new();
+    loop {
+      let entries = e5fs.readdir("/big", names.len() as AddressSize, 300).unwrap();
+      if entries.is_empty() {
+        break;
+      }
+      assert!(entries.len() <= 300);
+      names.extend(entries.into_iter().map(|entry| entry.name));
+    }
+    names.sort();
+    assert_eq!(names, e5fs.read_dir("/big").unwrap().entries.into_keys().collect::<Vec<_>>());
+    assert!(matches!(e5fs.readdir("/file", 0, 1), Err(Errno::ENOTDIR(_))));
+
+    for index in 0..2000 {
+      e5fs.remove_file(&format!("/big/{index}")).unwrap();
+    }
+    assert_eq!(e5fs.read_dir("/big").unwrap().entries.len(), 2);
+    assert_eq!(e5fs.read_inode(file_number).unwrap().links_count, 1);
+    assert!(fsck::check(&mut e5fs, false).unwrap().is_clean());
+  }
+
+  #[test]
+  fn unhashed_directory_gets_hashed_when_written() {
+    let (mut e5fs, _) = memory_e5fs(E5FS_SIZE);
+    let etc = e5fs.create_dir("/etc").unwrap().number;
+    let motd = e5fs.create_file("/etc/motd").unwrap().number;
+
+    // The way directories were written before they were hashed
+    let mut dir = e5fs.read_as_dir_i(etc).unwrap();
+    e5fs.write_data_i(hashdir::entries_to_bytes(dir.entries.values()), etc, false).unwrap();
+    assert!(e5fs.buckets_count_i(etc).unwrap().is_none());
+    assert_eq!(e5fs.lookup_path("/etc/motd").unwrap().number, motd);
+
+    e5fs.create_file("/etc/passwd").unwrap();
+    assert_eq!(e5fs.buckets_count_i(etc).unwrap(), Some(1));
+    dir.insert(e5fs.lookup_path("/etc/passwd").unwrap().number, "passwd").unwrap();
+    assert_eq!(e5fs.read_as_dir_i(etc).unwrap(), dir);
+    assert!(fsck::check(&mut e5fs, false).unwrap().is_clean());
+  }
+
+  #[test]
+  fn mkfs_on_region_stays_in_bounds() {
+    let tempfile = mktemp().to_owned();
+    mkenxvd("1M".to_owned(), tempfile.clone());
+
+    let region = FileRegion::open_region(tempfile.as_str(), 512, Some(512 * 1024)).unwrap();
+    let mut e5fs = E5FSFilesystem::mkfs_storage(Box::new(region), 0.05, 4096).unwrap();
+    e5fs.create_file("/test").unwrap();
+    drop(e5fs);
+
+    let bytes = std::fs::read(tempfile.as_str()).unwrap();
+    assert!(bytes[..512].iter().all(|&byte| byte == 0));
+    assert!(bytes[512 + 512 * 1024..].iter().all(|&byte| byte == 0));
+
+    let region = FileRegion::open_region(tempfile.as_str(), 512, Some(512 * 1024)).unwrap();
+    let mut e5fs = E5FSFilesystem::from_storage(Box::new(region)).unwrap();
+    assert!(e5fs.lookup_path("/test").is_ok());
+  }
+
+  #[test]
+  fn probe_works() {
+    let tempfile = mktemp().to_owned();
+    mkenxvd("1M".to_owned(), tempfile.clone());
+
+    let mut storage = FileRegion::open(tempfile.as_str()).unwrap();
+    assert_eq!(E5FSFilesystem::probe(&mut storage), None);
+
+    let e5fs = E5FSFilesystem::mkfs(tempfile.as_str(), 0.05, 4096).unwrap();
+    let uuid = Uuid::from_bytes(e5fs.superblock.uuid).to_string();
+    drop(e5fs);
+
+    let mut storage = FileRegion::open(tempfile.as_str()).unwrap();
+    assert_eq!(
+      E5FSFilesystem::probe(&mut storage),
+      Some(FilesystemIdentity { uuid, label: String::new() }),
+    );
+  }
+
+  #[test]
+  fn mkfs_on_memory_works() {
+    let (mut e5fs, buffer) = memory_e5fs(E5FS_SIZE);
+    e5fs.create_file("/test").unwrap();
+    e5fs.write_file("/test", b"hello").unwrap();
+    drop(e5fs);
+
+    let mut e5fs = E5FSFilesystem::from_storage(Box::new(MemoryStorage::new(buffer))).unwrap();
+    assert_eq!(e5fs.read_file("/test", 5).unwrap(), b"hello");
+  }
+
+  #[test]
+  fn invalid_names_are_not_written() {
+    let (mut e5fs, _) = memory_e5fs(E5FS_SIZE);
+    let root_number = e5fs.fs_info.root_inode_number;
+    let file_number = e5fs.create_file("/file").unwrap().number;
+
+    for name in ["", "a/b", "a\0b"] {
+      assert!(matches!(e5fs.insert_dir_entry_i(root_number, file_number, name), Err(Errno::EINVAL(_))));
+    }
+    let long_name = "x".repeat(NAME_MAX + 1);
+    assert!(matches!(e5fs.insert_dir_entry_i(root_number, file_number, &long_name), Err(Errno::ENAMETOOLONG(_))));
+    assert!(matches!(e5fs.create_file(&format!("/{long_name}")), Err(Errno::ENAMETOOLONG(_))));
+    e5fs.create_file(&format!("/{}", "x".repeat(NAME_MAX))).unwrap();
+    assert_eq!(e5fs.read_dir("/").unwrap().entries.len(), 4);
+  }
+
+  #[test]
+  fn truncated_device_gives_eio() {
+    let (e5fs, buffer) = memory_e5fs(E5FS_SIZE);
+    let last_block_number = e5fs.fs_info.first_fbl_block_number - 1;
+    drop(e5fs);
+
+    let e5fs = E5FSFilesystem::from_storage(Box::new(MemoryStorage::new(buffer.clone()))).unwrap();
+    buffer.write().unwrap().truncate(512 * 1024);
+    assert!(matches!(e5fs.read_block(last_block_number), Err(Errno::EIO(_))));
+    drop(e5fs);
+
+    // Mount sees it is smaller than filesystem before reading anything
+    let result = E5FSFilesystem::from_storage(Box::new(MemoryStorage::new(buffer)));
+    assert!(matches!(result, Err(Errno::EILSEQ(_))));
+  }
+
+  #[test]
+  fn mount_is_dirty_until_unmount() {
+    let buffer = Arc::new(RwLock::new(vec![0u8; 1024 * 1024]));
+    let mount = || E5FSFilesystem::from_storage(Box::new(MemoryStorage::new(buffer.clone()))).unwrap();
+    let state = || E5FSFilesystem::read_superblock_from(&mut MemoryStorage::new(buffer.clone())).unwrap().state;
+
+    drop(E5FSFilesystem::mkfs_storage(Box::new(MemoryStorage::new(buffer.clone())), 0.05, 4096).unwrap());
+    assert_eq!(state(), STATE_CLEAN);
+
+    let mut e5fs = mount();
+    assert!(!e5fs.needs_check);
+    assert_eq!(state(), STATE_DIRTY);
+    e5fs.unmount().unwrap();
+    assert_eq!(state(), STATE_CLEAN);
+
+    // Read-only mount writes no state, nor anything else
+    let before = buffer.read().unwrap().clone();
+    let mut e5fs = E5FSFilesystem::from_storage_read_only(Box::new(MemoryStorage::new(buffer.clone())), None).unwrap();
+    assert_eq!(state(), STATE_CLEAN);
+    e5fs.unmount().unwrap();
+    assert!(*buffer.read().unwrap() == before);
+
+    // Machine went down without unmounting
+    drop(mount());
+    assert!(mount().needs_check);
+  }
+
+  #[test]
+  fn unfinished_operation_is_replayed_on_mount() {
+    use crate::eunix::journal::{JOURNAL_MAGIC, JOURNAL_COMMITTED};
+
+    let buffer = Arc::new(RwLock::new(vec![0u8; 1024 * 1024]));
+    drop(E5FSFilesystem::mkfs_storage(Box::new(MemoryStorage::new(buffer.clone())), 0.05, 4096).unwrap());
+
+    // Host went down after journal of label change was committed,
+    // before the label was written to superblock
+    let label_address = Superblock::size() as u64 - 4 - LABEL_MAX_LEN as u64;
+    let journal = JOURNAL_MAGIC.into_iter()
+      .chain(JOURNAL_COMMITTED.to_le_bytes())
+      .chain(1u32.to_le_bytes())
+      .chain(label_address.to_le_bytes())
+      .chain(7u32.to_le_bytes())
+      .chain(*b"crashed")
+      .collect::<Vec<u8>>();
+    buffer.write().unwrap()[Superblock::size() as usize..][..journal.len()].copy_from_slice(&journal);
+
+    let e5fs = E5FSFilesystem::from_storage(Box::new(MemoryStorage::new(buffer.clone()))).unwrap();
+    assert_eq!(e5fs.superblock.label(), "crashed");
+    assert_eq!(E5FSFilesystem::read_superblock_from(&mut MemoryStorage::new(buffer)).unwrap().label(), "crashed");
+  }
+
+  proptest::proptest! {
+    #[test]
+    fn damaged_superblock_fails_mount_without_panic(
+      damage in proptest::collection::vec((0..Superblock::size() as usize, proptest::num::u8::ANY), 1..8),
+    ) {
+      let buffer = Arc::new(RwLock::new(vec![0u8; 128 * 1024]));
+      drop(E5FSFilesystem::mkfs_storage(Box::new(MemoryStorage::new(buffer.clone())), 0.05, 4096).unwrap());
+      for (index, byte) in damage {
+        buffer.write().unwrap()[index] = byte;
+      }
+
+      if let Ok(mut e5fs) = E5FSFilesystem::from_storage(Box::new(MemoryStorage::new(buffer))) {
+        let _ = e5fs.read_dir("/");
+      }
+    }
+
+    #[test]
+    fn damaged_directory_fails_parse_without_panic(
+      entries_count in 0..8u32,
+      entries in proptest::collection::vec((proptest::num::u32::ANY, proptest::num::u16::ANY, "\\PC{0,8}"), 0..8),
+      cut in proptest::num::usize::ANY,
+    ) {
+      let storage = MemoryStorage::new(Arc::new(RwLock::new(vec![0u8; 128 * 1024])));
+      let fs_info = E5FSFilesystemBuilder::with_storage(Box::new(storage), 0.05, 4096).unwrap();
+      let mut data = entries_count.to_le_bytes().to_vec();
+      for (inode_number, rec_len, name) in entries {
+        data.extend(inode_number.to_le_bytes());
+        data.extend(rec_len.to_le_bytes());
+        data.push(name.len() as u8);
+        data.extend(name.as_bytes());
+      }
+      // Directory may end anywhere, even in the middle of entry
+      data.truncate(cut % (data.len() + 1));
+
+      let _ = E5FSFilesystem::parse_directory(&fs_info, data);
+    }
+  }
 }
 
 // vim:ts=2 sw=2
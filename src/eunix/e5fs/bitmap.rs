@@ -0,0 +1,151 @@
+use std::mem::size_of;
+
+use crate::eunix::fs::AddressSize;
+
+const WORD_BITS: AddressSize = u64::BITS;
+
+/// Free data blocks of e5fs, one bit each, set for a free one. It is
+/// read from `fbl` on mount and follows every write of it, so a free
+/// block is found without reading `fbl`. Since `BLOCK_MAP_FORMAT_VERSION`
+/// `fbl` starts with it, as `to_bytes` has it
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BlockBitmap {
+  words: Vec<u64>,
+  blocks_count: AddressSize,
+  /// No word before this one has a free block
+  hint: usize,
+}
+
+impl BlockBitmap {
+  /// Bitmap of `blocks_count` blocks, none of them free
+  pub fn new(blocks_count: AddressSize) -> Self {
+    Self {
+      words: vec![0; blocks_count.div_ceil(WORD_BITS) as usize],
+      blocks_count,
+      hint: 0,
+    }
+  }
+
+  /// Bitmap of blocks that `references` files use each: block that
+  /// no file uses is free, one that makes no sense is not
+  pub fn from_references(references: impl IntoIterator<Item = Option<AddressSize>>) -> Self {
+    let references = references.into_iter().collect::<Vec<_>>();
+    let mut bitmap = Self::new(references.len() as AddressSize);
+    for (block_number, references) in (0..).zip(references) {
+      bitmap.set_free(block_number, references == Some(0));
+    }
+    bitmap.hint = 0;
+
+    bitmap
+  }
+
+  /// Bitmap of `blocks_count` blocks that `bytes` of `to_bytes` have,
+  /// blocks past them are used
+  pub fn from_bytes(bytes: &[u8], blocks_count: AddressSize) -> Self {
+    let mut bitmap = Self::new(blocks_count);
+    for (word, chunk) in bitmap.words.iter_mut().zip(bytes.chunks(size_of::<u64>())) {
+      let mut word_bytes = [0u8; size_of::<u64>()];
+      word_bytes[..chunk.len()].copy_from_slice(chunk);
+      *word = u64::from_le_bytes(word_bytes);
+    }
+    // Bits past the last block are not blocks
+    if blocks_count % WORD_BITS != 0 {
+      if let Some(last) = bitmap.words.last_mut() {
+        *last &= (1 << (blocks_count % WORD_BITS)) - 1;
+      }
+    }
+
+    bitmap
+  }
+
+  /// Returns: bit of block `N` as bit `N % 8` of byte `N / 8`
+  pub fn to_bytes(&self) -> Vec<u8> {
+    let mut bytes = self.words
+      .iter()
+      .flat_map(|word| word.to_le_bytes())
+      .collect::<Vec<u8>>();
+    bytes.truncate(self.blocks_count.div_ceil(8) as usize);
+
+    bytes
+  }
+
+  pub fn is_free(&self, block_number: AddressSize) -> bool {
+    block_number < self.blocks_count
+      && self.words[(block_number / WORD_BITS) as usize] & (1 << (block_number % WORD_BITS)) != 0
+  }
+
+  /// Mark block free or used, blocks past the end are ignored
+  pub fn set_free(&mut self, block_number: AddressSize, free: bool) {
+    if block_number >= self.blocks_count {
+      return;
+    }
+
+    let index = (block_number / WORD_BITS) as usize;
+    let bit = 1 << (block_number % WORD_BITS);
+    match free {
+      true => {
+        self.words[index] |= bit;
+        self.hint = self.hint.min(index);
+      },
+      false => self.words[index] &= !bit,
+    }
+  }
+
+  pub fn free_count(&self) -> AddressSize {
+    self.words.iter().map(|word| word.count_ones()).sum()
+  }
+
+  /// Returns: the lowest free block, `None` if there are none.
+  /// Words before it are not looked at again until a block
+  /// in them is released
+  pub fn first_free(&mut self) -> Option<AddressSize> {
+    while let Some(&word) = self.words.get(self.hint) {
+      if word != 0 {
+        return Some(self.hint as AddressSize * WORD_BITS + word.trailing_zeros());
+      }
+      self.hint += 1;
+    }
+
+    None
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn first_free_follows_claims_and_releases() {
+    // Blocks 0, 1 and 129 are used
+    let references = (0..130).map(|block_number| match block_number {
+      0 | 1 | 129 => Some(1),
+      _ => Some(0),
+    });
+    let mut bitmap = BlockBitmap::from_references(references);
+    assert_eq!(bitmap.free_count(), 127);
+
+    assert_eq!(bitmap.first_free(), Some(2));
+    for block_number in 2..129 {
+      bitmap.set_free(block_number, false);
+    }
+    assert_eq!(bitmap.first_free(), None);
+    assert_eq!(bitmap.free_count(), 0);
+
+    bitmap.set_free(70, true);
+    bitmap.set_free(1, true);
+    assert_eq!(bitmap.first_free(), Some(1));
+    assert!(bitmap.is_free(70) && !bitmap.is_free(129) && !bitmap.is_free(130));
+    // Past the end
+    bitmap.set_free(200, true);
+    assert_eq!(bitmap.free_count(), 2);
+
+    let bytes = bitmap.to_bytes();
+    assert_eq!(bytes.len(), 17);
+    assert_eq!(bytes[0], 0b10);
+    assert_eq!(BlockBitmap::from_bytes(&bytes, 130).words, bitmap.words);
+    // Bits of blocks past the end are dropped
+    assert_eq!(BlockBitmap::from_bytes(&[0xff; 17], 130).free_count(), 130);
+  }
+}
+
+// vim:ts=2 sw=2
@@ -0,0 +1,170 @@
+use aes::Aes256;
+use aes::cipher::{BlockDecrypt, BlockEncrypt, KeyInit};
+use sha2::{Digest, Sha256};
+
+use crate::eunix::fs::AddressSize;
+use crate::eunix::rng::{HostRng, Rng};
+
+/// First bytes of the block that holds `CryptHeader`
+pub const CRYPT_MAGIC: [u8; 8] = *b"e5fscryp";
+/// Rounds of SHA-256 that passphrase goes through to become a key
+pub const KEY_DERIVATION_ROUNDS: u32 = 10_000;
+/// Block of encrypted filesystem that holds `CryptHeader`, claimed
+/// by mkfs before any file. It is the first block of data, so that
+/// it can be found without knowing anything but the layout
+pub const CRYPT_HEADER_BLOCK_NUMBER: AddressSize = 0;
+
+const KEY_CHECK_CONTEXT: &[u8] = b"e5fs key check";
+const DATA_KEY_CONTEXT: &[u8] = b"e5fs data key";
+const TWEAK_KEY_CONTEXT: &[u8] = b"e5fs tweak key";
+/// Size of AES block, which XTS encrypts data in
+const AES_BLOCK_SIZE: usize = 16;
+
+pub type Key = [u8; 32];
+
+/// What is needed to tell whether passphrase is right, kept
+/// unencrypted in `CRYPT_HEADER_BLOCK_NUMBER`:
+/// magic, rounds (u32), salt and key check
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct CryptHeader {
+  pub rounds: u32,
+  pub salt: [u8; 16],
+  /// Digest of key, not the key itself
+  pub key_check: [u8; 32],
+}
+
+impl CryptHeader {
+  pub const SIZE: usize = CRYPT_MAGIC.len() + 4 + 16 + 32;
+
+  /// Header with new salt for `passphrase`.
+  /// Returns: it and the key
+  pub fn new(passphrase: &str) -> (Self, Key) {
+    let mut salt = [0u8; 16];
+    HostRng.fill(&mut salt);
+    let key = derive_key(passphrase, &salt, KEY_DERIVATION_ROUNDS);
+
+    (Self { rounds: KEY_DERIVATION_ROUNDS, salt, key_check: key_check(&key) }, key)
+  }
+
+  /// Key for `passphrase`, if it is the right one
+  pub fn unlock(&self, passphrase: &str) -> Option<Key> {
+    let key = derive_key(passphrase, &self.salt, self.rounds);
+
+    (key_check(&key) == self.key_check).then_some(key)
+  }
+
+  pub fn to_bytes(&self) -> Vec<u8> {
+    let mut bytes = Vec::with_capacity(Self::SIZE);
+    bytes.extend(CRYPT_MAGIC);
+    bytes.extend(self.rounds.to_le_bytes());
+    bytes.extend(self.salt);
+    bytes.extend(self.key_check);
+
+    bytes
+  }
+
+  /// Returns: `None` if `bytes` don't start with a header
+  pub fn parse(bytes: &[u8]) -> Option<Self> {
+    if bytes.len() < Self::SIZE || bytes[..CRYPT_MAGIC.len()] != CRYPT_MAGIC {
+      return None;
+    }
+    let bytes = &bytes[CRYPT_MAGIC.len()..];
+
+    Some(Self {
+      rounds: u32::from_le_bytes(bytes[..4].try_into().unwrap()),
+      salt: bytes[4..20].try_into().unwrap(),
+      key_check: bytes[20..52].try_into().unwrap(),
+    })
+  }
+}
+
+fn derive_key(passphrase: &str, salt: &[u8], rounds: u32) -> Key {
+  let mut key: Key = Sha256::new().chain_update(salt).chain_update(passphrase).finalize().into();
+  for _ in 0..rounds {
+    key = Sha256::new().chain_update(key).chain_update(salt).chain_update(passphrase).finalize().into();
+  }
+
+  key
+}
+
+fn key_check(key: &Key) -> [u8; 32] {
+  Sha256::new().chain_update(KEY_CHECK_CONTEXT).chain_update(key).finalize().into()
+}
+
+/// Encrypt `data` of block `block_number` in place with AES-256-XTS,
+/// block number being the tweak. Keys for it are derived from `key`
+pub fn encrypt_block(key: &Key, block_number: AddressSize, data: &mut [u8]) {
+  xts(&subkey(key, DATA_KEY_CONTEXT), &subkey(key, TWEAK_KEY_CONTEXT), block_number as u128, data, true);
+}
+
+/// Undo `encrypt_block`
+pub fn decrypt_block(key: &Key, block_number: AddressSize, data: &mut [u8]) {
+  xts(&subkey(key, DATA_KEY_CONTEXT), &subkey(key, TWEAK_KEY_CONTEXT), block_number as u128, data, false);
+}
+
+fn subkey(key: &Key, context: &[u8]) -> Key {
+  Sha256::new().chain_update(context).chain_update(key).finalize().into()
+}
+
+/// XTS mode of IEEE 1619 over AES-256, for `data` of whole AES
+/// blocks: every one of them is XORed with tweak before and after
+/// going through AES, and tweak is multiplied by x in GF(2^128)
+/// for the next one. `unit` is the number of data unit
+fn xts(data_key: &Key, tweak_key: &Key, unit: u128, data: &mut [u8], encrypt: bool) {
+  assert_eq!(data.len() % AES_BLOCK_SIZE, 0, "crypt: data must be whole AES blocks");
+  let cipher = Aes256::new(data_key.into());
+
+  let mut tweak = unit.to_le_bytes();
+  Aes256::new(tweak_key.into()).encrypt_block((&mut tweak).into());
+  for chunk in data.chunks_exact_mut(AES_BLOCK_SIZE) {
+    let chunk: &mut [u8; AES_BLOCK_SIZE] = chunk.try_into().unwrap();
+    chunk.iter_mut().zip(tweak).for_each(|(byte, tweak_byte)| *byte ^= tweak_byte);
+    match encrypt {
+      true => cipher.encrypt_block(chunk.into()),
+      false => cipher.decrypt_block(chunk.into()),
+    }
+    chunk.iter_mut().zip(tweak).for_each(|(byte, tweak_byte)| *byte ^= tweak_byte);
+
+    // x^128 = x^7 + x^2 + x + 1
+    let value = u128::from_le_bytes(tweak);
+    tweak = ((value << 1) ^ ((value >> 127) * 0x87)).to_le_bytes();
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn xts_matches_ieee_1619() {
+    // Vector 10 of IEEE 1619-2007
+    let data_key = hex::decode("2718281828459045235360287471352662497757247093699959574966967627").unwrap();
+    let tweak_key = hex::decode("3141592653589793238462643383279502884197169399375105820974944592").unwrap();
+    let plaintext: Vec<u8> = (0..=255).chain(0..=255).collect();
+
+    let mut data = plaintext.clone();
+    xts(&data_key.clone().try_into().unwrap(), &tweak_key.clone().try_into().unwrap(), 0xff, &mut data, true);
+    assert_eq!(hex::encode(&data[..16]), "1c3b3a102f770386e4836c99e370cf9b");
+    assert_eq!(hex::encode(&data[data.len() - 16..]), "c4f36ffda9fcea70b9c6e693e148c151");
+
+    xts(&data_key.try_into().unwrap(), &tweak_key.try_into().unwrap(), 0xff, &mut data, false);
+    assert_eq!(data, plaintext);
+  }
+
+  #[test]
+  fn same_data_is_encrypted_differently_in_other_blocks() {
+    let (_, key) = CryptHeader::new("passphrase");
+    let mut first = vec![0u8; 512];
+    let mut second = first.clone();
+
+    encrypt_block(&key, 1, &mut first);
+    encrypt_block(&key, 2, &mut second);
+    assert_ne!(first, second);
+    assert_ne!(first, vec![0u8; 512]);
+
+    decrypt_block(&key, 1, &mut first);
+    assert_eq!(first, vec![0u8; 512]);
+  }
+}
+
+// vim:ts=2 sw=2
@@ -0,0 +1,139 @@
+use super::{E5FSFilesystem, Superblock};
+use crate::eunix::fs::{AddressSize, FileMode, NO_ADDRESS};
+use crate::eunix::kernel::Errno;
+
+/// What dumpe5fs prints about filesystem
+#[derive(Debug, Clone)]
+pub struct E5FSDump {
+  pub superblock: Superblock,
+  /// Bytes between superblock and inode table
+  pub journal_size: AddressSize,
+  pub encrypted: bool,
+  /// Addresses of backups of superblock that are in place
+  pub superblock_backups: Vec<AddressSize>,
+  pub used_inodes_count: AddressSize,
+  /// Blocks marked free in fbl
+  pub free_blocks_count: AddressSize,
+  /// Blocks that more than one file uses, see `snapshot`
+  pub shared_blocks_count: AddressSize,
+  /// Blocks that fbl makes no sense for
+  pub bad_blocks_count: AddressSize,
+  /// Blocks fbl itself takes
+  pub fbl_blocks_count: AddressSize,
+  /// Used inodes, with `inodes` only
+  pub inodes: Vec<InodeDump>,
+}
+
+/// Used inode and blocks it has
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct InodeDump {
+  pub number: AddressSize,
+  pub mode: FileMode,
+  pub links_count: AddressSize,
+  pub file_size: AddressSize,
+  /// Blocks of data, `NO_ADDRESS` for holes. Empty for devices
+  pub block_numbers: Vec<AddressSize>,
+  /// Block with numbers of blocks past direct ones
+  pub indirect_block_number: Option<AddressSize>,
+  /// Block with extended attributes
+  pub xattr_block_number: Option<AddressSize>,
+}
+
+/// Read superblock and count used inodes and blocks of e5fs, without
+/// checking anything like fsck does. With `inodes`, list used inodes
+/// and their blocks too
+///
+/// Errors:
+/// EIO -> device can't be read
+pub fn dump(e5fs: &E5FSFilesystem, inodes: bool) -> Result<E5FSDump, Errno> {
+  let first_fbl_block_number = e5fs.fs_info.first_fbl_block_number;
+
+  let mut dump = E5FSDump {
+    superblock: e5fs.superblock,
+    journal_size: e5fs.fs_info.journal_size,
+    encrypted: e5fs.key.is_some(),
+    superblock_backups: e5fs.superblock_backups()
+      .into_iter()
+      .filter(|(address, block_numbers)| e5fs.has_superblock_backup(*address, block_numbers.clone()))
+      .map(|(address, _)| address)
+      .collect(),
+    used_inodes_count: 0,
+    free_blocks_count: 0,
+    shared_blocks_count: 0,
+    bad_blocks_count: 0,
+    fbl_blocks_count: e5fs.fs_info.blocks_count - first_fbl_block_number,
+    inodes: Vec::new(),
+  };
+
+  for references in e5fs.read_references()? {
+    match references {
+      Some(0) => dump.free_blocks_count += 1,
+      Some(1) => (),
+      Some(_) => dump.shared_blocks_count += 1,
+      None => dump.bad_blocks_count += 1,
+    }
+  }
+
+  for inode_number in 0..e5fs.fs_info.inodes_count {
+    let inode = e5fs.read_inode(inode_number)?;
+    if super::fsck::is_free(&inode) {
+      continue;
+    }
+    dump.used_inodes_count += 1;
+
+    if inodes {
+      let (block_numbers, indirect_block_number) = match inode.is_device() {
+        true => (Vec::new(), NO_ADDRESS),
+        false => (
+          e5fs.block_numbers_i(&inode)?
+            .into_iter()
+            .take(inode.file_size.div_ceil(e5fs.fs_info.block_size) as usize)
+            .collect(),
+          inode.indirect_block_numbers[0],
+        ),
+      };
+      dump.inodes.push(InodeDump {
+        number: inode_number,
+        mode: inode.mode,
+        links_count: inode.links_count,
+        file_size: inode.file_size,
+        block_numbers,
+        indirect_block_number: (indirect_block_number != NO_ADDRESS).then_some(indirect_block_number),
+        xattr_block_number: (inode.xattr_block_number != NO_ADDRESS).then_some(inode.xattr_block_number),
+      });
+    }
+  }
+
+  Ok(dump)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::{memory_e5fs, E5FS_SIZE};
+  use crate::eunix::e5fs::fsck;
+  use crate::eunix::fs::Filesystem;
+
+  #[test]
+  fn dump_counts_what_fsck_does() {
+    let (mut e5fs, _) = memory_e5fs(E5FS_SIZE);
+    e5fs.create_dir("/etc").unwrap();
+    e5fs.create_file("/etc/motd").unwrap();
+    e5fs.write_at("/etc/motd", 8192, b"hello").unwrap();
+    e5fs.set_xattr("/etc/motd", "user.comment", Some(b"hi")).unwrap();
+    let motd_number = e5fs.lookup_path("/etc/motd").unwrap().number;
+
+    let dump = dump(&e5fs, true).unwrap();
+    let report = fsck::check(&mut e5fs, false).unwrap();
+    assert_eq!(dump.used_inodes_count, report.used_inodes_count);
+    assert_eq!(dump.superblock.blocks_count - dump.free_blocks_count, report.used_blocks_count);
+    assert_eq!(dump.inodes.len() as AddressSize, dump.used_inodes_count);
+
+    let motd = dump.inodes.iter().find(|inode| inode.number == motd_number).unwrap();
+    assert_eq!(motd.block_numbers.len(), 3);
+    assert_eq!(&motd.block_numbers[1..], [NO_ADDRESS, e5fs.read_inode(motd_number).unwrap().direct_block_numbers[2]]);
+    assert!(motd.xattr_block_number.is_some());
+  }
+}
+
+// vim:ts=2 sw=2
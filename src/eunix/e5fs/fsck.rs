@@ -0,0 +1,765 @@
+use std::collections::{BTreeMap, BTreeSet, VecDeque};
+use std::mem::size_of;
+
+use super::hashdir::{self, BUCKET_MAGIC};
+use super::snapshot::SNAPSHOTS_DIR_NAME;
+use super::{Directory, E5FSFilesystem, INode};
+use crate::eunix::fs::{AddressSize, FileModeType, NO_ADDRESS};
+use crate::eunix::kernel::Errno;
+
+/// Exit status of fsck when nothing was wrong, like that of e2fsck
+pub const FSCK_OK: AddressSize = 0;
+/// Exit status of fsck when everything wrong was fixed
+pub const FSCK_CORRECTED: AddressSize = 1;
+/// Exit status of fsck when something wrong was left as is
+pub const FSCK_UNCORRECTED: AddressSize = 4;
+
+/// One inconsistency that `check` found
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct FsckProblem {
+  pub message: String,
+  pub repaired: bool,
+}
+
+#[derive(Debug, Default)]
+pub struct FsckReport {
+  pub problems: Vec<FsckProblem>,
+  /// Inodes that can be reached from root
+  pub used_inodes_count: AddressSize,
+  pub inodes_count: AddressSize,
+  /// Blocks of files, of free blocks list, of superblock backups
+  /// and header of encryption
+  pub used_blocks_count: AddressSize,
+  pub blocks_count: AddressSize,
+}
+
+impl FsckReport {
+  pub fn is_clean(&self) -> bool {
+    self.problems.is_empty()
+  }
+
+  pub fn exit_code(&self) -> AddressSize {
+    match self.problems.iter().all(|problem| problem.repaired) {
+      _ if self.is_clean() => FSCK_OK,
+      true => FSCK_CORRECTED,
+      false => FSCK_UNCORRECTED,
+    }
+  }
+}
+
+/// Check that e5fs is consistent: superblock matches its counts and
+/// backups, every directory entry points to a used inode, link counts
+/// match entries, every used inode is in some directory and every data
+/// block is either used by exactly one file, holds a superblock backup
+/// or header of encryption, or is marked free in fbl.
+/// With `repair`, fix what is found in one transaction
+pub fn check(e5fs: &mut E5FSFilesystem, repair: bool) -> Result<FsckReport, Errno> {
+  e5fs.transaction(|e5fs| {
+    let mut checker = Checker {
+      e5fs,
+      repair,
+      report: FsckReport::default(),
+      reachable: BTreeSet::new(),
+      references: BTreeMap::new(),
+      snapshot_dirs: BTreeSet::new(),
+      snapshot_references: BTreeMap::new(),
+    };
+
+    checker.check_superblock()?;
+    if checker.check_tree()? {
+      checker.check_links_counts()?;
+      checker.check_orphans()?;
+      checker.check_free_inode_numbers()?;
+      checker.check_blocks()?;
+    }
+    checker.check_free_counts()?;
+
+    Ok(checker.report)
+  })
+}
+
+/// Inode that no file uses: released one or one that was never used
+/// at all, those are zeroed by mkfs
+pub(super) fn is_free(inode: &INode) -> bool {
+  inode.mode.free() == 1 || (inode.mode.0 == 0 && inode.links_count == 0)
+}
+
+fn is_dir(inode: &INode) -> bool {
+  inode.mode.file_type() == FileModeType::Dir as u8
+}
+
+struct Checker<'a> {
+  e5fs: &'a mut E5FSFilesystem,
+  repair: bool,
+  report: FsckReport,
+  /// Inodes in directory tree, what it will be after repair
+  reachable: BTreeSet<AddressSize>,
+  /// Directory entries pointing to inode, `.` and `..` included
+  references: BTreeMap<AddressSize, AddressSize>,
+  /// /.snapshots and directories under it
+  snapshot_dirs: BTreeSet<AddressSize>,
+  /// Entries of `snapshot_dirs` pointing to inode of file
+  snapshot_references: BTreeMap<AddressSize, AddressSize>,
+}
+
+impl Checker<'_> {
+  /// Record a problem that can be fixed.
+  /// Returns: whether it should be fixed
+  fn found(&mut self, message: String) -> bool {
+    tracing::debug!("fsck: {message}");
+    self.report.problems.push(FsckProblem { message, repaired: self.repair });
+    self.repair
+  }
+
+  fn found_unrepairable(&mut self, message: String) {
+    tracing::debug!("fsck: {message}");
+    self.report.problems.push(FsckProblem { message, repaired: false });
+  }
+
+  /// Superblock against what can be told without it: block number
+  /// of fbl follows from count of blocks, and backups of superblock
+  /// have the same layout, version, label and reserved blocks, as
+  /// everything that changes them updates backups too. Mount has
+  /// checked that layout adds up and fits on device
+  fn check_superblock(&mut self) -> Result<(), Errno> {
+    // Mount has put it back already
+    if let Some(address) = self.e5fs.superblock_backup.take() {
+      self.report.problems.push(FsckProblem {
+        message: format!("superblock is corrupt, restored it from backup at {address}"),
+        repaired: true,
+      });
+    }
+
+    // What superblock is to be, backups are checked against it
+    let mut superblock = self.e5fs.superblock;
+    superblock.first_fbl_block_number = self.e5fs.fs_info.first_fbl_block_number;
+    if self.e5fs.superblock.first_fbl_block_number != superblock.first_fbl_block_number
+      && self.found(format!(
+        "superblock: first_fbl_block_number is {}, should be {}",
+        self.e5fs.superblock.first_fbl_block_number,
+        superblock.first_fbl_block_number,
+      ))
+    {
+      self.e5fs.superblock = superblock;
+      self.e5fs.write_superblock(&superblock)?;
+    }
+
+    for (address, block_numbers) in self.e5fs.superblock_backups() {
+      if !self.e5fs.has_superblock_backup(address, block_numbers) {
+        continue;
+      }
+      let backup = E5FSFilesystem::read_superblock_at(&mut *self.e5fs.fs_info.realfile.write().unwrap(), address)?;
+      let fields = [
+        ("format_version", backup.format_version.to_string(), superblock.format_version.to_string()),
+        ("filesystem_size", backup.filesystem_size.to_string(), superblock.filesystem_size.to_string()),
+        ("inodes_count", backup.inodes_count.to_string(), superblock.inodes_count.to_string()),
+        ("blocks_count", backup.blocks_count.to_string(), superblock.blocks_count.to_string()),
+        ("block_size", backup.block_size.to_string(), superblock.block_size.to_string()),
+        ("inode_table_size", backup.inode_table_size.to_string(), superblock.inode_table_size.to_string()),
+        ("first_fbl_block_number", backup.first_fbl_block_number.to_string(), superblock.first_fbl_block_number.to_string()),
+        ("reserved_blocks_count", backup.reserved_blocks_count.to_string(), superblock.reserved_blocks_count.to_string()),
+        ("label", format!("'{}'", backup.label()), format!("'{}'", superblock.label())),
+      ];
+
+      let mut fix = false;
+      for (field, value, expected) in fields {
+        if value != expected {
+          fix |= self.found(format!("superblock backup at {address}: {field} is {value}, should be {expected}"));
+        }
+      }
+
+      if fix {
+        self.e5fs.write_superblock_at(&superblock, address)?;
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Free counts in superblock, which every claim and release of
+  /// inode or block keeps up to date. Runs after everything else,
+  /// so counts match what repairs have left
+  fn check_free_counts(&mut self) -> Result<(), Errno> {
+    let (free_inodes_count, free_blocks_count) = self.e5fs.counted_free()?;
+    let superblock = self.e5fs.superblock;
+    let fields = [
+      ("free_inodes_count", superblock.free_inodes_count, free_inodes_count),
+      ("free_blocks_count", superblock.free_blocks_count, free_blocks_count),
+    ];
+
+    let mut fix = false;
+    for (field, value, expected) in fields {
+      if value != expected {
+        fix |= self.found(format!("superblock: {field} is {value}, should be {expected}"));
+      }
+    }
+
+    if fix {
+      let mut superblock = self.e5fs.superblock;
+      superblock.free_inodes_count = free_inodes_count;
+      superblock.free_blocks_count = free_blocks_count;
+      self.e5fs.superblock = superblock;
+      self.e5fs.write_superblock(&superblock)?;
+    }
+
+    Ok(())
+  }
+
+  /// Walk directories from root, dropping entries that can't be right.
+  /// Returns: whether there is a tree to check the rest against
+  fn check_tree(&mut self) -> Result<bool, Errno> {
+    let root = self.e5fs.fs_info.root_inode_number;
+    let root_inode = self.e5fs.read_inode(root)?;
+    if is_free(&root_inode) || !is_dir(&root_inode) {
+      self.found_unrepairable(format!("root inode {root} is not a directory"));
+      return Ok(false);
+    }
+
+    self.reach(root)?;
+    let mut queue = VecDeque::from([(root, root)]);
+    while let Some((dir_number, parent_number)) = queue.pop_front() {
+      let data = self.read_data(dir_number)?;
+      let mut fix = false;
+      let (entries, complete, buckets_count) = match hashdir::buckets(&data, self.e5fs.fs_info.block_size) {
+        None => {
+          let (entries, complete) = parse_entries(&data);
+          (entries.into_iter().map(|entry| (None, entry)).collect(), complete, 0)
+        },
+        Some(buckets) => {
+          let buckets_count = buckets.len() as AddressSize;
+          let mut entries = Vec::new();
+          let mut complete = true;
+          for (bucket, block) in (0..).zip(buckets) {
+            let Some(bucket_entries) = block.strip_prefix(&BUCKET_MAGIC) else {
+              fix |= self.found(format!("directory {dir_number}: bucket {bucket} is damaged"));
+              continue;
+            };
+            let (bucket_entries, bucket_complete) = parse_entries(bucket_entries);
+            entries.extend(bucket_entries.into_iter().map(|entry| (Some(bucket), entry)));
+            complete &= bucket_complete;
+          }
+          (entries, complete, buckets_count)
+        },
+      };
+      if !complete {
+        fix |= self.found(format!("directory {dir_number}: cut short"));
+      }
+
+      let mut kept = Directory::new();
+      for (bucket, (inode_number, rec_len, name)) in entries {
+        let lossy_name = String::from_utf8_lossy(&name).into_owned();
+        let name = match String::from_utf8(name) {
+          Ok(name) if !name.is_empty() && !name.contains('/') => name,
+          _ => {
+            fix |= self.found(format!("directory {dir_number}: entry '{lossy_name}' has bad name"));
+            continue;
+          },
+        };
+
+        // Guard for two entries of the same name
+        if kept.entries.contains_key(&name) {
+          fix |= self.found(format!("directory {dir_number}: entry '{name}' is there twice"));
+          continue;
+        }
+
+        if rec_len as usize != size_of::<AddressSize>() + size_of::<u16>() + size_of::<u8>() + name.len() {
+          fix |= self.found(format!("directory {dir_number}: entry '{name}' has record length {rec_len}"));
+        }
+
+        // Lookup wouldn't find it anywhere else
+        if bucket.map_or(false, |bucket| bucket != hashdir::bucket_of(&name, buckets_count)) {
+          fix |= self.found(format!("directory {dir_number}: entry '{name}' is in wrong bucket"));
+        }
+
+        // `.` and `..` are put right, not dropped
+        let expected = match name.as_str() {
+          "." => Some(dir_number),
+          ".." => Some(parent_number),
+          _ => None,
+        };
+        if let Some(expected) = expected {
+          if inode_number != expected {
+            fix |= self.found(format!("directory {dir_number}: '{name}' points to {inode_number}, should be {expected}"));
+          }
+          self.keep(&mut kept, expected, &name)?;
+          continue;
+        }
+
+        // Same bound as `parse_directory` has
+        if inode_number >= self.e5fs.fs_info.inodes_count.saturating_sub(1) {
+          fix |= self.found(format!("directory {dir_number}: entry '{name}' points to inode {inode_number} out of range"));
+          continue;
+        }
+
+        let inode = self.e5fs.read_inode(inode_number)?;
+        if is_free(&inode) {
+          fix |= self.found(format!("directory {dir_number}: entry '{name}' points to free inode {inode_number}"));
+          continue;
+        }
+
+        let in_snapshots = self.snapshot_dirs.contains(&dir_number);
+        if is_dir(&inode) {
+          // Guard for second link to directory, which makes loops
+          if self.reachable.contains(&inode_number) {
+            fix |= self.found(format!("directory {dir_number}: entry '{name}' is second link to directory {inode_number}"));
+            continue;
+          }
+          queue.push_back((inode_number, dir_number));
+          if in_snapshots || (dir_number == root && name == SNAPSHOTS_DIR_NAME) {
+            self.snapshot_dirs.insert(inode_number);
+          }
+        } else if in_snapshots {
+          *self.snapshot_references.entry(inode_number).or_default() += 1;
+        }
+
+        if !self.reachable.contains(&inode_number) {
+          self.reach(inode_number)?;
+        }
+        self.keep(&mut kept, inode_number, &name)?;
+      }
+
+      for (name, expected) in [(".", dir_number), ("..", parent_number)] {
+        if !kept.entries.contains_key(name) {
+          fix |= self.found(format!("directory {dir_number}: no '{name}'"));
+          self.keep(&mut kept, expected, name)?;
+        }
+      }
+
+      if fix {
+        self.e5fs.write_dir_i(&kept, dir_number)?;
+      }
+    }
+
+    Ok(true)
+  }
+
+  fn keep(&mut self, dir: &mut Directory, inode_number: AddressSize, name: &str) -> Result<(), Errno> {
+    dir.insert(inode_number, name)?;
+    *self.references.entry(inode_number).or_default() += 1;
+
+    Ok(())
+  }
+
+  /// Add inode to tree, checking that its blocks can be read
+  fn reach(&mut self, inode_number: AddressSize) -> Result<(), Errno> {
+    self.reachable.insert(inode_number);
+
+    let mut inode = self.e5fs.read_inode(inode_number)?;
+    let first_fbl_block_number = self.e5fs.fs_info.first_fbl_block_number;
+    let mut fix = false;
+    if inode.xattr_block_number != NO_ADDRESS && inode.xattr_block_number >= first_fbl_block_number {
+      fix |= self.found(format!("inode {inode_number}: attributes block {} is out of range", inode.xattr_block_number));
+      inode.xattr_block_number = NO_ADDRESS;
+    }
+    if inode.is_device() {
+      if fix {
+        self.e5fs.write_inode(&inode, inode_number)?;
+      }
+      return Ok(());
+    }
+
+    let indirect_block_number = inode.indirect_block_numbers[0];
+    if indirect_block_number != NO_ADDRESS && indirect_block_number >= first_fbl_block_number {
+      // Blocks in it are lost, slots become holes
+      fix |= self.found(format!("inode {inode_number}: indirect block {indirect_block_number} is out of range"));
+      inode.indirect_block_numbers[0] = NO_ADDRESS;
+    }
+
+    let mut block_numbers = self.e5fs.block_numbers_i(&inode)?;
+    for slot in block_numbers.iter_mut().filter(|slot| **slot != NO_ADDRESS && **slot >= first_fbl_block_number) {
+      // Files may have holes, so the slot just becomes one
+      fix |= self.found(format!("inode {inode_number}: block {slot} is out of range"));
+      *slot = NO_ADDRESS;
+    }
+
+    let capacity = block_numbers.len() as AddressSize * self.e5fs.fs_info.block_size;
+    if inode.file_size > capacity {
+      fix |= self.found(format!("inode {inode_number}: size is {}, inode can have {capacity} bytes", inode.file_size));
+      inode.file_size = capacity;
+    }
+
+    if fix {
+      self.e5fs.set_block_numbers_i(&mut inode, &block_numbers)?;
+      self.e5fs.write_inode(&inode, inode_number)?;
+    }
+
+    Ok(())
+  }
+
+  /// Data blocks, indirect block and block of attributes of
+  /// inode, without holes and blocks out of range
+  fn data_blocks(&self, inode: &INode) -> Result<Vec<AddressSize>, Errno> {
+    let data_block_numbers = match inode.is_device() {
+      true => Vec::new(),
+      false => self.e5fs.block_numbers_i(inode)?,
+    };
+    let indirect_block_numbers = match inode.is_device() {
+      true => &[][..],
+      false => &inode.indirect_block_numbers[..1],
+    };
+
+    Ok(data_block_numbers
+      .into_iter()
+      .chain(indirect_block_numbers.iter().copied())
+      .chain([inode.xattr_block_number])
+      .filter(|&block_number| block_number < self.e5fs.fs_info.first_fbl_block_number)
+      .collect())
+  }
+
+  /// Like `read_data_i`, but blocks that don't exist read as holes
+  fn read_data(&self, inode_number: AddressSize) -> Result<Vec<u8>, Errno> {
+    let inode = self.e5fs.read_inode(inode_number)?;
+    let block_size = self.e5fs.fs_info.block_size;
+
+    let mut data = Vec::new();
+    for block_number in self.e5fs.block_numbers_i(&inode)?.into_iter().take(inode.file_size.div_ceil(block_size) as usize) {
+      match block_number < self.e5fs.fs_info.first_fbl_block_number {
+        true => data.extend(self.e5fs.read_block(block_number)?.data),
+        false => data.extend(vec![0; block_size as usize]),
+      }
+    }
+    data.truncate(inode.file_size as usize);
+
+    Ok(data)
+  }
+
+  /// Links count of inode is count of entries pointing to it, and
+  /// those in snapshots are counted in its snapshot links too
+  fn check_links_counts(&mut self) -> Result<(), Errno> {
+    for (&inode_number, &references) in &self.references.clone() {
+      let mut inode = self.e5fs.read_inode(inode_number)?;
+      let snapshot_references = self.snapshot_references.get(&inode_number).copied().unwrap_or(0);
+      let mut fix = false;
+      if inode.links_count != references {
+        fix |= self.found(format!("inode {inode_number}: links count is {}, should be {references}", inode.links_count));
+      }
+      if inode.snapshot_links as AddressSize != snapshot_references {
+        fix |= self.found(format!("inode {inode_number}: snapshot links count is {}, should be {snapshot_references}", inode.snapshot_links));
+      }
+
+      if fix {
+        inode.links_count = references;
+        inode.snapshot_links = snapshot_references.min(u16::MAX as AddressSize) as u16;
+        self.e5fs.write_inode(&inode, inode_number)?;
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Used inodes that are in no directory get released
+  fn check_orphans(&mut self) -> Result<(), Errno> {
+    for inode_number in 0..self.e5fs.fs_info.inodes_count {
+      let mut inode = self.e5fs.read_inode(inode_number)?;
+      if self.reachable.contains(&inode_number) || is_free(&inode) {
+        continue;
+      }
+
+      if self.found(format!("inode {inode_number}: used, but not in any directory")) {
+        inode.mode = inode.mode.with_free(1);
+        inode.links_count = 0;
+        self.e5fs.write_inode(&inode, inode_number)?;
+      }
+    }
+
+    self.report.used_inodes_count = self.reachable.len() as AddressSize;
+    self.report.inodes_count = self.e5fs.fs_info.inodes_count;
+
+    Ok(())
+  }
+
+  /// `free_inode_numbers` must not hand out used inodes
+  fn check_free_inode_numbers(&mut self) -> Result<(), Errno> {
+    let mut superblock = self.e5fs.superblock;
+    let mut seen = BTreeSet::new();
+    let mut fix = false;
+
+    for free_inode_number in superblock.free_inode_numbers.iter_mut() {
+      let inode_number = *free_inode_number;
+      if inode_number == NO_ADDRESS {
+        continue;
+      }
+
+      if inode_number >= self.e5fs.fs_info.inodes_count
+        || self.reachable.contains(&inode_number)
+        || !seen.insert(inode_number)
+      {
+        fix |= self.found(format!("superblock: inode {inode_number} is in free inodes, but can't be claimed"));
+        *free_inode_number = NO_ADDRESS;
+      }
+    }
+
+    if fix {
+      self.e5fs.superblock = superblock;
+      self.e5fs.write_superblock(&superblock)?;
+    }
+
+    Ok(())
+  }
+
+  /// Every data block belongs to one file, to as many as fbl says
+  /// share it, or is free
+  fn check_blocks(&mut self) -> Result<(), Errno> {
+    let first_fbl_block_number = self.e5fs.fs_info.first_fbl_block_number;
+    let blocks_count = self.e5fs.fs_info.blocks_count;
+
+    let mut owners = vec![Vec::new(); first_fbl_block_number as usize];
+    for &inode_number in &self.reachable {
+      for block_number in self.data_blocks(&self.e5fs.read_inode(inode_number)?)? {
+        owners[block_number as usize].push(inode_number);
+      }
+    }
+    let reserved_blocks = self.e5fs.reserved_blocks();
+
+    // Files past the first that may go on sharing each block, as fbl
+    // knows about them. Others get copies of it
+    let mut kept = owners.iter().map(Vec::len).collect::<Vec<usize>>();
+    for (block_number, recorded) in (0..).zip(self.e5fs.read_references()?) {
+      let owners = &owners[block_number as usize];
+      let references = match owners.len() {
+        _ if reserved_blocks.contains(&block_number) => 1,
+        0 | 1 => owners.len() as AddressSize,
+        _ => {
+          let references = recorded.unwrap_or(1).clamp(1, owners.len() as AddressSize);
+          kept[block_number as usize] = references as usize;
+          references
+        },
+      };
+      if recorded == Some(references) {
+        continue;
+      }
+
+      let message = match (recorded, references) {
+        (Some(0), _) => format!("block {block_number}: used, but marked free"),
+        (Some(_), 0) => format!("block {block_number}: not used, but not marked free"),
+        (Some(recorded), _) => format!("block {block_number}: fbl has {recorded} files using it, should be {references}"),
+        (None, _) => format!("block {block_number}: fbl makes no sense for it, should have {references} files using it"),
+      };
+      if self.found(message) {
+        self.e5fs.write_block_references(block_number, references)?;
+        let free_blocks_count = &mut self.e5fs.superblock.free_blocks_count;
+        match (recorded == Some(0), references == 0) {
+          (true, false) => *free_blocks_count = free_blocks_count.saturating_sub(1),
+          (false, true) => *free_blocks_count += 1,
+          _ => (),
+        }
+      }
+    }
+
+    // With fbl fixed, copies of shared blocks can be claimed
+    for (block_number, owners) in (0..).zip(&owners) {
+      let kept = kept[block_number as usize];
+      if owners.len() <= kept {
+        continue;
+      }
+
+      let inode_numbers = owners.iter().map(|n| n.to_string()).collect::<Vec<_>>().join(", ");
+      if !self.found(format!("block {block_number}: shared by inodes {inode_numbers}")) {
+        continue;
+      }
+
+      for &inode_number in &owners[kept..] {
+        let copy_number = self.e5fs.claim_free_block()?;
+        let block = self.e5fs.read_block(block_number)?;
+        self.e5fs.write_block(&block, copy_number)?;
+
+        let mut inode = self.e5fs.read_inode(inode_number)?;
+        let mut block_numbers = self.e5fs.block_numbers_i(&inode)?;
+        // Device number is not a block
+        let (data_slots_count, indirect_slots_count) = match inode.is_device() {
+          true => (0, 0),
+          false => (block_numbers.len(), 1),
+        };
+        let slots = block_numbers[..data_slots_count]
+          .iter_mut()
+          .chain(&mut inode.indirect_block_numbers[..indirect_slots_count])
+          .chain([&mut inode.xattr_block_number]);
+        for slot in slots.filter(|slot| **slot == block_number) {
+          *slot = copy_number;
+        }
+        if data_slots_count > 0 {
+          self.e5fs.set_block_numbers_i(&mut inode, &block_numbers)?;
+        }
+        self.e5fs.write_inode(&inode, inode_number)?;
+      }
+    }
+
+    self.report.used_blocks_count = (blocks_count - first_fbl_block_number)
+      + (0..).zip(&owners)
+        .filter(|(block_number, owners)| !owners.is_empty() || reserved_blocks.contains(block_number))
+        .count() as AddressSize;
+    self.report.blocks_count = blocks_count;
+
+    Ok(())
+  }
+}
+
+/// Entries of directory as they are: `(inode_number, rec_len, name)`.
+/// Returns: them and whether there were as many as directory says
+fn parse_entries(data: &[u8]) -> (Vec<(AddressSize, u16, Vec<u8>)>, bool) {
+  let mut entries = Vec::new();
+  let Some((entries_count, mut rest)) = split_number::<4>(data) else {
+    return (entries, false);
+  };
+
+  for _ in 0..AddressSize::from_le_bytes(entries_count) {
+    let entry = split_number::<4>(rest).and_then(|(inode_number, rest)| {
+      let (rec_len, rest) = split_number::<2>(rest)?;
+      let (&name_len, rest) = rest.split_first()?;
+      let name = rest.get(..name_len as usize)?;
+
+      Some(((AddressSize::from_le_bytes(inode_number), u16::from_le_bytes(rec_len), name.to_owned()), &rest[name_len as usize..]))
+    });
+
+    match entry {
+      Some((entry, next)) => {
+        entries.push(entry);
+        rest = next;
+      },
+      None => return (entries, false),
+    }
+  }
+
+  (entries, true)
+}
+
+fn split_number<const N: usize>(data: &[u8]) -> Option<([u8; N], &[u8])> {
+  let bytes = data.get(..N)?.try_into().ok()?;
+
+  Some((bytes, &data[N..]))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::{memory_e5fs, E5FS_SIZE};
+  use crate::eunix::e5fs::SUPERBLOCK_BACKUP_ADDRESSES;
+  use crate::eunix::fs::Filesystem;
+
+  fn populated() -> E5FSFilesystem {
+    let (mut e5fs, _) = memory_e5fs(E5FS_SIZE);
+    e5fs.create_dir("/etc").unwrap();
+    e5fs.create_file("/etc/passwd").unwrap();
+    e5fs.write_file("/etc/passwd", &[b'x'; 5000]).unwrap();
+    e5fs.link("/etc/passwd", "/passwd").unwrap();
+    e5fs.symlink("/etc", "/config").unwrap();
+
+    e5fs
+  }
+
+  #[test]
+  fn fresh_filesystem_is_clean() {
+    let mut e5fs = populated();
+    let report = check(&mut e5fs, false).unwrap();
+
+    assert_eq!(report.problems, Vec::new());
+    assert_eq!(report.exit_code(), FSCK_OK);
+    assert_eq!(report.used_inodes_count, 4);
+  }
+
+  #[test]
+  fn wrong_links_count_is_repaired() {
+    let mut e5fs = populated();
+    let number = e5fs.lookup_path("/etc/passwd").unwrap().number;
+    e5fs.write_links_count_i(number, 7).unwrap();
+
+    let report = check(&mut e5fs, false).unwrap();
+    assert_eq!(report.exit_code(), FSCK_UNCORRECTED);
+    assert_eq!(e5fs.read_inode(number).unwrap().links_count, 7);
+
+    let report = check(&mut e5fs, true).unwrap();
+    assert_eq!(report.exit_code(), FSCK_CORRECTED);
+    assert_eq!(e5fs.read_inode(number).unwrap().links_count, 2);
+    assert!(check(&mut e5fs, false).unwrap().is_clean());
+  }
+
+  #[test]
+  fn lost_and_shared_blocks_are_repaired() {
+    let mut e5fs = populated();
+    let passwd = e5fs.lookup_path("/etc/passwd").unwrap().number;
+    let passwd = e5fs.read_inode(passwd).unwrap();
+    let config = e5fs.lookup_path("/config").unwrap().number;
+    let mut config = e5fs.read_inode(config).unwrap();
+
+    // Block of link is lost, link gets second block of passwd instead
+    config.direct_block_numbers[0] = passwd.direct_block_numbers[1];
+    e5fs.write_inode(&config, config.number).unwrap();
+
+    let report = check(&mut e5fs, true).unwrap();
+    assert_eq!(report.problems.len(), 2, "{:?}", report.problems);
+    assert_eq!(report.exit_code(), FSCK_CORRECTED);
+
+    let config = e5fs.read_inode(config.number).unwrap();
+    assert_ne!(config.direct_block_numbers[0], passwd.direct_block_numbers[1]);
+    assert_eq!(e5fs.read_file("/etc/passwd", AddressSize::MAX).unwrap(), vec![b'x'; 5000]);
+    assert!(check(&mut e5fs, false).unwrap().is_clean());
+  }
+
+  #[test]
+  fn bad_entries_and_orphans_are_repaired() {
+    let mut e5fs = populated();
+    let etc = e5fs.lookup_path("/etc").unwrap().number;
+    let passwd = e5fs.lookup_path("/etc/passwd").unwrap().number;
+    let config = e5fs.lookup_path("/config").unwrap().number;
+
+    // Entry to nowhere, `..` to wrong place, /passwd and /config dropped
+    let mut dir = e5fs.read_as_dir_i(etc).unwrap();
+    dir.insert(3000, "nowhere").unwrap();
+    dir.remove("..").unwrap();
+    dir.insert(etc, "..").unwrap();
+    e5fs.write_dir_i(&dir, etc).unwrap();
+    let mut root = e5fs.read_as_dir_i(0).unwrap();
+    root.remove("passwd").unwrap();
+    root.remove("config").unwrap();
+    e5fs.write_dir_i(&root, 0).unwrap();
+
+    let report = check(&mut e5fs, true).unwrap();
+    assert_eq!(report.exit_code(), FSCK_CORRECTED, "{:?}", report.problems);
+    assert_eq!(e5fs.read_dir("/etc").unwrap().entries.keys().collect::<Vec<_>>(), [".", "..", "passwd"]);
+    assert_eq!(e5fs.stat("/etc/..").unwrap().inode_number, 0);
+    assert_eq!(e5fs.read_inode(passwd).unwrap().links_count, 1);
+    assert!(is_free(&e5fs.read_inode(config).unwrap()));
+    assert!(check(&mut e5fs, false).unwrap().is_clean());
+  }
+
+  #[test]
+  fn wrong_free_counts_are_repaired() {
+    let mut e5fs = populated();
+    let stat = e5fs.statfs("/").unwrap();
+    e5fs.superblock.free_inodes_count += 5;
+    e5fs.superblock.free_blocks_count = 0;
+
+    let report = check(&mut e5fs, false).unwrap();
+    assert_eq!(report.problems.len(), 2, "{:?}", report.problems);
+    assert_eq!(report.exit_code(), FSCK_UNCORRECTED);
+
+    let report = check(&mut e5fs, true).unwrap();
+    assert_eq!(report.exit_code(), FSCK_CORRECTED);
+    assert_eq!(e5fs.statfs("/").unwrap(), stat);
+    assert!(check(&mut e5fs, false).unwrap().is_clean());
+  }
+
+  #[test]
+  fn superblock_is_checked_against_backups() {
+    let mut e5fs = populated();
+    let address = SUPERBLOCK_BACKUP_ADDRESSES[0];
+    let mut backup = e5fs.superblock;
+    backup.blocks_count += 1;
+    e5fs.write_superblock_at(&backup, address).unwrap();
+    e5fs.superblock.first_fbl_block_number += 1;
+    e5fs.write_superblock(&e5fs.superblock.clone()).unwrap();
+
+    let report = check(&mut e5fs, false).unwrap();
+    let messages: Vec<_> = report.problems.iter().map(|problem| problem.message.as_str()).collect();
+    let first_fbl_block_number = e5fs.fs_info.first_fbl_block_number;
+    assert_eq!(messages, [
+      format!("superblock: first_fbl_block_number is {}, should be {first_fbl_block_number}", first_fbl_block_number + 1),
+      format!("superblock backup at {address}: blocks_count is {}, should be {}", backup.blocks_count, backup.blocks_count - 1),
+    ]);
+
+    assert_eq!(check(&mut e5fs, true).unwrap().exit_code(), FSCK_CORRECTED);
+    assert!(check(&mut e5fs, false).unwrap().is_clean());
+  }
+}
+
+// vim:ts=2 sw=2
@@ -0,0 +1,242 @@
+use super::{Directory, DirectoryEntry, E5FSFilesystem};
+use crate::eunix::fs::AddressSize;
+use crate::eunix::kernel::Errno;
+
+/// First bytes of every block of hashed directory. Each block is a
+/// bucket: these bytes, then count of entries and entries, like
+/// unhashed directory has them. Entry goes to bucket `bucket_of`
+/// its name, so lookup reads only that block. Directory that doesn't
+/// start with them has all entries in one list, the way it used to
+/// be, and gets hashed when it is written
+pub const BUCKET_MAGIC: [u8; 4] = *b"E5DH";
+
+/// Bucket out of `buckets_count` that entry `name` goes to.
+/// FNV-1a, which is the same on every host
+pub fn bucket_of(name: &str, buckets_count: AddressSize) -> AddressSize {
+  let hash = name
+    .bytes()
+    .fold(0x811c_9dc5_u32, |hash, byte| (hash ^ byte as u32).wrapping_mul(0x0100_0193));
+
+  hash % buckets_count
+}
+
+/// Blocks of directory `data`, one per bucket.
+/// Returns: `None` if directory is not hashed
+pub fn buckets(data: &[u8], block_size: AddressSize) -> Option<std::slice::Chunks<'_, u8>> {
+  data
+    .starts_with(&BUCKET_MAGIC)
+    .then(|| data.chunks(block_size as usize))
+}
+
+/// Entries with count of them first, the way unhashed directory
+/// and every bucket of hashed one have them
+pub(super) fn entries_to_bytes<'a>(entries: impl ExactSizeIterator<Item = &'a DirectoryEntry>) -> Vec<u8> {
+  let mut bytes = (entries.len() as AddressSize).to_le_bytes().to_vec();
+  for entry in entries {
+    bytes.extend(entry.inode_number.to_le_bytes());
+    bytes.extend(entry.rec_len.to_le_bytes());
+    bytes.extend(entry.name_len.to_le_bytes());
+    bytes.extend(entry.name.as_bytes());
+  }
+
+  bytes
+}
+
+/// Block of bucket with `entries`.
+/// Returns: `None` if they don't fit in it
+fn bucket_to_bytes<'a>(entries: impl ExactSizeIterator<Item = &'a DirectoryEntry>, block_size: AddressSize) -> Option<Vec<u8>> {
+  let mut bytes = BUCKET_MAGIC.to_vec();
+  bytes.extend(entries_to_bytes(entries));
+  if bytes.len() > block_size as usize {
+    return None;
+  }
+  bytes.resize(block_size as usize, 0);
+
+  Some(bytes)
+}
+
+/// Blocks of hashed directory with entries of `dir`, as few of them
+/// as they fit in: count of buckets doubles until they do
+///
+/// Errors:
+/// ENOSPC -> entries don't fit even in `max_buckets_count` blocks
+pub(super) fn hashed_dir_to_bytes(dir: &Directory, block_size: AddressSize, max_buckets_count: AddressSize) -> Result<Vec<u8>, Errno> {
+  let buckets_counts = std::iter::successors(Some(1), |&count: &AddressSize| Some(count * 2))
+    .take_while(|&count| count < max_buckets_count)
+    .chain([max_buckets_count]);
+
+  for buckets_count in buckets_counts {
+    let mut buckets = vec![Vec::new(); buckets_count as usize];
+    for entry in dir.entries.values() {
+      buckets[bucket_of(&entry.name, buckets_count) as usize].push(entry);
+    }
+
+    let blocks = buckets
+      .into_iter()
+      .map(|entries| bucket_to_bytes(entries.into_iter(), block_size))
+      .collect::<Option<Vec<_>>>();
+    if let Some(blocks) = blocks {
+      return Ok(blocks.concat());
+    }
+  }
+
+  Err(Errno::ENOSPC(format!("e5fs: directory is full, {} entries don't fit in it", dir.entries.len()).into()))
+}
+
+impl E5FSFilesystem {
+  /// Inode number of entry `name` of directory. Only its
+  /// bucket is read if directory is hashed
+  pub(super) fn find_dir_entry_i(&self, dir_number: AddressSize, name: &str) -> Result<Option<AddressSize>, Errno> {
+    let dir = match self.buckets_count_i(dir_number)? {
+      Some(buckets_count) => self.read_bucket_i(dir_number, bucket_of(name, buckets_count))?,
+      None => self.read_as_dir_i(dir_number)?,
+    };
+
+    Ok(dir.entries.get(name).map(|entry| entry.inode_number))
+  }
+
+  /// Add entry to directory. Only its bucket is written if there is
+  /// room in it, whole directory otherwise, with more buckets
+  ///
+  /// Errors:
+  /// EEXIST -> there is entry `name` already
+  /// ENOSPC -> directory is full
+  pub(super) fn insert_dir_entry_i(&mut self, dir_number: AddressSize, inode_number: AddressSize, name: &str) -> Result<(), Errno> {
+    if let Some(buckets_count) = self.buckets_count_i(dir_number)? {
+      let bucket = bucket_of(name, buckets_count);
+      let mut entries = self.read_bucket_i(dir_number, bucket)?;
+      entries.insert(inode_number, name)?;
+      if self.write_bucket_i(dir_number, bucket, &entries)? {
+        return Ok(());
+      }
+    }
+
+    let mut dir = self.read_as_dir_i(dir_number)?;
+    dir.insert(inode_number, name)?;
+    self.write_dir_i(&dir, dir_number)?;
+
+    Ok(())
+  }
+
+  /// Remove entry `name` from directory, writing only its bucket
+  /// if directory is hashed.
+  /// Returns: inode number of entry
+  ///
+  /// Errors:
+  /// ENOENT -> there is no such entry
+  pub(super) fn remove_dir_entry_i(&mut self, dir_number: AddressSize, name: &str) -> Result<AddressSize, Errno> {
+    let not_found = || Errno::ENOENT(format!("e5fs: no such file or directory '{name}'").into());
+
+    if let Some(buckets_count) = self.buckets_count_i(dir_number)? {
+      let bucket = bucket_of(name, buckets_count);
+      let mut entries = self.read_bucket_i(dir_number, bucket)?;
+      let inode_number = entries.entries.get(name).ok_or_else(not_found)?.inode_number;
+      entries.remove(name)?;
+      // Bucket only gets smaller, so it fits
+      self.write_bucket_i(dir_number, bucket, &entries)?;
+      return Ok(inode_number);
+    }
+
+    let mut dir = self.read_as_dir_i(dir_number)?;
+    let inode_number = dir.entries.get(name).ok_or_else(not_found)?.inode_number;
+    dir.remove(name)?;
+    self.write_dir_i(&dir, dir_number)?;
+
+    Ok(inode_number)
+  }
+
+  /// Up to `count` entries of directory from entry number `offset`.
+  /// Hashed directory has them in order of buckets and is read a
+  /// bucket at a time, only until there are `count` of them
+  pub(super) fn read_dir_entries_i(&self, dir_number: AddressSize, offset: AddressSize, count: AddressSize) -> Result<Vec<DirectoryEntry>, Errno> {
+    let Some(buckets_count) = self.buckets_count_i(dir_number)? else {
+      let dir = self.read_as_dir_i(dir_number)?;
+      return Ok(dir.entries.into_values().skip(offset as usize).take(count as usize).collect());
+    };
+
+    let mut entries = Vec::new();
+    let mut skipped = 0;
+    for bucket in 0..buckets_count {
+      let left = count.saturating_sub(entries.len() as AddressSize);
+      if left == 0 {
+        break;
+      }
+      let bucket_entries = self.read_bucket_i(dir_number, bucket)?.entries;
+      let bucket_len = bucket_entries.len() as AddressSize;
+      if skipped + bucket_len <= offset {
+        skipped += bucket_len;
+        continue;
+      }
+      entries.extend(bucket_entries.into_values().skip(offset.saturating_sub(skipped) as usize).take(left as usize));
+      skipped = offset;
+    }
+
+    Ok(entries)
+  }
+
+  /// Count of buckets of directory, `None` if it is not hashed
+  pub(super) fn buckets_count_i(&self, dir_number: AddressSize) -> Result<Option<AddressSize>, Errno> {
+    let block_size = self.fs_info.block_size;
+    let magic = self.read_data_at_i(dir_number, 0, BUCKET_MAGIC.len() as AddressSize)?;
+    if magic != BUCKET_MAGIC {
+      return Ok(None);
+    }
+
+    Ok(Some(self.read_inode(dir_number)?.file_size.div_ceil(block_size)))
+  }
+
+  /// Entries in bucket `bucket` of hashed directory
+  ///
+  /// Errors:
+  /// EILSEQ -> block of bucket is not one
+  fn read_bucket_i(&self, dir_number: AddressSize, bucket: AddressSize) -> Result<Directory, Errno> {
+    let block_size = self.fs_info.block_size;
+    let data = self.read_data_at_i(dir_number, bucket * block_size, block_size)?;
+    let entries = data
+      .strip_prefix(&BUCKET_MAGIC)
+      .ok_or_else(|| Errno::EILSEQ(format!("e5fs: directory {dir_number} has damaged bucket {bucket}").into()))?;
+
+    E5FSFilesystem::parse_directory(&self.fs_info, entries.to_owned())
+  }
+
+  /// Put `entries` into block of bucket `bucket`.
+  /// Returns: whether they fit in it
+  fn write_bucket_i(&mut self, dir_number: AddressSize, bucket: AddressSize, entries: &Directory) -> Result<bool, Errno> {
+    let block_size = self.fs_info.block_size;
+    let Some(bytes) = bucket_to_bytes(entries.entries.values(), block_size) else {
+      return Ok(false);
+    };
+    self.write_data_at_i(&bytes, dir_number, bucket * block_size, false)?;
+
+    Ok(true)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn entries_spread_over_buckets_that_fit() {
+    let mut dir = Directory::new();
+    for index in 0..200 {
+      dir.insert(index, &format!("file{index}")).unwrap();
+    }
+
+    let bytes = hashed_dir_to_bytes(&dir, 512, 64).unwrap();
+    assert_eq!(bytes.len() % 512, 0);
+    let buckets_count = (bytes.len() / 512) as AddressSize;
+    assert!(buckets_count.is_power_of_two() && buckets_count > 1);
+    for (bucket, block) in (0..).zip(buckets(&bytes, 512).unwrap()) {
+      assert!(block.starts_with(&BUCKET_MAGIC));
+      let count = AddressSize::from_le_bytes(block[4..8].try_into().unwrap());
+      assert!(count > 0);
+      assert_eq!(bucket_of(&String::from_utf8_lossy(&block[15..15 + block[14] as usize]), buckets_count), bucket);
+    }
+
+    assert!(matches!(hashed_dir_to_bytes(&dir, 512, 2), Err(Errno::ENOSPC(_))));
+    assert!(buckets(&entries_to_bytes(dir.entries.values()), 512).is_none());
+  }
+}
+
+// vim:ts=2 sw=2
@@ -0,0 +1,391 @@
+use super::{Directory, E5FSFilesystem, INode};
+use super::trash::TRASH_DIR_NAME;
+use crate::eunix::fs::{AddressSize, FileMode, FileModeType, Filesystem, NO_ADDRESS, VFS};
+use crate::eunix::kernel::Errno;
+
+/// Directory in root of e5fs with snapshots in it, one directory
+/// tree per snapshot
+pub const SNAPSHOTS_DIR_NAME: &str = ".snapshots";
+
+/// Snapshots are copies of directories of the whole tree, but
+/// /.trash, in `/.snapshots/<name>`. Entries of copies point to the
+/// same inodes of files as the tree does: inode is copied only when
+/// file is changed through the tree, see `unshare_inode`, and its
+/// blocks only when they are written, see `unshare_blocks`
+impl E5FSFilesystem {
+  /// Names of snapshots, oldest first
+  pub fn list_snapshots(&mut self) -> Result<Vec<String>, Errno> {
+    let Some(snapshots_number) = self.snapshots_dir_i()? else {
+      return Ok(Vec::new());
+    };
+
+    let mut snapshots = self.read_as_dir_i(snapshots_number)?
+      .entries
+      .into_iter()
+      .filter(|(name, _)| name != "." && name != "..")
+      .map(|(name, entry)| Ok((self.read_inode(entry.inode_number)?.btime, name)))
+      .collect::<Result<Vec<_>, Errno>>()?;
+    snapshots.sort();
+
+    Ok(snapshots.into_iter().map(|(_, name)| name).collect())
+  }
+
+  /// Freeze current tree as snapshot `name`
+  ///
+  /// Errors:
+  /// EEXIST -> there is a snapshot with that name
+  /// EROFS  -> filesystem is read-only
+  pub fn create_snapshot(&mut self, name: &str) -> Result<(), Errno> {
+    self.check_writable("/")?;
+
+    self.transaction(|e5fs| {
+      let snapshots_number = match e5fs.snapshots_dir_i()? {
+        Some(snapshots_number) => snapshots_number,
+        // Owned by root and readable by everyone
+        None => e5fs.create_root_dir_i(SNAPSHOTS_DIR_NAME, FileMode::zero().with_user(0o7).with_group(0o5).with_others(0o5))?,
+      };
+      let mut snapshots = e5fs.read_as_dir_i(snapshots_number)?;
+      if snapshots.entries.contains_key(name) {
+        return Err(Errno::EEXIST(format!("e5fs: snapshot '{name}' already exists").into()));
+      }
+
+      let root_number = e5fs.fs_info.root_inode_number;
+      let snapshot_number = e5fs.clone_tree(root_number, snapshots_number, true)?;
+      let mut snapshot = e5fs.read_inode(snapshot_number)?;
+      snapshot.btime = e5fs.clock.now();
+      e5fs.write_inode(&snapshot, snapshot_number)?;
+
+      snapshots.insert(snapshot_number, name)?;
+      e5fs.write_dir_i(&snapshots, snapshots_number)?;
+      let links_count = e5fs.read_inode(snapshots_number)?.links_count;
+      e5fs.write_links_count_i(snapshots_number, links_count + 1)?;
+
+      Ok(())
+    })
+  }
+
+  /// Remove snapshot `name`, releasing blocks only it has
+  ///
+  /// Errors:
+  /// ENOENT -> there is no such snapshot
+  /// EROFS  -> filesystem is read-only
+  pub fn delete_snapshot(&mut self, name: &str) -> Result<(), Errno> {
+    self.check_writable("/")?;
+
+    self.transaction(|e5fs| {
+      let (snapshots_number, snapshot_number) = e5fs.find_snapshot(name)?;
+
+      e5fs.release_tree(snapshot_number, true)?;
+
+      let mut snapshots = e5fs.read_as_dir_i(snapshots_number)?;
+      snapshots.remove(name)?;
+      e5fs.write_dir_i(&snapshots, snapshots_number)?;
+      let links_count = e5fs.read_inode(snapshots_number)?.links_count;
+      e5fs.write_links_count_i(snapshots_number, links_count - 1)?;
+
+      Ok(())
+    })
+  }
+
+  /// Make tree what it was in snapshot `name`, which is kept
+  ///
+  /// Errors:
+  /// ENOENT -> there is no such snapshot
+  /// EROFS  -> filesystem is read-only
+  pub fn rollback_snapshot(&mut self, name: &str) -> Result<(), Errno> {
+    self.check_writable("/")?;
+
+    self.transaction(|e5fs| {
+      let (_, snapshot_number) = e5fs.find_snapshot(name)?;
+      let root_number = e5fs.fs_info.root_inode_number;
+
+      // Everything goes but snapshots themselves and trash...
+      let mut root = e5fs.read_as_dir_i(root_number)?;
+      let entries = root.entries
+        .iter()
+        .filter(|(name, _)| *name != "." && *name != ".." && !is_kept_in_root(name))
+        .map(|(name, entry)| (name.clone(), entry.inode_number))
+        .collect::<Vec<_>>();
+      for (entry_name, inode_number) in entries {
+        e5fs.release_tree(inode_number, false)?;
+        root.remove(&entry_name)?;
+      }
+      let kept_count = root.entries.keys().filter(|name| is_kept_in_root(name)).count() as AddressSize;
+
+      // ...and copies of what snapshot has take its place
+      let subdirs_count = e5fs.clone_entries(snapshot_number, root_number, &mut root, false)?;
+      e5fs.write_dir_i(&root, root_number)?;
+
+      let snapshot = e5fs.read_inode(snapshot_number)?;
+      let mut root = e5fs.read_inode(root_number)?;
+      root.mode = snapshot.mode;
+      root.uid = snapshot.uid;
+      root.gid = snapshot.gid;
+      root.mtime = snapshot.mtime;
+      root.ctime = e5fs.clock.now();
+      // `.`, `..` and `..` of every subdirectory, kept ones included
+      root.links_count = 2 + kept_count + subdirs_count;
+      e5fs.write_inode(&root, root_number)
+    })
+  }
+
+  /// Make snapshot `name` root of filesystem and filesystem
+  /// read-only, to mount the snapshot
+  ///
+  /// Errors:
+  /// ENOENT -> there is no such snapshot
+  pub fn open_snapshot(&mut self, name: &str) -> Result<(), Errno> {
+    let (_, snapshot_number) = self.find_snapshot(name)?;
+    self.fs_info.root_inode_number = snapshot_number;
+    self.read_only = true;
+
+    Ok(())
+  }
+
+  /// Errors:
+  /// EROFS -> filesystem is read-only or `pathname` is in /.snapshots,
+  ///          which only changes through snapshot operations
+  pub(super) fn check_writable(&self, pathname: &str) -> Result<(), Errno> {
+    // VFS may hand over `//.snapshots`
+    let in_snapshots = pathname.split('/').find(|component| !component.is_empty()) == Some(SNAPSHOTS_DIR_NAME);
+
+    match (self.read_only, in_snapshots) {
+      (true, _) => Err(Errno::EROFS(format!("e5fs: {pathname}: filesystem is read-only").into())),
+      (_, true) => Err(Errno::EROFS(format!("e5fs: {pathname}: snapshots are read-only").into())),
+      _ => Ok(()),
+    }
+  }
+
+  /// Returns: inode of /.snapshots and of root of snapshot `name`
+  fn find_snapshot(&mut self, name: &str) -> Result<(AddressSize, AddressSize), Errno> {
+    let snapshot = match self.snapshots_dir_i()? {
+      Some(snapshots_number) => self.read_as_dir_i(snapshots_number)?
+        .entries
+        .get(name)
+        .filter(|_| name != "." && name != "..")
+        .map(|entry| (snapshots_number, entry.inode_number)),
+      None => None,
+    };
+
+    snapshot.ok_or_else(|| Errno::ENOENT(format!("e5fs: no snapshot '{name}'").into()))
+  }
+
+  /// Inode of /.snapshots, if there is one
+  fn snapshots_dir_i(&mut self) -> Result<Option<AddressSize>, Errno> {
+    self.root_dir_entry_i(SNAPSHOTS_DIR_NAME)
+  }
+
+  /// Inode of entry `name` in root directory, if there is one
+  pub(super) fn root_dir_entry_i(&mut self, name: &str) -> Result<Option<AddressSize>, Errno> {
+    // Snapshot mounted as root has no snapshots in it
+    let root_number = self.fs_info.root_inode_number;
+
+    Ok(self.read_as_dir_i(root_number)?
+      .entries
+      .get(name)
+      .map(|entry| entry.inode_number))
+  }
+
+  /// Make directory `name` in root, owned by root, with permissions
+  /// of `mode`, for the filesystem itself to keep things in
+  pub(super) fn create_root_dir_i(&mut self, name: &str, mode: FileMode) -> Result<AddressSize, Errno> {
+    let root_number = self.fs_info.root_inode_number;
+    let (dir_number, _) = self.allocate_file()?;
+
+    let mut dir = Directory::new();
+    dir.insert(root_number, "..")?;
+    dir.insert(dir_number, ".")?;
+    self.write_dir_i(&dir, dir_number)?;
+
+    let mut inode = self.read_inode(dir_number)?;
+    inode.mode = mode.with_file_type(FileModeType::Dir as u8);
+    inode.links_count = 2;
+    inode.uid = 0;
+    inode.gid = 0;
+    self.write_inode(&inode, dir_number)?;
+
+    let mut root = self.read_as_dir_i(root_number)?;
+    root.insert(dir_number, name)?;
+    self.write_dir_i(&root, root_number)?;
+    let links_count = self.read_inode(root_number)?.links_count;
+    self.write_links_count_i(root_number, links_count + 1)?;
+
+    Ok(dir_number)
+  }
+
+  /// Copy of directory and everything under it, for entry in
+  /// directory `parent_number`, or one more link to file, which is
+  /// counted as link from snapshot if it is `into_snapshot`.
+  /// Returns: inode of copy, or that of file
+  fn clone_tree(&mut self, inode_number: AddressSize, parent_number: AddressSize, into_snapshot: bool) -> Result<AddressSize, Errno> {
+    let mut inode = self.read_inode(inode_number)?;
+
+    if inode.mode.file_type() != FileModeType::Dir as u8 {
+      inode.links_count += 1;
+      if into_snapshot {
+        inode.snapshot_links = inode.snapshot_links
+          .checked_add(1)
+          .ok_or_else(|| Errno::ENOSPC(format!("e5fs: inode {inode_number} is in too many snapshots").into()))?;
+      }
+      self.write_inode(&inode, inode_number)?;
+
+      return Ok(inode_number);
+    }
+
+    let clone_number = self.claim_free_inode()?;
+    if inode.xattr_block_number != NO_ADDRESS {
+      self.reference_block(inode.xattr_block_number)?;
+    }
+
+    // Directory gets blocks of its own, claimed when it is written
+    let clone = INode {
+      number: clone_number,
+      file_size: 0,
+      direct_block_numbers: [NO_ADDRESS; 12],
+      indirect_block_numbers: [NO_ADDRESS; 3],
+      ..inode
+    };
+    self.write_inode(&clone, clone_number)?;
+
+    let mut dir = Directory::new();
+    dir.insert(parent_number, "..")?;
+    dir.insert(clone_number, ".")?;
+    let subdirs_count = self.clone_entries(inode_number, clone_number, &mut dir, into_snapshot)?;
+    self.write_dir_i(&dir, clone_number)?;
+
+    // Times are those of original, not of writing the copy
+    let INode { file_size, direct_block_numbers, indirect_block_numbers, .. } = self.read_inode(clone_number)?;
+    self.write_inode(&INode { file_size, direct_block_numbers, indirect_block_numbers, links_count: 2 + subdirs_count, ..clone }, clone_number)?;
+
+    Ok(clone_number)
+  }
+
+  /// Put copies of entries of directory `dir_number` into `into`,
+  /// directory `into_number`. /.snapshots and /.trash are left out.
+  /// Returns: count of directories among them
+  fn clone_entries(&mut self, dir_number: AddressSize, into_number: AddressSize, into: &mut Directory, into_snapshot: bool) -> Result<AddressSize, Errno> {
+    let is_root = dir_number == self.fs_info.root_inode_number;
+    let mut subdirs_count = 0;
+
+    for (name, entry) in self.read_as_dir_i(dir_number)?.entries {
+      if name == "." || name == ".." || (is_root && is_kept_in_root(&name)) {
+        continue;
+      }
+
+      let clone_number = self.clone_tree(entry.inode_number, into_number, into_snapshot)?;
+      if self.read_inode(clone_number)?.mode.file_type() == FileModeType::Dir as u8 {
+        subdirs_count += 1;
+      }
+      into.insert(clone_number, &name)?;
+    }
+
+    Ok(subdirs_count)
+  }
+
+  /// Give file at `pathname` an inode of its own if it shares one with
+  /// snapshots, so that changing it leaves them as they were. Other
+  /// links to it from the tree go to the new inode too
+  pub(super) fn unshare_inode(&mut self, pathname: &str) -> Result<(), Errno> {
+    let inode_number = self.lookup_path(pathname)?.number;
+    let inode = self.read_inode(inode_number)?;
+    if inode.snapshot_links == 0 {
+      return Ok(());
+    }
+
+    let links_count = inode.links_count - inode.snapshot_links as AddressSize;
+    let copy_number = self.claim_free_inode()?;
+    let mut copy = INode { number: copy_number, links_count, snapshot_links: 0, ..inode };
+    if inode.xattr_block_number != NO_ADDRESS {
+      self.reference_block(inode.xattr_block_number)?;
+    }
+    // Device files keep device number where blocks would be.
+    // Data blocks are shared, indirect block is not
+    if !inode.is_device() {
+      let block_numbers = self.block_numbers_i(&inode)?;
+      for &block_number in block_numbers.iter().filter(|&&block_number| block_number != NO_ADDRESS) {
+        self.reference_block(block_number)?;
+      }
+      copy.indirect_block_numbers = [NO_ADDRESS; 3];
+      self.set_block_numbers_i(&mut copy, &block_numbers)?;
+    }
+    self.write_inode(&copy, copy_number)?;
+    self.write_links_count_i(inode_number, inode.snapshot_links as AddressSize)?;
+
+    // Hard links can be anywhere, only they need the tree walked
+    match links_count {
+      1 => {
+        let parent_number = self.lookup_path(&VFS::parent_dir(pathname)?)?.number;
+        let (_, name) = VFS::split_path(pathname)?;
+        self.remove_dir_entry_i(parent_number, &name)?;
+        self.insert_dir_entry_i(parent_number, copy_number, &name)
+      },
+      _ => self.relink_i(self.fs_info.root_inode_number, inode_number, copy_number),
+    }
+  }
+
+  /// Point entries that point to inode `from` to inode `to` instead, in
+  /// directory `dir_number` and under it. /.snapshots is left as is
+  fn relink_i(&mut self, dir_number: AddressSize, from: AddressSize, to: AddressSize) -> Result<(), Errno> {
+    let is_root = dir_number == self.fs_info.root_inode_number;
+
+    for (name, entry) in self.read_as_dir_i(dir_number)?.entries {
+      if name == "." || name == ".." || (is_root && name == SNAPSHOTS_DIR_NAME) {
+        continue;
+      }
+
+      if entry.inode_number == from {
+        self.remove_dir_entry_i(dir_number, &name)?;
+        self.insert_dir_entry_i(dir_number, to, &name)?;
+      } else if self.read_inode(entry.inode_number)?.mode.file_type() == FileModeType::Dir as u8 {
+        self.relink_i(entry.inode_number, from, to)?;
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Drop one link to inode, one from snapshot if it is
+  /// `from_snapshot`, releasing it and its blocks if it was the
+  /// last one. Directories go with everything under them
+  pub(super) fn release_tree(&mut self, inode_number: AddressSize, from_snapshot: bool) -> Result<(), Errno> {
+    let mut inode = self.read_inode(inode_number)?;
+
+    if inode.mode.file_type() == FileModeType::Dir as u8 {
+      for (name, entry) in self.read_as_dir_i(inode_number)?.entries {
+        if name != "." && name != ".." {
+          self.release_tree(entry.inode_number, from_snapshot)?;
+        }
+      }
+      inode.links_count = 0;
+    } else {
+      inode.links_count = inode.links_count.saturating_sub(1);
+      if from_snapshot {
+        inode.snapshot_links = inode.snapshot_links.saturating_sub(1);
+      }
+    }
+
+    if inode.links_count == 0 {
+      self.release_blocks_i(&mut inode)?;
+      if inode.xattr_block_number != NO_ADDRESS {
+        self.release_block(inode.xattr_block_number)?;
+        inode.xattr_block_number = NO_ADDRESS;
+      }
+      inode.mode = inode.mode.with_free(1);
+      self.superblock.free_inodes_count += 1;
+    }
+    // Snapshots keep file as it was
+    if !from_snapshot && inode.snapshot_links == 0 {
+      inode.ctime = self.clock.now();
+    }
+
+    self.write_inode(&inode, inode_number)
+  }
+}
+
+/// Entries of root that stay out of snapshots and stay as they
+/// are when tree is rolled back to one
+fn is_kept_in_root(name: &str) -> bool {
+  name == SNAPSHOTS_DIR_NAME || name == TRASH_DIR_NAME
+}
+
+// vim:ts=2 sw=2
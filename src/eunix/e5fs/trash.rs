@@ -0,0 +1,271 @@
+use super::E5FSFilesystem;
+use crate::eunix::fs::{AddressSize, FileMode, FileModeType, Filesystem, VFS};
+use crate::eunix::kernel::{Errno, UnixtimeSize};
+
+/// Directory in root of e5fs that removed files go to, if it is there
+pub const TRASH_DIR_NAME: &str = ".trash";
+
+/// Attribute of file in trash: pathname it was removed from
+pub const TRASH_PATHNAME_XATTR: &str = "trusted.trash.pathname";
+
+/// Files that have been in trash longer than this are purged when
+/// another file goes there: 30 days
+pub const TRASH_MAX_AGE: UnixtimeSize = 30 * 24 * 60 * 60;
+
+/// File in trash
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct TrashEntry {
+  /// Name of it in /.trash, its inode number
+  pub name: String,
+  /// Pathname it was removed from
+  pub pathname: String,
+  /// When it was removed
+  pub deleted: UnixtimeSize,
+  pub size: AddressSize,
+}
+
+/// Files whose last link is removed go to /.trash, if filesystem
+/// has one, instead of being released. There they are named by
+/// their inode numbers and can be put back with `undelete` until
+/// they are purged. Directories are released as before, and so is
+/// file removed from /.trash itself
+impl E5FSFilesystem {
+  /// Make /.trash, so removed files go there from now on
+  ///
+  /// Errors:
+  /// EROFS -> filesystem is read-only
+  pub fn enable_trash(&mut self) -> Result<(), Errno> {
+    self.check_writable("/")?;
+
+    self.transaction(|e5fs| {
+      if e5fs.root_dir_entry_i(TRASH_DIR_NAME)?.is_none() {
+        // Only root sees what others have removed
+        e5fs.create_root_dir_i(TRASH_DIR_NAME, FileMode::zero().with_user(0o7))?;
+      }
+
+      Ok(())
+    })
+  }
+
+  /// Release everything in trash and remove /.trash, so removed
+  /// files are released right away again
+  ///
+  /// Errors:
+  /// EROFS -> filesystem is read-only
+  pub fn disable_trash(&mut self) -> Result<(), Errno> {
+    self.check_writable("/")?;
+
+    self.transaction(|e5fs| {
+      let Some(trash_number) = e5fs.root_dir_entry_i(TRASH_DIR_NAME)? else {
+        return Ok(());
+      };
+
+      let root_number = e5fs.fs_info.root_inode_number;
+      e5fs.remove_dir_entry_i(root_number, TRASH_DIR_NAME)?;
+      e5fs.release_tree(trash_number, false)?;
+      let links_count = e5fs.read_inode(root_number)?.links_count;
+      e5fs.write_links_count_i(root_number, links_count - 1)?;
+
+      Ok(())
+    })
+  }
+
+  /// Files in trash, removed first go first. Empty if there is no trash
+  pub fn list_trash(&mut self) -> Result<Vec<TrashEntry>, Errno> {
+    let Some(trash_number) = self.root_dir_entry_i(TRASH_DIR_NAME)? else {
+      return Ok(Vec::new());
+    };
+
+    let mut entries = Vec::new();
+    for (name, entry) in self.read_as_dir_i(trash_number)?.entries {
+      if name == "." || name == ".." {
+        continue;
+      }
+      let inode = self.read_inode(entry.inode_number)?;
+      let pathname = self.read_xattrs_i(entry.inode_number)?
+        .remove(TRASH_PATHNAME_XATTR)
+        .map(|pathname| String::from_utf8_lossy(&pathname).into_owned())
+        .unwrap_or_default();
+      entries.push(TrashEntry {
+        name,
+        pathname,
+        deleted: inode.ctime,
+        size: inode.file_size,
+      });
+    }
+    entries.sort_by_key(|entry| (entry.deleted, entry.name.clone()));
+
+    Ok(entries)
+  }
+
+  /// Put file `name` of trash back where it was removed from, or
+  /// at `pathname` if it is given.
+  /// Returns: pathname file is at now
+  ///
+  /// Errors:
+  /// ENOENT -> there is no such file in trash, or parent directory
+  ///           of pathname is gone
+  /// EEXIST -> there is a file at pathname already
+  /// EROFS  -> filesystem is read-only
+  pub fn undelete(&mut self, name: &str, pathname: Option<&str>) -> Result<String, Errno> {
+    self.check_writable("/")?;
+
+    self.transaction(|e5fs| {
+      let trash_number = e5fs.root_dir_entry_i(TRASH_DIR_NAME)?
+        .ok_or_else(|| Errno::ENOENT(String::from("e5fs: there is no trash").into()))?;
+      let inode_number = e5fs.find_dir_entry_i(trash_number, name)?
+        .filter(|_| name != "." && name != "..")
+        .ok_or_else(|| Errno::ENOENT(format!("e5fs: no '{name}' in trash").into()))?;
+
+      let mut xattrs = e5fs.read_xattrs_i(inode_number)?;
+      let removed_from = xattrs.remove(TRASH_PATHNAME_XATTR);
+      let pathname = match (pathname, removed_from) {
+        (Some(pathname), _) => pathname.to_owned(),
+        (None, Some(pathname)) => String::from_utf8_lossy(&pathname).into_owned(),
+        (None, None) => return Err(Errno::ENOENT(format!("e5fs: '{name}' in trash has no pathname to put it back at").into())),
+      };
+      e5fs.check_writable(&pathname)?;
+
+      let parent_number = e5fs.lookup_path(&VFS::parent_dir(&pathname)?)?.number;
+      let (_, final_component) = VFS::split_path(&pathname)?;
+      if e5fs.find_dir_entry_i(parent_number, &final_component)?.is_some() {
+        return Err(Errno::EEXIST(format!("e5fs: {pathname}: file exists").into()));
+      }
+
+      e5fs.insert_dir_entry_i(parent_number, inode_number, &final_component)?;
+      e5fs.remove_dir_entry_i(trash_number, name)?;
+      e5fs.write_xattrs_i(inode_number, &xattrs)?;
+
+      Ok(pathname)
+    })
+  }
+
+  /// Release files that were removed before `deleted_before` for good.
+  /// Returns: how many of them there were
+  ///
+  /// Errors:
+  /// EROFS -> filesystem is read-only
+  pub fn purge_trash(&mut self, deleted_before: UnixtimeSize) -> Result<usize, Errno> {
+    self.check_writable("/")?;
+
+    self.transaction(|e5fs| {
+      let Some(trash_number) = e5fs.root_dir_entry_i(TRASH_DIR_NAME)? else {
+        return Ok(0);
+      };
+
+      let purged = e5fs.list_trash()?
+        .into_iter()
+        .filter(|entry| entry.deleted < deleted_before)
+        .collect::<Vec<_>>();
+      for entry in &purged {
+        let inode_number = e5fs.remove_dir_entry_i(trash_number, &entry.name)?;
+        e5fs.release_tree(inode_number, false)?;
+      }
+
+      Ok(purged.len())
+    })
+  }
+
+  /// Move inode, whose last link is being removed from `pathname`
+  /// in directory `parent_number`, to trash instead of releasing it.
+  /// Files that have been in trash for `TRASH_MAX_AGE` are purged.
+  /// Returns: whether it went there. It does not if there is no
+  /// trash, it is a directory or in trash already, or there is no
+  /// free block for its pathname
+  pub(super) fn trash_i(&mut self, inode_number: AddressSize, pathname: &str, parent_number: AddressSize) -> Result<bool, Errno> {
+    let Some(trash_number) = self.root_dir_entry_i(TRASH_DIR_NAME)? else {
+      return Ok(false);
+    };
+    let inode = self.read_inode(inode_number)?;
+    if parent_number == trash_number || inode.mode.file_type() == FileModeType::Dir as u8 {
+      return Ok(false);
+    }
+
+    let now = self.clock.now();
+    self.purge_trash(now.saturating_sub(TRASH_MAX_AGE))?;
+
+    let mut xattrs = self.read_xattrs_i(inode_number)?;
+    xattrs.insert(TRASH_PATHNAME_XATTR.to_owned(), pathname.as_bytes().to_vec());
+    match self.write_xattrs_i(inode_number, &xattrs) {
+      Err(Errno::ENOSPC(_)) => return Ok(false),
+      result => result?,
+    }
+    self.insert_dir_entry_i(trash_number, inode_number, &inode_number.to_string())?;
+
+    let mut inode = self.read_inode(inode_number)?;
+    inode.ctime = now;
+    self.write_inode(&inode, inode_number)?;
+
+    Ok(true)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::{memory_e5fs, E5FS_SIZE};
+  use crate::eunix::e5fs::fsck;
+  use crate::eunix::fs::EVERYTHING;
+
+  #[test]
+  fn removed_file_is_undeleted_from_trash() {
+    let (mut e5fs, _) = memory_e5fs(E5FS_SIZE);
+    e5fs.create_dir("/etc").unwrap();
+    e5fs.create_file("/etc/motd").unwrap();
+    e5fs.write_file("/etc/motd", b"hello").unwrap();
+    let motd_number = e5fs.lookup_path("/etc/motd").unwrap().number;
+
+    // Without trash, file is released
+    e5fs.create_file("/tmp").unwrap();
+    e5fs.remove_file("/tmp").unwrap();
+    assert!(e5fs.list_trash().unwrap().is_empty());
+
+    e5fs.enable_trash().unwrap();
+    let free_blocks_count = e5fs.superblock.free_blocks_count;
+    e5fs.remove_file("/etc/motd").unwrap();
+    assert!(e5fs.lookup_path("/etc/motd").is_err());
+    let trash = e5fs.list_trash().unwrap();
+    assert_eq!(trash.len(), 1);
+    assert_eq!((trash[0].name.as_str(), trash[0].pathname.as_str(), trash[0].size), (motd_number.to_string().as_str(), "/etc/motd", 5));
+    // Only the block of its pathname is claimed
+    assert_eq!(e5fs.superblock.free_blocks_count, free_blocks_count - 1);
+
+    e5fs.create_file("/etc/motd").unwrap();
+    assert!(matches!(e5fs.undelete(&trash[0].name, None), Err(Errno::EEXIST(_))));
+    assert_eq!(e5fs.undelete(&trash[0].name, Some("/etc/motd.old")).unwrap(), "/etc/motd.old");
+    assert_eq!(e5fs.read_file("/etc/motd.old", EVERYTHING).unwrap(), b"hello");
+    assert!(e5fs.list_xattr("/etc/motd.old").unwrap().is_empty());
+    assert!(e5fs.list_trash().unwrap().is_empty());
+    assert!(fsck::check(&mut e5fs, false).unwrap().is_clean());
+  }
+
+  #[test]
+  fn trash_is_purged() {
+    let (mut e5fs, _) = memory_e5fs(E5FS_SIZE);
+    e5fs.enable_trash().unwrap();
+    let free_inodes_count = e5fs.superblock.free_inodes_count;
+    e5fs.create_file("/a").unwrap();
+    e5fs.create_file("/b").unwrap();
+    e5fs.remove_file("/a").unwrap();
+    e5fs.remove_file("/b").unwrap();
+
+    // Removing from trash releases for good
+    let first = e5fs.list_trash().unwrap().remove(0);
+    e5fs.remove_file(&format!("/{TRASH_DIR_NAME}/{}", first.name)).unwrap();
+    assert_eq!(e5fs.list_trash().unwrap().len(), 1);
+
+    assert_eq!(e5fs.purge_trash(0).unwrap(), 0);
+    assert_eq!(e5fs.purge_trash(UnixtimeSize::MAX).unwrap(), 1);
+    assert_eq!(e5fs.superblock.free_inodes_count, free_inodes_count);
+
+    e5fs.create_file("/c").unwrap();
+    e5fs.remove_file("/c").unwrap();
+    e5fs.disable_trash().unwrap();
+    assert!(e5fs.lookup_path(&format!("/{TRASH_DIR_NAME}")).is_err());
+    // /.trash itself is released too
+    assert_eq!(e5fs.superblock.free_inodes_count, free_inodes_count + 1);
+    assert!(fsck::check(&mut e5fs, false).unwrap().is_clean());
+  }
+}
+
+// vim:ts=2 sw=2
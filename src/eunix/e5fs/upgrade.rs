@@ -0,0 +1,250 @@
+use super::{E5FSFilesystem, BLOCK_MAP_FORMAT_VERSION, FORMAT_VERSION, SUPERBLOCK_BACKUP_ADDRESSES};
+use crate::eunix::fs::FileModeType;
+use crate::eunix::kernel::Errno;
+
+impl E5FSFilesystem {
+  /// Bring filesystem of older format version to `FORMAT_VERSION`,
+  /// one version at a time. Every step is made of transactions that
+  /// each leave filesystem readable by both versions, so it fits in
+  /// the journal, and version goes up in the last one. Filesystem
+  /// stays at version that it can't be upgraded from, see `map_blocks`.
+  /// Returns: whether it was upgraded
+  ///
+  /// Errors:
+  /// EROFS -> filesystem is read-only
+  pub fn upgrade(&mut self) -> Result<bool, Errno> {
+    let format_version = self.superblock.format_version;
+    if format_version >= FORMAT_VERSION {
+      return Ok(false);
+    }
+    if self.read_only {
+      return Err(Errno::EROFS(String::from("e5fs: cannot upgrade read-only filesystem").into()));
+    }
+
+    while self.superblock.format_version < FORMAT_VERSION {
+      let upgraded = match self.superblock.format_version {
+        0 => {
+          self.hash_directories()?;
+          self.transaction(|e5fs| e5fs.write_format_version(1))?;
+          true
+        },
+        1 => self.map_blocks()?,
+        version => unreachable!("e5fs: there is no upgrade from format version {version}"),
+      };
+      if !upgraded {
+        break;
+      }
+    }
+
+    Ok(self.superblock.format_version > format_version)
+  }
+
+  fn write_format_version(&mut self, format_version: u32) -> Result<(), Errno> {
+    self.superblock.format_version = format_version;
+    self.write_superblock(&self.superblock.clone())?;
+    self.update_superblock_backups()
+  }
+
+  /// 0 -> 1: write every directory that is one list of entries as
+  /// hashed one, each in a transaction of its own. Free counts need
+  /// nothing, mount has counted them and superblock gets them when
+  /// it is written
+  fn hash_directories(&mut self) -> Result<(), Errno> {
+    for inode_number in 0..self.fs_info.inodes_count {
+      let inode = self.read_inode(inode_number)?;
+      if super::fsck::is_free(&inode)
+        || inode.mode.file_type() != FileModeType::Dir as u8
+        || self.buckets_count_i(inode_number)?.is_some()
+      {
+        continue;
+      }
+
+      self.transaction(|e5fs| {
+        let dir = e5fs.read_as_dir_i(inode_number)?;
+        e5fs.write_dir_i(&dir, inode_number)
+      })?;
+    }
+
+    Ok(())
+  }
+
+  /// 1 -> 2: write `fbl` as bitmap of free blocks and table of
+  /// references, which take less room, so blocks past them that
+  /// it took are free. Layout changes with version, so it is one
+  /// transaction.
+  /// Returns: whether it was done. It is not if new `fbl` doesn't
+  /// fit in the journal, filesystem works as it is at version 1
+  fn map_blocks(&mut self) -> Result<bool, Errno> {
+    let superblocks_count = 1 + SUPERBLOCK_BACKUP_ADDRESSES.len();
+    let extents = std::iter::once(self.fs_info.blocks_needed_for_block_map(self.fs_info.blocks_count) * self.fs_info.block_size)
+      .chain(std::iter::repeat(self.fs_info.superblock_size).take(superblocks_count))
+      .map(u64::from);
+    if !self.fs_info.realfile.read().unwrap().fits(extents) {
+      tracing::info!("e5fs: bitmap of free blocks doesn't fit in journal, staying at format version 1");
+      return Ok(false);
+    }
+
+    // What makes no sense in `fbl` is left for fsck, block is not free
+    let mut references = self.read_references()?
+      .into_iter()
+      .map(|references| references.unwrap_or(1))
+      .collect::<Vec<_>>();
+    let old_first_fbl_block_number = self.fs_info.first_fbl_block_number;
+    let (inodes_count, blocks_count) = (self.fs_info.inodes_count, self.fs_info.blocks_count);
+
+    let result = self.transaction(|e5fs| {
+      e5fs.fs_info.format_version = BLOCK_MAP_FORMAT_VERSION;
+      e5fs.fs_info
+        .set_counts(inodes_count, blocks_count)
+        .or_else(|message| Err(Errno::EINVAL(format!("e5fs: {message}").into())))?;
+
+      let first_fbl_block_number = e5fs.fs_info.first_fbl_block_number;
+      references.resize(first_fbl_block_number as usize, 0);
+      e5fs.write_references(references)?;
+      e5fs.superblock.free_blocks_count += first_fbl_block_number - old_first_fbl_block_number;
+      e5fs.superblock.first_fbl_block_number = first_fbl_block_number;
+      e5fs.write_format_version(BLOCK_MAP_FORMAT_VERSION)
+    });
+    if result.is_err() {
+      self.fs_info.format_version = 1;
+      self.fs_info
+        .set_counts(inodes_count, blocks_count)
+        .expect("filesystem was laid out like that");
+    }
+
+    result.map(|_| true)
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::{Arc, RwLock};
+
+  use super::*;
+  use crate::testing::{memory_e5fs, E5FS_SIZE};
+  use crate::eunix::devices::MemoryStorage;
+  use crate::eunix::e5fs::{fsck, hashdir, LEGACY_SUPERBLOCK_SIZE};
+  use crate::eunix::fs::Filesystem;
+
+  /// Lay `fbl` out as entry of every block, like version 1 does
+  fn write_block_list(e5fs: &mut E5FSFilesystem) {
+    let references = e5fs.read_references().unwrap()
+      .into_iter()
+      .map(Option::unwrap)
+      .collect::<Vec<_>>();
+    let (inodes_count, blocks_count) = (e5fs.fs_info.inodes_count, e5fs.fs_info.blocks_count);
+    e5fs.fs_info.format_version = 1;
+    e5fs.fs_info.set_counts(inodes_count, blocks_count).unwrap();
+
+    let first_fbl_block_number = e5fs.fs_info.first_fbl_block_number;
+    assert!(references[first_fbl_block_number as usize..].iter().all(|&references| references == 0));
+    e5fs.write_references(references[..first_fbl_block_number as usize].to_vec()).unwrap();
+    e5fs.superblock.free_blocks_count -= e5fs.superblock.first_fbl_block_number - first_fbl_block_number;
+    e5fs.superblock.first_fbl_block_number = first_fbl_block_number;
+    e5fs.superblock.format_version = 1;
+    e5fs.write_superblock(&e5fs.superblock.clone()).unwrap();
+  }
+
+  #[test]
+  fn old_filesystem_is_upgraded_on_mount() {
+    // Journal of it is 32K, less than all directories take
+    let (mut e5fs, buffer) = memory_e5fs(512 * 1024);
+    let etc = e5fs.create_dir("/etc").unwrap().number;
+    e5fs.create_file("/etc/motd").unwrap();
+    let dirs = (0..12)
+      .map(|number| e5fs.create_dir(&format!("/etc/{number}")).unwrap().number)
+      .collect::<Vec<_>>();
+
+    // What filesystem made before versions looks like
+    for &number in &dirs {
+      let dir = e5fs.read_as_dir_i(number).unwrap();
+      e5fs.write_data_i(hashdir::entries_to_bytes(dir.entries.values()), number, false).unwrap();
+    }
+    let dir = e5fs.read_as_dir_i(etc).unwrap();
+    e5fs.write_data_i(hashdir::entries_to_bytes(dir.entries.values()), etc, false).unwrap();
+    write_block_list(&mut e5fs);
+    e5fs.superblock.format_version = 0;
+    e5fs.superblock.free_blocks_count = 0;
+    e5fs.write_superblock(&e5fs.superblock.clone()).unwrap();
+    drop(e5fs);
+
+    let mut e5fs = E5FSFilesystem::from_storage_read_only(Box::new(MemoryStorage::new(buffer.clone())), None).unwrap();
+    assert_eq!(e5fs.superblock.format_version, 0);
+    assert!(e5fs.lookup_path("/etc/motd").is_ok());
+    assert!(matches!(e5fs.upgrade(), Err(Errno::EROFS(_))));
+    drop(e5fs);
+
+    let mut e5fs = E5FSFilesystem::from_storage(Box::new(MemoryStorage::new(buffer.clone()))).unwrap();
+    assert_eq!(e5fs.superblock.format_version, FORMAT_VERSION);
+    assert_eq!(e5fs.buckets_count_i(etc).unwrap(), Some(1));
+    assert_eq!(e5fs.read_as_dir_i(etc).unwrap(), dir);
+    assert!(dirs.iter().all(|&number| e5fs.buckets_count_i(number).unwrap() == Some(1)));
+    assert!(fsck::check(&mut e5fs, false).unwrap().is_clean());
+    assert!(!e5fs.upgrade().unwrap());
+  }
+
+  #[test]
+  fn block_list_is_upgraded_to_block_map() {
+    // Big enough for block list to take more blocks than block map
+    let (mut e5fs, buffer) = memory_e5fs(16 * E5FS_SIZE);
+    e5fs.create_dir("/etc").unwrap();
+    e5fs.create_file("/etc/motd").unwrap();
+    e5fs.write_file("/etc/motd", b"hello\n").unwrap();
+    e5fs.create_snapshot("before").unwrap();
+    e5fs.create_file("/etc/hostname").unwrap();
+    e5fs.unshare_inode("/etc/motd").unwrap();
+    let references = e5fs.read_references().unwrap();
+    assert!(references.iter().any(|&references| references > Some(1)));
+    let (first_fbl_block_number, free_blocks_count) = (e5fs.superblock.first_fbl_block_number, e5fs.superblock.free_blocks_count);
+    write_block_list(&mut e5fs);
+    assert!(e5fs.superblock.first_fbl_block_number < first_fbl_block_number);
+    assert_eq!(e5fs.read_references().unwrap(), references[..e5fs.superblock.first_fbl_block_number as usize]);
+    drop(e5fs);
+
+    let mut e5fs = E5FSFilesystem::from_storage(Box::new(MemoryStorage::new(buffer))).unwrap();
+    assert_eq!(e5fs.superblock.format_version, FORMAT_VERSION);
+    assert_eq!(e5fs.superblock.first_fbl_block_number, first_fbl_block_number);
+    assert_eq!(e5fs.superblock.free_blocks_count, free_blocks_count);
+    assert_eq!(e5fs.read_references().unwrap(), references);
+    assert!(fsck::check(&mut e5fs, false).unwrap().is_clean());
+    e5fs.delete_snapshot("before").unwrap();
+    assert_eq!(e5fs.read_file("/etc/motd", 0).unwrap(), b"hello\n");
+    assert!(fsck::check(&mut e5fs, false).unwrap().is_clean());
+  }
+
+  #[test]
+  fn newer_filesystem_is_not_mounted() {
+    let (mut e5fs, buffer) = memory_e5fs(E5FS_SIZE);
+    e5fs.superblock.format_version = FORMAT_VERSION + 1;
+    e5fs.write_superblock(&e5fs.superblock.clone()).unwrap();
+    drop(e5fs);
+
+    let result = E5FSFilesystem::from_storage(Box::new(MemoryStorage::new(buffer)));
+    assert!(matches!(result, Err(Errno::EINVAL(_))));
+  }
+
+  #[test]
+  fn filesystem_with_legacy_superblock_is_mounted() {
+    // Made by mkfs of before superblock had uuid, label and
+    // state, with 512 byte blocks and "hello\n" in /etc/motd
+    let buffer = Arc::new(RwLock::new(include_bytes!("testdata/legacy.enxvd").to_vec()));
+
+    let mut e5fs = E5FSFilesystem::from_storage(Box::new(MemoryStorage::new(buffer.clone()))).unwrap();
+    assert_eq!(e5fs.fs_info.superblock_size, LEGACY_SUPERBLOCK_SIZE);
+    assert_eq!(e5fs.fs_info.journal_size, 0);
+    assert_eq!(e5fs.superblock.format_version, FORMAT_VERSION);
+    assert_eq!(e5fs.read_file("/etc/motd", 0).unwrap(), b"hello\n");
+    assert!(matches!(e5fs.set_label("home"), Err(Errno::ENOTSUP(_))));
+    e5fs.write_file("/etc/motd", b"bye\n").unwrap();
+    assert!(fsck::check(&mut e5fs, false).unwrap().is_clean());
+    drop(e5fs);
+
+    // Root inode right after superblock is not clobbered by it
+
+    let mut e5fs = E5FSFilesystem::from_storage_read_only(Box::new(MemoryStorage::new(buffer)), None).unwrap();
+    assert_eq!(e5fs.read_file("/etc/motd", 0).unwrap(), b"bye\n");
+    assert!(fsck::check(&mut e5fs, false).unwrap().is_clean());
+  }
+}
+
+// vim:ts=2 sw=2
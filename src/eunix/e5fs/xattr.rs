@@ -0,0 +1,120 @@
+use std::collections::BTreeMap;
+
+use super::{Block, E5FSFilesystem};
+use crate::eunix::fs::{AddressSize, NO_ADDRESS};
+use crate::eunix::kernel::Errno;
+
+/// Extended attributes of inode by name, kept in its spill block
+/// (`INode::xattr_block_number`) as count (u32), then name length
+/// (u8), value length (u16), name and value of every attribute.
+/// Inode without them has no block
+pub type Xattrs = BTreeMap<String, Vec<u8>>;
+
+impl E5FSFilesystem {
+  /// Errors:
+  /// EILSEQ -> spill block is out of range or can't be parsed
+  pub(super) fn read_xattrs_i(&self, inode_number: AddressSize) -> Result<Xattrs, Errno> {
+    let block_number = self.read_inode(inode_number)?.xattr_block_number;
+    if block_number == NO_ADDRESS {
+      return Ok(Xattrs::new());
+    }
+    if block_number >= self.fs_info.first_fbl_block_number {
+      return Err(Errno::EILSEQ(format!("e5fs: inode {inode_number} has attributes in block {block_number}, which is out of range").into()));
+    }
+
+    parse_xattrs(&self.read_block(block_number)?.data)
+  }
+
+  /// Put `xattrs` into spill block of inode, claiming it if there was
+  /// none, or releasing it if `xattrs` are empty. Block shared with
+  /// a snapshot is not written over, inode gets a copy instead
+  ///
+  /// Errors:
+  /// ENOSPC -> `xattrs` don't fit in a block, or there are no free ones
+  pub(super) fn write_xattrs_i(&mut self, inode_number: AddressSize, xattrs: &Xattrs) -> Result<(), Errno> {
+    let data = xattrs_to_bytes(xattrs)?;
+    if data.len() as AddressSize > self.fs_info.block_data_size {
+      return Err(Errno::ENOSPC(format!("e5fs: attributes of inode {inode_number} don't fit in a block").into()));
+    }
+
+    let mut inode = self.read_inode(inode_number)?;
+    if inode.xattr_block_number != NO_ADDRESS
+      && (xattrs.is_empty() || self.block_references(inode.xattr_block_number)? > 1)
+    {
+      self.release_block(inode.xattr_block_number)?;
+      inode.xattr_block_number = NO_ADDRESS;
+    }
+    if !xattrs.is_empty() {
+      if inode.xattr_block_number == NO_ADDRESS {
+        inode.xattr_block_number = self.claim_free_block()?;
+      }
+      self.write_block(&Block { data }, inode.xattr_block_number)?;
+    }
+    inode.ctime = self.clock.now();
+
+    self.write_inode(&inode, inode_number)
+  }
+}
+
+fn xattrs_to_bytes(xattrs: &Xattrs) -> Result<Vec<u8>, Errno> {
+  let mut bytes = Vec::new();
+  bytes.extend((xattrs.len() as u32).to_le_bytes());
+  for (name, value) in xattrs {
+    let name_len = u8::try_from(name.len())
+      .or(Err(Errno::ENAMETOOLONG(format!("e5fs: attribute name can't be longer than {}", u8::MAX).into())))?;
+    let value_len = u16::try_from(value.len())
+      .or(Err(Errno::E2BIG(format!("e5fs: attribute value can't be longer than {}", u16::MAX).into())))?;
+    bytes.extend(name_len.to_le_bytes());
+    bytes.extend(value_len.to_le_bytes());
+    bytes.extend(name.as_bytes());
+    bytes.extend(value);
+  }
+
+  Ok(bytes)
+}
+
+fn parse_xattrs(mut data: &[u8]) -> Result<Xattrs, Errno> {
+  // Block may be damaged, so taking past its end is an error
+  let mut take = |count: usize, what: &str| -> Result<&[u8], Errno> {
+    if data.len() < count {
+      return Err(Errno::EILSEQ(format!("e5fs: can't parse attribute {what}").into()));
+    }
+    let (taken, rest) = data.split_at(count);
+    data = rest;
+    Ok(taken)
+  };
+
+  let count = u32::from_le_bytes(take(4, "count")?.try_into().unwrap());
+  let mut xattrs = Xattrs::new();
+  for _ in 0..count {
+    let name_len = take(1, "name length")?[0] as usize;
+    let value_len = u16::from_le_bytes(take(2, "value length")?.try_into().unwrap()) as usize;
+    let name = String::from_utf8(take(name_len, "name")?.to_owned())
+      .or(Err(Errno::EILSEQ(String::from("e5fs: can't parse attribute name").into())))?;
+    let value = take(value_len, "value")?.to_owned();
+    xattrs.insert(name, value);
+  }
+
+  Ok(xattrs)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn xattrs_survive_bytes() {
+    let xattrs = Xattrs::from([
+      (String::from("user.comment"), b"hello".to_vec()),
+      (String::from("system.posix_acl_access"), vec![0, 1, 2, 255]),
+      (String::from("user.empty"), Vec::new()),
+    ]);
+    let mut bytes = xattrs_to_bytes(&xattrs).unwrap();
+    assert_eq!(parse_xattrs(&bytes).unwrap(), xattrs);
+
+    bytes.truncate(bytes.len() - 1);
+    assert!(matches!(parse_xattrs(&bytes), Err(Errno::EILSEQ(_))));
+  }
+}
+
+// vim:ts=2 sw=2
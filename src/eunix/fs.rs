@@ -1,11 +1,12 @@
-use std::{collections::BTreeMap, any::Any, str::FromStr};
+use std::{collections::BTreeMap, any::Any, str::FromStr, sync::{Arc, RwLock}};
 use core::fmt::{Debug, self};
 use fancy_regex::Regex;
 use itertools::Itertools;
+use serde::{Serialize, Deserialize};
 
-use crate::{util::{fixedpoint, unixtime, self}, binaries::PASSWD_PATH};
+use crate::util::{fixedpoint, unixtime};
 
-use super::{kernel::{Errno, UnixtimeSize, Times, KERNEL_MESSAGE_HEADER_ERR, ROOT_UID, ROOT_GID}, users::Passwd};
+use super::{clock::Clock, kernel::{Errno, ErrnoContext, UnixtimeSize, Times, KERNEL_MESSAGE_HEADER_ERR, ROOT_UID}, devfs::DeviceFilesystem, devices::{CharDevice, CONTROLLING_TTY_RDEV}, watch::{Watches, IN_CREATE, IN_MODIFY, IN_DELETE}};
 
 pub type AddressSize = u32;
 pub type Id = u16;
@@ -19,6 +20,20 @@ pub const NO_ADDRESS: AddressSize = AddressSize::MAX;
 pub const EVERYTHING: AddressSize = AddressSize::MAX;
 pub const NOBODY_UID: Id = Id::MAX;
 pub const NOBODY_GID: Id = Id::MAX;
+/// Most symbolic links followed in one path before giving up with ELOOP,
+/// same as Linux
+pub const MAX_SYMLINK_HOPS: usize = 40;
+/// Longest name of file in bytes, which is all that fits in
+/// `name_len` of e5fs directory entry, same as Linux
+pub const NAME_MAX: usize = 255;
+/// Size of buffer for pathname in bytes, same as Linux. Pathname
+/// has to be shorter, as there is room for terminating NUL in it
+pub const PATH_MAX: usize = 4096;
+/// Namespaces of names of extended attributes, like `user.comment`.
+/// Anybody who may write a file may set its `user` ones, the rest
+/// are set by root only and `trusted` ones are not seen by others.
+/// `system` is where ACLs go
+pub const XATTR_NAMESPACES: [&str; 4] = ["user", "trusted", "security", "system"];
 
 enum Devtype {
   File  = 0b000,
@@ -26,28 +41,41 @@ enum Devtype {
   Sys   = 0b010,
   Block = 0b011,
   Char  = 0b100,
+  Symlink = 0b101,
 }
 
 pub const PERM_R: u8 = 0b100;
 pub const PERM_W: u8 = 0b010;
 pub const PERM_X: u8 = 0b001;
 
+/// Positions of fields of `FileMode`, from the least significant bit
+const OTHERS_SHIFT: u16 = 0;
+const GROUP_SHIFT: u16 = 3;
+const USER_SHIFT: u16 = 6;
+const FILE_TYPE_SHIFT: u16 = 9;
+const STICKY_SHIFT: u16 = 12;
+const SETGID_SHIFT: u16 = 13;
+const SETUID_SHIFT: u16 = 14;
+const FREE_SHIFT: u16 = 15;
+
 //    free?
-///   | unused
-///   | |   filetype
-///   | |   |   user
-///   | |   |   |   group
-///   | |   |   |   |   others
-///   | |   |   |   |   |
-///   f xxx ttt rwx rwx rwx
+///   | suid
+///   | |sgid
+///   | ||sticky
+///   | |||  filetype
+///   | |||  |   user
+///   | |||  |   |   group
+///   | |||  |   |   |   others
+///   | |||  |   |   |   |
+///   f ugs ttt rwx rwx rwx
 /// 0b0_000_000_110_000_000
 /// Where:
 /// filetype:
 ///   000 - file   100 - char
-///   001 - dir    101 - unused
+///   001 - dir    101 - symlink
 ///   010 - sys    110 - unused
 ///   011 - block  111 - unused
-#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Serialize, Deserialize)]
 pub struct FileMode(pub u16);
 pub enum FileModeType {
   File = 0b000,
@@ -55,6 +83,7 @@ pub enum FileModeType {
   Sys = 0b010,
   Block = 0b011,
   Char = 0b100,
+  Symlink = 0b101,
 }
 
 impl fmt::Display for FileModeType {
@@ -65,6 +94,7 @@ impl fmt::Display for FileModeType {
       FileModeType::Sys => write!(f, "system special"),
       FileModeType::Block => write!(f, "block device"),
       FileModeType::Char => write!(f, "character special"),
+      FileModeType::Symlink => write!(f, "symbolic link"),
     }
   }
 }
@@ -80,7 +110,8 @@ impl TryFrom<u8> for FileModeType {
       x if x == FileModeType::Sys as u8 => Ok(FileModeType::Sys),
       x if x == FileModeType::Block as u8 => Ok(FileModeType::Block),
       x if x == FileModeType::Char as u8 => Ok(FileModeType::Char),
-      _ => Err(Errno::EINVAL(format!("cannot convert raw file type to enum: this error should not occur, bruh"))),
+      x if x == FileModeType::Symlink as u8 => Ok(FileModeType::Symlink),
+      _ => Err(Errno::EINVAL(format!("cannot convert raw file type to enum: this error should not occur, bruh").into())),
     }
   }
 }
@@ -104,88 +135,86 @@ impl FileMode {
     Self(0b0000000_000_000_000)
   }
 
+  /// Returns: `width` bits of mode starting at bit `shift`
+  fn bits(&self, shift: u16, width: u16) -> u8 {
+    (self.0 >> shift & ((1 << width) - 1)) as u8
+  }
+
+  /// Returns: mode with `width` bits at `shift` set to `value`.
+  /// Bits of `value` that don't fit are dropped
+  fn with_bits(&self, shift: u16, width: u16, value: u8) -> Self {
+    let mask = ((1 << width) - 1) << shift;
+
+    Self(self.0 & !mask | (value as u16) << shift & mask)
+  }
+
   pub fn free(&self) -> u8 {
-    let mut current = format!("{:016b}", self.0);
+    self.bits(FREE_SHIFT, 1)
+  }
 
-    u8::from_str_radix(&current[0..1], 2).expect(&format!("can't parse in free: {}", &current))
+  pub fn setuid(&self) -> bool {
+    self.bits(SETUID_SHIFT, 1) == 1
   }
-  
-  pub fn file_type(&self) -> u8 {
-    let mut current = format!("{:016b}", self.0);
 
-    u8::from_str_radix(&current[4..7], 2).expect(&format!("can't parse in type: {}", &current))
+  pub fn setgid(&self) -> bool {
+    self.bits(SETGID_SHIFT, 1) == 1
   }
 
-  pub fn user(&self) -> u8 {
-    let mut current = format!("{:016b}", self.0);
+  pub fn sticky(&self) -> bool {
+    self.bits(STICKY_SHIFT, 1) == 1
+  }
 
-    u8::from_str_radix(&current[7..10], 2).expect(&format!("can't parse in user: {}", &current))
+  pub fn file_type(&self) -> u8 {
+    self.bits(FILE_TYPE_SHIFT, 3)
   }
 
-  pub fn group(&self) -> u8 {
-    let mut current = format!("{:016b}", self.0);
+  pub fn user(&self) -> u8 {
+    self.bits(USER_SHIFT, 3)
+  }
 
-    u8::from_str_radix(&current[10..13], 2).expect(&format!("can't parse in group: {}", &current))
+  pub fn group(&self) -> u8 {
+    self.bits(GROUP_SHIFT, 3)
   }
 
   pub fn others(&self) -> u8 {
-    let mut current = format!("{:016b}", self.0);
-    
-    u8::from_str_radix(&current[13..16], 2).expect(&format!("can't parse in others: {}", &current))
+    self.bits(OTHERS_SHIFT, 3)
   }
 
   pub fn with_free(&self, mask: u8) -> Self {
-    let mut current = format!("{:016b}", self.0);
-    let mask = format!("{:01b}", mask);
+    self.with_bits(FREE_SHIFT, 1, mask)
+  }
 
-    current.replace_range(0..1, &mask);
-    Self(u16::from_str_radix(&current, 2).expect(&format!("can't parse in free: {}", &current)))
+  pub fn with_setuid(&self, setuid: bool) -> Self {
+    self.with_bits(SETUID_SHIFT, 1, setuid as u8)
   }
-  
-  pub fn with_file_type(&self, mask: u8) -> Self {
-    let mut current = format!("{:016b}", self.0);
-    let mask = format!("{:03b}", mask);
 
-    current.replace_range(4..7, &mask);
-    Self(u16::from_str_radix(&current, 2).expect(&format!("can't parse in type: {}", &current)))
+  pub fn with_setgid(&self, setgid: bool) -> Self {
+    self.with_bits(SETGID_SHIFT, 1, setgid as u8)
   }
 
-  pub fn with_user(&self, mask: u8) -> Self {
-    let mut current = format!("{:016b}", self.0);
-    let mask = format!("{:03b}", mask);
+  pub fn with_sticky(&self, sticky: bool) -> Self {
+    self.with_bits(STICKY_SHIFT, 1, sticky as u8)
+  }
 
-    current.replace_range(7..10, &mask);
-    Self(u16::from_str_radix(&current, 2).expect(&format!("can't parse in user: {}", &current)))
+  pub fn with_file_type(&self, mask: u8) -> Self {
+    self.with_bits(FILE_TYPE_SHIFT, 3, mask)
   }
 
-  pub fn with_group(&self, mask: u8) -> Self {
-    let mut current = format!("{:016b}", self.0);
-    let mask = format!("{:03b}", mask);
+  pub fn with_user(&self, mask: u8) -> Self {
+    self.with_bits(USER_SHIFT, 3, mask)
+  }
 
-    current.replace_range(10..13, &mask);
-    Self(u16::from_str_radix(&current, 2).expect(&format!("can't parse in group: {}", &current)))
+  pub fn with_group(&self, mask: u8) -> Self {
+    self.with_bits(GROUP_SHIFT, 3, mask)
   }
 
   pub fn with_others(&self, mask: u8) -> Self {
-    let mut current = format!("{:016b}", self.0);
-    let mask = format!("{:03b}", mask);
-    
-    current.replace_range(13..16, &mask);
-    Self(u16::from_str_radix(&current, 2).expect(&format!("can't parse in others: {}", &current)))
+    self.with_bits(OTHERS_SHIFT, 3, mask)
   }
 
   pub fn get_raw(&self) -> u16 {
     self.0
   }
-
-  /// gets the bit at position `n`. Bits are numbered from 0 (least significant) to 31 (most significant).
-  fn get_bit_at(input: u32, n: u8) -> bool {
-    if n < 32 {
-      input & (1 << n) != 0
-    } else {
-      false
-    }
-  }
 }
 
 impl std::ops::Add for FileMode {
@@ -198,6 +227,42 @@ impl std::ops::Add for FileMode {
 
 pub type FileDescriptor = AddressSize;
 
+/// Major and minor numbers of a device, like `dev_t`:
+/// major identifies the driver, minor - the device instance
+#[derive(Debug, Default, PartialEq, Eq, Clone, Copy, PartialOrd, Ord, Serialize, Deserialize)]
+pub struct DeviceNumber {
+  pub major: u16,
+  pub minor: u16,
+}
+
+impl DeviceNumber {
+  pub const fn new(major: u16, minor: u16) -> Self {
+    Self {
+      major,
+      minor,
+    }
+  }
+
+  /// From on-disk representation: `major << 16 | minor`
+  pub fn from_raw(raw: AddressSize) -> Self {
+    Self {
+      major: (raw >> 16) as u16,
+      minor: (raw & 0xffff) as u16,
+    }
+  }
+
+  /// To on-disk representation: `major << 16 | minor`
+  pub fn to_raw(&self) -> AddressSize {
+    (self.major as AddressSize) << 16 | self.minor as AddressSize
+  }
+}
+
+impl fmt::Display for DeviceNumber {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}, {}", self.major, self.minor)
+  }
+}
+
 /// struct stat {
 ///   dev_t     st_dev;         /* ID of device containing file */
 ///   ino_t     st_ino;         /* Inode number */
@@ -222,7 +287,7 @@ pub type FileDescriptor = AddressSize;
 ///   #define st_mtime st_mtim.tv_sec
 ///   #define st_ctime st_ctim.tv_sec
 /// };
-#[derive(Debug)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct FileStat {
   pub mode: FileMode,
   pub size: AddressSize,
@@ -230,6 +295,8 @@ pub struct FileStat {
   pub links_count: AddressSize,
   pub uid: u16,
   pub gid: u16,
+  /// Device number (if special file)
+  pub rdev: DeviceNumber,
   pub block_size: AddressSize,
   pub atime: UnixtimeSize,
   pub mtime: UnixtimeSize,
@@ -237,6 +304,25 @@ pub struct FileStat {
   pub btime: UnixtimeSize,
 }
 
+/// struct statfs {
+///   __fsword_t f_bsize;    /* Optimal transfer block size */
+///   fsblkcnt_t f_blocks;   /* Total data blocks in filesystem */
+///   fsblkcnt_t f_bfree;    /* Free blocks in filesystem */
+///   fsfilcnt_t f_files;    /* Total inodes in filesystem */
+///   fsfilcnt_t f_ffree;    /* Free inodes in filesystem */
+///   ...
+/// };
+#[derive(Debug, Default, Clone, Copy, PartialEq, Eq)]
+pub struct FilesystemStat {
+  pub block_size: AddressSize,
+  pub blocks_count: AddressSize,
+  pub free_blocks_count: AddressSize,
+  /// Free blocks that only root can claim
+  pub reserved_blocks_count: AddressSize,
+  pub inodes_count: AddressSize,
+  pub free_inodes_count: AddressSize,
+}
+
 #[derive(Debug, Clone, Copy)]
 pub enum OpenMode {
   Read,
@@ -249,6 +335,10 @@ pub struct OpenFlags {
   pub mode: OpenMode,
   pub create: bool,
   pub append: bool,
+  /// Empty regular file on open
+  pub truncate: bool,
+  /// Descriptor is not inherited by spawned processes
+  pub close_on_exec: bool,
 }
 impl OpenFlags {
   pub fn mode(&self) -> OpenMode {
@@ -260,12 +350,20 @@ impl OpenFlags {
   pub fn append(&self) -> bool {
     self.append
   }
+  pub fn truncate(&self) -> bool {
+    self.truncate
+  }
+  pub fn close_on_exec(&self) -> bool {
+    self.close_on_exec
+  }
 
   pub fn new(mode: OpenMode, create: bool, append: bool) -> Self {
     Self {
       mode,
       create,
       append,
+      truncate: false,
+      close_on_exec: false,
     }
   }
   pub fn with_mode(mut self, mode: OpenMode) -> Self {
@@ -280,6 +378,14 @@ impl OpenFlags {
     self.append = append;
     self
   }
+  pub fn with_truncate(mut self, truncate: bool) -> Self {
+    self.truncate = truncate;
+    self
+  }
+  pub fn with_close_on_exec(mut self, close_on_exec: bool) -> Self {
+    self.close_on_exec = close_on_exec;
+    self
+  }
 }
 
 #[derive(Debug, PartialEq, Eq)]
@@ -328,6 +434,8 @@ pub struct VINode {
   pub ctime: UnixtimeSize,
   /// Birth (creation) time (non-standard)
   pub btime: UnixtimeSize,
+  /// Device number (if special file)
+  pub rdev: DeviceNumber,
   /// Inode number 
   pub number: AddressSize,
 }
@@ -350,12 +458,19 @@ impl VINode {
       mtime: unixtime(),
       ctime: unixtime(),
       btime: unixtime(),
+      rdev: DeviceNumber::default(),
       number: 0,
     }
   }
+
+  /// Whether inode is a block or character special file
+  pub fn is_device(&self) -> bool {
+    self.mode.file_type() == FileModeType::Block as u8
+      || self.mode.file_type() == FileModeType::Char as u8
+  }
 }
 
-pub trait Filesystem {
+pub trait Filesystem: Send + Sync {
   // Получить count байт из файловой
   // системы по указанному
   // pathname_from_fs_root,
@@ -367,18 +482,127 @@ pub trait Filesystem {
   fn remove_file(&mut self, pathname: &str)
     -> Result<(), Errno>;
 
+  /// Remove name `pathname` of file that is not a directory
+  ///
+  /// Errors:
+  /// EISDIR -> it is a directory, see `rmdir`
+  fn unlink(&mut self, pathname: &str)
+    -> Result<(), Errno> {
+    if self.lookup_path(pathname)?.mode.file_type() == FileModeType::Dir as u8 {
+      return Err(Errno::EISDIR(format!("{}: {pathname}: is a directory", self.name()).into()));
+    }
+
+    self.remove_file(pathname)
+  }
+
+  /// Remove directory at `pathname`, which has to be empty
+  ///
+  /// Errors:
+  /// ENOTDIR   -> it is not a directory
+  /// ENOTEMPTY -> there are files in it
+  fn rmdir(&mut self, pathname: &str)
+    -> Result<(), Errno> {
+    if self.lookup_path(pathname)?.mode.file_type() != FileModeType::Dir as u8 {
+      return Err(Errno::ENOTDIR(format!("{}: {pathname}: not a directory", self.name()).into()));
+    }
+    if self.read_dir(pathname)?.entries.keys().any(|name| name != "." && name != "..") {
+      return Err(Errno::ENOTEMPTY(format!("{}: {pathname}: directory not empty", self.name()).into()));
+    }
+
+    self.remove_file(pathname)
+  }
+
   fn create_dir(&mut self, pathname: &str)
     -> Result<VINode, Errno>;
 
+  /// Create special file of `file_type` (`Block` or `Char`)
+  /// referring to device `device_number`
+  fn mknod(&mut self, pathname: &str, file_type: FileModeType, device_number: DeviceNumber)
+    -> Result<VINode, Errno>;
+
+  /// Make `new` one more name of file at `existing`,
+  /// which can't be a directory
+  fn link(&mut self, existing: &str, new: &str)
+    -> Result<VINode, Errno>;
+
+  /// Make symbolic link at `pathname` pointing to `target`,
+  /// which doesn't have to exist
+  fn symlink(&mut self, target: &str, pathname: &str)
+    -> Result<VINode, Errno>;
+
+  /// Give file at `old` name `new` instead, moving it to another
+  /// directory if needed. File at `new`, if any, is replaced.
+  /// Filesystems that can't do better link it at `new` and remove
+  /// `old`, which works only for files and only if `new` is free
+  ///
+  /// Errors:
+  /// EISDIR    -> `new` is a directory and `old` is not
+  /// ENOTDIR   -> `old` is a directory and `new` is not
+  /// ENOTEMPTY -> `new` is a directory that has files
+  /// EINVAL    -> directory would be moved into itself
+  fn rename(&mut self, old: &str, new: &str)
+    -> Result<(), Errno> {
+    self.link(old, new)?;
+    self.remove_file(old)
+  }
+
+  /// Target of symbolic link at `pathname`
+  fn readlink(&mut self, pathname: &str)
+    -> Result<String, Errno>;
+
   fn read_file(&mut self, pathname: &str, count: AddressSize)
     -> Result<Vec<u8>, Errno>;
 
   fn write_file(&mut self, pathname: &str, data: &[u8])
     -> Result<VINode, Errno>;
 
+  /// Read up to `count` bytes of file at `pathname` from `offset`,
+  /// nothing if `offset` is past the end
+  fn read_at(&mut self, pathname: &str, offset: AddressSize, count: AddressSize)
+    -> Result<Vec<u8>, Errno> {
+    let contents = self.read_file(pathname, EVERYTHING)?;
+    Ok(contents.into_iter().skip(offset as usize).take(count as usize).collect())
+  }
+
+  /// Write `data` over file at `pathname` from `offset`, growing it
+  /// if needed. Filesystems that can't do better read the whole file
+  /// and write it back
+  fn write_at(&mut self, pathname: &str, offset: AddressSize, data: &[u8])
+    -> Result<VINode, Errno> {
+    let mut contents = self.read_file(pathname, EVERYTHING)?;
+    splice_at(&mut contents, offset, data);
+    self.write_file(pathname, &contents)
+  }
+
+  /// Make file at `pathname` `size` bytes long, cutting it or
+  /// filling it with zeros
+  fn truncate(&mut self, pathname: &str, size: AddressSize)
+    -> Result<VINode, Errno> {
+    let mut contents = self.read_file(pathname, EVERYTHING)?;
+    contents.resize(size as usize, 0);
+    self.write_file(pathname, &contents)
+  }
+
+  /// Write `data` past the end of file at `pathname`
+  fn append_file(&mut self, pathname: &str, data: &[u8])
+    -> Result<VINode, Errno> {
+    let size = self.stat(pathname)?.size;
+    self.write_at(pathname, size, data)
+  }
+
   fn read_dir(&mut self, pathname: &str)
     -> Result<VDirectory, Errno>;
 
+  /// Up to `count` entries of directory at `pathname` from entry
+  /// number `offset`, nothing if it is past the end, so big directory
+  /// is listed a part at a time. Filesystems that can't do better
+  /// read the whole directory
+  fn readdir(&mut self, pathname: &str, offset: AddressSize, count: AddressSize)
+    -> Result<Vec<VDirectoryEntry>, Errno> {
+    let dir = self.read_dir(pathname)?;
+    Ok(dir.entries.into_values().skip(offset as usize).take(count as usize).collect())
+  }
+
   fn stat(&mut self, pathname: &str)
     -> Result<FileStat, Errno>;
 
@@ -391,14 +615,68 @@ pub trait Filesystem {
   fn change_times(&mut self, pathname: &str, times: Times)
     -> Result<(), Errno>;
 
+  /// Value of extended attribute `name` (like `user.comment`)
+  /// of file at `pathname`
+  fn get_xattr(&mut self, pathname: &str, _name: &str)
+    -> Result<Vec<u8>, Errno> {
+    Err(Errno::ENOTSUP(format!("{}: {pathname}: extended attributes are not supported", self.name()).into()))
+  }
+
+  /// Set extended attribute `name` of file at `pathname` to
+  /// `value`, or remove it with `None`
+  fn set_xattr(&mut self, pathname: &str, _name: &str, _value: Option<&[u8]>)
+    -> Result<(), Errno> {
+    Err(Errno::ENOTSUP(format!("{}: {pathname}: extended attributes are not supported", self.name()).into()))
+  }
+
+  /// Names of extended attributes of file at `pathname`
+  fn list_xattr(&mut self, pathname: &str)
+    -> Result<Vec<String>, Errno> {
+    Err(Errno::ENOTSUP(format!("{}: {pathname}: extended attributes are not supported", self.name()).into()))
+  }
+
+  /// Sizes and free space of filesystem that has `pathname`.
+  /// Filesystems that have no blocks of their own report zeros
+  fn statfs(&mut self, _pathname: &str)
+    -> Result<FilesystemStat, Errno> {
+    Ok(FilesystemStat::default())
+  }
+
+  /// Write everything filesystem caches to its device.
+  /// Filesystems without device have nothing to write
+  fn sync(&mut self)
+    -> Result<(), Errno> {
+    Ok(())
+  }
+
   // Поиск файла в файловой системе. Возвращает INode фала.
   // Для VFS сначала матчинг на маунт-поинты и вызов lookup_path("/mount/point") у конкретной файловой системы;
   // Для конкретных реализаций (e5fs) поиск сразу от рута файловой системы
   fn lookup_path(&mut self, pathname: &str)
     -> Result<VINode, Errno>;
 
+  /// Take timestamps from `clock` instead of host time,
+  /// filesystems without timestamps of their own ignore it
+  fn set_clock(&mut self, _clock: Arc<dyn Clock>) {}
+
+  /// Take ids of whoever does operations that come next, filesystems
+  /// that keep blocks for root or pass ids to a server look at them
+  fn set_current_user(&mut self, _uid: Id, _gid: Id) {}
+
   fn name(&self) -> String;
   fn as_any(&mut self) -> &mut dyn Any;
+  fn as_any_ref(&self) -> &dyn Any;
+}
+
+/// Put `data` over `contents` from `offset`, with zeros
+/// between the end of `contents` and `offset`
+pub fn splice_at(contents: &mut Vec<u8>, offset: AddressSize, data: &[u8]) {
+  let offset = offset as usize;
+  let end = offset + data.len();
+  if contents.len() < end {
+    contents.resize(end, 0);
+  }
+  contents[offset..end].copy_from_slice(data);
 }
 
 impl Debug for dyn Filesystem {
@@ -410,132 +688,485 @@ impl Debug for dyn Filesystem {
 impl Filesystem for VFS {
   fn create_file(&mut self, pathname: &str)
     -> Result<VINode, Errno> {
+    // Creating through a dangling link creates its target
+    let pathname = &self.resolve_path(pathname, true)?;
     let parent_vinode = self.lookup_path(&VFS::parent_dir(pathname)?)?;
-    self.permission_check(parent_vinode, PERM_W)?;
+    self.permission_check(parent_vinode, PERM_W | PERM_X)
+      .with_context(|| format!("create_file {pathname}"))?;
 
     let (mount_point, internal_pathname) = self.match_mount_point(pathname)?;
+    self.check_writable(&mount_point).with_context(|| format!("create_file {pathname}"))?;
     let mounted_fs = self.mount_points.get_mut(&mount_point).expect("VFS::create_file: we know that mount_point exist");  
 
-    mounted_fs.driver.create_file(&internal_pathname)?;
-    mounted_fs.driver.change_owners(&pathname, self.current_uid, self.current_gid)?;
+    mounted_fs.driver.create_file(&internal_pathname)
+      .with_context(|| format!("create_file {pathname}"))?;
+    self.watches.notify(pathname, IN_CREATE);
+    mounted_fs.driver.change_owners(&internal_pathname, self.current_uid, self.current_gid)
+      .with_context(|| format!("change_owners {pathname}"))?;
     mounted_fs.driver.lookup_path(&internal_pathname)
+      .with_context(|| format!("lookup_path {pathname}"))
   }
 
   fn remove_file(&mut self, pathname: &str)
     -> Result<(), Errno> {
-    let parent_vinode = self.lookup_path(&VFS::parent_dir(pathname)?)?;
-    // let vinode = self.lookup_path(&VFS::parent_dir(pathname)?)?;
-    self.permission_check(parent_vinode, PERM_W)?;
-    // self.permission_check(vinode, PERM_W)?;
-
-    let (mount_point, internal_pathname) = self.match_mount_point(pathname)?;
+    let (pathname, mount_point, internal_pathname) = self.check_removable(pathname, "remove_file")?;
     let mounted_fs = self.mount_points.get_mut(&mount_point).expect("VFS::remove_file: we know that mount_point exist");  
     mounted_fs.driver.remove_file(&internal_pathname)
+      .with_context(|| format!("remove_file {pathname}"))?;
+    self.watches.notify(&pathname, IN_DELETE);
+
+    Ok(())
+  }
+
+  fn unlink(&mut self, pathname: &str)
+    -> Result<(), Errno> {
+    let (pathname, mount_point, internal_pathname) = self.check_removable(pathname, "unlink")?;
+    let mounted_fs = self.mount_points.get_mut(&mount_point).expect("VFS::unlink: we know that mount_point exist");
+    mounted_fs.driver.unlink(&internal_pathname)
+      .with_context(|| format!("unlink {pathname}"))?;
+    self.watches.notify(&pathname, IN_DELETE);
+
+    Ok(())
+  }
+
+  fn rmdir(&mut self, pathname: &str)
+    -> Result<(), Errno> {
+    let (pathname, mount_point, internal_pathname) = self.check_removable(pathname, "rmdir")?;
+    let mounted_fs = self.mount_points.get_mut(&mount_point).expect("VFS::rmdir: we know that mount_point exist");
+    mounted_fs.driver.rmdir(&internal_pathname)
+      .with_context(|| format!("rmdir {pathname}"))?;
+    self.watches.notify(&pathname, IN_DELETE);
+
+    Ok(())
   }
 
   fn create_dir(&mut self, pathname: &str)
     -> Result<VINode, Errno> {
+    let pathname = &self.resolve_path(pathname, false)?;
     let parent_vinode = self.lookup_path(&VFS::parent_dir(pathname)?)?;
-    self.permission_check(parent_vinode, PERM_W)?;
-    let vinode = self.lookup_path(pathname)?;
+    self.permission_check(parent_vinode, PERM_W | PERM_X)
+      .with_context(|| format!("create_dir {pathname}"))?;
 
     let (mount_point, internal_pathname) = self.match_mount_point(pathname)?;
+    self.check_writable(&mount_point).with_context(|| format!("create_dir {pathname}"))?;
     let mounted_fs = self.mount_points.get_mut(&mount_point).expect("VFS::create_dir: we know that mount_point exist");  
 
-    mounted_fs.driver.create_dir(&internal_pathname)?;
-    mounted_fs.driver.change_mode(&pathname, vinode.mode.with_user(0b111))?;
-    mounted_fs.driver.change_owners(&pathname, self.current_uid, self.current_gid)?;
+    mounted_fs.driver.create_dir(&internal_pathname)
+      .with_context(|| format!("create_dir {pathname}"))?;
+    self.watches.notify(pathname, IN_CREATE);
+    let vinode = mounted_fs.driver.lookup_path(&internal_pathname)
+      .with_context(|| format!("lookup_path {pathname}"))?;
+    mounted_fs.driver.change_mode(&internal_pathname, vinode.mode.with_user(0b111))
+      .with_context(|| format!("change_mode {pathname}"))?;
+    mounted_fs.driver.change_owners(&internal_pathname, self.current_uid, self.current_gid)
+      .with_context(|| format!("change_owners {pathname}"))?;
+    mounted_fs.driver.lookup_path(&internal_pathname)
+      .with_context(|| format!("lookup_path {pathname}"))
+  }
+
+  fn mknod(&mut self, pathname: &str, file_type: FileModeType, device_number: DeviceNumber)
+    -> Result<VINode, Errno> {
+    let pathname = &self.resolve_path(pathname, false)?;
+    let parent_vinode = self.lookup_path(&VFS::parent_dir(pathname)?)?;
+    self.permission_check(parent_vinode, PERM_W | PERM_X)
+      .with_context(|| format!("mknod {pathname}"))?;
+
+    // Guard - only root can create device nodes
+    if self.current_uid != ROOT_UID {
+      return Err(Errno::EPERM(format!("fs::mknod: operation not permitted").into()))
+    }
+
+    let (mount_point, internal_pathname) = self.match_mount_point(pathname)?;
+    self.check_writable(&mount_point).with_context(|| format!("mknod {pathname}"))?;
+    let mounted_fs = self.mount_points.get_mut(&mount_point).expect("VFS::mknod: we know that mount_point exist");  
+
+    mounted_fs.driver.mknod(&internal_pathname, file_type, device_number)
+      .with_context(|| format!("mknod {pathname}"))?;
+    self.watches.notify(pathname, IN_CREATE);
+    mounted_fs.driver.change_owners(&internal_pathname, self.current_uid, self.current_gid)
+      .with_context(|| format!("change_owners {pathname}"))?;
+    mounted_fs.driver.lookup_path(&internal_pathname)
+      .with_context(|| format!("lookup_path {pathname}"))
+  }
+
+  fn link(&mut self, existing: &str, new: &str)
+    -> Result<VINode, Errno> {
+    // Like Linux, link to a symbolic link itself, not to its target
+    let existing = &self.resolve_path(existing, false)?;
+    let new = &self.resolve_path(new, false)?;
+    self.lookup_link(existing)?;
+    let parent_vinode = self.lookup_path(&VFS::parent_dir(new)?)?;
+    self.permission_check(parent_vinode, PERM_W | PERM_X)
+      .with_context(|| format!("link {new}"))?;
+
+    let (mount_point, internal_existing) = self.match_mount_point(existing)?;
+    let (new_mount_point, internal_new) = self.match_mount_point(new)?;
+    // Guard - a name can only be given in the same filesystem
+    if mount_point != new_mount_point {
+      return Err(Errno::EXDEV(format!("link {existing} {new}: {existing} is on {mount_point}, {new} is on {new_mount_point}").into()));
+    }
+    self.check_writable(&mount_point).with_context(|| format!("link {existing} {new}"))?;
+
+    let mounted_fs = self.mount_points.get_mut(&mount_point).expect("VFS::link: we know that mount_point exist");
+    let vinode = mounted_fs.driver.link(&internal_existing, &internal_new)
+      .with_context(|| format!("link {existing} {new}"))?;
+    self.watches.notify(new, IN_CREATE);
+
+    Ok(vinode)
+  }
+
+  fn rename(&mut self, old: &str, new: &str)
+    -> Result<(), Errno> {
+    // Guard for renaming directory by its "." or ".."
+    for pathname in [old, new] {
+      if matches!(pathname.trim_end_matches('/').rsplit('/').next(), Some("." | "..")) {
+        return Err(Errno::EINVAL(format!("rename {old} {new}: cannot rename '.' or '..'").into()));
+      }
+    }
+    // Like Linux, rename a symbolic link itself, not its target
+    let old = &self.resolve_path(old, false)?;
+    let new = &self.resolve_path(new, false)?;
+    let vinode = self.lookup_link(old)?;
+    for pathname in [old, new] {
+      let parent_vinode = self.lookup_path(&VFS::parent_dir(pathname)?)?;
+      self.permission_check(parent_vinode, PERM_W | PERM_X)
+        .with_context(|| format!("rename {old} {new}"))?;
+
+      // Guard for sticky directory, like /tmp: only owners
+      // take files away from it or replace them there
+      let owner = match pathname == old {
+        true => Some(vinode.uid),
+        false => self.lookup_link(new).ok().map(|vinode| vinode.uid),
+      };
+      if let Some(owner) = owner.filter(|_| parent_vinode.mode.sticky()) {
+        if ![owner, parent_vinode.uid, ROOT_UID].contains(&self.current_uid) {
+          return Err(Errno::EPERM(format!("fs::rename: {pathname}: operation not permitted").into()));
+        }
+      }
+    }
+
+    // Guard for mount points themselves
+    if let Some(pathname) = [old, new].into_iter().find(|&pathname| self.mount_points.contains_key(pathname) || self.bind_mounts.contains_key(pathname)) {
+      return Err(Errno::EBUSY(format!("rename {old} {new}: {pathname} is a mount point").into()));
+    }
+    let (mount_point, internal_old) = self.match_mount_point(old)?;
+    let (new_mount_point, internal_new) = self.match_mount_point(new)?;
+    // Guard - a file only moves within its filesystem
+    if mount_point != new_mount_point {
+      return Err(Errno::EXDEV(format!("rename {old} {new}: {old} is on {mount_point}, {new} is on {new_mount_point}").into()));
+    }
+    self.check_writable(&mount_point).with_context(|| format!("rename {old} {new}"))?;
+
+    let mounted_fs = self.mount_points.get_mut(&mount_point).expect("VFS::rename: we know that mount_point exist");
+    mounted_fs.driver.rename(&internal_old, &internal_new)
+      .with_context(|| format!("rename {old} {new}"))?;
+    // Like it was removed from one place and created at the other
+    self.watches.notify(old, IN_DELETE);
+    self.watches.notify(new, IN_CREATE);
+
+    Ok(())
+  }
+
+  fn symlink(&mut self, target: &str, pathname: &str)
+    -> Result<VINode, Errno> {
+    // Guard for target that could not be followed
+    if target.len() >= PATH_MAX {
+      return Err(Errno::ENAMETOOLONG(format!("symlink {pathname}: target must be shorter than {PATH_MAX} bytes").into()));
+    }
+    let pathname = &self.resolve_path(pathname, false)?;
+    let parent_vinode = self.lookup_path(&VFS::parent_dir(pathname)?)?;
+    self.permission_check(parent_vinode, PERM_W | PERM_X)
+      .with_context(|| format!("symlink {pathname}"))?;
+
+    let (mount_point, internal_pathname) = self.match_mount_point(pathname)?;
+    self.check_writable(&mount_point).with_context(|| format!("symlink {pathname}"))?;
+    let mounted_fs = self.mount_points.get_mut(&mount_point).expect("VFS::symlink: we know that mount_point exist");
+
+    mounted_fs.driver.symlink(target, &internal_pathname)
+      .with_context(|| format!("symlink {pathname}"))?;
+    self.watches.notify(pathname, IN_CREATE);
+    mounted_fs.driver.change_owners(&internal_pathname, self.current_uid, self.current_gid)
+      .with_context(|| format!("change_owners {pathname}"))?;
     mounted_fs.driver.lookup_path(&internal_pathname)
+      .with_context(|| format!("lookup_path {pathname}"))
+  }
+
+  fn readlink(&mut self, pathname: &str)
+    -> Result<String, Errno> {
+    let pathname = &self.resolve_path(pathname, false)?;
+    self.readlink_mounted(pathname)
   }
 
   fn read_file(&mut self, pathname: &str, _count: AddressSize)
     -> Result<Vec<u8>, Errno> {
+    let pathname = &self.resolve_path(pathname, true)?;
     let vinode = self.lookup_path(pathname)?;
-    self.permission_check(vinode, PERM_R)?;
+    self.permission_check(vinode, PERM_R)
+      .with_context(|| format!("read_file {pathname}"))?;
 
-    let (mount_point, internal_pathname) = self.match_mount_point(pathname)?;
+    let (mount_point, internal_pathname) = self.match_device_or_mount_point(pathname, vinode)?;
     let mounted_fs = self.mount_points.get_mut(&mount_point).expect("VFS::read_file: we know that mount_point exist");  
     mounted_fs.driver.read_file(&internal_pathname, EVERYTHING)
+      .with_context(|| format!("read_file {pathname}"))
   }
 
   fn write_file(&mut self, pathname: &str, data: &[u8])
     -> Result<VINode, Errno> {
+    let pathname = &self.resolve_path(pathname, true)?;
     let vinode = self.lookup_path(pathname)?;
-    self.permission_check(vinode, PERM_W)?;
+    self.permission_check(vinode, PERM_W)
+      .with_context(|| format!("write_file {pathname}"))?;
 
-    let (mount_point, internal_pathname) = self.match_mount_point(pathname)?;
+    let (mount_point, internal_pathname) = self.match_device_or_mount_point(pathname, vinode)?;
+    self.check_writable(&mount_point).with_context(|| format!("write_file {pathname}"))?;
     let mounted_fs = self.mount_points.get_mut(&mount_point).expect("VFS::write_file: we know that mount_point exist");  
-    mounted_fs.driver.write_file(&internal_pathname, data)
+    let vinode = mounted_fs.driver.write_file(&internal_pathname, data)
+      .with_context(|| format!("write_file {pathname}"))?;
+    self.watches.notify(pathname, IN_MODIFY);
+
+    Ok(vinode)
+  }
+
+  fn read_at(&mut self, pathname: &str, offset: AddressSize, count: AddressSize)
+    -> Result<Vec<u8>, Errno> {
+    let pathname = &self.resolve_path(pathname, true)?;
+    let vinode = self.lookup_path(pathname)?;
+    self.permission_check(vinode, PERM_R)
+      .with_context(|| format!("read_at {pathname}"))?;
+
+    let (mount_point, internal_pathname) = self.match_device_or_mount_point(pathname, vinode)?;
+    let mounted_fs = self.mount_points.get_mut(&mount_point).expect("VFS::read_at: we know that mount_point exist");  
+    mounted_fs.driver.read_at(&internal_pathname, offset, count)
+      .with_context(|| format!("read_at {pathname}"))
+  }
+
+  fn write_at(&mut self, pathname: &str, offset: AddressSize, data: &[u8])
+    -> Result<VINode, Errno> {
+    let pathname = &self.resolve_path(pathname, true)?;
+    let vinode = self.lookup_path(pathname)?;
+    self.permission_check(vinode, PERM_W)
+      .with_context(|| format!("write_at {pathname}"))?;
+
+    let (mount_point, internal_pathname) = self.match_device_or_mount_point(pathname, vinode)?;
+    self.check_writable(&mount_point).with_context(|| format!("write_at {pathname}"))?;
+    let mounted_fs = self.mount_points.get_mut(&mount_point).expect("VFS::write_at: we know that mount_point exist");  
+    let vinode = mounted_fs.driver.write_at(&internal_pathname, offset, data)
+      .with_context(|| format!("write_at {pathname}"))?;
+    self.watches.notify(pathname, IN_MODIFY);
+
+    Ok(vinode)
+  }
+
+  fn truncate(&mut self, pathname: &str, size: AddressSize)
+    -> Result<VINode, Errno> {
+    let pathname = &self.resolve_path(pathname, true)?;
+    let vinode = self.lookup_path(pathname)?;
+    self.permission_check(vinode, PERM_W)
+      .with_context(|| format!("truncate {pathname}"))?;
+
+    let (mount_point, internal_pathname) = self.match_mount_point(pathname)?;
+    self.check_writable(&mount_point).with_context(|| format!("truncate {pathname}"))?;
+    let mounted_fs = self.mount_points.get_mut(&mount_point).expect("VFS::truncate: we know that mount_point exist");  
+    let vinode = mounted_fs.driver.truncate(&internal_pathname, size)
+      .with_context(|| format!("truncate {pathname}"))?;
+    self.watches.notify(pathname, IN_MODIFY);
+
+    Ok(vinode)
+  }
+
+  fn append_file(&mut self, pathname: &str, data: &[u8])
+    -> Result<VINode, Errno> {
+    let pathname = &self.resolve_path(pathname, true)?;
+    let vinode = self.lookup_path(pathname)?;
+    self.permission_check(vinode, PERM_W)
+      .with_context(|| format!("append_file {pathname}"))?;
+
+    let (mount_point, internal_pathname) = self.match_device_or_mount_point(pathname, vinode)?;
+    self.check_writable(&mount_point).with_context(|| format!("append_file {pathname}"))?;
+    let mounted_fs = self.mount_points.get_mut(&mount_point).expect("VFS::append_file: we know that mount_point exist");  
+    let vinode = mounted_fs.driver.append_file(&internal_pathname, data)
+      .with_context(|| format!("append_file {pathname}"))?;
+    self.watches.notify(pathname, IN_MODIFY);
+
+    Ok(vinode)
   }
 
   fn read_dir(&mut self, pathname: &str)
     -> Result<VDirectory, Errno> {
-    // let vinode = self.lookup_path(&VFS::parent_dir(pathname)?)?;
-    // self.permission_check(vinode, PERM_W)?;
+    let pathname = &self.resolve_path(pathname, true)?;
+    let vinode = self.lookup_path(pathname)?;
+    self.permission_check(vinode, PERM_R)
+      .with_context(|| format!("read_dir {pathname}"))?;
 
     let (mount_point, internal_pathname) = self.match_mount_point(pathname)?;
     let mounted_fs = self.mount_points.get_mut(&mount_point).expect("VFS::read_dir: we know that mount_point exist");  
 
     // Guard for Not a directory
-    match mounted_fs.driver.stat(&internal_pathname)? {
+    let stat = mounted_fs.driver.stat(&internal_pathname)
+      .with_context(|| format!("stat {pathname}"))?;
+    match stat {
       stat if stat.mode.file_type() != FileModeType::Dir as u8 
-        => return Err(Errno::ENOTDIR(String::from("read_dir: not a directory"))),
+        => return Err(Errno::ENOTDIR(String::from("read_dir: not a directory").into())),
       _ => (),
     }
 
     mounted_fs.driver.read_dir(&internal_pathname)
+      .with_context(|| format!("read_dir {pathname}"))
+  }
+
+  fn readdir(&mut self, pathname: &str, offset: AddressSize, count: AddressSize)
+    -> Result<Vec<VDirectoryEntry>, Errno> {
+    let pathname = &self.resolve_path(pathname, true)?;
+    let vinode = self.lookup_path(pathname)?;
+    self.permission_check(vinode, PERM_R)
+      .with_context(|| format!("readdir {pathname}"))?;
+
+    let (mount_point, internal_pathname) = self.match_mount_point(pathname)?;
+    let mounted_fs = self.mount_points.get_mut(&mount_point).expect("VFS::readdir: we know that mount_point exist");
+
+    // Guard for Not a directory
+    let stat = mounted_fs.driver.stat(&internal_pathname)
+      .with_context(|| format!("stat {pathname}"))?;
+    if stat.mode.file_type() != FileModeType::Dir as u8 {
+      return Err(Errno::ENOTDIR(String::from("readdir: not a directory").into()));
+    }
+
+    mounted_fs.driver.readdir(&internal_pathname, offset, count)
+      .with_context(|| format!("readdir {pathname}"))
   }
 
   fn stat(&mut self, pathname: &str)
     -> Result<FileStat, Errno> {
+    let pathname = &self.resolve_path(pathname, true)?;
     let (mount_point, internal_pathname) = self.match_mount_point(pathname)?;
     let mounted_fs = self.mount_points.get_mut(&mount_point).expect("VFS::stat: we know that mount_point exist");  
     mounted_fs.driver.stat(&internal_pathname)
+      .with_context(|| format!("stat {pathname}"))
   }
 
   fn change_mode(&mut self, pathname: &str, mode: FileMode)
     -> Result<(), Errno> {
+    let pathname = &self.resolve_path(pathname, true)?;
     let vinode = self.lookup_path(pathname)?;
-    let bytes = self.read_file("/etc/passwd", AddressSize::MAX)?;
-    let contents = String::from_utf8(bytes)
-      .or(Err(Errno::EILSEQ(format!("fs::change_mode: can't parse utf8"))))?;
-    let passwd = Passwd::parse_passwds(&contents)
-      .into_iter()
-      .find(|passwd| passwd.uid == self.current_uid)
-      .ok_or(Errno::EACCES(format!("fs::change_mode: no such uid in /etc/passwd")))?;
 
     // Guard for ownership
-    if vinode.uid != self.current_uid && vinode.gid != passwd.gid && passwd.uid != ROOT_UID {
-      return Err(Errno::EPERM(format!("fs::change_mode: operation not permitted")))
+    if vinode.uid != self.current_uid && self.current_uid != ROOT_UID {
+      return Err(Errno::EPERM(format!("fs::change_mode: operation not permitted").into()))
     }
 
     let (mount_point, internal_pathname) = self.match_mount_point(pathname)?;
+    self.check_writable(&mount_point).with_context(|| format!("change_mode {pathname}"))?;
     let mounted_fs = self.mount_points.get_mut(&mount_point).expect("VFS::change_mode: we know that mount_point exist");  
     mounted_fs.driver.change_mode(&internal_pathname, mode)
+      .with_context(|| format!("change_mode {pathname}"))
   }
 
   fn change_owners(&mut self, pathname: &str, uid: Id, gid: Id) 
     -> Result<(), Errno> {
+    let pathname = &self.resolve_path(pathname, true)?;
     let vinode = self.lookup_path(pathname)?;
 
     // Guard - only root can change ownership
     if self.current_uid != ROOT_UID {
-      return Err(Errno::EPERM(format!("fs::change_owners: operation not permitted")))
+      return Err(Errno::EPERM(format!("fs::change_owners: operation not permitted").into()))
     }
 
     let (mount_point, internal_pathname) = self.match_mount_point(pathname)?;
+    self.check_writable(&mount_point).with_context(|| format!("change_owners {pathname}"))?;
     let mounted_fs = self.mount_points.get_mut(&mount_point).expect("VFS::change_owners: we know that mount_point exist");  
     mounted_fs.driver.change_owners(&internal_pathname, uid, gid)
+      .with_context(|| format!("change_owners {pathname}"))
   }
 
   fn change_times(&mut self, pathname: &str, times: Times)
     -> Result<(), Errno> {
+    let pathname = &self.resolve_path(pathname, true)?;
     let vinode = self.lookup_path(pathname)?;
-    self.permission_check(vinode, PERM_W)?;
+    // Owner may change times of file they can't write
+    if vinode.uid != self.current_uid {
+      self.permission_check(vinode, PERM_W)
+        .with_context(|| format!("change_times {pathname}"))?;
+    }
 
     let (mount_point, internal_pathname) = self.match_mount_point(pathname)?;
+    self.check_writable(&mount_point).with_context(|| format!("change_times {pathname}"))?;
     let mounted_fs = self.mount_points.get_mut(&mount_point).expect("VFS::change_mode: we know that mount_point exist");  
     mounted_fs.driver.change_times(&internal_pathname, times)
+      .with_context(|| format!("change_times {pathname}"))
+  }
+
+  fn get_xattr(&mut self, pathname: &str, name: &str)
+    -> Result<Vec<u8>, Errno> {
+    let pathname = &self.resolve_path(pathname, true)?;
+    let vinode = self.lookup_path(pathname)?;
+    self.permission_check(vinode, PERM_R)
+      .with_context(|| format!("get_xattr {pathname}"))?;
+
+    // Guard for attributes others can't see
+    if VFS::xattr_namespace(name)? == "trusted" && self.current_uid != ROOT_UID {
+      return Err(Errno::ENODATA(format!("fs::get_xattr: {pathname}: no attribute '{name}'").into()));
+    }
+
+    let (mount_point, internal_pathname) = self.match_mount_point(pathname)?;
+    let mounted_fs = self.mount_points.get_mut(&mount_point).expect("VFS::get_xattr: we know that mount_point exist");  
+    mounted_fs.driver.get_xattr(&internal_pathname, name)
+      .with_context(|| format!("get_xattr {pathname}"))
+  }
+
+  fn set_xattr(&mut self, pathname: &str, name: &str, value: Option<&[u8]>)
+    -> Result<(), Errno> {
+    let pathname = &self.resolve_path(pathname, true)?;
+    let vinode = self.lookup_path(pathname)?;
+    self.permission_check(vinode, PERM_W)
+      .with_context(|| format!("set_xattr {pathname}"))?;
+
+    // Guard for attributes that are root's
+    if VFS::xattr_namespace(name)? != "user" && self.current_uid != ROOT_UID {
+      return Err(Errno::EPERM(format!("fs::set_xattr: operation not permitted").into()));
+    }
+
+    let (mount_point, internal_pathname) = self.match_mount_point(pathname)?;
+    self.check_writable(&mount_point).with_context(|| format!("set_xattr {pathname}"))?;
+    let mounted_fs = self.mount_points.get_mut(&mount_point).expect("VFS::set_xattr: we know that mount_point exist");  
+    mounted_fs.driver.set_xattr(&internal_pathname, name, value)
+      .with_context(|| format!("set_xattr {pathname}"))
+  }
+
+  fn list_xattr(&mut self, pathname: &str)
+    -> Result<Vec<String>, Errno> {
+    let pathname = &self.resolve_path(pathname, true)?;
+    let vinode = self.lookup_path(pathname)?;
+    self.permission_check(vinode, PERM_R)
+      .with_context(|| format!("list_xattr {pathname}"))?;
+
+    let (mount_point, internal_pathname) = self.match_mount_point(pathname)?;
+    let mounted_fs = self.mount_points.get_mut(&mount_point).expect("VFS::list_xattr: we know that mount_point exist");  
+    let names = mounted_fs.driver.list_xattr(&internal_pathname)
+      .with_context(|| format!("list_xattr {pathname}"))?;
+
+    let current_uid = self.current_uid;
+    Ok(names.into_iter().filter(|name| current_uid == ROOT_UID || !name.starts_with("trusted.")).collect())
+  }
+
+  fn statfs(&mut self, pathname: &str)
+    -> Result<FilesystemStat, Errno> {
+    let pathname = &self.resolve_path(pathname, true)?;
+    self.lookup_path(pathname)?;
+
+    let (mount_point, internal_pathname) = self.match_mount_point(pathname)?;
+    let mounted_fs = self.mount_points.get_mut(&mount_point).expect("VFS::statfs: we know that mount_point exist");  
+    mounted_fs.driver.statfs(&internal_pathname)
+      .with_context(|| format!("statfs {pathname}"))
+  }
+
+  /// Sync every mounted filesystem.
+  /// Returns: the first error, after trying to sync the rest anyway
+  fn sync(&mut self)
+    -> Result<(), Errno> {
+    self.mount_points
+      .iter_mut()
+      .map(|(mount_point, mounted_fs)| mounted_fs.driver.sync().with_context(|| format!("sync {mount_point}")))
+      .fold(Ok(()), |result, sync_result| result.and(sync_result))
   }
 
   // Поиск файла в файловой системе. Возвращает INode фала.
@@ -543,9 +1174,8 @@ impl Filesystem for VFS {
   // Для конкретных реализаций (e5fs) поиск сразу от рута файловой системы
   fn lookup_path(&mut self, pathname: &str)
     -> Result<VINode, Errno> {
-    let (mount_point, internal_pathname) = self.match_mount_point(pathname)?;
-    let mounted_fs = self.mount_points.get_mut(&mount_point).expect("VFS::lookup_path: we know that mount_point exist");  
-    mounted_fs.driver.lookup_path(&internal_pathname)
+    let pathname = &self.resolve_path(pathname, true)?;
+    self.lookup_mounted(pathname)
   }
 
   fn name(&self) -> String {
@@ -555,6 +1185,10 @@ impl Filesystem for VFS {
   fn as_any(&mut self) -> &mut dyn Any {
     self
   }
+
+  fn as_any_ref(&self) -> &dyn Any {
+    self
+  }
 }
 
 #[derive(Debug, Clone)]
@@ -562,6 +1196,8 @@ pub struct FileDescription {
   pub vinode: VINode,
   pub flags: OpenFlags,
   pub pathname: Option<String>,
+  /// Where next `read` or `write` of regular file starts
+  pub offset: AddressSize,
 }
 impl FileDescription {
   // pub fn new() {
@@ -574,18 +1210,79 @@ impl FileDescription {
   // }
 }
 
+/// Who uses VFS and from where: ids, terminal and working directory
+/// of current process. Every thread of kernel has its own, that VFS
+/// is switched to when the thread locks it
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct VfsContext {
+  pub uid: Id,
+  pub gid: Id,
+  pub sgids: Vec<Id>,
+  pub tty: Option<String>,
+  pub dir: String,
+}
+
 #[derive(Debug)]
 pub struct VFS {
   pub mount_points: BTreeMap<String, MountedFilesystem>,
+  /// Mount points that show a subtree of a mounted filesystem,
+  /// made with `mount --bind`
+  pub bind_mounts: BTreeMap<String, BindMount>,
   pub open_files: BTreeMap<String, FileDescription>,
   pub current_uid: Id,
   pub current_gid: Id,
+  /// Supplementary groups of current user, from /etc/group
+  pub current_sgids: Vec<Id>,
+  /// Name of controlling terminal of current process in devfs,
+  /// that `/dev/tty` resolves to
+  pub current_tty: Option<String>,
+  /// Working directory of current process, that pathnames
+  /// not starting with '/' are relative to
+  pub current_dir: String,
+  /// Watches that changes made through VFS are reported to
+  pub watches: Watches,
 }
 
 #[derive(Debug)]
 pub struct MountedFilesystem {
   pub r#type: FilesystemType,
-  pub driver: Box<dyn Filesystem>
+  pub driver: Box<dyn Filesystem>,
+  pub flags: MountFlags,
+  /// What was mounted, as it was given to `mount`,
+  /// like `/dev/sda` or `LABEL=home`
+  pub source: String,
+}
+
+/// Options of mounted filesystem that VFS and kernel honor
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub struct MountFlags {
+  /// `ro`: nothing on it is created, written or removed through VFS,
+  /// and nothing is written to device
+  pub read_only: bool,
+  /// `noexec`: binaries on it are not executed
+  pub noexec: bool,
+  /// `nosuid`: setuid and setgid bits of binaries on it are ignored
+  pub nosuid: bool,
+  /// `noatime`: access times of files on it are not updated on read
+  pub noatime: bool,
+}
+
+/// Options like `mount` takes them: `rw` or `ro`, then the rest
+/// that are set, comma-separated
+impl fmt::Display for MountFlags {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    let mut options = vec![match self.read_only {
+      true => "ro",
+      false => "rw",
+    }];
+    for (option, is_set) in [("noexec", self.noexec), ("nosuid", self.nosuid), ("noatime", self.noatime)] {
+      if is_set {
+        options.push(option);
+      }
+    }
+
+    write!(f, "{}", options.join(","))
+  }
 }
 
 impl MountedFilesystem {
@@ -593,13 +1290,26 @@ impl MountedFilesystem {
   }
 }
 
+/// Directory of a mounted filesystem shown at another mount point.
+/// Operations under that mount point are forwarded to the filesystem
+/// with the mount point replaced by the directory
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct BindMount {
+  /// Mount point of the filesystem that has the directory
+  pub mount_point: String,
+  /// Pathname of the directory on that filesystem
+  pub internal_pathname: String,
+}
+
 #[derive(Debug, PartialEq, Eq)]
 pub enum FilesystemType {
   devfs,
   binfs,
-  // procfs(ProcessFilesystem),
-  // sysfs(SysFilesystem),
+  procfs,
+  sysfs,
   e5fs,
+  netfs,
+  overlayfs,
   // tmpfs(MemFilesystem),
 }
 
@@ -610,9 +1320,11 @@ impl FromStr for FilesystemType {
     match s {
       "devfs" => Ok(FilesystemType::devfs),
       "binfs" => Ok(FilesystemType::binfs),
-      // "procfs" => Ok(FilesystemType::procfs),
-      // "sysfs" => Ok(FilesystemType::sysfs),
+      "procfs" => Ok(FilesystemType::procfs),
+      "sysfs" => Ok(FilesystemType::sysfs),
       "e5fs" => Ok(FilesystemType::e5fs),
+      "netfs" => Ok(FilesystemType::netfs),
+      "overlayfs" | "overlay" => Ok(FilesystemType::overlayfs),
       // "tmpfs" => Ok(FilesystemType::tmpfs),
       _ => Err(format!("<unknown_fs>")),
     }
@@ -624,15 +1336,205 @@ impl fmt::Display for FilesystemType {
     match self {
       FilesystemType::devfs => write!(f, "devfs"),
       FilesystemType::binfs => write!(f, "binfs"),
-      // FilesystemType::procfs => write!(f, "procfs"),
-      // FilesystemType::sysfs => write!(f, "sysfs"),
+      FilesystemType::procfs => write!(f, "procfs"),
+      FilesystemType::sysfs => write!(f, "sysfs"),
       FilesystemType::e5fs => write!(f, "e5fs"),
+      FilesystemType::netfs => write!(f, "netfs"),
+      FilesystemType::overlayfs => write!(f, "overlayfs"),
       // FilesystemType::tmpfs => write!(f, "tmpfs"),
     }
   }
 }
 
 impl VFS {
+  /// Make `uid` and `gid` those of whoever uses vfs next,
+  /// telling mounted filesystems about them
+  pub fn set_current_ids(&mut self, uid: Id, gid: Id) {
+    self.current_uid = uid;
+    self.current_gid = gid;
+    for mounted_fs in self.mount_points.values_mut() {
+      mounted_fs.driver.set_current_user(uid, gid);
+    }
+  }
+
+  /// Make `context` that of whoever uses vfs next. Mounted
+  /// filesystems are told about ids only when they change
+  pub fn switch_context(&mut self, context: &VfsContext) {
+    if (self.current_uid, self.current_gid) != (context.uid, context.gid) {
+      self.set_current_ids(context.uid, context.gid);
+    }
+    self.current_sgids.clone_from(&context.sgids);
+    self.current_tty.clone_from(&context.tty);
+    self.current_dir.clone_from(&context.dir);
+  }
+
+  /// Driver of character device at `pathname`, checked like
+  /// `read_file` and `write_file` check it for `mask`, so that
+  /// it can be read or written with VFS unlocked.
+  /// Returns: `None` if it is not a character device
+  pub fn char_device(&mut self, pathname: &str, mask: u8) -> Result<Option<Arc<RwLock<dyn CharDevice>>>, Errno> {
+    let pathname = &self.resolve_path(pathname, true)?;
+    let vinode = self.lookup_path(pathname)?;
+    if vinode.mode.file_type() != FileModeType::Char as u8 {
+      return Ok(None);
+    }
+    self.permission_check(vinode, mask)
+      .with_context(|| format!("char_device {pathname}"))?;
+
+    let (mount_point, internal_pathname) = self.match_device_or_mount_point(pathname, vinode)?;
+    if mask & PERM_W != 0 {
+      self.check_writable(&mount_point).with_context(|| format!("char_device {pathname}"))?;
+    }
+    let mounted_fs = self.mount_points.get_mut(&mount_point).expect("VFS::char_device: we know that mount_point exist");
+    let Some(devfs) = mounted_fs.driver.as_any().downcast_mut::<DeviceFilesystem>() else {
+      return Ok(None);
+    };
+
+    devfs.char_device(&internal_pathname).map(Some)
+  }
+
+  /// Recursively copy `source` to `target`, which must not exist yet,
+  /// preserving modes and giving every copy to `uid`:`gid`
+  pub fn copy_tree(&mut self, source: &str, target: &str, uid: Id, gid: Id) -> Result<(), Errno> {
+    let vinode = self.lookup_link(source)?;
+
+    match FileModeType::try_from(vinode.mode.file_type())? {
+      FileModeType::Dir => {
+        self.create_dir(target)?;
+
+        let names = self.read_dir(source)?
+          .entries
+          .into_keys()
+          .filter(|name| name != "." && name != "..")
+          .collect::<Vec<_>>();
+        for name in names {
+          self.copy_tree(
+            &format!("{}/{name}", source.trim_end_matches('/')),
+            &format!("{}/{name}", target.trim_end_matches('/')),
+            uid,
+            gid,
+          )?;
+        }
+      },
+      file_type @ (FileModeType::Block | FileModeType::Char) => {
+        self.mknod(target, file_type, vinode.rdev)?;
+      },
+      FileModeType::File | FileModeType::Sys => {
+        let data = self.read_file(source, EVERYTHING)?;
+        self.create_file(target)?;
+        self.write_file(target, &data)?;
+      },
+      // Links are copied as links, mode and owners would go to their targets
+      FileModeType::Symlink => {
+        let link_target = self.readlink(source)?;
+        self.symlink(&link_target, target)?;
+        return Ok(());
+      },
+    }
+
+    self.change_mode(target, vinode.mode)?;
+    self.change_owners(target, uid, gid)
+  }
+
+  /// Same as `lookup_path`, but if `pathname` itself is a symbolic
+  /// link, return the link and not what it points to
+  pub fn lookup_link(&mut self, pathname: &str) -> Result<VINode, Errno> {
+    let pathname = &self.resolve_path(pathname, false)?;
+    self.lookup_mounted(pathname)
+  }
+
+  /// Lookup in mounted filesystem without following any links
+  fn lookup_mounted(&mut self, pathname: &str) -> Result<VINode, Errno> {
+    let (mount_point, internal_pathname) = self.match_mount_point(pathname)?;
+    let mounted_fs = self.mount_points.get_mut(&mount_point).expect("VFS::lookup_path: we know that mount_point exist");  
+    mounted_fs.driver.lookup_path(&internal_pathname)
+      .with_context(|| format!("lookup_path {pathname}"))
+  }
+
+  /// Replace symbolic links in `pathname` with their targets, the final
+  /// component only if `follow_last`. Also drops `.` and resolves `..`
+  /// against the real parent. Components after the first missing one are
+  /// kept as they are, for the operation to report the missing file
+  pub fn resolve_path(&mut self, pathname: &str, follow_last: bool) -> Result<String, Errno> {
+    // Nothing to resolve, let the operation complain about the path
+    if pathname.is_empty() {
+      return Ok(String::new());
+    }
+    let pathname = &self.absolute_path(pathname);
+
+    // Components left to walk, the next one on top
+    let mut pending: Vec<String> = pathname.split('/').rev().map(str::to_owned).collect();
+    let mut resolved: Vec<String> = Vec::new();
+    // Directory of `resolved`, if it is looked up already
+    let mut dir_vinode: Option<VINode> = None;
+    let mut hops = 0;
+
+    while let Some(component) = pending.pop() {
+      match component.as_str() {
+        "" | "." => continue,
+        ".." => {
+          resolved.pop();
+          dir_vinode = None;
+          continue;
+        },
+        _ => (),
+      }
+
+      // Looking for entry of directory needs permission to search it
+      let dir = match dir_vinode {
+        Some(dir) => dir,
+        None => self.lookup_mounted(&format!("/{}", resolved.join("/")))?,
+      };
+      if dir.mode.file_type() == FileModeType::Dir as u8 {
+        self.permission_check(dir, PERM_X)
+          .with_context(|| format!("search /{}", resolved.join("/")))?;
+      }
+      dir_vinode = Some(dir);
+
+      let is_last = pending.iter().all(|component| component.is_empty() || component == ".");
+      let candidate = format!("/{}", resolved.iter().chain([&component]).join("/"));
+      let vinode = match self.lookup_mounted(&candidate) {
+        Ok(vinode) => vinode,
+        // There is no going back up through missing directory
+        Err(errno) if pending.iter().any(|component| component == "..") => return Err(errno),
+        Err(_) => {
+          resolved.push(component);
+          resolved.extend(pending.drain(..).rev().filter(|component| !component.is_empty()));
+          break;
+        },
+      };
+
+      if vinode.mode.file_type() != FileModeType::Symlink as u8 || (is_last && !follow_last) {
+        resolved.push(component);
+        dir_vinode = Some(vinode);
+        continue;
+      }
+
+      hops += 1;
+      if hops > MAX_SYMLINK_HOPS {
+        return Err(Errno::ELOOP(format!("{pathname}: too many levels of symbolic links").into()));
+      }
+
+      let target = self.readlink_mounted(&candidate)?;
+      // Relative targets are relative to the directory of the link
+      if target.starts_with('/') {
+        resolved.clear();
+        dir_vinode = None;
+      }
+      pending.extend(target.split('/').rev().map(str::to_owned));
+    }
+
+    Ok(format!("/{}", resolved.join("/")))
+  }
+
+  /// Target of link at `pathname` without following any links
+  fn readlink_mounted(&mut self, pathname: &str) -> Result<String, Errno> {
+    let (mount_point, internal_pathname) = self.match_mount_point(pathname)?;
+    let mounted_fs = self.mount_points.get_mut(&mount_point).expect("VFS::readlink: we know that mount_point exist");
+    mounted_fs.driver.readlink(&internal_pathname)
+      .with_context(|| format!("readlink {pathname}"))
+  }
+
   pub fn parent_dir(pathname: &str) -> Result<String, Errno> {
     let (everything_else, final_component) = VFS::split_path(pathname)?;
     Ok(format!("/{}", everything_else.join("/")))
@@ -644,23 +1546,27 @@ impl VFS {
   }
 
   pub fn remove_open_file(&mut self, pathname: &str, file_description: &FileDescription) -> Result<(), Errno> {
-    self.open_files.remove(pathname).ok_or_else(|| Errno::ENOENT(String::from("vfs: no such file was open"))).map(|_| ())
+    self.open_files.remove(pathname).ok_or_else(|| Errno::ENOENT(String::from("vfs: no such file was open").into())).map(|_| ())
   }
 
   /// Returns: `(mount_point_pathname, internal_pathname)`
   pub fn match_mount_point(&self, pathname: &str)
     -> Result<(String, String), Errno> 
   {
-    let (mount_point, _mounted_fs) = self.mount_points
-      .iter()
-      .sorted_by(|(key1, _), (key2, _)| key1.len().cmp(&key2.len()))
+    // Filesystems get pathnames without "." and "..", which
+    // may lead out of their mount points
+    let pathname = &VFS::normalize_path(&self.absolute_path(pathname))?;
+    let mount_point = self.mount_points
+      .keys()
+      .chain(self.bind_mounts.keys())
+      .sorted_by(|key1, key2| key1.len().cmp(&key2.len()))
       .rev()
-      .map(|(key, value)| (key.clone(), value))
-      .find(|(mount_point, _mounted_fs)| {
+      .cloned()
+      .find(|mount_point| {
         let re = Regex::new(&format!("^{}", mount_point)).unwrap();
         re.is_match(pathname).expect("fix yo regex nerd (is_match)")
       })
-      .ok_or_else(|| Errno::ENOENT(String::from("VFS::lookup_path: no such file or directory")))?;
+      .ok_or_else(|| Errno::ENOENT(String::from("VFS::lookup_path: no such file or directory").into()))?;
     // println!("[{KERNEL_MESSAGE_HEADER_ERR}]: mount_point: {mount_point}");
 
     let regex = Regex::new(&format!("^{}", mount_point))
@@ -675,23 +1581,154 @@ impl VFS {
     // Add leading slash - required by (my) standart
     let internal_pathname = format!("/{}", internal_pathname);
 
+    // Bind mount forwards to the directory it shows
+    if let Some(bind_mount) = self.bind_mounts.get(&mount_point) {
+      let internal_pathname = VFS::join_path(&bind_mount.internal_pathname, &internal_pathname);
+      return Ok((bind_mount.mount_point.clone(), internal_pathname));
+    }
+
     Ok((mount_point.to_owned(), internal_pathname))
   }
 
+  /// Check that current user may remove name `pathname` from its
+  /// directory by `operation`, and that it is not a mount point.
+  /// Returns: absolute pathname, mount point of it and pathname in it
+  fn check_removable(&mut self, pathname: &str, operation: &str) -> Result<(String, String, String), Errno> {
+    // Guard for removing directory by its "." or ".."
+    if matches!(pathname.trim_end_matches('/').rsplit('/').next(), Some("." | "..")) {
+      return Err(Errno::EINVAL(format!("{operation} {pathname}: cannot remove '.' or '..'").into()));
+    }
+    let pathname = &self.resolve_path(pathname, false)?;
+    if self.mount_points.contains_key(pathname) || self.bind_mounts.contains_key(pathname) {
+      return Err(Errno::EBUSY(format!("{operation} {pathname}: is a mount point").into()));
+    }
+    let parent_vinode = self.lookup_path(&VFS::parent_dir(pathname)?)?;
+    self.permission_check(parent_vinode, PERM_W | PERM_X)
+      .with_context(|| format!("{operation} {pathname}"))?;
+
+    // Guard for sticky directory, like /tmp: only owners
+    // remove files from it
+    let vinode = self.lookup_link(pathname)?;
+    if parent_vinode.mode.sticky()
+      && ![vinode.uid, parent_vinode.uid, ROOT_UID].contains(&self.current_uid)
+    {
+      return Err(Errno::EPERM(format!("fs::{operation}: {pathname}: operation not permitted").into()));
+    }
+
+    let (mount_point, internal_pathname) = self.match_mount_point(pathname)?;
+    self.check_writable(&mount_point).with_context(|| format!("{operation} {pathname}"))?;
+
+    Ok((pathname.clone(), mount_point, internal_pathname))
+  }
+  /// Guard for read-only mount
+  ///
+  /// Errors:
+  /// EROFS -> filesystem at `mount_point` is mounted with `ro`
+  pub fn check_writable(&self, mount_point: &str) -> Result<(), Errno> {
+    match self.mount_points.get(mount_point) {
+      Some(mounted_fs) if mounted_fs.flags.read_only => Err(Errno::EROFS(format!("fs: {mount_point} is mounted read-only").into())),
+      _ => Ok(()),
+    }
+  }
+
+  /// Same as `match_mount_point`, but if `vinode` is a device
+  /// node outside of devfs, match the devfs node of that device
+  /// instead, so that I/O goes to the device driver
+  pub fn match_device_or_mount_point(&mut self, pathname: &str, vinode: VINode)
+    -> Result<(String, String), Errno> 
+  {
+    let (mount_point, internal_pathname) = self.match_mount_point(pathname)?;
+    let is_on_devfs = self
+      .mount_points
+      .get(&mount_point)
+      .map(|mounted_fs| mounted_fs.r#type == FilesystemType::devfs)
+      .unwrap_or(false);
+    let is_controlling_tty = vinode.is_device() && vinode.rdev == CONTROLLING_TTY_RDEV;
+
+    if !vinode.is_device() || (is_on_devfs && !is_controlling_tty) {
+      return Ok((mount_point, internal_pathname));
+    }
+
+    let (devfs_mount_point, mounted_fs) = self
+      .mount_points
+      .iter_mut()
+      .find(|(_, mounted_fs)| mounted_fs.r#type == FilesystemType::devfs)
+      .ok_or(Errno::ENXIO(format!("VFS: no devfs mounted to serve device {}", vinode.rdev).into()))?;
+    let devfs = mounted_fs
+      .driver
+      .as_any()
+      .downcast_mut::<DeviceFilesystem>()
+      .expect("we know that mounted_fs.driver === instanceof DeviceFilesystem");
+    let name = match is_controlling_tty {
+      true => self.current_tty
+        .clone()
+        .ok_or(Errno::ENXIO(String::from("VFS: no controlling terminal").into()))?,
+      false => devfs.device_name_by_number(vinode.rdev)?,
+    };
+
+    Ok((devfs_mount_point.to_owned(), format!("/{name}")))
+  }
+
   /// "/"            -> `([], "/")`
   /// "/foo"         -> `([], "foo")`
   /// "/foo/bar"     -> `(["foo"], "bar")`
   /// "/foo/bar/baz" -> `(["foo", "bar"], "baz")`
+  /// `pathname` as it is if it starts with '/', relative
+  /// to `current_dir` otherwise
+  pub fn absolute_path(&self, pathname: &str) -> String {
+    match pathname {
+      "" => String::new(),
+      pathname if pathname.starts_with('/') => pathname.to_owned(),
+      pathname => format!("{}/{pathname}", self.current_dir.trim_end_matches('/')),
+    }
+  }
+  /// Pathname of `pathname`, that is relative to `directory`
+  /// even if it starts with '/', from where `directory` is
+  pub fn join_path(directory: &str, pathname: &str) -> String {
+    match pathname.trim_start_matches('/') {
+      "" => directory.to_owned(),
+      rest => format!("{}/{rest}", directory.trim_end_matches('/')),
+    }
+  }
+  /// `pathname` without "." and ".." components and repeated
+  /// slashes, ".." of root being root.
+  ///
+  /// Errors:
+  /// EINVAL -> `pathname` is empty or doesn't start with '/'
+  pub fn normalize_path(pathname: &str) -> Result<String, Errno> {
+    if !pathname.starts_with('/') {
+      return Err(Errno::EINVAL(format!("path must start with '/': '{pathname}'").into()));
+    }
+
+    let mut components = Vec::new();
+    for component in pathname.split('/') {
+      match component {
+        "" | "." => (),
+        ".." => {
+          components.pop();
+        },
+        component => components.push(component),
+      }
+    }
+
+    Ok(format!("/{}", components.join("/")))
+  }
   pub fn split_path(pathname: &str) -> Result<(Vec<String>, String), Errno> {
     // Guard for empty `pathname`
     match &pathname {
       pathname if pathname.chars().count() == 0 => { 
-        return Err(Errno::EINVAL(String::from("zero-length path")))
+        return Err(Errno::EINVAL(String::from("zero-length path").into()))
       },
       pathname if pathname
         .chars()
         .nth(0)
-        .unwrap() != '/' => return Err(Errno::EINVAL(String::from("path must start with '/'"))),
+        .unwrap() != '/' => return Err(Errno::EINVAL(String::from("path must start with '/'").into())),
+      pathname if pathname.contains('\0') => {
+        return Err(Errno::EINVAL(String::from("path must not contain NUL").into()))
+      },
+      pathname if pathname.len() >= PATH_MAX => {
+        return Err(Errno::ENAMETOOLONG(format!("path must be shorter than {PATH_MAX} bytes").into()))
+      },
       _ => (),
     };
 
@@ -729,6 +1766,11 @@ impl VFS {
       .last()
       .expect("fs::split_path: we know that there is element").to_owned();
 
+    // Guard for names that don't fit in directory entry
+    if let Some(name) = pathname.split('/').find(|name| name.len() > NAME_MAX) {
+      return Err(Errno::ENAMETOOLONG(format!("name '{name}' is longer than {NAME_MAX} bytes").into()));
+    }
+
     match pathname.split('/').count() {
       // E.g. with '/test1' we have vec!["", "test1"]
       1 => Ok((Vec::new(), final_component)),
@@ -736,79 +1778,57 @@ impl VFS {
     }
   }
 
-  fn permission_check(&mut self, vinode: VINode, wanted_perm_mask: u8) 
+  /// Check that current user may execute file of `vinode`,
+  /// or search it if it is a directory, see `permission_check`
+  pub fn execute_check(&self, vinode: &VINode) -> Result<(), Errno> {
+    self.permission_check(*vinode, PERM_X)
+      .with_context(|| "fs::execute_check")
+  }
+
+  /// Namespace of extended attribute `name`.
+  /// Errors: ENOTSUP if it is not one of `XATTR_NAMESPACES`
+  fn xattr_namespace(name: &str) -> Result<&str, Errno> {
+    match name.split_once('.') {
+      Some((namespace, rest)) if !rest.is_empty() && XATTR_NAMESPACES.contains(&namespace) => Ok(namespace),
+      _ => Err(Errno::ENOTSUP(format!("fs: attribute '{name}' is not in any of namespaces {}", XATTR_NAMESPACES.join(", ")).into())),
+    }
+  }
+
+  /// Whether current user is in group `gid`, as primary or supplementary
+  fn is_in_group(&self, gid: Id) -> bool {
+    self.current_gid == gid || self.current_sgids.contains(&gid)
+  }
+
+  /// Check that current user may do with file of `vinode` what
+  /// `wanted_perm_mask` of `PERM_R`, `PERM_W` and `PERM_X` says. Only
+  /// the class the user falls into first is consulted: owner, group
+  /// (primary or supplementary), others. Root may read and write
+  /// anything and search any directory, but execute only files that
+  /// have any execute bit set
+  ///
+  /// Errors:
+  /// EACCES -> user may not
+  pub fn permission_check(&self, vinode: VINode, wanted_perm_mask: u8)
     -> Result<(), Errno> {
-    let (mount_point, _) = self.match_mount_point(PASSWD_PATH)?;
-    let mounted_fs = self.mount_points.get_mut(&mount_point).expect("VFS::read_file: we know that mount_point exist");  
-    let bytes = mounted_fs.driver.read_file(PASSWD_PATH, EVERYTHING)?;
-
-    let contents = String::from_utf8(bytes)
-      .or(Err(Errno::EILSEQ(format!("fs::permission_check: can't parse utf8"))))?;
-    let passwd = Passwd::parse_passwds(&contents)
-      .into_iter()
-      .chain(std::iter::once(Passwd { 
-        name: String::from("root"), 
-        password: String::from(""), 
-        uid: ROOT_UID, 
-        gid: ROOT_GID, 
-        comment: String::from(""), 
-        home: String::from(""), 
-        shell: String::from("") 
-      }))
-      .find(|passwd| passwd.uid == self.current_uid)
-      .ok_or(Errno::EACCES(format!("fs::permission_check: no such uid in /etc/passwd")))?;
-
-    let others_read = util::get_bit_at(vinode.mode.others(), 2);
-    let others_write = util::get_bit_at(vinode.mode.others(), 1);
-    let others_execute = util::get_bit_at(vinode.mode.others(), 0);
-    let group_read = util::get_bit_at(vinode.mode.group(), 2) && passwd.gid == vinode.gid;
-    let group_write = util::get_bit_at(vinode.mode.group(), 1) && passwd.gid == vinode.gid;
-    let group_execute = util::get_bit_at(vinode.mode.group(), 0) && passwd.gid == vinode.gid;
-    let user_read = util::get_bit_at(vinode.mode.user(), 2) && (passwd.uid == vinode.uid || passwd.uid == ROOT_UID);
-    let user_write = util::get_bit_at(vinode.mode.user(), 1) && (passwd.uid == vinode.uid || passwd.uid == ROOT_UID);
-    let user_execute = util::get_bit_at(vinode.mode.user(), 0) && (passwd.uid == vinode.uid || passwd.uid == ROOT_UID);
-    
-    // println!("[] vinode: {vinode:#?}");
-    // println!("[] others_read: {others_read}");
-    // println!("[] others_write: {others_write}");
-    // println!("[] others_execute: {others_execute}");
-    // println!("[]");
-    // println!("[] group_read: {group_read}");
-    // println!("[] group_write: {group_write}");
-    // println!("[] group_execute: {group_execute}");
-    // println!("[]");
-    // println!("[] user_read: {user_read}");
-    // println!("[] user_write: {user_write}");
-    // println!("[] user_execute: {user_execute}");
-
-    let wanted_read = util::get_bit_at(wanted_perm_mask, 2);
-    let wanted_write = util::get_bit_at(wanted_perm_mask, 1);
-    let wanted_execute = util::get_bit_at(wanted_perm_mask, 0);
-    let is_read_matches = ((user_read || group_read || others_read) == wanted_read) || !wanted_read;
-    let is_write_matches = ((user_write || group_write || others_write) == wanted_write) || !wanted_write;
-    let is_execute_matches = ((user_execute || group_execute || others_execute) == wanted_execute) || !wanted_execute;
-    let is_permission_matches = is_read_matches && is_write_matches && is_execute_matches;
-
-    // println!("[]");
-    // println!("[] wanted read: {}", util::get_bit_at(wanted_perm_mask, 2));
-    // println!("[] wanted write: {}", util::get_bit_at(wanted_perm_mask, 1));
-    // println!("[] wanted execute: {}", util::get_bit_at(wanted_perm_mask, 0));
-    // println!("[]");
-    // println!("[] current uid: {}", passwd.uid);
-    // println!("[] current gid: {}", passwd.gid);
-    // println!("[]");
-    // println!("[] granted: {is_permission_granted}");
-
-    let perm_denied_errno = Errno::EACCES(format!("fs::permission_check: permission denied"));
-
-    is_permission_matches.then_some(()).ok_or(perm_denied_errno)
+    let any_execute = (vinode.mode.user() | vinode.mode.group() | vinode.mode.others()) & PERM_X;
+    let permissions = match () {
+      _ if self.current_uid == ROOT_UID && vinode.mode.file_type() == FileModeType::Dir as u8 => PERM_R | PERM_W | PERM_X,
+      _ if self.current_uid == ROOT_UID => PERM_R | PERM_W | any_execute,
+      _ if self.current_uid == vinode.uid => vinode.mode.user(),
+      _ if self.is_in_group(vinode.gid) => vinode.mode.group(),
+      _ => vinode.mode.others(),
+    };
+
+    (permissions & wanted_perm_mask == wanted_perm_mask)
+      .then_some(())
+      .ok_or(Errno::EACCES(format!("fs::permission_check: permission denied").into()))
   }
 }
 
 
 #[cfg(test)]
 mod tests {
-  use crate::{util::{mkenxvd, mktemp}, eunix::{e5fs::E5FSFilesystem, devfs::DeviceFilesystem}};
+  use crate::{util::{mkenxvd, mktemp}, binaries::PASSWD_PATH, eunix::{e5fs::E5FSFilesystem, binfs::BinFilesytem, kernel::ROOT_GID}};
 
 use super::*;
 
@@ -826,6 +1846,328 @@ use super::*;
     assert_eq!(filemode.get_raw(), expected);
   }
 
+  #[test]
+  fn mount_flags_display_like_mount_options() {
+    assert_eq!(MountFlags::default().to_string(), "rw");
+    assert_eq!(MountFlags { noatime: true, ..MountFlags::default() }.to_string(), "rw,noatime");
+    assert_eq!(MountFlags { read_only: true, noexec: true, nosuid: true, noatime: true }.to_string(), "ro,noexec,nosuid,noatime");
+  }
+
+  #[test]
+  fn file_mode_fields_round_trip() {
+    let fields: [(&dyn Fn(FileMode, u8) -> FileMode, &dyn Fn(FileMode) -> u8, u8); 8] = [
+      (&|mode, value| mode.with_free(value), &|mode| mode.free(), 0b1),
+      (&|mode, value| mode.with_setuid(value == 1), &|mode| mode.setuid() as u8, 0b1),
+      (&|mode, value| mode.with_setgid(value == 1), &|mode| mode.setgid() as u8, 0b1),
+      (&|mode, value| mode.with_sticky(value == 1), &|mode| mode.sticky() as u8, 0b1),
+      (&|mode, value| mode.with_file_type(value), &|mode| mode.file_type(), 0b111),
+      (&|mode, value| mode.with_user(value), &|mode| mode.user(), 0b111),
+      (&|mode, value| mode.with_group(value), &|mode| mode.group(), 0b111),
+      (&|mode, value| mode.with_others(value), &|mode| mode.others(), 0b111),
+    ];
+
+    // Every value of every field, over modes with other bits all clear and all set
+    for (index, (with, get, max)) in fields.iter().enumerate() {
+      for base in [FileMode::zero(), FileMode::new(u16::MAX)] {
+        for value in 0..=*max {
+          let mode = with(base, value);
+          assert_eq!(get(mode), value);
+          for (other_index, (_, other_get, _)) in fields.iter().enumerate() {
+            if other_index != index {
+              assert_eq!(other_get(mode), other_get(base));
+            }
+          }
+        }
+      }
+    }
+
+    // Every raw mode reads back into itself
+    for raw in 0..=u16::MAX {
+      let mode = FileMode::new(raw);
+      let rebuilt = fields
+        .iter()
+        .fold(FileMode::zero(), |rebuilt, (with, get, _)| with(rebuilt, get(mode)));
+      assert_eq!(rebuilt, mode);
+    }
+
+    assert_eq!(FileMode::zero().with_user(0b1111).get_raw(), 0b0_000_000_111_000_000);
+    assert!(FileMode::zero().with_setuid(true).with_sticky(true).setuid());
+  }
+
+  /// VFS with binfs as root - files written there read back as is
+  fn vfs_with_binfs_root() -> VFS {
+    let mut binfs = BinFilesytem::new();
+    binfs.create_file(PASSWD_PATH).unwrap();
+    binfs.write_file(PASSWD_PATH, b"root:x:0:0::/root:").unwrap();
+
+    VFS {
+      mount_points: BTreeMap::from([(String::from("/"), MountedFilesystem {
+        r#type: FilesystemType::binfs,
+        driver: Box::new(binfs),
+        flags: MountFlags::default(),
+        source: String::new(),
+      })]),
+      bind_mounts: BTreeMap::new(),
+      open_files: BTreeMap::new(),
+      current_uid: ROOT_UID,
+      current_gid: ROOT_GID,
+      current_sgids: vec![ROOT_GID],
+      current_tty: None,
+      current_dir: String::from("/"),
+      watches: Watches::default(),
+    }
+  }
+
+  #[test]
+  fn copy_tree_works() {
+    let mut vfs = vfs_with_binfs_root();
+    vfs.create_dir("/etc/skel").unwrap();
+    vfs.create_file("/etc/skel/.profile").unwrap();
+    vfs.write_file("/etc/skel/.profile", b"export EDITOR=ed").unwrap();
+    vfs.create_dir("/etc/skel/.config").unwrap();
+    vfs.create_file("/etc/skel/.config/rc").unwrap();
+    vfs.create_dir("/home").unwrap();
+
+    vfs.copy_tree("/etc/skel", "/home/user", 1000, 100).unwrap();
+
+    assert_eq!(vfs.read_file("/home/user/.profile", EVERYTHING).unwrap(), b"export EDITOR=ed");
+    let stat = vfs.stat("/home/user/.config/rc").unwrap();
+    assert_eq!((stat.uid, stat.gid), (1000, 100));
+    assert_eq!(vfs.stat("/home/user").unwrap().mode, vfs.stat("/etc/skel").unwrap().mode);
+  }
+
+  #[test]
+  fn symlinks_are_resolved() {
+    let tempfile = mktemp().to_owned();
+    mkenxvd("1M".to_owned(), tempfile.clone());
+    E5FSFilesystem::mkfs(tempfile.as_str(), 0.05, 4096).unwrap();
+    let mut e5fs = E5FSFilesystem::from(tempfile.as_str()).unwrap();
+    e5fs.create_dir("/etc").unwrap();
+    e5fs.create_file(PASSWD_PATH).unwrap();
+    e5fs.write_file(PASSWD_PATH, b"root:x:0:0::/root:").unwrap();
+    let mut vfs = VFS {
+      mount_points: BTreeMap::from([(String::from("/"), MountedFilesystem {
+        r#type: FilesystemType::e5fs,
+        driver: Box::new(e5fs),
+        flags: MountFlags::default(),
+        source: String::new(),
+      })]),
+      ..vfs_with_binfs_root()
+    };
+    vfs.create_file("/etc/motd").unwrap();
+    vfs.write_file("/etc/motd", b"hello").unwrap();
+
+    // Absolute, relative and through a linked directory
+    vfs.symlink("/etc/motd", "/motd").unwrap();
+    vfs.symlink("motd", "/etc/issue").unwrap();
+    vfs.symlink("etc", "/config").unwrap();
+    assert_eq!(vfs.read_file("/motd", EVERYTHING).unwrap(), b"hello");
+    assert_eq!(vfs.read_file("/etc/issue", EVERYTHING).unwrap(), b"hello");
+    assert_eq!(vfs.read_file("/config/issue", EVERYTHING).unwrap(), b"hello");
+    assert_eq!(vfs.readlink("/config/issue").unwrap(), "motd");
+    assert_eq!(vfs.lookup_link("/motd").unwrap().mode.file_type(), FileModeType::Symlink as u8);
+    assert_eq!(vfs.lookup_path("/motd").unwrap().number, vfs.lookup_path("/etc/motd").unwrap().number);
+
+    vfs.symlink("/loop2", "/loop1").unwrap();
+    vfs.symlink("/loop1", "/loop2").unwrap();
+    assert!(matches!(vfs.read_file("/loop1", EVERYTHING), Err(Errno::ELOOP(_))));
+
+    // Removing the link leaves its target
+    vfs.remove_file("/motd").unwrap();
+    assert_eq!(vfs.read_file("/etc/motd", EVERYTHING).unwrap(), b"hello");
+  }
+
+  #[test]
+  fn dot_and_dot_dot_are_resolved() {
+    let mut vfs = vfs_with_binfs_root();
+    vfs.create_dir("/bin").unwrap();
+    vfs.create_dir("/mnt").unwrap();
+    let mut mounted = BinFilesytem::new();
+    mounted.create_file("/file").unwrap();
+    vfs.mount_points.insert(String::from("/mnt"), MountedFilesystem {
+      r#type: FilesystemType::binfs,
+      driver: Box::new(mounted),
+      flags: MountFlags::default(),
+      source: String::new(),
+    });
+
+    assert_eq!(VFS::normalize_path("//a/./b/../../..//c/").unwrap(), "/c");
+    assert!(matches!(VFS::normalize_path("a/b"), Err(Errno::EINVAL(_))));
+
+    let root = vfs.lookup_path("/").unwrap().number;
+    assert_eq!(vfs.lookup_path("/bin/..").unwrap().number, root);
+    assert!(vfs.read_dir("/bin/../.").unwrap().entries.contains_key("bin"));
+    // Up out of mount point and back into it
+    assert!(vfs.read_dir("/mnt/..").unwrap().entries.contains_key("mnt"));
+    assert_eq!(vfs.match_mount_point("/mnt/./file/..").unwrap(), (String::from("/mnt"), String::from("/")));
+    assert!(vfs.lookup_path("/mnt/../mnt/./file").is_ok());
+    assert!(matches!(vfs.lookup_path("/missing/../bin"), Err(Errno::ENOENT(_))));
+    assert!(matches!(vfs.remove_file("/bin/.."), Err(Errno::EINVAL(_))));
+  }
+
+  #[test]
+  fn permissions_are_enforced() {
+    let mut vfs = vfs_with_binfs_root();
+    let chmod = |vfs: &mut VFS, pathname: &str, user: u8, group: u8, others: u8| {
+      let mode = vfs.stat(pathname).unwrap().mode;
+      vfs.change_mode(pathname, mode.with_user(user).with_group(group).with_others(others)).unwrap();
+    };
+    chmod(&mut vfs, "/etc", 0o7, 0o5, 0o5);
+    chmod(&mut vfs, PASSWD_PATH, 0o6, 0o4, 0o4);
+    vfs.create_file("/etc/shadow").unwrap();
+    chmod(&mut vfs, "/etc/shadow", 0o0, 0o0, 0o0);
+    vfs.create_dir("/root").unwrap();
+    chmod(&mut vfs, "/root", 0o7, 0o0, 0o0);
+    vfs.create_file("/root/notes").unwrap();
+    vfs.create_dir("/tmp").unwrap();
+    let tmp_mode = vfs.stat("/tmp").unwrap().mode.with_user(0o7).with_group(0o7).with_others(0o7).with_sticky(true);
+    vfs.change_mode("/tmp", tmp_mode).unwrap();
+
+    // Root is not stopped by mode bits
+    assert!(vfs.read_file("/etc/shadow", EVERYTHING).is_ok());
+
+    vfs.set_current_ids(1000, 100);
+    assert!(vfs.read_file(PASSWD_PATH, EVERYTHING).is_ok());
+    assert!(matches!(vfs.write_file(PASSWD_PATH, b""), Err(Errno::EACCES(_))));
+    assert!(matches!(vfs.read_file("/etc/shadow", EVERYTHING), Err(Errno::EACCES(_))));
+    assert!(matches!(vfs.remove_file(PASSWD_PATH), Err(Errno::EACCES(_))));
+    assert!(matches!(vfs.create_file("/etc/motd"), Err(Errno::EACCES(_))));
+    assert!(matches!(vfs.change_mode(PASSWD_PATH, FileMode::zero()), Err(Errno::EPERM(_))));
+    assert!(matches!(vfs.change_owners("/tmp", 1000, 100), Err(Errno::EPERM(_))));
+    // Neither listed nor searched
+    assert!(matches!(vfs.read_dir("/root"), Err(Errno::EACCES(_))));
+    assert!(matches!(vfs.lookup_path("/root/notes"), Err(Errno::EACCES(_))));
+
+    // Owner class goes first, even if others may do more
+    vfs.create_file("/tmp/mine").unwrap();
+    chmod(&mut vfs, "/tmp/mine", 0o0, 0o6, 0o6);
+    assert!(matches!(vfs.read_file("/tmp/mine", EVERYTHING), Err(Errno::EACCES(_))));
+
+    // Only owner removes from sticky directory
+    vfs.set_current_ids(1001, 100);
+    assert!(matches!(vfs.remove_file("/tmp/mine"), Err(Errno::EPERM(_))));
+    vfs.set_current_ids(1000, 100);
+    vfs.remove_file("/tmp/mine").unwrap();
+  }
+
+  #[test]
+  fn read_only_mount_rejects_changes() {
+    let mut vfs = vfs_with_binfs_root();
+    vfs.create_dir("/mnt").unwrap();
+    let mut mounted = BinFilesytem::new();
+    mounted.create_file("/file").unwrap();
+    mounted.write_file("/file", b"hello").unwrap();
+    vfs.mount_points.insert(String::from("/mnt"), MountedFilesystem {
+      r#type: FilesystemType::binfs,
+      driver: Box::new(mounted),
+      flags: MountFlags { read_only: true, ..MountFlags::default() },
+      source: String::new(),
+    });
+
+    assert_eq!(vfs.read_file("/mnt/file", EVERYTHING).unwrap(), b"hello");
+    assert!(vfs.read_dir("/mnt").unwrap().entries.contains_key("file"));
+    let mode = vfs.stat("/mnt/file").unwrap().mode;
+    assert!(matches!(vfs.create_file("/mnt/new"), Err(Errno::EROFS(_))));
+    assert!(matches!(vfs.create_dir("/mnt/dir"), Err(Errno::EROFS(_))));
+    assert!(matches!(vfs.write_file("/mnt/file", b"bye"), Err(Errno::EROFS(_))));
+    assert!(matches!(vfs.remove_file("/mnt/file"), Err(Errno::EROFS(_))));
+    assert!(matches!(vfs.change_mode("/mnt/file", mode.with_others(0o7)), Err(Errno::EROFS(_))));
+    assert_eq!(vfs.read_file("/mnt/file", EVERYTHING).unwrap(), b"hello");
+
+    // The rest of the tree is not
+    vfs.create_file("/new").unwrap();
+  }
+
+  #[test]
+  fn unlink_and_rmdir_check_file_type() {
+    let mut vfs = vfs_with_binfs_root();
+    vfs.create_dir("/var").unwrap();
+    vfs.create_file("/var/log").unwrap();
+
+    assert!(matches!(vfs.unlink("/var"), Err(Errno::EISDIR(_))));
+    assert!(matches!(vfs.rmdir("/var/log"), Err(Errno::ENOTDIR(_))));
+    assert!(matches!(vfs.rmdir("/var"), Err(Errno::ENOTEMPTY(_))));
+    assert!(matches!(vfs.rmdir("/"), Err(Errno::EBUSY(_))));
+    vfs.unlink("/var/log").unwrap();
+    vfs.rmdir("/var").unwrap();
+    assert!(matches!(vfs.lookup_path("/var"), Err(Errno::ENOENT(_))));
+  }
+
+  #[test]
+  fn rename_stays_within_filesystem() {
+    let mut vfs = vfs_with_binfs_root();
+    vfs.create_dir("/mnt").unwrap();
+    vfs.mount_points.insert(String::from("/mnt"), MountedFilesystem {
+      r#type: FilesystemType::binfs,
+      driver: Box::new(BinFilesytem::new()),
+      flags: MountFlags::default(),
+      source: String::new(),
+    });
+    vfs.create_file("/file").unwrap();
+    vfs.write_file("/file", b"hello").unwrap();
+
+    vfs.rename("/file", "/renamed").unwrap();
+    assert!(matches!(vfs.lookup_path("/file"), Err(Errno::ENOENT(_))));
+    assert_eq!(vfs.read_file("/renamed", EVERYTHING).unwrap(), b"hello");
+
+    assert!(matches!(vfs.rename("/renamed", "/mnt/file"), Err(Errno::EXDEV(_))));
+    assert!(matches!(vfs.rename("/mnt", "/media"), Err(Errno::EBUSY(_))));
+    assert!(matches!(vfs.rename("/renamed/..", "/dir"), Err(Errno::EINVAL(_))));
+  }
+
+  #[test]
+  fn changes_are_reported_to_watches() {
+    use crate::eunix::watch::WatchEvent;
+
+    let mut vfs = vfs_with_binfs_root();
+    vfs.create_dir("/etc").unwrap();
+    let etc = vfs.watches.add(1, "/etc", IN_CREATE | IN_DELETE);
+    let motd = vfs.watches.add(1, "/etc/motd", IN_MODIFY);
+
+    vfs.create_file("/etc/motd").unwrap();
+    vfs.write_file("/etc/motd", b"hello").unwrap();
+    vfs.rename("/etc/motd", "/etc/issue").unwrap();
+    vfs.unlink("/etc/issue").unwrap();
+    // Failed changes are not reported
+    assert!(vfs.unlink("/etc/issue").is_err());
+
+    let event = |watch_descriptor, mask, name: Option<&str>| WatchEvent { watch_descriptor, mask, name: name.map(String::from) };
+    assert_eq!(vfs.watches.take_events(1), vec![
+      event(etc, IN_CREATE, Some("motd")),
+      event(motd, IN_MODIFY, None),
+      event(etc, IN_DELETE, Some("motd")),
+      event(etc, IN_CREATE, Some("issue")),
+      event(etc, IN_DELETE, Some("issue")),
+    ]);
+  }
+
+  #[test]
+  fn execute_check_uses_supplementary_groups() {
+    let mut vfs = VFS {
+      mount_points: BTreeMap::new(),
+      bind_mounts: BTreeMap::new(),
+      open_files: BTreeMap::new(),
+      current_uid: 1000,
+      current_gid: 100,
+      current_sgids: Vec::new(),
+      current_tty: None,
+      current_dir: String::from("/"),
+      watches: Watches::default(),
+    };
+    // ------x---, owned by root:wheel
+    let vinode = VINode {
+      mode: FileMode::zero().with_group(0b001),
+      uid: ROOT_UID,
+      gid: 10,
+      ..Default::default()
+    };
+
+    assert!(matches!(vfs.execute_check(&vinode), Err(Errno::EACCES(_))));
+
+    vfs.current_sgids = vec![100, 10];
+    assert!(vfs.execute_check(&vinode).is_ok());
+  }
+
 }
 
 #[cfg(test)]
@@ -875,37 +2217,51 @@ mod vfs_split_path_tests {
   #[test]
   fn split_path_zero_length() {
     match VFS::split_path("") {
-      Err(errno) => assert_eq!(errno, Errno::EINVAL(String::from("zero-length path"))),
+      Err(errno) => assert_eq!(errno, Errno::EINVAL(String::from("zero-length path").into())),
       _ => unreachable!(),
     };
   }
   #[test]
   fn split_path_invalid_1() {
     match VFS::split_path("test1") {
-      Err(errno) => assert_eq!(errno, Errno::EINVAL(String::from("path must start with '/'"))),
+      Err(errno) => assert_eq!(errno, Errno::EINVAL(String::from("path must start with '/'").into())),
       _ => unreachable!(),
     };
   }
   #[test]
   fn split_path_invalid_1_trailing() {
     match VFS::split_path("test1/") {
-      Err(errno) => assert_eq!(errno, Errno::EINVAL(String::from("path must start with '/'"))),
+      Err(errno) => assert_eq!(errno, Errno::EINVAL(String::from("path must start with '/'").into())),
       _ => unreachable!(),
     };
   }
   #[test]
   fn split_path_invalid_2() {
     match VFS::split_path("test1/test2") {
-      Err(errno) => assert_eq!(errno, Errno::EINVAL(String::from("path must start with '/'"))),
+      Err(errno) => assert_eq!(errno, Errno::EINVAL(String::from("path must start with '/'").into())),
       _ => unreachable!(),
     };
   }
   #[test]
   fn split_path_invalid_3() {
     match VFS::split_path("test1/test2/test3") {
-      Err(errno) => assert_eq!(errno, Errno::EINVAL(String::from("path must start with '/'"))),
+      Err(errno) => assert_eq!(errno, Errno::EINVAL(String::from("path must start with '/'").into())),
       _ => unreachable!(),
     };
   }
+  #[test]
+  fn split_path_invalid_nul() {
+    assert!(matches!(VFS::split_path("/test1\0/test2"), Err(Errno::EINVAL(_))));
+  }
+  #[test]
+  fn split_path_too_long() {
+    let name = "a".repeat(NAME_MAX);
+    assert_eq!(VFS::split_path(&format!("/{name}")).unwrap(), (Vec::new(), name.clone()));
+    assert!(matches!(VFS::split_path(&format!("/{name}a/test1")), Err(Errno::ENAMETOOLONG(_))));
+
+    let pathname = format!("/{name}").repeat(PATH_MAX / (NAME_MAX + 1) - 1);
+    assert!(VFS::split_path(&pathname).is_ok());
+    assert!(matches!(VFS::split_path(&format!("{pathname}/{name}")), Err(Errno::ENAMETOOLONG(_))));
+  }
 }
 // vim:ts=2 sw=2
@@ -0,0 +1,451 @@
+use std::collections::BTreeMap;
+use std::io::{self, prelude::*, SeekFrom};
+
+use super::devices::BlockStorage;
+
+/// First bytes of journal, anything else there is not a journal
+pub const JOURNAL_MAGIC: [u8; 4] = *b"e5jl";
+/// `state` of journal with nothing to replay
+pub const JOURNAL_EMPTY: u32 = 0;
+/// `state` of journal with a whole transaction, which may not be
+/// in its place yet
+pub const JOURNAL_COMMITTED: u32 = 1;
+/// Magic, state and count of records
+pub const JOURNAL_HEADER_SIZE: u64 = 12;
+/// Address (u64) and length (u32) before bytes of every record
+const RECORD_HEADER_SIZE: u64 = 12;
+
+/// Write-ahead log over another storage, kept in its region
+/// `[journal_address, journal_address + journal_size)`.
+///
+/// Writes between `begin` and `commit` stay in memory, where reads see
+/// them, until `commit`. It writes them all to the journal first and
+/// to their places after that. If host goes down in between, `replay`
+/// on next mount puts them in place, so storage has either all writes
+/// of a transaction or none of them. `abort` drops writes of
+/// transaction instead, and so does `commit` of one that doesn't fit
+/// in the journal. Writes outside of transactions
+/// and `write_direct` are not journaled at all
+#[derive(Debug)]
+pub struct JournaledStorage {
+  storage: Box<dyn BlockStorage>,
+  journal_address: u64,
+  journal_size: u64,
+  /// Writes since last commit: `(address, bytes)`, in order
+  pending: Vec<(u64, Vec<u8>)>,
+  /// Count of `pending` writes when every transaction that is not
+  /// committed yet was begun, the innermost last. Nested ones are
+  /// committed with the outermost one
+  savepoints: Vec<usize>,
+  position: u64,
+}
+
+impl JournaledStorage {
+  pub fn new(storage: Box<dyn BlockStorage>, journal_address: u64, journal_size: u64) -> Self {
+    Self {
+      storage,
+      journal_address,
+      journal_size,
+      pending: Vec::new(),
+      savepoints: Vec::new(),
+      position: 0,
+    }
+  }
+
+  /// Make journal region an empty journal, for mkfs
+  pub fn format(&mut self) -> io::Result<()> {
+    if !self.has_journal() {
+      return Ok(());
+    }
+
+    self.write_header(JOURNAL_EMPTY, 0)
+  }
+
+  /// Write `buf` at current position right away, past the journal
+  pub fn write_direct(&mut self, buf: &[u8]) -> io::Result<()> {
+    self.storage.seek(SeekFrom::Start(self.position))?;
+    self.storage.write_all(buf)?;
+    // Pending writes under it would bring old bytes back on commit
+    for (address, bytes) in &mut self.pending {
+      overlay(bytes, *address, buf, self.position);
+    }
+    self.position += buf.len() as u64;
+
+    Ok(())
+  }
+
+  pub fn begin(&mut self) {
+    self.savepoints.push(self.pending.len());
+  }
+
+  /// End transaction, dropping its pending writes, but not those
+  /// of transactions it is nested in
+  pub fn abort(&mut self) {
+    if let Some(savepoint) = self.savepoints.pop() {
+      self.pending.truncate(savepoint);
+    }
+  }
+
+  /// Whether transaction that writes `extents`, lengths of
+  /// places it writes to that don't touch, can be committed
+  pub fn fits(&self, extents: impl IntoIterator<Item = u64>) -> bool {
+    if !self.has_journal() {
+      return true;
+    }
+
+    let records_size = extents
+      .into_iter()
+      .map(|length| RECORD_HEADER_SIZE + length)
+      .sum::<u64>();
+
+    JOURNAL_HEADER_SIZE + records_size <= self.journal_size
+  }
+
+  /// End transaction, putting pending writes to storage through the
+  /// journal if it is the outermost one. Writes to the same place
+  /// take room in the journal once. Storage of filesystem made before
+  /// journals has no room for one, writes are put in place there.
+  ///
+  /// Errors:
+  /// StorageFull -> transaction doesn't fit in the journal, nothing
+  ///                of it is written
+  pub fn commit(&mut self) -> io::Result<()> {
+    self.savepoints.pop();
+    if !self.savepoints.is_empty() || self.pending.is_empty() {
+      return Ok(());
+    }
+
+    let records = coalesced(std::mem::take(&mut self.pending));
+    if !self.has_journal() {
+      return self.apply(&records);
+    }
+    if !self.fits(records.iter().map(|(_, bytes)| bytes.len() as u64)) {
+      let size = records.iter().map(|(_, bytes)| bytes.len()).sum::<usize>();
+      return Err(io::Error::new(
+        io::ErrorKind::StorageFull,
+        format!("journal: transaction of {size} bytes doesn't fit in journal of {} bytes", self.journal_size),
+      ));
+    }
+    let records_size = records
+      .iter()
+      .map(|(_, bytes)| RECORD_HEADER_SIZE + bytes.len() as u64)
+      .sum::<u64>();
+
+    let mut journal = Vec::with_capacity(records_size as usize);
+    for (address, bytes) in &records {
+      journal.extend(address.to_le_bytes());
+      journal.extend((bytes.len() as u32).to_le_bytes());
+      journal.extend(bytes);
+    }
+    self.storage.seek(SeekFrom::Start(self.journal_address + JOURNAL_HEADER_SIZE))?;
+    self.storage.write_all(&journal)?;
+    self.storage.flush()?;
+
+    // From here on the transaction survives a crash
+    self.write_header(JOURNAL_COMMITTED, records.len() as u32)?;
+    self.apply(&records)?;
+    self.write_header(JOURNAL_EMPTY, 0)
+  }
+
+  fn has_journal(&self) -> bool {
+    self.journal_size >= JOURNAL_HEADER_SIZE
+  }
+
+  /// Put transaction that was left in journal in place.
+  /// Returns: whether there was one
+  pub fn replay(&mut self) -> io::Result<bool> {
+    if !self.has_journal() {
+      return Ok(false);
+    }
+
+    let mut header = [0u8; JOURNAL_HEADER_SIZE as usize];
+    self.storage.seek(SeekFrom::Start(self.journal_address))?;
+    self.storage.read_exact(&mut header)?;
+    let state = u32::from_le_bytes(header[4..8].try_into().unwrap());
+    let records_count = u32::from_le_bytes(header[8..12].try_into().unwrap());
+    if header[..4] != JOURNAL_MAGIC || state != JOURNAL_COMMITTED {
+      return Ok(false);
+    }
+
+    let mut journal = vec![0u8; (self.journal_size - JOURNAL_HEADER_SIZE) as usize];
+    self.storage.read_exact(&mut journal)?;
+
+    let corrupted = || io::Error::new(io::ErrorKind::InvalidData, "journal: record past end of journal");
+    let mut records = Vec::new();
+    let mut offset = 0;
+    for _ in 0..records_count {
+      let record_header = journal.get(offset..offset + RECORD_HEADER_SIZE as usize).ok_or_else(corrupted)?;
+      let address = u64::from_le_bytes(record_header[..8].try_into().unwrap());
+      let length = u32::from_le_bytes(record_header[8..].try_into().unwrap()) as usize;
+      offset += RECORD_HEADER_SIZE as usize;
+
+      let bytes = journal.get(offset..offset + length).ok_or_else(corrupted)?;
+      records.push((address, bytes.to_owned()));
+      offset += length;
+    }
+
+    self.apply(&records)?;
+    self.write_header(JOURNAL_EMPTY, 0)?;
+
+    Ok(true)
+  }
+
+  fn apply(&mut self, records: &[(u64, Vec<u8>)]) -> io::Result<()> {
+    for (address, bytes) in records {
+      self.storage.seek(SeekFrom::Start(*address))?;
+      self.storage.write_all(bytes)?;
+    }
+
+    self.storage.flush()
+  }
+
+  fn write_header(&mut self, state: u32, records_count: u32) -> io::Result<()> {
+    let mut header = Vec::with_capacity(JOURNAL_HEADER_SIZE as usize);
+    header.extend(JOURNAL_MAGIC);
+    header.extend(state.to_le_bytes());
+    header.extend(records_count.to_le_bytes());
+
+    self.storage.seek(SeekFrom::Start(self.journal_address))?;
+    self.storage.write_all(&header)?;
+    self.storage.flush()
+  }
+}
+
+/// Writes that have the same effect as `records` done in order, none
+/// of them touching another: ones that overlap or adjoin are merged
+fn coalesced(records: Vec<(u64, Vec<u8>)>) -> Vec<(u64, Vec<u8>)> {
+  let mut extents = BTreeMap::<u64, Vec<u8>>::new();
+  for (address, bytes) in records {
+    let end = address + bytes.len() as u64;
+    let touching = extents
+      .range(..=end)
+      .rev()
+      .take_while(|(&extent_address, extent)| extent_address + extent.len() as u64 >= address)
+      .map(|(&extent_address, _)| extent_address)
+      .collect::<Vec<_>>();
+
+    let start = touching.iter().copied().fold(address, u64::min);
+    let mut merged = Vec::new();
+    for extent_address in touching.into_iter().rev() {
+      let extent = extents.remove(&extent_address).unwrap();
+      let offset = (extent_address - start) as usize;
+      merged.resize(merged.len().max(offset + extent.len()), 0);
+      merged[offset..][..extent.len()].copy_from_slice(&extent);
+    }
+    let offset = (address - start) as usize;
+    merged.resize(merged.len().max(offset + bytes.len()), 0);
+    merged[offset..][..bytes.len()].copy_from_slice(&bytes);
+
+    extents.insert(start, merged);
+  }
+
+  extents.into_iter().collect()
+}
+
+/// Copy part of `source` (at `source_address`) that overlaps
+/// `target` (at `target_address`) into `target`
+fn overlay(target: &mut [u8], target_address: u64, source: &[u8], source_address: u64) {
+  let start = target_address.max(source_address);
+  let end = (target_address + target.len() as u64).min(source_address + source.len() as u64);
+
+  if start < end {
+    target[(start - target_address) as usize..(end - target_address) as usize]
+      .copy_from_slice(&source[(start - source_address) as usize..(end - source_address) as usize]);
+  }
+}
+
+impl Read for JournaledStorage {
+  fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+    self.storage.seek(SeekFrom::Start(self.position))?;
+    let count = self.storage.read(buf)?;
+    // Pending writes are newer than storage
+    for (address, bytes) in &self.pending {
+      overlay(&mut buf[..count], self.position, bytes, *address);
+    }
+    self.position += count as u64;
+
+    Ok(count)
+  }
+}
+
+impl Write for JournaledStorage {
+  fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+    if self.savepoints.is_empty() {
+      self.write_direct(buf)?;
+      return Ok(buf.len());
+    }
+
+    let count = (buf.len() as u64).min(self.storage.size().saturating_sub(self.position)) as usize;
+    if count == 0 {
+      return Ok(0);
+    }
+
+    self.pending.push((self.position, buf[..count].to_owned()));
+    self.position += count as u64;
+
+    Ok(count)
+  }
+
+  /// Flushes storage, not pending writes, that is what `commit` is for
+  fn flush(&mut self) -> io::Result<()> {
+    self.storage.flush()
+  }
+}
+
+impl Seek for JournaledStorage {
+  fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+    let position = match pos {
+      SeekFrom::Start(position) => Some(position),
+      SeekFrom::End(delta) => self.storage.size().checked_add_signed(delta),
+      SeekFrom::Current(delta) => self.position.checked_add_signed(delta),
+    }
+    .ok_or(io::Error::new(io::ErrorKind::InvalidInput, "seek before start of storage"))?;
+
+    self.position = position;
+    Ok(position)
+  }
+}
+
+impl BlockStorage for JournaledStorage {
+  fn size(&self) -> u64 {
+    self.storage.size()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::sync::{Arc, RwLock};
+
+  use super::*;
+  use crate::eunix::devices::MemoryStorage;
+
+  const JOURNAL_ADDRESS: u64 = 16;
+  const JOURNAL_SIZE: u64 = 256;
+
+  fn journaled(buffer: &Arc<RwLock<Vec<u8>>>) -> JournaledStorage {
+    JournaledStorage::new(Box::new(MemoryStorage::new(buffer.clone())), JOURNAL_ADDRESS, JOURNAL_SIZE)
+  }
+
+  #[test]
+  fn writes_reach_storage_on_commit() {
+    let buffer = Arc::new(RwLock::new(vec![0u8; 1024]));
+    let mut storage = journaled(&buffer);
+    storage.format().unwrap();
+
+    storage.begin();
+    storage.seek(SeekFrom::Start(512)).unwrap();
+    storage.write_all(b"eunix").unwrap();
+    assert_eq!(&buffer.read().unwrap()[512..517], [0u8; 5]);
+
+    // Pending writes are seen by reads
+    let mut bytes = [0u8; 7];
+    storage.seek(SeekFrom::Start(511)).unwrap();
+    storage.read_exact(&mut bytes).unwrap();
+    assert_eq!(&bytes, b"\0eunix\0");
+
+    storage.commit().unwrap();
+    assert_eq!(&buffer.read().unwrap()[512..517], b"eunix");
+    assert!(!storage.replay().unwrap());
+  }
+
+  #[test]
+  fn committed_transaction_is_replayed() {
+    let buffer = Arc::new(RwLock::new(vec![0u8; 1024]));
+    let mut storage = journaled(&buffer);
+    storage.format().unwrap();
+    storage.begin();
+    storage.seek(SeekFrom::Start(512)).unwrap();
+    storage.write_all(b"eunix").unwrap();
+    storage.seek(SeekFrom::Start(600)).unwrap();
+    storage.write_all(b"e5fs").unwrap();
+    storage.commit().unwrap();
+
+    // Crash after journal was committed, before writes were in place
+    let mut crashed = buffer.read().unwrap().clone();
+    crashed[512..517].fill(0);
+    crashed[600..604].fill(0);
+    let header = JOURNAL_MAGIC.into_iter()
+      .chain(JOURNAL_COMMITTED.to_le_bytes())
+      .chain(2u32.to_le_bytes())
+      .collect::<Vec<u8>>();
+    crashed[JOURNAL_ADDRESS as usize..][..header.len()].copy_from_slice(&header);
+    *buffer.write().unwrap() = crashed;
+
+    let mut storage = journaled(&buffer);
+    assert!(storage.replay().unwrap());
+    assert_eq!(&buffer.read().unwrap()[512..517], b"eunix");
+    assert_eq!(&buffer.read().unwrap()[600..604], b"e5fs");
+    assert!(!storage.replay().unwrap());
+  }
+
+  #[test]
+  fn aborted_transaction_is_dropped() {
+    let buffer = Arc::new(RwLock::new(vec![0u8; 1024]));
+    let mut storage = journaled(&buffer);
+    storage.format().unwrap();
+
+    storage.begin();
+    storage.seek(SeekFrom::Start(512)).unwrap();
+    storage.write_all(b"eunix").unwrap();
+    // Nested one only drops its own writes
+    storage.begin();
+    storage.seek(SeekFrom::Start(600)).unwrap();
+    storage.write_all(b"e5fs").unwrap();
+    storage.abort();
+    storage.commit().unwrap();
+    assert_eq!(&buffer.read().unwrap()[512..517], b"eunix");
+    assert_eq!(&buffer.read().unwrap()[600..604], [0u8; 4]);
+
+    storage.begin();
+    storage.seek(SeekFrom::Start(512)).unwrap();
+    storage.write_all(b"EUNIX").unwrap();
+    storage.abort();
+    storage.commit().unwrap();
+    assert_eq!(&buffer.read().unwrap()[512..517], b"eunix");
+  }
+
+  #[test]
+  fn transaction_bigger_than_journal_is_dropped() {
+    let buffer = Arc::new(RwLock::new(vec![0u8; 1024]));
+    let mut storage = journaled(&buffer);
+    storage.format().unwrap();
+
+    storage.begin();
+    storage.seek(SeekFrom::Start(512)).unwrap();
+    storage.write_all(&[1; JOURNAL_SIZE as usize]).unwrap();
+    let error = storage.commit().unwrap_err();
+    assert_eq!(error.kind(), io::ErrorKind::StorageFull);
+    assert!(buffer.read().unwrap()[512..].iter().all(|&byte| byte == 0));
+    assert!(!storage.replay().unwrap());
+
+    // Writes over the same bytes take room once
+    storage.begin();
+    for _ in 0..JOURNAL_SIZE {
+      storage.seek(SeekFrom::Start(512)).unwrap();
+      storage.write_all(b"eunix").unwrap();
+    }
+    storage.seek(SeekFrom::Start(515)).unwrap();
+    storage.write_all(b"NIX").unwrap();
+    storage.commit().unwrap();
+    assert_eq!(&buffer.read().unwrap()[512..519], b"eunNIX\0");
+  }
+
+  #[test]
+  fn direct_writes_are_not_undone_by_commit() {
+    let buffer = Arc::new(RwLock::new(vec![0u8; 1024]));
+    let mut storage = journaled(&buffer);
+    storage.format().unwrap();
+
+    storage.begin();
+    storage.seek(SeekFrom::Start(512)).unwrap();
+    storage.write_all(b"metadata").unwrap();
+    storage.seek(SeekFrom::Start(516)).unwrap();
+    storage.write_direct(b"DATA").unwrap();
+    assert_eq!(&buffer.read().unwrap()[516..520], b"DATA");
+
+    storage.commit().unwrap();
+    assert_eq!(&buffer.read().unwrap()[512..520], b"metaDATA");
+  }
+}
+
+// vim:ts=2 sw=2
@@ -1,14 +1,33 @@
-use crate::binaries::PASSWD_PATH;
-use crate::eunix::devfs::DeviceFilesystem;
-use crate::eunix::binfs::BinFilesytem;
-use crate::eunix::fs::{FileDescription, FileDescriptor, VFS, OpenMode, MountedFilesystem, OpenFlags};
+use crate::eunix::devfs::{DeviceFilesystem, DISK_BY_LABEL_PATH, DISK_BY_UUID_PATH};
+use crate::eunix::devices::{BlockStorage, LoopDevice, RamDisk, TTYMode, CharDevice, DeviceDriver, CONTROLLING_TTY_NAME, NET_MAJOR, SD_MAJOR, SD_MINORS_PER_DISK, MAX_FRAME_SIZE, disk_name};
+use crate::eunix::netfs::NetFilesystem;
+use crate::eunix::overlayfs::{OverlayFilesystem, Layer};
+use crate::eunix::net::{self, Packet, Socket, SocketAddress, SocketDescriptor, SocketState, SocketType, CONNECT_TIMEOUT, EPHEMERAL_PORTS, FLAG_ACK, FLAG_FIN, ECHO_REQUEST, PACKET_HEADER_SIZE};
+use crate::eunix::binfs::{BinFilesytem, Binary, BinaryFn};
+use crate::eunix::procfs::{self, ProcessFilesystem};
+use crate::eunix::sysfs::SystemFilesystem;
+use crate::eunix::script;
+use crate::binaries::{EXIT_FAILURE, DEV_PATH};
+use crate::eunix::fs::{FileDescription, FileDescriptor, VFS, OpenMode, MountedFilesystem, MountFlags, BindMount, OpenFlags, VfsContext};
 use crate::eunix;
-use crate::machine::{MachineDeviceTable, VirtualDeviceType};
-use std::collections::BTreeMap;
-
-use super::fs::{AddressSize, Filesystem, FilesystemType, VDirectory, Id, VINode, FileStat, NOBODY_UID, NOBODY_GID};
-use super::users::Passwd;
+use crate::machine::{MachineDeviceTable, MachineDevice, MachineResources, MachineClock, VirtualDeviceType};
+use std::sync::{Arc, Mutex, MutexGuard, MappedMutexGuard, RwLock, RwLockReadGuard, RwLockWriteGuard};
+use std::ops::{Deref, DerefMut};
+use std::collections::{BTreeMap, BTreeSet};
+use std::fmt;
+use std::io;
+use serde::{Serialize, Deserialize};
+
+use super::fs::{AddressSize, Filesystem, FilesystemType, VDirectoryEntry, Id, DeviceNumber, FileMode, PERM_R, PERM_W, PERM_X, FileStat, FileModeType, NOBODY_UID, NOBODY_GID, EVERYTHING};
+use super::users::{Passwd, Group};
+use super::userdb::{UserDb, FileUserDb};
 use super::virtfs::{VirtFsFilesystem, Payload};
+use super::watch::{Watches, WatchDescriptor, WatchEvent, IN_ALL_EVENTS, IN_MODIFY};
+use super::signal::{self, Signal, Disposition, SIGCHLD, SIGINT, SIGKILL};
+use super::clock::{self, Clock};
+use super::rng::{self, Rng};
+use super::dmesg::{Dmesg, DmesgRecord};
+use super::console;
 
 pub type Args = Vec<String>;
 pub type UnixtimeSize = u64;
@@ -23,54 +42,319 @@ pub struct Times {
   pub btime: UnixtimeSize,
 }
 
+/// How `Kernel::mount_with_options` mounts filesystem
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct MountOptions {
+  /// Unlocks encrypted filesystem
+  pub passphrase: Option<String>,
+  pub flags: MountFlags,
+  /// Snapshot to mount instead of the current tree, read-only
+  pub snapshot: Option<String>,
+  /// Directory that overlayfs shows, with changes going to `upperdir`
+  pub lowerdir: Option<String>,
+  pub upperdir: Option<String>,
+}
+
+/// Line of mount table, like one of /proc/mounts
 #[derive(Debug, Clone, PartialEq, Eq)]
-pub enum Errno {
-  /// Permission denied
-  EACCES(String),
-  /// Operation not permitted
-  EPERM(String),
-  /// Is a directory
-  EISDIR(String),
-  /// Not a directory
-  ENOTDIR(String),
-  /// Name too long
-  ENAMETOOLONG(String),
-  /// Not implemented
-  ENOSYS(String),
-  /// No such entity
-  ENOENT(String),
-  /// I/O Error
-  EIO(String),
-  /// Invalid argument
-  EINVAL(String),
-  /// Illegal byte sequence
-  EILSEQ(String),
-  /// No such process
-  ESRCH(String),
-  /// Bad filesystem (not standart)
-  EBADFS(String),
-  /// Bad file desctiptor
-  EBADFD(String),
-  /// File exists
-  EEXIST(String),
-  /// No space left on dev
-  ENOSPC(String),
+pub struct MountEntry {
+  /// What is mounted, type of filesystem if it was mounted
+  /// from nothing, like procfs
+  pub source: String,
+  pub target: String,
+  pub r#type: String,
+  pub flags: MountFlags,
+}
+
+/// `Errno` with variants of `$name = $code` lines, numbers are the
+/// ones of Linux, and matches over all of them, so a new error is
+/// added in one place
+macro_rules! errno {
+  (
+    $(#[$meta:meta])*
+    pub enum Errno {
+      $(
+        $(#[$variant_meta:meta])*
+        $name:ident = $code:literal,
+      )*
+    }
+  ) => {
+    $(#[$meta])*
+    pub enum Errno {
+      $(
+        $(#[$variant_meta])*
+        $name(ErrnoMessage),
+      )*
+    }
+
+    impl Errno {
+      fn code_and_name(&self) -> (i32, &'static str) {
+        match self {
+          $(Errno::$name(_) => ($code, stringify!($name)),)*
+        }
+      }
+
+      /// Returns: message, with messages of errors it was caused by
+      pub fn message(&self) -> &ErrnoMessage {
+        match self {
+          $(Errno::$name(message))|* => message,
+        }
+      }
+
+      fn message_mut(&mut self) -> &mut ErrnoMessage {
+        match self {
+          $(Errno::$name(message))|* => message,
+        }
+      }
+    }
+  };
+}
+
+errno! {
+  /// Error of kernel, filesystem or driver: what went wrong, with
+  /// message saying what was being done, outermost operation first,
+  /// like `read_file /etc/motd: e5fs: no such file (ENOENT)`.
+  /// Errors it was caused by are its `source`s
+  #[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+  pub enum Errno {
+    /// Permission denied
+    EACCES = 13,
+    /// Operation not permitted
+    EPERM = 1,
+    /// Is a directory
+    EISDIR = 21,
+    /// Not a directory
+    ENOTDIR = 20,
+    /// Name too long
+    ENAMETOOLONG = 36,
+    /// Not implemented
+    ENOSYS = 38,
+    /// No such entity
+    ENOENT = 2,
+    /// I/O Error
+    EIO = 5,
+    /// Invalid argument
+    EINVAL = 22,
+    /// Illegal byte sequence
+    EILSEQ = 84,
+    /// No such process
+    ESRCH = 3,
+    /// Bad filesystem (not standart), it is `EUCLEAN` of Linux,
+    /// as ext4 uses it for corrupted filesystems
+    EBADFS = 117,
+    /// Bad file desctiptor
+    EBADFD = 77,
+    /// Address already in use
+    EADDRINUSE = 98,
+    /// Connection refused
+    ECONNREFUSED = 111,
+    /// Connection timed out
+    ETIMEDOUT = 110,
+    /// Socket is not connected
+    ENOTCONN = 107,
+    /// Message too long
+    EMSGSIZE = 90,
+    /// File exists
+    EEXIST = 17,
+    /// No space left on dev
+    ENOSPC = 28,
+    /// Inappropriate ioctl for device (not a typewriter)
+    ENOTTY = 25,
+    /// No such device or address
+    ENXIO = 6,
+    /// Device or resource busy
+    EBUSY = 16,
+    /// Cross-device link
+    EXDEV = 18,
+    /// Too many levels of symbolic links
+    ELOOP = 40,
+    /// Read-only file system
+    EROFS = 30,
+    /// No data available (no such attribute)
+    ENODATA = 61,
+    /// Operation not supported
+    ENOTSUP = 95,
+    /// Argument list too long (value of attribute too big)
+    E2BIG = 7,
+    /// Directory not empty
+    ENOTEMPTY = 39,
+    /// Illegal seek
+    ESPIPE = 29,
+    /// No child processes
+    ECHILD = 10,
+    /// Out of memory
+    ENOMEM = 12,
+  }
+}
+
+impl Errno {
+  pub fn code(&self) -> i32 {
+    self.code_and_name().0
+  }
+
+  /// Returns: name like `ENOENT`
+  pub fn name(&self) -> &'static str {
+    self.code_and_name().1
+  }
+
+  /// Same error, caused by this one, with `context` - operation and
+  /// what it was done on, like `open /etc/passwd` - as its message
+  pub fn context(self, context: impl fmt::Display) -> Self {
+    let mut errno = self.clone();
+    *errno.message_mut() = ErrnoMessage {
+      message: context.to_string(),
+      cause: Some(Box::new(self)),
+    };
+    errno
+  }
+}
+
+impl fmt::Display for Errno {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{} ({})", self.message(), self.name())
+  }
+}
+
+impl std::error::Error for Errno {
+  fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+    self.message().cause
+      .as_deref()
+      .map(|cause| cause as &(dyn std::error::Error + 'static))
+  }
+}
+
+/// Message of `Errno`, with error it was caused by.
+/// Displayed with messages of causes after it, separated by `: `
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct ErrnoMessage {
+  message: String,
+  cause: Option<Box<Errno>>,
+}
+
+impl ErrnoMessage {
+  /// Returns: error this one was caused by
+  pub fn cause(&self) -> Option<&Errno> {
+    self.cause.as_deref()
+  }
+}
+
+impl fmt::Display for ErrnoMessage {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}", self.message)?;
+    if let Some(cause) = &self.cause {
+      write!(f, ": {}", cause.message())?;
+    }
+    Ok(())
+  }
+}
+
+impl From<String> for ErrnoMessage {
+  fn from(message: String) -> Self {
+    Self { message, cause: None }
+  }
+}
+
+impl From<&str> for ErrnoMessage {
+  fn from(message: &str) -> Self {
+    Self::from(message.to_owned())
+  }
+}
+
+/// Errors of host, like ones of image files and sockets of network interfaces
+impl From<io::Error> for Errno {
+  fn from(error: io::Error) -> Self {
+    let message = ErrnoMessage::from(error.to_string());
+    match error.kind() {
+      io::ErrorKind::NotFound => Errno::ENOENT(message),
+      io::ErrorKind::PermissionDenied => Errno::EACCES(message),
+      io::ErrorKind::AlreadyExists => Errno::EEXIST(message),
+      io::ErrorKind::InvalidInput => Errno::EINVAL(message),
+      io::ErrorKind::InvalidData => Errno::EILSEQ(message),
+      io::ErrorKind::StorageFull => Errno::ENOSPC(message),
+      io::ErrorKind::AddrInUse => Errno::EADDRINUSE(message),
+      io::ErrorKind::ConnectionRefused => Errno::ECONNREFUSED(message),
+      io::ErrorKind::TimedOut => Errno::ETIMEDOUT(message),
+      io::ErrorKind::NotConnected => Errno::ENOTCONN(message),
+      _ => Errno::EIO(message),
+    }
+  }
+}
+
+/// Say what was being done when error happened, see `Errno::context`
+pub trait ErrnoContext<T> {
+  fn with_context<C: fmt::Display>(self, context: impl FnOnce() -> C) -> Result<T, Errno>;
+}
+
+impl<T, E: Into<Errno>> ErrnoContext<T> for Result<T, E> {
+  fn with_context<C: fmt::Display>(self, context: impl FnOnce() -> C) -> Result<T, Errno> {
+    self.map_err(|error| error.into().context(context()))
+  }
 }
 
 pub static KERNEL_MESSAGE_HEADER_ERR: &'static str = "\x1b[93mkernel\x1b[0m";
+/// `KERNEL_MESSAGE_HEADER_ERR`, without color if it is turned off on host
+pub fn kernel_message_header_err() -> &'static str {
+  if console::color() {
+    KERNEL_MESSAGE_HEADER_ERR
+  } else {
+    "kernel"
+  }
+}
+/// Serial port that kernel messages are logged to, if machine has one
+pub const KERNEL_LOG_PATH: &'static str = "/dev/ttyS0";
 pub const ROOT_UID: Id = 0;
+/// Pid of first process, which gets orphans
+pub const INIT_PID: AddressSize = 1;
 pub const ROOT_GID: Id = 0;
+/// Access time newer than modification is set again on read
+/// after this long: a day
+pub const ATIME_INTERVAL: UnixtimeSize = 24 * 60 * 60;
+pub const STDIN_FILENO: FileDescriptor = 0;
+pub const STDOUT_FILENO: FileDescriptor = 1;
+pub const STDERR_FILENO: FileDescriptor = 2;
+
+/// Descriptor open in process. Its file description, with offset,
+/// is shared by descriptors made of it by `dup` and `fork`, so they
+/// read and write one after another, but close on exec is its own
+#[derive(Debug, Clone)]
+pub struct OpenFileDescriptor {
+  pub file_description: Arc<Mutex<FileDescription>>,
+  /// Descriptor is not inherited by spawned processes
+  pub close_on_exec: bool,
+}
+impl OpenFileDescriptor {
+  fn new(file_description: FileDescription, close_on_exec: bool) -> Self {
+    Self {
+      file_description: Arc::new(Mutex::new(file_description)),
+      close_on_exec,
+    }
+  }
+}
 
 #[derive(Debug, Clone)]
 pub struct Process {
   // 0 -> stdin, 1 -> stdout, 2 -> stderr, 3.. -> user-opened
-  pub file_descriptors: BTreeMap<FileDescriptor, FileDescription>,
+  pub file_descriptors: BTreeMap<FileDescriptor, OpenFileDescriptor>,
   // User id
   pub uid: Id,
   /// Parent pid
   pub ppid: AddressSize,
   pub pid: AddressSize,
+  /// Session id - pid of session leader
+  pub sid: AddressSize,
+  /// Name of controlling terminal in devfs (like `tty1`),
+  /// shared by all processes in session
+  pub controlling_tty: Option<String>,
   pub binary: String,
+  /// Open sockets, numbered separately from file descriptors
+  pub sockets: BTreeMap<SocketDescriptor, Socket>,
+  /// Working directory, that relative pathnames start from
+  pub cwd: String,
+  /// Set when process exits, it stays in process table
+  /// as zombie until parent waits for it
+  pub exit_code: Option<AddressSize>,
+  /// Signals sent to process that were not delivered yet
+  pub pending_signals: BTreeSet<Signal>,
 }
 
 impl Process {
@@ -80,12 +364,25 @@ impl Process {
       uid: ROOT_UID,
       ppid: 0,
       pid,
+      sid: pid,
+      controlling_tty: None,
       binary: String::from(bin_pathname),
+      sockets: BTreeMap::new(),
+      cwd: String::from("/"),
+      exit_code: None,
+      pending_signals: BTreeSet::new(),
     };
 
     process
   }
 
+  /// Like Linux, the lowest descriptor that is not open
+  fn lowest_free_descriptor(&self) -> FileDescriptor {
+    (0..)
+      .find(|file_descriptor| !self.file_descriptors.contains_key(file_descriptor))
+      .expect("there are fewer open files than descriptors")
+  }
+
   fn with_ppid(mut self, pid: u32) -> Self {
     self.ppid = pid;
     self
@@ -100,28 +397,78 @@ impl Process {
 
 #[derive(Debug, Clone)]
 pub struct KernelDeviceTable {
-  /// `realpath -> (dev_type, mounted_pathname)` 
-  pub devices: BTreeMap<String, (VirtualDeviceType, Option<String>)>
+  /// `(device, mounted_pathname)` in order of the machine
+  pub devices: Vec<(MachineDevice, Option<String>)>,
+  /// Sizes of RAM disks in bytes, `ram_disks[N]` is `ramN`
+  pub ram_disks: Vec<u64>,
 }
 impl From<MachineDeviceTable> for KernelDeviceTable {
   fn from(mach_dev_table: MachineDeviceTable) -> Self {
     Self {
       devices: mach_dev_table.devices
-        .iter()
-        .map(|(realpath, dev_type)| (realpath.to_owned(), (dev_type.to_owned(), Option::<String>::None)))
+        .into_iter()
+        .map(|device| (device, Option::<String>::None))
         .collect(),
+      ram_disks: mach_dev_table.ram_disks,
+    }
+  }
+}
+
+/// Processes by pid, see `Kernel::processes`
+#[derive(Debug, Default)]
+pub struct ProcessTable {
+  processes: BTreeMap<AddressSize, Process>,
+  /// Pid given to the latest process, next one gets a greater one
+  last_pid: AddressSize,
+}
+
+impl ProcessTable {
+  /// Pid after the latest one that is not taken
+  fn allocate_pid(&mut self) -> AddressSize {
+    loop {
+      self.last_pid = self.last_pid.wrapping_add(1).max(INIT_PID);
+      if !self.processes.contains_key(&self.last_pid) {
+        return self.last_pid;
+      }
     }
   }
 }
 
+impl Deref for ProcessTable {
+  type Target = BTreeMap<AddressSize, Process>;
+
+  fn deref(&self) -> &Self::Target {
+    &self.processes
+  }
+}
+
+impl DerefMut for ProcessTable {
+  fn deref_mut(&mut self) -> &mut Self::Target {
+    &mut self.processes
+  }
+}
+
 type IdMap = BTreeMap<Id, String>;
 
+/// Kernel as one thread of host sees it. VFS, process table and
+/// device table are shared by threads made with `Kernel::fork_thread`,
+/// each behind its own lock, the rest is of this thread. System call
+/// locks what it touches for as long as it needs it: one that waits,
+/// like read of terminal, waits with nothing locked but the device.
+/// Locks that are held together are taken in that order: users,
+/// VFS, processes, devices
 #[derive(Debug)]
 pub struct Kernel {
-  pub vfs: VFS,
-  pub processes: BTreeMap<AddressSize, Process>,
+  /// Mounted filesystems, open files and watches, see `Kernel::vfs`
+  vfs: Arc<Mutex<VFS>>,
+  /// Ids, terminal and directory of current process,
+  /// that VFS is switched to when this thread locks it
+  pub vfs_context: VfsContext,
+  processes: Arc<Mutex<ProcessTable>>,
   pub current_process_id: AddressSize,
-  pub device_table: KernelDeviceTable,
+  device_table: Arc<RwLock<KernelDeviceTable>>,
+  /// Memory and CPUs of the machine
+  pub resources: MachineResources,
   // Current user id
   pub current_uid: Id,
   // Primary group of current user
@@ -132,30 +479,66 @@ pub struct Kernel {
   pub uid_map: IdMap,
   // Map uid => name
   pub gid_map: IdMap,
+  /// Where users and groups come from
+  pub user_db: Arc<Mutex<dyn UserDb>>,
+  /// Set by `reboot` and `poweroff`, init should exit when it's there
+  pub power_action: Arc<Mutex<Option<PowerAction>>>,
+  /// Time of the machine, shared with mounted filesystems
+  pub clock: Arc<dyn Clock>,
+  /// Randomness for /dev/random and password salts
+  pub rng: Arc<dyn Rng>,
+  /// Kernel events, see `Kernel::log`
+  pub dmesg: Arc<Mutex<Dmesg>>,
+  /// Editor of host that `ed` falls back to when EDITOR is not set
+  pub host_editor: Option<String>,
 
   // registered_filesystems: BTreeMap<>,
 }
 
+/// What `reboot` and `poweroff` ask machine to do once init exits
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum PowerAction {
+  Reboot,
+  Poweroff,
+}
+
 pub struct KernelParams {
   pub init: String,
+  pub resources: MachineResources,
+  pub clock: MachineClock,
 }
 
 impl Kernel {
   pub fn new(devices: &MachineDeviceTable, params: KernelParams) -> Self {
     let KernelParams {
       init,
+      resources,
+      clock,
     } = params;
 
     let mut kernel = Self {
-      vfs: VFS {
+      vfs: Arc::new(Mutex::new(VFS {
         mount_points: BTreeMap::new(),
+        bind_mounts: BTreeMap::new(),
         open_files: BTreeMap::new(),
         current_uid: ROOT_UID,
         current_gid: ROOT_GID,
+        current_sgids: vec![ROOT_GID],
+        current_tty: None,
+        current_dir: String::from("/"),
+        watches: Watches::default(),
+      })),
+      vfs_context: VfsContext {
+        uid: ROOT_UID,
+        gid: ROOT_GID,
+        sgids: vec![ROOT_GID],
+        tty: None,
+        dir: String::from("/"),
       },
-      processes: BTreeMap::new(),
+      processes: Arc::new(Mutex::new(ProcessTable::default())),
       current_process_id: 0,
-      device_table: devices.clone().into(),
+      device_table: Arc::new(RwLock::new(devices.clone().into())),
+      resources,
       current_uid: ROOT_UID,
       current_gid: ROOT_GID,
       current_sgids: vec![ROOT_GID],
@@ -167,6 +550,12 @@ impl Kernel {
         (ROOT_GID, String::from("root")),
         (NOBODY_GID, String::from("nobody")),
       ]),
+      user_db: Arc::new(Mutex::new(FileUserDb::default())),
+      power_action: Arc::new(Mutex::new(None)),
+      clock: clock::from_machine_clock(clock),
+      rng: rng::host_rng(),
+      dmesg: Arc::new(Mutex::new(Dmesg::default())),
+      host_editor: None,
     };
 
     // let init_pid = kernel.allocate_pid();
@@ -181,79 +570,204 @@ impl Kernel {
 
     kernel
   }
-  pub fn devices(&self) -> &KernelDeviceTable {
-    &self.device_table
+  pub fn devices(&self) -> RwLockReadGuard<'_, KernelDeviceTable> {
+    self.device_table.read().unwrap()
+  }
+  pub(crate) fn devices_mut(&self) -> RwLockWriteGuard<'_, KernelDeviceTable> {
+    self.device_table.write().unwrap()
+  }
+  /// Bytes of memory taken, which is by RAM disks only
+  pub fn memory_used(&self) -> u64 {
+    self.devices().ram_disks.iter().sum()
   }
   pub fn current_process_id(&self) -> u32 {
     self.current_process_id
   }
-  pub fn vfs(&self) -> &VFS {
-    &self.vfs
+  /// Lock VFS for this thread, switched to ids, terminal and
+  /// directory of its current process
+  pub fn vfs(&self) -> MutexGuard<'_, VFS> {
+    let mut vfs = self.vfs.lock().unwrap();
+    vfs.switch_context(&self.vfs_context);
+
+    vfs
   }
-  pub fn processes(&self) -> &BTreeMap<AddressSize, Process> {
-    &self.processes
+  pub fn processes(&self) -> MutexGuard<'_, ProcessTable> {
+    self.processes.lock().unwrap()
   }
 
-  fn allocate_pid(&self) -> AddressSize {
-    self.current_process_id() + 1
+  /// Fork current process like `fork`, but to run child on another
+  /// thread of host: it is current in returned kernel, which shares
+  /// VFS, processes and devices with this one, and parent stays
+  /// current here. Child is waited for once it exits, like any other.
+  /// Returns: kernel of child
+  ///
+  /// Errors:
+  /// ESRCH -> there is no current process
+  pub fn fork_thread(&mut self) -> Result<Kernel, Errno> {
+    let pid = self.fork_process()?;
+
+    Ok(Kernel {
+      vfs: Arc::clone(&self.vfs),
+      vfs_context: self.vfs_context.clone(),
+      processes: Arc::clone(&self.processes),
+      current_process_id: pid,
+      device_table: Arc::clone(&self.device_table),
+      resources: self.resources,
+      current_uid: self.current_uid,
+      current_gid: self.current_gid,
+      current_sgids: self.current_sgids.clone(),
+      uid_map: self.uid_map.clone(),
+      gid_map: self.gid_map.clone(),
+      user_db: Arc::clone(&self.user_db),
+      power_action: Arc::clone(&self.power_action),
+      clock: Arc::clone(&self.clock),
+      rng: Arc::clone(&self.rng),
+      dmesg: Arc::clone(&self.dmesg),
+      host_editor: self.host_editor.clone(),
+    })
   }
 
   pub fn update_uid_gid_maps(&mut self) -> Result<(), Errno> {
-    let bytes = self.vfs.read_file(PASSWD_PATH, AddressSize::MAX)?;
-    let contents = String::from_utf8(bytes)
-      .or(Err(Errno::EILSEQ(format!("kernel::update_passwd: invalid bytes in {PASSWD_PATH}"))))?;
-    let passwds = Passwd::parse_passwds(&contents);
+    let passwds = self.passwds()?;
 
     for passwd in passwds {
       self.uid_map.insert(passwd.uid, passwd.name);
       // self.gid_map.insert(passwd.gid, passwd.name);
     }
 
+    for group in self.groups()? {
+      self.gid_map.insert(group.gid, group.name);
+    }
+
+    Ok(())
+  }
+
+  /// All users of `user_db`
+  pub fn passwds(&mut self) -> Result<Vec<Passwd>, Errno> {
+    self.user_db.lock().unwrap().passwds(&mut self.vfs())
+  }
+
+  /// All groups of `user_db`
+  pub fn groups(&mut self) -> Result<Vec<Group>, Errno> {
+    self.user_db.lock().unwrap().groups(&mut self.vfs())
+  }
+
+  /// User named `name` in `user_db`
+  pub fn user_by_name(&mut self, name: &str) -> Result<Option<Passwd>, Errno> {
+    self.user_db.lock().unwrap().user_by_name(&mut self.vfs(), name)
+  }
+
+  /// Name of user `uid` from `user_db`, or from `uid_map`
+  /// if it can't be asked or doesn't know it
+  pub fn user_name(&mut self, uid: Id) -> Option<String> {
+    let passwd = self.user_db.lock().unwrap().user_by_uid(&mut self.vfs(), uid);
+    match passwd {
+      Ok(Some(passwd)) => Some(passwd.name),
+      _ => self.uid_map.get(&uid).cloned(),
+    }
+  }
+
+  /// Name of group `gid` from `user_db`, or from `gid_map`
+  /// if it can't be asked or doesn't know it
+  pub fn group_name(&mut self, gid: Id) -> Option<String> {
+    let group = self.user_db.lock().unwrap().group_by_gid(&mut self.vfs(), gid);
+    match group {
+      Ok(Some(group)) => Some(group.name),
+      _ => self.gid_map.get(&gid).cloned(),
+    }
+  }
+
+  /// Set supplementary groups of current user to primary group
+  /// and groups listing them in /etc/group, should be called
+  /// after `current_uid` and `current_gid` change
+  pub fn update_current_sgids(&mut self) -> Result<(), Errno> {
+    let mut sgids = vec![self.current_gid];
+
+    if let Some(name) = self.uid_map.get(&self.current_uid).cloned() {
+      sgids.extend(self
+        .groups()?
+        .into_iter()
+        .filter(|group| group.user_list.contains(&name))
+        .map(|group| group.gid)
+        .filter(|gid| *gid != self.current_gid)
+      );
+    }
+
+    self.current_sgids = sgids;
+    self.update_vfs_current_uid_gid();
+
     Ok(())
   }
 
   pub fn update_vfs_current_uid_gid(&mut self) {
-    self.vfs.current_uid = self.current_uid;
-    self.vfs.current_gid = self.current_gid;
+    self.set_vfs_ids(self.current_uid, self.current_gid);
+    self.vfs_context.sgids = self.current_sgids.clone();
+  }
+
+  pub fn update_vfs_current_tty(&mut self) {
+    self.vfs_context.tty = self.controlling_tty();
+  }
+
+  pub fn update_vfs_current_dir(&mut self) {
+    if let Ok(cwd) = self.getcwd() {
+      self.vfs_context.dir = cwd;
+    }
+  }
+
+  /// Make `uid` and `gid` those that VFS checks permissions
+  /// with, like ones of owner of setuid binary
+  pub fn set_vfs_ids(&mut self, uid: Id, gid: Id) {
+    (self.vfs_context.uid, self.vfs_context.gid) = (uid, gid);
+  }
+
+  /// Returns: name of controlling terminal of current process in devfs
+  pub fn controlling_tty(&self) -> Option<String> {
+    self.processes()
+      .get(&self.current_process_id)
+      .and_then(|process| process.controlling_tty.clone())
   }
 
   fn open_stdio_files(&mut self, process: &mut Process) -> Result<(), Errno> {
+    let mut vfs = self.vfs();
     // Ensure that /proc filesystem exists
-    self.vfs.mount_points.get("/proc").ok_or(Errno::ENOENT(String::from("Kernel::open_stdio_files: cannot open stdio files, /proc is not mounted")))?;
+    vfs.mount_points.get("/proc").ok_or(Errno::ENOENT(String::from("Kernel::open_stdio_files: cannot open stdio files, /proc is not mounted").into()))?;
 
     // Identify and create /proc/{pid} and /proc/{pid}/fd,
     // ignoring if already exists
     let process_pathname = format!("/proc/{}", process.pid);
     let process_fd_pathname = format!("{}/fd", process_pathname);
-    self.vfs.create_dir(&process_pathname)
-      .and_then(|_| self.vfs.create_dir(&process_fd_pathname))?;
+    vfs.create_dir(&process_pathname)
+      .and_then(|_| vfs.create_dir(&process_fd_pathname))?;
 
     // Create /proc/{pid}/fd/0, /proc/{pid}/fd/1, /proc/{pid}/fd/2
     let stdin_pathname = format!("{}/{}", process_fd_pathname, 0);
     let stdout_pathname = format!("{}/{}", process_fd_pathname, 1);
     let stderr_pathname = format!("{}/{}", process_fd_pathname, 2);
-    let stdin_vinode = self.vfs.create_file(stdin_pathname.as_str())?;
-    let stdout_vinode = self.vfs.create_file(stdout_pathname.as_str())?;
-    let stderr_vinode = self.vfs.create_file(stderr_pathname.as_str())?;
+    let stdin_vinode = vfs.create_file(stdin_pathname.as_str())?;
+    let stdout_vinode = vfs.create_file(stdout_pathname.as_str())?;
+    let stderr_vinode = vfs.create_file(stderr_pathname.as_str())?;
 
     // Actually insert all 3 stdio files as opened to process' fd table
-    process.file_descriptors.insert(0, FileDescription {
+    process.file_descriptors.insert(0, OpenFileDescriptor::new(FileDescription {
       vinode: stdin_vinode,
       flags: OpenFlags::new(OpenMode::ReadWrite, true, false),
       pathname: Some(stdin_pathname),
-    });
+      offset: 0,
+    }, false));
     
-    process.file_descriptors.insert(0, FileDescription {
+    process.file_descriptors.insert(0, OpenFileDescriptor::new(FileDescription {
       vinode: stdout_vinode,
       flags: OpenFlags::new(OpenMode::ReadWrite, true, false),
       pathname: Some(stdout_pathname),
-    });
+      offset: 0,
+    }, false));
 
-    process.file_descriptors.insert(0, FileDescription {
+    process.file_descriptors.insert(0, OpenFileDescriptor::new(FileDescription {
       vinode: stderr_vinode,
       flags: OpenFlags::new(OpenMode::ReadWrite, true, false),
       pathname: Some(stderr_pathname),
-    });
+      offset: 0,
+    }, false));
 
     Ok(())
   }
@@ -263,21 +777,267 @@ impl Kernel {
   fn spawn_process(&mut self, bin_pathname: &str) -> Result<Process, Errno> {
     // Parent process id - current process, lul
     let ppid = self.current_process_id();
+    let mut processes = self.processes.lock().unwrap();
 
     // Set current pid to newly allocated one - for spawned process
-    self.current_process_id = self.allocate_pid();
+    self.current_process_id = processes.allocate_pid();
 
     // Create new process
-    let process = Process::new(bin_pathname, self.current_process_id)
+    let mut process = Process::new(bin_pathname, self.current_process_id)
       .with_ppid(ppid)
       .with_uid(ROOT_UID);
 
+    // Stay in parent's session and directory, if there is a parent,
+    // and get descriptors it has open, but those closed on exec
+    if let Some(parent) = processes.get(&ppid) {
+      process.sid = parent.sid;
+      process.controlling_tty = parent.controlling_tty.clone();
+      process.cwd = parent.cwd.clone();
+      process.file_descriptors = parent.file_descriptors
+        .iter()
+        .filter(|(_, open_file_descriptor)| !open_file_descriptor.close_on_exec)
+        .map(|(file_descriptor, open_file_descriptor)| (*file_descriptor, open_file_descriptor.clone()))
+        .collect();
+    }
+
     // Insert it to processes table
-    self.processes.insert(self.current_process_id, process.clone());
+    processes.insert(self.current_process_id, process.clone());
+    drop(processes);
+    self.update_procfs();
 
     Ok(process)
   }
 
+  /// Make process `pid` current, with its directory and terminal
+  fn switch_to(&mut self, pid: AddressSize) {
+    self.current_process_id = pid;
+    self.update_vfs_current_dir();
+    self.update_vfs_current_tty();
+    self.update_procfs();
+  }
+
+  /// Copy current process to new one, that becomes current.
+  /// Child gets all descriptors of parent, sharing their offsets,
+  /// but not its sockets.
+  /// Returns: pid of child
+  ///
+  /// Errors:
+  /// ESRCH -> there is no current process
+  pub fn fork(&mut self) -> Result<AddressSize, Errno> {
+    let pid = self.fork_process()?;
+    self.switch_to(pid);
+
+    Ok(pid)
+  }
+
+  /// Copy current process to new one, like `fork` does, but
+  /// leave it current.
+  /// Returns: pid of child
+  fn fork_process(&mut self) -> Result<AddressSize, Errno> {
+    let mut processes = self.processes();
+    let parent = processes
+      .get(&self.current_process_id)
+      .cloned()
+      .ok_or(Errno::ESRCH(String::from("fork: cannot get current process").into()))?;
+
+    let pid = processes.allocate_pid();
+    processes.insert(pid, Process {
+      pid,
+      ppid: parent.pid,
+      uid: self.current_uid,
+      sockets: BTreeMap::new(),
+      exit_code: None,
+      pending_signals: BTreeSet::new(),
+      ..parent
+    });
+
+    Ok(pid)
+  }
+
+  /// Run binary at `pathname` in current process, closing descriptors
+  /// that are closed on exec, then exit with its exit code, so parent
+  /// is current again after that.
+  ///
+  /// Errors: those of `exec`, process is still current then and
+  /// should exit itself
+  pub fn execve(&mut self, pathname: &str, argv: &[&str]) -> Result<(), Errno> {
+    let mut processes = self.processes();
+    let process = processes
+      .get_mut(&self.current_process_id)
+      .ok_or(Errno::ESRCH(String::from("execve: cannot get current process").into()))?;
+    process.binary = pathname.to_owned();
+    process.file_descriptors.retain(|_, open_file_descriptor| !open_file_descriptor.close_on_exec);
+    drop(processes);
+
+    let exit_code = match (self.exec(pathname, argv)?, self.deliver_signals()) {
+      (_, Some(signal)) => signal::exit_code(signal),
+      (exit_code, None) => exit_code,
+    };
+
+    self.exit(exit_code)
+  }
+
+  /// End current process with `exit_code`: close its descriptors,
+  /// sockets and watches, give its children to init, send SIGCHLD
+  /// to its parent and make it current. It is zombie until parent
+  /// waits for it
+  ///
+  /// Errors:
+  /// ESRCH -> there is no current process
+  /// EPERM -> it is init, there is no parent to go back to
+  pub fn exit(&mut self, exit_code: AddressSize) -> Result<(), Errno> {
+    let pid = self.current_process_id;
+    let ppid = self.processes()
+      .get(&pid)
+      .ok_or(Errno::ESRCH(String::from("exit: cannot get current process").into()))?
+      .ppid;
+    if !self.processes().contains_key(&ppid) {
+      return Err(Errno::EPERM(format!("exit: process {pid} has no parent").into()));
+    }
+
+    let socket_descriptors = self.sockets_mut()?.keys().copied().collect::<Vec<_>>();
+    for socket_descriptor in socket_descriptors {
+      let _ = self.close_socket(socket_descriptor);
+    }
+    self.vfs().watches.remove_process(pid);
+
+    let mut processes = self.processes();
+    for process in processes.values_mut() {
+      if process.ppid == pid {
+        process.ppid = INIT_PID;
+      }
+    }
+    let process = processes
+      .get_mut(&pid)
+      .expect("exit: we know that current process exists");
+    process.file_descriptors.clear();
+    process.exit_code = Some(exit_code);
+    process.pending_signals.clear();
+
+    processes
+      .get_mut(&ppid)
+      .expect("exit: we know that parent exists")
+      .pending_signals
+      .insert(SIGCHLD);
+    drop(processes);
+    self.switch_to(ppid);
+
+    Ok(())
+  }
+
+  /// Remove exited child `pid` of current process from process table.
+  /// Children run to the end before their parent is current again,
+  /// unless they run on another thread, see `fork_thread`, so one
+  /// that has not exited will not be waited for.
+  /// Returns: exit code of child
+  ///
+  /// Errors:
+  /// ECHILD -> `pid` is not child of current process, or has not exited
+  pub fn waitpid(&mut self, pid: AddressSize) -> Result<AddressSize, Errno> {
+    let mut processes = self.processes();
+    let exit_code = match processes.get(&pid) {
+      Some(Process { ppid, exit_code: Some(exit_code), .. }) if *ppid == self.current_process_id => *exit_code,
+      _ => return Err(Errno::ECHILD(format!("waitpid: {pid} is not exited child of {}", self.current_process_id).into())),
+    };
+    processes.remove(&pid);
+    drop(processes);
+    self.update_procfs();
+
+    Ok(exit_code)
+  }
+
+  /// Send `signal` to process `pid`, it is delivered when process
+  /// checks for signals or its binary returns. Signal 0 is not sent,
+  /// only whether it could be is checked
+  ///
+  /// Errors:
+  /// EINVAL -> there is no such signal
+  /// ESRCH  -> there is no such process, or it has exited
+  /// EPERM  -> process is of another user, and current one is not root
+  pub fn kill(&mut self, pid: AddressSize, signal: Signal) -> Result<(), Errno> {
+    if signal != 0 && !signal::SIGNALS.contains(&signal) {
+      return Err(Errno::EINVAL(format!("kill: unknown signal {signal}").into()));
+    }
+    let (uid, effective_uid) = (self.current_uid, self.vfs_context.uid);
+    let mut processes = self.processes();
+    let process = match processes.get_mut(&pid) {
+      Some(process) if process.exit_code.is_none() => process,
+      _ => return Err(Errno::ESRCH(format!("kill: no process {pid}").into())),
+    };
+    if ![uid, effective_uid].contains(&ROOT_UID) && ![uid, effective_uid].contains(&process.uid) {
+      return Err(Errno::EPERM(format!("kill: process {pid} is not of user {uid}").into()));
+    }
+
+    if signal != 0 {
+      process.pending_signals.insert(signal);
+    }
+
+    Ok(())
+  }
+
+  /// Ctrl-C on terminal of host goes to foreground process, which is
+  /// current one
+  fn take_host_interrupt(&mut self) {
+    if console::take_interrupt() {
+      if let Some(process) = self.processes().get_mut(&self.current_process_id) {
+        process.pending_signals.insert(SIGINT);
+      }
+    }
+  }
+
+  /// Signal that terminates current process, if one is pending.
+  /// Init gets no signals it would be terminated by, like that of Linux
+  fn terminating_signal(&mut self) -> Option<Signal> {
+    self.take_host_interrupt();
+    let processes = self.processes();
+    let process = processes.get(&self.current_process_id)?;
+    if process.pid == INIT_PID {
+      return None;
+    }
+
+    match process.pending_signals.contains(&SIGKILL) {
+      true => Some(SIGKILL),
+      false => process.pending_signals
+        .iter()
+        .copied()
+        .find(|signal| signal::default_disposition(*signal) == Disposition::Terminate),
+    }
+  }
+
+  /// Whether current process has signal pending that terminates it,
+  /// so binary that runs for long should return
+  pub fn termination_pending(&mut self) -> bool {
+    self.terminating_signal().is_some()
+  }
+
+  /// Deliver pending signals of current process: ignored ones are
+  /// dropped, and so are the rest once one terminates it.
+  /// Returns: signal that terminates process
+  pub fn deliver_signals(&mut self) -> Option<Signal> {
+    let signal = self.terminating_signal();
+    if let Some(process) = self.processes().get_mut(&self.current_process_id) {
+      process.pending_signals.clear();
+    }
+
+    signal
+  }
+
+  /// Run binary at `pathname` in child process, like shell does:
+  /// fork, execve in child and wait for it.
+  /// Returns: exit code of child
+  ///
+  /// Errors: those of `execve`, child is reaped then
+  pub fn run(&mut self, pathname: &str, argv: &[&str]) -> Result<AddressSize, Errno> {
+    let pid = self.fork()?;
+    if let Err(errno) = self.execve(pathname, argv) {
+      self.exit(EXIT_FAILURE)?;
+      self.waitpid(pid)?;
+      return Err(errno);
+    }
+
+    self.waitpid(pid)
+  }
+
 }
 
 impl Kernel {
@@ -288,17 +1048,37 @@ impl Kernel {
 
 impl Kernel {
   pub fn exec(&mut self, pathname: &str, argv: &[&str]) -> Result<AddressSize, Errno> {
-    let (mount_point, internal_pathname) = self.vfs.match_mount_point(pathname)?;
+    let mut vfs = self.vfs();
+    let pathname = &vfs.resolve_path(pathname, true)?;
+    // Guard for missing execute permission
+    let vinode = vfs.lookup_path(pathname)?;
+    vfs.execute_check(&vinode)?;
+
+    let (mount_point, internal_pathname) = vfs.match_mount_point(pathname)?;
     // println!("[{KERNEL_MESSAGE_HEADER_ERR}]: mount_point: {mount_point}");
     // println!("[{KERNEL_MESSAGE_HEADER_ERR}]: internal_pathname: {internal_pathname}");
+    let flags = vfs.mount_points[&mount_point].flags;
+    if flags.noexec {
+      return Err(Errno::EACCES(format!("exec: filesystem {mount_point} is mounted noexec").into()));
+    }
+
+    // Setuid and setgid binaries run with ids of their owners
+    let (uid, gid) = (self.vfs_context.uid, self.vfs_context.gid);
+    let effective_uid = match vinode.mode.setuid() && !flags.nosuid {
+      true => vinode.uid,
+      false => uid,
+    };
+    let effective_gid = match vinode.mode.setgid() && !flags.nosuid {
+      true => vinode.gid,
+      false => gid,
+    };
 
-    match self
-      .vfs
+    let binary = match vfs
       .mount_points
       .get_mut(mount_point.as_str())
       .expect(&format!("[{KERNEL_MESSAGE_HEADER_ERR}]: critical: we know that mount_point {mount_point} exists"))
     {
-      MountedFilesystem { r#type: FilesystemType::binfs, driver } => {
+      MountedFilesystem { r#type: FilesystemType::binfs, driver, .. } => {
         let binfs = driver
           .as_any()
           .downcast_mut::<BinFilesytem>()
@@ -309,154 +1089,1943 @@ impl Kernel {
         // Lookup for binary file
         let vinode = binfs.lookup_path(&internal_pathname)?;
         // Try to read it's payload and get binary out of it
-        let binary = match binfs.virtfs.read_payload(vinode.number) {
+        match binfs.virtfs.read_payload(vinode.number) {
             Ok(Payload::File(binary)) => binary,
-            Ok(Payload::Directory(_)) => return Err(Errno::EISDIR(format!("exec: is a directory: {pathname}"))),
+            Ok(Payload::Directory(_)) => return Err(Errno::EISDIR(format!("exec: is a directory: {pathname}").into())),
+            Ok(Payload::Generator(_)) => return Err(Errno::EACCES(format!("exec: not a binary: {pathname}").into())),
             Err(errno) => return Err(errno),
-        };
-
-        // Convert &[&str] -> Vec<String>
-        let argv = argv.iter().map(|arg| arg.to_string()).to_owned().collect();
-
-        let exit_code = binary.0(argv, self);
-
-        Ok(exit_code)
+        }
       },
       _ => {
-        Err(Errno::EACCES(format!("exec: filesystem {mount_point} is noexec")))
+        return Err(Errno::EACCES(format!("exec: filesystem {mount_point} is noexec").into()));
       },
-    }
-  }
-  pub fn open(&mut self, pathname: &str, flags: OpenFlags) -> Result<FileDescriptor, Errno> {
-    let current_process = self
-      .processes
-      .get_mut(&self.current_process_id)
-      .ok_or(Errno::ESRCH(String::from("open: cannot get current process")))?; 
-    
-    let vinode = self.vfs.lookup_path(pathname)?;
-    let file_description = FileDescription {
-      vinode,
-      flags,
-      pathname: Some(pathname.to_owned()),
     };
+    // Binary runs with nothing locked, it locks what it needs itself
+    drop(vfs);
 
-    current_process.file_descriptors.insert(
-      current_process.file_descriptors.len() as FileDescriptor,
-      file_description.to_owned()
-    );
+    // Convert &[&str] -> Vec<String>
+    let argv = argv.iter().map(|arg| arg.to_string()).to_owned().collect();
 
-    // We know that `len()` is at least 1
-    Ok((current_process.file_descriptors.len() - 1) as FileDescriptor)
+    self.set_vfs_ids(effective_uid, effective_gid);
+    self.update_procfs();
+    let exit_code = match binary {
+      Binary::Native(binary_fn) => binary_fn(argv, self),
+      Binary::Script(source) => script::run(&source, argv, self),
+    };
+    self.set_vfs_ids(uid, gid);
+
+    Ok(exit_code)
   }
+  /// Make `binary_fn` executable at `pathname` (must be on binfs),
+  /// replacing binary that is already there
+  pub fn register_binary(&mut self, pathname: &str, binary_fn: BinaryFn) -> Result<(), Errno> {
+    let mut vfs = self.vfs();
+    let (mount_point, internal_pathname) = vfs.match_mount_point(pathname)?;
+    let mounted_fs = vfs.mount_points.get_mut(&mount_point).expect("Kernel::register_binary: we know that mount_point exist");
+
+    // Guard for not binfs
+    if mounted_fs.r#type != FilesystemType::binfs {
+      return Err(Errno::EINVAL(format!("register_binary: {pathname} is not on binfs").into()));
+    }
 
-  pub fn close(&mut self, file_descriptor: FileDescriptor) -> Result<(), Errno> {
-    let current_process = self.processes
-      .get_mut(&self.current_process_id)
-      .ok_or(Errno::ESRCH(String::from("open: cannot get current process")))?; 
-    
-    current_process.file_descriptors.remove(&file_descriptor);
+    let binfs = mounted_fs.driver
+      .as_any()
+      .downcast_mut::<BinFilesytem>()
+      .expect("we know that mounted_fs.driver === instanceof BinFilesytem");
+
+    match binfs.lookup_path(&internal_pathname) {
+      Ok(_) => binfs.replace_bin(&internal_pathname, binary_fn)?,
+      Err(Errno::ENOENT(_)) => binfs.add_bin(&internal_pathname, binary_fn)?,
+      Err(errno) => return Err(errno),
+    };
 
     Ok(())
   }
+  /// Read file at `pathname`. Unlike `vfs.read_file`, files generated
+  /// from kernel state, like ones on procfs, are generated anew
+  /// instead of showing state as of the last change of processes or mounts.
+  /// Character devices are read with VFS unlocked, so that read
+  /// that waits for input, like that of terminal, stalls no one else
+  pub fn read_file(&mut self, pathname: &str, count: AddressSize) -> Result<Vec<u8>, Errno> {
+    let driver = self.vfs().char_device(pathname, PERM_R)?;
+    if let Some(driver) = driver {
+      return driver.write().unwrap().read(count)
+        .with_context(|| format!("read_file {pathname}"));
+    }
 
-  pub fn read(&self, file_descriptor: FileDescriptor, count: AddressSize) -> Result<Vec<u8>, Errno> {
-    todo!();
-  }
-  pub fn stat(&self, file_descriptor: FileDescriptor) -> Result<FileStat, Errno> {
-    todo!();
+    if self.is_on_procfs(pathname)? {
+      self.update_procfs();
+      return self.vfs().read_file(pathname, count);
+    }
+
+    let data = self.vfs().read_file(pathname, count)?;
+    self.update_atime(pathname);
+
+    Ok(data)
   }
-  pub fn write(&mut self, file_descriptor: FileDescriptor, buffer: Vec<u8>) -> Result<AddressSize, Errno> {
-    todo!();
+  /// Write `data` to file at `pathname`, like `vfs.write_file`, but
+  /// character devices are written with VFS unlocked, like `read_file`
+  /// reads them
+  pub fn write_file(&mut self, pathname: &str, data: &[u8]) -> Result<(), Errno> {
+    let driver = self.vfs().char_device(pathname, PERM_W)?;
+    let Some(driver) = driver else {
+      return self.vfs().write_file(pathname, data).map(|_| ());
+    };
+
+    driver.write().unwrap().write(data)
+      .with_context(|| format!("write_file {pathname}"))?;
+    let mut vfs = self.vfs();
+    let pathname = vfs.resolve_path(pathname, true)?;
+    vfs.watches.notify(&pathname, IN_MODIFY);
+
+    Ok(())
   }
-  pub fn chmod(&mut self, file_descriptor: FileDescriptor, new_perms: Vec<u8>) -> Result<(), Errno> {
-    todo!();
+  /// Read up to `count` bytes of file at `pathname` from `offset`,
+  /// generated files included, like `read_file`
+  pub fn read_at(&mut self, pathname: &str, offset: AddressSize, count: AddressSize) -> Result<Vec<u8>, Errno> {
+    if self.is_on_procfs(pathname)? {
+      self.update_procfs();
+      return self.vfs().read_at(pathname, offset, count);
+    }
+
+    let data = self.vfs().read_at(pathname, offset, count)?;
+    self.update_atime(pathname);
+
+    Ok(data)
   }
-  pub fn getdents(&mut self, file_descriptor: FileDescriptor) -> Result<VDirectory, Errno> {
-    let process = self.processes
-      .get(&self.current_process_id())
-      .ok_or(Errno::ESRCH(String::from("cannot get current process")))?;
-    let FileDescription {
-      vinode: _inode,
-      flags,
-      pathname,
-    } = process.file_descriptors.get(&file_descriptor).ok_or(Errno::ENOENT(String::from("no such file descriptor")))?;
+  /// Whether file at `pathname` is on procfs
+  fn is_on_procfs(&self, pathname: &str) -> Result<bool, Errno> {
+    let vfs = self.vfs();
+    let (mount_point, _) = vfs.match_mount_point(pathname)?;
 
-    // Guard for OpenMode
-    match flags.mode() {
-      OpenMode::Write => return Err(Errno::EACCES(String::from("getdents: permission denied"))),
-      OpenMode::ReadWrite | OpenMode::Read => (),
+    Ok(vfs.mount_points[&mount_point].r#type == FilesystemType::procfs)
+  }
+  /// Generate files of every mounted procfs from current state,
+  /// for reads of them through VFS
+  fn update_procfs(&mut self) {
+    let is_procfs = |mounted_fs: &MountedFilesystem| mounted_fs.r#type == FilesystemType::procfs;
+    if !self.vfs().mount_points.values().any(is_procfs) {
+      return;
     }
 
-    self.vfs.read_dir(
-      pathname
-        .as_ref()
-        .ok_or(Errno::EIO(String::from("this error shouldn't happen as far as i see")))?
-        .as_str()
-    )
+    // Generated with nothing locked, as it locks what it reads
+    let contents = procfs::generate(self);
+    let mut vfs = self.vfs();
+    for mounted_fs in vfs.mount_points.values_mut().filter(|mounted_fs| is_procfs(mounted_fs)) {
+      mounted_fs.driver
+        .as_any()
+        .downcast_mut::<ProcessFilesystem>()
+        .expect("we know that mounted_fs.driver === instanceof ProcessFilesystem")
+        .update(&contents)
+        .expect("procfs: we know that it has every file it generates");
+    }
   }
-  pub fn mount(&mut self, source: &str, target: &str, fs_type: FilesystemType) -> Result<(), Errno> {
-    if let Some(_) = self.vfs.mount_points.get(target) {
-      return Err(Errno::EINVAL(String::from("mount point already taken")))
+  /// Set access time of regular file at `pathname` that has just
+  /// been read. Like relatime of Linux, it is set only if it is
+  /// before modification or a day old, so that reads are not writes
+  /// every time. Not set on `noatime` and read-only mounts, and read
+  /// goes on if it can't be set
+  fn update_atime(&mut self, pathname: &str) {
+    let now = self.clock.now();
+    let mut vfs = self.vfs();
+    let Ok((mount_point, internal_pathname)) = vfs.match_mount_point(pathname) else {
+      return;
+    };
+    let mounted_fs = vfs.mount_points.get_mut(&mount_point).expect("Kernel::update_atime: we know that mount_point exist");
+    if mounted_fs.flags.noatime || mounted_fs.flags.read_only {
+      return;
     }
 
-    let mounted_fs = match fs_type {
-      FilesystemType::e5fs => {
-        let (mount_point, internal_path) = self.vfs.match_mount_point(source)?;
-        let mounted_fs = self.vfs.mount_points.get_mut(&mount_point).expect("VFS::lookup_path: we know that mount_point exist");  
-
-        let realpath = if mounted_fs.r#type == FilesystemType::devfs {
-          let devfs = mounted_fs.driver
-            .as_any()
-            .downcast_ref::<DeviceFilesystem>()
-            .expect("we know that mounted_fs.driver === instanceof DeviceFilesystem");
-
-          devfs.device_by_pathname(&internal_path)?
-        } else {
-          return Err(Errno::EINVAL(String::from("source is not a device")));
-        };
+    let Ok(stat) = mounted_fs.driver.stat(&internal_pathname) else {
+      return;
+    };
+    let is_recent = stat.atime > stat.mtime.max(stat.ctime) && stat.atime + ATIME_INTERVAL > now;
+    if stat.mode.file_type() != FileModeType::File as u8 || is_recent {
+      return;
+    }
 
-        // Instantiate new e5fs around device that we've found
-        let e5fs = eunix::e5fs::E5FSFilesystem::from(realpath.as_str())?;
+    let _ = mounted_fs.driver.change_times(&internal_pathname, Times {
+      atime: now,
+      mtime: stat.mtime,
+      ctime: stat.ctime,
+      btime: stat.btime,
+    });
+  }
+  /// Check whether current user may read, write or execute file at
+  /// `pathname`, as `mask` of `PERM_R`, `PERM_W` and `PERM_X` says,
+  /// without opening it, like `access`. With empty `mask` it is only
+  /// checked that file is there. Like on Linux, real ids of user are
+  /// checked, not ones that setuid binary runs with
+  ///
+  /// Errors:
+  /// ENOENT -> there is no such file
+  /// EACCES -> user may not do that, or file is to be executed
+  ///           and filesystem is mounted `noexec`
+  /// EROFS  -> file is to be written and filesystem is read-only
+  pub fn access(&mut self, pathname: &str, mask: u8) -> Result<(), Errno> {
+    let mut vfs = self.vfs();
+    let pathname = vfs.resolve_path(pathname, true)?;
+    let vinode = vfs.lookup_path(&pathname)?;
+
+    (vfs.current_uid, vfs.current_gid) = (self.current_uid, self.current_gid);
+    let permitted = vfs.permission_check(vinode, mask);
+    (vfs.current_uid, vfs.current_gid) = (self.vfs_context.uid, self.vfs_context.gid);
+    permitted.with_context(|| format!("access {pathname}"))?;
+
+    // Device nodes are written on read-only filesystem too
+    if mask & PERM_W != 0 {
+      let (mount_point, _) = vfs.match_device_or_mount_point(&pathname, vinode)?;
+      vfs.check_writable(&mount_point)
+        .with_context(|| format!("access {pathname}"))?;
+    }
+    let (mount_point, _) = vfs.match_mount_point(&pathname)?;
+    if mask & PERM_X != 0 && vfs.mount_points[&mount_point].flags.noexec {
+      return Err(Errno::EACCES(format!("access {pathname}: filesystem {mount_point} is mounted noexec").into()));
+    }
 
-        MountedFilesystem {
-          r#type: FilesystemType::e5fs,
-          driver: Box::new(e5fs),
+    Ok(())
+  }
+  pub fn open(&mut self, pathname: &str, flags: OpenFlags) -> Result<FileDescriptor, Errno> {
+    let mut vfs = self.vfs();
+
+    // File that is just created is open in any mode
+    let vinode = match vfs.lookup_path(pathname) {
+      Err(Errno::ENOENT(_)) if flags.create() => vfs.create_file(pathname)?,
+      Ok(vinode) => {
+        let wanted_perm_mask = match flags.mode() {
+          OpenMode::Read => PERM_R,
+          OpenMode::Write => PERM_W,
+          OpenMode::ReadWrite => PERM_R | PERM_W,
+        };
+        vfs.permission_check(vinode, wanted_perm_mask)
+          .with_context(|| format!("open {pathname}"))?;
+        // Device nodes are written on read-only filesystem too
+        if wanted_perm_mask & PERM_W != 0 {
+          let (mount_point, _) = vfs.match_device_or_mount_point(pathname, vinode)?;
+          vfs.check_writable(&mount_point)
+            .with_context(|| format!("open {pathname}"))?;
         }
+        vinode
       },
-      FilesystemType::binfs => {
-        let binfs = BinFilesytem::new();
+      Err(errno) => return Err(errno),
+    };
 
-        MountedFilesystem {
-          r#type: FilesystemType::binfs,
-          driver: Box::new(binfs),
-        }
-      },
-      FilesystemType::devfs => {
-        let devfs = eunix::devfs::DeviceFilesystem::new(self.devices());
+    let writable = !matches!(flags.mode(), OpenMode::Read);
+    if flags.truncate() && writable && vinode.mode.file_type() == FileModeType::File as u8 {
+      vfs.write_file(pathname, &[])?;
+    }
 
-        MountedFilesystem {
+    // Descriptor stays on the same file when directory changes
+    let file_description = FileDescription {
+      vinode,
+      flags: flags.with_close_on_exec(false),
+      pathname: Some(vfs.absolute_path(pathname)),
+      offset: 0,
+    };
+
+    let mut processes = self.processes();
+    let current_process = processes
+      .get_mut(&self.current_process_id)
+      .ok_or(Errno::ESRCH(String::from("open: cannot get current process").into()))?; 
+    let file_descriptor = current_process.lowest_free_descriptor();
+    current_process.file_descriptors.insert(file_descriptor, OpenFileDescriptor::new(file_description, flags.close_on_exec()));
+
+    Ok(file_descriptor)
+  }
+  /// Open `file_descriptor` again at the lowest descriptor that is
+  /// not open, like `dup`. The copy shares file description, and so
+  /// offset, with `file_descriptor`, but is not closed on exec.
+  /// Returns: the new descriptor
+  ///
+  /// Errors:
+  /// EBADFD -> `file_descriptor` is not open
+  pub fn dup(&mut self, file_descriptor: FileDescriptor) -> Result<FileDescriptor, Errno> {
+    let file_description = self.shared_file_description(file_descriptor)
+      .with_context(|| format!("dup {file_descriptor}"))?;
+    let open_file_descriptor = OpenFileDescriptor { file_description, close_on_exec: false };
+
+    let mut processes = self.processes();
+    let current_process = processes
+      .get_mut(&self.current_process_id)
+      .ok_or(Errno::ESRCH(String::from("dup: cannot get current process").into()))?;
+    let new_file_descriptor = current_process.lowest_free_descriptor();
+    current_process.file_descriptors.insert(new_file_descriptor, open_file_descriptor);
+
+    Ok(new_file_descriptor)
+  }
+  /// Open `file_descriptor` again at `new_file_descriptor`, closing
+  /// what was open there, like `dup2`. They share file description
+  /// like after `dup`. Nothing is done if they are the same. That is
+  /// how stdio is redirected
+  /// Returns: `new_file_descriptor`
+  ///
+  /// Errors:
+  /// EBADFD -> `file_descriptor` is not open
+  pub fn dup2(&mut self, file_descriptor: FileDescriptor, new_file_descriptor: FileDescriptor) -> Result<FileDescriptor, Errno> {
+    let file_description = self.shared_file_description(file_descriptor)
+      .with_context(|| format!("dup2 {file_descriptor}"))?;
+    if file_descriptor == new_file_descriptor {
+      return Ok(new_file_descriptor);
+    }
+
+    self.processes()
+      .get_mut(&self.current_process_id)
+      .ok_or(Errno::ESRCH(String::from("dup2: cannot get current process").into()))?
+      .file_descriptors
+      .insert(new_file_descriptor, OpenFileDescriptor { file_description, close_on_exec: false });
+
+    Ok(new_file_descriptor)
+  }
+
+  /// Open terminal at `pathname` as standard input, output and
+  /// error of current process, that its children inherit
+  pub fn open_stdio(&mut self, pathname: &str) -> Result<(), Errno> {
+    let file_descriptor = self.open(pathname, OpenFlags::new(OpenMode::ReadWrite, false, false))?;
+    for stdio_file_descriptor in [STDIN_FILENO, STDOUT_FILENO, STDERR_FILENO] {
+      self.dup2(file_descriptor, stdio_file_descriptor)?;
+    }
+    if file_descriptor > STDERR_FILENO {
+      self.close(file_descriptor)?;
+    }
+
+    Ok(())
+  }
+
+  pub fn close(&mut self, file_descriptor: FileDescriptor) -> Result<(), Errno> {
+    let mut processes = self.processes();
+    let current_process = processes
+      .get_mut(&self.current_process_id)
+      .ok_or(Errno::ESRCH(String::from("open: cannot get current process").into()))?; 
+    
+    current_process.file_descriptors.remove(&file_descriptor);
+
+    Ok(())
+  }
+
+  /// Read up to `count` bytes from file open at `file_descriptor`.
+  /// Regular files are read from offset of the descriptor, which is
+  /// moved past what is read, devices just give what they have
+  pub fn read(&mut self, file_descriptor: FileDescriptor, count: AddressSize) -> Result<Vec<u8>, Errno> {
+    let FileDescription {
+      vinode,
+      flags,
+      pathname,
+      offset,
+    } = self.file_description(file_descriptor)?;
+    let pathname = pathname
+      .ok_or(Errno::EIO(String::from("read: file description has no pathname").into()))?;
+
+    // Guard for OpenMode
+    match flags.mode() {
+      OpenMode::Write => return Err(Errno::EBADFD(String::from("read: file is not open for reading").into())),
+      OpenMode::ReadWrite | OpenMode::Read => (),
+    }
+
+    if vinode.mode.file_type() != FileModeType::File as u8 {
+      return self.read_file(&pathname, count);
+    }
+
+    let data = self.read_at(&pathname, offset, count)?;
+    self.shared_file_description(file_descriptor)?.lock().unwrap().offset = offset + data.len() as AddressSize;
+
+    Ok(data)
+  }
+  /// Returns: status of file open at `file_descriptor`
+  pub fn stat(&mut self, file_descriptor: FileDescriptor) -> Result<FileStat, Errno> {
+    let pathname = self.file_description(file_descriptor)?.pathname
+      .ok_or(Errno::EIO(String::from("stat: file description has no pathname").into()))?;
+
+    self.vfs().stat(&pathname)
+  }
+  /// Move offset of `file_descriptor` to `position`, from the start,
+  /// the current offset or the end of file, like `lseek`.
+  /// Returns: new offset from the start
+  ///
+  /// Errors:
+  /// EINVAL -> offset would be before the start
+  /// ESPIPE -> it is a device, which has no offset
+  pub fn lseek(&mut self, file_descriptor: FileDescriptor, position: io::SeekFrom) -> Result<AddressSize, Errno> {
+    let FileDescription {
+      vinode,
+      pathname,
+      offset,
+      ..
+    } = self.file_description(file_descriptor)?;
+
+    // Guard for devices, their data is not at offsets
+    if vinode.is_device() {
+      return Err(Errno::ESPIPE(String::from("lseek: device has no offset").into()));
+    }
+
+    let offset = match position {
+      io::SeekFrom::Start(position) => AddressSize::try_from(position).ok(),
+      io::SeekFrom::Current(delta) => offset.checked_add_signed(delta.try_into().unwrap_or(i32::MIN)),
+      io::SeekFrom::End(delta) => {
+        let pathname = pathname
+          .ok_or(Errno::EIO(String::from("lseek: file description has no pathname").into()))?;
+        let size = self.vfs().stat(&pathname)?.size;
+        size.checked_add_signed(delta.try_into().unwrap_or(i32::MIN))
+      },
+    }
+    .ok_or(Errno::EINVAL(format!("lseek: {position:?} is out of file").into()))?;
+    self.shared_file_description(file_descriptor)?.lock().unwrap().offset = offset;
+
+    Ok(offset)
+  }
+  /// Write `buffer` to file open at `file_descriptor`. Regular files
+  /// are written from offset of the descriptor or, if opened with
+  /// `append`, at the end, and offset is moved past what is written
+  pub fn write(&mut self, file_descriptor: FileDescriptor, buffer: Vec<u8>) -> Result<AddressSize, Errno> {
+    let FileDescription {
+      vinode,
+      flags,
+      pathname,
+      offset,
+    } = self.file_description(file_descriptor)?;
+    let pathname = pathname
+      .ok_or(Errno::EIO(String::from("write: file description has no pathname").into()))?;
+
+    // Guard for OpenMode
+    match flags.mode() {
+      OpenMode::Read => return Err(Errno::EBADFD(String::from("write: file is not open for writing").into())),
+      OpenMode::ReadWrite | OpenMode::Write => (),
+    }
+
+    let count = buffer.len() as AddressSize;
+    if vinode.mode.file_type() != FileModeType::File as u8 {
+      self.write_file(&pathname, &buffer)?;
+      return Ok(count);
+    }
+
+    let offset = match flags.append() {
+      true => self.vfs().append_file(&pathname, &buffer)?.file_size,
+      false => {
+        self.vfs().write_at(&pathname, offset, &buffer)?;
+        offset + count
+      },
+    };
+    self.shared_file_description(file_descriptor)?.lock().unwrap().offset = offset;
+
+    Ok(count)
+  }
+  /// Copy of file description of current process at `file_descriptor`
+  fn file_description(&self, file_descriptor: FileDescriptor) -> Result<FileDescription, Errno> {
+    self.shared_file_description(file_descriptor)
+      .map(|file_description| file_description.lock().unwrap().clone())
+  }
+  /// File description of current process at `file_descriptor`,
+  /// shared with descriptors that are copies of it
+  fn shared_file_description(&self, file_descriptor: FileDescriptor) -> Result<Arc<Mutex<FileDescription>>, Errno> {
+    self.processes()
+      .get(&self.current_process_id())
+      .ok_or(Errno::ESRCH(String::from("cannot get current process").into()))?
+      .file_descriptors
+      .get(&file_descriptor)
+      .map(|open_file_descriptor| Arc::clone(&open_file_descriptor.file_description))
+      .ok_or(Errno::EBADFD(String::from("no such file descriptor").into()))
+  }
+  /// Watch file at `pathname` for events in `mask`, like
+  /// `inotify_add_watch`. Directory is watched for events of files
+  /// in it too. Events are taken with `read_watch_events`.
+  /// Returns: descriptor of the watch
+  ///
+  /// Errors:
+  /// EINVAL -> there are no events in `mask`
+  /// ENOENT -> there is no such file
+  /// EACCES -> current user can't read it
+  pub fn add_watch(&mut self, pathname: &str, mask: u8) -> Result<WatchDescriptor, Errno> {
+    if mask & IN_ALL_EVENTS == 0 {
+      return Err(Errno::EINVAL(format!("add_watch {pathname}: no events to watch").into()));
+    }
+    let pathname = self.vfs().resolve_path(pathname, true)?;
+    let vinode = self.vfs().lookup_path(&pathname)?;
+    self.vfs().permission_check(vinode, PERM_R)
+      .with_context(|| format!("add_watch {pathname}"))?;
+
+    Ok(self.vfs().watches.add(self.current_process_id, &pathname, mask))
+  }
+  /// Stop watch of current process, like `inotify_rm_watch`
+  ///
+  /// Errors:
+  /// EINVAL -> current process has no such watch
+  pub fn remove_watch(&mut self, watch_descriptor: WatchDescriptor) -> Result<(), Errno> {
+    self.vfs().watches.remove(self.current_process_id, watch_descriptor)
+  }
+  /// Events of watches of current process since the last call,
+  /// the oldest first. Empty if nothing happened
+  pub fn read_watch_events(&mut self) -> Vec<WatchEvent> {
+    self.vfs().watches.take_events(self.current_process_id)
+  }
+  /// Make directory at `pathname` working directory of current process
+  ///
+  /// Errors:
+  /// ENOENT  -> there is no such directory
+  /// ENOTDIR -> it is not a directory
+  /// EACCES  -> current user may not search it
+  pub fn chdir(&mut self, pathname: &str) -> Result<(), Errno> {
+    let pathname = self.vfs().resolve_path(pathname, true)?;
+    let vinode = self.vfs().lookup_path(&pathname)?;
+    if vinode.mode.file_type() != FileModeType::Dir as u8 {
+      return Err(Errno::ENOTDIR(format!("chdir: {pathname}: not a directory").into()));
+    }
+    self.vfs().execute_check(&vinode)
+      .map_err(|_| Errno::EACCES(format!("chdir: {pathname}: permission denied").into()))?;
+
+    let current_process_id = self.current_process_id();
+    self.processes()
+      .get_mut(&current_process_id)
+      .ok_or(Errno::ESRCH(String::from("chdir: cannot get current process").into()))?
+      .cwd = pathname;
+    self.update_vfs_current_dir();
+
+    Ok(())
+  }
+  /// Returns: working directory of current process
+  pub fn getcwd(&self) -> Result<String, Errno> {
+    self.processes()
+      .get(&self.current_process_id())
+      .map(|process| process.cwd.clone())
+      .ok_or(Errno::ESRCH(String::from("getcwd: cannot get current process").into()))
+  }
+  /// Set mode of file open at `file_descriptor`, like `fchmod`
+  pub fn chmod(&mut self, file_descriptor: FileDescriptor, mode: FileMode) -> Result<(), Errno> {
+    let pathname = self.file_description(file_descriptor)?.pathname
+      .ok_or(Errno::EIO(String::from("chmod: file description has no pathname").into()))?;
+
+    self.vfs().change_mode(&pathname, mode)
+  }
+  /// Up to `count` entries of directory open at `file_descriptor`,
+  /// from where the last call stopped. Nothing when all of them are
+  /// listed already
+  pub fn getdents(&mut self, file_descriptor: FileDescriptor, count: AddressSize) -> Result<Vec<VDirectoryEntry>, Errno> {
+    let FileDescription {
+      flags,
+      pathname,
+      offset,
+      ..
+    } = self.file_description(file_descriptor)?;
+    let pathname = pathname
+      .ok_or(Errno::EIO(String::from("getdents: file description has no pathname").into()))?;
+
+    // Guard for OpenMode
+    match flags.mode() {
+      OpenMode::Write => return Err(Errno::EBADFD(String::from("getdents: directory is not open for reading").into())),
+      OpenMode::ReadWrite | OpenMode::Read => (),
+    }
+
+    let entries = self.vfs().readdir(&pathname, offset, count)?;
+    self.shared_file_description(file_descriptor)?.lock().unwrap().offset = offset + entries.len() as AddressSize;
+
+    Ok(entries)
+  }
+  /// Storage of block device that `source` of `mount` names:
+  /// its pathname, `LABEL=<label>` or `UUID=<uuid>`
+  pub fn open_mount_source(&mut self, source: &str) -> Result<Box<dyn BlockStorage>, Errno> {
+    let source = Self::mount_source_pathname(source)?;
+    let mut vfs = self.vfs();
+    let (mount_point, internal_path) = vfs.match_mount_point(&source)?;
+    let mounted_fs = vfs.mount_points.get_mut(&mount_point).expect("VFS::lookup_path: we know that mount_point exist");
+
+    if mounted_fs.r#type != FilesystemType::devfs {
+      return Err(Errno::EINVAL(String::from("source is not a device").into()));
+    }
+
+    mounted_fs.driver
+      .as_any()
+      .downcast_ref::<DeviceFilesystem>()
+      .expect("we know that mounted_fs.driver === instanceof DeviceFilesystem")
+      .open_block_storage(&internal_path)
+  }
+
+  /// Returns: pathname of device that mount `source` stands for.
+  /// Filesystems with labels and UUIDs have aliases in devfs
+  ///
+  /// Errors: `EINVAL` if label or UUID would name
+  /// something outside of their directory
+  fn mount_source_pathname(source: &str) -> Result<String, Errno> {
+    let (directory, name) = match source.split_once('=') {
+      Some(("LABEL", label)) => (DISK_BY_LABEL_PATH, label),
+      Some(("UUID", uuid)) => (DISK_BY_UUID_PATH, uuid),
+      _ => return Ok(source.to_owned()),
+    };
+    if name.is_empty() || name == "." || name == ".." || name.contains('/') {
+      return Err(Errno::EINVAL(format!("bad mount source: {source}").into()));
+    }
+
+    Ok(format!("{DEV_PATH}{directory}/{name}"))
+  }
+  /// Returns: positions of disks of machine in device table,
+  /// `N`th of them is `N`th disk
+  fn disk_positions(&self) -> Vec<usize> {
+    self.devices().devices
+      .iter()
+      .enumerate()
+      .filter(|(_, (device, _))| device.r#type == VirtualDeviceType::BlockDevice)
+      .map(|(position, _)| position)
+      .collect()
+  }
+  /// Returns: index of disk that mount `source` is, `None` if it
+  /// is not a whole disk of machine
+  fn disk_of_mount_source(&mut self, source: &str) -> Option<u16> {
+    // Root is mounted from devfs before there is root to look it up from
+    let mut vfs = self.vfs();
+    let (mount_point, internal_pathname) = vfs.match_mount_point(&Self::mount_source_pathname(source).ok()?).ok()?;
+    let mounted_fs = vfs.mount_points.get_mut(&mount_point)?;
+    if mounted_fs.r#type != FilesystemType::devfs {
+      return None;
+    }
+    let rdev = mounted_fs.driver.stat(&internal_pathname).ok()?.rdev;
+    drop(vfs);
+
+    (0..self.disk_positions().len() as u16)
+      .find(|index| rdev == DeviceNumber::new(SD_MAJOR, index * SD_MINORS_PER_DISK))
+  }
+  /// Returns: mount point of filesystem on block device at `pathname`,
+  /// or on whole disk or partition of it, `None` if nothing there
+  /// is mounted. Mounts are matched by device numbers of their sources
+  pub fn device_mount_point(&mut self, pathname: &str) -> Option<String> {
+    let mut vfs = self.vfs();
+    let rdev = vfs.stat(pathname).ok()?.rdev;
+    let overlaps = |other: DeviceNumber| other == rdev || (
+      other.major == SD_MAJOR && rdev.major == SD_MAJOR
+        && other.minor / SD_MINORS_PER_DISK == rdev.minor / SD_MINORS_PER_DISK
+        && (other.minor % SD_MINORS_PER_DISK == 0 || rdev.minor % SD_MINORS_PER_DISK == 0)
+    );
+
+    let mounts: Vec<(String, String)> = vfs.mount_points
+      .iter()
+      .filter(|(_, mounted_fs)| mounted_fs.r#type == FilesystemType::e5fs)
+      .map(|(mount_point, mounted_fs)| (mount_point.clone(), mounted_fs.source.clone()))
+      .collect();
+    mounts.into_iter().find_map(|(mount_point, source)| {
+      let other = vfs.stat(&Self::mount_source_pathname(&source).ok()?).ok()?.rdev;
+      overlaps(other).then_some(mount_point)
+    })
+  }
+  /// Record that disk `index` is mounted at `mount_point`, or is
+  /// not mounted, in device table and `mounted` of it in sysfs
+  fn set_disk_mounted(&mut self, index: u16, mount_point: Option<String>) {
+    let name = disk_name(index);
+    let mut vfs = self.vfs();
+    for mounted_fs in vfs.mount_points.values_mut().filter(|mounted_fs| mounted_fs.r#type == FilesystemType::sysfs) {
+      let _ = mounted_fs.driver
+        .as_any()
+        .downcast_mut::<SystemFilesystem>()
+        .expect("we know that mounted_fs.driver === instanceof SystemFilesystem")
+        .set_mounted(&name, mount_point.as_deref());
+    }
+
+    drop(vfs);
+
+    let position = self.disk_positions()[index as usize];
+    self.devices_mut().devices[position].1 = mount_point;
+  }
+
+  pub fn mount(&mut self, source: &str, target: &str, fs_type: FilesystemType) -> Result<(), Errno> {
+    self.mount_with_options(source, target, fs_type, &MountOptions::default())
+  }
+
+  /// Same as `mount`, with `options`
+  ///
+  /// Errors:
+  /// EACCES -> filesystem is encrypted and passphrase is wrong
+  ///           or not given
+  /// ENOENT -> there is no such snapshot
+  pub fn mount_with_options(&mut self, source: &str, target: &str, fs_type: FilesystemType, options: &MountOptions) -> Result<(), Errno> {
+    let vfs = self.vfs();
+    if vfs.mount_points.contains_key(target) || vfs.bind_mounts.contains_key(target) {
+      return Err(Errno::EINVAL(String::from("mount point already taken").into()))
+    }
+    drop(vfs);
+
+    let disk = match fs_type {
+      FilesystemType::e5fs => self.disk_of_mount_source(source),
+      _ => None,
+    };
+    let mut mounted_fs = match fs_type {
+      FilesystemType::e5fs => {
+        let storage = self.open_mount_source(source)?;
+
+        // Instantiate new e5fs around device that we've found.
+        // Snapshot may be of filesystem mounted elsewhere, which
+        // is the only one to write to device then
+        let passphrase = options.passphrase.as_deref();
+        let mut e5fs = match options.flags.read_only || options.snapshot.is_some() {
+          true => eunix::e5fs::E5FSFilesystem::from_storage_read_only(storage, passphrase)?,
+          false => eunix::e5fs::E5FSFilesystem::from_storage_with_passphrase(storage, passphrase)?,
+        };
+        if let Some(snapshot) = &options.snapshot {
+          e5fs.open_snapshot(snapshot)?;
+        }
+        if let Some(address) = e5fs.superblock_backup {
+          self.log(tracing::Level::WARN, &format!("{source} had corrupt superblock, restored it from backup at {address}, check it with fsck.e5fs"));
+        } else if e5fs.needs_check && options.snapshot.is_none() {
+          self.log(tracing::Level::WARN, &format!("{source} was not cleanly unmounted, check it with fsck.e5fs"));
+        }
+
+        MountedFilesystem {
+          r#type: FilesystemType::e5fs,
+          driver: Box::new(e5fs),
+          flags: MountFlags::default(),
+          source: source.to_owned(),
+        }
+      },
+      FilesystemType::binfs => {
+        let binfs = BinFilesytem::new();
+
+        MountedFilesystem {
+          r#type: FilesystemType::binfs,
+          driver: Box::new(binfs),
+          flags: MountFlags::default(),
+          source: source.to_owned(),
+        }
+      },
+      FilesystemType::procfs => {
+        let procfs = ProcessFilesystem::new();
+
+        MountedFilesystem {
+          r#type: FilesystemType::procfs,
+          driver: Box::new(procfs),
+          flags: MountFlags::default(),
+          source: source.to_owned(),
+        }
+      },
+      FilesystemType::sysfs => {
+        let sysfs = SystemFilesystem::new(&self.devices())?;
+
+        MountedFilesystem {
+          r#type: FilesystemType::sysfs,
+          driver: Box::new(sysfs),
+          flags: MountFlags::default(),
+          source: source.to_owned(),
+        }
+      },
+      FilesystemType::netfs => {
+        // Tree is exported on the other end of network interface
+        let nic = match self.devfs_at(source)? {
+          Some((mut devfs, name)) => match (devfs.stat(&format!("/{name}"))?.rdev.major, devfs.device_driver(&name)?) {
+            (NET_MAJOR, DeviceDriver::Char(nic)) => nic,
+            _ => return Err(Errno::EINVAL(format!("{source} is not a network interface").into())),
+          },
+          None => return Err(Errno::EINVAL(String::from("source is not a device").into())),
+        };
+
+        MountedFilesystem {
+          r#type: FilesystemType::netfs,
+          driver: Box::new(NetFilesystem::new(nic)),
+          flags: MountFlags::default(),
+          source: source.to_owned(),
+        }
+      },
+      FilesystemType::overlayfs => {
+        let (Some(lowerdir), Some(upperdir)) = (&options.lowerdir, &options.upperdir) else {
+          return Err(Errno::EINVAL(String::from("overlayfs needs lowerdir and upperdir").into()));
+        };
+        let (lower_mount_point, lower_root) = self.layer_dir(lowerdir)?;
+        let (upper_mount_point, upper_root) = self.layer_dir(upperdir)?;
+        if lower_mount_point == upper_mount_point {
+          return Err(Errno::EINVAL(String::from("lowerdir and upperdir must be on different filesystems").into()));
+        }
+        if self.vfs().mount_points[&upper_mount_point].flags.read_only {
+          return Err(Errno::EROFS(format!("upperdir {upperdir} is on read-only filesystem").into()));
+        }
+
+        let take_layer = |mount_point: String, root: String| Layer {
+          mounted_fs: self.vfs().mount_points.remove(&mount_point).expect("we know that layer mount_point exists"),
+          mount_point,
+          root,
+        };
+        let lower = take_layer(lower_mount_point, lower_root);
+        let upper = take_layer(upper_mount_point, upper_root);
+
+        MountedFilesystem {
+          r#type: FilesystemType::overlayfs,
+          driver: Box::new(OverlayFilesystem::new(lower, upper)),
+          flags: MountFlags::default(),
+          source: source.to_owned(),
+        }
+      },
+      FilesystemType::devfs => {
+        let devfs = eunix::devfs::DeviceFilesystem::new(&self.devices(), self.rng.clone());
+
+        MountedFilesystem {
           r#type: FilesystemType::devfs,
           driver: Box::new(devfs),
+          flags: MountFlags::default(),
+          source: source.to_owned(),
         }
       },
     };
 
     // Finally, insert constructed mounted_fs
-    self.vfs.mount_points.insert(target.to_owned(), mounted_fs);
+    mounted_fs.flags = MountFlags {
+      read_only: options.flags.read_only || options.snapshot.is_some(),
+      ..options.flags
+    };
+    mounted_fs.driver.set_clock(self.clock.clone());
+    mounted_fs.driver.set_current_user(self.vfs_context.uid, self.vfs_context.gid);
+    self.vfs().mount_points.insert(target.to_owned(), mounted_fs);
+    if let Some(index) = disk {
+      self.set_disk_mounted(index, Some(target.to_owned()));
+    }
+    self.update_procfs();
 
     Ok(())
   }
+  /// Returns: devfs that `pathname` is on, locked with VFS, and name
+  /// of device in it, `None` if `pathname` is not on devfs
+  fn devfs_at(&self, pathname: &str) -> Result<Option<(MappedMutexGuard<'_, DeviceFilesystem>, String)>, Errno> {
+    let vfs = self.vfs();
+    let (mount_point, internal_pathname) = vfs.match_mount_point(pathname)?;
+    if vfs.mount_points[&mount_point].r#type != FilesystemType::devfs {
+      return Ok(None);
+    }
+    let (_, name) = VFS::split_path(&internal_pathname)?;
+
+    let devfs = MutexGuard::map(vfs, |vfs| vfs.mount_points
+      .get_mut(&mount_point)
+      .expect("Kernel::devfs_at: we know that mount_point exist")
+      .driver
+      .as_any()
+      .downcast_mut::<DeviceFilesystem>()
+      .expect("we know that mounted_fs.driver === instanceof DeviceFilesystem")
+    );
+
+    Ok(Some((devfs, name)))
+  }
+  /// Returns: mount point of the first mounted devfs and its driver,
+  /// locked with VFS
+  fn devfs(&self) -> Result<(String, MappedMutexGuard<'_, DeviceFilesystem>), Errno> {
+    let vfs = self.vfs();
+    let mount_point = vfs.mount_points
+      .iter()
+      .find(|(_, mounted_fs)| mounted_fs.r#type == FilesystemType::devfs)
+      .map(|(mount_point, _)| mount_point.to_owned())
+      .ok_or(Errno::ENOENT(String::from("devfs is not mounted").into()))?;
+
+    let devfs = MutexGuard::map(vfs, |vfs| vfs.mount_points
+      .get_mut(&mount_point)
+      .expect("Kernel::devfs: we know that mount_point exist")
+      .driver
+      .as_any()
+      .downcast_mut::<DeviceFilesystem>()
+      .expect("we know that mounted_fs.driver === instanceof DeviceFilesystem")
+    );
+
+    Ok((mount_point, devfs))
+  }
+  /// Returns: name in devfs and driver of character device at
+  /// `pathname`, `/dev/tty` being controlling terminal. Terminal
+  /// may be in the middle of read, so driver is to be locked
+  /// with VFS unlocked
+  fn char_device_at(&mut self, pathname: &str) -> Result<(String, Arc<RwLock<dyn CharDevice>>), Errno> {
+    let controlling_tty = self.controlling_tty();
+    let (devfs, name) = self
+      .devfs_at(pathname)?
+      .ok_or(Errno::ENOTTY(format!("{pathname} is not a tty").into()))?;
+
+    // `/dev/tty` stands for controlling terminal
+    let name = match name.as_str() {
+      CONTROLLING_TTY_NAME => controlling_tty.ok_or(Errno::ENXIO(String::from("no controlling terminal").into()))?,
+      _ => name,
+    };
+
+    match devfs.device_driver(&name)? {
+      DeviceDriver::Char(driver) => Ok((name, driver)),
+      DeviceDriver::Block(_) => Err(Errno::ENOTTY(format!("{pathname} is not a tty").into())),
+    }
+  }
+  /// Switch line discipline of TTY at `pathname` (must be on devfs)
+  pub fn set_tty_mode(&mut self, pathname: &str, mode: TTYMode) -> Result<(), Errno> {
+    let (_, driver) = self.char_device_at(pathname)
+      .with_context(|| "set_tty_mode")?;
+    if !driver.read().unwrap().is_tty() {
+      return Err(Errno::ENOTTY(format!("set_tty_mode: {pathname} is not a tty").into()));
+    }
+
+    driver.write().unwrap().set_tty_mode(mode)?;
+
+    Ok(())
+  }
+  /// Make TTY at `pathname` (must be on devfs) the controlling
+  /// terminal of current session
+  pub fn set_controlling_tty(&mut self, pathname: &str) -> Result<(), Errno> {
+    let (name, driver) = self.tty_driver_at(pathname)
+      .with_context(|| "set_controlling_tty")?;
+    drop(driver);
+
+    let mut processes = self.processes();
+    let sid = processes
+      .get(&self.current_process_id)
+      .ok_or(Errno::ESRCH(format!("set_controlling_tty: no current process").into()))?
+      .sid;
+    processes
+      .values_mut()
+      .filter(|process| process.sid == sid)
+      .for_each(|process| process.controlling_tty = Some(name.to_owned()));
+    drop(processes);
+    self.update_vfs_current_tty();
+
+    Ok(())
+  }
+  /// Returns: name in devfs and driver of terminal at `pathname`,
+  /// not `/dev/tty` itself
+  fn tty_driver_at(&mut self, pathname: &str) -> Result<(String, Arc<RwLock<dyn CharDevice>>), Errno> {
+    let (devfs, name) = self
+      .devfs_at(pathname)?
+      .ok_or(Errno::ENOTTY(format!("{pathname} is not a tty").into()))?;
+    let driver = devfs.device_driver(&name)?;
+    drop(devfs);
+
+    match driver {
+      DeviceDriver::Char(driver) if name != CONTROLLING_TTY_NAME && driver.read().unwrap().is_tty() => Ok((name, driver)),
+      _ => Err(Errno::ENOTTY(format!("{pathname} is not a tty").into())),
+    }
+  }
+  /// Returns: driver of terminal at `pathname`
+  pub fn tty_driver(&mut self, pathname: &str) -> Result<Arc<RwLock<dyn CharDevice>>, Errno> {
+    self.tty_driver_at(pathname)
+      .map(|(_, driver)| driver)
+      .with_context(|| "tty_driver")
+  }
+  /// Plug `driver` into TTY at `pathname` in place of its own,
+  /// like another terminal attached to the same line
+  pub fn replace_tty_driver(&mut self, pathname: &str, driver: Arc<RwLock<dyn CharDevice>>) -> Result<(), Errno> {
+    let (name, _) = self.tty_driver_at(pathname)
+      .with_context(|| "replace_tty_driver")?;
+    let (mut devfs, _) = self
+      .devfs_at(pathname)?
+      .ok_or(Errno::ENOTTY(format!("replace_tty_driver: {pathname} is not a tty").into()))?;
+
+    devfs.replace_driver(&name, DeviceDriver::Char(driver)).map(|_| ())
+  }
+  /// Attach regular file at `pathname` as the first free loop device.
+  /// Returns: pathname of the device, like `/dev/loop0`
+  pub fn attach_loop(&mut self, pathname: &str) -> Result<String, Errno> {
+    // Guard for not a regular file
+    let mode = self.vfs().stat(pathname)?.mode;
+    if mode.file_type() != FileModeType::File as u8 {
+      return Err(Errno::EINVAL(format!("attach_loop: {pathname} is not a regular file").into()));
+    }
+
+    let data = self.vfs().read_file(pathname, EVERYTHING)?;
+    let (mount_point, mut devfs) = self.devfs()?;
+    let name = LoopDevice::attach(&mut devfs, pathname, data)?;
+
+    Ok(format!("{}/{name}", mount_point.trim_end_matches('/')))
+  }
+  /// Detach loop device at `pathname`, writing its contents
+  /// back to the attached file
+  pub fn detach_loop(&mut self, pathname: &str) -> Result<(), Errno> {
+    let (mut devfs, name) = self
+      .devfs_at(pathname)?
+      .ok_or(Errno::EINVAL(format!("detach_loop: {pathname} is not a loop device").into()))?;
+    let (backing_pathname, data) = LoopDevice::detach(&mut devfs, &name)?;
+    drop(devfs);
+
+    self.vfs().write_file(&backing_pathname, &data)?;
+
+    Ok(())
+  }
+  /// Create new RAM disk of `size` bytes, taken from memory of machine.
+  /// Returns: pathname of the device, like `/dev/ram0`
+  ///
+  /// Errors:
+  /// ENOMEM -> there is less than `size` bytes of memory left
+  /// ENXIO  -> all RAM devices are taken
+  pub fn create_ram_disk(&mut self, size: u64) -> Result<String, Errno> {
+    let free = self.resources.memory.saturating_sub(self.memory_used());
+    if size > free {
+      return Err(Errno::ENOMEM(format!("create_ram_disk: {size} bytes is more than {free} bytes of memory left").into()));
+    }
+
+    let (mount_point, mut devfs) = self.devfs()?;
+    let name = RamDisk::create(&mut devfs, size)?;
+    drop(devfs);
+    self.devices_mut().ram_disks.push(size);
+
+    Ok(format!("{}/{name}", mount_point.trim_end_matches('/')))
+  }
+  /// Returns: `(device_pathname, backing_pathname)` of every attached loop device
+  pub fn loop_devices(&mut self) -> Result<Vec<(String, String)>, Errno> {
+    let (mount_point, devfs) = self.devfs()?;
+
+    Ok(
+      LoopDevice::list(&devfs)
+        .into_iter()
+        .map(|(name, backing_pathname)| (format!("{}/{name}", mount_point.trim_end_matches('/')), backing_pathname))
+        .collect()
+    )
+  }
+  /// Record `message` in dmesg and emit it as a `kernel` tracing event.
+  /// Messages at console level of dmesg are also written to the serial port,
+  /// unless there is no serial port or devfs is not mounted yet
+  pub fn log(&mut self, level: tracing::Level, message: &str) {
+    match level {
+      tracing::Level::ERROR => tracing::error!(target: "kernel", "{message}"),
+      tracing::Level::WARN => tracing::warn!(target: "kernel", "{message}"),
+      tracing::Level::INFO => tracing::info!(target: "kernel", "{message}"),
+      tracing::Level::DEBUG => tracing::debug!(target: "kernel", "{message}"),
+      tracing::Level::TRACE => tracing::trace!(target: "kernel", "{message}"),
+    }
+
+    let mut dmesg = self.dmesg.lock().unwrap();
+    dmesg.push(DmesgRecord { time: self.clock.now(), level, message: message.to_owned() });
+    let is_on_console = dmesg.is_on_console(level);
+    drop(dmesg);
+    if is_on_console {
+      let _ = self.write_file(KERNEL_LOG_PATH, format!("[kernel]: {message}\n").as_bytes());
+    }
+  }
+  /// Show directory `source` at `target` too, like `mount --bind`.
+  /// Filesystems mounted under `source` are not shown there
+  ///
+  /// Errors:
+  /// ENOTDIR -> source is not a directory
+  /// EINVAL  -> target is already a mount point
+  pub fn bind_mount(&mut self, source: &str, target: &str) -> Result<(), Errno> {
+    let mut vfs = self.vfs();
+    let target = VFS::normalize_path(&vfs.absolute_path(target))?;
+    if vfs.mount_points.contains_key(&target) || vfs.bind_mounts.contains_key(&target) {
+      return Err(Errno::EINVAL(String::from("mount point already taken").into()))
+    }
+    if vfs.lookup_path(source)?.mode.file_type() != FileModeType::Dir as u8 {
+      return Err(Errno::ENOTDIR(format!("{source}: not a directory").into()));
+    }
+
+    // Binding a bind mount shows the same directory
+    let (mount_point, internal_pathname) = vfs.match_mount_point(source)?;
+    vfs.bind_mounts.insert(target, BindMount {
+      mount_point,
+      internal_pathname,
+    });
+    drop(vfs);
+    self.update_procfs();
+
+    Ok(())
+  }
+  /// Everything that is mounted, sorted by target. Bind mounts have
+  /// source, type and options of filesystem they show, like on Linux
+  pub fn mount_table(&self) -> Vec<MountEntry> {
+    let vfs = self.vfs();
+    let bind_mounts = vfs.bind_mounts
+      .iter()
+      .filter_map(|(target, bind_mount)| Some((target, vfs.mount_points.get(&bind_mount.mount_point)?)));
+
+    let mut entries = vfs.mount_points
+      .iter()
+      .chain(bind_mounts)
+      .map(|(target, mounted_fs)| MountEntry {
+        source: match mounted_fs.source.is_empty() {
+          true => mounted_fs.r#type.to_string(),
+          false => mounted_fs.source.clone(),
+        },
+        target: target.clone(),
+        r#type: mounted_fs.r#type.to_string(),
+        flags: mounted_fs.flags,
+      })
+      .collect::<Vec<_>>();
+    entries.sort_by(|entry1, entry2| entry1.target.cmp(&entry2.target));
+
+    entries
+  }
+
+  /// Mount point and pathname on its filesystem of directory
+  /// `pathname`, that overlayfs takes filesystem from to be its layer
+  ///
+  /// Errors:
+  /// ENOTDIR -> it is not a directory
+  /// EBUSY   -> it is on root filesystem, or filesystem is bind
+  ///            mounted elsewhere
+  fn layer_dir(&mut self, pathname: &str) -> Result<(String, String), Errno> {
+    let mut vfs = self.vfs();
+    if vfs.lookup_path(pathname)?.mode.file_type() != FileModeType::Dir as u8 {
+      return Err(Errno::ENOTDIR(format!("{pathname}: not a directory").into()));
+    }
+
+    let (mount_point, internal_pathname) = vfs.match_mount_point(pathname)?;
+    if mount_point == "/" {
+      return Err(Errno::EBUSY(format!("{pathname}: root filesystem can't be a layer").into()));
+    }
+    if vfs.bind_mounts.values().any(|bind_mount| bind_mount.mount_point == mount_point) {
+      return Err(Errno::EBUSY(format!("{mount_point}: filesystem is bind mounted elsewhere").into()));
+    }
+
+    Ok((mount_point, internal_pathname))
+  }
+
+  /// Unmount filesystem at `target`, flushing it to its device.
+  /// Layers of overlayfs go back where they were mounted
+  ///
+  /// Errors:
+  /// ENOENT -> nothing is mounted at target
+  /// EBUSY  -> filesystem at target is shown elsewhere by bind mount,
+  ///           or something is mounted where layer of overlay was
   pub fn umount(&mut self, target: &str) -> Result<(), Errno> {
-    self.vfs.mount_points.remove(target).ok_or(Errno::ENOENT(String::from("no such mount point")))?;
+    let mut vfs = self.vfs();
+    if vfs.bind_mounts.remove(target).is_some() {
+      drop(vfs);
+      self.update_procfs();
+      return Ok(());
+    }
+    if vfs.bind_mounts.values().any(|bind_mount| bind_mount.mount_point == target) {
+      return Err(Errno::EBUSY(format!("{target}: filesystem is bind mounted elsewhere").into()));
+    }
+    let overlay = vfs.mount_points
+      .get(target)
+      .and_then(|mounted_fs| mounted_fs.driver.as_any_ref().downcast_ref::<OverlayFilesystem>());
+    if let Some(layer) = overlay.iter().flat_map(|overlay| overlay.layers()).find(|layer| vfs.mount_points.contains_key(&layer.mount_point)) {
+      return Err(Errno::EBUSY(format!("{target}: {} is mounted over, its layer can't go back", layer.mount_point).into()));
+    }
+
+    // Filesystem stays mounted if it can't be flushed
+    let mounted_fs = vfs.mount_points.get_mut(target).ok_or(Errno::ENOENT(String::from("no such mount point").into()))?;
+    if mounted_fs.r#type == FilesystemType::e5fs {
+      mounted_fs.driver
+        .as_any()
+        .downcast_mut::<eunix::e5fs::E5FSFilesystem>()
+        .expect("we know that mounted_fs.driver === instanceof E5FSFilesystem")
+        .unmount()?;
+    }
+
+    let mut mounted_fs = vfs.mount_points.remove(target).expect("we know that target is mounted");
+    if mounted_fs.r#type == FilesystemType::overlayfs {
+      let overlay = mounted_fs.driver
+        .as_any()
+        .downcast_mut::<OverlayFilesystem>()
+        .expect("we know that mounted_fs.driver === instanceof OverlayFilesystem");
+      for layer in overlay.take_layers() {
+        vfs.mount_points.insert(layer.mount_point, layer.mounted_fs);
+      }
+    }
+    drop(vfs);
+
+    let mounted_disk = (0..).zip(self.disk_positions())
+      .find(|&(_, position)| self.devices().devices[position].1.as_deref() == Some(target));
+    if let Some((index, _)) = mounted_disk {
+      self.set_disk_mounted(index, None);
+    }
+    self.update_procfs();
+
+    Ok(())
+  }
+  /// Unmount everything, nested mount points first.
+  /// Returns: the first error, after trying to unmount the rest anyway
+  pub fn umount_all(&mut self) -> Result<(), Errno> {
+    // Nested mount points sort after their parents. Bind mounts
+    // go first, they keep filesystems they show busy. Layers that
+    // overlays give back are unmounted on the next pass
+    let mut result = Ok(());
+    loop {
+      let vfs = self.vfs();
+      let targets = vfs.bind_mounts.keys().rev()
+        .chain(vfs.mount_points.keys().rev())
+        .cloned()
+        .collect::<Vec<_>>();
+      drop(vfs);
+
+      result = targets
+        .iter()
+        .map(|target| self.umount(target))
+        .fold(result, |result, umount_result| result.and(umount_result));
+
+      let vfs = self.vfs();
+      let left_count = vfs.bind_mounts.len() + vfs.mount_points.len();
+      drop(vfs);
+      if left_count == 0 || left_count >= targets.len() {
+        return result;
+      }
+    }
+  }
+}
+
+// Sockets. Kernel looks at network interface only when
+// sockets on it are used: there are no interrupts
+impl Kernel {
+  /// Returns: driver of network interface named `interface` in devfs
+  fn nic(&mut self, interface: &str) -> Result<Arc<RwLock<dyn CharDevice>>, Errno> {
+    let (_, mut devfs) = self.devfs()?;
+
+    match (devfs.stat(&format!("/{interface}"))?.rdev.major, devfs.device_driver(interface)?) {
+      (NET_MAJOR, DeviceDriver::Char(nic)) => Ok(nic),
+      _ => Err(Errno::ENXIO(format!("net: {interface} is not a network interface").into())),
+    }
+  }
+  /// Returns: sockets of current process, locked with process table
+  fn sockets_mut(&self) -> Result<MappedMutexGuard<'_, BTreeMap<SocketDescriptor, Socket>>, Errno> {
+    MutexGuard::filter_map(self.processes(), |processes| processes
+      .get_mut(&self.current_process_id)
+      .map(|process| &mut process.sockets)
+    )
+    .or(Err(Errno::ESRCH(String::from("net: cannot get current process").into())))
+  }
+  /// Returns: socket of current process, locked with process table
+  fn socket_mut(&self, socket_descriptor: SocketDescriptor) -> Result<MappedMutexGuard<'_, Socket>, Errno> {
+    MappedMutexGuard::filter_map(self.sockets_mut()?, |sockets| sockets.get_mut(&socket_descriptor))
+      .or(Err(Errno::EBADFD(format!("net: socket {socket_descriptor} is not open").into())))
+  }
+  /// Returns: interface that socket is bound to
+  fn bound_interface(&mut self, socket_descriptor: SocketDescriptor) -> Result<String, Errno> {
+    self.socket_mut(socket_descriptor)?
+      .local
+      .as_ref()
+      .map(|local| local.interface.clone())
+      .ok_or(Errno::EINVAL(format!("net: socket {socket_descriptor} is not bound").into()))
+  }
+  fn transmit(&mut self, interface: &str, packet: &Packet) -> Result<(), Errno> {
+    self.nic(interface)?.write().unwrap().write(&packet.encode())
+  }
+  /// Take every frame that came to `interface` and give packets
+  /// in them to sockets of every process. Frames that are not
+  /// packets, like netfs ones, are dropped
+  fn poll_interface(&mut self, interface: &str) -> Result<(), Errno> {
+    let nic = self.nic(interface)?;
+
+    loop {
+      let frame = nic.write().unwrap().read(MAX_FRAME_SIZE as AddressSize)?;
+      if frame.is_empty() {
+        return Ok(());
+      }
+      let packet = match Packet::decode(&frame) {
+        Some(packet) => packet,
+        None => continue,
+      };
+
+      let source = SocketAddress::new(interface, packet.source_port);
+      let reply = match self.processes()
+        .values_mut()
+        .flat_map(|process| process.sockets.values_mut())
+        .find(|socket| socket.accepts(interface, &packet)) {
+        Some(socket) => socket.receive(source, packet),
+        None => net::unclaimed_reply(&packet),
+      };
+      if let Some(reply) = reply {
+        nic.write().unwrap().write(&reply.encode())?;
+      }
+    }
+  }
+  /// Make new socket of `type` in socket table of current process
+  pub fn socket(&mut self, r#type: SocketType) -> Result<SocketDescriptor, Errno> {
+    let mut sockets = self.sockets_mut()?;
+    let socket_descriptor = sockets.keys().last().map_or(0, |last| last + 1);
+    sockets.insert(socket_descriptor, Socket::new(r#type));
+
+    Ok(socket_descriptor)
+  }
+  /// Bind socket to `address`, port 0 means any free one
+  pub fn bind(&mut self, socket_descriptor: SocketDescriptor, address: SocketAddress) -> Result<(), Errno> {
+    self.nic(&address.interface)?;
+    let r#type = self.socket_mut(socket_descriptor)?.r#type;
+
+    // Connections made by `accept` share port with their listening socket
+    let taken_ports = self.processes()
+      .values()
+      .flat_map(|process| process.sockets.values())
+      .filter(|socket| socket.r#type == r#type)
+      .filter_map(|socket| socket.local.as_ref())
+      .filter(|local| local.interface == address.interface)
+      .map(|local| local.port)
+      .collect::<Vec<_>>();
+    let port = match address.port {
+      0 => EPHEMERAL_PORTS
+        .into_iter()
+        .find(|port| !taken_ports.contains(port))
+        .ok_or(Errno::EADDRINUSE(String::from("net: no free ports").into()))?,
+      port if taken_ports.contains(&port) => return Err(Errno::EADDRINUSE(format!("net: {address} is in use").into())),
+      port => port,
+    };
+
+    let mut socket = self.socket_mut(socket_descriptor)?;
+    if socket.local.is_some() {
+      return Err(Errno::EINVAL(format!("net: socket {socket_descriptor} is bound already").into()));
+    }
+    socket.local = Some(SocketAddress::new(&address.interface, port));
 
     Ok(())
   }
+  pub fn socket_type(&mut self, socket_descriptor: SocketDescriptor) -> Result<SocketType, Errno> {
+    Ok(self.socket_mut(socket_descriptor)?.r#type)
+  }
+  /// Wait for connections to bound stream socket
+  pub fn listen(&mut self, socket_descriptor: SocketDescriptor) -> Result<(), Errno> {
+    self.bound_interface(socket_descriptor)?;
+    let mut socket = self.socket_mut(socket_descriptor)?;
+    if socket.r#type != SocketType::Stream {
+      return Err(Errno::EINVAL(format!("net: socket {socket_descriptor} is not a stream one").into()));
+    }
+    socket.state = SocketState::Listening;
+
+    Ok(())
+  }
+  /// Take connection made to listening socket. Never blocks.
+  /// Returns: new socket of connection and address of its peer,
+  /// `None` if there are no connections yet
+  pub fn accept(&mut self, socket_descriptor: SocketDescriptor) -> Result<Option<(SocketDescriptor, SocketAddress)>, Errno> {
+    let interface = self.bound_interface(socket_descriptor)?;
+    self.poll_interface(&interface)?;
+
+    let mut socket = self.socket_mut(socket_descriptor)?;
+    if socket.state != SocketState::Listening {
+      return Err(Errno::EINVAL(format!("net: socket {socket_descriptor} is not listening").into()));
+    }
+    let connection = match socket.backlog.pop_front() {
+      Some(connection) => connection,
+      None => return Ok(None),
+    };
+
+    drop(socket);
+
+    let peer = connection.peer.clone().expect("we know that connections have peer");
+    let mut sockets = self.sockets_mut()?;
+    let connection_descriptor = sockets.keys().last().map_or(0, |last| last + 1);
+    sockets.insert(connection_descriptor, connection);
+
+    Ok(Some((connection_descriptor, peer)))
+  }
+  /// Set the other end of socket, binding it to free port if it is not bound.
+  /// Stream sockets wait for connection to be made for `CONNECT_TIMEOUT`
+  pub fn connect(&mut self, socket_descriptor: SocketDescriptor, peer: SocketAddress) -> Result<(), Errno> {
+    if self.socket_mut(socket_descriptor)?.local.is_none() {
+      self.bind(socket_descriptor, SocketAddress::new(&peer.interface, 0))?;
+    }
+    let interface = self.bound_interface(socket_descriptor)?;
+    if interface != peer.interface {
+      return Err(Errno::EINVAL(format!("net: socket {socket_descriptor} is bound to {interface}, not {}", peer.interface).into()));
+    }
+
+    let mut socket = self.socket_mut(socket_descriptor)?;
+    socket.peer = Some(peer);
+    if socket.r#type != SocketType::Stream {
+      socket.state = SocketState::Connected;
+      return Ok(());
+    }
+    socket.state = SocketState::Connecting;
+    let syn = socket.packet(net::FLAG_SYN, Vec::new());
+    drop(socket);
+    self.transmit(&interface, &syn)?;
+
+    let started = std::time::Instant::now();
+    while started.elapsed() < CONNECT_TIMEOUT {
+      self.poll_interface(&interface)?;
+      match self.socket_mut(socket_descriptor)?.state {
+        SocketState::Connected => return Ok(()),
+        SocketState::Closed => return Err(Errno::ECONNREFUSED(String::from("net: connection refused").into())),
+        _ => std::thread::sleep(std::time::Duration::from_millis(1)),
+      }
+    }
+
+    self.socket_mut(socket_descriptor)?.state = SocketState::Closed;
+    Err(Errno::ETIMEDOUT(String::from("net: connection timed out").into()))
+  }
+  /// Send `data` to peer of connected socket. Stream data is split
+  /// into packets, datagrams and echo requests must fit in one.
+  /// Returns: count of bytes sent
+  pub fn send(&mut self, socket_descriptor: SocketDescriptor, data: &[u8]) -> Result<AddressSize, Errno> {
+    let interface = self.bound_interface(socket_descriptor)?;
+    let socket = self.socket_mut(socket_descriptor)?;
+    if socket.state != SocketState::Connected {
+      return Err(Errno::ENOTCONN(format!("net: socket {socket_descriptor} is not connected").into()));
+    }
+
+    let max_payload = MAX_FRAME_SIZE - PACKET_HEADER_SIZE;
+    let packets = match socket.r#type {
+      SocketType::Stream => data
+        .chunks(max_payload)
+        .map(|chunk| socket.packet(FLAG_ACK, chunk.to_vec()))
+        .collect::<Vec<_>>(),
+      _ if data.len() > max_payload => {
+        return Err(Errno::EMSGSIZE(format!("net: message of {} bytes is larger than {max_payload}", data.len()).into()));
+      },
+      SocketType::Datagram => vec![socket.packet(0, data.to_vec())],
+      SocketType::Echo => vec![socket.packet(ECHO_REQUEST, data.to_vec())],
+    };
+    drop(socket);
+    for packet in packets {
+      self.transmit(&interface, &packet)?;
+    }
+
+    Ok(data.len() as AddressSize)
+  }
+  /// Receive at most `count` bytes. Never blocks: datagrams and echo
+  /// replies come whole or not at all, stream data may come in parts.
+  /// Returns: data and address it came from, `None` if nothing came yet,
+  /// empty data at the end of stream
+  pub fn recv(&mut self, socket_descriptor: SocketDescriptor, count: AddressSize) -> Result<Option<(SocketAddress, Vec<u8>)>, Errno> {
+    let interface = self.bound_interface(socket_descriptor)?;
+    self.poll_interface(&interface)?;
+
+    let mut socket = self.socket_mut(socket_descriptor)?;
+    match socket.received.pop_front() {
+      Some((source, mut data)) => {
+        if socket.r#type == SocketType::Stream && data.len() > count as usize {
+          let rest = data.split_off(count as usize);
+          socket.received.push_front((source.clone(), rest));
+        }
+        data.truncate(count as usize);
+        Ok(Some((source, data)))
+      },
+      None if socket.r#type == SocketType::Stream && socket.state == SocketState::Closed => {
+        Ok(Some((socket.peer.clone().expect("we know that closed stream sockets have peer"), Vec::new())))
+      },
+      None => Ok(None),
+    }
+  }
+  /// Close socket, telling peer of stream one about it
+  pub fn close_socket(&mut self, socket_descriptor: SocketDescriptor) -> Result<(), Errno> {
+    let socket = self.sockets_mut()?
+      .remove(&socket_descriptor)
+      .ok_or(Errno::EBADFD(format!("net: socket {socket_descriptor} is not open").into()))?;
+
+    match (&socket.local, socket.r#type, socket.state) {
+      (Some(local), SocketType::Stream, SocketState::Connected) => self.transmit(&local.interface.clone(), &socket.packet(FLAG_FIN, Vec::new())),
+      _ => Ok(()),
+    }
+  }
 }
 
 #[cfg(test)]
 mod tests {
   use super::*;
+  use crate::testing::{kernel, kernel_with_binfs_root, kernel_with_devices, kernel_with_e5fs_root, memory_e5fs, E5FS_SIZE};
+
+  #[test]
+  fn errno_carries_code_and_context() {
+    let errno = Err::<(), _>(Errno::ENOENT(String::from("e5fs: no such file").into()))
+      .with_context(|| "read_file /etc/motd")
+      .unwrap_err();
+
+    assert!(matches!(errno, Errno::ENOENT(_)));
+    assert_eq!((errno.code(), errno.name()), (2, "ENOENT"));
+    assert_eq!(errno.to_string(), "read_file /etc/motd: e5fs: no such file (ENOENT)");
+
+    let cause = std::error::Error::source(&errno).unwrap().downcast_ref::<Errno>().unwrap();
+    assert_eq!(errno.message().cause(), Some(cause));
+    assert_eq!(cause.to_string(), "e5fs: no such file (ENOENT)");
+    assert!(std::error::Error::source(cause).is_none());
+
+    let errno = Errno::from(io::Error::new(io::ErrorKind::PermissionDenied, "read-only"));
+    assert_eq!(errno, Errno::EACCES(String::from("read-only").into()));
+  }
+
+  #[test]
+  fn mount_sources_by_label_stay_in_their_directory() {
+    assert_eq!(Kernel::mount_source_pathname("/dev/sda").unwrap(), "/dev/sda");
+    assert_eq!(Kernel::mount_source_pathname("LABEL=home").unwrap(), format!("{DEV_PATH}{DISK_BY_LABEL_PATH}/home"));
+    assert_eq!(Kernel::mount_source_pathname("UUID=abc").unwrap(), format!("{DEV_PATH}{DISK_BY_UUID_PATH}/abc"));
+
+    for source in ["LABEL=..", "LABEL=.", "LABEL=", "UUID=../../sda", "LABEL=a/b"] {
+      assert!(matches!(Kernel::mount_source_pathname(source), Err(Errno::EINVAL(_))), "{source}");
+    }
+  }
+
+  #[test]
+  fn failed_unmount_keeps_filesystem_mounted() {
+    let mut kernel = kernel_with_binfs_root();
+    let (mut e5fs, buffer) = memory_e5fs(E5FS_SIZE);
+    e5fs.create_file("/file").unwrap();
+    kernel.vfs().mount_points.insert(String::from("/mnt"), MountedFilesystem {
+      r#type: FilesystemType::e5fs,
+      driver: Box::new(e5fs),
+      flags: MountFlags::default(),
+      source: String::new(),
+    });
+
+    // Superblock can't be written to device that is gone
+    let contents = std::mem::take(&mut *buffer.write().unwrap());
+    assert!(matches!(kernel.umount("/mnt"), Err(Errno::EIO(_))));
+    assert!(kernel.vfs().mount_points.contains_key("/mnt"));
+
+    *buffer.write().unwrap() = contents;
+    kernel.umount("/mnt").unwrap();
+    assert!(!kernel.vfs().mount_points.contains_key("/mnt"));
+  }
+
+  #[test]
+  fn read_and_write_move_offset() {
+    use crate::eunix::fs::{OpenFlags, OpenMode};
+
+    let mut kernel = kernel_with_e5fs_root();
+
+    let file_descriptor = kernel.open("/file", OpenFlags::new(OpenMode::ReadWrite, true, false)).unwrap();
+    kernel.write(file_descriptor, b"hello".to_vec()).unwrap();
+    kernel.write(file_descriptor, b" world".to_vec()).unwrap();
+    kernel.close(file_descriptor).unwrap();
+
+    let file_descriptor = kernel.open("/file", OpenFlags::new(OpenMode::Read, false, false)).unwrap();
+    assert_eq!(kernel.read(file_descriptor, 6).unwrap(), b"hello ");
+    assert_eq!(kernel.read(file_descriptor, 100).unwrap(), b"world");
+    assert_eq!(kernel.read(file_descriptor, 100).unwrap(), b"");
+    assert!(matches!(kernel.write(file_descriptor, b"x".to_vec()), Err(Errno::EBADFD(_))));
+    kernel.close(file_descriptor).unwrap();
+
+    let file_descriptor = kernel.open("/file", OpenFlags::new(OpenMode::Write, false, false).with_truncate(true)).unwrap();
+    kernel.write(file_descriptor, b"bye".to_vec()).unwrap();
+    assert_eq!(kernel.vfs().read_file("/file", EVERYTHING).unwrap(), b"bye");
 
+    kernel.vfs().create_dir("/dir").unwrap();
+    for name in ["a", "b", "c"] {
+      kernel.vfs().create_file(&format!("/dir/{name}")).unwrap();
+    }
+    let file_descriptor = kernel.open("/dir", OpenFlags::new(OpenMode::Read, false, false)).unwrap();
+    let names = |entries: Vec<VDirectoryEntry>| entries.into_iter().map(|entry| entry.name).collect::<Vec<_>>();
+    assert_eq!(names(kernel.getdents(file_descriptor, 2).unwrap()), [".", ".."]);
+    assert_eq!(names(kernel.getdents(file_descriptor, 2).unwrap()), ["a", "b"]);
+    assert_eq!(names(kernel.getdents(file_descriptor, 2).unwrap()), ["c"]);
+    assert!(kernel.getdents(file_descriptor, 2).unwrap().is_empty());
+  }
+
+  #[test]
+  fn lseek_moves_offset() {
+    use crate::eunix::fs::{OpenFlags, OpenMode};
+
+    let mut kernel = kernel_with_e5fs_root();
+    kernel.mount("", "/dev", FilesystemType::devfs).unwrap();
+
+    let first = kernel.open("/etc/passwd", OpenFlags::new(OpenMode::Read, false, false)).unwrap();
+    let file_descriptor = kernel.open("/file", OpenFlags::new(OpenMode::ReadWrite, true, false)).unwrap();
+    kernel.write(file_descriptor, b"hello world".to_vec()).unwrap();
+
+    assert_eq!(kernel.lseek(file_descriptor, io::SeekFrom::Start(6)).unwrap(), 6);
+    assert_eq!(kernel.read(file_descriptor, 100).unwrap(), b"world");
+    assert_eq!(kernel.lseek(file_descriptor, io::SeekFrom::End(-5)).unwrap(), 6);
+    kernel.write(file_descriptor, b"there".to_vec()).unwrap();
+    assert_eq!(kernel.lseek(file_descriptor, io::SeekFrom::Current(-11)).unwrap(), 0);
+    assert_eq!(kernel.read(file_descriptor, 100).unwrap(), b"hello there");
+    assert!(matches!(kernel.lseek(file_descriptor, io::SeekFrom::Current(-12)), Err(Errno::EINVAL(_))));
+    assert_eq!(kernel.stat(file_descriptor).unwrap().size, 11);
+    let mode = kernel.stat(file_descriptor).unwrap().mode;
+    kernel.chmod(file_descriptor, mode.with_others(0o7)).unwrap();
+    assert_eq!(kernel.vfs().stat("/file").unwrap().mode.others(), 0o7);
+
+    // Descriptors that are closed are taken again, lowest first
+    kernel.close(first).unwrap();
+    let random = kernel.open("/dev/random", OpenFlags::new(OpenMode::Read, false, false)).unwrap();
+    assert_eq!(random, first);
+    assert!(matches!(kernel.lseek(random, io::SeekFrom::Start(0)), Err(Errno::ESPIPE(_))));
+  }
+
+  #[test]
+  fn dup_copies_descriptors() {
+    use crate::eunix::fs::{OpenFlags, OpenMode};
+
+    let mut kernel = kernel_with_e5fs_root();
+
+    let log = kernel.open("/log", OpenFlags::new(OpenMode::Write, true, true)).unwrap();
+    let secret = kernel.open("/secret", OpenFlags::new(OpenMode::ReadWrite, true, false).with_close_on_exec(true)).unwrap();
+    kernel.write(secret, b"hello".to_vec()).unwrap();
+
+    // Copy starts where the original is and is inherited
+    let copy = kernel.dup(secret).unwrap();
+    assert_eq!(copy, 2);
+    assert_eq!(kernel.lseek(copy, io::SeekFrom::Current(0)).unwrap(), 5);
+    let pid = kernel.current_process_id();
+    let processes = kernel.processes();
+    assert!(processes[&pid].file_descriptors[&secret].close_on_exec);
+    assert!(!processes[&pid].file_descriptors[&copy].close_on_exec);
+    drop(processes);
+
+    // Whatever is open at the new descriptor is replaced
+    assert_eq!(kernel.dup2(log, copy).unwrap(), copy);
+    kernel.write(copy, b"one".to_vec()).unwrap();
+    kernel.write(log, b"two".to_vec()).unwrap();
+    assert_eq!(kernel.vfs().read_file("/log", EVERYTHING).unwrap(), b"onetwo");
+    assert_eq!(kernel.dup2(log, log).unwrap(), log);
+    assert!(matches!(kernel.dup(7), Err(Errno::EBADFD(_))));
+    assert!(matches!(kernel.dup2(7, log), Err(Errno::EBADFD(_))));
+
+    // Spawned process gets all but those closed on exec
+    let parent = kernel.current_process_id();
+    let child = kernel.spawn_process("/bin/sh").unwrap();
+    assert_eq!(child.ppid, parent);
+    assert_eq!(child.file_descriptors.keys().copied().collect::<Vec<_>>(), vec![log, copy]);
+  }
+
+  #[test]
+  fn dup_shares_offset() {
+    use crate::eunix::fs::{OpenFlags, OpenMode};
+
+    let mut kernel = kernel_with_e5fs_root();
+
+    // Like `cmd > out 2>&1`
+    let out = kernel.open("/out", OpenFlags::new(OpenMode::Write, true, false)).unwrap();
+    kernel.dup2(out, STDOUT_FILENO).unwrap();
+    kernel.dup2(STDOUT_FILENO, STDERR_FILENO).unwrap();
+    kernel.write(STDOUT_FILENO, b"one ".to_vec()).unwrap();
+    kernel.write(STDERR_FILENO, b"two ".to_vec()).unwrap();
+    kernel.write(STDOUT_FILENO, b"three".to_vec()).unwrap();
+    assert_eq!(kernel.vfs().read_file("/out", EVERYTHING).unwrap(), b"one two three");
+    assert_eq!(kernel.lseek(out, io::SeekFrom::Current(0)).unwrap(), 13);
+
+    // Child writes where parent stopped
+    kernel.fork().unwrap();
+    kernel.write(STDERR_FILENO, b"!".to_vec()).unwrap();
+    kernel.exit(0).unwrap();
+    kernel.write(out, b"?".to_vec()).unwrap();
+    assert_eq!(kernel.vfs().read_file("/out", EVERYTHING).unwrap(), b"one two three!?");
+  }
+
+  #[test]
+  fn relative_pathnames_start_from_cwd() {
+    use crate::eunix::fs::{OpenFlags, OpenMode};
+
+    let mut kernel = kernel_with_binfs_root();
+    kernel.vfs().create_dir("/home").unwrap();
+    kernel.vfs().create_dir("/home/user").unwrap();
+
+    assert_eq!(kernel.getcwd().unwrap(), "/");
+    kernel.chdir("home/user").unwrap();
+    assert_eq!(kernel.getcwd().unwrap(), "/home/user");
+    kernel.vfs().create_file("foo.txt").unwrap();
+    kernel.vfs().write_file("./foo.txt", b"foo").unwrap();
+    assert_eq!(kernel.vfs().read_file("/home/user/foo.txt", EVERYTHING).unwrap(), b"foo");
+
+    let file_descriptor = kernel.open("foo.txt", OpenFlags::new(OpenMode::Read, false, false)).unwrap();
+    kernel.chdir("..").unwrap();
+    assert_eq!(kernel.getcwd().unwrap(), "/home");
+    assert_eq!(kernel.read(file_descriptor, EVERYTHING).unwrap(), b"foo");
+    assert_eq!(kernel.vfs().read_file("../home/user/foo.txt", EVERYTHING).unwrap(), b"foo");
+
+    assert!(matches!(kernel.chdir("user/foo.txt"), Err(Errno::ENOTDIR(_))));
+    assert!(matches!(kernel.chdir("nope"), Err(Errno::ENOENT(_))));
+    assert_eq!(kernel.getcwd().unwrap(), "/home");
+  }
+
+  #[test]
+  fn bind_mount_shows_directory_elsewhere() {
+
+    let mut kernel = kernel_with_binfs_root();
+    kernel.vfs().create_dir("/srv").unwrap();
+    kernel.vfs().create_dir("/srv/www").unwrap();
+    kernel.vfs().create_file("/srv/www/index.html").unwrap();
+    kernel.vfs().write_file("/srv/www/index.html", b"hi").unwrap();
+    kernel.vfs().create_dir("/mnt").unwrap();
+
+    assert!(matches!(kernel.bind_mount("/srv/www/index.html", "/mnt"), Err(Errno::ENOTDIR(_))));
+    kernel.bind_mount("/srv/www", "/mnt").unwrap();
+    assert!(matches!(kernel.bind_mount("/etc", "/mnt"), Err(Errno::EINVAL(_))));
+    assert_eq!(kernel.vfs().match_mount_point("/mnt/index.html").unwrap(), (String::from("/"), String::from("/srv/www/index.html")));
+    assert_eq!(kernel.vfs().read_file("/mnt/index.html", EVERYTHING).unwrap(), b"hi");
+    kernel.vfs().create_file("/mnt/new").unwrap();
+    assert!(kernel.vfs().lookup_path("/srv/www/new").is_ok());
+    let number = |kernel: &mut Kernel, pathname| kernel.vfs().lookup_path(pathname).unwrap().number;
+    assert_eq!(number(&mut kernel, "/mnt"), number(&mut kernel, "/srv/www"));
+    assert_eq!(number(&mut kernel, "/mnt/.."), number(&mut kernel, "/"));
+
+    // Filesystem that is shown elsewhere stays
+    assert!(matches!(kernel.umount("/"), Err(Errno::EBUSY(_))));
+    kernel.umount("/mnt").unwrap();
+    assert!(matches!(kernel.vfs().lookup_path("/mnt/index.html"), Err(Errno::ENOENT(_))));
+  }
+
+  #[test]
+  fn ram_disks_take_memory() {
+    let mut kernel = kernel();
+    kernel.mount("", "/", FilesystemType::binfs).unwrap();
+    kernel.vfs().create_dir("/dev").unwrap();
+    kernel.mount("", "/dev", FilesystemType::devfs).unwrap();
+    kernel.resources.memory = 8192;
+    let memory = kernel.resources.memory;
+
+    assert_eq!(kernel.create_ram_disk(memory / 2).unwrap(), "/dev/ram0");
+    assert_eq!(kernel.memory_used(), memory / 2);
+    assert!(matches!(kernel.create_ram_disk(memory / 2 + 1), Err(Errno::ENOMEM(_))));
+    assert_eq!(kernel.create_ram_disk(memory / 2).unwrap(), "/dev/ram1");
+    assert!(matches!(kernel.create_ram_disk(1), Err(Errno::ENOMEM(_))));
+  }
+
+  #[test]
+  fn tty_is_read_and_written_through_devfs() {
+    use crate::eunix::console::BufferConsole;
+    use crate::eunix::devices::{HostTTY, TTY_MAJOR};
+
+    let mut kernel = kernel();
+    kernel.mount("", "/", FilesystemType::binfs).unwrap();
+    kernel.vfs().create_dir("/dev").unwrap();
+    kernel.mount("", "/dev", FilesystemType::devfs).unwrap();
+    let console = Arc::new(RwLock::new(BufferConsole::default()));
+    let tty = HostTTY::new("/dev/stdin", console.clone());
+    kernel.devfs().unwrap().1.register_device("tty1", DeviceNumber::new(TTY_MAJOR, 1), DeviceDriver::Char(Arc::new(RwLock::new(tty)))).unwrap();
+    console.write().unwrap().type_input(b"ls /\nq");
+
+    let file_descriptor = kernel.open("/dev/tty1", OpenFlags::new(OpenMode::ReadWrite, false, false)).unwrap();
+    kernel.write(file_descriptor, b"# ".to_vec()).unwrap();
+    assert_eq!(kernel.read(file_descriptor, EVERYTHING).unwrap(), b"ls /\n");
+    // Mode is switched through controlling terminal
+    kernel.set_controlling_tty("/dev/tty1").unwrap();
+    kernel.set_tty_mode("/dev/tty", TTYMode::Raw).unwrap();
+    assert_eq!(kernel.read(file_descriptor, EVERYTHING).unwrap(), b"q");
+    assert_eq!(kernel.read(file_descriptor, EVERYTHING).unwrap(), b"");
+    assert_eq!(console.write().unwrap().take_output(), b"# ls /\n");
+
+    assert!(matches!(kernel.set_tty_mode("/dev/random", TTYMode::Raw), Err(Errno::ENOTTY(_))));
+    assert!(matches!(kernel.set_tty_mode("/file", TTYMode::Raw), Err(Errno::ENOTTY(_))));
+  }
+
+  #[test]
+  fn blocked_tty_read_does_not_stall_other_threads() {
+    use std::sync::mpsc;
+    use crate::eunix::devices::TTY_MAJOR;
+
+    /// Terminal that blocks on read until something is typed
+    #[derive(Debug)]
+    struct BlockingTTY {
+      reading: mpsc::Sender<()>,
+      input: Mutex<mpsc::Receiver<Vec<u8>>>,
+    }
+    impl CharDevice for BlockingTTY {
+      fn read(&mut self, _count: AddressSize) -> Result<Vec<u8>, Errno> {
+        self.reading.send(()).unwrap();
+        Ok(self.input.lock().unwrap().recv().unwrap())
+      }
+      fn write(&mut self, _data: &[u8]) -> Result<(), Errno> {
+        Ok(())
+      }
+      fn is_tty(&self) -> bool {
+        true
+      }
+    }
+
+    let mut kernel = kernel();
+    kernel.mount("", "/", FilesystemType::binfs).unwrap();
+    kernel.vfs().create_dir("/dev").unwrap();
+    kernel.mount("", "/dev", FilesystemType::devfs).unwrap();
+    let (reading, reading_receiver) = mpsc::channel();
+    let (input, input_receiver) = mpsc::channel();
+    let tty = BlockingTTY { reading, input: Mutex::new(input_receiver) };
+    kernel.devfs().unwrap().1.register_device("tty1", DeviceNumber::new(TTY_MAJOR, 1), DeviceDriver::Char(Arc::new(RwLock::new(tty)))).unwrap();
+
+    // Like getty waiting for login
+    let mut getty = kernel.fork_thread().unwrap();
+    let getty = std::thread::spawn(move || {
+      let file_descriptor = getty.open("/dev/tty1", OpenFlags::new(OpenMode::Read, false, false)).unwrap();
+      getty.read(file_descriptor, EVERYTHING)
+    });
+    reading_receiver.recv().unwrap();
+
+    // Everything but the terminal is there while it waits
+    kernel.vfs().create_dir("/home").unwrap();
+    assert!(kernel.vfs().lookup_path("/home").is_ok());
+    assert_eq!(kernel.processes().len(), 2);
+    assert!(kernel.devices().ram_disks.is_empty());
+
+    input.send(b"root\n".to_vec()).unwrap();
+    assert_eq!(getty.join().unwrap().unwrap(), b"root\n");
+  }
+
+  #[test]
+  fn mount_table_has_sources_and_bind_mounts() {
+
+    let mut kernel = kernel_with_binfs_root();
+    kernel.vfs().create_dir("/srv").unwrap();
+    kernel.vfs().create_dir("/mnt").unwrap();
+    kernel.vfs().create_dir("/proc").unwrap();
+    let options = MountOptions { flags: MountFlags { read_only: true, noexec: true, ..MountFlags::default() }, ..MountOptions::default() };
+    kernel.mount_with_options("tools", "/proc", FilesystemType::procfs, &options).unwrap();
+    kernel.bind_mount("/srv", "/mnt").unwrap();
+
+    // Bind mount shows source and options of root filesystem
+    let entry = |source: &str, target: &str, r#type: &str, flags| MountEntry {
+      source: source.to_owned(),
+      target: target.to_owned(),
+      r#type: r#type.to_owned(),
+      flags,
+    };
+    assert_eq!(kernel.mount_table(), vec![
+      entry("binfs", "/", "binfs", MountFlags::default()),
+      entry("binfs", "/mnt", "binfs", MountFlags::default()),
+      entry("tools", "/proc", "procfs", options.flags),
+    ]);
+
+    kernel.umount("/mnt").unwrap();
+    assert_eq!(kernel.mount_table().iter().map(|entry| entry.target.as_str()).collect::<Vec<_>>(), ["/", "/proc"]);
+  }
+
+  #[test]
+  fn mounted_disks_are_shown_in_sysfs() {
+    use crate::util::{mktemp, mkenxvd};
+
+    let disk = mktemp();
+    mkenxvd(String::from("1M"), disk.clone());
+    eunix::e5fs::E5FSFilesystem::mkfs(&disk, 0.05, 4096).unwrap();
+    let disk = MachineDevice { realpath: disk, r#type: VirtualDeviceType::BlockDevice, read_only: false, backend: None };
+    let mut kernel = kernel_with_devices(vec![disk], MachineClock::default());
+    kernel.mount("", "/", FilesystemType::binfs).unwrap();
+    kernel.mount("", "/dev", FilesystemType::devfs).unwrap();
+    kernel.mount("", "/sys", FilesystemType::sysfs).unwrap();
+
+    kernel.mount("/dev/sda", "/mnt", FilesystemType::e5fs).unwrap();
+    assert_eq!(kernel.devices().devices[0].1.as_deref(), Some("/mnt"));
+    assert_eq!(kernel.device_mount_point("/dev/sda").as_deref(), Some("/mnt"));
+    assert_eq!(kernel.read_file("/sys/class/block/sda/mounted", EVERYTHING).unwrap(), b"/mnt\n");
+
+    // Made after mount, sysfs knows it too
+    kernel.mount("", "/sys2", FilesystemType::sysfs).unwrap();
+    assert_eq!(kernel.read_file("/sys2/class/block/sda/mounted", EVERYTHING).unwrap(), b"/mnt\n");
+
+    kernel.umount("/mnt").unwrap();
+    assert_eq!(kernel.devices().devices[0].1, None);
+    assert_eq!(kernel.device_mount_point("/dev/sda"), None);
+    assert_eq!(kernel.read_file("/sys/class/block/sda/mounted", EVERYTHING).unwrap(), b"\n");
+  }
+
+  #[test]
+  fn access_checks_real_ids() {
+
+    let mut kernel = kernel_with_binfs_root();
+    kernel.vfs().create_file("/file").unwrap();
+    kernel.vfs().change_mode("/file", FileMode::zero().with_user(0o6).with_group(0o4).with_others(0o4)).unwrap();
+
+    assert!(kernel.access("/file", 0).is_ok());
+    assert!(matches!(kernel.access("/nope", 0), Err(Errno::ENOENT(_))));
+    assert!(kernel.access("/file", PERM_R | PERM_W).is_ok());
+    // Root executes only what somebody may execute
+    assert!(matches!(kernel.access("/file", PERM_X), Err(Errno::EACCES(_))));
+
+    // Setuid root binary of user is still checked as user
+    kernel.current_uid = 1000;
+    assert!(kernel.access("/file", PERM_R).is_ok());
+    assert!(matches!(kernel.access("/file", PERM_W), Err(Errno::EACCES(_))));
+    assert_eq!(kernel.vfs().current_uid, ROOT_UID);
+    kernel.current_uid = ROOT_UID;
+
+    kernel.vfs().mount_points.get_mut("/").unwrap().flags.read_only = true;
+    assert!(matches!(kernel.access("/file", PERM_W), Err(Errno::EROFS(_))));
+    kernel.vfs().mount_points.get_mut("/").unwrap().flags.noexec = true;
+    kernel.vfs().mount_points.get_mut("/").unwrap().flags.read_only = false;
+    kernel.vfs().change_mode("/file", FileMode::zero().with_user(0o7)).unwrap();
+    assert!(matches!(kernel.access("/file", PERM_X), Err(Errno::EACCES(_))));
+  }
+
+  #[test]
+  fn mount_flags_are_honored() {
+
+    let mut kernel = kernel_with_binfs_root();
+    kernel.mount("", "/bin", FilesystemType::binfs).unwrap();
+    fn id(_: Args, kernel: &mut Kernel) -> AddressSize {
+      kernel.vfs().current_uid as AddressSize
+    }
+    kernel.register_binary("/bin/id", id).unwrap();
+    let mode = kernel.vfs().stat("/bin/id").unwrap().mode;
+    kernel.vfs().change_mode("/bin/id", mode.with_setuid(true).with_others(0o5)).unwrap();
+
+    // Setuid root binary runs as root
+    kernel.set_vfs_ids(1000, 100);
+    assert_eq!(kernel.exec("/bin/id", &["id"]).unwrap(), 0);
+    assert_eq!(kernel.vfs().current_uid, 1000);
+    kernel.vfs().mount_points.get_mut("/bin").unwrap().flags.nosuid = true;
+    assert_eq!(kernel.exec("/bin/id", &["id"]).unwrap(), 1000);
+    kernel.vfs().mount_points.get_mut("/bin").unwrap().flags.noexec = true;
+    assert!(matches!(kernel.exec("/bin/id", &["id"]), Err(Errno::EACCES(_))));
+    kernel.set_vfs_ids(ROOT_UID, ROOT_GID);
+
+    kernel.vfs().create_file("/file").unwrap();
+    let times = |atime| Times { atime, mtime: 10, ctime: 10, btime: 10 };
+    kernel.vfs().change_times("/file", times(5)).unwrap();
+    kernel.read_file("/file", EVERYTHING).unwrap();
+    let atime = kernel.vfs().stat("/file").unwrap().atime;
+    assert!(atime > 10);
+    // Recent enough access time is not set again
+    kernel.vfs().change_times("/file", times(atime - 1)).unwrap();
+    kernel.read_file("/file", EVERYTHING).unwrap();
+    assert_eq!(kernel.vfs().stat("/file").unwrap().atime, atime - 1);
+    kernel.vfs().change_times("/file", times(5)).unwrap();
+    kernel.vfs().mount_points.get_mut("/").unwrap().flags.noatime = true;
+    kernel.read_file("/file", EVERYTHING).unwrap();
+    assert_eq!(kernel.vfs().stat("/file").unwrap().atime, 5);
+  }
+
+  #[test]
+  fn fork_exec_wait_reaps_child() {
+    use crate::eunix::fs::{OpenFlags, OpenMode};
+
+    let mut kernel = kernel();
+    kernel.mount("", "/", FilesystemType::binfs).unwrap();
+    kernel.vfs().create_dir("/bin").unwrap();
+    fn getpid(_: Args, kernel: &mut Kernel) -> AddressSize {
+      let process = &kernel.processes()[&kernel.current_process_id()];
+      assert_eq!(process.binary, "/bin/getpid");
+      process.pid
+    }
+    kernel.register_binary("/bin/getpid", getpid).unwrap();
+    kernel.vfs().create_file("/log").unwrap();
+    let log = kernel.open("/log", OpenFlags::new(OpenMode::Write, false, false).with_close_on_exec(true)).unwrap();
+    let init = kernel.current_process_id();
+
+    let child = kernel.fork().unwrap();
+    assert_ne!(child, init);
+    assert_eq!(kernel.current_process_id(), child);
+    assert_eq!(kernel.processes()[&child].ppid, init);
+    // Descriptors closed on exec are still there after fork
+    assert!(kernel.processes()[&child].file_descriptors.contains_key(&log));
+    // Nothing to reap while child runs
+    kernel.current_process_id = init;
+    assert!(matches!(kernel.waitpid(child), Err(Errno::ECHILD(_))));
+    kernel.current_process_id = child;
+
+    kernel.execve("/bin/getpid", &["getpid"]).unwrap();
+    assert_eq!(kernel.current_process_id(), init);
+    assert!(kernel.processes()[&child].file_descriptors.is_empty());
+    assert_eq!(kernel.waitpid(child).unwrap(), child);
+    assert!(!kernel.processes().contains_key(&child));
+    assert!(matches!(kernel.waitpid(child), Err(Errno::ECHILD(_))));
+
+    // Every run gets its own pid, and failed exec is reaped too
+    let first = kernel.run("/bin/getpid", &["getpid"]).unwrap();
+    assert_ne!(kernel.run("/bin/getpid", &["getpid"]).unwrap(), first);
+    assert!(matches!(kernel.run("/bin/nope", &["nope"]), Err(Errno::ENOENT(_))));
+    assert_eq!(kernel.current_process_id(), init);
+    assert_eq!(kernel.processes().keys().copied().collect::<Vec<_>>(), vec![init]);
+    // Init has no parent to exit to
+    assert!(matches!(kernel.exit(0), Err(Errno::EPERM(_))));
+  }
+
+  #[test]
+  fn signals_terminate_processes() {
+    use crate::eunix::signal::{SIGTERM, exit_code};
+
+    let mut kernel = kernel();
+    kernel.mount("", "/", FilesystemType::binfs).unwrap();
+    kernel.vfs().create_dir("/bin").unwrap();
+    // Sees that it is told to stop, like server does in its loop
+    fn serve(_: Args, kernel: &mut Kernel) -> AddressSize {
+      let pid = kernel.current_process_id();
+      kernel.kill(pid, SIGTERM).unwrap();
+      assert!(kernel.termination_pending());
+      0
+    }
+    fn ignore(_: Args, kernel: &mut Kernel) -> AddressSize {
+      let pid = kernel.current_process_id();
+      kernel.kill(pid, SIGCHLD).unwrap();
+      assert!(!kernel.termination_pending());
+      0
+    }
+    kernel.register_binary("/bin/serve", serve).unwrap();
+    kernel.register_binary("/bin/ignore", ignore).unwrap();
+    let init = kernel.current_process_id();
+
+    assert_eq!(kernel.run("/bin/serve", &["serve"]).unwrap(), exit_code(SIGTERM));
+    assert_eq!(kernel.run("/bin/ignore", &["ignore"]).unwrap(), 0);
+    // Parent is told about every child
+    assert!(kernel.processes()[&init].pending_signals.contains(&SIGCHLD));
+
+    // Init is not terminated, and SIGKILL goes before others
+    kernel.kill(init, SIGTERM).unwrap();
+    assert_eq!(kernel.deliver_signals(), None);
+    kernel.current_uid = 1000;
+    kernel.set_vfs_ids(1000, 1000);
+    let child = kernel.fork().unwrap();
+    kernel.kill(child, SIGINT).unwrap();
+    kernel.kill(child, SIGKILL).unwrap();
+    assert_eq!(kernel.deliver_signals(), Some(SIGKILL));
+    assert_eq!(kernel.deliver_signals(), None);
+
+    // Only root signals processes of others
+    assert!(matches!(kernel.kill(init, SIGTERM), Err(Errno::EPERM(_))));
+    assert!(kernel.kill(child, 0).is_ok());
+    assert!(matches!(kernel.kill(child, 3), Err(Errno::EINVAL(_))));
+    kernel.exit(0).unwrap();
+    assert!(matches!(kernel.kill(child, 0), Err(Errno::ESRCH(_))));
+  }
 }
 
 // vim:ts=2 sw=2
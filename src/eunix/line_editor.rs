@@ -0,0 +1,462 @@
+/// Key typed into terminal, decoded from bytes of raw mode
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Key {
+  Char(char),
+  Enter,
+  Tab,
+  Backspace,
+  Delete,
+  Left,
+  Right,
+  Up,
+  Down,
+  Home,
+  End,
+  /// Ctrl-C: drop the line
+  Interrupt,
+  /// Ctrl-D: end of input on empty line, `Delete` otherwise
+  EndOfInput,
+  /// Ctrl-W: delete word before cursor
+  DeleteWord,
+  /// Ctrl-U: delete everything before cursor
+  DeleteToStart,
+  /// Ctrl-K: delete everything from cursor
+  DeleteToEnd,
+  /// Ctrl-R: search history backwards as query is typed
+  Search,
+  /// Ctrl-G: leave search, back to line as it was
+  Abort,
+  /// Escape sequence or control character that means nothing here
+  Unknown,
+}
+
+/// Turns bytes typed into raw terminal into keys: escape sequences
+/// of arrows and UTF-8 come in several bytes
+#[derive(Debug, Default)]
+pub struct KeyDecoder {
+  pending: Vec<u8>,
+}
+
+impl KeyDecoder {
+  /// Returns: key, once all of its bytes are there
+  pub fn push(&mut self, byte: u8) -> Option<Key> {
+    self.pending.push(byte);
+
+    let key = match self.pending[..] {
+      [0x1b] | [0x1b, b'[' | b'O'] => return None,
+      [0x1b, b'[' | b'O', .., last] if !(0x40..=0x7e).contains(&last) => return None,
+      [0x1b, b'[' | b'O', ref sequence @ ..] => match sequence {
+        b"A" => Key::Up,
+        b"B" => Key::Down,
+        b"C" => Key::Right,
+        b"D" => Key::Left,
+        b"H" | b"1~" | b"7~" => Key::Home,
+        b"F" | b"4~" | b"8~" => Key::End,
+        b"3~" => Key::Delete,
+        _ => Key::Unknown,
+      },
+      [0x1b, ..] => Key::Unknown,
+      [byte] if byte < 0x80 => Self::ascii_key(byte),
+      [first, ..] => {
+        let length = match first {
+          0xc0..=0xdf => 2,
+          0xe0..=0xef => 3,
+          0xf0..=0xf7 => 4,
+          _ => 1,
+        };
+        if self.pending.len() < length {
+          return None;
+        }
+        std::str::from_utf8(&self.pending)
+          .ok()
+          .and_then(|string| string.chars().next())
+          .map_or(Key::Unknown, Key::Char)
+      },
+      [] => unreachable!("byte was just pushed"),
+    };
+    self.pending.clear();
+
+    Some(key)
+  }
+
+  fn ascii_key(byte: u8) -> Key {
+    match byte {
+      b'\r' | b'\n' => Key::Enter,
+      b'\t' => Key::Tab,
+      0x08 | 0x7f => Key::Backspace,
+      0x01 => Key::Home,
+      0x02 => Key::Left,
+      0x03 => Key::Interrupt,
+      0x04 => Key::EndOfInput,
+      0x05 => Key::End,
+      0x06 => Key::Right,
+      0x07 => Key::Abort,
+      0x0b => Key::DeleteToEnd,
+      0x0e => Key::Down,
+      0x10 => Key::Up,
+      0x12 => Key::Search,
+      0x15 => Key::DeleteToStart,
+      0x17 => Key::DeleteWord,
+      byte if byte < 0x20 => Key::Unknown,
+      byte => Key::Char(byte as char),
+    }
+  }
+}
+
+/// What caller of `LineEditor::feed` should do next
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum Edit {
+  /// Line is not done yet, keep feeding
+  Pending,
+  /// Enter was pressed on this line, newline not included
+  Line(String),
+  /// Tab was pressed after this word, answer with `LineEditor::complete`
+  Complete(String),
+  /// Ctrl-C, line is dropped and prompt should be shown again
+  Interrupted,
+  /// Ctrl-D on empty line
+  EndOfInput,
+}
+
+/// Incremental search through history with Ctrl-R
+#[derive(Debug, Clone)]
+struct Search {
+  query: String,
+  /// Index in history of the line that matches query
+  matched: Option<usize>,
+  /// Line as it was before search, for Ctrl-G
+  original: Vec<char>,
+}
+
+/// Line editing for terminals in raw mode, like readline: cursor
+/// movement, Ctrl-W/Ctrl-U/Ctrl-K, history on Up/Down and search in
+/// it with Ctrl-R. Knows nothing about terminals: it is fed bytes
+/// and says what to write back, so that any console can drive it
+#[derive(Debug, Default)]
+pub struct LineEditor {
+  prompt: String,
+  line: Vec<char>,
+  /// Position in `line`, in chars
+  cursor: usize,
+  history: Vec<String>,
+  /// Index in history shown with Up and Down, and the line that
+  /// was being typed before going there
+  browsing: Option<(usize, Vec<char>)>,
+  search: Option<Search>,
+  decoder: KeyDecoder,
+}
+
+impl LineEditor {
+  /// Start a new line after `prompt`, which is already written
+  pub fn start(&mut self, prompt: &str) {
+    self.prompt = prompt.to_owned();
+    self.line.clear();
+    self.cursor = 0;
+    self.browsing = None;
+    self.search = None;
+  }
+
+  /// Line typed so far
+  pub fn line(&self) -> String {
+    self.line.iter().collect()
+  }
+
+  /// Line typed so far up to cursor
+  pub fn line_before_cursor(&self) -> String {
+    self.line[..self.cursor].iter().collect()
+  }
+
+  /// Lines entered, the oldest first
+  pub fn history(&self) -> &[String] {
+    &self.history
+  }
+
+  /// Remember `line` for Up and Ctrl-R, unless it's blank or the
+  /// same as the last one
+  pub fn add_history(&mut self, line: &str) {
+    if !line.trim().is_empty() && self.history.last().map(String::as_str) != Some(line) {
+      self.history.push(line.to_owned());
+    }
+  }
+
+  /// Feed one byte typed into the terminal.
+  /// Returns: what to do next and bytes to write to the terminal
+  pub fn feed(&mut self, byte: u8) -> (Edit, Vec<u8>) {
+    match self.decoder.push(byte) {
+      Some(key) => self.handle(key),
+      None => (Edit::Pending, Vec::new()),
+    }
+  }
+
+  pub fn handle(&mut self, key: Key) -> (Edit, Vec<u8>) {
+    if self.search.is_some() {
+      match self.handle_search(key) {
+        Some(result) => return result,
+        // Any other key takes the match and is handled as usual
+        None => self.leave_search(),
+      }
+    }
+
+    match key {
+      Key::Char(char) => {
+        self.line.insert(self.cursor, char);
+        self.cursor += 1;
+        // Typing at the end is most of typing, no need to redraw for it
+        if self.cursor == self.line.len() {
+          return (Edit::Pending, char.to_string().into_bytes());
+        }
+      },
+      Key::Enter => {
+        let line = self.line();
+        self.add_history(&line);
+        self.start(&self.prompt.clone());
+        return (Edit::Line(line), b"\r\n".to_vec());
+      },
+      Key::Tab => return (Edit::Complete(self.word_before_cursor()), Vec::new()),
+      Key::Interrupt => {
+        self.start(&self.prompt.clone());
+        return (Edit::Interrupted, b"^C\r\n".to_vec());
+      },
+      Key::EndOfInput if self.line.is_empty() => return (Edit::EndOfInput, b"\r\n".to_vec()),
+      Key::EndOfInput | Key::Delete => {
+        if self.cursor < self.line.len() {
+          self.line.remove(self.cursor);
+        }
+      },
+      Key::Backspace => {
+        if self.cursor > 0 {
+          self.cursor -= 1;
+          self.line.remove(self.cursor);
+        }
+      },
+      Key::Left => self.cursor = self.cursor.saturating_sub(1),
+      Key::Right => self.cursor = (self.cursor + 1).min(self.line.len()),
+      Key::Home => self.cursor = 0,
+      Key::End => self.cursor = self.line.len(),
+      Key::DeleteWord => {
+        let end = self.cursor;
+        while self.cursor > 0 && self.line[self.cursor - 1] == ' ' {
+          self.cursor -= 1;
+        }
+        while self.cursor > 0 && self.line[self.cursor - 1] != ' ' {
+          self.cursor -= 1;
+        }
+        self.line.drain(self.cursor..end);
+      },
+      Key::DeleteToStart => {
+        self.line.drain(..self.cursor);
+        self.cursor = 0;
+      },
+      Key::DeleteToEnd => self.line.truncate(self.cursor),
+      Key::Up => self.browse(-1),
+      Key::Down => self.browse(1),
+      Key::Search => {
+        self.search = Some(Search { query: String::new(), matched: None, original: self.line.clone() });
+        return (Edit::Pending, self.render());
+      },
+      Key::Abort | Key::Unknown => return (Edit::Pending, Vec::new()),
+    }
+
+    (Edit::Pending, self.render())
+  }
+
+  /// Returns: nothing if `key` ends the search
+  fn handle_search(&mut self, key: Key) -> Option<(Edit, Vec<u8>)> {
+    let search = self.search.as_mut()?;
+    match key {
+      Key::Char(char) => {
+        search.query.push(char);
+        // Longer query can still match the same line
+        let from = search.matched.map_or(self.history.len(), |matched| matched + 1);
+        search.matched = Self::find(&self.history, &search.query, from);
+      },
+      Key::Backspace => {
+        search.query.pop();
+        search.matched = Self::find(&self.history, &search.query, self.history.len());
+      },
+      Key::Search => {
+        let from = search.matched.unwrap_or(self.history.len());
+        if let Some(matched) = Self::find(&self.history, &search.query, from) {
+          search.matched = Some(matched);
+        }
+      },
+      Key::Abort | Key::Interrupt => {
+        self.line = search.original.clone();
+        self.cursor = self.line.len();
+        self.search = None;
+      },
+      _ => return None,
+    }
+
+    Some((Edit::Pending, self.render()))
+  }
+
+  /// Index of the latest line in `history` before `before` with `query` in it
+  fn find(history: &[String], query: &str, before: usize) -> Option<usize> {
+    history[..before.min(history.len())]
+      .iter()
+      .rposition(|line| line.contains(query))
+  }
+
+  fn leave_search(&mut self) {
+    if let Some(Search { matched: Some(matched), .. }) = self.search.take() {
+      self.line = self.history[matched].chars().collect();
+      self.cursor = self.line.len();
+    }
+  }
+
+  /// Move through history by `step`, back to the line being typed
+  /// after the latest one
+  fn browse(&mut self, step: isize) {
+    let (index, typed) = self.browsing.take().unwrap_or((self.history.len(), self.line.clone()));
+    let index = (index as isize + step).clamp(0, self.history.len() as isize) as usize;
+
+    self.line = match self.history.get(index) {
+      Some(line) => line.chars().collect(),
+      None => typed.clone(),
+    };
+    self.cursor = self.line.len();
+    if index < self.history.len() {
+      self.browsing = Some((index, typed));
+    }
+  }
+
+  /// Word that cursor is at the end of, for completion
+  pub fn word_before_cursor(&self) -> String {
+    let start = self.line[..self.cursor]
+      .iter()
+      .rposition(|&char| char == ' ')
+      .map_or(0, |position| position + 1);
+
+    self.line[start..self.cursor].iter().collect()
+  }
+
+  /// Replace word before cursor with the only one of `candidates`
+  /// or with what all of them start with. Candidates are listed
+  /// if that changes nothing.
+  /// Returns: bytes to write to the terminal
+  pub fn complete(&mut self, candidates: &[String]) -> Vec<u8> {
+    let word = self.word_before_cursor();
+    let Some(first) = candidates.first() else {
+      return Vec::new();
+    };
+    let mut common = first.clone();
+    for candidate in &candidates[1..] {
+      let length = common
+        .chars()
+        .zip(candidate.chars())
+        .take_while(|(left, right)| left == right)
+        .map(|(char, _)| char.len_utf8())
+        .sum();
+      common.truncate(length);
+    }
+    // Directories end with '/' and are completed further from there
+    if candidates.len() == 1 && !common.ends_with('/') {
+      common.push(' ');
+    }
+
+    if common.chars().count() <= word.chars().count() {
+      let mut output = format!("\r\n{}\r\n", candidates.join("  ")).into_bytes();
+      output.extend(self.render());
+      return output;
+    }
+
+    let start = self.cursor - word.chars().count();
+    self.line.splice(start..self.cursor, common.chars());
+    self.cursor = start + common.chars().count();
+
+    self.render()
+  }
+
+  /// Bytes that redraw prompt and line and put cursor where it is
+  fn render(&self) -> Vec<u8> {
+    let (prompt, line, cursor) = match &self.search {
+      Some(Search { query, matched, .. }) => {
+        let line = matched.map_or(String::new(), |matched| self.history[matched].clone());
+        let cursor = line.chars().count();
+        (format!("(reverse-i-search)`{query}': "), line, cursor)
+      },
+      None => (self.prompt.clone(), self.line(), self.cursor),
+    };
+    let back = line.chars().count() - cursor;
+
+    let mut output = format!("\r{prompt}{line}\x1b[K");
+    if back > 0 {
+      output.push_str(&format!("\x1b[{back}D"));
+    }
+
+    output.into_bytes()
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  /// Feed `input` and return the last edit
+  fn type_bytes(editor: &mut LineEditor, input: &[u8]) -> Edit {
+    input
+      .iter()
+      .map(|&byte| editor.feed(byte).0)
+      .last()
+      .unwrap()
+  }
+
+  #[test]
+  fn escape_sequences_and_utf8_are_decoded() {
+    let mut decoder = KeyDecoder::default();
+    let keys = "\x1b[D\x1b[3~ж\x17"
+      .bytes()
+      .filter_map(|byte| decoder.push(byte))
+      .collect::<Vec<_>>();
+
+    assert_eq!(keys, vec![Key::Left, Key::Delete, Key::Char('ж'), Key::DeleteWord]);
+  }
+
+  #[test]
+  fn line_is_edited_with_cursor_and_kill_keys() {
+    let mut editor = LineEditor::default();
+    editor.start("# ");
+
+    // `ls /dev`, back to the start, `-l ` typed in between
+    type_bytes(&mut editor, b"ls /dev\x01\x1b[C\x1b[C -l");
+    assert_eq!(editor.line(), "ls -l /dev");
+    // Ctrl-E, Ctrl-W
+    type_bytes(&mut editor, b"\x05\x17");
+    assert_eq!(editor.line(), "ls -l ");
+    // Ctrl-U
+    type_bytes(&mut editor, b"\x15");
+    assert_eq!(editor.line(), "");
+    assert_eq!(type_bytes(&mut editor, b"pwd\r"), Edit::Line(String::from("pwd")));
+    assert_eq!(editor.history(), ["pwd"]);
+  }
+
+  #[test]
+  fn history_is_browsed_and_searched() {
+    let mut editor = LineEditor::default();
+    for line in ["cat /etc/passwd", "ls /", "cd /home"] {
+      editor.add_history(line);
+    }
+    editor.start("# ");
+
+    // Up twice, then Down
+    type_bytes(&mut editor, b"\x1b[A\x1b[A\x1b[B");
+    assert_eq!(editor.line(), "cd /home");
+    // Ctrl-R for "/e", then Ctrl-R again for the older match
+    type_bytes(&mut editor, b"\x15\x12/e");
+    type_bytes(&mut editor, b"\x12");
+    assert_eq!(type_bytes(&mut editor, b"\r"), Edit::Line(String::from("cat /etc/passwd")));
+  }
+
+  #[test]
+  fn word_is_completed() {
+    let mut editor = LineEditor::default();
+    editor.start("# ");
+
+    assert_eq!(type_bytes(&mut editor, b"cat /etc/pa\t"), Edit::Complete(String::from("/etc/pa")));
+    editor.complete(&[String::from("/etc/passwd")]);
+    assert_eq!(editor.line(), "cat /etc/passwd ");
+  }
+}
+
+// vim:ts=2 sw=2
@@ -0,0 +1,346 @@
+use std::collections::VecDeque;
+use std::fmt;
+use std::str::FromStr;
+use std::time::Duration;
+
+use super::fs::AddressSize;
+
+/// Socket number in socket table of a process, like file descriptor
+pub type SocketDescriptor = AddressSize;
+pub type Port = u16;
+
+/// How long `connect` waits for the other end to accept
+pub const CONNECT_TIMEOUT: Duration = Duration::from_secs(2);
+/// Every packet starts with it, frames without it are not for sockets
+pub const PACKET_MAGIC: u8 = b'n';
+/// Bytes of header before payload of a packet
+pub const PACKET_HEADER_SIZE: usize = 7;
+/// Ports given by `bind` to sockets that are used without one
+pub const EPHEMERAL_PORTS: std::ops::RangeInclusive<Port> = 49152..=65535;
+
+/// Flags of stream packets
+pub const FLAG_SYN: u8 = 0b0001;
+pub const FLAG_ACK: u8 = 0b0010;
+pub const FLAG_FIN: u8 = 0b0100;
+pub const FLAG_RST: u8 = 0b1000;
+/// Flags of echo packets, like ICMP types
+pub const ECHO_REPLY: u8 = 0;
+pub const ECHO_REQUEST: u8 = 8;
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SocketType {
+  /// Connection with ordered data, like TCP
+  Stream,
+  /// Separate messages, like UDP
+  Datagram,
+  /// Echo requests and replies, like ICMP one of `ping`.
+  /// Requests are answered by kernel of the other end
+  Echo,
+}
+
+impl SocketType {
+  fn protocol(self) -> u8 {
+    match self {
+      SocketType::Echo => 1,
+      SocketType::Stream => 6,
+      SocketType::Datagram => 17,
+    }
+  }
+
+  fn from_protocol(protocol: u8) -> Option<Self> {
+    match protocol {
+      1 => Some(SocketType::Echo),
+      6 => Some(SocketType::Stream),
+      17 => Some(SocketType::Datagram),
+      _ => None,
+    }
+  }
+}
+
+/// Network interface and port on it, like `eth0:7`.
+/// Interfaces are point-to-point links, so no host is needed
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SocketAddress {
+  /// Name of network interface in devfs
+  pub interface: String,
+  pub port: Port,
+}
+
+impl SocketAddress {
+  pub fn new(interface: &str, port: Port) -> Self {
+    Self {
+      interface: interface.to_owned(),
+      port,
+    }
+  }
+}
+
+impl FromStr for SocketAddress {
+  type Err = String;
+
+  fn from_str(s: &str) -> Result<Self, Self::Err> {
+    match s.rsplit_once(':') {
+      Some((interface, port)) if !interface.is_empty() => port
+        .parse()
+        .map(|port| SocketAddress::new(interface, port))
+        .map_err(|_| format!("invalid port '{port}'")),
+      _ => Err(format!("invalid address '{s}' (expected like eth0:7)")),
+    }
+  }
+}
+
+impl fmt::Display for SocketAddress {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    write!(f, "{}:{}", self.interface, self.port)
+  }
+}
+
+/// What goes in a frame of network interface:
+/// magic, protocol, source port, destination port, flags, payload.
+/// Ports are big endian
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Packet {
+  pub r#type: SocketType,
+  pub source_port: Port,
+  pub destination_port: Port,
+  pub flags: u8,
+  pub payload: Vec<u8>,
+}
+
+impl Packet {
+  pub fn encode(&self) -> Vec<u8> {
+    let mut frame = vec![PACKET_MAGIC, self.r#type.protocol()];
+    frame.extend(self.source_port.to_be_bytes());
+    frame.extend(self.destination_port.to_be_bytes());
+    frame.push(self.flags);
+    frame.extend(&self.payload);
+
+    frame
+  }
+
+  /// Returns: packet in `frame`, `None` if it is not a packet
+  pub fn decode(frame: &[u8]) -> Option<Self> {
+    if frame.len() < PACKET_HEADER_SIZE || frame[0] != PACKET_MAGIC {
+      return None;
+    }
+
+    Some(Self {
+      r#type: SocketType::from_protocol(frame[1])?,
+      source_port: Port::from_be_bytes([frame[2], frame[3]]),
+      destination_port: Port::from_be_bytes([frame[4], frame[5]]),
+      flags: frame[6],
+      payload: frame[PACKET_HEADER_SIZE..].to_vec(),
+    })
+  }
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum SocketState {
+  /// Just made or bound
+  Unconnected,
+  /// Stream one waiting for connections
+  Listening,
+  /// Stream one that sent SYN and waits for SYN|ACK
+  Connecting,
+  Connected,
+  /// Stream one which other end closed or refused connection
+  Closed,
+}
+
+#[derive(Debug, Clone)]
+pub struct Socket {
+  pub r#type: SocketType,
+  pub state: SocketState,
+  /// Address it is bound to
+  pub local: Option<SocketAddress>,
+  /// Address of the other end, for connected ones
+  pub peer: Option<SocketAddress>,
+  /// Received payloads with addresses they came from, not read yet
+  pub received: VecDeque<(SocketAddress, Vec<u8>)>,
+  /// Connections made to listening socket, not accepted yet.
+  /// Kernel makes them itself, like TCP does
+  pub backlog: VecDeque<Socket>,
+}
+
+impl Socket {
+  pub fn new(r#type: SocketType) -> Self {
+    Self {
+      r#type,
+      state: SocketState::Unconnected,
+      local: None,
+      peer: None,
+      received: VecDeque::new(),
+      backlog: VecDeque::new(),
+    }
+  }
+
+  /// Whether `packet` that came to `interface` is for this socket.
+  /// Stream packets go to connection with their peer,
+  /// new connections to listening socket
+  pub fn accepts(&self, interface: &str, packet: &Packet) -> bool {
+    let is_local = self.r#type == packet.r#type && self.local
+      .as_ref()
+      .map_or(false, |local| local.interface == interface && local.port == packet.destination_port);
+    let is_from_peer = self.peer
+      .as_ref()
+      .map_or(false, |peer| peer.port == packet.source_port);
+
+    match (self.r#type, self.state) {
+      (SocketType::Stream, SocketState::Listening) => is_local && (packet.flags == FLAG_SYN || self.backlog
+        .iter()
+        .any(|connection| connection.accepts(interface, packet))),
+      (SocketType::Stream, SocketState::Connecting | SocketState::Connected) => is_local && is_from_peer,
+      (SocketType::Stream, _) => false,
+      (SocketType::Datagram, _) => is_local,
+      (SocketType::Echo, _) => is_local && packet.flags == ECHO_REPLY,
+    }
+  }
+
+  /// Take in `packet` that came from `source`, which `accepts` it.
+  /// Returns: packet to answer with, if there is one
+  pub fn receive(&mut self, source: SocketAddress, packet: Packet) -> Option<Packet> {
+    match (self.r#type, self.state) {
+      (SocketType::Stream, SocketState::Listening) => {
+        let interface = source.interface.clone();
+        if let Some(connection) = self.backlog
+          .iter_mut()
+          .find(|connection| connection.accepts(&interface, &packet)) {
+          return connection.receive(source, packet);
+        }
+
+        let mut connection = Socket::new(SocketType::Stream);
+        connection.state = SocketState::Connected;
+        connection.local = self.local.clone();
+        connection.peer = Some(source);
+        let reply = connection.packet(FLAG_SYN | FLAG_ACK, Vec::new());
+        self.backlog.push_back(connection);
+        Some(reply)
+      },
+      (SocketType::Stream, _) => {
+        if packet.flags & FLAG_RST != 0 {
+          self.state = SocketState::Closed;
+          return None;
+        }
+        if self.state == SocketState::Connecting && packet.flags == FLAG_SYN | FLAG_ACK {
+          self.state = SocketState::Connected;
+          return Some(self.packet(FLAG_ACK, Vec::new()));
+        }
+        if !packet.payload.is_empty() {
+          self.received.push_back((source, packet.payload));
+        }
+        if packet.flags & FLAG_FIN != 0 {
+          self.state = SocketState::Closed;
+        }
+        None
+      },
+      (SocketType::Datagram | SocketType::Echo, _) => {
+        self.received.push_back((source, packet.payload));
+        None
+      },
+    }
+  }
+
+  /// Packet of this socket to its peer
+  pub fn packet(&self, flags: u8, payload: Vec<u8>) -> Packet {
+    Packet {
+      r#type: self.r#type,
+      source_port: self.local.as_ref().map_or(0, |local| local.port),
+      destination_port: self.peer.as_ref().map_or(0, |peer| peer.port),
+      flags,
+      payload,
+    }
+  }
+}
+
+/// Answer to packet that no socket took: kernel replies to echo
+/// requests, and resets stream connections to closed ports
+pub fn unclaimed_reply(packet: &Packet) -> Option<Packet> {
+  match (packet.r#type, packet.flags) {
+    (SocketType::Echo, ECHO_REQUEST) => Some(Packet {
+      r#type: SocketType::Echo,
+      source_port: packet.destination_port,
+      destination_port: packet.source_port,
+      flags: ECHO_REPLY,
+      payload: packet.payload.clone(),
+    }),
+    (SocketType::Stream, flags) if flags & FLAG_RST == 0 => Some(Packet {
+      r#type: SocketType::Stream,
+      source_port: packet.destination_port,
+      destination_port: packet.source_port,
+      flags: FLAG_RST,
+      payload: Vec::new(),
+    }),
+    _ => None,
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::machine::{MachineDevice, MachineClock, NetBackend, VirtualDeviceType};
+  use crate::eunix::{kernel::{Errno, Kernel}, fs::FilesystemType};
+  use crate::testing::kernel_with_devices;
+
+  /// Kernel with devfs on `/dev` and loopback interface `eth0`
+  fn kernel() -> Kernel {
+    let nic = MachineDevice { realpath: String::new(), r#type: VirtualDeviceType::NetDevice, read_only: false, backend: Some(NetBackend::Loopback) };
+    let mut kernel = kernel_with_devices(vec![nic], MachineClock::default());
+    kernel.mount("", "/dev", FilesystemType::devfs).unwrap();
+
+    kernel
+  }
+
+  #[test]
+  fn packet_is_encoded_and_decoded() {
+    let packet = Packet {
+      r#type: SocketType::Stream,
+      source_port: 49152,
+      destination_port: 7,
+      flags: FLAG_SYN | FLAG_ACK,
+      payload: b"hello".to_vec(),
+    };
+
+    assert_eq!(Packet::decode(&packet.encode()), Some(packet));
+    assert_eq!(Packet::decode(b"tag: 0\nbody: Done\n"), None);
+    assert_eq!("eth0:7".parse(), Ok(SocketAddress::new("eth0", 7)));
+    assert!("eth0".parse::<SocketAddress>().is_err());
+  }
+
+  #[test]
+  fn stream_connection_works_over_loopback() {
+    let mut kernel = kernel();
+    let listener = kernel.socket(SocketType::Stream).unwrap();
+    kernel.bind(listener, SocketAddress::new("eth0", 7)).unwrap();
+    kernel.listen(listener).unwrap();
+    let client = kernel.socket(SocketType::Stream).unwrap();
+
+    kernel.connect(client, SocketAddress::new("eth0", 7)).unwrap();
+    kernel.send(client, b"hello").unwrap();
+    let (server, peer) = kernel.accept(listener).unwrap().unwrap();
+    kernel.close_socket(client).unwrap();
+
+    assert_eq!(peer.port, *EPHEMERAL_PORTS.start());
+    assert_eq!(kernel.recv(server, 3).unwrap(), Some((peer.clone(), b"hel".to_vec())));
+    assert_eq!(kernel.recv(server, 3).unwrap(), Some((peer.clone(), b"lo".to_vec())));
+    // End of stream
+    assert_eq!(kernel.recv(server, 3).unwrap(), Some((peer, Vec::new())));
+    let refused = kernel.socket(SocketType::Stream).unwrap();
+    assert!(matches!(kernel.connect(refused, SocketAddress::new("eth0", 8)), Err(Errno::ECONNREFUSED(_))));
+    let taken = kernel.socket(SocketType::Stream).unwrap();
+    assert!(matches!(kernel.bind(taken, SocketAddress::new("eth0", 7)), Err(Errno::EADDRINUSE(_))));
+  }
+
+  #[test]
+  fn echo_requests_are_answered_by_kernel() {
+    let mut kernel = kernel();
+    let socket = kernel.socket(SocketType::Echo).unwrap();
+    kernel.connect(socket, SocketAddress::new("eth0", 0)).unwrap();
+
+    kernel.send(socket, b"ping").unwrap();
+
+    assert_eq!(kernel.recv(socket, 64).unwrap(), Some((SocketAddress::new("eth0", 0), b"ping".to_vec())));
+    assert_eq!(kernel.recv(socket, 64).unwrap(), None);
+  }
+}
+
+// vim:ts=2 sw=2
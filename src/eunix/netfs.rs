@@ -0,0 +1,557 @@
+use std::any::Any;
+use std::sync::{Arc, RwLock};
+use std::collections::BTreeMap;
+use std::time::{Duration, Instant};
+
+use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
+
+use super::devices::{CharDevice, MAX_FRAME_SIZE};
+use super::fs::{AddressSize, DeviceNumber, FileMode, FileModeType, FileStat, Filesystem, Id, VDirectory, VDirectoryEntry, VINode, MAX_SYMLINK_HOPS};
+use super::kernel::{Errno, Times, ROOT_GID, ROOT_UID};
+
+/// Most bytes of file that one message carries, so that
+/// messages fit in a frame of network interface
+pub const NETFS_MAX_DATA: AddressSize = 256;
+/// Most directory entries that one message carries
+pub const NETFS_MAX_ENTRIES: usize = 16;
+/// How long client waits for response before giving up
+pub const NETFS_TIMEOUT: Duration = Duration::from_secs(2);
+
+/// What client asks server to do. Pathnames are relative
+/// to the exported tree, offsets and counts are in bytes.
+/// Requests are idempotent where 9p is: reads, writes at offset and
+/// lookups can be repeated, creates and removes can not
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Request {
+  Stat { pathname: String },
+  /// Entries from `offset`th, at most `NETFS_MAX_ENTRIES` of them
+  ReadDir { pathname: String, offset: usize },
+  Read { pathname: String, offset: AddressSize, count: AddressSize },
+  /// Write at `offset`, file is cut there first. Data is hex
+  Write { pathname: String, offset: AddressSize, data: String },
+  CreateFile { pathname: String },
+  CreateDir { pathname: String },
+  Remove { pathname: String },
+  ChangeMode { pathname: String, mode: FileMode },
+  ChangeOwners { pathname: String, uid: Id, gid: Id },
+  ChangeTimes { pathname: String, atime: u64, mtime: u64, ctime: u64, btime: u64 },
+}
+
+/// Request of user `uid`:`gid` of client,
+/// server does it with permissions of that user
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Call {
+  pub uid: Id,
+  pub gid: Id,
+  pub request: Request,
+}
+
+/// What server answers, any request can get `Error`
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub enum Response {
+  Stat(FileStat),
+  /// `(name, inode number)` of entries
+  Entries(Vec<(String, AddressSize)>),
+  /// Read bytes as hex, less than asked for at end of file
+  Data(String),
+  Done,
+  Error(Errno),
+}
+
+/// Request or response, `tag` pairs them together
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Message<T> {
+  pub tag: u16,
+  pub body: T,
+}
+
+impl<T: Serialize + DeserializeOwned> Message<T> {
+  pub fn encode(&self) -> Result<Vec<u8>, Errno> {
+    let frame = serde_yaml::to_string(self)
+      .map_err(|error| Errno::EINVAL(format!("netfs: cannot encode message: {error}").into()))?
+      .into_bytes();
+    if frame.len() > MAX_FRAME_SIZE {
+      return Err(Errno::EINVAL(format!("netfs: message of {} bytes does not fit in frame", frame.len()).into()));
+    }
+
+    Ok(frame)
+  }
+
+  pub fn decode(frame: &[u8]) -> Result<Self, Errno> {
+    serde_yaml::from_slice(frame)
+      .map_err(|error| Errno::EINVAL(format!("netfs: invalid message: {error}").into()))
+  }
+}
+
+/// `pathname` of exported tree as pathname in `root` of server, with
+/// symbolic links replaced by their targets, the last component's only
+/// if `follow_last`. Links are resolved here, so that neither they
+/// nor ".." lead out of exported tree
+///
+/// Errors:
+/// EACCES -> `pathname` leads out of exported tree
+/// ELOOP -> there are too many links on the way
+fn server_pathname(fs: &mut dyn Filesystem, root: &str, pathname: &str, follow_last: bool) -> Result<String, Errno> {
+  let root = root.trim_end_matches('/');
+  let outside = || Errno::EACCES(format!("netfs: {pathname}: leads out of exported tree").into());
+
+  // Components left to walk, the next one on top
+  let mut pending: Vec<String> = pathname.split('/').rev().map(str::to_owned).collect();
+  let mut resolved: Vec<String> = Vec::new();
+  let mut hops = 0;
+  while let Some(component) = pending.pop() {
+    match component.as_str() {
+      "" | "." => continue,
+      ".." => {
+        resolved.pop().ok_or_else(outside)?;
+        continue;
+      },
+      _ => resolved.push(component),
+    }
+
+    let is_last = pending.iter().all(|component| component.is_empty() || component == ".");
+    if is_last && !follow_last {
+      break;
+    }
+    // Not a link, or not there for operation to complain about
+    let Ok(target) = fs.readlink(&format!("{root}/{}", resolved.join("/"))) else {
+      continue;
+    };
+
+    hops += 1;
+    if hops > MAX_SYMLINK_HOPS {
+      return Err(Errno::ELOOP(format!("netfs: {pathname}: too many levels of symbolic links").into()));
+    }
+
+    resolved.pop();
+    // Absolute targets are those of server, relative ones are
+    // relative to directory of the link
+    let target = match target.starts_with('/') {
+      true => {
+        resolved.clear();
+        target
+          .strip_prefix(root)
+          .filter(|target| target.is_empty() || target.starts_with('/'))
+          .ok_or_else(outside)?
+          .to_owned()
+      },
+      false => target,
+    };
+    pending.extend(target.split('/').rev().map(str::to_owned));
+  }
+
+  Ok(format!("{root}/{}", resolved.join("/")))
+}
+
+fn handle(fs: &mut dyn Filesystem, root: &str, request: Request) -> Result<Response, Errno> {
+  let pathname = |fs: &mut dyn Filesystem, pathname: &str| server_pathname(fs, root, pathname, true);
+
+  Ok(match request {
+    Request::Stat { pathname: path } => {
+      let path = pathname(fs, &path)?;
+      Response::Stat(fs.stat(&path)?)
+    },
+    Request::ReadDir { pathname: path, offset } => {
+      let path = pathname(fs, &path)?;
+      Response::Entries(fs
+        .read_dir(&path)?
+        .entries
+        .into_iter()
+        .skip(offset)
+        .take(NETFS_MAX_ENTRIES)
+        .map(|(name, entry)| (name, entry.inode_number))
+        .collect())
+    },
+    Request::Read { pathname: path, offset, count } => {
+      let path = pathname(fs, &path)?;
+      // Filesystems read from the start only, and some ignore `count`
+      let count = count.min(NETFS_MAX_DATA);
+      let data = fs.read_file(&path, offset.saturating_add(count))?;
+      let data = data.get(offset as usize..).unwrap_or_default();
+      Response::Data(hex::encode(&data[..data.len().min(count as usize)]))
+    },
+    Request::Write { pathname: path, offset, data } => {
+      let path = pathname(fs, &path)?;
+      let data = hex::decode(data)
+        .map_err(|_| Errno::EINVAL(String::from("netfs: data is not hex").into()))?;
+      let mut contents = match offset {
+        0 => Vec::new(),
+        _ => fs.read_file(&path, offset)?,
+      };
+      contents.resize(offset as usize, 0);
+      contents.extend(data);
+      fs.write_file(&path, &contents)?;
+      Response::Done
+    },
+    Request::CreateFile { pathname: path } => {
+      let path = server_pathname(fs, root, &path, false)?;
+      fs.create_file(&path)?;
+      Response::Done
+    },
+    Request::CreateDir { pathname: path } => {
+      let path = server_pathname(fs, root, &path, false)?;
+      fs.create_dir(&path)?;
+      Response::Done
+    },
+    Request::Remove { pathname: path } => {
+      let path = server_pathname(fs, root, &path, false)?;
+      fs.remove_file(&path)?;
+      Response::Done
+    },
+    Request::ChangeMode { pathname: path, mode } => {
+      let path = pathname(fs, &path)?;
+      fs.change_mode(&path, mode)?;
+      Response::Done
+    },
+    Request::ChangeOwners { pathname: path, uid, gid } => {
+      let path = pathname(fs, &path)?;
+      fs.change_owners(&path, uid, gid)?;
+      Response::Done
+    },
+    Request::ChangeTimes { pathname: path, atime, mtime, ctime, btime } => {
+      let path = pathname(fs, &path)?;
+      fs.change_times(&path, Times { atime, mtime, ctime, btime })?;
+      Response::Done
+    },
+  })
+}
+
+/// Returns: call in `frame`, `None` if `frame` is not one
+pub fn receive(frame: &[u8]) -> Option<Message<Call>> {
+  Message::decode(frame).ok()
+}
+
+/// Do request of `call` with tree at `root` of `fs`, which is
+/// to check permissions of user of `call` by now, see `Call`.
+/// Returns: frame of response
+pub fn respond(fs: &mut dyn Filesystem, root: &str, call: Message<Call>) -> Option<Vec<u8>> {
+  let Message { tag, body: Call { request, .. } } = call;
+  let body = handle(fs, root, request).unwrap_or_else(Response::Error);
+
+  let response = Message { tag, body }.encode().or_else(|errno| Message {
+    tag,
+    body: Response::Error(errno),
+  }.encode());
+
+  response.ok()
+}
+
+/// Client of tree exported by `respond` on the other end of network
+/// interface. Every operation is a request, and waits for response
+/// for `NETFS_TIMEOUT` at most
+#[derive(Debug)]
+pub struct NetFilesystem {
+  nic: Arc<RwLock<dyn CharDevice>>,
+  next_tag: u16,
+  /// Ids of whoever does operations, see `set_current_user`
+  uid: Id,
+  gid: Id,
+}
+
+impl NetFilesystem {
+  pub fn new(nic: Arc<RwLock<dyn CharDevice>>) -> Self {
+    Self {
+      nic,
+      next_tag: 0,
+      uid: ROOT_UID,
+      gid: ROOT_GID,
+    }
+  }
+
+  /// Send `request` and wait for response to it. Frames that are
+  /// not it, like late responses to previous requests, are dropped
+  fn call(&mut self, request: Request) -> Result<Response, Errno> {
+    let tag = self.next_tag;
+    self.next_tag = self.next_tag.wrapping_add(1);
+    let call = Call { uid: self.uid, gid: self.gid, request };
+    self.nic.write().unwrap().write(&Message { tag, body: call }.encode()?)?;
+
+    let started = Instant::now();
+    while started.elapsed() < NETFS_TIMEOUT {
+      let frame = self.nic.write().unwrap().read(MAX_FRAME_SIZE as AddressSize)?;
+      if frame.is_empty() {
+        std::thread::sleep(Duration::from_millis(1));
+        continue;
+      }
+
+      match Message::<Response>::decode(&frame) {
+        Ok(Message { tag: response_tag, body }) if response_tag == tag => {
+          return match body {
+            Response::Error(errno) => Err(errno),
+            body => Ok(body),
+          };
+        },
+        _ => (),
+      }
+    }
+
+    Err(Errno::EIO(String::from("netfs: server did not respond").into()))
+  }
+
+  /// Call for request that has nothing to return
+  fn call_done(&mut self, request: Request) -> Result<(), Errno> {
+    match self.call(request)? {
+      Response::Done => Ok(()),
+      response => Err(Errno::EIO(format!("netfs: unexpected response {response:?}").into())),
+    }
+  }
+}
+
+impl Filesystem for NetFilesystem {
+  fn create_file(&mut self, pathname: &str)
+    -> Result<VINode, Errno> {
+    self.call_done(Request::CreateFile { pathname: pathname.to_owned() })?;
+    self.lookup_path(pathname)
+  }
+
+  fn remove_file(&mut self, pathname: &str)
+    -> Result<(), Errno> {
+    self.call_done(Request::Remove { pathname: pathname.to_owned() })
+  }
+
+  fn create_dir(&mut self, pathname: &str)
+    -> Result<VINode, Errno> {
+    self.call_done(Request::CreateDir { pathname: pathname.to_owned() })?;
+    self.lookup_path(pathname)
+  }
+
+  fn mknod(&mut self, pathname: &str, _file_type: FileModeType, _device_number: DeviceNumber)
+    -> Result<VINode, Errno> {
+    Err(Errno::EPERM(format!("netfs: cannot make device over network: {pathname}").into()))
+  }
+
+  fn link(&mut self, _existing: &str, new: &str)
+    -> Result<VINode, Errno> {
+    Err(Errno::EPERM(format!("netfs: cannot link over network: {new}").into()))
+  }
+
+  fn symlink(&mut self, _target: &str, pathname: &str)
+    -> Result<VINode, Errno> {
+    Err(Errno::EPERM(format!("netfs: cannot link over network: {pathname}").into()))
+  }
+
+  fn readlink(&mut self, pathname: &str)
+    -> Result<String, Errno> {
+    Err(Errno::EINVAL(format!("netfs: {pathname}: not a symbolic link").into()))
+  }
+
+  fn read_file(&mut self, pathname: &str, count: AddressSize)
+    -> Result<Vec<u8>, Errno> {
+    let mut data = Vec::new();
+    while (data.len() as AddressSize) < count {
+      let chunk = match self.call(Request::Read {
+        pathname: pathname.to_owned(),
+        offset: data.len() as AddressSize,
+        count: (count - data.len() as AddressSize).min(NETFS_MAX_DATA),
+      })? {
+        Response::Data(chunk) => hex::decode(chunk)
+          .map_err(|_| Errno::EIO(String::from("netfs: data is not hex").into()))?,
+        response => return Err(Errno::EIO(format!("netfs: unexpected response {response:?}").into())),
+      };
+      let is_last = (chunk.len() as AddressSize) < NETFS_MAX_DATA;
+      data.extend(chunk);
+      if is_last {
+        break;
+      }
+    }
+
+    Ok(data)
+  }
+
+  fn write_file(&mut self, pathname: &str, data: &[u8])
+    -> Result<VINode, Errno> {
+    // Empty write still cuts the file
+    let mut offset = 0;
+    for chunk in data.chunks(NETFS_MAX_DATA as usize).chain(data.is_empty().then_some(&[][..])) {
+      self.call_done(Request::Write {
+        pathname: pathname.to_owned(),
+        offset,
+        data: hex::encode(chunk),
+      })?;
+      offset += chunk.len() as AddressSize;
+    }
+
+    self.lookup_path(pathname)
+  }
+
+  fn read_dir(&mut self, pathname: &str)
+    -> Result<VDirectory, Errno> {
+    let mut entries = BTreeMap::new();
+    loop {
+      let chunk = match self.call(Request::ReadDir { pathname: pathname.to_owned(), offset: entries.len() })? {
+        Response::Entries(chunk) => chunk,
+        response => return Err(Errno::EIO(format!("netfs: unexpected response {response:?}").into())),
+      };
+      let is_last = chunk.len() < NETFS_MAX_ENTRIES;
+      for (name, inode_number) in chunk {
+        entries.insert(name.clone(), VDirectoryEntry::new(inode_number, &name));
+      }
+      if is_last {
+        break;
+      }
+    }
+
+    Ok(VDirectory { entries })
+  }
+
+  fn stat(&mut self, pathname: &str)
+    -> Result<FileStat, Errno> {
+    match self.call(Request::Stat { pathname: pathname.to_owned() })? {
+      Response::Stat(stat) => Ok(stat),
+      response => Err(Errno::EIO(format!("netfs: unexpected response {response:?}").into())),
+    }
+  }
+
+  fn change_mode(&mut self, pathname: &str, mode: FileMode)
+    -> Result<(), Errno> {
+    self.call_done(Request::ChangeMode { pathname: pathname.to_owned(), mode })
+  }
+
+  fn change_owners(&mut self, pathname: &str, uid: Id, gid: Id)
+    -> Result<(), Errno> {
+    self.call_done(Request::ChangeOwners { pathname: pathname.to_owned(), uid, gid })
+  }
+
+  fn change_times(&mut self, pathname: &str, times: Times)
+    -> Result<(), Errno> {
+    let Times { atime, mtime, ctime, btime } = times;
+    self.call_done(Request::ChangeTimes { pathname: pathname.to_owned(), atime, mtime, ctime, btime })
+  }
+
+  fn lookup_path(&mut self, pathname: &str)
+    -> Result<VINode, Errno> {
+    let stat = self.stat(pathname)?;
+
+    Ok(VINode {
+      mode: stat.mode,
+      links_count: stat.links_count,
+      uid: stat.uid,
+      gid: stat.gid,
+      file_size: stat.size,
+      atime: stat.atime,
+      mtime: stat.mtime,
+      ctime: stat.ctime,
+      btime: stat.btime,
+      rdev: stat.rdev,
+      number: stat.inode_number,
+    })
+  }
+
+  fn set_current_user(&mut self, uid: Id, gid: Id) {
+    self.uid = uid;
+    self.gid = gid;
+  }
+
+  fn name(&self) -> String {
+    String::from("netfs")
+  }
+
+  fn as_any(&mut self) -> &mut dyn Any {
+    self
+  }
+
+  fn as_any_ref(&self) -> &dyn Any {
+    self
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::{memory_e5fs, E5FS_SIZE};
+  use crate::eunix::binfs::BinFilesytem;
+  use crate::eunix::fs::EVERYTHING;
+
+  /// Network interface wired right into server of `fs`: every
+  /// written request is served at once, and response is read back
+  struct ServerNic {
+    fs: Box<dyn Filesystem>,
+    responses: Vec<Vec<u8>>,
+    /// `(uid, gid)` of every call
+    users: Vec<(Id, Id)>,
+  }
+
+  impl std::fmt::Debug for ServerNic {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+      f.debug_struct("ServerNic").field("responses", &self.responses).finish()
+    }
+  }
+
+  impl CharDevice for ServerNic {
+    fn read(&mut self, _count: AddressSize) -> Result<Vec<u8>, Errno> {
+      Ok(self.responses.pop().unwrap_or_default())
+    }
+
+    fn write(&mut self, data: &[u8]) -> Result<(), Errno> {
+      let call = receive(data).unwrap();
+      self.users.push((call.body.uid, call.body.gid));
+      self.responses.extend(respond(self.fs.as_mut(), "/export", call));
+      Ok(())
+    }
+  }
+
+  fn server(fs: impl Filesystem + 'static) -> Arc<RwLock<ServerNic>> {
+    Arc::new(RwLock::new(ServerNic { fs: Box::new(fs), responses: Vec::new(), users: Vec::new() }))
+  }
+
+  #[test]
+  fn exported_tree_is_used_over_network() {
+    let mut fs = BinFilesytem::new();
+    fs.create_dir("/export").unwrap();
+    let mut netfs = NetFilesystem::new(server(fs));
+    // More than fits in one message
+    let contents = "echo hello\n".repeat(100);
+
+    netfs.create_dir("/etc").unwrap();
+    netfs.create_file("/etc/motd").unwrap();
+    netfs.write_file("/etc/motd", contents.as_bytes()).unwrap();
+
+    assert_eq!(netfs.read_file("/etc/motd", EVERYTHING).unwrap(), contents.as_bytes());
+    assert_eq!(netfs.read_file("/etc/motd", 4).unwrap(), b"echo");
+    assert_eq!(netfs.stat("/etc/motd").unwrap().size, contents.len() as AddressSize);
+    assert!(netfs.read_dir("/etc").unwrap().entries.contains_key("motd"));
+    assert!(matches!(netfs.stat("/nowhere"), Err(Errno::ENOENT(_))));
+  }
+
+  #[test]
+  fn pathnames_do_not_lead_out_of_exported_tree() {
+    // One with symbolic links
+    let (mut fs, _) = memory_e5fs(E5FS_SIZE);
+    fs.create_dir("/export").unwrap();
+    fs.create_dir("/export/etc").unwrap();
+    fs.create_file("/export/etc/motd").unwrap();
+    fs.create_file("/secret").unwrap();
+    fs.symlink("/export/etc", "/export/absolute").unwrap();
+    fs.symlink("../etc/motd", "/export/etc/relative").unwrap();
+    fs.symlink("/secret", "/export/out").unwrap();
+    fs.symlink("../secret", "/export/up").unwrap();
+    let mut netfs = NetFilesystem::new(server(fs));
+
+    assert!(netfs.stat("/etc/../etc/./motd").is_ok());
+    assert!(netfs.stat("/absolute/motd").is_ok());
+    assert!(netfs.stat("/etc/relative").is_ok());
+    for pathname in ["/..", "/../secret", "/etc/../../secret", "/out", "/up", "/up/x"] {
+      assert!(matches!(netfs.stat(pathname), Err(Errno::EACCES(_))), "{pathname}");
+    }
+    // Link itself is removed, not its target
+    netfs.remove_file("/etc/relative").unwrap();
+    assert!(netfs.stat("/etc/motd").is_ok());
+  }
+
+  #[test]
+  fn calls_carry_ids_of_current_user() {
+    let mut fs = BinFilesytem::new();
+    fs.create_dir("/export").unwrap();
+    let nic = server(fs);
+    let mut netfs = NetFilesystem::new(nic.clone());
+
+    netfs.stat("/").unwrap();
+    netfs.set_current_user(1000, 100);
+    netfs.stat("/").unwrap();
+
+    assert_eq!(nic.read().unwrap().users, [(ROOT_UID, ROOT_GID), (1000, 100)]);
+  }
+}
+
+// vim:ts=2 sw=2
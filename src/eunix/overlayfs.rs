@@ -0,0 +1,556 @@
+use std::any::Any;
+use std::collections::BTreeMap;
+use std::sync::Arc;
+
+use super::clock::Clock;
+use super::fs::{AddressSize, DeviceNumber, FileMode, FileModeType, FileStat, Filesystem, FilesystemStat, Id, MountedFilesystem, VDirectory, VINode, EVERYTHING, VFS};
+use super::kernel::{Errno, Times};
+
+const LOWER: usize = 0;
+const UPPER: usize = 1;
+
+/// Filesystem taken from its mount point to be a layer of overlay,
+/// with directory of it that overlay shows
+#[derive(Debug)]
+pub struct Layer {
+  /// Where filesystem was mounted, it goes back there on unmount
+  pub mount_point: String,
+  pub mounted_fs: MountedFilesystem,
+  /// Pathname of the directory on filesystem
+  pub root: String,
+}
+
+/// Writable upper layer over read-only lower one, like overlay of
+/// Linux. Files of lower layer are copied up before they change,
+/// removed ones are hidden by whiteouts: character devices 0:0 in
+/// upper layer. Directory made in place of removed one gets whiteouts
+/// of entries of the lower one, instead of being opaque. Layers are
+/// whole filesystems, taken from their mount points for as long as
+/// overlay is mounted
+pub struct OverlayFilesystem {
+  /// Lower layer, then upper one
+  layers: Vec<Layer>,
+}
+
+fn is_whiteout(stat: &FileStat) -> bool {
+  stat.mode.file_type() == FileModeType::Char as u8 && stat.rdev == DeviceNumber::default()
+}
+
+/// Inode numbers of layers, told apart
+fn layer_number(layer: usize, number: AddressSize) -> AddressSize {
+  number * 2 + layer as AddressSize
+}
+
+impl OverlayFilesystem {
+  pub fn new(lower: Layer, upper: Layer) -> Self {
+    Self {
+      layers: vec![lower, upper],
+    }
+  }
+
+  /// Lower layer, then upper one
+  pub fn layers(&self) -> &[Layer] {
+    &self.layers
+  }
+
+  /// Give layers back to be mounted where they were,
+  /// overlay has nothing after this
+  pub fn take_layers(&mut self) -> Vec<Layer> {
+    std::mem::take(&mut self.layers)
+  }
+
+  /// Filesystem of `layer` and pathname of `pathname` on it
+  fn layer(&mut self, layer: usize, pathname: &str) -> (&mut dyn Filesystem, String) {
+    let layer = &mut self.layers[layer];
+    let pathname = VFS::join_path(&layer.root, pathname);
+    (layer.mounted_fs.driver.as_mut(), pathname)
+  }
+
+  /// Returns: layer that file at `pathname` is on. It is upper one if
+  /// file is there, lower one if no directory on the way to it is
+  /// replaced in upper one
+  ///
+  /// Errors:
+  /// ENOENT -> there is no such file, or it is whited out
+  fn locate(&mut self, pathname: &str) -> Result<usize, Errno> {
+    let (mut components, final_component) = VFS::split_path(pathname)?;
+    if final_component != "/" {
+      components.push(final_component);
+    }
+
+    let mut partial = String::new();
+    for (index, component) in components.iter().enumerate() {
+      partial = format!("{partial}/{component}");
+      let (upper, upper_pathname) = self.layer(UPPER, &partial);
+      match upper.stat(&upper_pathname) {
+        Ok(stat) if is_whiteout(&stat) => {
+          return Err(Errno::ENOENT(format!("overlayfs: {pathname}: no such file or directory").into()));
+        },
+        Ok(stat) if index + 1 < components.len() && stat.mode.file_type() != FileModeType::Dir as u8 => {
+          return Err(Errno::ENOTDIR(format!("overlayfs: {partial}: not a directory").into()));
+        },
+        Ok(_) => (),
+        // The rest of the way is only in lower layer
+        Err(Errno::ENOENT(_)) => {
+          let (lower, lower_pathname) = self.layer(LOWER, pathname);
+          lower.stat(&lower_pathname)?;
+          return Ok(LOWER);
+        },
+        Err(errno) => return Err(errno),
+      }
+    }
+
+    Ok(UPPER)
+  }
+
+  /// Copy file at `pathname` and directories on the way to it from
+  /// lower layer to upper one, unless it is there already. Directory
+  /// is copied without entries, they stay in lower layer
+  fn copy_up(&mut self, pathname: &str) -> Result<(), Errno> {
+    if self.locate(pathname)? == UPPER {
+      return Ok(());
+    }
+    self.copy_up(&VFS::parent_dir(pathname)?)?;
+
+    let (lower, lower_pathname) = self.layer(LOWER, pathname);
+    let stat = lower.stat(&lower_pathname)?;
+    let file_type = stat.mode.file_type();
+    let contents = match file_type == FileModeType::File as u8 {
+      true => lower.read_file(&lower_pathname, EVERYTHING)?,
+      false => Vec::new(),
+    };
+    let target = match file_type == FileModeType::Symlink as u8 {
+      true => lower.readlink(&lower_pathname)?,
+      false => String::new(),
+    };
+    let xattrs = match lower.list_xattr(&lower_pathname) {
+      Ok(names) => names
+        .into_iter()
+        .map(|name| Ok((lower.get_xattr(&lower_pathname, &name)?, name)))
+        .collect::<Result<Vec<_>, Errno>>()?,
+      Err(Errno::ENOTSUP(_)) => Vec::new(),
+      Err(errno) => return Err(errno),
+    };
+
+    let (upper, upper_pathname) = self.layer(UPPER, pathname);
+    match file_type {
+      file_type if file_type == FileModeType::Dir as u8 => upper.create_dir(&upper_pathname)?,
+      file_type if file_type == FileModeType::Symlink as u8 => upper.symlink(&target, &upper_pathname)?,
+      file_type if file_type == FileModeType::Block as u8 => upper.mknod(&upper_pathname, FileModeType::Block, stat.rdev)?,
+      file_type if file_type == FileModeType::Char as u8 => upper.mknod(&upper_pathname, FileModeType::Char, stat.rdev)?,
+      _ => {
+        upper.create_file(&upper_pathname)?;
+        upper.write_file(&upper_pathname, &contents)?
+      },
+    };
+    for (value, name) in xattrs {
+      upper.set_xattr(&upper_pathname, &name, Some(&value))?;
+    }
+    upper.change_mode(&upper_pathname, stat.mode)?;
+    upper.change_owners(&upper_pathname, stat.uid, stat.gid)?;
+    upper.change_times(&upper_pathname, Times {
+      atime: stat.atime,
+      mtime: stat.mtime,
+      ctime: stat.ctime,
+      btime: stat.btime,
+    })
+  }
+
+  /// Make file at `pathname` in upper layer with `create`, in place of
+  /// whiteout if there is one. Directory made in place of one that is
+  /// in lower layer gets whiteouts of its entries, so they don't show
+  ///
+  /// Errors:
+  /// EEXIST -> there is a file at `pathname`
+  fn create_with(&mut self, pathname: &str, create: impl FnOnce(&mut dyn Filesystem, &str) -> Result<VINode, Errno>)
+    -> Result<VINode, Errno> {
+    match self.locate(pathname) {
+      Ok(_) => return Err(Errno::EEXIST(format!("overlayfs: {pathname}: file exists").into())),
+      Err(Errno::ENOENT(_)) => (),
+      Err(errno) => return Err(errno),
+    }
+    self.copy_up(&VFS::parent_dir(pathname)?)?;
+
+    let (upper, upper_pathname) = self.layer(UPPER, pathname);
+    let is_whited_out = matches!(upper.stat(&upper_pathname), Ok(stat) if is_whiteout(&stat));
+    if is_whited_out {
+      upper.remove_file(&upper_pathname)?;
+    }
+    let vinode = create(upper, &upper_pathname)?;
+    let is_dir = upper.stat(&upper_pathname)?.mode.file_type() == FileModeType::Dir as u8;
+
+    if is_whited_out && is_dir {
+      let (lower, lower_pathname) = self.layer(LOWER, pathname);
+      let names = match lower.read_dir(&lower_pathname) {
+        Ok(dir) => dir.entries.into_keys().filter(|name| name != "." && name != "..").collect(),
+        Err(Errno::ENOENT(_) | Errno::ENOTDIR(_)) => Vec::new(),
+        Err(errno) => return Err(errno),
+      };
+      let (upper, upper_pathname) = self.layer(UPPER, pathname);
+      for name in names {
+        upper.mknod(&VFS::join_path(&upper_pathname, &name), FileModeType::Char, DeviceNumber::default())?;
+      }
+    }
+
+    Ok(VINode { number: layer_number(UPPER, vinode.number), ..vinode })
+  }
+}
+
+impl Filesystem for OverlayFilesystem {
+  fn create_file(&mut self, pathname: &str)
+    -> Result<VINode, Errno> {
+    self.create_with(pathname, |upper, pathname| upper.create_file(pathname))
+  }
+
+  fn remove_file(&mut self, pathname: &str)
+    -> Result<(), Errno> {
+    let layer = self.locate(pathname)?;
+    let (fs, layer_pathname) = self.layer(layer, pathname);
+    let is_dir = fs.stat(&layer_pathname)?.mode.file_type() == FileModeType::Dir as u8;
+    if is_dir && self.read_dir(pathname)?.entries.keys().any(|name| name != "." && name != "..") {
+      return Err(Errno::ENOTEMPTY(format!("overlayfs: {pathname}: directory not empty").into()));
+    }
+
+    let (lower, lower_pathname) = self.layer(LOWER, pathname);
+    let is_in_lower = lower.stat(&lower_pathname).is_ok();
+    match layer {
+      UPPER => {
+        // Only whiteouts are left in directory
+        let (upper, upper_pathname) = self.layer(UPPER, pathname);
+        if is_dir {
+          for name in upper.read_dir(&upper_pathname)?.entries.into_keys().filter(|name| name != "." && name != "..") {
+            upper.remove_file(&VFS::join_path(&upper_pathname, &name))?;
+          }
+        }
+        upper.remove_file(&upper_pathname)?;
+      },
+      _ => self.copy_up(&VFS::parent_dir(pathname)?)?,
+    }
+
+    if is_in_lower {
+      let (upper, upper_pathname) = self.layer(UPPER, pathname);
+      upper.mknod(&upper_pathname, FileModeType::Char, DeviceNumber::default())?;
+    }
+
+    Ok(())
+  }
+
+  fn create_dir(&mut self, pathname: &str)
+    -> Result<VINode, Errno> {
+    self.create_with(pathname, |upper, pathname| upper.create_dir(pathname))
+  }
+
+  fn mknod(&mut self, pathname: &str, file_type: FileModeType, device_number: DeviceNumber)
+    -> Result<VINode, Errno> {
+    self.create_with(pathname, |upper, pathname| upper.mknod(pathname, file_type, device_number))
+  }
+
+  fn link(&mut self, existing: &str, new: &str)
+    -> Result<VINode, Errno> {
+    self.copy_up(existing)?;
+    let upper_existing = self.layer(UPPER, existing).1;
+    self.create_with(new, |upper, pathname| upper.link(&upper_existing, pathname))
+  }
+
+  fn symlink(&mut self, target: &str, pathname: &str)
+    -> Result<VINode, Errno> {
+    self.create_with(pathname, |upper, pathname| upper.symlink(target, pathname))
+  }
+
+  fn readlink(&mut self, pathname: &str)
+    -> Result<String, Errno> {
+    let layer = self.locate(pathname)?;
+    let (fs, pathname) = self.layer(layer, pathname);
+    fs.readlink(&pathname)
+  }
+
+  fn read_file(&mut self, pathname: &str, count: AddressSize)
+    -> Result<Vec<u8>, Errno> {
+    let layer = self.locate(pathname)?;
+    let (fs, pathname) = self.layer(layer, pathname);
+    fs.read_file(&pathname, count)
+  }
+
+  fn write_file(&mut self, pathname: &str, data: &[u8])
+    -> Result<VINode, Errno> {
+    self.copy_up(pathname)?;
+    let (upper, pathname) = self.layer(UPPER, pathname);
+    let vinode = upper.write_file(&pathname, data)?;
+    Ok(VINode { number: layer_number(UPPER, vinode.number), ..vinode })
+  }
+
+  fn read_at(&mut self, pathname: &str, offset: AddressSize, count: AddressSize)
+    -> Result<Vec<u8>, Errno> {
+    let layer = self.locate(pathname)?;
+    let (fs, pathname) = self.layer(layer, pathname);
+    fs.read_at(&pathname, offset, count)
+  }
+
+  fn write_at(&mut self, pathname: &str, offset: AddressSize, data: &[u8])
+    -> Result<VINode, Errno> {
+    self.copy_up(pathname)?;
+    let (upper, pathname) = self.layer(UPPER, pathname);
+    let vinode = upper.write_at(&pathname, offset, data)?;
+    Ok(VINode { number: layer_number(UPPER, vinode.number), ..vinode })
+  }
+
+  fn truncate(&mut self, pathname: &str, size: AddressSize)
+    -> Result<VINode, Errno> {
+    self.copy_up(pathname)?;
+    let (upper, pathname) = self.layer(UPPER, pathname);
+    let vinode = upper.truncate(&pathname, size)?;
+    Ok(VINode { number: layer_number(UPPER, vinode.number), ..vinode })
+  }
+
+  fn append_file(&mut self, pathname: &str, data: &[u8])
+    -> Result<VINode, Errno> {
+    self.copy_up(pathname)?;
+    let (upper, pathname) = self.layer(UPPER, pathname);
+    let vinode = upper.append_file(&pathname, data)?;
+    Ok(VINode { number: layer_number(UPPER, vinode.number), ..vinode })
+  }
+
+  /// Entries of both layers, upper ones first, without whiteouts
+  fn read_dir(&mut self, pathname: &str)
+    -> Result<VDirectory, Errno> {
+    let layer = self.locate(pathname)?;
+    let mut entries = BTreeMap::new();
+
+    let (lower, lower_pathname) = self.layer(LOWER, pathname);
+    match lower.read_dir(&lower_pathname) {
+      Ok(dir) => for (name, mut entry) in dir.entries {
+        entry.inode_number = layer_number(LOWER, entry.inode_number);
+        entries.insert(name, entry);
+      },
+      // Directory is only in upper layer
+      Err(Errno::ENOENT(_) | Errno::ENOTDIR(_)) if layer == UPPER => (),
+      Err(errno) => return Err(errno),
+    }
+
+    if layer == UPPER {
+      let (upper, upper_pathname) = self.layer(UPPER, pathname);
+      for (name, mut entry) in upper.read_dir(&upper_pathname)?.entries {
+        if is_whiteout(&upper.stat(&VFS::join_path(&upper_pathname, &name))?) {
+          entries.remove(&name);
+          continue;
+        }
+        entry.inode_number = layer_number(UPPER, entry.inode_number);
+        entries.insert(name, entry);
+      }
+    }
+
+    Ok(VDirectory { entries })
+  }
+
+  fn stat(&mut self, pathname: &str)
+    -> Result<FileStat, Errno> {
+    let layer = self.locate(pathname)?;
+    let (fs, pathname) = self.layer(layer, pathname);
+    let stat = fs.stat(&pathname)?;
+    Ok(FileStat { inode_number: layer_number(layer, stat.inode_number), ..stat })
+  }
+
+  fn change_mode(&mut self, pathname: &str, mode: FileMode)
+    -> Result<(), Errno> {
+    self.copy_up(pathname)?;
+    let (upper, pathname) = self.layer(UPPER, pathname);
+    upper.change_mode(&pathname, mode)
+  }
+
+  fn change_owners(&mut self, pathname: &str, uid: Id, gid: Id)
+    -> Result<(), Errno> {
+    self.copy_up(pathname)?;
+    let (upper, pathname) = self.layer(UPPER, pathname);
+    upper.change_owners(&pathname, uid, gid)
+  }
+
+  fn change_times(&mut self, pathname: &str, times: Times)
+    -> Result<(), Errno> {
+    self.copy_up(pathname)?;
+    let (upper, pathname) = self.layer(UPPER, pathname);
+    upper.change_times(&pathname, times)
+  }
+
+  fn get_xattr(&mut self, pathname: &str, name: &str)
+    -> Result<Vec<u8>, Errno> {
+    let layer = self.locate(pathname)?;
+    let (fs, pathname) = self.layer(layer, pathname);
+    fs.get_xattr(&pathname, name)
+  }
+
+  fn set_xattr(&mut self, pathname: &str, name: &str, value: Option<&[u8]>)
+    -> Result<(), Errno> {
+    self.copy_up(pathname)?;
+    let (upper, pathname) = self.layer(UPPER, pathname);
+    upper.set_xattr(&pathname, name, value)
+  }
+
+  fn list_xattr(&mut self, pathname: &str)
+    -> Result<Vec<String>, Errno> {
+    let layer = self.locate(pathname)?;
+    let (fs, pathname) = self.layer(layer, pathname);
+    fs.list_xattr(&pathname)
+  }
+
+  /// Space is the one of upper layer, where everything new goes
+  fn statfs(&mut self, _pathname: &str)
+    -> Result<FilesystemStat, Errno> {
+    let (upper, pathname) = self.layer(UPPER, "/");
+    upper.statfs(&pathname)
+  }
+
+  fn sync(&mut self)
+    -> Result<(), Errno> {
+    self.layers
+      .iter_mut()
+      .map(|layer| layer.mounted_fs.driver.sync())
+      .fold(Ok(()), |result, sync_result| result.and(sync_result))
+  }
+
+  fn lookup_path(&mut self, pathname: &str)
+    -> Result<VINode, Errno> {
+    let layer = self.locate(pathname)?;
+    let (fs, pathname) = self.layer(layer, pathname);
+    let vinode = fs.lookup_path(&pathname)?;
+    Ok(VINode { number: layer_number(layer, vinode.number), ..vinode })
+  }
+
+  fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+    for layer in &mut self.layers {
+      layer.mounted_fs.driver.set_clock(clock.clone());
+    }
+  }
+
+  fn set_current_user(&mut self, uid: Id, gid: Id) {
+    for layer in &mut self.layers {
+      layer.mounted_fs.driver.set_current_user(uid, gid);
+    }
+  }
+
+  fn name(&self) -> String {
+    String::from("overlayfs")
+  }
+
+  fn as_any(&mut self) -> &mut dyn Any {
+    self
+  }
+
+  fn as_any_ref(&self) -> &dyn Any {
+    self
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::{kernel, memory_e5fs, E5FS_SIZE};
+  use crate::eunix::binfs::BinFilesytem;
+  use crate::eunix::fs::{FilesystemType, MountFlags};
+
+  fn overlay() -> OverlayFilesystem {
+    let mut lower = BinFilesytem::new();
+    lower.create_dir("/image").unwrap();
+    lower.create_dir("/image/etc").unwrap();
+    lower.create_file("/image/etc/motd").unwrap();
+    lower.write_file("/image/etc/motd", b"hello").unwrap();
+    lower.create_file("/image/etc/hostname").unwrap();
+    lower.create_dir("/image/var").unwrap();
+    lower.create_file("/image/var/log").unwrap();
+    let (upper, _) = memory_e5fs(E5FS_SIZE);
+
+    let layer = |mount_point: &str, r#type, driver: Box<dyn Filesystem>, root: &str| Layer {
+      mount_point: mount_point.to_owned(),
+      mounted_fs: MountedFilesystem { r#type, driver, flags: MountFlags::default(), source: String::new() },
+      root: root.to_owned(),
+    };
+    OverlayFilesystem::new(
+      layer("/mnt/base", FilesystemType::binfs, Box::new(lower), "/image"),
+      layer("/mnt/state", FilesystemType::e5fs, Box::new(upper), "/"),
+    )
+  }
+
+  fn names(overlay: &mut OverlayFilesystem, pathname: &str) -> Vec<String> {
+    overlay.read_dir(pathname).unwrap().entries.into_keys().filter(|name| name != "." && name != "..").collect()
+  }
+
+  #[test]
+  fn changes_go_to_upper_layer() {
+    let mut overlay = overlay();
+    assert_eq!(overlay.read_file("/etc/motd", EVERYTHING).unwrap(), b"hello");
+    assert_eq!(names(&mut overlay, "/"), ["etc", "var"]);
+
+    // Copied up on write, lower layer stays as it was
+    overlay.append_file("/etc/motd", b" world").unwrap();
+    assert_eq!(overlay.read_file("/etc/motd", EVERYTHING).unwrap(), b"hello world");
+    let (lower, pathname) = overlay.layer(LOWER, "/etc/motd");
+    assert_eq!(lower.read_file(&pathname, EVERYTHING).unwrap(), b"hello");
+    let (upper, _) = overlay.layer(UPPER, "/");
+    assert_eq!(upper.read_file("/etc/motd", EVERYTHING).unwrap(), b"hello world");
+    assert!(upper.lookup_path("/etc/hostname").is_err());
+
+    overlay.create_file("/etc/issue").unwrap();
+    assert!(matches!(overlay.create_file("/etc/hostname"), Err(Errno::EEXIST(_))));
+    assert_eq!(names(&mut overlay, "/etc"), ["hostname", "issue", "motd"]);
+    // Layers have different numbers
+    assert_ne!(overlay.stat("/etc/motd").unwrap().inode_number, overlay.stat("/etc/hostname").unwrap().inode_number);
+  }
+
+  #[test]
+  fn removed_files_are_whited_out() {
+    let mut overlay = overlay();
+    overlay.remove_file("/etc/hostname").unwrap();
+    assert!(matches!(overlay.lookup_path("/etc/hostname"), Err(Errno::ENOENT(_))));
+    assert_eq!(names(&mut overlay, "/etc"), ["motd"]);
+    let (lower, pathname) = overlay.layer(LOWER, "/etc/hostname");
+    assert!(lower.lookup_path(&pathname).is_ok());
+
+    // New file in place of removed one
+    overlay.create_file("/etc/hostname").unwrap();
+    assert_eq!(overlay.read_file("/etc/hostname", EVERYTHING).unwrap(), b"");
+
+    assert!(matches!(overlay.remove_file("/var"), Err(Errno::ENOTEMPTY(_))));
+    overlay.remove_file("/var/log").unwrap();
+    overlay.remove_file("/var").unwrap();
+    assert_eq!(names(&mut overlay, "/"), ["etc"]);
+    assert!(matches!(overlay.lookup_path("/var/log"), Err(Errno::ENOENT(_))));
+
+    // Directory in place of removed one doesn't show what was in it
+    overlay.create_dir("/var").unwrap();
+    assert!(names(&mut overlay, "/var").is_empty());
+    assert!(matches!(overlay.lookup_path("/var/log"), Err(Errno::ENOENT(_))));
+  }
+
+  #[test]
+  fn layers_go_back_on_umount() {
+    use crate::eunix::kernel::MountOptions;
+
+    let mut kernel = kernel();
+    kernel.mount("", "/", FilesystemType::binfs).unwrap();
+    for pathname in ["/base", "/state", "/merged"] {
+      kernel.vfs().create_dir(pathname).unwrap();
+    }
+    let layers = overlay().take_layers();
+    for (layer, mount_point) in layers.into_iter().zip(["/base", "/state"]) {
+      kernel.vfs().mount_points.insert(mount_point.to_owned(), layer.mounted_fs);
+    }
+
+    let options = MountOptions {
+      lowerdir: Some(String::from("/base/image")),
+      upperdir: Some(String::from("/state")),
+      ..MountOptions::default()
+    };
+    assert!(matches!(kernel.mount_with_options("", "/merged", FilesystemType::overlayfs, &MountOptions::default()), Err(Errno::EINVAL(_))));
+    kernel.mount_with_options("", "/merged", FilesystemType::overlayfs, &options).unwrap();
+    // Layers are only seen through overlay
+    assert!(!kernel.vfs().mount_points.contains_key("/base"));
+    assert!(kernel.vfs().lookup_path("/base/image").is_err());
+    kernel.vfs().write_file("/merged/etc/motd", b"bye").unwrap();
+    assert_eq!(kernel.vfs().read_file("/merged/etc/motd", EVERYTHING).unwrap(), b"bye");
+
+    kernel.umount("/merged").unwrap();
+    assert_eq!(kernel.vfs().read_file("/base/image/etc/motd", EVERYTHING).unwrap(), b"hello");
+    assert_eq!(kernel.vfs().read_file("/state/etc/motd", EVERYTHING).unwrap(), b"bye");
+  }
+}
+
+// vim:ts=2 sw=2
@@ -0,0 +1,187 @@
+use std::io::SeekFrom;
+
+use super::devices::BlockStorage;
+use super::kernel::{Errno, ErrnoContext};
+
+/// Unit of partition offsets and lengths, in bytes
+pub const SECTOR_SIZE: u64 = 512;
+/// Number of entries in partition table
+pub const MAX_PARTITIONS: usize = 4;
+/// Partition type byte of e5fs partitions (same as Linux native)
+pub const PARTITION_TYPE_E5FS: u8 = 0x83;
+
+const PARTITION_ENTRIES_OFFSET: usize = 446;
+const PARTITION_ENTRY_SIZE: usize = 16;
+const BOOT_SIGNATURE_OFFSET: usize = 510;
+const BOOT_SIGNATURE: [u8; 2] = [0x55, 0xAA];
+
+/// Entry of partition table
+/// Serialized format (MBR, little endian):
+/// `status:u8 chs_first:[u8;3] type:u8 chs_last:[u8;3] first_sector:u32 sectors_count:u32`
+/// CHS addresses are not used and written as zeroes
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct Partition {
+  pub partition_type: u8,
+  pub first_sector: u32,
+  pub sectors_count: u32,
+}
+
+impl Partition {
+  /// Offset of partition from the start of the disk, in bytes
+  pub fn offset(&self) -> u64 {
+    self.first_sector as u64 * SECTOR_SIZE
+  }
+
+  /// Size of partition, in bytes
+  pub fn size(&self) -> u64 {
+    self.sectors_count as u64 * SECTOR_SIZE
+  }
+
+  fn from_bytes(bytes: &[u8]) -> Option<Self> {
+    let partition_type = bytes[4];
+    let first_sector = u32::from_le_bytes(bytes[8..12].try_into().unwrap());
+    let sectors_count = u32::from_le_bytes(bytes[12..16].try_into().unwrap());
+
+    // Type 0 marks an unused slot
+    (partition_type != 0 && sectors_count != 0).then_some(Self {
+      partition_type,
+      first_sector,
+      sectors_count,
+    })
+  }
+
+  fn to_bytes(&self) -> [u8; PARTITION_ENTRY_SIZE] {
+    let mut bytes = [0u8; PARTITION_ENTRY_SIZE];
+    bytes[4] = self.partition_type;
+    bytes[8..12].copy_from_slice(&self.first_sector.to_le_bytes());
+    bytes[12..16].copy_from_slice(&self.sectors_count.to_le_bytes());
+    bytes
+  }
+}
+
+/// MBR-like partition table, stored in the first sector of the disk
+/// Partition in slot `n` is exposed as `sdXn+1` by devfs
+#[derive(Debug, Default, Clone, PartialEq, Eq)]
+pub struct PartitionTable {
+  pub partitions: [Option<Partition>; MAX_PARTITIONS],
+}
+
+impl PartitionTable {
+  /// Parse first sector of the disk.
+  /// Returns: `None` if it has no boot signature
+  pub fn parse(sector: &[u8]) -> Option<Self> {
+    if sector.len() < SECTOR_SIZE as usize
+      || sector[BOOT_SIGNATURE_OFFSET..BOOT_SIGNATURE_OFFSET + 2] != BOOT_SIGNATURE {
+      return None;
+    }
+
+    let mut partitions = [None; MAX_PARTITIONS];
+    for (slot, partition) in partitions.iter_mut().enumerate() {
+      let offset = PARTITION_ENTRIES_OFFSET + slot * PARTITION_ENTRY_SIZE;
+      *partition = Partition::from_bytes(&sector[offset..offset + PARTITION_ENTRY_SIZE]);
+    }
+
+    Some(Self { partitions })
+  }
+
+  pub fn serialize(&self) -> Vec<u8> {
+    let mut sector = vec![0u8; SECTOR_SIZE as usize];
+    for (slot, partition) in self.partitions.iter().enumerate() {
+      if let Some(partition) = partition {
+        let offset = PARTITION_ENTRIES_OFFSET + slot * PARTITION_ENTRY_SIZE;
+        sector[offset..offset + PARTITION_ENTRY_SIZE].copy_from_slice(&partition.to_bytes());
+      }
+    }
+    sector[BOOT_SIGNATURE_OFFSET..BOOT_SIGNATURE_OFFSET + 2].copy_from_slice(&BOOT_SIGNATURE);
+    sector
+  }
+
+  /// Lay out partitions of `sizes` (in bytes) one after another,
+  /// right after the table sector. Size `0` means "rest of the disk"
+  /// and is only allowed for the last partition
+  pub fn with_sizes(disk_size: u64, sizes: &[u64]) -> Result<Self, Errno> {
+    // Guard for too many partitions
+    if sizes.len() > MAX_PARTITIONS {
+      return Err(Errno::EINVAL(format!("partitions: at most {MAX_PARTITIONS} partitions are supported").into()));
+    }
+
+    let disk_sectors = disk_size / SECTOR_SIZE;
+    let mut partitions = [None; MAX_PARTITIONS];
+    let mut next_sector = 1;
+
+    for (slot, &size) in sizes.iter().enumerate() {
+      let is_last = slot == sizes.len() - 1;
+      let sectors_count = match size {
+        0 if is_last => disk_sectors.saturating_sub(next_sector),
+        0 => return Err(Errno::EINVAL(String::from("partitions: only the last partition can take the rest of the disk").into())),
+        size => (size + SECTOR_SIZE - 1) / SECTOR_SIZE,
+      };
+
+      // Guard for partitions not fitting on disk
+      if sectors_count == 0 || next_sector + sectors_count > disk_sectors {
+        return Err(Errno::ENOSPC(format!("partitions: partition {} doesn't fit on disk", slot + 1).into()));
+      }
+
+      partitions[slot] = Some(Partition {
+        partition_type: PARTITION_TYPE_E5FS,
+        first_sector: next_sector.try_into().or(Err(Errno::EINVAL(String::from("partitions: disk is too big").into())))?,
+        sectors_count: sectors_count.try_into().or(Err(Errno::EINVAL(String::from("partitions: partition is too big").into())))?,
+      });
+      next_sector += sectors_count;
+    }
+
+    Ok(Self { partitions })
+  }
+
+  /// Read table from the first sector of `storage`.
+  /// Returns: `None` if disk is not partitioned
+  pub fn read_from(storage: &mut dyn BlockStorage) -> Result<Option<Self>, Errno> {
+    // Disks smaller than a sector can't hold a table
+    if storage.size() < SECTOR_SIZE {
+      return Ok(None);
+    }
+
+    let mut sector = vec![0u8; SECTOR_SIZE as usize];
+    storage
+      .seek(SeekFrom::Start(0))
+      .and_then(|_| storage.read_exact(&mut sector))
+      .with_context(|| "partitions: cannot read partition table")?;
+
+    Ok(Self::parse(&sector))
+  }
+
+  pub fn write_to(&self, storage: &mut dyn BlockStorage) -> Result<(), Errno> {
+    storage
+      .seek(SeekFrom::Start(0))
+      .and_then(|_| storage.write_all(&self.serialize()))
+      .and_then(|_| storage.flush())
+      .with_context(|| "partitions: cannot write partition table")
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn partition_table_roundtrip_works() {
+    let table = PartitionTable::with_sizes(1024 * 1024, &[512 * 1024, 0]).unwrap();
+
+    assert_eq!(table.partitions[0].unwrap().first_sector, 1);
+    assert_eq!(table.partitions[0].unwrap().size(), 512 * 1024);
+    assert_eq!(table.partitions[1].unwrap().first_sector, 1 + 1024);
+    assert_eq!(table.partitions[1].unwrap().sectors_count, 2048 - 1 - 1024);
+    assert_eq!(table.partitions[2], None);
+
+    assert_eq!(PartitionTable::parse(&table.serialize()), Some(table));
+  }
+
+  #[test]
+  fn partition_table_rejects_oversized() {
+    assert!(PartitionTable::with_sizes(1024 * 1024, &[1024 * 1024]).is_err());
+    assert!(PartitionTable::with_sizes(1024 * 1024, &[0, 1024]).is_err());
+    assert_eq!(PartitionTable::parse(&[0u8; SECTOR_SIZE as usize]), None);
+  }
+}
+
+// vim:ts=2 sw=2
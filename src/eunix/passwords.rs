@@ -0,0 +1,211 @@
+use argon2::{Argon2, Params, PasswordHasher, PasswordVerifier};
+use argon2::password_hash::{PasswordHash, SaltString};
+use sha2::{Sha256, Digest};
+
+use super::kernel::Errno;
+use super::rng::Rng;
+
+/// Scheme used when none is configured
+pub const DEFAULT_SCHEME: &'static str = "ARGON2";
+
+/// Way of hashing passwords for /etc/shadow. Hashes are stored as
+/// `$<id>$...`, so that scheme of every hash can be told by its prefix
+pub trait PasswordScheme {
+  /// Identifier between the first two `$` of hashes
+  fn id(&self) -> &'static str;
+  /// Hash `password` with a new salt from `rng`
+  fn hash(&self, password: &str, rng: &dyn Rng) -> Result<String, Errno>;
+  /// Check `password` against `hash` made by this scheme
+  fn verify(&self, password: &str, hash: &str) -> Result<bool, Errno>;
+}
+
+/// Salted SHA-256: `$sha256$<salt>$<hex digest of salt + password>`.
+/// Bare hex digests without salt are accepted as well, as they were
+/// the only format before schemes existed
+pub struct Sha256Scheme;
+
+impl Sha256Scheme {
+  fn digest(salt: &str, password: &str) -> String {
+    hex::encode(Sha256::digest(format!("{salt}{password}").as_bytes()))
+  }
+}
+
+impl PasswordScheme for Sha256Scheme {
+  fn id(&self) -> &'static str {
+    "sha256"
+  }
+
+  fn hash(&self, password: &str, rng: &dyn Rng) -> Result<String, Errno> {
+    let salt = hex::encode(rng.bytes(16));
+
+    Ok(format!("${}${salt}${}", self.id(), Self::digest(&salt, password)))
+  }
+
+  fn verify(&self, password: &str, hash: &str) -> Result<bool, Errno> {
+    // Legacy unsalted digest
+    if !hash.starts_with('$') {
+      return Ok(Self::digest("", password) == hash);
+    }
+
+    match hash.split('$').collect::<Vec<_>>()[..] {
+      ["", id, salt, digest] if id == self.id() => Ok(Self::digest(salt, password) == digest),
+      _ => Err(Errno::EINVAL(format!("passwords: malformed {} hash", self.id()).into())),
+    }
+  }
+}
+
+/// bcrypt: `$2b$<cost>$<salt and digest>`
+pub struct BcryptScheme {
+  pub cost: u32,
+}
+
+impl Default for BcryptScheme {
+  fn default() -> Self {
+    Self { cost: bcrypt::DEFAULT_COST }
+  }
+}
+
+impl PasswordScheme for BcryptScheme {
+  fn id(&self) -> &'static str {
+    "2b"
+  }
+
+  fn hash(&self, password: &str, rng: &dyn Rng) -> Result<String, Errno> {
+    let mut salt = [0u8; 16];
+    rng.fill(&mut salt);
+
+    bcrypt::hash_with_salt(password, self.cost, salt)
+      .map(|parts| parts.format_for_version(bcrypt::Version::TwoB))
+      .map_err(|error| Errno::EINVAL(format!("passwords: cannot hash with bcrypt: {error}").into()))
+  }
+
+  fn verify(&self, password: &str, hash: &str) -> Result<bool, Errno> {
+    bcrypt::verify(password, hash)
+      .map_err(|error| Errno::EINVAL(format!("passwords: malformed bcrypt hash: {error}").into()))
+  }
+}
+
+/// Argon2id in PHC format: `$argon2id$v=19$m=...,t=...,p=...$<salt>$<digest>`
+pub struct Argon2Scheme {
+  /// Memory cost in KiB
+  pub memory_cost: u32,
+  /// Number of iterations
+  pub time_cost: u32,
+}
+
+impl Default for Argon2Scheme {
+  fn default() -> Self {
+    Self {
+      memory_cost: Params::DEFAULT_M_COST,
+      time_cost: Params::DEFAULT_T_COST,
+    }
+  }
+}
+
+impl PasswordScheme for Argon2Scheme {
+  fn id(&self) -> &'static str {
+    "argon2id"
+  }
+
+  fn hash(&self, password: &str, rng: &dyn Rng) -> Result<String, Errno> {
+    let params = Params::new(self.memory_cost, self.time_cost, Params::DEFAULT_P_COST, None)
+      .map_err(|error| Errno::EINVAL(format!("passwords: invalid argon2 params: {error}").into()))?;
+    let salt = SaltString::encode_b64(&rng.bytes(16))
+      .map_err(|error| Errno::EINVAL(format!("passwords: cannot make argon2 salt: {error}").into()))?;
+
+    Argon2::new(argon2::Algorithm::Argon2id, argon2::Version::V0x13, params)
+      .hash_password(password.as_bytes(), &salt)
+      .map(|hash| hash.to_string())
+      .map_err(|error| Errno::EINVAL(format!("passwords: cannot hash with argon2: {error}").into()))
+  }
+
+  fn verify(&self, password: &str, hash: &str) -> Result<bool, Errno> {
+    // Params are taken from the hash itself
+    let hash = PasswordHash::new(hash)
+      .map_err(|error| Errno::EINVAL(format!("passwords: malformed argon2 hash: {error}").into()))?;
+
+    Ok(Argon2::default().verify_password(password.as_bytes(), &hash).is_ok())
+  }
+}
+
+/// Get scheme by name as in `ENCRYPT_METHOD` of /etc/login.defs
+pub fn scheme_by_name(name: &str) -> Result<Box<dyn PasswordScheme>, Errno> {
+  match name {
+    "SHA256" => Ok(Box::new(Sha256Scheme)),
+    "BCRYPT" => Ok(Box::new(BcryptScheme::default())),
+    "ARGON2" => Ok(Box::new(Argon2Scheme::default())),
+    _ => Err(Errno::EINVAL(format!("passwords: unknown scheme: {name}").into())),
+  }
+}
+
+/// Get scheme that made `hash`, by its `$<id>$` prefix
+pub fn scheme_of(hash: &str) -> Result<Box<dyn PasswordScheme>, Errno> {
+  match hash.split('$').nth(1) {
+    None => Ok(Box::new(Sha256Scheme)),
+    Some("sha256") => Ok(Box::new(Sha256Scheme)),
+    Some("2a" | "2b" | "2x" | "2y") => Ok(Box::new(BcryptScheme::default())),
+    Some("argon2id") => Ok(Box::new(Argon2Scheme::default())),
+    Some(id) => Err(Errno::EINVAL(format!("passwords: unknown scheme id: {id}").into())),
+  }
+}
+
+/// Check `password` against `hash` of any known scheme
+pub fn verify(password: &str, hash: &str) -> Result<bool, Errno> {
+  scheme_of(hash)?.verify(password, hash)
+}
+
+/// Whether `hash` should be rehashed with `scheme`
+pub fn needs_upgrade(hash: &str, scheme: &dyn PasswordScheme) -> bool {
+  scheme_of(hash)
+    .map(|hash_scheme| hash_scheme.id() != scheme.id() || !hash.starts_with('$'))
+    .unwrap_or(true)
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::eunix::rng::SeededRng;
+
+  fn fast_schemes() -> Vec<Box<dyn PasswordScheme>> {
+    vec![
+      Box::new(Sha256Scheme),
+      Box::new(BcryptScheme { cost: 4 }),
+      Box::new(Argon2Scheme { memory_cost: 64, time_cost: 1 }),
+    ]
+  }
+
+  #[test]
+  fn hash_and_verify_works() {
+    let rng = SeededRng::new(1);
+    for scheme in fast_schemes() {
+      let hash = scheme.hash("password", &rng).unwrap();
+
+      assert!(hash.starts_with(&format!("${}$", scheme.id())), "{hash}");
+      assert!(verify("password", &hash).unwrap());
+      assert!(!verify("wrong", &hash).unwrap());
+      assert_ne!(hash, scheme.hash("password", &rng).unwrap(), "salt must differ");
+    }
+  }
+
+  #[test]
+  fn same_seed_gives_same_hash() {
+    for scheme in fast_schemes() {
+      assert_eq!(
+        scheme.hash("password", &SeededRng::new(7)).unwrap(),
+        scheme.hash("password", &SeededRng::new(7)).unwrap(),
+      );
+    }
+  }
+
+  #[test]
+  fn legacy_sha256_is_verified_and_upgraded() {
+    let legacy = hex::encode(Sha256::digest(b"password"));
+
+    assert!(verify("password", &legacy).unwrap());
+    assert!(needs_upgrade(&legacy, &Sha256Scheme));
+    assert!(needs_upgrade(&legacy, &BcryptScheme { cost: 4 }));
+    assert!(!needs_upgrade(&Sha256Scheme.hash("password", &SeededRng::new(1)).unwrap(), &Sha256Scheme));
+  }
+}
+
+// vim:ts=2 sw=2
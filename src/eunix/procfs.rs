@@ -0,0 +1,305 @@
+use std::sync::Arc;
+use std::collections::BTreeMap;
+
+use super::{
+  clock::Clock,
+  fs::{Filesystem, AddressSize, FileModeType, VINode, VDirectory, FileStat, FileMode, DeviceNumber, Id},
+  virtfs::{VirtFsFilesystem, Payload, Generator},
+  kernel::{Kernel, Errno, Times},
+};
+
+/// Read-only filesystem exposing kernel state: processes and mounts.
+/// Every file is generated from the running kernel by `Kernel::update_procfs`,
+/// when that state changes and when the file is read with `Kernel::read_file`
+pub struct ProcessFilesystem {
+  pub virtfs: VirtFsFilesystem<String>,
+  /// Map of `inode number -> content` of generated files as of the last `update`
+  contents: BTreeMap<AddressSize, String>,
+}
+
+/// Mount table, `<source> <mount_point> <fs_type> <options> 0 0`
+/// on every line, like that of Linux
+fn mounts(kernel: &Kernel) -> String {
+  kernel.mount_table()
+    .into_iter()
+    .map(|entry| format!("{} {} {} {} 0 0\n", entry.source, entry.target, entry.r#type, entry.flags))
+    .collect()
+}
+
+/// Status of current process
+fn self_status(kernel: &Kernel) -> String {
+  match kernel.processes().get(&kernel.current_process_id()) {
+    Some(process) => format!(
+      "Name:\t{}\nPid:\t{}\nPPid:\t{}\nSid:\t{}\nUid:\t{}\nTty:\t{}\n",
+      process.binary,
+      process.pid,
+      process.ppid,
+      process.sid,
+      process.uid,
+      process.controlling_tty.as_deref().unwrap_or("?"),
+    ),
+    None => String::new(),
+  }
+}
+
+/// Memory of machine in KiB, like Linux one
+fn meminfo(kernel: &Kernel) -> String {
+  let total = kernel.resources.memory / 1024;
+  let free = total.saturating_sub(kernel.memory_used() / 1024);
+
+  format!("MemTotal:\t{total} kB\nMemFree:\t{free} kB\nMemAvailable:\t{free} kB\n")
+}
+
+/// Paragraph for every CPU of machine
+fn cpuinfo(kernel: &Kernel) -> String {
+  (0..kernel.resources.cpus)
+    .map(|processor| format!("processor\t: {processor}\nmodel name\t: eunix virtual cpu\n\n"))
+    .collect()
+}
+
+/// Table of all processes, `<pid> <ppid> <uid> <binary>` on every line
+fn processes(kernel: &Kernel) -> String {
+  kernel.processes()
+    .values()
+    .map(|process| format!("{} {} {} {}\n", process.pid, process.ppid, process.uid, process.binary))
+    .collect()
+}
+
+/// Files of procfs and what they are generated with
+const FILES: [(&str, Generator); 5] = [
+  ("/mounts", mounts),
+  ("/meminfo", meminfo),
+  ("/cpuinfo", cpuinfo),
+  ("/processes", processes),
+  ("/self/status", self_status),
+];
+
+/// Generate every file of procfs from `kernel`.
+/// Returns: `(pathname, content)` of them, for `ProcessFilesystem::update`
+pub fn generate(kernel: &Kernel) -> Vec<(&'static str, String)> {
+  FILES
+    .iter()
+    .map(|&(pathname, generator)| (pathname, generator(kernel)))
+    .collect()
+}
+
+impl ProcessFilesystem {
+  pub fn new() -> Self {
+    let mut virtfs = VirtFsFilesystem::new("procfs", 16);
+
+    for (pathname, generator) in FILES {
+      virtfs
+        .create_generated_file(pathname, generator)
+        .expect("procfs: we know that we have enough inodes and there is no dublicates");
+    }
+
+    Self {
+      virtfs,
+      contents: BTreeMap::new(),
+    }
+  }
+
+  /// Keep `contents` from `generate`, that reads of files return
+  /// until the next update. Files read empty before the first one
+  pub fn update(&mut self, contents: &[(&str, String)]) -> Result<(), Errno> {
+    for (pathname, content) in contents {
+      let inode_number = self.virtfs.lookup_path(pathname)?.number;
+      self.contents.insert(inode_number, content.to_owned());
+    }
+
+    Ok(())
+  }
+}
+
+impl Filesystem for ProcessFilesystem {
+  fn create_file(&mut self, pathname: &str)
+    -> Result<VINode, Errno> {
+    Err(Errno::EPERM(format!("procfs: read-only filesystem: {pathname}").into()))
+  }
+
+  fn remove_file(&mut self, pathname: &str)
+    -> Result<(), Errno> {
+    Err(Errno::EPERM(format!("procfs: read-only filesystem: {pathname}").into()))
+  }
+
+  fn create_dir(&mut self, pathname: &str)
+    -> Result<VINode, Errno> {
+    Err(Errno::EPERM(format!("procfs: read-only filesystem: {pathname}").into()))
+  }
+
+  fn mknod(&mut self, pathname: &str, _file_type: FileModeType, _device_number: DeviceNumber)
+    -> Result<VINode, Errno> {
+    Err(Errno::EPERM(format!("procfs: read-only filesystem: {pathname}").into()))
+  }
+
+  fn link(&mut self, _existing: &str, new: &str)
+    -> Result<VINode, Errno> {
+    Err(Errno::EPERM(format!("procfs: read-only filesystem: {new}").into()))
+  }
+
+  fn symlink(&mut self, _target: &str, pathname: &str)
+    -> Result<VINode, Errno> {
+    Err(Errno::EPERM(format!("procfs: read-only filesystem: {pathname}").into()))
+  }
+
+  fn readlink(&mut self, pathname: &str)
+    -> Result<String, Errno> {
+    Err(Errno::EINVAL(format!("procfs: {pathname}: not a symbolic link").into()))
+  }
+
+  fn read_file(&mut self, pathname: &str, count: AddressSize)
+    -> Result<Vec<u8>, Errno> {
+    let inode_number = self.virtfs.lookup_path(pathname)?.number;
+
+    match self.virtfs.read_payload(inode_number)? {
+      Payload::Generator(_) => Ok(
+        self.contents
+          .get(&inode_number)
+          .map(|content| content.as_bytes().to_owned())
+          .unwrap_or_default()
+      ),
+      _ => self.virtfs.read_file(pathname, count),
+    }
+  }
+
+  fn write_file(&mut self, pathname: &str, data: &[u8])
+    -> Result<VINode, Errno> {
+    self.virtfs.write_file(pathname, data)
+  }
+
+  fn read_dir(&mut self, pathname: &str)
+    -> Result<VDirectory, Errno> {
+    self.virtfs.read_dir(pathname)
+  }
+
+  fn stat(&mut self, pathname: &str)
+    -> Result<FileStat, Errno> {
+    self.virtfs.stat(pathname)
+  }
+
+  fn change_mode(&mut self, pathname: &str, _mode: FileMode)
+    -> Result<(), Errno> {
+    Err(Errno::EPERM(format!("procfs: read-only filesystem: {pathname}").into()))
+  }
+
+  fn change_owners(&mut self, pathname: &str, _uid: Id, _gid: Id)
+    -> Result<(), Errno> {
+    Err(Errno::EPERM(format!("procfs: read-only filesystem: {pathname}").into()))
+  }
+
+  fn change_times(&mut self, pathname: &str, times: Times)
+    -> Result<(), Errno> {
+    self.virtfs.change_times(pathname, times)
+  }
+
+  fn lookup_path(&mut self, pathname: &str)
+    -> Result<VINode, Errno> {
+    self.virtfs.lookup_path(pathname)
+  }
+
+  fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+    self.virtfs.set_clock(clock)
+  }
+
+  fn name(&self) -> String {
+    String::from("procfs")
+  }
+
+  fn as_any(&mut self) -> &mut dyn std::any::Any {
+    self
+  }
+
+  fn as_any_ref(&self) -> &dyn std::any::Any {
+    self
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::kernel;
+  use crate::{machine::MachineResources, eunix::{kernel::{Args, MountOptions}, fs::{FilesystemType, MountFlags}}};
+
+  fn kernel_with_procfs() -> Kernel {
+    let mut kernel = kernel();
+    kernel.mount("", "/", FilesystemType::binfs).unwrap();
+    kernel.vfs().create_dir("/proc").unwrap();
+    kernel.mount("", "/proc", FilesystemType::procfs).unwrap();
+
+    kernel
+  }
+
+  #[test]
+  fn mounts_are_generated_on_read() {
+    let mut kernel = kernel_with_procfs();
+    assert_eq!(kernel.read_file("/proc/mounts", AddressSize::MAX).unwrap(), b"binfs / binfs rw 0 0\nprocfs /proc procfs rw 0 0\n");
+
+    kernel.mount("", "/bin", FilesystemType::binfs).unwrap();
+
+    assert_eq!(kernel.read_file("/proc/mounts", AddressSize::MAX).unwrap(), b"binfs / binfs rw 0 0\nbinfs /bin binfs rw 0 0\nprocfs /proc procfs rw 0 0\n");
+
+    let options = MountOptions { flags: MountFlags { read_only: true, noexec: true, ..Default::default() }, ..Default::default() };
+    kernel.mount_with_options("tools", "/srv", FilesystemType::binfs, &options).unwrap();
+    kernel.bind_mount("/bin", "/mnt").unwrap();
+
+    assert_eq!(kernel.read_file("/proc/mounts", AddressSize::MAX).unwrap(), b"binfs / binfs rw 0 0\nbinfs /bin binfs rw 0 0\nbinfs /mnt binfs rw 0 0\nprocfs /proc procfs rw 0 0\ntools /srv binfs ro,noexec 0 0\n");
+  }
+
+  #[test]
+  fn self_status_shows_current_process() {
+    let mut kernel = kernel_with_procfs();
+
+    let status = String::from_utf8(kernel.read_file("/proc/self/status", AddressSize::MAX).unwrap()).unwrap();
+
+    assert!(status.contains("Name:\t/bin/init\n"));
+    assert!(status.contains(&format!("Pid:\t{}\n", kernel.current_process_id())));
+  }
+
+  #[test]
+  fn meminfo_and_cpuinfo_show_resources() {
+    let mut kernel = kernel_with_procfs();
+    kernel.resources = MachineResources { memory: 8 * 1024 * 1024, cpus: 2 };
+    kernel.devices_mut().ram_disks = vec![1024 * 1024];
+
+    let meminfo = String::from_utf8(kernel.read_file("/proc/meminfo", AddressSize::MAX).unwrap()).unwrap();
+    let cpuinfo = String::from_utf8(kernel.read_file("/proc/cpuinfo", AddressSize::MAX).unwrap()).unwrap();
+
+    assert!(meminfo.starts_with("MemTotal:\t8192 kB\nMemFree:\t7168 kB\n"));
+    assert_eq!(cpuinfo.matches("processor").count(), 2);
+  }
+
+  #[test]
+  fn procfs_is_read_only() {
+    let mut procfs = ProcessFilesystem::new();
+
+    assert!(matches!(procfs.write_file("/mounts", b"nope"), Err(Errno::EPERM(_))));
+    assert!(matches!(procfs.create_file("/file"), Err(Errno::EPERM(_))));
+    assert_eq!(procfs.stat("/mounts").unwrap().mode.user(), 0b100);
+  }
+
+  #[test]
+  fn files_are_read_through_vfs() {
+    let mut kernel = kernel_with_procfs();
+    assert_eq!(kernel.vfs().read_file("/proc/mounts", AddressSize::MAX).unwrap(), b"binfs / binfs rw 0 0\nprocfs /proc procfs rw 0 0\n");
+
+    kernel.mount("", "/bin", FilesystemType::binfs).unwrap();
+    assert_eq!(kernel.vfs().read_file("/proc/mounts", AddressSize::MAX).unwrap(), b"binfs / binfs rw 0 0\nbinfs /bin binfs rw 0 0\nprocfs /proc procfs rw 0 0\n");
+    assert_eq!(kernel.vfs().read_at("/proc/mounts", 27, 4).unwrap(), b"/bin");
+
+    // Binary sees its own process
+    fn status(_: Args, kernel: &mut Kernel) -> AddressSize {
+      let status = String::from_utf8(kernel.vfs().read_file("/proc/self/status", AddressSize::MAX).unwrap()).unwrap();
+      match status.contains("Name:\t/bin/status\n") {
+        true => 0,
+        false => 1,
+      }
+    }
+    kernel.register_binary("/bin/status", status).unwrap();
+    assert_eq!(kernel.run("/bin/status", &["status"]).unwrap(), 0);
+
+    // Never generated file reads empty
+    assert_eq!(ProcessFilesystem::new().read_file("/mounts", AddressSize::MAX).unwrap(), b"");
+  }
+}
+
+// vim:ts=2 sw=2
@@ -0,0 +1,248 @@
+use fancy_regex::Regex;
+use itertools::Itertools;
+
+use crate::binaries::as_root;
+use super::{
+  fs::{Filesystem, AddressSize, OpenFlags, OpenMode, EVERYTHING},
+  kernel::{Kernel, Errno},
+};
+
+/// History of all logins, logouts and boots, only ever appended to
+pub const WTMP_PATH: &'static str = "/var/log/wtmp";
+/// Sessions that are open right now, cleared on boot
+pub const UTMP_PATH: &'static str = "/var/run/utmp";
+
+/// Name and tty of boot records, as `last` shows them
+const BOOT_NAME: &'static str = "reboot";
+const BOOT_TTY: &'static str = "~";
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordType {
+  Boot,
+  Login,
+  Logout,
+}
+
+impl RecordType {
+  fn as_str(&self) -> &'static str {
+    match self {
+      Self::Boot => "BOOT",
+      Self::Login => "LOGIN",
+      Self::Logout => "LOGOUT",
+    }
+  }
+
+  fn from_str(string: &str) -> Option<Self> {
+    match string {
+      "BOOT" => Some(Self::Boot),
+      "LOGIN" => Some(Self::Login),
+      "LOGOUT" => Some(Self::Logout),
+      _ => None,
+    }
+  }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+/// Serialized format: `type:name:tty:pid:time`,
+/// `time` is seconds since unix epoch
+pub struct Record {
+  pub r#type: RecordType,
+  pub name: String,
+  pub tty: String,
+  pub pid: AddressSize,
+  pub time: u64,
+}
+
+impl Record {
+  /// Parse `type:name:tty:pid:time`
+  /// lines - invalid ones omitted
+  pub fn parse_records(string: &str) -> Vec<Record> {
+    string
+      .lines()
+      .flat_map(|line| {
+        if !Regex::new("^.*:.*:.*:.*:.*$").unwrap().is_match(line).unwrap() {
+          return None;
+        }
+
+        let mut split = line.split(":");
+
+        let r#type = RecordType::from_str(split.next().unwrap_or(""))?;
+        let name = split.next().unwrap_or("").to_owned();
+        let tty = split.next().unwrap_or("").to_owned();
+        let pid = split.next().unwrap_or("").parse::<AddressSize>().ok()?;
+        let time = split.next().unwrap_or("").parse::<u64>().ok()?;
+
+        Some(Record {
+          r#type,
+          name,
+          tty,
+          pid,
+          time,
+        })
+      })
+      .collect()
+  }
+
+  pub fn to_string(&self) -> String {
+    let Record { r#type, name, tty, pid, time } = self;
+
+    format!("{}:{name}:{tty}:{pid}:{time}", r#type.as_str())
+  }
+
+  pub fn serialize_records(records: &[Record]) -> String {
+    records
+      .iter()
+      .map(|record| format!("{}\n", record.to_string()))
+      .join("")
+  }
+
+  /// Record of current process in `kernel`, now
+  fn new(kernel: &Kernel, r#type: RecordType, name: &str) -> Self {
+    Self {
+      r#type,
+      name: name.to_owned(),
+      tty: kernel.controlling_tty().unwrap_or(String::from("?")),
+      pid: kernel.current_process_id(),
+      time: kernel.clock.now(),
+    }
+  }
+}
+
+/// Read records from `pathname`, which is missing if there are none
+pub fn read_records(kernel: &mut Kernel, pathname: &str) -> Result<Vec<Record>, Errno> {
+  match kernel.vfs().read_file(pathname, EVERYTHING) {
+    Ok(bytes) => {
+      let contents = String::from_utf8(bytes)
+        .or(Err(Errno::EILSEQ(format!("read_records: invalid utf8 in {pathname}").into())))?;
+      Ok(Record::parse_records(&contents))
+    },
+    Err(Errno::ENOENT(_)) => Ok(Vec::new()),
+    Err(errno) => Err(errno),
+  }
+}
+
+/// Append `record` to `pathname`, creating it if needed
+fn append_record(kernel: &mut Kernel, pathname: &str, record: &Record) -> Result<(), Errno> {
+  as_root(kernel, |kernel| {
+    let file_descriptor = kernel.open(pathname, OpenFlags::new(OpenMode::Write, true, true))?;
+    let result = kernel.write(file_descriptor, format!("{}\n", record.to_string()).into_bytes());
+    kernel.close(file_descriptor)?;
+
+    result.map(|_| ())
+  })
+}
+
+/// Start fresh utmp and write boot record to wtmp,
+/// creating directories for both if needed
+pub fn record_boot(kernel: &mut Kernel) -> Result<(), Errno> {
+  as_root(kernel, |kernel| {
+    for pathname in [WTMP_PATH, UTMP_PATH] {
+      let mut parent = String::new();
+      for name in pathname.split('/').filter(|name| !name.is_empty()).dropping_back(1) {
+        parent.push('/');
+        parent.push_str(name);
+
+        let mut vfs = kernel.vfs();
+        match vfs.lookup_path(&parent) {
+          Ok(_) => (),
+          Err(Errno::ENOENT(_)) => {
+            vfs.create_dir(&parent)?;
+          },
+          Err(errno) => return Err(errno),
+        }
+      }
+    }
+
+    // Nobody is logged in after boot
+    let file_descriptor = kernel.open(UTMP_PATH, OpenFlags::new(OpenMode::Write, true, false).with_truncate(true))?;
+    let result = kernel.write(file_descriptor, Vec::new());
+    kernel.close(file_descriptor)?;
+    result?;
+
+    let record = Record {
+      tty: String::from(BOOT_TTY),
+      ..Record::new(kernel, RecordType::Boot, BOOT_NAME)
+    };
+
+    append_record(kernel, WTMP_PATH, &record)
+  })
+}
+
+/// Record login of `name` on controlling terminal of current process
+pub fn record_login(kernel: &mut Kernel, name: &str) -> Result<(), Errno> {
+  let record = Record::new(kernel, RecordType::Login, name);
+
+  append_record(kernel, UTMP_PATH, &record)?;
+  append_record(kernel, WTMP_PATH, &record)
+}
+
+/// Record logout from controlling terminal of current process,
+/// closing its session in utmp
+pub fn record_logout(kernel: &mut Kernel) -> Result<(), Errno> {
+  let tty = kernel.controlling_tty().unwrap_or(String::from("?"));
+  let (sessions, closed): (Vec<_>, Vec<_>) = read_records(kernel, UTMP_PATH)?
+    .into_iter()
+    .partition(|record| record.tty != tty);
+  let name = closed
+    .last()
+    .map(|record| record.name.clone())
+    .unwrap_or_default();
+
+  as_root(kernel, |kernel| {
+    kernel.vfs().write_file(UTMP_PATH, Record::serialize_records(&sessions).as_bytes())
+  })?;
+
+  append_record(kernel, WTMP_PATH, &Record::new(kernel, RecordType::Logout, &name))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::kernel_with_binfs_root;
+
+  #[test]
+  fn record_roundtrip_works() {
+    let contents = "BOOT:reboot:~:1:100\nLOGIN:root:tty1:1:200\nbad line\nLOGOUT:root:tty1:1:oops\n";
+
+    let records = Record::parse_records(contents);
+
+    assert_eq!(records.len(), 2);
+    assert_eq!(records[1], Record {
+      r#type: RecordType::Login,
+      name: String::from("root"),
+      tty: String::from("tty1"),
+      pid: 1,
+      time: 200,
+    });
+    assert_eq!(Record::serialize_records(&records), "BOOT:reboot:~:1:100\nLOGIN:root:tty1:1:200\n");
+  }
+
+  #[test]
+  fn wtmp_is_appended_and_utmp_tracks_sessions() {
+    let mut kernel = kernel_with_binfs_root();
+
+    record_boot(&mut kernel).unwrap();
+    record_login(&mut kernel, "root").unwrap();
+
+    assert_eq!(read_records(&mut kernel, UTMP_PATH).unwrap().len(), 1);
+
+    record_logout(&mut kernel).unwrap();
+    record_boot(&mut kernel).unwrap();
+
+    let types = read_records(&mut kernel, WTMP_PATH)
+      .unwrap()
+      .into_iter()
+      .map(|record| (record.r#type, record.name))
+      .collect::<Vec<_>>();
+
+    assert_eq!(types, vec![
+      (RecordType::Boot, String::from(BOOT_NAME)),
+      (RecordType::Login, String::from("root")),
+      (RecordType::Logout, String::from("root")),
+      (RecordType::Boot, String::from(BOOT_NAME)),
+    ]);
+    assert!(read_records(&mut kernel, UTMP_PATH).unwrap().is_empty());
+  }
+}
+
+// vim:ts=2 sw=2
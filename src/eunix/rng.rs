@@ -0,0 +1,59 @@
+use std::fmt::Debug;
+use std::sync::{Arc, Mutex};
+
+/// Source of randomness for everything in kernel: /dev/random,
+/// password salts. Owned by `Kernel`, like `Clock`, so that tests
+/// can have the same "random" bytes every run
+pub trait Rng: Debug + Send + Sync {
+  fn fill(&self, bytes: &mut [u8]);
+
+  fn bytes(&self, count: usize) -> Vec<u8> {
+    let mut bytes = vec![0u8; count];
+    self.fill(&mut bytes);
+    bytes
+  }
+}
+
+/// Randomness of host
+#[derive(Debug, Default)]
+pub struct HostRng;
+
+impl Rng for HostRng {
+  fn fill(&self, bytes: &mut [u8]) {
+    getrandom::getrandom(bytes).expect("rng: host has no source of randomness");
+  }
+}
+
+/// Same bytes for the same seed (splitmix64), for reproducible tests.
+/// Not for anything that has to be secret
+#[derive(Debug)]
+pub struct SeededRng {
+  state: Mutex<u64>,
+}
+
+impl SeededRng {
+  pub fn new(seed: u64) -> Self {
+    Self { state: Mutex::new(seed) }
+  }
+}
+
+impl Rng for SeededRng {
+  fn fill(&self, bytes: &mut [u8]) {
+    let mut state = self.state.lock().unwrap();
+    for chunk in bytes.chunks_mut(8) {
+      *state = state.wrapping_add(0x9e3779b97f4a7c15);
+      let mut random = *state;
+      random = (random ^ (random >> 30)).wrapping_mul(0xbf58476d1ce4e5b9);
+      random = (random ^ (random >> 27)).wrapping_mul(0x94d049bb133111eb);
+      random ^= random >> 31;
+      chunk.copy_from_slice(&random.to_le_bytes()[..chunk.len()]);
+    }
+  }
+}
+
+/// Host randomness, for kernels that were not given another one
+pub fn host_rng() -> Arc<dyn Rng> {
+  Arc::new(HostRng)
+}
+
+// vim:ts=2 sw=2
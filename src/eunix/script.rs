@@ -0,0 +1,159 @@
+use rhai::{Engine, Scope, Array, Dynamic, EvalAltResult};
+
+use crate::binaries::{EXIT_FAILURE, kprintln};
+use super::{fs::{Filesystem, AddressSize}, kernel::{Args, Kernel, Errno}};
+
+fn script_error(function: &str, pathname: &str, errno: Errno) -> Box<EvalAltResult> {
+  format!("{function}: {pathname}: {errno}").into()
+}
+
+/// Run rhai script `source` as process with `args`
+///
+/// Script sees `args` array (`args[0]` is arg0) and can call into kernel with:
+/// - `read(pathname) -> string`
+/// - `write(pathname, data)` - creates file if it does not exist
+/// - `exec(pathname, argv) -> int`
+/// - `print(text)` - to stdout of the process
+///
+/// If script evaluates to integer, it is the exit code, otherwise it's 0
+pub fn run(source: &str, args: Args, kernel: &mut Kernel) -> AddressSize {
+  let arg0 = args.get(0).cloned().unwrap_or_default();
+
+  // Registered functions must be 'static, so they can't borrow kernel.
+  // SAFETY: engine lives only inside this function, while `kernel` is not
+  // touched here, so pointer is the only way to kernel until we return
+  let kernel: *mut Kernel = kernel;
+
+  let mut engine = Engine::new();
+  engine.register_fn("read", move |pathname: &str| -> Result<String, Box<EvalAltResult>> {
+    let kernel = unsafe { &mut *kernel };
+    let bytes = kernel
+      .read_file(pathname, AddressSize::MAX)
+      .map_err(|errno| script_error("read", pathname, errno))?;
+
+    String::from_utf8(bytes)
+      .map_err(|_| script_error("read", pathname, Errno::EILSEQ(String::from("not utf-8").into())))
+  });
+  engine.register_fn("write", move |pathname: &str, data: &str| -> Result<(), Box<EvalAltResult>> {
+    let kernel = unsafe { &mut *kernel };
+    let mut vfs = kernel.vfs();
+    match vfs.lookup_path(pathname) {
+      Ok(_) => (),
+      Err(Errno::ENOENT(_)) => {
+        vfs
+          .create_file(pathname)
+          .map_err(|errno| script_error("write", pathname, errno))?;
+      },
+      Err(errno) => return Err(script_error("write", pathname, errno)),
+    }
+    drop(vfs);
+
+    kernel
+      .write_file(pathname, data.as_bytes())
+      .map_err(|errno| script_error("write", pathname, errno))?;
+
+    Ok(())
+  });
+  engine.register_fn("exec", move |pathname: &str, argv: Array| -> Result<i64, Box<EvalAltResult>> {
+    let kernel = unsafe { &mut *kernel };
+    let argv = argv
+      .into_iter()
+      .map(|arg| arg.to_string())
+      .collect::<Vec<_>>();
+    let argv = argv
+      .iter()
+      .map(String::as_str)
+      .collect::<Vec<_>>();
+
+    let exit_code = kernel
+      .run(pathname, &argv)
+      .map_err(|errno| script_error("exec", pathname, errno))?;
+
+    Ok(exit_code as i64)
+  });
+
+  // `print` writes to stdout of the process, not of host
+  engine.on_print(move |text| {
+    let kernel = unsafe { &mut *kernel };
+    kprintln!(kernel, "{text}");
+  });
+
+  let mut scope = Scope::new();
+  scope.push_constant("args", args
+    .into_iter()
+    .map(Dynamic::from)
+    .collect::<Array>()
+  );
+
+  match engine.eval_with_scope::<Dynamic>(&mut scope, source) {
+    Ok(result) => result
+      .as_int()
+      .map(|exit_code| exit_code as AddressSize)
+      .unwrap_or(0),
+    Err(error) => {
+      let kernel = unsafe { &mut *kernel };
+      kprintln!(kernel, "{arg0}: {error}");
+      EXIT_FAILURE
+    },
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::kernel;
+  use crate::{eunix::{kernel::STDOUT_FILENO, fs::{FilesystemType, OpenFlags, OpenMode}}};
+
+  fn two(_: Args, _: &mut Kernel) -> AddressSize {
+    2
+  }
+
+  /// Kernel with binfs as root filesystem
+  fn kernel_with_binfs() -> Kernel {
+    let mut kernel = kernel();
+    kernel.mount("", "/", FilesystemType::binfs).unwrap();
+    kernel.register_binary("/two", two).unwrap();
+
+    kernel
+  }
+
+  #[test]
+  fn script_gets_args_and_returns_exit_code() {
+    let mut kernel = kernel_with_binfs();
+
+    let exit_code = run("args.len()", vec![String::from("argc"), String::from("a")], &mut kernel);
+
+    assert_eq!(exit_code, 2);
+  }
+
+  #[test]
+  fn script_can_exec() {
+    let mut kernel = kernel_with_binfs();
+
+    let source = r#"exec("/two", ["two"]) + 1"#;
+
+    assert_eq!(run(source, vec![String::from("script")], &mut kernel), 3);
+  }
+
+  #[test]
+  fn script_errors_fail() {
+    let mut kernel = kernel_with_binfs();
+
+    assert_eq!(run(r#"read("/missing")"#, vec![String::from("script")], &mut kernel), EXIT_FAILURE);
+    assert_eq!(run("this is not rhai", vec![String::from("script")], &mut kernel), EXIT_FAILURE);
+  }
+
+  #[test]
+  fn script_prints_to_stdout_of_process() {
+    let mut kernel = kernel_with_binfs();
+    let file_descriptor = kernel.open("/out", OpenFlags::new(OpenMode::Write, true, false)).unwrap();
+    kernel.dup2(file_descriptor, STDOUT_FILENO).unwrap();
+
+    assert_eq!(run(r#"print("hello"); read("/missing")"#, vec![String::from("script")], &mut kernel), EXIT_FAILURE);
+
+    let output = String::from_utf8(kernel.vfs().read_file("/out", AddressSize::MAX).unwrap()).unwrap();
+    assert!(output.starts_with("hello\nscript: "), "{output}");
+  }
+}
+
+// vim:ts=2 sw=2
@@ -0,0 +1,79 @@
+use super::fs::AddressSize;
+use super::kernel::Errno;
+
+pub type Signal = u8;
+
+/// Interrupt from terminal, Ctrl-C
+pub const SIGINT: Signal = 2;
+/// Kill, cannot be ignored
+pub const SIGKILL: Signal = 9;
+/// Polite request to terminate
+pub const SIGTERM: Signal = 15;
+/// Child has exited
+pub const SIGCHLD: Signal = 17;
+pub const SIGNALS: [Signal; 4] = [SIGINT, SIGKILL, SIGTERM, SIGCHLD];
+
+/// What happens to process when signal is delivered to it
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Disposition {
+  /// Process exits with `exit_code` of the signal
+  Terminate,
+  /// Signal is dropped
+  Ignore,
+}
+
+/// Disposition every process has for `signal`, as there are no handlers
+pub fn default_disposition(signal: Signal) -> Disposition {
+  match signal {
+    SIGCHLD => Disposition::Ignore,
+    _ => Disposition::Terminate,
+  }
+}
+
+/// Exit code of process terminated by `signal`, like shells show it
+pub fn exit_code(signal: Signal) -> AddressSize {
+  128 + signal as AddressSize
+}
+
+/// Name of `signal` without `SIG`, like `kill -l` prints them
+pub fn signal_name(signal: Signal) -> &'static str {
+  match signal {
+    SIGINT => "INT",
+    SIGKILL => "KILL",
+    SIGTERM => "TERM",
+    SIGCHLD => "CHLD",
+    _ => "UNKNOWN",
+  }
+}
+
+/// Get signal by number or name, with `SIG` or without, in any case
+///
+/// Errors:
+/// EINVAL -> there is no such signal
+pub fn parse_signal(name: &str) -> Result<Signal, Errno> {
+  let upper = name.to_uppercase();
+  let upper = upper.strip_prefix("SIG").unwrap_or(&upper);
+
+  SIGNALS
+    .into_iter()
+    .find(|signal| signal.to_string() == upper || signal_name(*signal) == upper)
+    .ok_or_else(|| Errno::EINVAL(format!("signal: unknown signal: {name}").into()))
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn signals_are_parsed_by_number_and_name() {
+    assert_eq!(parse_signal("9").unwrap(), SIGKILL);
+    assert_eq!(parse_signal("term").unwrap(), SIGTERM);
+    assert_eq!(parse_signal("SIGINT").unwrap(), SIGINT);
+    assert!(matches!(parse_signal("SIGFOO"), Err(Errno::EINVAL(_))));
+    assert!(matches!(parse_signal("0"), Err(Errno::EINVAL(_))));
+    assert_eq!(default_disposition(SIGCHLD), Disposition::Ignore);
+    assert_eq!(exit_code(SIGINT), 130);
+  }
+}
+
+// vim:ts=2 sw=2
@@ -0,0 +1,347 @@
+use std::sync::Arc;
+
+use super::{
+  clock::Clock,
+  fs::{Filesystem, AddressSize, FileModeType, VINode, VDirectory, FileStat, FileMode, DeviceNumber, Id},
+  virtfs::VirtFsFilesystem,
+  devices::{
+    net_device_name, disk_name, tty_device_name, serial_device_name, BlockDevice, HostDisk,
+    NET_MAJOR, SD_MAJOR, SD_MINORS_PER_DISK, TTY_MAJOR, SERIAL_MAJOR, SERIAL_FIRST_MINOR, RAM_MAJOR,
+  },
+  kernel::{Errno, KernelDeviceTable, Times},
+  partitions::SECTOR_SIZE,
+};
+use crate::machine::{NetBackend, VirtualDeviceType};
+
+/// Where network interfaces are listed, like on Linux
+pub const NET_CLASS_PATH: &'static str = "/class/net";
+
+/// Where disks and RAM disks are listed, like on Linux
+pub const BLOCK_CLASS_PATH: &'static str = "/class/block";
+
+/// Where terminals and serial ports are listed, like on Linux
+pub const TTY_CLASS_PATH: &'static str = "/class/tty";
+
+/// Read-only filesystem describing hardware of the machine.
+/// Made once on mount, as hardware doesn't change while running.
+/// Only `mounted` of disks changes, kernel keeps it up to date
+pub struct SystemFilesystem {
+  pub virtfs: VirtFsFilesystem<String>,
+}
+
+/// MAC address of `index`th network interface, in range of QEMU ones
+fn mac_address(index: u16) -> String {
+  format!("52:54:00:12:{:02x}:{:02x}", index >> 8, index & 0xff)
+}
+
+impl SystemFilesystem {
+  /// Attributes of every device in `device_table` go to
+  /// `/class/<class>/<name>/`, `dev` (`major:minor`) for all of them:
+  /// - `block`: disks and RAM disks, `size` in sectors, `ro` (`0`
+  ///   or `1`) and, for disks, `mounted` (mount point or nothing)
+  /// - `tty`: terminals and serial ports
+  /// - `net`: network interfaces, `address` and `backend`
+  pub fn new(device_table: &KernelDeviceTable) -> Result<Self, Errno> {
+    let mut sysfs = Self {
+      virtfs: VirtFsFilesystem::new("sysfs", SystemFilesystem::inodes_count(device_table)),
+    };
+
+    for class_path in [BLOCK_CLASS_PATH, TTY_CLASS_PATH, NET_CLASS_PATH] {
+      sysfs.add_dir(class_path)?;
+    }
+
+    let disks = device_table.devices
+      .iter()
+      .filter(|(device, _)| device.r#type == VirtualDeviceType::BlockDevice);
+    for (index, (device, mounted_pathname)) in (0..).zip(disks) {
+      let name = disk_name(index);
+      let size = HostDisk::new(&device.realpath, true).size();
+
+      sysfs.add_attributes(&format!("{BLOCK_CLASS_PATH}/{name}"), [
+        ("dev", format!("{SD_MAJOR}:{}", index * SD_MINORS_PER_DISK)),
+        ("size", (size / SECTOR_SIZE).to_string()),
+        ("ro", u8::from(device.read_only).to_string()),
+        ("mounted", mounted_pathname.clone().unwrap_or_default()),
+      ])?;
+    }
+
+    for (index, &size) in (0..).zip(&device_table.ram_disks) {
+      sysfs.add_attributes(&format!("{BLOCK_CLASS_PATH}/ram{index}"), [
+        ("dev", format!("{RAM_MAJOR}:{index}")),
+        ("size", (size / SECTOR_SIZE).to_string()),
+        ("ro", String::from("0")),
+      ])?;
+    }
+
+    let ttys = device_table.devices
+      .iter()
+      .filter(|(device, _)| device.r#type == VirtualDeviceType::TTYDevice);
+    for (index, _) in (0..).zip(ttys) {
+      sysfs.add_attributes(&format!("{TTY_CLASS_PATH}/{}", tty_device_name(index)), [
+        ("dev", format!("{TTY_MAJOR}:{}", index + 1)),
+      ])?;
+    }
+
+    let serials = device_table.devices
+      .iter()
+      .filter(|(device, _)| device.r#type == VirtualDeviceType::SerialDevice);
+    for (index, _) in (0..).zip(serials) {
+      sysfs.add_attributes(&format!("{TTY_CLASS_PATH}/{}", serial_device_name(index)), [
+        ("dev", format!("{SERIAL_MAJOR}:{}", SERIAL_FIRST_MINOR + index)),
+      ])?;
+    }
+
+    let backends = device_table.devices
+      .iter()
+      .filter(|(device, _)| device.r#type == VirtualDeviceType::NetDevice)
+      .map(|(device, _)| device.backend.clone().unwrap_or(NetBackend::Loopback));
+    for (index, backend) in (0..).zip(backends) {
+      let name = net_device_name(index);
+      let backend = match backend {
+        NetBackend::Loopback => String::from("loopback"),
+        NetBackend::Udp { bind, peer } => format!("udp {bind} {peer}"),
+      };
+
+      sysfs.add_attributes(&format!("{NET_CLASS_PATH}/{name}"), [
+        ("address", mac_address(index)),
+        ("dev", format!("{NET_MAJOR}:{index}")),
+        ("backend", backend),
+      ])?;
+    }
+
+    Ok(sysfs)
+  }
+
+  /// Count of inodes for devices of `device_table`: root, `/class` and
+  /// directories of classes, then directory and attributes of each one
+  fn inodes_count(device_table: &KernelDeviceTable) -> AddressSize {
+    let devices_inodes_count: AddressSize = device_table.devices
+      .iter()
+      .map(|(device, _)| match device.r#type {
+        VirtualDeviceType::BlockDevice => 1 + 4,
+        VirtualDeviceType::TTYDevice | VirtualDeviceType::SerialDevice => 1 + 1,
+        VirtualDeviceType::NetDevice => 1 + 3,
+      })
+      .sum();
+
+    2 + 3 + devices_inodes_count + device_table.ram_disks.len() as AddressSize * (1 + 3)
+  }
+
+  /// Set `mounted` of disk `name` to `mount_point`, or to nothing
+  ///
+  /// Errors:
+  /// ENOENT -> there is no such disk
+  pub fn set_mounted(&mut self, name: &str, mount_point: Option<&str>) -> Result<(), Errno> {
+    let vinode = self.virtfs.lookup_path(&format!("{BLOCK_CLASS_PATH}/{name}/mounted"))?;
+
+    self.virtfs.write_file_payload(vinode.number, format!("{}\n", mount_point.unwrap_or_default()))?;
+
+    Ok(())
+  }
+
+  /// Add a file with `value` on its own line for every attribute
+  /// of device at `pathname`
+  fn add_attributes<const N: usize>(&mut self, pathname: &str, attributes: [(&str, String); N]) -> Result<(), Errno> {
+    for (attribute, value) in attributes {
+      self.add_file(&format!("{pathname}/{attribute}"), format!("{value}\n"))?;
+    }
+
+    Ok(())
+  }
+
+  /// Add directory at `pathname` with its parents
+  fn add_dir(&mut self, pathname: &str) -> Result<(), Errno> {
+    let mut parent = String::new();
+    for name in pathname.split('/').filter(|name| !name.is_empty()) {
+      parent.push('/');
+      parent.push_str(name);
+
+      match self.virtfs.lookup_path(&parent) {
+        Ok(_) => (),
+        Err(Errno::ENOENT(_)) => {
+          self.virtfs.create_dir(&parent)?;
+        },
+        Err(errno) => return Err(errno),
+      }
+    }
+
+    Ok(())
+  }
+
+  /// Add read-only file at `pathname` with its parents
+  fn add_file(&mut self, pathname: &str, contents: String) -> Result<VINode, Errno> {
+    let parent = &pathname[..pathname.rfind('/').unwrap_or(0)];
+    self.add_dir(parent)?;
+
+    // r--r--r--
+    let vinode = self.virtfs.create_file(pathname)?;
+    self.virtfs.change_mode(pathname, vinode.mode.with_user(0b100).with_group(0b100).with_others(0b100))?;
+
+    self.virtfs.write_file_payload(vinode.number, contents)
+  }
+}
+
+impl Filesystem for SystemFilesystem {
+  fn create_file(&mut self, pathname: &str)
+    -> Result<VINode, Errno> {
+    Err(Errno::EPERM(format!("sysfs: read-only filesystem: {pathname}").into()))
+  }
+
+  fn remove_file(&mut self, pathname: &str)
+    -> Result<(), Errno> {
+    Err(Errno::EPERM(format!("sysfs: read-only filesystem: {pathname}").into()))
+  }
+
+  fn create_dir(&mut self, pathname: &str)
+    -> Result<VINode, Errno> {
+    Err(Errno::EPERM(format!("sysfs: read-only filesystem: {pathname}").into()))
+  }
+
+  fn mknod(&mut self, pathname: &str, _file_type: FileModeType, _device_number: DeviceNumber)
+    -> Result<VINode, Errno> {
+    Err(Errno::EPERM(format!("sysfs: read-only filesystem: {pathname}").into()))
+  }
+
+  fn link(&mut self, _existing: &str, new: &str)
+    -> Result<VINode, Errno> {
+    Err(Errno::EPERM(format!("sysfs: read-only filesystem: {new}").into()))
+  }
+
+  fn symlink(&mut self, _target: &str, pathname: &str)
+    -> Result<VINode, Errno> {
+    Err(Errno::EPERM(format!("sysfs: read-only filesystem: {pathname}").into()))
+  }
+
+  fn readlink(&mut self, pathname: &str)
+    -> Result<String, Errno> {
+    Err(Errno::EINVAL(format!("sysfs: {pathname}: not a symbolic link").into()))
+  }
+
+  fn read_file(&mut self, pathname: &str, count: AddressSize)
+    -> Result<Vec<u8>, Errno> {
+    self.virtfs.read_file(pathname, count)
+  }
+
+  fn write_file(&mut self, pathname: &str, _data: &[u8])
+    -> Result<VINode, Errno> {
+    Err(Errno::EPERM(format!("sysfs: read-only filesystem: {pathname}").into()))
+  }
+
+  fn read_dir(&mut self, pathname: &str)
+    -> Result<VDirectory, Errno> {
+    self.virtfs.read_dir(pathname)
+  }
+
+  fn stat(&mut self, pathname: &str)
+    -> Result<FileStat, Errno> {
+    self.virtfs.stat(pathname)
+  }
+
+  fn change_mode(&mut self, pathname: &str, _mode: FileMode)
+    -> Result<(), Errno> {
+    Err(Errno::EPERM(format!("sysfs: read-only filesystem: {pathname}").into()))
+  }
+
+  fn change_owners(&mut self, pathname: &str, _uid: Id, _gid: Id)
+    -> Result<(), Errno> {
+    Err(Errno::EPERM(format!("sysfs: read-only filesystem: {pathname}").into()))
+  }
+
+  fn change_times(&mut self, pathname: &str, times: Times)
+    -> Result<(), Errno> {
+    self.virtfs.change_times(pathname, times)
+  }
+
+  fn lookup_path(&mut self, pathname: &str)
+    -> Result<VINode, Errno> {
+    self.virtfs.lookup_path(pathname)
+  }
+
+  fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+    self.virtfs.set_clock(clock)
+  }
+
+  fn name(&self) -> String {
+    String::from("sysfs")
+  }
+
+  fn as_any(&mut self) -> &mut dyn std::any::Any {
+    self
+  }
+
+  fn as_any_ref(&self) -> &dyn std::any::Any {
+    self
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::machine::{MachineDevice, MachineDeviceTable};
+  use crate::util::{mktemp, mkenxvd};
+
+  #[test]
+  fn net_devices_are_listed() {
+    let device_table = KernelDeviceTable::from(MachineDeviceTable {
+      devices: vec![
+        MachineDevice { realpath: String::from("/tmp/tty1"), r#type: VirtualDeviceType::TTYDevice, read_only: false, backend: None },
+        MachineDevice { realpath: String::new(), r#type: VirtualDeviceType::NetDevice, read_only: false, backend: Some(NetBackend::Loopback) },
+      ],
+      ram_disks: Vec::new(),
+    });
+    let mut sysfs = SystemFilesystem::new(&device_table).unwrap();
+
+    let names = sysfs.read_dir(NET_CLASS_PATH).unwrap().entries.into_keys().collect::<Vec<_>>();
+
+    assert!(names.contains(&String::from("eth0")));
+    assert!(!names.contains(&String::from("eth1")));
+    assert_eq!(sysfs.read_file("/class/net/eth0/dev", AddressSize::MAX).unwrap(), format!("{NET_MAJOR}:0\n").as_bytes());
+    assert_eq!(sysfs.read_file("/class/net/eth0/backend", AddressSize::MAX).unwrap(), b"loopback\n");
+    assert!(matches!(sysfs.write_file("/class/net/eth0/address", b"00:00:00:00:00:00"), Err(Errno::EPERM(_))));
+  }
+
+  #[test]
+  fn many_net_devices_fit() {
+    let device_table = KernelDeviceTable::from(MachineDeviceTable {
+      devices: (0..100)
+        .map(|_| MachineDevice { realpath: String::new(), r#type: VirtualDeviceType::NetDevice, read_only: false, backend: Some(NetBackend::Loopback) })
+        .collect(),
+      ram_disks: vec![4096; 100],
+    });
+    let mut sysfs = SystemFilesystem::new(&device_table).unwrap();
+
+    assert_eq!(sysfs.read_dir(NET_CLASS_PATH).unwrap().entries.len(), 100);
+    assert_eq!(sysfs.read_file("/class/net/eth99/backend", AddressSize::MAX).unwrap(), b"loopback\n");
+    assert_eq!(sysfs.read_file("/class/block/ram99/size", AddressSize::MAX).unwrap(), b"8\n");
+  }
+
+  #[test]
+  fn block_and_tty_devices_are_listed() {
+    let disk = mktemp();
+    mkenxvd(String::from("1M"), disk.clone());
+    let device_table = KernelDeviceTable {
+      devices: vec![
+        (MachineDevice { realpath: String::from("/tmp/tty1"), r#type: VirtualDeviceType::TTYDevice, read_only: false, backend: None }, None),
+        (MachineDevice { realpath: disk, r#type: VirtualDeviceType::BlockDevice, read_only: true, backend: None }, Some(String::from("/mnt"))),
+        (MachineDevice { realpath: String::from("/tmp/serial0.log"), r#type: VirtualDeviceType::SerialDevice, read_only: false, backend: None }, None),
+      ],
+      ram_disks: vec![4096],
+    };
+    let mut sysfs = SystemFilesystem::new(&device_table).unwrap();
+    let mut read = |pathname: &str| String::from_utf8(sysfs.read_file(pathname, AddressSize::MAX).unwrap()).unwrap();
+
+    assert_eq!(read("/class/block/sda/dev"), format!("{SD_MAJOR}:0\n"));
+    assert_eq!(read("/class/block/sda/size"), "2048\n");
+    assert_eq!(read("/class/block/sda/ro"), "1\n");
+    assert_eq!(read("/class/block/sda/mounted"), "/mnt\n");
+    assert_eq!(read("/class/block/ram0/size"), "8\n");
+    assert_eq!(read("/class/tty/tty1/dev"), format!("{TTY_MAJOR}:1\n"));
+    assert_eq!(read("/class/tty/ttyS0/dev"), format!("{SERIAL_MAJOR}:{SERIAL_FIRST_MINOR}\n"));
+
+    sysfs.set_mounted("sda", None).unwrap();
+    assert_eq!(sysfs.read_file("/class/block/sda/mounted", AddressSize::MAX).unwrap(), b"\n");
+    assert!(matches!(sysfs.set_mounted("sdb", None), Err(Errno::ENOENT(_))));
+  }
+}
+
+// vim:ts=2 sw=2
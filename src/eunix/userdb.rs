@@ -0,0 +1,117 @@
+use core::fmt::Debug;
+
+use crate::binaries::{PASSWD_PATH, GROUP_PATH};
+use super::{
+  fs::{Filesystem, Id, VFS, EVERYTHING},
+  kernel::Errno,
+  users::{Passwd, Group},
+};
+
+/// Source of users and groups. Lookups get `vfs` to read from,
+/// so that backends can keep their database on any filesystem
+pub trait UserDb: Debug + Send + Sync {
+  /// All users
+  fn passwds(&mut self, vfs: &mut VFS) -> Result<Vec<Passwd>, Errno>;
+  /// All groups
+  fn groups(&mut self, vfs: &mut VFS) -> Result<Vec<Group>, Errno>;
+
+  fn user_by_name(&mut self, vfs: &mut VFS, name: &str) -> Result<Option<Passwd>, Errno> {
+    Ok(self.passwds(vfs)?.into_iter().find(|passwd| passwd.name == name))
+  }
+
+  fn user_by_uid(&mut self, vfs: &mut VFS, uid: Id) -> Result<Option<Passwd>, Errno> {
+    Ok(self.passwds(vfs)?.into_iter().find(|passwd| passwd.uid == uid))
+  }
+
+  fn group_by_gid(&mut self, vfs: &mut VFS, gid: Id) -> Result<Option<Group>, Errno> {
+    Ok(self.groups(vfs)?.into_iter().find(|group| group.gid == gid))
+  }
+}
+
+/// Users and groups from /etc/passwd and /etc/group.
+/// Missing group file has no groups, missing passwd file is an error
+#[derive(Debug)]
+pub struct FileUserDb {
+  pub passwd_path: String,
+  pub group_path: String,
+}
+
+impl Default for FileUserDb {
+  fn default() -> Self {
+    Self {
+      passwd_path: String::from(PASSWD_PATH),
+      group_path: String::from(GROUP_PATH),
+    }
+  }
+}
+
+impl FileUserDb {
+  fn read_to_string(vfs: &mut VFS, pathname: &str) -> Result<String, Errno> {
+    let bytes = vfs.read_file(pathname, EVERYTHING)?;
+
+    String::from_utf8(bytes).or(Err(Errno::EILSEQ(format!("userdb: invalid bytes in {pathname}").into())))
+  }
+}
+
+impl UserDb for FileUserDb {
+  fn passwds(&mut self, vfs: &mut VFS) -> Result<Vec<Passwd>, Errno> {
+    Ok(Passwd::parse_passwds(&Self::read_to_string(vfs, &self.passwd_path)?))
+  }
+
+  fn groups(&mut self, vfs: &mut VFS) -> Result<Vec<Group>, Errno> {
+    match Self::read_to_string(vfs, &self.group_path) {
+      Ok(contents) => Ok(Group::parse_groups(&contents)),
+      Err(Errno::ENOENT(_)) => Ok(Vec::new()),
+      Err(errno) => Err(errno),
+    }
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use std::collections::BTreeMap;
+
+  use super::*;
+  use crate::eunix::{binfs::BinFilesytem, fs::{MountedFilesystem, MountFlags, FilesystemType}, kernel::{ROOT_UID, ROOT_GID}, watch::Watches};
+
+  fn vfs_with_files(files: &[(&str, &str)]) -> VFS {
+    let mut binfs = BinFilesytem::new();
+    for (pathname, contents) in files {
+      binfs.create_file(pathname).unwrap();
+      binfs.write_file(pathname, contents.as_bytes()).unwrap();
+    }
+
+    VFS {
+      mount_points: BTreeMap::from([(String::from("/"), MountedFilesystem {
+        r#type: FilesystemType::binfs,
+        driver: Box::new(binfs),
+        flags: MountFlags::default(),
+        source: String::new(),
+      })]),
+      bind_mounts: BTreeMap::new(),
+      open_files: BTreeMap::new(),
+      current_uid: ROOT_UID,
+      current_gid: ROOT_GID,
+      current_sgids: vec![ROOT_GID],
+      current_tty: None,
+      current_dir: String::from("/"),
+      watches: Watches::default(),
+    }
+  }
+
+  #[test]
+  fn file_user_db_works() {
+    let mut vfs = vfs_with_files(&[
+      (PASSWD_PATH, "root:x:0:0::/root:\nuser:x:1000:100::/home/user:"),
+    ]);
+    let mut user_db = FileUserDb::default();
+
+    assert_eq!(user_db.user_by_name(&mut vfs, "user").unwrap().map(|passwd| passwd.uid), Some(1000));
+    assert_eq!(user_db.user_by_uid(&mut vfs, 0).unwrap().map(|passwd| passwd.name), Some(String::from("root")));
+    assert!(user_db.user_by_name(&mut vfs, "nobody").unwrap().is_none());
+    // There is no /etc/group
+    assert!(user_db.groups(&mut vfs).unwrap().is_empty());
+  }
+}
+
+// vim:ts=2 sw=2
@@ -4,7 +4,8 @@ use itertools::Itertools;
 use super::fs::Id;
 
 #[derive(Debug)]
-/// Serialized format: `name:password:uid:gid:comment:home:shell`
+/// Serialized format: `name:password:uid:gid:comment:home:shell`,
+/// `password` is `x` if the hash is in /etc/shadow
 pub struct Passwd {
   pub name: String,
   pub password: String,
@@ -20,6 +21,7 @@ pub enum ParseError {
   InvalidUid,
   InvalidGid,
   InvalidUserList,
+  InvalidDays,
 }
 
 impl Passwd {
@@ -32,7 +34,8 @@ impl Passwd {
         if !Regex::new("^.*:.*:.*:.*:.*:.*:.*$")
           .unwrap()
           .is_match(line)
-          .unwrap() 
+          // Line too long to match is bad too
+          .unwrap_or(false)
         {
           return Err(ParseError::BadLine);
         }
@@ -85,6 +88,114 @@ impl Passwd {
 }
 
 
+/// Prefix of password hash that makes it match no password
+pub const LOCKED_PREFIX: char = '!';
+
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AccountStatus {
+  Active,
+  /// Password hash is prefixed with `!`
+  Locked,
+  /// Expiration date has passed
+  Expired,
+}
+
+#[derive(Debug)]
+/// Serialized format: `name:password:last_change:expire`,
+/// where `last_change` and `expire` are days since unix epoch,
+/// empty `expire` means that account never expires.
+/// `password` prefixed with `!` is locked
+pub struct Shadow {
+  pub name: String,
+  pub password: String,
+  pub last_change: u64,
+  pub expire: Option<u64>,
+}
+
+impl Shadow {
+  /// Parse `name:password:last_change:expire`
+  /// lines - invalid ones omitted
+  pub fn parse_shadows(string: &str) -> Vec<Shadow> {
+    string
+      .lines()
+      .flat_map(|line| {
+        if !Regex::new("^.*:.*:.*:.*$").unwrap().is_match(line).unwrap_or(false) {
+          return Err(ParseError::BadLine);
+        }
+
+        let mut split = line.split(":");
+
+        let name = split.next().unwrap_or("").to_owned();
+        let password = split.next().unwrap_or("").to_owned();
+        let last_change = split
+          .next()
+          .unwrap_or("")
+          .parse::<u64>()
+          .or(Err(ParseError::InvalidDays))?;
+        let expire = match split.next().unwrap_or("") {
+          "" => None,
+          expire => Some(expire.parse::<u64>().or(Err(ParseError::InvalidDays))?),
+        };
+
+        Ok(Shadow {
+          name,
+          password,
+          last_change,
+          expire,
+        })
+      })
+      .collect()
+  }
+
+  /// Whether account is expired on `day` (days since unix epoch)
+  pub fn is_expired(&self, day: u64) -> bool {
+    self.expire.map_or(false, |expire| day >= expire)
+  }
+
+  pub fn is_locked(&self) -> bool {
+    self.password.starts_with(LOCKED_PREFIX)
+  }
+
+  /// Lock password, keeping the hash so that it can be unlocked
+  pub fn lock(&mut self) {
+    if !self.is_locked() {
+      self.password.insert(0, LOCKED_PREFIX);
+    }
+  }
+
+  pub fn unlock(&mut self) {
+    if let Some(password) = self.password.strip_prefix(LOCKED_PREFIX) {
+      self.password = password.to_owned();
+    }
+  }
+
+  /// Status of account on `day` (days since unix epoch)
+  pub fn status(&self, day: u64) -> AccountStatus {
+    if self.is_locked() {
+      AccountStatus::Locked
+    } else if self.is_expired(day) {
+      AccountStatus::Expired
+    } else {
+      AccountStatus::Active
+    }
+  }
+
+  pub fn to_string(&self) -> String {
+    let Shadow { name, password, last_change, expire } = self;
+
+    let expire = expire.map(|expire| expire.to_string()).unwrap_or_default();
+
+    format!("{name}:{password}:{last_change}:{expire}")
+  }
+
+  pub fn serialize_shadows(shadows: &[Shadow]) -> String {
+    shadows
+      .into_iter()
+      .map(Self::to_string)
+      .join("\n")
+  }
+}
+
 #[derive(Debug)]
 /// Serialized format: `name:gid:user1,user2,user3`
 pub struct Group {
@@ -94,13 +205,13 @@ pub struct Group {
 }
 
 impl Group {
-  /// Parse `name:password:uid:gid:comment:home:shell`
+  /// Parse `name:gid:user1,user2,user3`
   /// lines - invalid ones omitted
   pub fn parse_groups(string: &str) -> Vec<Group> {
     string
       .lines()
       .flat_map(|line| {
-        if !Regex::new("^.*:.*:.*:.*$").unwrap().is_match(line).unwrap() {
+        if !Regex::new("^.*:.*:.*$").unwrap().is_match(line).unwrap_or(false) {
           return Err(ParseError::BadLine);
         }
 
@@ -122,6 +233,7 @@ impl Group {
           .unwrap_or("")
           .to_owned()
           .split(",")
+          .filter(|user| !user.is_empty())
           .map(ToOwned::to_owned)
           .collect();
 
@@ -150,4 +262,56 @@ impl Group {
   }
 }
 
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn shadow_roundtrip_works() {
+    let contents = "root:abcd:19000:\nuser:ef01:19100:19200\nbad line";
+
+    let shadows = Shadow::parse_shadows(contents);
+
+    assert_eq!(shadows.len(), 2);
+    assert_eq!(shadows[0].expire, None);
+    assert!(shadows[1].is_expired(19200));
+    assert!(!shadows[1].is_expired(19199));
+    assert_eq!(Shadow::serialize_shadows(&shadows), "root:abcd:19000:\nuser:ef01:19100:19200");
+  }
+
+  #[test]
+  fn shadow_lock_and_expiry_work() {
+    let mut shadow = Shadow::parse_shadows("user:ef01:19100:19200").remove(0);
+    assert_eq!(shadow.status(19199), AccountStatus::Active);
+    assert_eq!(shadow.status(19200), AccountStatus::Expired);
+
+    shadow.lock();
+    shadow.lock();
+    assert_eq!(shadow.password, "!ef01");
+    assert_eq!(shadow.status(19199), AccountStatus::Locked);
+
+    shadow.unlock();
+    assert_eq!(shadow.password, "ef01");
+    assert_eq!(shadow.status(19199), AccountStatus::Active);
+  }
+
+  #[test]
+  fn parse_groups_works() {
+    let groups = Group::parse_groups("wheel:10:root,user\nnobody:65534:\nbad line");
+
+    assert_eq!(groups.len(), 2);
+    assert_eq!((groups[0].gid, groups[0].user_list.clone()), (10, vec![String::from("root"), String::from("user")]));
+    assert!(groups[1].user_list.is_empty());
+  }
+
+  proptest::proptest! {
+    #[test]
+    fn any_text_is_parsed_without_panic(string in "([a-z0-9:,!]{0,12}\\n?){0,16}|\\PC*") {
+      Passwd::parse_passwds(&string);
+      Group::parse_groups(&string);
+      Shadow::parse_shadows(&string);
+    }
+  }
+}
+
 // vim:ts=2 sw=2
@@ -0,0 +1,268 @@
+use super::fs::{Filesystem, FileModeType, Id, VFS};
+use super::kernel::{Errno, Times, UnixtimeSize};
+
+/// Archives are made of blocks of this size: header, then contents
+/// of file padded to the block size
+pub const BLOCK_SIZE: usize = 512;
+
+const NAME: (usize, usize) = (0, 100);
+const MODE: (usize, usize) = (100, 108);
+const UID: (usize, usize) = (108, 116);
+const GID: (usize, usize) = (116, 124);
+const SIZE: (usize, usize) = (124, 136);
+const MTIME: (usize, usize) = (136, 148);
+const CHECKSUM: (usize, usize) = (148, 156);
+const TYPEFLAG: usize = 156;
+const MAGIC: (usize, usize) = (257, 262);
+const PREFIX: (usize, usize) = (345, 500);
+
+/// What an entry of archive is
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum EntryKind {
+  File(Vec<u8>),
+  Dir,
+}
+
+/// File or directory from archive, with attributes as they were
+/// when it was archived
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct Entry {
+  /// Absolute pathname, `/` for the root of archive
+  pub pathname: String,
+  /// Permission bits, like `0o755`
+  pub permissions: u16,
+  pub uid: Id,
+  pub gid: Id,
+  pub mtime: UnixtimeSize,
+  pub kind: EntryKind,
+}
+
+/// Returns: number in octal field `range` of `header`,
+/// which is padded with spaces or NULs
+fn octal_field(header: &[u8], range: (usize, usize), name: &str) -> Result<u64, Errno> {
+  let field = String::from_utf8_lossy(&header[range.0..range.1]);
+  let digits = field.trim_matches(|char: char| char == '\0' || char == ' ');
+  if digits.is_empty() {
+    return Ok(0);
+  }
+
+  u64::from_str_radix(digits, 8)
+    .or(Err(Errno::EINVAL(format!("ustar: invalid {name} '{digits}'").into())))
+}
+
+/// Returns: NUL-terminated string in field `range` of `header`
+fn string_field(header: &[u8], range: (usize, usize)) -> String {
+  let field = &header[range.0..range.1];
+  let length = field.iter().position(|&byte| byte == 0).unwrap_or(field.len());
+
+  String::from_utf8_lossy(&field[..length]).into_owned()
+}
+
+/// Returns: `pathname` as absolute path without `.` components
+/// and trailing slash, like `./etc/` -> `/etc`
+fn normalize_pathname(pathname: &str) -> String {
+  let components = pathname
+    .split('/')
+    .filter(|component| !component.is_empty() && *component != ".")
+    .collect::<Vec<_>>();
+
+  format!("/{}", components.join("/"))
+}
+
+/// Parse ustar (POSIX.1-1988) archive, GNU and pax ones included.
+/// Pax extended headers are skipped, so their attributes are lost
+/// Returns:
+/// EINVAL -> if archive is truncated, header is corrupted,
+///           or there is an entry that is not a file or directory
+pub fn parse(archive: &[u8]) -> Result<Vec<Entry>, Errno> {
+  let mut entries = Vec::new();
+  let mut offset = 0;
+
+  while offset + BLOCK_SIZE <= archive.len() {
+    let header = &archive[offset..offset + BLOCK_SIZE];
+    // End of archive is marked by zero blocks
+    if header.iter().all(|&byte| byte == 0) {
+      return Ok(entries);
+    }
+
+    if !string_field(header, MAGIC).starts_with("ustar") {
+      return Err(Errno::EINVAL(format!("ustar: no ustar header at offset {offset}").into()));
+    }
+    // Checksum is taken with checksum field itself as spaces
+    let checksum = header
+      .iter()
+      .enumerate()
+      .map(|(index, &byte)| match (CHECKSUM.0..CHECKSUM.1).contains(&index) {
+        true => b' ' as u64,
+        false => byte as u64,
+      })
+      .sum::<u64>();
+    if checksum != octal_field(header, CHECKSUM, "checksum")? {
+      return Err(Errno::EINVAL(format!("ustar: wrong checksum of header at offset {offset}").into()));
+    }
+
+    let size = octal_field(header, SIZE, "size")? as usize;
+    let contents_offset = offset + BLOCK_SIZE;
+    if contents_offset + size > archive.len() {
+      return Err(Errno::EINVAL(format!("ustar: archive is truncated at offset {offset}").into()));
+    }
+    let contents = &archive[contents_offset..contents_offset + size];
+    offset = contents_offset + (size + BLOCK_SIZE - 1) / BLOCK_SIZE * BLOCK_SIZE;
+
+    let prefix = string_field(header, PREFIX);
+    let name = string_field(header, NAME);
+    let pathname = normalize_pathname(&format!("{prefix}/{name}"));
+    let kind = match header[TYPEFLAG] {
+      b'0' | b'\0' | b'7' => EntryKind::File(contents.to_vec()),
+      b'5' => EntryKind::Dir,
+      // Pax extended headers
+      b'x' | b'g' => continue,
+      typeflag => {
+        return Err(Errno::EINVAL(format!("ustar: {pathname}: unsupported entry type '{}'", typeflag as char).into()));
+      },
+    };
+
+    entries.push(Entry {
+      pathname,
+      permissions: (octal_field(header, MODE, "mode")? & 0o777) as u16,
+      uid: octal_field(header, UID, "uid")? as Id,
+      gid: octal_field(header, GID, "gid")? as Id,
+      mtime: octal_field(header, MTIME, "mtime")?,
+      kind,
+    });
+  }
+
+  Err(Errno::EINVAL(String::from("ustar: archive has no end").into()))
+}
+
+/// Create every entry of `entries` in `fs`, replacing files that
+/// are there already. Missing parents are made as directories,
+/// modes, owners and times of entries are preserved
+pub fn unpack(fs: &mut dyn Filesystem, entries: &[Entry]) -> Result<(), Errno> {
+  for entry in entries {
+    let mut pathname = String::new();
+    // Parents, then entry itself. Root is always there
+    let (ancestors, _) = match entry.pathname.as_str() {
+      "/" => (Vec::new(), String::new()),
+      entry_pathname => VFS::split_path(entry_pathname)?,
+    };
+    for name in ancestors.iter().filter(|name| !name.is_empty()) {
+      pathname = format!("{pathname}/{name}");
+      match fs.lookup_path(&pathname) {
+        Ok(_) => (),
+        Err(Errno::ENOENT(_)) => {
+          fs.create_dir(&pathname)?;
+          let vinode = fs.lookup_path(&pathname)?;
+          fs.change_mode(&pathname, vinode.mode.with_user(0o7).with_group(0o5).with_others(0o5))?;
+          fs.change_owners(&pathname, 0, 0)?;
+        },
+        Err(errno) => return Err(errno),
+      }
+    }
+
+    match (&entry.kind, fs.lookup_path(&entry.pathname)) {
+      (EntryKind::Dir, Ok(vinode)) if vinode.mode.file_type() == FileModeType::Dir as u8 => (),
+      (EntryKind::File(_), Ok(vinode)) if vinode.mode.file_type() == FileModeType::Dir as u8 => {
+        return Err(Errno::EISDIR(format!("ustar: {}: is a directory", entry.pathname).into()));
+      },
+      (EntryKind::Dir, Ok(_)) => {
+        return Err(Errno::ENOTDIR(format!("ustar: {}: is not a directory", entry.pathname).into()));
+      },
+      (_, Ok(_)) => (),
+      (EntryKind::Dir, Err(Errno::ENOENT(_))) => {
+        fs.create_dir(&entry.pathname)?;
+      },
+      (EntryKind::File(_), Err(Errno::ENOENT(_))) => {
+        fs.create_file(&entry.pathname)?;
+      },
+      (_, Err(errno)) => return Err(errno),
+    }
+    if let EntryKind::File(contents) = &entry.kind {
+      fs.write_file(&entry.pathname, contents)?;
+    }
+
+    let mode = fs.lookup_path(&entry.pathname)?.mode;
+    fs.change_mode(&entry.pathname, mode
+      .with_user((entry.permissions >> 6 & 0o7) as u8)
+      .with_group((entry.permissions >> 3 & 0o7) as u8)
+      .with_others((entry.permissions & 0o7) as u8))?;
+    fs.change_owners(&entry.pathname, entry.uid, entry.gid)?;
+  }
+
+  // Times go last, as creating children changes times of directories
+  for entry in entries {
+    fs.change_times(&entry.pathname, Times {
+      atime: entry.mtime,
+      mtime: entry.mtime,
+      ctime: entry.mtime,
+      btime: entry.mtime,
+    })?;
+  }
+
+  Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::eunix::{binfs::BinFilesytem, fs::EVERYTHING};
+
+  /// Header of ustar entry, as made by `tar --format=ustar`
+  fn header(name: &str, typeflag: u8, mode: u16, size: usize) -> Vec<u8> {
+    let mut header = vec![0u8; BLOCK_SIZE];
+    let mut set = |range: (usize, usize), value: &[u8]| header[range.0..range.0 + value.len()].copy_from_slice(value);
+    set(NAME, name.as_bytes());
+    set(MODE, format!("{mode:07o}\0").as_bytes());
+    set(UID, b"0000001\0");
+    set(GID, b"0000002\0");
+    set(SIZE, format!("{size:011o}\0").as_bytes());
+    set(MTIME, b"00000001750\0");
+    set(MAGIC, b"ustar");
+    header[TYPEFLAG] = typeflag;
+    header[CHECKSUM.0..CHECKSUM.1].copy_from_slice(b"        ");
+    let checksum = header.iter().map(|&byte| byte as u64).sum::<u64>();
+    header[CHECKSUM.0..CHECKSUM.1].copy_from_slice(format!("{checksum:06o}\0 ").as_bytes());
+
+    header
+  }
+
+  fn archive() -> Vec<u8> {
+    let mut archive = header("./etc/", b'5', 0o755, 0);
+    archive.extend(header("./etc/motd", b'0', 0o640, 6));
+    archive.extend(b"hello\n");
+    archive.resize(archive.len() + BLOCK_SIZE - 6, 0);
+    archive.resize(archive.len() + 2 * BLOCK_SIZE, 0);
+
+    archive
+  }
+
+  #[test]
+  fn archive_is_parsed() {
+    let entries = parse(&archive()).unwrap();
+
+    assert_eq!(entries, vec![
+      Entry { pathname: String::from("/etc"), permissions: 0o755, uid: 1, gid: 2, mtime: 1000, kind: EntryKind::Dir },
+      Entry { pathname: String::from("/etc/motd"), permissions: 0o640, uid: 1, gid: 2, mtime: 1000, kind: EntryKind::File(b"hello\n".to_vec()) },
+    ]);
+
+    let mut corrupted = archive();
+    corrupted[NAME.0] = b'X';
+    assert!(matches!(parse(&corrupted), Err(Errno::EINVAL(_))));
+    assert!(matches!(parse(&archive()[..BLOCK_SIZE + 10]), Err(Errno::EINVAL(_))));
+  }
+
+  #[test]
+  fn archive_is_unpacked_with_attributes() {
+    let mut binfs = BinFilesytem::new();
+
+    unpack(&mut binfs, &parse(&archive()).unwrap()).unwrap();
+
+    assert_eq!(binfs.read_file("/etc/motd", EVERYTHING).unwrap(), b"hello\n");
+    let stat = binfs.stat("/etc/motd").unwrap();
+    assert_eq!((stat.mode.user(), stat.mode.group(), stat.mode.others()), (0o6, 0o4, 0o0));
+    assert_eq!((stat.uid, stat.gid, stat.mtime), (1, 2, 1000));
+    assert_eq!(binfs.stat("/etc").unwrap().mtime, 1000);
+  }
+}
+
+// vim:ts=2 sw=2
@@ -6,34 +6,47 @@ use std::io::prelude::*;
 use std::io::SeekFrom;
 use std::io::Write;
 use std::path::Display;
+use std::sync::Arc;
 use std::slice::SliceIndex;
 
 // use fancy_regex::Regex;
+use serde::{Serialize, Deserialize};
+use serde::de::DeserializeOwned;
 
 use crate::eunix::fs::FileModeType;
 use crate::eunix::fs::NOBODY_UID;
 use crate::eunix::kernel::KERNEL_MESSAGE_HEADER_ERR;
 // use crate::util::fixedpoint;
-// use crate::util::unixtime;
 
+use super::clock::{Clock, host_clock};
 use super::fs::AddressSize;
+use super::fs::DeviceNumber;
 use super::fs::FileMode;
 use super::fs::FileStat;
+use super::fs::FilesystemStat;
 use super::fs::Filesystem;
 use super::fs::Id;
 use super::fs::NOBODY_GID;
 use super::fs::NO_ADDRESS;
+use super::fs::{EVERYTHING, splice_at};
 use super::fs::VDirectory;
 use super::fs::VDirectoryEntry;
 use super::fs::VINode;
 use super::fs::VFS;
 use super::kernel::Errno;
+use super::kernel::Kernel;
 use super::kernel::ROOT_GID;
 use super::kernel::ROOT_UID;
 use super::kernel::Times;
 use super::kernel::UnixtimeSize;
 
-pub trait VirtFsFile = Clone + Default + fmt::Display;
+pub trait VirtFsFile = Clone + Default + fmt::Display + Send + Sync + 'static;
+
+/// Parses bytes written to a file into its payload
+pub type VirtFsWriter<T> = fn(&[u8]) -> Result<T, Errno>;
+
+/// Synthesizes contents of a file from kernel state
+pub type Generator = fn(&Kernel) -> String;
 
 const ROOT_INODE_NUMBER: AddressSize = 0;
 
@@ -45,7 +58,7 @@ const ROOT_INODE_NUMBER: AddressSize = 0;
  * fbl_chunk - vector of numbers parsed from fbl block
  * */
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct DirectoryEntry {
   pub inode_number: AddressSize,
   pub name: String,
@@ -60,7 +73,7 @@ impl DirectoryEntry {
   }
 }
 
-#[derive(Debug, Clone, PartialEq, Eq)]
+#[derive(Debug, Clone, PartialEq, Eq, Serialize, Deserialize)]
 pub struct Directory {
   pub entries: BTreeMap<String, DirectoryEntry>,
 }
@@ -72,12 +85,6 @@ impl Directory {
     }
   }
 
-  fn from(entries: BTreeMap<String, DirectoryEntry>) -> Self {
-    Self {
-      entries,
-    }
-  }
-
   // pub fn entries_count(&self) -> AddressSize {
   //   self.entries_count
   // }
@@ -93,7 +100,7 @@ impl Directory {
     Ok(())
   }
   pub fn remove(&mut self, name: &str) -> Result<(), Errno> {
-    self.entries.remove(name).ok_or(Errno::ENOENT(String::from("no such file in directory")))?;
+    self.entries.remove(name).ok_or(Errno::ENOENT(String::from("no such file in directory").into()))?;
     Ok(())
   }
 }
@@ -102,6 +109,8 @@ impl Directory {
 pub enum Payload<T: VirtFsFile> {
   Directory(Directory),
   File(T),
+  /// File without stored contents, see `procfs::generate`
+  Generator(Generator),
 }
 
 impl<T: VirtFsFile> fmt::Display for Payload<T> {
@@ -109,6 +118,8 @@ impl<T: VirtFsFile> fmt::Display for Payload<T> {
     match self {
       Payload::Directory(dir) => write!(formatter, "{:?}", dir),
       Payload::File(file) => write!(formatter, "{}", file),
+      // Contents exist only while being read
+      Payload::Generator(_) => Ok(()),
     }
   }
 }
@@ -119,7 +130,7 @@ impl<T: VirtFsFile> Default for Payload<T> {
   }
 }
 
-#[derive(Debug, Clone)]
+#[derive(Debug, Clone, Serialize, Deserialize)]
 pub struct INode {
   mode: FileMode,
   links_count: AddressSize,
@@ -170,6 +181,7 @@ impl From<INode> for VINode {
       ctime: inode.ctime,
       mtime: inode.mtime,
       btime: inode.btime,
+      rdev: DeviceNumber::default(),
       number: inode.number,
     }
   }
@@ -192,6 +204,23 @@ impl From<Directory> for VDirectory {
   }
 }
 
+/// Payload as stored in snapshots, contents of files are `None`
+/// for filesystems that opt out of payload serialization
+#[derive(Debug, Serialize, Deserialize)]
+enum SnapshotPayload<F> {
+  Directory(Directory),
+  File(Option<F>),
+}
+
+/// Serializable state of virtfs, see `VirtFsFilesystem::serialize`
+#[derive(Debug, Serialize, Deserialize)]
+struct Snapshot<F> {
+  name: String,
+  inodes: Vec<INode>,
+  payloads: Vec<Option<SnapshotPayload<F>>>,
+  max_inodes_count: Option<AddressSize>,
+}
+
 // 16 + 4 + 4 + 4 + 4 + 4 + 4 + 4 + (4 * 16) + (4 * 16)
 // 16 + 8 + 8 + 8 + 8 + 8 + 8 + 8 + (8 * 16) + (8 * 16)
 #[derive(Default, Debug)]
@@ -220,6 +249,12 @@ pub struct VirtFsFilesystem<T: VirtFsFile> {
   pub name: String,
   pub inodes: Vec<INode>,
   pub payloads: Vec<Option<Payload<T>>>,
+  /// Parser of written bytes, files are read-only without one
+  pub writer: Option<VirtFsWriter<T>>,
+  /// Max number of inodes (and payloads) the tables can grow to,
+  /// `None` means unlimited
+  pub max_inodes_count: Option<AddressSize>,
+  pub clock: Arc<dyn Clock>,
 }
 
 impl<T: VirtFsFile> VirtFsFilesystem<T> {
@@ -228,15 +263,17 @@ impl<T: VirtFsFile> VirtFsFilesystem<T> {
   fn allocate_file(&mut self) -> Result<AddressSize, Errno> {
     let inode_number = self.claim_free_inode()?;
 
+    let now = self.clock.now();
     let mut inode = INode {
       mode: FileMode::default().with_free(0),
       links_count: 0,
       file_size: 0,
       uid: ROOT_UID,
       gid: ROOT_GID,
-      atime: 0,
-      mtime: 0,
-      ctime: 0,
+      atime: now,
+      mtime: now,
+      ctime: now,
+      btime: now,
       number: inode_number,
       ..Default::default()
     };
@@ -250,34 +287,50 @@ impl<T: VirtFsFile> VirtFsFilesystem<T> {
     Ok(inode_number)
   }
 
+  /// Whether table of `len` entries can grow by one more
+  fn can_grow(&self, len: usize) -> bool {
+    self.max_inodes_count
+      .map_or(true, |max_inodes_count| len < max_inodes_count as usize)
+  }
+
+  /// Returns:
+  /// ENOSPC -> if no free inode exists and table can't grow
   fn claim_free_inode(&mut self) -> Result<AddressSize, Errno> {
-    if let Some(inode_number) = self
+    let inode_number = match self
       .inodes
       .iter()
       .position(|inode| inode.mode.free() == 1)
     {
-      let inode = self
-        .inodes
-        .get_mut(inode_number)
-        .expect("virtfs: we know that inode_number exists");
-      inode.mode = inode.mode.with_free(0);
-      Ok(inode_number as AddressSize)
-    } else {
-      Err(Errno::ENOSPC(String::from("virtfs: no free inodes left")))
-    }
+      Some(inode_number) => inode_number,
+      None if self.can_grow(self.inodes.len()) => {
+        self.inodes.push(Default::default());
+        self.inodes.len() - 1
+      },
+      None => return Err(Errno::ENOSPC(String::from("virtfs: no free inodes left").into())),
+    };
+
+    let inode = self
+      .inodes
+      .get_mut(inode_number)
+      .expect("virtfs: we know that inode_number exists");
+    inode.mode = inode.mode.with_free(0);
+    Ok(inode_number as AddressSize)
   }
 
-  fn claim_free_payload(&self) -> Result<AddressSize, Errno> {
+  /// Returns:
+  /// ENOSPC -> if no free payload exists and table can't grow
+  fn claim_free_payload(&mut self) -> Result<AddressSize, Errno> {
     if let Some(payload_number) = self
       .payloads
       .iter()
       .position(Option::is_none)
     {
-        Ok(payload_number as AddressSize)
+      Ok(payload_number as AddressSize)
+    } else if self.can_grow(self.payloads.len()) {
+      self.payloads.push(None);
+      Ok(self.payloads.len() as AddressSize - 1)
     } else {
-      // self.payloads.push(None);
-      // Ok(self.payloads.len() as AddressSize - 1)
-      Err(Errno::ENOSPC(String::from("virtfs: no free blocks (payloads) left")))
+      Err(Errno::ENOSPC(String::from("virtfs: no free blocks (payloads) left").into()))
     }
   }
 
@@ -286,21 +339,75 @@ impl<T: VirtFsFile> VirtFsFilesystem<T> {
       .inodes
       .get_mut(free_inode_number as usize)
       .ok_or(
-        Errno::EIO(String::from("virtfs: write_inode: no such inode"))
+        Errno::EIO(String::from("virtfs: write_inode: no such inode").into())
       )? = inode.clone();
 
     Ok(())
   }
-  pub fn write_payload(&mut self, payload: &Payload<T>, free_payload_number: u32) -> Result<(), Errno> {
+  /// Write `payload` of file with inode `inode_number`
+  pub fn write_payload(&mut self, payload: &Payload<T>, inode_number: AddressSize) -> Result<(), Errno> {
+    let payload_number = self.read_inode(inode_number)?.payload_number;
+
     *self
       .payloads
-      .get_mut(free_payload_number as usize)
+      .get_mut(payload_number as usize)
       .ok_or(
-        Errno::EIO(String::from("virtfs: write_payload: no such inode"))
+        Errno::EIO(format!("virtfs: write_payload: no payload for inode #{inode_number} (payload_number was #{payload_number})").into())
       )? = Some(payload.clone());
 
     Ok(())
   }
+
+  /// Replace contents of file with inode `inode_number` by `file`.
+  /// Size becomes the length of what `read_file` returns for it
+  pub fn write_file_payload(&mut self, inode_number: AddressSize, file: T) -> Result<VINode, Errno> {
+    let file_size = format!("{file}").len() as AddressSize;
+    self.write_payload(&Payload::File(file), inode_number)?;
+
+    let mut inode = self.read_inode(inode_number)?;
+    inode.file_size = file_size;
+    inode.mtime = self.clock.now();
+    inode.ctime = self.clock.now();
+    self.write_inode(&inode, inode_number)?;
+
+    Ok(inode.into())
+  }
+
+  /// Create read-only file at `pathname`, which contents are
+  /// made by `generator` each time it's read
+  pub fn create_generated_file(&mut self, pathname: &str, generator: Generator) -> Result<VINode, Errno> {
+    let vinode = self.create_file(pathname)?;
+    self.write_payload(&Payload::Generator(generator), vinode.number)?;
+
+    // r--r--r--
+    let mut inode = self.read_inode(vinode.number)?;
+    inode.mode = inode.mode.with_user(0b100).with_group(0b100).with_others(0b100);
+    self.write_inode(&inode, vinode.number)?;
+
+    Ok(inode.into())
+  }
+
+  /// Release inode `inode_number` and its payload,
+  /// unless other inodes still share the payload
+  fn release_file(&mut self, inode_number: AddressSize) -> Result<(), Errno> {
+    let inode = self.read_inode(inode_number)?;
+    let is_payload_shared = self.inodes
+      .iter()
+      .enumerate()
+      .any(|(other_number, other)| {
+        other_number != inode_number as usize
+          && other.mode.free() == 0
+          && other.payload_number == inode.payload_number
+      });
+
+    if !is_payload_shared {
+      if let Some(payload) = self.payloads.get_mut(inode.payload_number as usize) {
+        *payload = None;
+      }
+    }
+
+    self.write_inode(&INode { number: inode_number, ..Default::default() }, inode_number)
+  }
 }
 
 impl<T: VirtFsFile> Filesystem for VirtFsFilesystem<T> {
@@ -314,8 +421,12 @@ impl<T: VirtFsFile> Filesystem for VirtFsFilesystem<T> {
     //              `VFS::match_mount_point`
     let dir_pathname = format!("/{}", everything_else.join("/"));
 
-    // Get dir path with this regex
-    let dir_inode = self.lookup_path(dir_pathname.as_str())?;
+    // Get dir path with this regex, creating missing
+    // parent directories like `mkdir -p`
+    let dir_inode = match self.lookup_path(dir_pathname.as_str()) {
+      Err(Errno::ENOENT(_)) => self.create_dir(dir_pathname.as_str())?,
+      result => result?,
+    };
 
     // Read dir from disk
     let mut dir = self.read_dir_from_inode(dir_inode.number)?;
@@ -325,7 +436,7 @@ impl<T: VirtFsFile> Filesystem for VirtFsFilesystem<T> {
       .iter()
       .find(|(name, _entry)| format!("/{}", name) == dirent_name)
     {
-       return Err(Errno::EINVAL(String::from("file already exists")));
+       return Err(Errno::EINVAL(String::from("file already exists").into()));
     }
 
     // Allocate inode
@@ -337,31 +448,116 @@ impl<T: VirtFsFile> Filesystem for VirtFsFilesystem<T> {
     // Write dir
     self.write_dir(&dir, dir_inode.number)?;
 
-    let file_inode = self.read_inode(file_inode_number)?;
+    // Directory entry is the only link for now
+    let mut file_inode = self.read_inode(file_inode_number)?;
+    file_inode.links_count = 1;
+    self.write_inode(&file_inode, file_inode_number)?;
 
     Ok(file_inode.into())
   }
 
   fn remove_file(&mut self, pathname: &str)
     -> Result<(), Errno> {
-        todo!()
-    } 
+    let parent_pathname = VFS::parent_dir(pathname)?;
+    let (_, final_component) = VFS::split_path(pathname)?;
+    let parent_inode_number = self.lookup_path(&parent_pathname)?.number;
+    let mut parent_dir = self.read_dir_from_inode(parent_inode_number)?;
+
+    if final_component == "." || final_component == ".." {
+      return Err(Errno::EINVAL(format!("virtfs::remove_file: you cannot remove self or parent-reference").into()))
+    }
+
+    // Mutate dir and write (save) it
+    let DirectoryEntry {
+      inode_number,
+      ..
+    } = parent_dir
+      .entries
+      .remove(&final_component)
+      .ok_or(Errno::ENOENT(format!("virtfs::remove_file: no such file or directory '{final_component}'").into()))?;
+    self.write_dir(&parent_dir, parent_inode_number)?;
+
+    // Read inode and update it's values
+    let mut inode = self.read_inode(inode_number)?;
+    inode.links_count = inode.links_count.saturating_sub(1);
+    inode.ctime = self.clock.now();
+
+    // Free inode and payload if no links left
+    if inode.links_count < 1 {
+      return self.release_file(inode_number);
+    }
+
+    self.write_inode(&inode, inode_number)
+  } 
 
   fn create_dir(&mut self, pathname: &str)
     -> Result<VINode, Errno> {
+    // Directories are listable by everyone: rwxr-xr-x
     let vinode = self.create_file(pathname)?;
-    self.change_mode(pathname, vinode.mode.with_file_type(FileModeType::Dir as u8))?;
-    self.write_payload(&Payload::Directory(Directory::new()), vinode.number)?;
+    self.change_mode(pathname, vinode.mode
+      .with_file_type(FileModeType::Dir as u8)
+      .with_user(0b111)
+      .with_group(0b101)
+      .with_others(0b101))?;
+    self.write_dir(&Directory::new(), vinode.number)?;
 
     Ok(vinode)
   }
 
+  fn mknod(&mut self, _pathname: &str, _file_type: FileModeType, _device_number: DeviceNumber)
+    -> Result<VINode, Errno> {
+    Err(Errno::EPERM(format!("{}: device nodes are not supported", self.name).into()))
+  }
+
+  fn link(&mut self, existing: &str, new: &str)
+    -> Result<VINode, Errno> {
+    let vinode = self.lookup_path(existing)?;
+    // Guard for directories, links to them would make loops
+    if vinode.mode.file_type() == FileModeType::Dir as u8 {
+      return Err(Errno::EPERM(format!("{}: {existing}: hard links to directories are not allowed", self.name).into()));
+    }
+
+    let (_, final_component) = VFS::split_path(new)?;
+    let parent_inode_number = self.lookup_path(&VFS::parent_dir(new)?)?.number;
+    let mut parent_dir = self.read_dir_from_inode(parent_inode_number)?;
+
+    // Guard for file already existing
+    if parent_dir.entries.contains_key(&final_component) {
+      return Err(Errno::EEXIST(format!("{}: {new}: file exists", self.name).into()));
+    }
+
+    parent_dir.insert(vinode.number, &final_component)?;
+    self.write_dir(&parent_dir, parent_inode_number)?;
+
+    let mut inode = self.read_inode(vinode.number)?;
+    inode.links_count += 1;
+    inode.ctime = self.clock.now();
+    self.write_inode(&inode, vinode.number)?;
+
+    Ok(inode.into())
+  }
+
+  fn symlink(&mut self, _target: &str, pathname: &str)
+    -> Result<VINode, Errno> {
+    Err(Errno::EPERM(format!("{}: {pathname}: symbolic links are not supported", self.name).into()))
+  }
+
+  fn readlink(&mut self, pathname: &str)
+    -> Result<String, Errno> {
+    Err(Errno::EINVAL(format!("{}: {pathname}: not a symbolic link", self.name).into()))
+  }
+
   fn read_file(&mut self, pathname: &str, _count: AddressSize)
     -> Result<Vec<u8>, Errno> {
     let inode_number = self.lookup_path(pathname)?.number;
     let file = self
       .read_from_file(inode_number)?;
 
+    // Guard for generated files - we have no kernel to generate them from
+    if let Payload::Generator(_) = file {
+      return Err(Errno::EIO(format!("{}: {pathname} is generated by kernel, read it with Kernel::read_file", self.name).into()));
+    }
+
     Ok(
       format!("{file}")
         .as_bytes()
@@ -371,7 +567,34 @@ impl<T: VirtFsFile> Filesystem for VirtFsFilesystem<T> {
 
   fn write_file(&mut self, pathname: &str, data: &[u8])
     -> Result<VINode, Errno> {
-      todo!("Accept callbacks for read and write from the instantiator")
+    let inode_number = self.lookup_path(pathname)?.number;
+
+    match self.read_from_file(inode_number)? {
+      Payload::Directory(_) => return Err(Errno::EISDIR(format!("{}: is a directory: {pathname}", self.name).into())),
+      Payload::Generator(_) => return Err(Errno::EPERM(format!("{}: {pathname} is generated by kernel", self.name).into())),
+      Payload::File(_) => (),
+    }
+
+    let writer = self.writer.ok_or(Errno::EPERM(format!("{}: files are read-only", self.name).into()))?;
+    let file = writer(data)?;
+
+    self.write_file_payload(inode_number, file)
+  }
+
+  fn read_at(&mut self, pathname: &str, offset: AddressSize, count: AddressSize)
+    -> Result<Vec<u8>, Errno> {
+    // Payload is kept parsed, so bytes are only there as a whole
+    let contents = self.read_file(pathname, EVERYTHING)?;
+
+    Ok(contents.into_iter().skip(offset as usize).take(count as usize).collect())
+  }
+
+  fn write_at(&mut self, pathname: &str, offset: AddressSize, data: &[u8])
+    -> Result<VINode, Errno> {
+    let mut contents = self.read_file(pathname, EVERYTHING)?;
+    splice_at(&mut contents, offset, data);
+
+    self.write_file(pathname, &contents)
   }
 
   fn read_dir(&mut self, pathname: &str)
@@ -405,6 +628,7 @@ impl<T: VirtFsFile> Filesystem for VirtFsFilesystem<T> {
       links_count,
       uid,
       gid,
+      rdev: DeviceNumber::default(),
       block_size: 0,
       atime,
       mtime,
@@ -421,12 +645,24 @@ impl<T: VirtFsFile> Filesystem for VirtFsFilesystem<T> {
 
   fn change_owners(&mut self, pathname: &str, uid: Id, gid: Id) 
     -> Result<(), Errno> {
-    todo!()
+    let inode_number = self.lookup_path(pathname)?.number;
+    let mut inode = self.read_inode(inode_number)?;
+    inode.uid = uid;
+    inode.gid = gid;
+    inode.ctime = self.clock.now();
+    self.write_inode(&inode, inode_number)
   }
 
   fn change_times(&mut self, pathname: &str, times: Times)
     -> Result<(), Errno> {
-    todo!()
+    let inode_number = self.lookup_path(pathname)?.number;
+    let mut inode = self.read_inode(inode_number)?;
+    inode.atime = times.atime;
+    inode.mtime = times.mtime;
+    inode.ctime = times.ctime;
+    inode.btime = times.btime;
+
+    self.write_inode(&inode, inode_number)
   }
 
   // Поиск файла в файловой системе. Возвращает INode фала.
@@ -458,7 +694,7 @@ impl<T: VirtFsFile> Filesystem for VirtFsFilesystem<T> {
       // TODO: pass inode to read_dir_from_inode
       while everything_else.len() > 0 {
         if !is_dir(inode.clone().into()) {
-          return Err(Errno::ENOTDIR(String::from("virtfs.lookup_path: not a directory (find_dir)")))
+          return Err(Errno::ENOTDIR(String::from("virtfs.lookup_path: not a directory (find_dir)").into()))
         }
 
         let piece = everything_else.pop_front().unwrap();
@@ -466,7 +702,7 @@ impl<T: VirtFsFile> Filesystem for VirtFsFilesystem<T> {
         if let Some(entry) = dir.entries.get(&piece.to_owned()) {
           inode = virtfs.read_inode(entry.inode_number)?;
         } else {
-          return Err(Errno::ENOENT(String::from("virtfs.lookup_path: no such file or directory")))
+          return Err(Errno::ENOENT(String::from("virtfs.lookup_path: no such file or directory").into()))
         }
       }
 
@@ -482,20 +718,32 @@ impl<T: VirtFsFile> Filesystem for VirtFsFilesystem<T> {
     Ok(
       dir.entries
         .get(&final_component)
-        .ok_or_else(|| Errno::ENOENT(String::from("virtfs.lookup_path: no such file or directory (get(final_component))")))
+        .ok_or_else(|| Errno::ENOENT(String::from("virtfs.lookup_path: no such file or directory (get(final_component))").into()))
         // Read its inode_number
         .and_then(|entry| self.read_inode(entry.inode_number))?
         .into()
     )
   }
 
+  fn statfs(&mut self, _pathname: &str) -> Result<FilesystemStat, Errno> {
+    Ok(VirtFsFilesystem::statfs(self))
+  }
+
+  fn set_clock(&mut self, clock: Arc<dyn Clock>) {
+    self.clock = clock;
+  }
+
   fn name(&self) -> String { 
     self.name().clone()
   }
 
-fn as_any(&mut self) -> &mut dyn Any {
-    unimplemented!("virtfs: as_any for virtfs is undefined")
-  } 
+  fn as_any(&mut self) -> &mut dyn Any {
+    self
+  }
+
+  fn as_any_ref(&self) -> &dyn Any {
+    self
+  }
 }
 
 impl<T: VirtFsFile> VirtFsFilesystem<T> {
@@ -506,6 +754,9 @@ impl<T: VirtFsFile> VirtFsFilesystem<T> {
       name: name.to_owned(),
       inodes: vec![Default::default(); inodes_count as usize],
       payloads: vec![None; inodes_count as usize],
+      writer: None,
+      max_inodes_count: None,
+      clock: host_clock(),
     };
 
     // Create the root inode
@@ -515,8 +766,18 @@ impl<T: VirtFsFile> VirtFsFilesystem<T> {
       .mode
       .with_free(0)
       .with_file_type(FileModeType::Dir as u8)
+      .with_user(0b111)
+      .with_group(0b101)
+      .with_others(0b101)
     ;
     root_inode.payload_number = root_payload_number;
+    root_inode.uid = ROOT_UID;
+    root_inode.gid = ROOT_GID;
+    let now = virtfs.clock.now();
+    root_inode.atime = now;
+    root_inode.mtime = now;
+    root_inode.ctime = now;
+    root_inode.btime = now;
 
     // Create root directory
     let mut dir = Directory::new();
@@ -530,6 +791,97 @@ impl<T: VirtFsFile> VirtFsFilesystem<T> {
     virtfs
   }
 
+  /// Make files writable, parsing written bytes with `writer`
+  pub fn with_writer(mut self, writer: VirtFsWriter<T>) -> Self {
+    self.writer = Some(writer);
+    self
+  }
+
+  fn to_snapshot<F>(&self, file_to_snapshot: impl Fn(&T) -> Option<F>) -> Snapshot<F> {
+    Snapshot {
+      name: self.name.clone(),
+      inodes: self.inodes.clone(),
+      payloads: self.payloads
+        .iter()
+        .map(|payload| payload.as_ref().map(|payload| match payload {
+          Payload::Directory(dir) => SnapshotPayload::Directory(dir.clone()),
+          Payload::File(file) => SnapshotPayload::File(file_to_snapshot(file)),
+          Payload::Generator(_) => SnapshotPayload::File(None),
+        }))
+        .collect(),
+      max_inodes_count: self.max_inodes_count,
+    }
+  }
+
+  fn from_snapshot<F>(snapshot: Snapshot<F>, snapshot_to_file: impl Fn(Option<F>) -> T) -> Self {
+    Self {
+      superblock: Superblock::new(),
+      name: snapshot.name,
+      inodes: snapshot.inodes,
+      payloads: snapshot.payloads
+        .into_iter()
+        .map(|payload| payload.map(|payload| match payload {
+          SnapshotPayload::Directory(dir) => Payload::Directory(dir),
+          SnapshotPayload::File(file) => Payload::File(snapshot_to_file(file)),
+        }))
+        .collect(),
+      writer: None,
+      max_inodes_count: snapshot.max_inodes_count,
+      clock: host_clock(),
+    }
+  }
+
+  /// Dump as YAML, storing contents of files as `file_to_snapshot`
+  /// makes them. Files it returns `None` for are left out
+  pub fn serialize_with<F: Serialize>(&self, file_to_snapshot: impl Fn(&T) -> Option<F>) -> Result<String, Errno> {
+    serde_yaml::to_string(&self.to_snapshot(file_to_snapshot))
+      .map_err(|error| Errno::EIO(format!("{}: cannot serialize: {error}", self.name).into()))
+  }
+
+  /// Restore virtfs dumped by `serialize_with`, making files
+  /// out of stored contents with `snapshot_to_file`.
+  /// Writer has to be set again
+  pub fn deserialize_with<F: DeserializeOwned>(serialized: &str, snapshot_to_file: impl Fn(Option<F>) -> T)
+    -> Result<Self, Errno> {
+    let snapshot = serde_yaml::from_str::<Snapshot<F>>(serialized)
+      .map_err(|error| Errno::EINVAL(format!("virtfs: invalid snapshot: {error}").into()))?;
+
+    Ok(VirtFsFilesystem::from_snapshot(snapshot, snapshot_to_file))
+  }
+
+  /// Limit growth of inode and payload tables to `max_inodes_count` entries
+  pub fn with_max_inodes_count(mut self, max_inodes_count: AddressSize) -> Self {
+    self.max_inodes_count = Some(max_inodes_count);
+    self
+  }
+
+  /// Returns: usage of inode and payload tables, payloads are counted
+  /// as blocks. Totals are the cap if there is one, current sizes otherwise
+  pub fn statfs(&self) -> FilesystemStat {
+    let inodes_count = self.inodes.len() as AddressSize;
+    let payloads_count = self.payloads.len() as AddressSize;
+    let used_inodes_count = self.inodes
+      .iter()
+      .filter(|inode| inode.mode.free() == 0)
+      .count() as AddressSize;
+    let used_payloads_count = self.payloads
+      .iter()
+      .filter(|payload| payload.is_some())
+      .count() as AddressSize;
+
+    let inodes_count = self.max_inodes_count.unwrap_or(inodes_count).max(inodes_count);
+    let blocks_count = self.max_inodes_count.unwrap_or(payloads_count).max(payloads_count);
+
+    FilesystemStat {
+      block_size: 0,
+      blocks_count,
+      free_blocks_count: blocks_count - used_payloads_count,
+      reserved_blocks_count: 0,
+      inodes_count,
+      free_inodes_count: inodes_count - used_inodes_count,
+    }
+  }
+
   pub fn name(&self) -> String {
     self.name.clone()
   }
@@ -540,26 +892,26 @@ impl<T: VirtFsFile> VirtFsFilesystem<T> {
       // let payload_number = self
       //   .inodes
       //   .get(inode_number as usize)
-      //   .ok_or(Errno::EIO(String::from("virtfs: inode does not exist for inode_number")))?
+      //   .ok_or(Errno::EIO(String::from("virtfs: inode does not exist for inode_number").into()))?
       //   .payload_number
       // ;
 
       let payload_number = inode.payload_number;
 
       *self.payloads.get_mut(payload_number as usize)
-        .ok_or(Errno::EIO(String::from("virtfs: payload does not exist for payload_number")))?
+        .ok_or(Errno::EIO(String::from("virtfs: payload does not exist for payload_number").into()))?
         = Some(Payload::Directory(dir.clone()));
 
       Ok(())
     } else {
-      Err(Errno::ENOENT(String::from("virtfs: no such file or directory")))
+      Err(Errno::ENOENT(String::from("virtfs: no such file or directory").into()))
     }
   }
 
   fn read_dir_from_inode(&self, inode_number: AddressSize) -> Result<Directory, Errno> {
     match self.read_from_file(inode_number)? {
       Payload::Directory(directory) => Ok(directory),
-      Payload::File(_) => Err(Errno::ENOTDIR(String::from("tried to read file from inode (TODO: inode number here), got directory"))),
+      Payload::File(_) | Payload::Generator(_) => Err(Errno::ENOTDIR(String::from("tried to read file from inode (TODO: inode number here), got directory").into())),
     }
   }
 
@@ -570,23 +922,14 @@ impl<T: VirtFsFile> VirtFsFilesystem<T> {
       .payloads
       .get(payload_number as usize)
       .to_owned()
-      .ok_or(Errno::EIO(format!("virtfs: read_from_file: no payload for inode #{inode_number} (payload_number was #{payload_number}) [1]")))?
+      .ok_or(Errno::EIO(format!("virtfs: read_from_file: no payload for inode #{inode_number} (payload_number was #{payload_number}) [1]").into()))?
       .to_owned()
-      .ok_or(Errno::EIO(format!("virtfs: read_from_file: no payload for inode #{inode_number} (payload_number was #{payload_number}) [2]")))?
+      .ok_or(Errno::EIO(format!("virtfs: read_from_file: no payload for inode #{inode_number} (payload_number was #{payload_number}) [2]").into()))?
     ;
 
     Ok(payload)
   }
 
-  fn get_inode_blocks_count(&mut self, inode_number: AddressSize) -> Result<AddressSize, Errno> {
-    Ok(0)
-  }
-
-  fn read_mode(&mut self, inode_number: AddressSize) -> Result<FileMode, Errno> {
-    let inode = self.read_inode(inode_number)?;
-    Ok(inode.mode)
-  }
-
   fn write_mode(&mut self, inode_number: AddressSize, mode: FileMode) -> Result<(), Errno> {
     let mut inode = self.read_inode(inode_number)?;
     inode.mode = mode;
@@ -599,7 +942,7 @@ impl<T: VirtFsFile> VirtFsFilesystem<T> {
       self
        .inodes
        .get(inode_number as usize)
-       .ok_or(Errno::ENOENT(String::from("virtfs: read_inode: no such file or directory")))?
+       .ok_or(Errno::ENOENT(String::from("virtfs: read_inode: no such file or directory").into()))?
        .clone()
      )
   }
@@ -613,12 +956,153 @@ impl<T: VirtFsFile> VirtFsFilesystem<T> {
            .payloads
            .get(inode.payload_number as usize)
        })
-       .ok_or(Errno::ENOENT(format!("virtfs: read_payload: payload does not exist for inode {inode_number}")))?
+       .ok_or(Errno::ENOENT(format!("virtfs: read_payload: payload does not exist for inode {inode_number}").into()))?
        .to_owned()
-       .ok_or(Errno::ENOENT(format!("virtfs: read_payload: payload does not exist for inode {inode_number}")))?
+       .ok_or(Errno::ENOENT(format!("virtfs: read_payload: payload does not exist for inode {inode_number}").into()))?
        .to_owned()
      )
   }
 }
 
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  fn parse_string(data: &[u8]) -> Result<String, Errno> {
+    String::from_utf8(data.to_owned()).or(Err(Errno::EILSEQ(String::from("virtfs: not utf-8").into())))
+  }
+
+  #[test]
+  fn write_file_works() {
+    let mut virtfs = VirtFsFilesystem::<String>::new("testfs", 8).with_writer(parse_string);
+    virtfs.create_file("/file").unwrap();
+
+    let vinode = virtfs.write_file("/file", b"hello").unwrap();
+
+    assert_eq!(vinode.file_size, 5);
+    assert_eq!(virtfs.read_file("/file", 5).unwrap(), b"hello");
+  }
+
+  #[test]
+  fn read_and_write_at_offset_works() {
+    let mut virtfs = VirtFsFilesystem::<String>::new("testfs", 8).with_writer(parse_string);
+    virtfs.create_file("/file").unwrap();
+    virtfs.write_file("/file", b"hello").unwrap();
+
+    assert_eq!(virtfs.write_at("/file", 4, b"o world").unwrap().file_size, 11);
+    assert_eq!(virtfs.read_at("/file", 6, 100).unwrap(), b"world");
+    assert_eq!(virtfs.write_at("/file", 13, b"!").unwrap().file_size, 14);
+    assert_eq!(virtfs.read_at("/file", 11, 3).unwrap(), b"\0\0!");
+  }
+
+  #[test]
+  fn write_file_without_writer_fails() {
+    let mut virtfs = VirtFsFilesystem::<String>::new("testfs", 8);
+    virtfs.create_file("/file").unwrap();
+
+    assert!(matches!(virtfs.write_file("/file", b"hello"), Err(Errno::EPERM(_))));
+  }
+
+  #[test]
+  fn remove_file_frees_inode_and_payload() {
+    let mut virtfs = VirtFsFilesystem::<String>::new("testfs", 2).with_max_inodes_count(2);
+    virtfs.create_file("/file").unwrap();
+
+    // Filesystem of two inodes is full now
+    assert!(virtfs.create_file("/other").is_err());
+
+    virtfs.remove_file("/file").unwrap();
+
+    assert!(matches!(virtfs.lookup_path("/file"), Err(Errno::ENOENT(_))));
+    virtfs.create_file("/other").unwrap();
+  }
+
+  #[test]
+  fn tables_grow_up_to_cap() {
+    let mut virtfs = VirtFsFilesystem::<String>::new("testfs", 1).with_max_inodes_count(3);
+
+    virtfs.create_file("/a").unwrap();
+    virtfs.create_file("/b").unwrap();
+    assert!(matches!(virtfs.create_file("/c"), Err(Errno::ENOSPC(_))));
+
+    let stat = virtfs.statfs();
+    assert_eq!((stat.inodes_count, stat.free_inodes_count), (3, 0));
+    assert_eq!((stat.blocks_count, stat.free_blocks_count), (3, 0));
+
+    virtfs.remove_file("/a").unwrap();
+    assert_eq!(virtfs.statfs().free_inodes_count, 1);
+  }
+
+  #[test]
+  fn as_any_downcasts() {
+    let mut virtfs: Box<dyn Filesystem> = Box::new(VirtFsFilesystem::<String>::new("testfs", 8));
+
+    assert!(virtfs.as_any_ref().downcast_ref::<VirtFsFilesystem<String>>().is_some());
+    assert!(virtfs.as_any().downcast_mut::<VirtFsFilesystem<String>>().is_some());
+    assert!(virtfs.as_any_ref().downcast_ref::<VirtFsFilesystem<u8>>().is_none());
+  }
+
+  #[test]
+  fn generated_files_are_read_only() {
+    fn generator(_: &Kernel) -> String {
+      String::from("generated")
+    }
+    let mut virtfs = VirtFsFilesystem::<String>::new("testfs", 8).with_writer(parse_string);
+
+    let vinode = virtfs.create_generated_file("/file", generator).unwrap();
+
+    assert_eq!((vinode.mode.user(), vinode.mode.group(), vinode.mode.others()), (0b100, 0b100, 0b100));
+    assert!(matches!(virtfs.read_payload(vinode.number).unwrap(), Payload::Generator(_)));
+    assert!(matches!(virtfs.write_file("/file", b"hello"), Err(Errno::EPERM(_))));
+  }
+
+  #[test]
+  fn create_file_creates_parents() {
+    let mut virtfs = VirtFsFilesystem::<String>::new("testfs", 8);
+
+    virtfs.create_file("/a/b/file").unwrap();
+
+    assert_eq!(virtfs.stat("/a").unwrap().mode.file_type(), FileModeType::Dir as u8);
+    assert_eq!(virtfs.stat("/a/b").unwrap().mode.file_type(), FileModeType::Dir as u8);
+    assert!(virtfs.read_dir("/a/b").unwrap().entries.contains_key("file"));
+  }
+
+  #[test]
+  fn serialize_roundtrip_works() {
+    let mut virtfs = VirtFsFilesystem::<String>::new("testfs", 8).with_writer(parse_string);
+    virtfs.create_file("/dir/file").unwrap();
+    virtfs.write_file("/dir/file", b"hello").unwrap();
+
+    let serialized = virtfs.serialize_with(|file| Some(file.clone())).unwrap();
+    let mut restored = VirtFsFilesystem::<String>::deserialize_with(&serialized, Option::unwrap_or_default).unwrap();
+
+    assert_eq!(restored.read_file("/dir/file", 5).unwrap(), b"hello");
+    assert_eq!(restored.stat("/dir/file").unwrap().size, 5);
+    assert_eq!(restored.name(), "testfs");
+  }
+
+  #[test]
+  fn serialize_leaves_out_files_without_snapshot() {
+    let mut virtfs = VirtFsFilesystem::<String>::new("testfs", 8).with_writer(parse_string);
+    virtfs.create_file("/file").unwrap();
+    virtfs.write_file("/file", b"hello").unwrap();
+
+    let serialized = virtfs.serialize_with::<String>(|_| None).unwrap();
+    let mut restored = VirtFsFilesystem::<String>::deserialize_with(&serialized, Option::unwrap_or_default).unwrap();
+
+    assert_eq!(restored.read_file("/file", 5).unwrap(), b"");
+  }
+
+  #[test]
+  fn change_times_works() {
+    let mut virtfs = VirtFsFilesystem::<String>::new("testfs", 8);
+    virtfs.create_file("/file").unwrap();
+
+    virtfs.change_times("/file", Times { atime: 1, mtime: 2, ctime: 3, btime: 4 }).unwrap();
+
+    let stat = virtfs.stat("/file").unwrap();
+    assert_eq!((stat.atime, stat.mtime, stat.ctime, stat.btime), (1, 2, 3, 4));
+  }
+}
+
 // vim:ts=2 sw=2
@@ -0,0 +1,200 @@
+use std::collections::{BTreeMap, VecDeque};
+
+use super::fs::{AddressSize, VFS};
+use super::kernel::Errno;
+
+pub type WatchDescriptor = AddressSize;
+
+/// File is created in watched directory
+pub const IN_CREATE: u8 = 0b001;
+/// Watched file, or file in watched directory, is written
+pub const IN_MODIFY: u8 = 0b010;
+/// Watched file, or file in watched directory, is removed
+pub const IN_DELETE: u8 = 0b100;
+pub const IN_ALL_EVENTS: u8 = IN_CREATE | IN_MODIFY | IN_DELETE;
+
+/// Most events waiting to be read, later ones are dropped
+/// until some are read
+pub const MAX_QUEUED_EVENTS: usize = 1024;
+
+/// Something that happened to a watched file
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct WatchEvent {
+  pub watch_descriptor: WatchDescriptor,
+  /// One of `IN_CREATE`, `IN_MODIFY`, `IN_DELETE`
+  pub mask: u8,
+  /// Name of file in watched directory it happened to,
+  /// `None` if it happened to watched file itself
+  pub name: Option<String>,
+}
+
+/// Interest of process `pid` in `pathname`
+#[derive(Debug, Clone)]
+pub struct Watch {
+  pub pid: AddressSize,
+  /// Absolute pathname
+  pub pathname: String,
+  pub mask: u8,
+}
+
+/// Watches of all processes and events that wait to be read, like
+/// inotify of Linux. VFS tells about changes it makes, by pathnames
+/// they are made at, so changes seen through other pathnames, like
+/// bind mounts or other hard links, are not reported
+#[derive(Debug, Default)]
+pub struct Watches {
+  watches: BTreeMap<WatchDescriptor, Watch>,
+  events: VecDeque<(AddressSize, WatchEvent)>,
+}
+
+impl Watches {
+  /// Watch `pathname` for events in `mask` on behalf of `pid`.
+  /// If process watches it already, the watch gets `mask` instead.
+  /// Returns: descriptor of the watch
+  pub fn add(&mut self, pid: AddressSize, pathname: &str, mask: u8) -> WatchDescriptor {
+    if let Some((watch_descriptor, watch)) = self.watches
+      .iter_mut()
+      .find(|(_, watch)| watch.pid == pid && watch.pathname == pathname)
+    {
+      watch.mask = mask;
+      return *watch_descriptor;
+    }
+
+    let watch_descriptor = (1..)
+      .find(|watch_descriptor| !self.watches.contains_key(watch_descriptor))
+      .expect("there are fewer watches than descriptors");
+    self.watches.insert(watch_descriptor, Watch {
+      pid,
+      pathname: pathname.to_owned(),
+      mask,
+    });
+
+    watch_descriptor
+  }
+
+  /// Stop watch of `pid` and drop its events that are not read
+  ///
+  /// Errors:
+  /// EINVAL -> `pid` has no such watch
+  pub fn remove(&mut self, pid: AddressSize, watch_descriptor: WatchDescriptor) -> Result<(), Errno> {
+    match self.watches.get(&watch_descriptor) {
+      Some(watch) if watch.pid == pid => (),
+      _ => return Err(Errno::EINVAL(format!("watch: no watch {watch_descriptor}").into())),
+    }
+
+    self.watches.remove(&watch_descriptor);
+    self.events.retain(|(_, event)| event.watch_descriptor != watch_descriptor);
+
+    Ok(())
+  }
+
+  /// Stop all watches of `pid`, when it exits
+  pub fn remove_process(&mut self, pid: AddressSize) {
+    self.watches.retain(|_, watch| watch.pid != pid);
+    self.events.retain(|(event_pid, _)| *event_pid != pid);
+  }
+
+  /// Watches of `pid`
+  pub fn of_process(&self, pid: AddressSize) -> impl Iterator<Item = (&WatchDescriptor, &Watch)> {
+    self.watches
+      .iter()
+      .filter(move |(_, watch)| watch.pid == pid)
+  }
+
+  /// Take events of watches of `pid`, the oldest first
+  pub fn take_events(&mut self, pid: AddressSize) -> Vec<WatchEvent> {
+    let (taken, left) = self.events
+      .drain(..)
+      .partition::<Vec<_>, _>(|(event_pid, _)| *event_pid == pid);
+    self.events = left.into();
+
+    taken
+      .into_iter()
+      .map(|(_, event)| event)
+      .collect()
+  }
+
+  /// Tell watches of `pathname` and of its parent directory
+  /// that event `mask` happened to file at absolute `pathname`
+  pub fn notify(&mut self, pathname: &str, mask: u8) {
+    if self.watches.is_empty() {
+      return;
+    }
+    let parent = VFS::parent_dir(pathname).ok();
+    let name = VFS::split_path(pathname).ok().map(|(_, name)| name);
+
+    for (watch_descriptor, watch) in &self.watches {
+      if watch.mask & mask == 0 || self.events.len() == MAX_QUEUED_EVENTS {
+        continue;
+      }
+      let name = match watch.pathname == pathname {
+        true => None,
+        false if parent.as_deref() == Some(watch.pathname.as_str()) => name.clone(),
+        false => continue,
+      };
+
+      self.events.push_back((watch.pid, WatchEvent {
+        watch_descriptor: *watch_descriptor,
+        mask,
+        name,
+      }));
+    }
+  }
+}
+
+/// Name of event in `mask`, like inotify tools print them
+pub fn event_name(mask: u8) -> &'static str {
+  match mask {
+    IN_CREATE => "CREATE",
+    IN_MODIFY => "MODIFY",
+    IN_DELETE => "DELETE",
+    _ => "UNKNOWN",
+  }
+}
+
+/// Get event mask by name of event, in any case
+pub fn parse_event(name: &str) -> Result<u8, Errno> {
+  match name.to_lowercase().as_str() {
+    "create" => Ok(IN_CREATE),
+    "modify" => Ok(IN_MODIFY),
+    "delete" => Ok(IN_DELETE),
+    _ => Err(Errno::EINVAL(format!("watch: unknown event: {name}").into())),
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+
+  #[test]
+  fn events_go_to_watches_of_file_and_parent() {
+    let mut watches = Watches::default();
+    let etc = watches.add(1, "/etc", IN_CREATE | IN_DELETE);
+    let passwd = watches.add(2, "/etc/passwd", IN_ALL_EVENTS);
+    assert_eq!(watches.add(1, "/etc", IN_ALL_EVENTS), etc);
+
+    watches.notify("/etc/passwd", IN_MODIFY);
+    watches.notify("/etc/motd", IN_CREATE);
+    watches.notify("/etc/ssh/config", IN_CREATE);
+    watches.notify("/etc/passwd", IN_DELETE);
+
+    assert_eq!(watches.take_events(2), vec![
+      WatchEvent { watch_descriptor: passwd, mask: IN_MODIFY, name: None },
+      WatchEvent { watch_descriptor: passwd, mask: IN_DELETE, name: None },
+    ]);
+    assert_eq!(watches.take_events(1).iter().map(|event| (event.mask, event.name.as_deref())).collect::<Vec<_>>(), vec![
+      (IN_MODIFY, Some("passwd")),
+      (IN_CREATE, Some("motd")),
+      (IN_DELETE, Some("passwd")),
+    ]);
+    assert!(watches.take_events(1).is_empty());
+
+    watches.notify("/etc/motd", IN_DELETE);
+    assert!(matches!(watches.remove(2, etc), Err(Errno::EINVAL(_))));
+    watches.remove(1, etc).unwrap();
+    assert!(watches.take_events(1).is_empty());
+    assert_eq!(parse_event("Modify").unwrap(), IN_MODIFY);
+  }
+}
+
+// vim:ts=2 sw=2
@@ -0,0 +1,58 @@
+#![feature(type_alias_impl_trait)]
+#![feature(trait_alias)]
+#![feature(const_fmt_arguments_new)]
+#![feature(let_chains)]
+#![feature(mapped_lock_guards)]
+
+//! Eunix - toy unix-like operating system running on a virtual machine.
+//!
+//! - `config` - `HostConfig` from `~/.config/eunix/config.toml`,
+//!   defaults for flags of `eunix`
+//! - `machine` - virtual machine described by `machine.yaml`: devices,
+//!   memory, CPUs. `Machine::run` boots an `os::OperatingSystem` on it
+//! - `kernel` - `Kernel` with its VFS, processes and users, made
+//!   from a `MachineDeviceTable` without booting anything
+//! - `fs` - `Filesystem` trait, VFS and types shared by filesystems
+//! - `binfs` - in-memory filesystem of native binaries and rhai scripts,
+//!   that `Kernel::register_binary` adds `BinaryFn`s to
+//! - `e5fs` - on-disk filesystem, `E5FSFilesystem::mkfs` makes one in a
+//!   host file and `E5FSFilesystem::from` reads it back
+//! - `ustar` - tar archives, to fill filesystems with `ustar::unpack`
+//! - `binaries` - programs that are installed into /bin
+//! - `clock`, `rng` - time and randomness of `Kernel`, host ones by
+//!   default, `FixedClock` and `SeededRng` make runs reproducible
+//! - `console` - `ConsoleBackend` that consoles are shown on: terminal
+//!   of host, or `BufferConsole` that another frontend drives
+//!
+//! ```no_run
+//! use eunix::{
+//!   e5fs::E5FSFilesystem,
+//!   fs::{Filesystem, FilesystemType},
+//!   kernel::{Kernel, KernelParams},
+//!   machine::{MachineClock, MachineDeviceTable, MachineResources},
+//! };
+//!
+//! let mut e5fs = E5FSFilesystem::mkfs("disk.enxvd", 0.05, 4096).unwrap();
+//! e5fs.create_dir("/etc").unwrap();
+//!
+//! let devices = MachineDeviceTable { devices: Vec::new(), ram_disks: Vec::new() };
+//! let mut kernel = Kernel::new(&devices, KernelParams {
+//!   init: String::from("/bin/init"),
+//!   resources: MachineResources::default(),
+//!   clock: MachineClock::default(),
+//! });
+//! kernel.mount("", "/", FilesystemType::binfs).unwrap();
+//! ```
+
+mod eunix;
+mod util;
+#[cfg(test)]
+mod testing;
+pub mod machine;
+pub mod config;
+pub mod os;
+pub mod binaries;
+
+pub use eunix::{kernel, fs, binfs, e5fs, ustar, devices, console, netfs, clock, rng};
+
+// vim:ts=2 sw=2
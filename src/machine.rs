@@ -1,9 +1,15 @@
+use std::fmt;
+use std::net::SocketAddr;
 use std::path::Path;
 use std::str::FromStr;
 use serde::{Serialize, Deserialize};
 
-use crate::eunix::fs::AddressSize;
-use crate::eunix::kernel::Kernel;
+use crate::binaries::EXIT_FAILURE;
+use crate::eunix::e5fs::E5FSFilesystem;
+use crate::eunix::fs::{AddressSize, FilesystemType};
+use crate::eunix::kernel::{Errno, PowerAction, kernel_message_header_err};
+use crate::os::OperatingSystem;
+use crate::util::parse_size;
 use std::collections::BTreeMap;
 
 
@@ -11,6 +17,22 @@ use std::collections::BTreeMap;
 pub enum VirtualDeviceType {
   BlockDevice,
   TTYDevice,
+  /// Serial port, eunix-side writes are appended to a log file on host
+  SerialDevice,
+  /// Network interface, frames go through its `NetBackend`
+  NetDevice,
+}
+
+/// Where frames sent by network interface go
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub enum NetBackend {
+  /// Frames come back to the interface itself
+  Loopback,
+  /// Frames are datagrams sent from `bind` address on host to `peer`
+  Udp {
+    bind: SocketAddr,
+    peer: SocketAddr,
+  },
 }
 
 // pub trait VirtualDevice: InstanceOf {
@@ -26,66 +48,487 @@ pub enum VirtualDeviceType {
 //   }
 // }
 
-#[derive(Debug)]
-pub struct OperatingSystem {
-  pub kernel: Kernel,
+/// Device backed by a file on host
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct MachineDevice {
+  /// Empty for network interfaces, they have `backend` instead
+  pub realpath: String,
+  pub r#type: VirtualDeviceType,
+  /// Writes to the device fail, like to a CD
+  pub read_only: bool,
+  /// Backend of network interface, `None` for other devices
+  pub backend: Option<NetBackend>,
 }
 
 #[derive(Debug, Clone)]
 pub struct MachineDeviceTable {
-  pub devices: BTreeMap<String, VirtualDeviceType>,
+  /// Devices in the order they get names in (`sda`, `sdb`, ...):
+  /// ones with `order` key first, by it, then the rest by path
+  pub devices: Vec<MachineDevice>,
+  /// Sizes of RAM disks in bytes, `ram_disks[N]` is `ramN`
+  pub ram_disks: Vec<u64>,
 }
 // /// realpath -> (dev_type, pathname) 
 // pub type DeviceTable = BTreeMap<String, (VirtualDeviceType, Option<String>)>; 
 
+/// Memory size when machine.yaml has no `memory`
+pub const DEFAULT_MEMORY: u64 = 64 * 1024 * 1024;
+/// Number of CPUs when machine.yaml has no `cpus`
+pub const DEFAULT_CPUS: u32 = 1;
+
+/// Memory and CPUs of machine, as /proc/meminfo, /proc/cpuinfo and
+/// `free` report them
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct MachineResources {
+  /// Memory size in bytes, RAM disks take from it, those of
+  /// machine.yaml and ones created at runtime
+  pub memory: u64,
+  pub cpus: u32,
+}
+
+impl Default for MachineResources {
+  fn default() -> Self {
+    Self {
+      memory: DEFAULT_MEMORY,
+      cpus: DEFAULT_CPUS,
+    }
+  }
+}
+
+/// How time goes on machine, `clock` of machine.yaml
+#[derive(Debug, Clone, Copy, PartialEq, Default)]
+pub enum MachineClock {
+  /// Time of host
+  #[default]
+  Host,
+  /// Time stands still at `epoch` seconds since unix epoch
+  Fixed { epoch: u64 },
+  /// Time goes `speed` times as fast as host time, from host time at boot
+  Scaled { speed: f64 },
+}
+
 #[derive(Debug)]
 pub struct Machine {
   device_table: MachineDeviceTable,
-  is_booted: bool,
+  resources: MachineResources,
+  clock: MachineClock,
+  /// Host file that console session is logged to
+  transcript: Option<String>,
+  /// Times machine was booted, reboots included
+  boots_count: u32,
 }
 
 #[derive(Debug, PartialEq, Serialize, Deserialize)]
 pub struct MachineSchema {
-  machine: BTreeMap<String, BTreeMap<String, BTreeMap<String, String>>>,
+  machine: MachineSection,
+}
+
+#[derive(Debug, PartialEq, Serialize, Deserialize)]
+struct MachineSection {
+  memory: Option<String>,
+  cpus: Option<String>,
+  clock: Option<BTreeMap<String, String>>,
+  transcript: Option<String>,
+  devices: Option<BTreeMap<String, BTreeMap<String, String>>>,
+  /// Anything else, which is reported as unknown
+  #[serde(flatten)]
+  unknown: BTreeMap<String, serde_yaml::Value>,
+}
+
+/// Problem with one key of machine.yaml
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SchemaIssue {
+  /// Dotted path of the key, like `machine.devices.disk1.type`
+  pub key: String,
+  /// Line of the key in the schema, starting from 1
+  pub line: Option<usize>,
+  pub message: String,
+}
+
+#[derive(Debug)]
+pub enum MachineError {
+  /// Schema can't be read from host
+  Read { path: String, message: String },
+  /// Schema is not YAML of expected shape
+  Syntax { path: String, line: Option<usize>, message: String },
+  /// Schema is well-formed, but describes machine that can't be made
+  Invalid { path: String, issues: Vec<SchemaIssue> },
+}
+
+impl fmt::Display for MachineError {
+  fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+    match self {
+      Self::Read { path, message } => write!(f, "machine: cannot read {path}: {message}"),
+      Self::Syntax { path, line: Some(line), message } => write!(f, "machine: {path}:{line}: {message}"),
+      Self::Syntax { path, line: None, message } => write!(f, "machine: {path}: {message}"),
+      Self::Invalid { path, issues } => {
+        write!(f, "machine: {path} is invalid:")?;
+        for SchemaIssue { key, line, message } in issues {
+          match line {
+            Some(line) => write!(f, "\n  {path}:{line}: {key}: {message}")?,
+            None => write!(f, "\n  {path}: {key}: {message}")?,
+          }
+        }
+        Ok(())
+      },
+    }
+  }
+}
+
+/// Options that devices can have in machine.yaml
+const DEVICE_OPTIONS: [&'static str; 9] = ["type", "path", "size", "format", "read-only", "order", "backend", "bind", "peer"];
+
+/// Line of key at `keys` (like `["machine", "devices", "disk1"]`) in YAML
+/// `source`, found by indentation. Lines are counted from 1. With `nth`,
+/// look for `nth` occurence of the last key - there can be duplicates
+fn key_line(source: &str, keys: &[&str], nth: usize) -> Option<usize> {
+  let indent_of = |line: &str| line.len() - line.trim_start().len();
+  let lines = source
+    .lines()
+    .enumerate()
+    .filter(|(_, line)| !line.trim().is_empty() && !line.trim_start().starts_with('#'))
+    .collect::<Vec<_>>();
+
+  let (mut start, mut parent_indent, mut line_number) = (0, None, None);
+  for (depth, key) in keys.iter().enumerate() {
+    // Children end where indentation gets back to parent's
+    let children = lines[start..]
+      .iter()
+      .take_while(|(_, line)| parent_indent.map_or(true, |indent| indent_of(line) > indent))
+      .collect::<Vec<_>>();
+    let child_indent = indent_of(children.first()?.1);
+
+    let position = children
+      .iter()
+      .enumerate()
+      .filter(|(_, (_, line))| indent_of(line) == child_indent)
+      .filter(|(_, (_, line))| line.trim_start().strip_prefix(key).map_or(false, |rest| rest.starts_with(':')))
+      .nth(if depth == keys.len() - 1 { nth } else { 0 })
+      .map(|(position, _)| start + position)?;
+
+    let (index, line) = lines[position];
+    parent_indent = Some(indent_of(line));
+    line_number = Some(index + 1);
+    start = position + 1;
+  }
+
+  line_number
 }
 
 impl Machine {
-  pub fn new(machine_schema_path: &str) -> Self {
-    let machine_schema_reader = std::fs::File::open(machine_schema_path)
-      .unwrap();
-
-    let machine_schema = 
-      serde_yaml::from_reader::<_, MachineSchema>(machine_schema_reader)
-        .unwrap();
-
-    let devices = MachineDeviceTable { 
-      devices: machine_schema.machine
-      .get("devices")
-      .unwrap()
-      .into_iter()
-      .map(|(_name, device)| {
-        let device_path = Path::new(&machine_schema_path).parent().unwrap().join(device.get("path").unwrap());
-        let device_type = device.get("type").unwrap();
-
-        let a = String::from_str(device_path.to_str().unwrap()).unwrap();
-        (a, match device_type.as_ref() {
-          "block" => VirtualDeviceType::BlockDevice,
-          "tty" => VirtualDeviceType::TTYDevice,
-          _ => panic!("machine: can't start: unknown device type in {}", machine_schema_path),
-        })
-      })
-      .collect()
+  pub fn new(machine_schema_path: &str) -> Result<Self, MachineError> {
+    let source = std::fs::read_to_string(machine_schema_path)
+      .map_err(|error| MachineError::Read { path: machine_schema_path.to_owned(), message: error.to_string() })?;
+
+    let machine_schema = serde_yaml::from_str::<MachineSchema>(&source)
+      .map_err(|error| MachineError::Syntax {
+        path: machine_schema_path.to_owned(),
+        line: error.location().map(|location| location.line()),
+        message: error.to_string(),
+      })?;
+
+    let mut issues = Vec::new();
+    let mut issue = |keys: &[&str], message: String| issues.push(SchemaIssue {
+      key: keys.join("."),
+      line: key_line(&source, keys, 0),
+      message,
+    });
+
+    let mut devices = MachineDeviceTable { 
+      devices: Vec::new(),
+      ram_disks: Vec::new(),
     };
+    let mut orders = BTreeMap::new();
+    // Block devices with size get blank image if there is none yet,
+    // made into filesystem if they have format
+    let mut images = Vec::new();
 
-    Self {
-      is_booted: false,
+    for key in machine_schema.machine.unknown.keys() {
+      issue(&["machine", key], String::from("unknown key (expected memory, cpus, clock, transcript or devices)"));
+    }
+
+    let mut resources = MachineResources::default();
+    if let Some(memory) = &machine_schema.machine.memory {
+      match parse_size(memory) {
+        Some(memory) if memory > 0 => resources.memory = memory,
+        _ => issue(&["machine", "memory"], String::from("invalid size (expected positive number with optional K, M or G)")),
+      }
+    }
+    if let Some(cpus) = &machine_schema.machine.cpus {
+      match cpus.parse::<u32>() {
+        Ok(cpus) if cpus > 0 => resources.cpus = cpus,
+        _ => issue(&["machine", "cpus"], String::from("expected positive number")),
+      }
+    }
+
+    let mut clock = MachineClock::default();
+    if let Some(clock_options) = &machine_schema.machine.clock {
+      for key in clock_options.keys().filter(|key| !["epoch", "speed"].contains(&key.as_str())) {
+        issue(&["machine", "clock", key], String::from("unknown option (expected epoch or speed)"));
+      }
+      match (clock_options.get("epoch"), clock_options.get("speed")) {
+        (Some(epoch), None) => match epoch.parse::<u64>() {
+          Ok(epoch) => clock = MachineClock::Fixed { epoch },
+          Err(_) => issue(&["machine", "clock", "epoch"], String::from("expected seconds since unix epoch")),
+        },
+        (None, Some(speed)) => match speed.parse::<f64>() {
+          Ok(speed) if speed > 0.0 && speed.is_finite() => clock = MachineClock::Scaled { speed },
+          _ => issue(&["machine", "clock", "speed"], String::from("expected positive number")),
+        },
+        (Some(_), Some(_)) => issue(&["machine", "clock"], String::from("clock has both epoch and speed (expected one of them)")),
+        (None, None) => issue(&["machine", "clock"], String::from("clock has no epoch or speed")),
+      }
+    }
+
+    // Relative to machine.yaml, like device paths
+    let transcript = machine_schema.machine.transcript.as_ref().and_then(|path| {
+      let transcript_path = Path::new(&machine_schema_path).parent().unwrap().join(path);
+      match transcript_path.parent().map_or(false, Path::is_dir) {
+        true => Some(transcript_path.to_string_lossy().into_owned()),
+        false => {
+          issue(&["machine", "transcript"], format!("no directory for transcript at {}", transcript_path.display()));
+          None
+        },
+      }
+    });
+
+    let no_devices = BTreeMap::new();
+    let schema_devices = match &machine_schema.machine.devices {
+      Some(schema_devices) => schema_devices,
+      None => {
+        issue(&["machine"], String::from("no devices"));
+        &no_devices
+      },
+    };
+
+    for (name, device) in schema_devices {
+      let keys = ["machine", "devices", name];
+      let option = |option: &'static str| [keys[0], keys[1], keys[2], option];
+
+      for key in device.keys().filter(|key| !DEVICE_OPTIONS.contains(&key.as_str())) {
+        issue(&[keys[0], keys[1], keys[2], key], format!("unknown option (expected one of {})", DEVICE_OPTIONS.join(", ")));
+      }
+
+      let r#type = match device.get("type").map(String::as_str) {
+        Some("block") => VirtualDeviceType::BlockDevice,
+        Some("tty") => VirtualDeviceType::TTYDevice,
+        Some("serial") => VirtualDeviceType::SerialDevice,
+        // RAM disks live in memory, so they have size instead of path
+        Some("ram") => {
+          match device.get("size").map(|size| parse_size(size)) {
+            Some(Some(size)) => devices.ram_disks.push(size),
+            Some(None) => issue(&option("size"), String::from("invalid size (expected number with optional K, M or G)")),
+            None => issue(&keys, String::from("ram device has no size")),
+          }
+          continue;
+        },
+        // Network interfaces have backend instead of path
+        Some("net") => {
+          let address = |option: &'static str| device
+            .get(option)
+            .map(|address| address.parse::<SocketAddr>().map_err(|_| address.to_owned()));
+          let backend = match (device.get("backend").map(String::as_str), address("bind"), address("peer")) {
+            (None | Some("loopback"), None, None) => Some(NetBackend::Loopback),
+            (None | Some("loopback"), _, _) => {
+              issue(&keys, String::from("bind and peer are only for udp backend"));
+              None
+            },
+            (Some("udp"), Some(Ok(bind)), Some(Ok(peer))) => Some(NetBackend::Udp { bind, peer }),
+            (Some("udp"), bind, peer) => {
+              for (key, address) in [("bind", bind), ("peer", peer)] {
+                match address {
+                  Some(Ok(_)) => (),
+                  Some(Err(address)) => issue(&option(key), format!("invalid address '{address}' (expected host:port)")),
+                  None => issue(&keys, format!("udp backend has no {key}")),
+                }
+              }
+              None
+            },
+            (Some(backend), _, _) => {
+              issue(&option("backend"), format!("unknown backend '{backend}' (expected loopback or udp)"));
+              None
+            },
+          };
+
+          if let Some(backend) = backend {
+            devices.devices.push(MachineDevice {
+              realpath: String::new(),
+              r#type: VirtualDeviceType::NetDevice,
+              read_only: false,
+              backend: Some(backend),
+            });
+          }
+          continue;
+        },
+        Some(device_type) => {
+          issue(&option("type"), format!("unknown device type '{device_type}' (expected block, tty, serial, net or ram)"));
+          continue;
+        },
+        None => {
+          issue(&keys, String::from("device has no type"));
+          continue;
+        },
+      };
+
+      let device_path = match device.get("path") {
+        Some(path) => Path::new(&machine_schema_path).parent().unwrap().join(path),
+        None => {
+          issue(&keys, String::from("device has no path"));
+          continue;
+        },
+      };
+      let realpath = String::from_str(device_path.to_str().unwrap()).unwrap();
+      if devices.devices.iter().any(|device: &MachineDevice| device.realpath == realpath) {
+        issue(&option("path"), format!("path {} is taken by another device", device_path.display()));
+      }
+
+      let read_only = match device.get("read-only").map(String::as_str) {
+        None | Some("false") => false,
+        Some("true") => true,
+        Some(_) => {
+          issue(&option("read-only"), String::from("expected true or false"));
+          false
+        },
+      };
+      if let Some(order) = device.get("order") {
+        match order.parse::<u32>() {
+          Ok(order) => {
+            orders.insert(realpath.clone(), order);
+          },
+          Err(_) => issue(&option("order"), String::from("expected non-negative number")),
+        }
+      }
+      let format = match (r#type, device.get("format").map(String::as_str)) {
+        (_, None) => None,
+        (VirtualDeviceType::BlockDevice, Some("e5fs")) => Some(FilesystemType::e5fs),
+        (VirtualDeviceType::BlockDevice, Some(format)) => {
+          issue(&option("format"), format!("cannot format as '{format}' (expected e5fs)"));
+          None
+        },
+        (_, Some(_)) => {
+          issue(&option("format"), String::from("only block devices can be formatted"));
+          None
+        },
+      };
+      if let (VirtualDeviceType::BlockDevice, Some(size)) = (r#type, device.get("size")) {
+        match parse_size(size) {
+          Some(size) if !device_path.exists() => images.push((option("size").join("."), device_path.clone(), size, format)),
+          Some(_) => (),
+          None => issue(&option("size"), String::from("invalid size (expected number with optional K, M or G)")),
+        }
+      } else if format.is_some() && !device_path.exists() {
+        issue(&keys, String::from("device has format but no size to create image of"));
+      }
+
+      devices.devices.push(MachineDevice {
+        realpath,
+        r#type,
+        read_only,
+        backend: None,
+      });
+    }
+
+    // RAM disks live in machine memory
+    let ram_disks_size = devices.ram_disks.iter().sum::<u64>();
+    if ram_disks_size > resources.memory {
+      issues.push(SchemaIssue {
+        key: String::from("machine.memory"),
+        line: key_line(&source, &["machine", "memory"], 0),
+        message: format!("RAM disks take {ram_disks_size} bytes, more than {} bytes of memory", resources.memory),
+      });
+    }
+
+    // Parsed map has the last of duplicates only, so look for them in source
+    for name in schema_devices.keys() {
+      let keys = ["machine", "devices", name];
+      if let Some(line) = key_line(&source, &keys, 1) {
+        issues.push(SchemaIssue {
+          key: keys.join("."),
+          line: Some(line),
+          message: String::from("duplicate device name"),
+        });
+      }
+    }
+
+    // Nothing is created on host for invalid machine
+    if issues.is_empty() {
+      for (key, device_path, size, format) in images {
+        let message = match std::fs::File::create(&device_path).and_then(|file| file.set_len(size)) {
+          Err(error) => format!("cannot create image at {}: {error}", device_path.display()),
+          Ok(()) => match format.map(|format| Self::format_image(&device_path, format)) {
+            Some(Err(errno)) => format!("cannot format image at {}: {errno}", device_path.display()),
+            Some(Ok(())) | None => continue,
+          },
+        };
+        let keys = key.split('.').collect::<Vec<_>>();
+        issues.push(SchemaIssue {
+          line: key_line(&source, &keys, 0),
+          key,
+          message,
+        });
+      }
+    }
+    if !issues.is_empty() {
+      return Err(MachineError::Invalid { path: machine_schema_path.to_owned(), issues });
+    }
+
+    devices.devices.sort_by_key(|device| {
+      let order = orders.get(&device.realpath).copied();
+      (order.is_none(), order, device.realpath.clone())
+    });
+
+    Ok(Self {
+      boots_count: 0,
       device_table: devices,
+      resources,
+      clock,
+      transcript,
+    })
+  }
+  /// Make filesystem of type `format` on blank image at `device_path`
+  fn format_image(device_path: &Path, format: FilesystemType) -> Result<(), Errno> {
+    let realpath = device_path.to_str().unwrap();
+    match format {
+      FilesystemType::e5fs => E5FSFilesystem::mkfs(realpath, 0.05, 4096).map(|_| ()),
+      format => Err(Errno::EINVAL(format!("cannot format {realpath} as {format}").into())),
     }
   }
   pub fn device_table(&self) -> &MachineDeviceTable {
     &self.device_table
   }
-  pub fn run(&self, os: OperatingSystem) {
+  pub fn resources(&self) -> MachineResources {
+    self.resources
+  }
+  pub fn clock(&self) -> MachineClock {
+    self.clock
+  }
+  pub fn transcript(&self) -> Option<&str> {
+    self.transcript.as_deref()
+  }
+  pub fn boots_count(&self) -> u32 {
+    self.boots_count
+  }
+  /// Boot `os` on this machine and run its init until it exits,
+  /// booting again with fresh kernel as long as it asks for reboot.
+  /// Returns: exit code of the last init, failure if boot failed
+  pub fn run(&mut self, mut os: OperatingSystem) -> AddressSize {
+    loop {
+      self.boots_count += 1;
+      if let Err(errno) = os.boot(self) {
+        println!("[{}]: boot failed: {errno}", kernel_message_header_err());
+        os.shutdown();
+        return EXIT_FAILURE;
+      }
+
+      let exit_code = os.start_init();
+      let power_action = os.kernel.power_action.lock().unwrap().take();
+      os.shutdown();
+
+      match power_action {
+        Some(PowerAction::Reboot) => os.reset(self),
+        Some(PowerAction::Poweroff) | None => return exit_code,
+      }
+    }
   }
 }
 
@@ -95,6 +538,187 @@ impl Machine {
 #[cfg(test)]
 mod tests {
     use crate::util::{mktemp, mkenxvd};
+    use super::*;
+
+  #[test]
+  fn ram_disks_are_parsed() {
+    let tempfile = mktemp().to_owned();
+    std::fs::write(&tempfile, "machine:\n  devices:\n    ram0:\n      type: ram\n      size: 4M\n    ram1:\n      type: ram\n      size: 512K\n").unwrap();
+
+    let machine = Machine::new(&tempfile).unwrap();
+
+    assert_eq!(machine.device_table().ram_disks, vec![4 * 1024 * 1024, 512 * 1024]);
+    assert!(machine.device_table().devices.is_empty());
+  }
+
+  #[test]
+  fn serial_devices_are_parsed() {
+    let tempfile = mktemp().to_owned();
+    std::fs::write(&tempfile, "machine:\n  devices:\n    serial0:\n      type: serial\n      path: ./serial0.log\n").unwrap();
+
+    let machine = Machine::new(&tempfile).unwrap();
+
+    assert_eq!(
+      machine.device_table().devices.iter().map(|device| device.r#type).collect::<Vec<_>>(),
+      vec![VirtualDeviceType::SerialDevice],
+    );
+  }
+
+  #[test]
+  fn device_options_are_parsed() {
+    let directory = mktemp().trim().to_owned() + ".d";
+    std::fs::create_dir(&directory).unwrap();
+    let schema_path = format!("{directory}/machine.yaml");
+    std::fs::write(&schema_path, concat!(
+      "machine:\n",
+      "  devices:\n",
+      "    cdrom:\n      type: block\n      path: ./a.iso\n      size: 2K\n      read-only: true\n",
+      "    disk2:\n      type: block\n      path: ./b.enxvd\n      size: 1K\n      order: 1\n",
+      "    disk1:\n      type: block\n      path: ./c.enxvd\n      size: 1K\n      order: 0\n",
+    )).unwrap();
+
+    let machine = Machine::new(&schema_path).unwrap();
+    let devices = &machine.device_table().devices;
+
+    // Ordered ones go first
+    assert!(devices[0].realpath.ends_with("c.enxvd"));
+    assert!(devices[1].realpath.ends_with("b.enxvd"));
+    assert!(devices[2].realpath.ends_with("a.iso"));
+    assert_eq!(devices.iter().map(|device| device.read_only).collect::<Vec<_>>(), vec![false, false, true]);
+    // Missing images are created
+    assert_eq!(std::fs::metadata(&devices[2].realpath).unwrap().len(), 2 * 1024);
+  }
+
+  #[test]
+  fn missing_images_are_formatted() {
+    let directory = mktemp().trim().to_owned() + ".d";
+    std::fs::create_dir(&directory).unwrap();
+    let schema_path = format!("{directory}/machine.yaml");
+    std::fs::write(&schema_path, "machine:\n  devices:\n    disk1:\n      type: block\n      path: ./a.enxvd\n      size: 1M\n      format: e5fs\n").unwrap();
+
+    let machine = Machine::new(&schema_path).unwrap();
+
+    assert!(E5FSFilesystem::from(&machine.device_table().devices[0].realpath).is_ok());
+
+    std::fs::write(&schema_path, "machine:\n  devices:\n    disk2:\n      type: block\n      path: ./b.enxvd\n      format: ext4\n").unwrap();
+
+    assert!(matches!(
+      Machine::new(&schema_path),
+      Err(MachineError::Invalid { issues, .. }) if issues.iter().any(|issue| issue.key == "machine.devices.disk2.format")
+    ));
+  }
+
+  #[test]
+  fn net_devices_are_parsed() {
+    let tempfile = mktemp().to_owned();
+    std::fs::write(&tempfile, concat!(
+      "machine:\n",
+      "  devices:\n",
+      "    eth0:\n      type: net\n",
+      "    eth1:\n      type: net\n      backend: udp\n      bind: 127.0.0.1:5000\n      peer: 127.0.0.1:5001\n",
+    )).unwrap();
+
+    let machine = Machine::new(&tempfile).unwrap();
+
+    assert_eq!(
+      machine.device_table().devices.iter().map(|device| device.backend.clone()).collect::<Vec<_>>(),
+      vec![
+        Some(NetBackend::Loopback),
+        Some(NetBackend::Udp { bind: "127.0.0.1:5000".parse().unwrap(), peer: "127.0.0.1:5001".parse().unwrap() }),
+      ],
+    );
+
+    std::fs::write(&tempfile, "machine:\n  devices:\n    eth0:\n      type: net\n      backend: udp\n      bind: nowhere\n").unwrap();
+    let keys = match Machine::new(&tempfile) {
+      Err(MachineError::Invalid { issues, .. }) => issues.into_iter().map(|issue| issue.key).collect::<Vec<_>>(),
+      result => panic!("expected invalid machine, got {result:?}"),
+    };
+    assert_eq!(keys, vec!["machine.devices.eth0.bind", "machine.devices.eth0"]);
+  }
+
+  #[test]
+  fn invalid_schemas_are_reported() {
+    let tempfile = mktemp().to_owned();
+    std::fs::write(&tempfile, concat!(
+      "machine:\n",
+      "  devices:\n",
+      "    disk1:\n      type: blok\n      path: ./a.enxvd\n",
+      "    tty1:\n      type: tty\n",
+      "    ram0:\n      type: ram\n      size: lots\n      colour: red\n",
+      "    tty1:\n      type: tty\n      path: ./tty1.enxtty\n",
+    )).unwrap();
+
+    let issues = match Machine::new(&tempfile) {
+      Err(MachineError::Invalid { issues, .. }) => issues,
+      result => panic!("expected invalid machine, got {result:?}"),
+    };
+    let issues = issues
+      .iter()
+      .map(|SchemaIssue { key, line, .. }| (key.as_str(), *line))
+      .collect::<Vec<_>>();
+
+    assert_eq!(issues, vec![
+      ("machine.devices.disk1.type", Some(4)),
+      ("machine.devices.ram0.colour", Some(11)),
+      ("machine.devices.ram0.size", Some(10)),
+      ("machine.devices.tty1", Some(12)),
+    ]);
+  }
+
+  #[test]
+  fn resources_are_parsed() {
+    let tempfile = mktemp().to_owned();
+    std::fs::write(&tempfile, "machine:\n  memory: 8M\n  cpus: 2\n  devices:\n    ram0:\n      type: ram\n      size: 4M\n").unwrap();
+
+    assert_eq!(Machine::new(&tempfile).unwrap().resources(), MachineResources { memory: 8 * 1024 * 1024, cpus: 2 });
+
+    std::fs::write(&tempfile, "machine:\n  memory: 2M\n  devices:\n    ram0:\n      type: ram\n      size: 4M\n").unwrap();
+
+    assert!(matches!(
+      Machine::new(&tempfile),
+      Err(MachineError::Invalid { issues, .. }) if issues[0].key == "machine.memory" && issues[0].line == Some(2)
+    ));
+  }
+
+  #[test]
+  fn clock_is_parsed() {
+    let tempfile = mktemp().to_owned();
+    std::fs::write(&tempfile, "machine:\n  clock:\n    epoch: 86400\n  devices:\n    ram0:\n      type: ram\n      size: 4M\n").unwrap();
+
+    assert_eq!(Machine::new(&tempfile).unwrap().clock(), MachineClock::Fixed { epoch: 86400 });
+
+    std::fs::write(&tempfile, "machine:\n  clock:\n    epoch: 0\n    speed: 2\n  devices:\n    ram0:\n      type: ram\n      size: 4M\n").unwrap();
+
+    assert!(matches!(
+      Machine::new(&tempfile),
+      Err(MachineError::Invalid { issues, .. }) if issues[0].key == "machine.clock" && issues[0].line == Some(2)
+    ));
+  }
+
+  #[test]
+  fn transcript_is_relative_to_schema() {
+    let directory = mktemp().trim().to_owned() + ".d";
+    std::fs::create_dir(&directory).unwrap();
+    let schema_path = format!("{directory}/machine.yaml");
+    std::fs::write(&schema_path, "machine:\n  transcript: ./tty1.transcript\n  devices:\n    ram0:\n      type: ram\n      size: 4M\n").unwrap();
+
+    assert_eq!(Machine::new(&schema_path).unwrap().transcript(), Some(format!("{directory}/./tty1.transcript").as_str()));
+
+    std::fs::write(&schema_path, "machine:\n  transcript: ./nowhere/tty1.transcript\n  devices:\n    ram0:\n      type: ram\n      size: 4M\n").unwrap();
+
+    assert!(matches!(
+      Machine::new(&schema_path),
+      Err(MachineError::Invalid { issues, .. }) if issues[0].key == "machine.transcript"
+    ));
+  }
+
+  #[test]
+  fn syntax_errors_have_line() {
+    let tempfile = mktemp().to_owned();
+    std::fs::write(&tempfile, "machine:\n  devices:\n    disk1: [\n").unwrap();
+
+    assert!(matches!(Machine::new(&tempfile), Err(MachineError::Syntax { line: Some(_), .. })));
+  }
 
   #[test]
   fn lookup_path_works() {
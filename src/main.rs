@@ -1,284 +1,90 @@
-#![feature(type_alias_impl_trait)]
-#![feature(trait_alias)]
-#![feature(const_fmt_arguments_new)]
-#![feature(let_chains)]
-
-mod eunix;
-mod machine;
-mod util;
-mod binaries;
-
-use fancy_regex::Regex;
-use machine::{Machine, OperatingSystem};
-use sha2::{Sha256, Digest};
-use std::io::*;
-use crate::{eunix::{fs::{Filesystem, FileModeType, EVERYTHING, Id}, kernel::{KERNEL_MESSAGE_HEADER_ERR, KernelParams, Errno, ROOT_UID, ROOT_GID}, binfs::BinFilesytem, users::Passwd, e5fs::E5FSFilesystem}, machine::VirtualDeviceType, binaries::{EXIT_SUCCESS, PASSWD_PATH}};
+use clap::Parser;
+use eunix::config::HostConfig;
+use eunix::console;
+use eunix::machine::Machine;
+use eunix::os::{OperatingSystem, DEFAULT_INIT};
 use std::path::Path;
 
-pub fn main() {
-  let machine = Machine::new(
-    Path::new(env!("CARGO_MANIFEST_DIR")).join("machines/1/machine.yaml")
-      .to_str()
-      .unwrap()
-  );
-  let mut os = OperatingSystem {
-    kernel: eunix::kernel::Kernel::new(machine.device_table(), KernelParams {
-      init: String::from("/bin/init"),
-    }),
-  };
-
-  let (sda1_realpath, _) = machine
-    .device_table()
-    .devices
-    .iter()
-    .take(1)
-    .find(|(_realpath, &dev_type)| dev_type == VirtualDeviceType::BlockDevice)
-    .unwrap();
-
-  // E5FSFilesystem::mkfs(sda1_realpath, 0.05, 4096).unwrap();
-
-
-  os.kernel.mount("", "/dev", eunix::fs::FilesystemType::devfs).unwrap();
-  os.kernel.mount("/dev/sda", "/", eunix::fs::FilesystemType::e5fs).unwrap();
-
+/// Run eunix on a virtual machine
+#[derive(Debug, Parser)]
+#[clap(name = "eunix", version)]
+struct HostArgs {
+  /// Host config with defaults for these flags,
+  /// `~/.config/eunix/config.toml` by default
+  #[clap(short, long)]
+  config: Option<String>,
+
+  /// Path to machine.yaml on host, `machines/1/machine.yaml` of the source tree by default
+  #[clap(short, long)]
+  machine: Option<String>,
+
+  /// Pathname of first program to run in eunix
+  #[clap(short, long, default_value = DEFAULT_INIT)]
+  init: String,
+
+  /// Host file to restore /bin from on boot and save it to on shutdown
+  #[clap(short, long)]
+  snapshot: Option<String>,
+
+  /// Run headless: type commands from this host file into the console
+  /// as root, then exit with exit code of the last one
+  #[clap(long)]
+  script: Option<String>,
+
+  /// Events of kernel and filesystems to print to stderr of host:
+  /// error, warn, info, debug or trace. `warn` by default
+  #[clap(long)]
+  log_level: Option<tracing::Level>,
+}
 
-  os.kernel.mount("", "/bin", eunix::fs::FilesystemType::binfs).unwrap();
+fn exit_with(error: impl std::fmt::Display) -> ! {
+  println!("{error}");
+  std::process::exit(1);
+}
 
-  // let e5fs = os
-  //   .kernel
-  //   .vfs
-  //   .mount_points
-  //   .get_mut("/")
-  //   .unwrap()
-  //   .driver
-  //   .as_any()
-  //   .downcast_mut::<E5FSFilesystem>()
-  //   .unwrap();
-  // e5fs.change_owners("/proc", ROOT_UID, ROOT_GID).unwrap();
-  // e5fs.change_owners("/root", ROOT_UID, ROOT_GID).unwrap();
-  // e5fs.change_owners("/dev", ROOT_UID, ROOT_GID).unwrap();
-  // e5fs.change_owners("/sys", ROOT_UID, ROOT_GID).unwrap();
-  // e5fs.change_owners("/bin", ROOT_UID, ROOT_GID).unwrap();
-  // e5fs.create_dir("/proc").unwrap();
-  // e5fs.create_dir("/root").unwrap();
-  // e5fs.create_dir("/dev").unwrap();
-  // e5fs.create_dir("/sys").unwrap();
-  // e5fs.create_dir("/bin").unwrap();
-  // drop(e5fs);
+pub fn main() {
+  let args = HostArgs::parse();
+  let config = HostConfig::load(args.config.as_deref()).unwrap_or_else(|error| exit_with(error));
 
-  if let Err(errno) = os.kernel.update_uid_gid_maps() {
-    println!("[{KERNEL_MESSAGE_HEADER_ERR}]: cannot update '{PASSWD_PATH}': {errno:?}");
+  let log_level = match (args.log_level, &config.log_level) {
+    (Some(log_level), _) => log_level,
+    (None, Some(log_level)) => log_level.parse().unwrap_or_else(|_| exit_with(format!("config: unknown log_level: {log_level}"))),
+    (None, None) => tracing::Level::WARN,
+  };
+  tracing_subscriber::fmt()
+    .with_max_level(log_level)
+    .with_writer(std::io::stderr)
+    .with_ansi(config.color)
+    .init();
+  console::set_color(config.color);
+
+  let machine_path = args.machine.or(config.machine.clone()).unwrap_or_else(|| {
+    Path::new(env!("CARGO_MANIFEST_DIR"))
+      .join("machines/1/machine.yaml")
+      .to_string_lossy()
+      .into_owned()
+  });
+  let mut machine = Machine::new(&machine_path).unwrap_or_else(|error| exit_with(error));
+  let mut os = OperatingSystem::new(&machine, &args.init)
+    .with_console(console::host_console());
+  if let Some(snapshot) = args.snapshot {
+    os = os.with_snapshot(&snapshot);
+  } else if let Some(snapshot) = config.snapshot_for(&machine_path) {
+    if let Some(snapshot_dir) = &config.snapshot_dir {
+      std::fs::create_dir_all(snapshot_dir)
+        .unwrap_or_else(|error| exit_with(format!("config: cannot create {snapshot_dir}: {error}")));
+    }
+    os = os.with_snapshot(&snapshot);
   }
-
-  // let eunix_inode = os.kernel.vfs.create_file("/mnt").unwrap();
-  // let mnt_inode = os.kernel.vfs.create_dir("/mnt").unwrap();
-  // assert_eq!(mnt_inode.number, 1, "mnt_inode should be 1");
-  // let mnt_eblan_inode = os.kernel.vfs.create_dir("/mnt/eblan").unwrap();
-  // assert_eq!(mnt_eblan_inode.number, 2, "mnt_eblan_inode should be 2");
-
-  let binfs = os
-    .kernel
-    .vfs
-    .mount_points
-    .get_mut("/bin")
-    .unwrap()
-    .driver
-    .as_any()
-    .downcast_mut::<BinFilesytem>()
-    .unwrap();
-  binfs.add_bins(vec![
-    (String::from("/ls"),           binaries::ls),        // [x]
-    (String::from("/stat"),         binaries::stat),      // [x]
-    (String::from("/df"),           binaries::df),        // [ ]
-    (String::from("/du"),           binaries::du),        // [ ]
-    (String::from("/cat"),          binaries::cat),       // [x]
-    (String::from("/mkfs.e5fs"),    binaries::mkfs_e5fs), // [x]
-    (String::from("/mkdir"),        binaries::mkdir),     // [x]
-    (String::from("/rmdir"),        binaries::rmdir),     // [ ]
-    (String::from("/touch"),        binaries::touch),     // [x]
-    (String::from("/rm"),           binaries::rm),        // [x]
-    (String::from("/mv"),           binaries::mv),        // [x]
-    (String::from("/cp"),           binaries::cp),        // [x]
-    (String::from("/write"),        binaries::write),     // [x]
-    (String::from("/ed"),           binaries::ed),        // [x]
-    (String::from("/chmod"),        binaries::chmod),     // [x]
-    (String::from("/chown"),        binaries::chown),     // [x]
-    (String::from("/uname"),        binaries::uname),     // [x]
-    (String::from("/mount"),        binaries::mount),     // [x]
-    (String::from("/lsblk"),        binaries::lsblk),     // [x]
-    (String::from("/passwd"),       binaries::passwd),    // [x]
-    (String::from("/id"),           binaries::id),        // [x]
-    (String::from("/whoami"),       binaries::whoami),    // [x]
-    (String::from("/su"),           binaries::su),        // [x]
-    (String::from("/useradd"),      binaries::useradd),   // [x]
-    (String::from("/usermod"),      binaries::usermod),   // [ ]
-    (String::from("/userdel"),      binaries::userdel),   // [x]
-    (String::from("/groupmod"),     binaries::groupmod),  // [ ]
-    (String::from("/groupdel"),     binaries::groupdel),  // [ ]
-  ]).expect("we know that we have enough inodes and there is no dublicates");
-
-  let mut input_username = String::new();
-  let mut input_password = String::new();
-
-  // print!("{}[2J", 27 as char);
-  std::process::Command::new("clear").status().unwrap();
-  println!("Eunix v1.0.0 (tty1)");
-  println!();
-
-  ////////////////////////////////////////////////////////////////////
-
-  match os.kernel.vfs.read_file(PASSWD_PATH, EVERYTHING) {
-    Ok(bytes) => {
-      loop {
-        print!("eunix login: ");
-        stdout().flush().unwrap();
-        stdin().read_line(&mut input_username).unwrap();
-        print!("Password: ");
-        stdout().flush().unwrap();
-        stdin().read_line(&mut input_password).unwrap();
-        let input_username = input_username.trim();
-        // let input_password = input_password.trim();
-        let input_password = hex::encode(Sha256::digest(&input_password.as_bytes()));
-        let contents = String::from_utf8(bytes.clone()).unwrap();
-        let passwds = Passwd::parse_passwds(&contents);
-
-        match passwds.iter().find(|&p| p.name == input_username) {
-          Some(Passwd { password, uid, gid, .. }) => {
-            if *password == input_password {
-              os.kernel.current_uid = *uid;
-              os.kernel.current_gid = *gid;
-              os.kernel.update_vfs_current_uid_gid();
-              break;
-            }
-          },
-          None => {
-          },
-        }
-        println!("Login incorrect");
-        println!();
-      }
-    },
-    Err(Errno::ENOENT(_)) => {
-      println!("login: {PASSWD_PATH} does not exist, logging as root");
-    },
-    Err(errno) => {
-      println!("login: unexpected error: {errno:?}");
-    },
+  if let Some(editor) = &config.editor {
+    os = os.with_editor(editor);
   }
-
-  ////////////////////////////////////////////////////////////////////
-
-  fn caret_by_uid(uid: Id) -> String {
-    if uid == ROOT_UID {
-      String::from("#")
-    } else {
-      String::from("$")
-    }
+  if let Some(script) = args.script {
+    os = os.with_script(&script);
   }
 
-  // Shell vars
-  let ifs = ' ';
-  let mut ps1 = format!("({: >3}) {} ", 0, caret_by_uid(os.kernel.current_uid));
-  let mut pwd = String::from("/");
-  let path = String::from("/usr/bin:/bin");
-
-  let mut command = String::new();
-
-  loop {
-    // A basic REPL prompt
-    command.clear();
-    print!("{ps1}");
-    stdout().flush().unwrap();
-    stdin().read_line(&mut command).unwrap();
-
-    // Parse args
-    let args = command
-      .trim() // Trim leading newline
-      .split(ifs) // Split by IFS (space)
-      .collect::<Vec<&str>>(); // Collect as [arg0, arg1, arg2, ...]
-
-    /* Execute command
-     * args[0] - program (or builtin) pathname/name 
-     * args[1..] - arguments 
-    */
-    match args[0] {
-      /* Echo buintin */
-      "echo" => {
-        let args = args[1..].join(" ");
-        println!("{args}");
-      },
-
-      /* Cd buintin */
-      "cd" => {
-        let pathname = args[1];
-        
-        match os.kernel.vfs.lookup_path(pathname) {
-          Ok(vinode) => {
-            if vinode.mode.file_type() == FileModeType::Dir as u8 {
-              pwd = pathname.to_owned();
-            } else {
-              eprintln!("cd: not a directory: {pathname}")
-            }
-          },
-          Err(Errno::ENOENT(_)) => {
-            eprintln!("cd: no such file or directory: {pathname}")
-          },
-          Err(errno) => {
-            eprintln!("cd: unexpected kernel error occured while looking for {pathname}: {errno:?}")
-          },
-        }
-      },
-
-      /* Pwd (print working directory) buintin */
-      "pwd" => {
-        println!("{pwd}");
-      },
-
-      /* Exit buintin */
-      "exit" => break,
-
-      /* No builtin matched - run pathname */
-      command => {
-        // Calculate pathname
-        // Match command against PATH: 
-        // if (found in PATH) -> return new pathname
-        // otherwise          -> return command literally
-        let pathname = if Regex::new("^[_\\.a-zA-Z][^\\/\\n]*$")
-          .unwrap()
-          .is_match(command)
-          .unwrap()
-        {
-          if let Some(pathname) = path
-            .split(':')
-            .find_map(|location_pathname| {
-              let pathname = format!("{location_pathname}/{command}");
-              os.kernel.vfs.lookup_path(&pathname).ok().and_then(|_| Some(pathname))
-            })
-          {
-            pathname
-          } else {
-            command.to_string()
-          }
-        } else {
-          command.to_string()
-        };
-        
-        // Execute calculated pathname
-        match os.kernel.exec(&pathname, args.as_ref()) {
-          Ok(exit_code) => {
-            // println!("[{KERNEL_MESSAGE_HEADER_ERR}]: program finished with exit code {exit_code}");
-            ps1 = format!("({exit_code: >3}) {} ", caret_by_uid(os.kernel.current_uid));
-          },
-          Err(Errno::ENOENT(_)) => {
-            println!("sh: no such file or directory: {pathname}");
-          },
-          Err(errno) => {
-            println!("[{KERNEL_MESSAGE_HEADER_ERR}]: kernel can't exec {pathname}: ERRNO: {errno:?}");
-          },
-        }
-      }
-    }
-  }
+  let exit_code = machine.run(os);
+  std::process::exit(exit_code as i32);
 }
 
 // vim:ts=2 sw=2
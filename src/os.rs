@@ -0,0 +1,718 @@
+use std::sync::{Arc, MappedMutexGuard, MutexGuard, RwLock};
+
+use fancy_regex::Regex;
+use itertools::Itertools;
+use tracing::Level;
+
+use crate::binaries::{
+  self, PASSWD_PATH, GROUP_PATH, CONSOLE_PATH, TTY_PATH, EXIT_SUCCESS, EXIT_FAILURE, EXIT_ENOENT,
+  authenticate, account_status, prompt_line, edit_line, kprintln, keprintln,
+};
+use crate::eunix::{
+  binfs::{BinFilesytem, BinaryFn},
+  console::{self, ConsoleBackend},
+  line_editor::LineEditor,
+  devices::{HostTTY, ScriptTTY, TranscriptTTY},
+  fs::{Filesystem, FileModeType, FilesystemType, AddressSize, Id, EVERYTHING, PERM_X},
+  kernel::{Kernel, KernelParams, Errno, ErrnoContext, kernel_message_header_err, ROOT_UID},
+  records::{self, WTMP_PATH},
+  users::{Passwd, AccountStatus},
+};
+use crate::machine::Machine;
+
+/// Filesystems to mount after root, one per line:
+/// `<source> <target> <fs_type>`, like on Linux without options
+pub const FSTAB_PATH: &'static str = "/etc/fstab";
+/// Device that root filesystem is on
+pub const ROOT_DEVICE: &'static str = "/dev/sda";
+/// Where binaries are registered
+pub const BIN_PATH: &'static str = "/bin";
+/// Built-in init: login prompt and shell on console
+pub const DEFAULT_INIT: &'static str = "/bin/init";
+
+/// Line of /etc/fstab
+#[derive(Debug, PartialEq, Eq)]
+pub struct FstabEntry {
+  pub source: String,
+  pub target: String,
+  pub fs_type: FilesystemType,
+}
+
+impl FstabEntry {
+  /// Parse `<source> <target> <fs_type> [options...]`
+  /// lines - comments, blank and invalid ones omitted
+  pub fn parse_fstab(string: &str) -> Vec<FstabEntry> {
+    string
+      .lines()
+      .map(str::trim)
+      .filter(|line| !line.is_empty() && !line.starts_with('#'))
+      .flat_map(|line| {
+        let mut split = line.split_whitespace();
+
+        Some(FstabEntry {
+          source: split.next()?.to_owned(),
+          target: split.next()?.to_owned(),
+          fs_type: split.next()?.parse().ok()?,
+        })
+      })
+      .collect()
+  }
+}
+
+/// Filesystems mounted after root when there is no /etc/fstab
+fn default_fstab() -> Vec<FstabEntry> {
+  vec![
+    FstabEntry { source: String::new(), target: String::from(BIN_PATH), fs_type: FilesystemType::binfs },
+    FstabEntry { source: String::new(), target: String::from("/proc"), fs_type: FilesystemType::procfs },
+    FstabEntry { source: String::new(), target: String::from("/sys"), fs_type: FilesystemType::sysfs },
+  ]
+}
+
+/// Binaries registered in binfs on /bin at boot
+fn default_binaries() -> Vec<(String, BinaryFn)> {
+  vec![
+    (String::from("/ls"),           binaries::ls),        // [x]
+    (String::from("/stat"),         binaries::stat),      // [x]
+    (String::from("/test"),         binaries::test),      // [x]
+    (String::from("/df"),           binaries::df),        // [x]
+    (String::from("/sync"),         binaries::sync),      // [x]
+    (String::from("/du"),           binaries::du),        // [ ]
+    (String::from("/cat"),          binaries::cat),       // [x]
+    (String::from("/mkfs.e5fs"),    binaries::mkfs_e5fs), // [x]
+    (String::from("/fsck.e5fs"),    binaries::fsck_e5fs), // [x]
+    (String::from("/resize.e5fs"),  binaries::resize_e5fs), // [x]
+    (String::from("/blkid"),        binaries::blkid), // [x]
+    (String::from("/dumpe5fs"),     binaries::dumpe5fs), // [x]
+    (String::from("/snapshot"),     binaries::snapshot), // [x]
+    (String::from("/undelete"),     binaries::undelete), // [x]
+    (String::from("/inotifywait"),  binaries::inotifywait), // [x]
+    (String::from("/mkdir"),        binaries::mkdir),     // [x]
+    (String::from("/mknod"),        binaries::mknod),     // [x]
+    (String::from("/fdisk"),        binaries::fdisk),     // [x]
+    (String::from("/losetup"),      binaries::losetup),   // [x]
+    (String::from("/rmdir"),        binaries::rmdir),     // [ ]
+    (String::from("/touch"),        binaries::touch),     // [x]
+    (String::from("/truncate"),     binaries::truncate),  // [x]
+    (String::from("/rm"),           binaries::rm),        // [x]
+    (String::from("/mv"),           binaries::mv),        // [x]
+    (String::from("/cp"),           binaries::cp),        // [x]
+    (String::from("/ln"),           binaries::ln),        // [x]
+    (String::from("/readlink"),     binaries::readlink),  // [x]
+    (String::from("/write"),        binaries::write),     // [x]
+    (String::from("/ed"),           binaries::ed),        // [x]
+    (String::from("/chmod"),        binaries::chmod),     // [x]
+    (String::from("/chown"),        binaries::chown),     // [x]
+    (String::from("/getfattr"),     binaries::getfattr),  // [x]
+    (String::from("/setfattr"),     binaries::setfattr),  // [x]
+    (String::from("/uname"),        binaries::uname),     // [x]
+    (String::from("/mount"),        binaries::mount),     // [x]
+    (String::from("/netfsd"),       binaries::netfsd),    // [x]
+    (String::from("/nc"),           binaries::nc),        // [x]
+    (String::from("/ping"),         binaries::ping),      // [x]
+    (String::from("/lsblk"),        binaries::lsblk),     // [x]
+    (String::from("/passwd"),       binaries::passwd),    // [x]
+    (String::from("/id"),           binaries::id),        // [x]
+    (String::from("/whoami"),       binaries::whoami),    // [x]
+    (String::from("/kill"),         binaries::kill),      // [x]
+    (String::from("/free"),         binaries::free),      // [x]
+    (String::from("/dmesg"),        binaries::dmesg),     // [x]
+    (String::from("/reboot"),       binaries::reboot),    // [x]
+    (String::from("/poweroff"),     binaries::poweroff),  // [x]
+    (String::from("/who"),          binaries::who),       // [x]
+    (String::from("/last"),         binaries::last),      // [x]
+    (String::from("/su"),           binaries::su),        // [x]
+    (String::from("/useradd"),      binaries::useradd),   // [x]
+    (String::from("/usermod"),      binaries::usermod),   // [ ]
+    (String::from("/userdel"),      binaries::userdel),   // [x]
+    (String::from("/groupmod"),     binaries::groupmod),  // [ ]
+    (String::from("/groupdel"),     binaries::groupdel),  // [ ]
+  ]
+}
+
+/// What `word` before cursor can be completed to: a program in `path`
+/// if it's the first one in `line`, a pathname otherwise.
+/// Directories end with '/'
+fn complete_word(kernel: &mut Kernel, path: &str, pwd: &str, line: &str, word: &str) -> Vec<String> {
+  let (directories, prefix) = match word.rsplit_once('/') {
+    Some((directory, _)) => (vec![format!("{directory}/")], format!("{directory}/")),
+    None if line.trim_start() == word => (path.split(':').map(|directory| format!("{directory}/")).collect(), String::new()),
+    None => (vec![format!("{}/", pwd.trim_end_matches('/'))], String::new()),
+  };
+  let name_start = &word[prefix.len()..];
+
+  let names = directories
+    .iter()
+    .filter_map(|directory| Some((directory, kernel.vfs().read_dir(directory).ok()?)))
+    .flat_map(|(directory, entries)| entries.entries
+      .into_keys()
+      .filter(|name| name != "." && name != ".." && name.starts_with(name_start))
+      .map(|name| (format!("{directory}{name}"), name))
+      .collect::<Vec<_>>())
+    .collect::<Vec<_>>();
+
+  names
+    .into_iter()
+    .map(|(pathname, name)| match kernel.vfs().stat(&pathname) {
+      Ok(stat) if stat.mode.file_type() == FileModeType::Dir as u8 => format!("{prefix}{name}/"),
+      _ => format!("{prefix}{name}"),
+    })
+    .unique()
+    .collect()
+}
+
+fn caret_by_uid(uid: Id) -> String {
+  if uid == ROOT_UID {
+    String::from("#")
+  } else {
+    String::from("$")
+  }
+}
+
+#[derive(Debug)]
+pub struct OperatingSystem {
+  pub kernel: Kernel,
+  /// Pathname of first program to run, see `DEFAULT_INIT`
+  pub init: String,
+  /// Host file that binfs on /bin is restored from on boot
+  /// and saved to on shutdown
+  pub snapshot: Option<String>,
+  /// Host file typed into console instead of stdin, see `ScriptTTY`
+  pub script: Option<String>,
+  /// Console that `script` is typed into, kept between reboots
+  /// so that script goes on where it stopped
+  script_tty: Option<Arc<RwLock<ScriptTTY>>>,
+  /// Terminal that console is shown on, terminal of host if `None`
+  console: Option<Arc<RwLock<dyn ConsoleBackend>>>,
+  /// Editor of host for `ed`, when EDITOR is not set
+  editor: Option<String>,
+}
+
+impl OperatingSystem {
+  /// Kernel for `machine`, not booted yet
+  pub fn new(machine: &Machine, init: &str) -> Self {
+    Self {
+      kernel: Self::new_kernel(machine, init),
+      init: init.to_owned(),
+      snapshot: None,
+      script: None,
+      script_tty: None,
+      console: None,
+      editor: None,
+    }
+  }
+
+  fn new_kernel(machine: &Machine, init: &str) -> Kernel {
+    Kernel::new(machine.device_table(), KernelParams {
+      init: init.to_owned(),
+      resources: machine.resources(),
+      clock: machine.clock(),
+    })
+  }
+
+  /// Start over with fresh kernel, to boot again after `shutdown`
+  pub fn reset(&mut self, machine: &Machine) {
+    self.kernel = Self::new_kernel(machine, &self.init);
+  }
+
+  /// Keep /bin in host file at `realpath` between runs
+  pub fn with_snapshot(mut self, realpath: &str) -> Self {
+    self.snapshot = Some(realpath.to_owned());
+    self
+  }
+
+  /// Run headless: console reads commands from host file
+  /// at `realpath`, logged in as root
+  pub fn with_script(mut self, realpath: &str) -> Self {
+    self.script = Some(realpath.to_owned());
+    self
+  }
+
+  /// Show console on `console` instead of terminal of host,
+  /// for frontends like terminal emulator in a browser
+  pub fn with_console(mut self, console: Arc<RwLock<dyn ConsoleBackend>>) -> Self {
+    self.console = Some(console);
+    self
+  }
+
+  /// Open files edited with `ed` in `editor` of host,
+  /// unless EDITOR says otherwise
+  pub fn with_editor(mut self, editor: &str) -> Self {
+    self.editor = Some(editor.to_owned());
+    self
+  }
+
+  /// Bring the system up on `machine`: mount devfs and root,
+  /// then everything from /etc/fstab (or defaults) and register binaries
+  pub fn boot(&mut self, machine: &Machine) -> Result<(), Errno> {
+    self.kernel.host_editor = self.editor.clone();
+    for device in machine.device_table().devices.iter() {
+      self.kernel.log(Level::INFO, &format!("probed {:?} at {}", device.r#type, device.realpath));
+    }
+
+    self.kernel.mount("", "/dev", FilesystemType::devfs)?;
+    self.kernel.log(Level::INFO, "mounted devfs on /dev");
+    self.kernel.log(Level::INFO, &format!("boot #{}", machine.boots_count()));
+    if let Some(console) = self.console.clone() {
+      self.kernel.replace_tty_driver(CONSOLE_PATH, Arc::new(RwLock::new(HostTTY::new(CONSOLE_PATH, console))))?;
+    }
+    if let Some(realpath) = self.script.clone() {
+      let script_tty = match (&self.script_tty, self.console.clone()) {
+        (Some(script_tty), _) => script_tty.clone(),
+        (None, Some(console)) => Arc::new(RwLock::new(ScriptTTY::open(&realpath)?.with_console(console))),
+        (None, None) => Arc::new(RwLock::new(ScriptTTY::open(&realpath)?)),
+      };
+      self.script_tty = Some(script_tty.clone());
+      self.kernel.replace_tty_driver(CONSOLE_PATH, script_tty)?;
+      self.kernel.log(Level::INFO, &format!("attached {realpath} to {CONSOLE_PATH}"));
+    }
+    if let Some(realpath) = machine.transcript() {
+      let console = self.kernel.tty_driver(CONSOLE_PATH)?;
+      self.kernel.replace_tty_driver(CONSOLE_PATH, Arc::new(RwLock::new(TranscriptTTY::new(realpath, console, self.kernel.clock.clone()))))?;
+      self.kernel.log(Level::INFO, &format!("logging {CONSOLE_PATH} to {realpath}"));
+    }
+    self.kernel.set_controlling_tty(CONSOLE_PATH)?;
+    self.kernel.mount(ROOT_DEVICE, "/", FilesystemType::e5fs)?;
+    self.kernel.log(Level::INFO, &format!("mounted {ROOT_DEVICE} on /"));
+    self.kernel.open_stdio(CONSOLE_PATH)?;
+
+    let fstab = match self.kernel.vfs().read_file(FSTAB_PATH, EVERYTHING) {
+      Ok(bytes) => FstabEntry::parse_fstab(&String::from_utf8_lossy(&bytes)),
+      Err(Errno::ENOENT(_)) => default_fstab(),
+      Err(errno) => return Err(errno),
+    };
+    for FstabEntry { source, target, fs_type } in fstab {
+      // Root and devfs are there already
+      if target == "/" || target == "/dev" {
+        continue;
+      }
+
+      let name = fs_type.to_string();
+      match self.kernel.mount(&source, &target, fs_type) {
+        Ok(()) => self.kernel.log(Level::INFO, &format!("mounted {name} on {target}")),
+        Err(errno) => self.kernel.log(Level::WARN, &format!("cannot mount {name} on {target}: {errno}")),
+      }
+    }
+
+    if let Some(realpath) = self.snapshot.clone() {
+      match self.restore_snapshot(&realpath) {
+        Ok(true) => self.kernel.log(Level::INFO, &format!("restored {BIN_PATH} from {realpath}")),
+        Ok(false) => (),
+        Err(errno) => {
+          println!("[{}]: cannot restore snapshot {realpath}: {errno}", kernel_message_header_err());
+          self.kernel.log(Level::ERROR, &format!("cannot restore snapshot {realpath}: {errno}"));
+        },
+      }
+    }
+    self.register_binaries()?;
+
+    if let Err(errno) = self.kernel.update_uid_gid_maps() {
+      println!("[{}]: cannot update '{PASSWD_PATH}': {errno}", kernel_message_header_err());
+      self.kernel.log(Level::WARN, &format!("cannot update '{PASSWD_PATH}': {errno}"));
+    }
+    if let Err(errno) = records::record_boot(&mut self.kernel) {
+      self.kernel.log(Level::WARN, &format!("cannot record boot in '{WTMP_PATH}': {errno}"));
+    }
+
+    Ok(())
+  }
+
+  fn bin_filesystem(&mut self) -> Result<MappedMutexGuard<'_, BinFilesytem>, Errno> {
+    MutexGuard::filter_map(self.kernel.vfs(), |vfs| vfs
+      .mount_points
+      .get_mut(BIN_PATH)
+      .filter(|mounted_fs| mounted_fs.r#type == FilesystemType::binfs)
+      .map(|mounted_fs| mounted_fs
+        .driver
+        .as_any()
+        .downcast_mut::<BinFilesytem>()
+        .expect("we know that mounted_fs.driver === instanceof BinFilesytem")))
+      .map_err(|_| Errno::ENOENT(format!("os: binfs is not mounted on {BIN_PATH}").into()))
+  }
+
+  /// Add binaries, replacing ones restored from snapshot
+  fn register_binaries(&mut self) -> Result<(), Errno> {
+    let mut binfs = self.bin_filesystem()?;
+
+    for (pathname, binary_fn) in default_binaries() {
+      match binfs.lookup_path(&pathname) {
+        Ok(_) => binfs.replace_bin(&pathname, binary_fn)?,
+        Err(Errno::ENOENT(_)) => binfs.add_bin(&pathname, binary_fn)?,
+        Err(errno) => return Err(errno),
+      };
+    }
+
+    Ok(())
+  }
+
+  /// Replace binfs on /bin with one saved in `realpath`.
+  /// Returns: whether there was a snapshot to restore
+  fn restore_snapshot(&mut self, realpath: &str) -> Result<bool, Errno> {
+    let serialized = match std::fs::read_to_string(realpath) {
+      Ok(serialized) => serialized,
+      Err(error) if error.kind() == std::io::ErrorKind::NotFound => return Ok(false),
+      Err(error) => return Err(Errno::EIO(format!("os: cannot read {realpath}: {error}").into())),
+    };
+
+    let mut binfs = BinFilesytem::deserialize(&serialized)?;
+    binfs.set_clock(self.kernel.clock.clone());
+    *self.bin_filesystem()? = binfs;
+
+    Ok(true)
+  }
+
+  /// Bring the system down: save snapshot and unmount everything,
+  /// so that filesystems are flushed and marked clean
+  pub fn shutdown(&mut self) {
+    if let Err(errno) = self.save_snapshot() {
+      println!("[{}]: cannot save snapshot: {errno}", kernel_message_header_err());
+      self.kernel.log(Level::ERROR, &format!("cannot save snapshot: {errno}"));
+    }
+    if let Err(errno) = self.kernel.umount_all() {
+      println!("[{}]: cannot unmount filesystems: {errno}", kernel_message_header_err());
+      self.kernel.log(Level::ERROR, &format!("cannot unmount filesystems: {errno}"));
+    }
+  }
+
+  /// Save binfs on /bin to snapshot, if there is one
+  pub fn save_snapshot(&mut self) -> Result<(), Errno> {
+    let Some(realpath) = self.snapshot.clone() else {
+      return Ok(());
+    };
+    let serialized = self.bin_filesystem()?.serialize()?;
+
+    std::fs::write(&realpath, serialized)
+      .with_context(|| format!("os: cannot write {realpath}"))
+  }
+
+  /// Run init until it exits: the console for `DEFAULT_INIT`,
+  /// otherwise program at `init` pathname.
+  /// Returns: exit code of init
+  pub fn start_init(&mut self) -> AddressSize {
+    if self.init == DEFAULT_INIT {
+      return self.console();
+    }
+
+    let init = self.init.clone();
+    match self.kernel.exec(&init, &[init.as_str()]) {
+      Ok(exit_code) => {
+        self.kernel.log(Level::INFO, &format!("init {init} exited with code {exit_code}"));
+        exit_code
+      },
+      Err(errno) => {
+        println!("[{}]: kernel can't exec init {init}: ERRNO: {errno}", kernel_message_header_err());
+        self.kernel.log(Level::ERROR, &format!("cannot exec init {init}: {errno}"));
+        EXIT_FAILURE
+      },
+    }
+  }
+
+  /// Console session of init: login prompt, then shell until `exit`.
+  /// Headless console skips login, as there is nobody to type password.
+  /// Returns: exit code of the shell
+  pub fn console(&mut self) -> AddressSize {
+    if self.script.is_none() {
+      // print!("{}[2J", 27 as char);
+      std::process::Command::new("clear").status().unwrap();
+      // Ctrl-C interrupts command that runs, not the machine
+      console::hook_interrupt();
+    }
+    kprintln!(self.kernel, "Eunix v1.0.0 (tty1)");
+    kprintln!(self.kernel);
+
+    if self.script.is_some() {
+      let name = self.kernel.user_name(ROOT_UID).unwrap_or(String::from("root"));
+      if let Err(errno) = records::record_login(&mut self.kernel, &name) {
+        self.kernel.log(Level::WARN, &format!("cannot record login in '{WTMP_PATH}': {errno}"));
+      }
+    } else if !self.login() {
+      return EXIT_FAILURE;
+    }
+
+    let exit_code = self.shell();
+
+    if let Err(errno) = records::record_logout(&mut self.kernel) {
+      self.kernel.log(Level::WARN, &format!("cannot record logout in '{WTMP_PATH}': {errno}"));
+    }
+
+    exit_code
+  }
+
+  /// Ask for user name and password until they are right.
+  /// Returns: whether somebody logged in, `false` if console was closed
+  fn login(&mut self) -> bool {
+    match self.kernel.passwds() {
+      Ok(_) => {
+        loop {
+          let input_username = match prompt_line(&mut self.kernel, "eunix login: ") {
+            Ok(input_username) if !input_username.is_empty() => input_username,
+            _ => return false,
+          };
+          let input_password = match prompt_line(&mut self.kernel, "Password: ") {
+            Ok(input_password) if !input_password.is_empty() => input_password,
+            _ => return false,
+          };
+          let input_username = input_username.trim();
+          let user = self.kernel.user_by_name(input_username);
+
+          match user.ok().flatten().as_ref() {
+            Some(passwd @ Passwd { uid, gid, .. }) => {
+              match account_status(&mut self.kernel, passwd) {
+                Ok(AccountStatus::Active) => (),
+                Ok(AccountStatus::Locked) => {
+                  kprintln!(self.kernel, "Your account is locked; please contact your system administrator");
+                  kprintln!(self.kernel);
+                  continue;
+                },
+                Ok(AccountStatus::Expired) => {
+                  kprintln!(self.kernel, "Your account has expired; please contact your system administrator");
+                  kprintln!(self.kernel);
+                  continue;
+                },
+                Err(errno) => {
+                  kprintln!(self.kernel, "login: cannot check account of '{}': {errno}", passwd.name);
+                },
+              }
+              if authenticate(&mut self.kernel, passwd, &input_password).unwrap_or(false) {
+                self.kernel.current_uid = *uid;
+                self.kernel.current_gid = *gid;
+                self.kernel.update_vfs_current_uid_gid();
+                if let Err(errno) = self.kernel.update_current_sgids() {
+                  kprintln!(self.kernel, "login: cannot read '{GROUP_PATH}': {errno}");
+                }
+                if let Err(errno) = records::record_login(&mut self.kernel, &passwd.name) {
+                  self.kernel.log(Level::WARN, &format!("cannot record login in '{WTMP_PATH}': {errno}"));
+                }
+                return true;
+              }
+            },
+            None => {
+            },
+          }
+          kprintln!(self.kernel, "Login incorrect");
+          kprintln!(self.kernel);
+        }
+      },
+      Err(Errno::ENOENT(_)) => {
+        kprintln!(self.kernel, "login: {PASSWD_PATH} does not exist, logging as root");
+      },
+      Err(errno) => {
+        kprintln!(self.kernel, "login: unexpected error: {errno}");
+      },
+    }
+
+    true
+  }
+
+  /// Read commands from controlling terminal and run them
+  /// until `exit` or end of input.
+  /// Returns: exit code given to `exit` or of the last command
+  fn shell(&mut self) -> AddressSize {
+    // Shell vars
+    let ifs = ' ';
+    let mut exit_code = EXIT_SUCCESS;
+    let path = String::from("/usr/bin:/bin");
+    let mut line_editor = LineEditor::default();
+
+    loop {
+      // A basic REPL prompt
+      let ps1 = format!("({exit_code: >3}) {} ", caret_by_uid(self.kernel.current_uid));
+      let pwd = self.kernel.getcwd().unwrap_or_else(|_| String::from("/"));
+      let complete = |kernel: &mut Kernel, line: &str, word: &str| complete_word(kernel, &path, &pwd, line, word);
+      let command = match edit_line(&mut self.kernel, &ps1, &mut line_editor, complete) {
+        // End of input, not even a newline
+        Ok(command) if command.is_empty() => break,
+        Ok(command) => command,
+        Err(errno) => {
+          kprintln!(self.kernel, "sh: cannot read from {TTY_PATH}: {errno}");
+          break;
+        },
+      };
+
+      // Parse args
+      let args = command
+        .trim() // Trim leading newline
+        .split(ifs) // Split by IFS (space)
+        .collect::<Vec<&str>>(); // Collect as [arg0, arg1, arg2, ...]
+
+      /* Execute command
+       * args[0] - program (or builtin) pathname/name
+       * args[1..] - arguments
+      */
+      match args[0] {
+        /* Empty line */
+        "" => (),
+
+        /* Echo buintin */
+        "echo" => {
+          let args = args[1..].join(" ");
+          kprintln!(self.kernel, "{args}");
+          exit_code = EXIT_SUCCESS;
+        },
+
+        /* Cd buintin */
+        "cd" => {
+          let pathname = args.get(1).copied().unwrap_or("/");
+
+          exit_code = EXIT_FAILURE;
+          match self.kernel.chdir(pathname) {
+            Ok(()) => exit_code = EXIT_SUCCESS,
+            Err(Errno::ENOTDIR(_)) => {
+              keprintln!(self.kernel, "cd: not a directory: {pathname}")
+            },
+            Err(Errno::ENOENT(_)) => {
+              keprintln!(self.kernel, "cd: no such file or directory: {pathname}")
+            },
+            Err(Errno::EACCES(_)) => {
+              keprintln!(self.kernel, "cd: permission denied: {pathname}")
+            },
+            Err(errno) => {
+              keprintln!(self.kernel, "cd: unexpected kernel error occured while looking for {pathname}: {errno}")
+            },
+          }
+        },
+
+        /* Pwd (print working directory) buintin */
+        "pwd" => {
+          match self.kernel.getcwd() {
+            Ok(pwd) => {
+              kprintln!(self.kernel, "{pwd}");
+              exit_code = EXIT_SUCCESS;
+            },
+            Err(errno) => {
+              keprintln!(self.kernel, "pwd: {errno}");
+              exit_code = EXIT_FAILURE;
+            },
+          }
+        },
+
+        /* Exit buintin, with exit code of the last command by default */
+        "exit" => {
+          if let Some(code) = args.get(1) {
+            match code.parse::<AddressSize>() {
+              Ok(code) => exit_code = code,
+              Err(_) => {
+                kprintln!(self.kernel, "exit: numeric argument required: {code}");
+                exit_code = EXIT_FAILURE;
+              },
+            }
+          }
+          break;
+        },
+
+        /* No builtin matched - run pathname */
+        command => {
+          // Calculate pathname
+          // Match command against PATH:
+          // if (executable in PATH) -> return new pathname
+          // otherwise               -> return command literally
+          let pathname = if Regex::new("^[_\\.a-zA-Z][^\\/\\n]*$")
+            .unwrap()
+            .is_match(command)
+            .unwrap()
+          {
+            if let Some(pathname) = path
+              .split(':')
+              .find_map(|location_pathname| {
+                let pathname = format!("{location_pathname}/{command}");
+                self.kernel.access(&pathname, PERM_X).ok().and_then(|_| Some(pathname))
+              })
+            {
+              pathname
+            } else {
+              command.to_string()
+            }
+          } else {
+            command.to_string()
+          };
+
+          // Init drops signals sent to it, and so Ctrl-C that was
+          // pressed before command, it is not for the command
+          self.kernel.deliver_signals();
+
+          // Execute calculated pathname
+          exit_code = match self.kernel.run(&pathname, args.as_ref()) {
+            Ok(exit_code) => exit_code,
+            Err(Errno::ENOENT(_)) => {
+              kprintln!(self.kernel, "sh: no such file or directory: {pathname}");
+              EXIT_ENOENT
+            },
+            Err(errno) => {
+              kprintln!(self.kernel, "[{}]: kernel can't exec {pathname}: ERRNO: {errno}", kernel_message_header_err());
+              EXIT_FAILURE
+            },
+          };
+
+          // `reboot` or `poweroff` was run
+          if self.kernel.power_action.lock().unwrap().is_some() {
+            break;
+          }
+        }
+      }
+    }
+
+    exit_code
+  }
+}
+
+#[cfg(test)]
+mod tests {
+  use super::*;
+  use crate::testing::kernel_with_devices;
+  use crate::machine::{MachineDevice, MachineClock, VirtualDeviceType};
+  use crate::util::mktemp;
+
+  /// Headless system on binfs root with `script` typed into console
+  fn headless_os(script: &str) -> OperatingSystem {
+    let tty = MachineDevice {
+      realpath: String::from("/dev/stdin"),
+      r#type: VirtualDeviceType::TTYDevice,
+      read_only: false,
+      backend: None,
+    };
+    let mut os = OperatingSystem {
+      kernel: kernel_with_devices(vec![tty], MachineClock::default()),
+      init: String::from(DEFAULT_INIT),
+      snapshot: None,
+      script: None,
+      script_tty: None,
+      console: None,
+      editor: None,
+    };
+    let realpath = mktemp();
+    std::fs::write(&realpath, script).unwrap();
+
+    os.kernel.mount("", "/", FilesystemType::binfs).unwrap();
+    let mut vfs = os.kernel.vfs();
+    let root = &mut vfs.mount_points.get_mut("/").unwrap().driver;
+    root.create_file(PASSWD_PATH).unwrap();
+    root.write_file(PASSWD_PATH, b"root:x:0:0::/root:").unwrap();
+    root.create_dir("/dev").unwrap();
+    drop(vfs);
+    os.kernel.mount("", "/dev", FilesystemType::devfs).unwrap();
+    os.kernel.replace_tty_driver(CONSOLE_PATH, Arc::new(RwLock::new(ScriptTTY::open(&realpath).unwrap()))).unwrap();
+    os.kernel.set_controlling_tty(CONSOLE_PATH).unwrap();
+    os.kernel.open_stdio(CONSOLE_PATH).unwrap();
+
+    os.with_script(&realpath)
+  }
+
+  #[test]
+  fn headless_shell_exits_with_status() {
+    assert_eq!(headless_os("echo hello\n\nexit 3\necho unreachable\n").shell(), 3);
+    // Without `exit`, status is of the last command before end of input
+    assert_eq!(headless_os("pwd\n/no/such/binary").shell(), EXIT_ENOENT);
+    assert_eq!(headless_os("cd /dev\n").shell(), EXIT_SUCCESS);
+  }
+
+  #[test]
+  fn parse_fstab_works() {
+    let fstab = FstabEntry::parse_fstab("# root is mounted by kernel\n/dev/sda / e5fs defaults 0 1\n\nnone /proc procfs\n/dev/sdb /home nofs\n");
+
+    assert_eq!(fstab, vec![
+      FstabEntry { source: String::from("/dev/sda"), target: String::from("/"), fs_type: FilesystemType::e5fs },
+      FstabEntry { source: String::from("none"), target: String::from("/proc"), fs_type: FilesystemType::procfs },
+    ]);
+  }
+}
+
+// vim:ts=2 sw=2
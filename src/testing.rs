@@ -0,0 +1,73 @@
+//! Fixtures shared by tests of the crate: kernels of machines without
+//! anything to boot, and e5fs in memory
+
+use std::sync::{Arc, RwLock};
+
+use crate::binaries::PASSWD_PATH;
+use crate::eunix::devices::MemoryStorage;
+use crate::eunix::e5fs::E5FSFilesystem;
+use crate::eunix::fs::{Filesystem, FilesystemType, MountedFilesystem, MountFlags};
+use crate::eunix::kernel::{Kernel, KernelParams};
+use crate::machine::{MachineClock, MachineDevice, MachineDeviceTable, MachineResources};
+use crate::os::DEFAULT_INIT;
+
+/// Size of e5fs that `memory_e5fs` makes, enough for most tests
+pub const E5FS_SIZE: usize = 1024 * 1024;
+
+/// Kernel of machine with `devices` and no RAM disks, with nothing mounted
+pub fn kernel_with_devices(devices: Vec<MachineDevice>, clock: MachineClock) -> Kernel {
+  let devices = MachineDeviceTable {
+    devices,
+    ram_disks: Vec::new(),
+  };
+
+  Kernel::new(&devices, KernelParams { init: String::from(DEFAULT_INIT), resources: MachineResources::default(), clock })
+}
+
+/// Kernel of machine without devices, with nothing mounted
+pub fn kernel() -> Kernel {
+  kernel_with_devices(Vec::new(), MachineClock::default())
+}
+
+/// Kernel with binfs as root filesystem - files written there read back as is -
+/// and only root in /etc/passwd
+pub fn kernel_with_binfs_root() -> Kernel {
+  let mut kernel = kernel();
+  kernel.mount("", "/", FilesystemType::binfs).unwrap();
+  let mut vfs = kernel.vfs();
+  let root = &mut vfs.mount_points.get_mut("/").unwrap().driver;
+  root.create_file(PASSWD_PATH).unwrap();
+  root.write_file(PASSWD_PATH, b"root:x:0:0::/root:").unwrap();
+  drop(vfs);
+
+  kernel
+}
+
+/// Kernel with empty e5fs in memory as root filesystem
+/// and only root in /etc/passwd
+pub fn kernel_with_e5fs_root() -> Kernel {
+  let kernel = kernel();
+  let (mut e5fs, _) = memory_e5fs(E5FS_SIZE);
+  e5fs.create_dir("/etc").unwrap();
+  e5fs.create_file(PASSWD_PATH).unwrap();
+  e5fs.write_file(PASSWD_PATH, b"root:x:0:0::/root:").unwrap();
+  kernel.vfs().mount_points.insert(String::from("/"), MountedFilesystem {
+    r#type: FilesystemType::e5fs,
+    driver: Box::new(e5fs),
+    flags: MountFlags::default(),
+    source: String::new(),
+  });
+
+  kernel
+}
+
+/// Empty e5fs in `size` bytes of memory, with 4096-byte blocks.
+/// Returns: it and the memory, to mount it again from
+pub fn memory_e5fs(size: usize) -> (E5FSFilesystem, Arc<RwLock<Vec<u8>>>) {
+  let buffer = Arc::new(RwLock::new(vec![0u8; size]));
+  let e5fs = E5FSFilesystem::mkfs_storage(Box::new(MemoryStorage::new(buffer.clone())), 0.05, 4096).unwrap();
+
+  (e5fs, buffer)
+}
+
+// vim:ts=2 sw=2
@@ -1,5 +1,4 @@
-use super::*;
-use std::{process::Command, ops::BitAnd};
+use std::{process::Command, ops::BitAnd, path::Path};
 
 pub fn mkenxvd(size: String, file_path: String) {
   let path = Path::new(env!("CARGO_MANIFEST_DIR")).join("scripts/mkenxvd.sh");
@@ -51,6 +50,18 @@ pub fn fixedpoint<F, T>(f: F, initial: T) -> T
   result
 }
 
+/// Parse size like `4096`, `512K`, `1M` or `1G` into bytes
+pub fn parse_size(size: &str) -> Option<u64> {
+  let (digits, multiplier) = match size.chars().last()? {
+    'K' | 'k' => (&size[..size.len() - 1], 1024),
+    'M' | 'm' => (&size[..size.len() - 1], 1024 * 1024),
+    'G' | 'g' => (&size[..size.len() - 1], 1024 * 1024 * 1024),
+    _ => (size, 1),
+  };
+
+  digits.parse::<u64>().ok().map(|number| number * multiplier)
+}
+
 /// Gets the bit at position `n`.
 /// Bits are numbered from 0 (least significant) to 7 (most significant).
 pub fn get_bit_at(input: u8, n: u8) -> bool {
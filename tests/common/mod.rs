@@ -0,0 +1,14 @@
+//! Helpers shared by integration tests, benches and tests of binaries
+
+/// Empty host file of `size` bytes, unique to this run
+pub fn image(name: &str, size: u64) -> String {
+  let realpath = std::env::temp_dir()
+    .join(format!("eunix-{}-{name}.enxvd", std::process::id()))
+    .to_string_lossy()
+    .into_owned();
+  std::fs::File::create(&realpath).unwrap().set_len(size).unwrap();
+
+  realpath
+}
+
+// vim:ts=2 sw=2
@@ -0,0 +1,87 @@
+mod common;
+
+use std::sync::Arc;
+
+use eunix::{
+  e5fs::E5FSFilesystem,
+  fs::{Filesystem, FilesystemType, EVERYTHING},
+  kernel::{Kernel, KernelParams},
+  machine::{MachineClock, MachineDeviceTable, MachineResources},
+  rng::{Rng, SeededRng},
+};
+
+use common::image;
+
+/// Kernel of machine without devices, with clock stopped at epoch
+fn kernel() -> Kernel {
+  let devices = MachineDeviceTable { devices: Vec::new(), ram_disks: Vec::new() };
+  Kernel::new(&devices, KernelParams {
+    init: String::from("/bin/init"),
+    resources: MachineResources::default(),
+    clock: MachineClock::Fixed { epoch: 0 },
+  })
+}
+
+#[test]
+fn e5fs_image_is_usable_from_host() {
+  let realpath = image("host", 1024 * 1024);
+
+  let mut e5fs = E5FSFilesystem::mkfs(&realpath, 0.05, 4096).unwrap();
+  e5fs.create_dir("/etc").unwrap();
+  e5fs.create_file("/etc/motd").unwrap();
+  e5fs.write_file("/etc/motd", b"hello\n").unwrap();
+  e5fs.unmount().unwrap();
+
+  let mut e5fs = E5FSFilesystem::from(&realpath).unwrap();
+  assert_eq!(e5fs.read_file("/etc/motd", EVERYTHING).unwrap(), b"hello\n");
+
+  std::fs::remove_file(&realpath).unwrap();
+}
+
+#[test]
+fn kernel_is_constructed_without_machine() {
+  let mut kernel = kernel();
+
+  kernel.mount("", "/", FilesystemType::binfs).unwrap();
+  let mut vfs = kernel.vfs();
+  let root = &mut vfs.mount_points.get_mut("/").unwrap().driver;
+  root.create_file("/file").unwrap();
+
+  assert_eq!(root.stat("/file").unwrap().mtime, 0);
+}
+
+#[test]
+fn kernel_is_shared_between_threads() {
+  let mut kernel = kernel();
+  kernel.mount("", "/", FilesystemType::binfs).unwrap();
+
+  let threads = (0..4)
+    .map(|number| {
+      let mut kernel = kernel.fork_thread().unwrap();
+      std::thread::spawn(move || {
+        kernel.vfs().create_file(&format!("/file{number}")).unwrap();
+        kernel.exit(0).unwrap();
+      })
+    })
+    .collect::<Vec<_>>();
+  for thread in threads {
+    thread.join().unwrap();
+  }
+
+  let mut vfs = kernel.vfs();
+  assert!((0..4).all(|number| vfs.stat(&format!("/file{number}")).is_ok()));
+}
+
+#[test]
+fn dev_random_reads_from_kernel_rng() {
+  let mut kernel = kernel();
+  kernel.rng = Arc::new(SeededRng::new(42));
+
+  kernel.mount("", "/", FilesystemType::devfs).unwrap();
+  let mut vfs = kernel.vfs();
+  let root = &mut vfs.mount_points.get_mut("/").unwrap().driver;
+
+  assert_eq!(root.read_file("/random", 16).unwrap(), SeededRng::new(42).bytes(16));
+}
+
+// vim:ts=2 sw=2